@@ -0,0 +1,89 @@
+//! Command-line utility managing API keys.
+//!
+//! ```sh
+//! apikeys create <NAME> <SCOPE>...
+//! apikeys list
+//! apikeys revoke <ID>
+//! ```
+//!
+//! Manages the API keys authenticating the HTTP endpoints that are not
+//! DIDComm endpoints, in the key store found under `STORAGE_DIRPATH`.
+//! Scopes are `admin`, `invitations` and `usage`. Created keys are only
+//! printed once, as their secret is not persisted.
+
+use chrono::Utc;
+use did_endpoint::util::{
+    apikeys::{ApiKeyScope, ApiKeyStore},
+    filesystem::StdFileSystem,
+};
+
+const USAGE: &str = "Usage: apikeys create <NAME> <SCOPE>... | apikeys list | apikeys revoke <ID>";
+
+fn main() {
+    // Load dotenv-flow variables
+    dotenv_flow::dotenv_flow().ok();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["create", name, scopes @ ..] if !scopes.is_empty() => create(name, scopes),
+        ["list"] => list(),
+        ["revoke", id] => revoke(id),
+        _ => Err(String::from(USAGE)),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+fn storage_dirpath() -> Result<String, String> {
+    std::env::var("STORAGE_DIRPATH").map_err(|_| String::from("Missing STORAGE_DIRPATH"))
+}
+
+fn create(name: &str, scopes: &[&str]) -> Result<(), String> {
+    let scopes = scopes
+        .iter()
+        .map(|scope| scope.parse::<ApiKeyScope>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| err.to_string())?;
+
+    let mut fs = StdFileSystem;
+    let (record, key) = ApiKeyStore::new(&mut fs, &storage_dirpath()?)
+        .create(name, &scopes, Utc::now().timestamp())
+        .map_err(|err| err.to_string())?;
+
+    println!("created {} ({})", record.id, record.name);
+    println!("{key}");
+    Ok(())
+}
+
+fn list() -> Result<(), String> {
+    let mut fs = StdFileSystem;
+    let keys = ApiKeyStore::new(&mut fs, &storage_dirpath()?)
+        .list()
+        .map_err(|err| err.to_string())?;
+
+    for key in keys {
+        let scopes: Vec<_> = key.scopes.iter().map(ToString::to_string).collect();
+        let status = if key.revoked_time.is_some() {
+            "revoked"
+        } else {
+            "active"
+        };
+        println!("{}\t{}\t{}\t{}", key.id, key.name, scopes.join(","), status);
+    }
+
+    Ok(())
+}
+
+fn revoke(id: &str) -> Result<(), String> {
+    let mut fs = StdFileSystem;
+    ApiKeyStore::new(&mut fs, &storage_dirpath()?)
+        .revoke(id, Utc::now().timestamp())
+        .map_err(|err| err.to_string())?;
+
+    println!("revoked {id}");
+    Ok(())
+}
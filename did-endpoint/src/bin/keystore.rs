@@ -0,0 +1,89 @@
+//! Command-line utility managing the mediator's keystore.
+//!
+//! ```sh
+//! keystore import <FILE>...
+//! ```
+//!
+//! Imports the keys held by PEM (PKCS#8), JWK or JWKS files into the
+//! keystore found under `STORAGE_DIRPATH`, so that identities managed by
+//! other software can be migrated. Keys without a key identifier are named
+//! after the file they are read from.
+
+use did_endpoint::util::{
+    filesystem::StdFileSystem,
+    keystore::{read_key_file, KeyStore, KeyStoreError},
+};
+use std::{collections::HashSet, io::ErrorKind, path::Path};
+
+const USAGE: &str = "Usage: keystore import <FILE>...";
+
+fn main() {
+    // Load dotenv-flow variables
+    dotenv_flow::dotenv_flow().ok();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, files)) if command == "import" && !files.is_empty() => import(files),
+        _ => Err(String::from(USAGE)),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+fn import(files: &[String]) -> Result<(), String> {
+    let storage_dirpath =
+        std::env::var("STORAGE_DIRPATH").map_err(|_| String::from("Missing STORAGE_DIRPATH"))?;
+
+    // Read and validate all keys before touching the keystore
+    let mut keys = vec![];
+    for file in files {
+        let content = std::fs::read_to_string(file).map_err(|err| format!("{file}: {err}"))?;
+        let default_kid = Path::new(file)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("imported");
+
+        let read = read_key_file(&content, default_kid).map_err(|err| format!("{file}: {err}"))?;
+        keys.extend(read);
+    }
+
+    // Only a missing keystore is created, not one failing to be read
+    let (mut fs, mut new_fs) = (StdFileSystem, StdFileSystem);
+    let mut store = match KeyStore::latest(&mut fs, &storage_dirpath) {
+        Ok(store) => store,
+        Err(KeyStoreError::NotFound) => KeyStore::new(&mut new_fs, &storage_dirpath),
+        Err(KeyStoreError::IoError(err)) if err.kind() == ErrorKind::NotFound => {
+            KeyStore::new(&mut new_fs, &storage_dirpath)
+        }
+        Err(err) => return Err(format!("keystore: {err}")),
+    };
+
+    // Check all keys, against the keystore and one another, so that none
+    // is imported unless all can be
+    let mut kids = HashSet::new();
+    for (kid, key) in &keys {
+        store
+            .check_import(kid, key)
+            .map_err(|err| format!("{kid}: {err}"))?;
+        if !kids.insert(kid) {
+            return Err(format!(
+                "{kid}: {}",
+                KeyStoreError::DuplicateKid(kid.clone())
+            ));
+        }
+    }
+
+    let imported = keys.iter().map(|(kid, key)| (kid.as_str(), key));
+    store
+        .import_all(imported)
+        .map_err(|err| format!("keystore: {err}"))?;
+    for (kid, _) in &keys {
+        println!("imported {kid}");
+    }
+
+    println!("keystore: {}", store.path());
+    Ok(())
+}
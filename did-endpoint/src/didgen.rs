@@ -1,10 +1,19 @@
-use crate::util::{didweb, filesystem::StdFileSystem, keystore::KeyStore};
+use crate::util::{
+    didweb,
+    filesystem::{FileSystem, StdFileSystem},
+    keystore::{assertion_key, read_key_file, KeyStore, ToPublic},
+};
 use did_utils::{
+    crypto::{
+        ed25519::Ed25519KeyPair,
+        traits::{CoreSign, Generate, ECDH},
+        x25519::X25519KeyPair,
+    },
     didcore::{
-        AssertionMethod, Authentication, Document, KeyAgreement, KeyFormat, Service,
-        VerificationMethod,
+        AssertionMethod, Authentication, CapabilityDelegation, CapabilityInvocation, Document,
+        DocumentBuilder, KeyAgreement, KeyFormat, Relationship, Service, VerificationMethod,
     },
-    key_jwk::jwk::Jwk,
+    key_jwk::{jwk::Jwk, key::Key, okp::OkpCurves},
     ldmodel::Context,
 };
 use std::path::Path;
@@ -17,8 +26,14 @@ pub enum Error {
     MissingServerPublicDomain,
     #[error("DidAddressDerivationError")]
     DidAddressDerivationError,
+    #[error("InvalidDidDocument: {0}")]
+    InvalidDidDocument(String),
     #[error("PersistenceError")]
     PersistenceError,
+    #[error("InvalidKeys: {0}")]
+    InvalidKeys(String),
+    #[error("KeyControlError: {0}")]
+    KeyControlError(String),
     #[error("Generic: {0}")]
     Generic(String),
 }
@@ -44,6 +59,12 @@ pub fn didgen(storage_dirpath: &str, server_public_domain: &str) -> Result<Docum
         .gen_ed25519_jwk()
         .map_err(|_| Error::KeyGenerationError)?;
 
+    // Sign audit log entries with the assertion key, including those of
+    // the keys generated so far
+    store
+        .set_audit_signer(&assertion_key)
+        .map_err(|_| Error::KeyGenerationError)?;
+
     // Generate agreement key
     tracing::debug!("generating agreement key");
     let agreement_key = store
@@ -127,24 +148,14 @@ fn gen_diddoc(
         String::from("https://w3id.org/security/suites/jws-2020/v1"),
     ]);
 
-    let diddoc = Document {
-        authentication: Some(vec![Authentication::Reference(
-            authentication_method.id.clone(), //
-        )]),
-        assertion_method: Some(vec![AssertionMethod::Reference(
-            assertion_method.id.clone(), //
-        )]),
-        key_agreement: Some(vec![KeyAgreement::Reference(
-            agreement_method.id.clone(), //
-        )]),
-        verification_method: Some(vec![
-            authentication_method,
-            assertion_method,
-            agreement_method,
-        ]),
-        service: Some(vec![service]),
-        ..Document::new(context, did)
-    };
+    let diddoc = DocumentBuilder::new(did)
+        .context(context)
+        .add_verification_method(authentication_method, &[Relationship::Authentication])
+        .add_verification_method(assertion_method, &[Relationship::AssertionMethod])
+        .add_verification_method(agreement_method, &[Relationship::KeyAgreement])
+        .add_service(service)
+        .build()
+        .map_err(|err| Error::InvalidDidDocument(err.to_string()))?;
 
     // Serialize and persist to file
 
@@ -158,6 +169,194 @@ fn gen_diddoc(
     Ok(diddoc)
 }
 
+/// Bootstraps the identity of the mediator from an existing DID document
+/// and the keys it references, held by a JWK, JWKS or PEM file, instead
+/// of generating them.
+///
+/// Control of every key of the document is checked before anything is
+/// persisted at `storage_dirpath`: signing keys must sign a challenge that
+/// verifies against the document, and agreement keys must agree on a
+/// secret with an ephemeral key. The identity is persisted again only if
+/// the document changed since the last bootstrap.
+pub fn import_identity(
+    storage_dirpath: &str,
+    diddoc_path: &str,
+    keys_path: &str,
+) -> Result<Document, Error> {
+    let diddoc: Document = std::fs::read_to_string(diddoc_path)
+        .map_err(|err| Error::InvalidDidDocument(err.to_string()))
+        .and_then(|content| {
+            serde_json::from_str(&content).map_err(|err| Error::InvalidDidDocument(err.to_string()))
+        })?;
+
+    let keys = std::fs::read_to_string(keys_path)
+        .map_err(|err| Error::InvalidKeys(err.to_string()))
+        .and_then(|content| {
+            read_key_file(&content, "identity").map_err(|err| Error::InvalidKeys(err.to_string()))
+        })?;
+
+    // Pair verification methods, listed or embedded in relationships,
+    // with the keys controlling them
+    let mut controlled = vec![];
+    for method in verification_methods(&diddoc) {
+        let Some(KeyFormat::Jwk(pubkey)) = &method.public_key else {
+            return Err(Error::InvalidDidDocument(format!(
+                "{}: unsupported key format",
+                method.id
+            )));
+        };
+
+        let (kid, key) = keys
+            .iter()
+            .find(|(_, key)| {
+                key.to_jwk()
+                    .is_ok_and(|jwk| jwk.to_public().key == pubkey.to_public().key)
+            })
+            .ok_or_else(|| Error::KeyControlError(format!("{}: no matching key", method.id)))?;
+
+        let jwk = key
+            .to_jwk()
+            .map_err(|err| Error::InvalidKeys(err.to_string()))?;
+        check_control(&jwk, pubkey)
+            .map_err(|err| Error::KeyControlError(format!("{}: {err}", method.id)))?;
+
+        if !controlled.iter().any(|(imported, _)| imported == kid) {
+            controlled.push((kid.as_str(), key));
+        }
+    }
+
+    // Skip persistence if the identity is already in place
+    let didpath = format!("{storage_dirpath}/did.json");
+    let persisted: Option<Document> = std::fs::read_to_string(&didpath)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+    if persisted.as_ref() == Some(&diddoc) && validate_diddoc(storage_dirpath).is_ok() {
+        tracing::debug!("identity already imported");
+        return Ok(diddoc);
+    }
+
+    // Import the keys before the document referencing them, so that an
+    // interrupted import never leaves a document without its keys
+    let mut fs = StdFileSystem;
+    let mut store = KeyStore::new(&mut fs, storage_dirpath);
+    store
+        .import_all(controlled)
+        .map_err(|err| Error::InvalidKeys(err.to_string()))?;
+
+    // Sign the audit log entries of the imports with the assertion key
+    if let Some(pubkey) = assertion_key(&diddoc) {
+        store
+            .set_audit_signer(&pubkey)
+            .map_err(|err| Error::InvalidKeys(err.to_string()))?;
+    }
+    let keystore_path = store.path();
+
+    let did_json = serde_json::to_string_pretty(&diddoc).unwrap();
+    let mut fs = StdFileSystem;
+    fs.create_dir_all(storage_dirpath)
+        .and_then(|_| fs.write_atomic(&didpath, &did_json))
+        .map_err(|_| Error::PersistenceError)?;
+
+    tracing::info!("imported identity {} to {keystore_path}", diddoc.id);
+    Ok(diddoc)
+}
+
+/// Verification methods of a DID document, whether listed or embedded in
+/// verification relationships
+fn verification_methods(diddoc: &Document) -> Vec<&VerificationMethod> {
+    let mut methods: Vec<_> = diddoc.verification_method.iter().flatten().collect();
+
+    let authentication = diddoc.authentication.iter().flatten();
+    methods.extend(authentication.filter_map(|method| match method {
+        Authentication::Embedded(method) => Some(method.as_ref()),
+        Authentication::Reference(_) => None,
+    }));
+    let assertion = diddoc.assertion_method.iter().flatten();
+    methods.extend(assertion.filter_map(|method| match method {
+        AssertionMethod::Embedded(method) => Some(method.as_ref()),
+        AssertionMethod::Reference(_) => None,
+    }));
+    let agreement = diddoc.key_agreement.iter().flatten();
+    methods.extend(agreement.filter_map(|method| match method {
+        KeyAgreement::Embedded(method) => Some(method.as_ref()),
+        KeyAgreement::Reference(_) => None,
+    }));
+    let invocation = diddoc.capability_invocation.iter().flatten();
+    methods.extend(invocation.filter_map(|method| match method {
+        CapabilityInvocation::Embedded(method) => Some(method.as_ref()),
+        CapabilityInvocation::Reference(_) => None,
+    }));
+    let delegation = diddoc.capability_delegation.iter().flatten();
+    methods.extend(delegation.filter_map(|method| match method {
+        CapabilityDelegation::Embedded(method) => Some(method.as_ref()),
+        CapabilityDelegation::Reference(_) => None,
+    }));
+
+    methods
+}
+
+/// Checks that the keystore controls every key of the persisted DID
+/// document, by signing challenges and agreeing on keys with them
+pub fn self_test(storage_dirpath: &str) -> Result<(), String> {
+    validate_diddoc(storage_dirpath)?;
+
+    let content = std::fs::read_to_string(format!("{storage_dirpath}/did.json"))
+        .map_err(|_| String::from("Unreadable did.json"))?;
+    let diddoc: Document =
+        serde_json::from_str(&content).map_err(|_| String::from("Unparseable did.json"))?;
+
+    let mut fs = StdFileSystem;
+    let store = KeyStore::latest(&mut fs, storage_dirpath).map_err(|err| err.to_string())?;
+    for method in verification_methods(&diddoc) {
+        let Some(KeyFormat::Jwk(pubkey)) = &method.public_key else {
+            return Err(format!("Unsupported key format for {}", method.id));
+        };
+
+        let jwk = store
+            .find_keypair(pubkey)
+            .ok_or_else(|| format!("Keystore mismatch for {}", method.id))?;
+        check_control(&jwk, pubkey).map_err(|err| format!("{}: {err}", method.id))?;
+    }
+
+    Ok(())
+}
+
+/// Checks that a private key controls a public key of the DID document
+fn check_control(jwk: &Jwk, pubkey: &Jwk) -> Result<(), String> {
+    let Key::Okp(okp) = &jwk.key else {
+        return Err(String::from("unsupported key type"));
+    };
+
+    match okp.crv {
+        OkpCurves::Ed25519 => {
+            let challenge = uuid::Uuid::new_v4();
+            let signer: Ed25519KeyPair = jwk.clone().try_into().map_err(|_| "invalid key")?;
+            let verifier: Ed25519KeyPair = pubkey.clone().try_into().map_err(|_| "invalid key")?;
+
+            let signature = signer
+                .sign(challenge.as_bytes())
+                .map_err(|_| "failed to sign challenge")?;
+            verifier
+                .verify(challenge.as_bytes(), &signature)
+                .map_err(|_| String::from("challenge signature does not verify"))
+        }
+        OkpCurves::X25519 => {
+            let ephemeral = X25519KeyPair::new().map_err(|_| "failed to generate ephemeral key")?;
+            let private: X25519KeyPair = jwk.clone().try_into().map_err(|_| "invalid key")?;
+            let public: X25519KeyPair = pubkey.clone().try_into().map_err(|_| "invalid key")?;
+
+            match (
+                private.key_exchange(&ephemeral),
+                ephemeral.key_exchange(&public),
+            ) {
+                (Some(ours), Some(theirs)) if ours == theirs => Ok(()),
+                _ => Err(String::from("key agreement does not match")),
+            }
+        }
+        _ => Err(String::from("unsupported curve")),
+    }
+}
+
 /// Validates the integrity of the persisted diddoc
 pub fn validate_diddoc(storage_dirpath: &str) -> Result<(), String> {
     // Validate that did.json exists
@@ -332,6 +531,85 @@ mod tests {
         cleanup(&storage_dirpath);
     }
 
+    #[test]
+    fn test_import_identity() {
+        let (storage_dirpath, server_public_domain) = setup();
+        let (source_dirpath, _) = setup();
+
+        // Bring the identity of another installation
+        didgen(&source_dirpath, &server_public_domain).unwrap();
+        let diddoc_path = format!("{source_dirpath}/did.json");
+        let keys_path = format!("{source_dirpath}/keys.jwks");
+
+        let mut fs = StdFileSystem;
+        let keystore = KeyStore::latest(&mut fs, &source_dirpath).unwrap();
+        let keys: Vec<Jwk> =
+            serde_json::from_str(&std::fs::read_to_string(keystore.path()).unwrap()).unwrap();
+        std::fs::write(&keys_path, serde_json::json!({ "keys": keys }).to_string()).unwrap();
+
+        let diddoc = import_identity(&storage_dirpath, &diddoc_path, &keys_path).unwrap();
+        assert_eq!(diddoc.id, "did:web:example.com");
+        assert!(validate_diddoc(&storage_dirpath).is_ok());
+
+        // Importing again is a no-op
+        import_identity(&storage_dirpath, &diddoc_path, &keys_path).unwrap();
+
+        // Keys not controlling the document are refused
+        let other_keys = serde_json::json!({ "keys": keys[1..] });
+        std::fs::write(&keys_path, other_keys.to_string()).unwrap();
+        assert!(matches!(
+            import_identity(&storage_dirpath, &diddoc_path, &keys_path),
+            Err(Error::KeyControlError(_))
+        ));
+
+        cleanup(&storage_dirpath);
+        cleanup(&source_dirpath);
+    }
+
+    #[test]
+    fn test_import_identity_with_embedded_methods() {
+        let (storage_dirpath, server_public_domain) = setup();
+        let (source_dirpath, _) = setup();
+
+        didgen(&source_dirpath, &server_public_domain).unwrap();
+        let diddoc_path = format!("{source_dirpath}/did.json");
+        let keys_path = format!("{source_dirpath}/keys.jwks");
+
+        let mut fs = StdFileSystem;
+        let keystore = KeyStore::latest(&mut fs, &source_dirpath).unwrap();
+        let keys: Vec<Jwk> =
+            serde_json::from_str(&std::fs::read_to_string(keystore.path()).unwrap()).unwrap();
+        std::fs::write(&keys_path, serde_json::json!({ "keys": keys }).to_string()).unwrap();
+
+        // Embed an agreement key the provided keys do not control
+        let mut diddoc: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&diddoc_path).unwrap()).unwrap();
+        diddoc["keyAgreement"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({
+                "id": "did:web:example.com#keys-4",
+                "type": "JsonWebKey2020",
+                "controller": "did:web:example.com",
+                "publicKeyJwk": {
+                    "kty": "OKP",
+                    "crv": "X25519",
+                    "x": "3p7bfXt9wbTTW2HC7OQ1Nz-DQ8hbeGdNrfx-FG-IK08"
+                }
+            }));
+        std::fs::write(&diddoc_path, diddoc.to_string()).unwrap();
+
+        // Nothing is persisted for an identity not fully controlled
+        assert!(matches!(
+            import_identity(&storage_dirpath, &diddoc_path, &keys_path),
+            Err(Error::KeyControlError(_))
+        ));
+        assert!(!Path::new(&format!("{storage_dirpath}/did.json")).exists());
+        assert!(KeyStore::latest(&mut fs, &storage_dirpath).is_err());
+
+        cleanup(&source_dirpath);
+    }
+
     #[test]
     fn test_validate_diddoc() {
         let (storage_dirpath, server_public_domain) = setup();
@@ -341,4 +619,15 @@ mod tests {
 
         cleanup(&storage_dirpath);
     }
+
+    #[test]
+    fn test_identity_self_test() {
+        let (storage_dirpath, server_public_domain) = setup();
+        assert!(self_test(&storage_dirpath).is_err());
+
+        didgen(&storage_dirpath, &server_public_domain).unwrap();
+        self_test(&storage_dirpath).unwrap();
+
+        cleanup(&storage_dirpath);
+    }
 }
@@ -1,28 +1,79 @@
-use super::{didgen, web};
+use super::{
+    didgen,
+    util::{filesystem::StdFileSystem, keystore, metered::StoreMetrics},
+    web,
+};
 use axum::Router;
 use server_plugin::{Plugin, PluginError};
+use utoipa::{openapi::OpenApi as OpenApiDoc, OpenApi};
 
 #[derive(Default)]
-pub struct DidEndpointPlugin;
+pub struct DidEndpointPlugin {
+    metrics: StoreMetrics,
+}
 
 impl Plugin for DidEndpointPlugin {
     fn name(&self) -> &'static str {
         "did_endpoint"
     }
 
+    fn migrate(&self) -> Result<(), PluginError> {
+        let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").ok_or_else(|| {
+            tracing::error!("STORAGE_DIRPATH env variable required");
+            PluginError::InitError
+        })?;
+
+        match keystore::migrate_layout(&mut StdFileSystem, &storage_dirpath) {
+            Ok(true) => tracing::info!(
+                "migrated keystore to layout {}",
+                keystore::KEYSTORE_LAYOUT_VERSION
+            ),
+            Ok(false) => (),
+            Err(err) => {
+                tracing::error!("failed to migrate keystore: {err}");
+                return Err(PluginError::InitError);
+            }
+        }
+
+        Ok(())
+    }
+
     fn mount(&self) -> Result<(), PluginError> {
-        let storage_dirpath = std::env::var("STORAGE_DIRPATH").map_err(|_| {
+        let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").ok_or_else(|| {
             tracing::error!("STORAGE_DIRPATH env variable required");
             PluginError::InitError
         })?;
 
+        // Operators may bring their own identity instead of generating one
+        match (
+            server_plugin::reload::var("DID_DOCUMENT_PATH"),
+            server_plugin::reload::var("DID_KEYS_PATH"),
+        ) {
+            (Some(diddoc_path), Some(keys_path)) => {
+                didgen::import_identity(&storage_dirpath, &diddoc_path, &keys_path).map_err(
+                    |err| {
+                        tracing::error!("failed to import identity from {diddoc_path}: {err}");
+                        PluginError::InitError
+                    },
+                )?;
+
+                return Ok(());
+            }
+            (None, None) => (),
+            _ => {
+                tracing::error!("DID_DOCUMENT_PATH and DID_KEYS_PATH must be set together");
+                return Err(PluginError::InitError);
+            }
+        }
+
         if didgen::validate_diddoc(&storage_dirpath).is_err() {
             tracing::debug!("diddoc validation failed, will generate one");
 
-            let server_public_domain = std::env::var("SERVER_PUBLIC_DOMAIN").map_err(|_| {
-                tracing::error!("SERVER_PUBLIC_DOMAIN env variable required");
-                PluginError::InitError
-            })?;
+            let server_public_domain = server_plugin::reload::var("SERVER_PUBLIC_DOMAIN")
+                .ok_or_else(|| {
+                    tracing::error!("SERVER_PUBLIC_DOMAIN env variable required");
+                    PluginError::InitError
+                })?;
 
             didgen::didgen(&storage_dirpath, &server_public_domain).map_err(|_| {
                 tracing::error!("failed to generate an initial keystore and its DID document");
@@ -37,7 +88,39 @@ impl Plugin for DidEndpointPlugin {
         Ok(())
     }
 
+    fn self_test(&self) -> Result<(), PluginError> {
+        let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").unwrap_or_default();
+
+        // The mediator must control the keys it advertises
+        didgen::self_test(&storage_dirpath).map_err(|err| {
+            tracing::error!("identity self-test failed: {err}");
+            PluginError::InitError
+        })
+    }
+
     fn routes(&self) -> Router {
-        web::routes()
+        let routes = web::routes();
+
+        // Administrative routes are opt-in, and require API keys
+        let admin_enabled =
+            server_plugin::reload::var("ADMIN_API_ENABLED").is_some_and(|v| v == "true");
+        match server_plugin::reload::var("STORAGE_DIRPATH") {
+            Some(storage_dirpath) if admin_enabled => {
+                routes.merge(web::admin_routes(&storage_dirpath, self.metrics.clone()))
+            }
+            _ => routes,
+        }
+    }
+
+    fn openapi(&self) -> Option<OpenApiDoc> {
+        let mut doc = web::ApiDoc::openapi();
+
+        let admin_enabled =
+            server_plugin::reload::var("ADMIN_API_ENABLED").is_some_and(|v| v == "true");
+        if admin_enabled {
+            doc.merge(web::AdminApiDoc::openapi());
+        }
+
+        Some(doc)
     }
 }
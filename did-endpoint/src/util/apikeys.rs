@@ -0,0 +1,437 @@
+//! API keys authenticating the HTTP endpoints that are not DIDComm endpoints,
+//! e.g. administrative routes and the invitation generator.
+//!
+//! Keys are granted scopes, one per group of routes, and only their SHA-256
+//! digest is persisted. A key is presented as `<id>.<secret>` in either the
+//! `X-API-Key` header or as a bearer token.
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, Request, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    Router,
+};
+use did_utils::crypto::{
+    constant_time::{ct_eq, ct_find},
+    sha256_hash::sha256_hash,
+};
+use fd_lock::RwLock;
+use multibase::Base;
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs::File, str::FromStr, sync::Arc};
+use utoipa::{
+    openapi::{
+        security::{self, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+        Components, OpenApi,
+    },
+    Modify, ToSchema,
+};
+
+use super::filesystem::{FileSystem, StdFileSystem};
+
+/// Header carrying API keys, as an alternative to bearer tokens
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Security schemes of API keys in OpenAPI documents, as a header or as a
+/// bearer token. Routes requiring API keys reference either.
+pub const API_KEY_SECURITY_SCHEMES: [&str; 2] = ["api_key", "bearer"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiKeyError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("corrupted key file: {0}")]
+    ParseError(serde_json::Error),
+    #[error("unknown API key")]
+    NotFound,
+    #[error("API key not valid for scope `{0}`")]
+    Unauthorized(ApiKeyScope),
+    #[error("unknown scope `{0}`")]
+    InvalidScope(String),
+}
+
+/// Group of routes an API key grants access to
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyScope {
+    /// Administrative routes, including the management of API keys
+    Admin,
+    /// Generation of out-of-band invitations
+    Invitations,
+    /// Export of usage records
+    Usage,
+}
+
+impl FromStr for ApiKeyScope {
+    type Err = ApiKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_owned()))
+            .map_err(|_| ApiKeyError::InvalidScope(s.to_owned()))
+    }
+}
+
+impl fmt::Display for ApiKeyScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scope = serde_json::to_value(self).map_err(|_| fmt::Error)?;
+        write!(f, "{}", scope.as_str().unwrap_or_default())
+    }
+}
+
+/// Persisted record of an API key
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub created_time: i64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_time: Option<i64>,
+
+    /// Multibase-encoded SHA-256 digest of the secret
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub digest: String,
+}
+
+/// File-based store of API keys
+pub struct ApiKeyStore<'a> {
+    fs: &'a mut dyn FileSystem,
+    dirpath: String,
+}
+
+impl<'a> ApiKeyStore<'a> {
+    pub fn new(fs: &'a mut dyn FileSystem, storage_dirpath: &str) -> Self {
+        Self {
+            fs,
+            dirpath: storage_dirpath.to_owned(),
+        }
+    }
+
+    /// Creates a key, returning its record and the key to hand over,
+    /// which cannot be retrieved afterwards.
+    pub fn create(
+        &mut self,
+        name: &str,
+        scopes: &[ApiKeyScope],
+        now: i64,
+    ) -> Result<(ApiKey, String), ApiKeyError> {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let secret = format!(
+            "{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+
+        let key = ApiKey {
+            id: id.clone(),
+            name: name.to_owned(),
+            scopes: scopes.to_vec(),
+            created_time: now,
+            revoked_time: None,
+            digest: digest(&secret),
+        };
+
+        let mut lockfile = self.lockfile()?;
+        let _guard = lockfile
+            .as_mut()
+            .map(RwLock::write)
+            .transpose()
+            .map_err(ApiKeyError::IoError)?;
+
+        let mut keys = self.read()?;
+        keys.push(key.clone());
+        self.write(&keys)?;
+
+        Ok((key, format!("{id}.{secret}")))
+    }
+
+    /// Lists keys, without their digests
+    pub fn list(&self) -> Result<Vec<ApiKey>, ApiKeyError> {
+        let keys = self.read()?;
+        Ok(keys
+            .into_iter()
+            .map(|key| ApiKey {
+                digest: String::new(),
+                ..key
+            })
+            .collect())
+    }
+
+    /// Revokes a key
+    pub fn revoke(&mut self, id: &str, now: i64) -> Result<(), ApiKeyError> {
+        let mut lockfile = self.lockfile()?;
+        let _guard = lockfile
+            .as_mut()
+            .map(RwLock::write)
+            .transpose()
+            .map_err(ApiKeyError::IoError)?;
+
+        let mut keys = self.read()?;
+        let key = keys
+            .iter_mut()
+            .find(|key| key.id == id && key.revoked_time.is_none())
+            .ok_or(ApiKeyError::NotFound)?;
+        key.revoked_time = Some(now);
+
+        self.write(&keys)
+    }
+
+    /// Authenticates a key presented for a scope.
+    ///
+    /// Unknown, revoked and mismatching keys take the same path, so that
+    /// response times do not reveal whether a key identifier exists.
+    pub fn authenticate(&self, presented: &str, scope: ApiKeyScope) -> Result<ApiKey, ApiKeyError> {
+        let (id, secret) = presented.split_once('.').unwrap_or((presented, ""));
+        let keys = self.read()?;
+
+        let key = ct_find(&keys, |key| {
+            ct_eq(key.id.as_bytes(), id.as_bytes()) & key.revoked_time.is_none()
+        });
+
+        // Secrets are checked against a placeholder for unknown keys
+        let expected = key
+            .and_then(|key| multibase::decode(&key.digest).ok())
+            .map_or(vec![0; 32], |(_, digest)| digest);
+        let valid = ct_eq(&expected, &sha256_hash(secret.as_bytes())) & key.is_some();
+
+        let key = key
+            .filter(|_| valid)
+            .cloned()
+            .ok_or(ApiKeyError::NotFound)?;
+
+        if !key.scopes.contains(&scope) {
+            return Err(ApiKeyError::Unauthorized(scope));
+        }
+
+        Ok(key)
+    }
+
+    fn path(&self) -> String {
+        format!("{}/apikeys.json", self.dirpath)
+    }
+
+    /// Opens the file locked across read-modify-write cycles, apart from
+    /// the key file which gets replaced on writes
+    fn lockfile(&mut self) -> Result<Option<RwLock<File>>, ApiKeyError> {
+        self.fs
+            .create_dir_all(&self.dirpath)
+            .map_err(ApiKeyError::IoError)?;
+        self.fs
+            .lockfile(&format!("{}/apikeys.lock", self.dirpath))
+            .map_err(ApiKeyError::IoError)
+    }
+
+    fn read(&self) -> Result<Vec<ApiKey>, ApiKeyError> {
+        match self.fs.read_to_string(&self.path()) {
+            Ok(content) => serde_json::from_str(&content).map_err(ApiKeyError::ParseError),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(err) => Err(ApiKeyError::IoError(err)),
+        }
+    }
+
+    fn write(&mut self, keys: &[ApiKey]) -> Result<(), ApiKeyError> {
+        let content = serde_json::to_string_pretty(keys).map_err(ApiKeyError::ParseError)?;
+
+        self.fs
+            .create_dir_all(&self.dirpath)
+            .map_err(ApiKeyError::IoError)?;
+        self.fs
+            .write_atomic(&self.path(), &content)
+            .map_err(ApiKeyError::IoError)
+    }
+}
+
+fn digest(secret: &str) -> String {
+    multibase::encode(Base::Base58Btc, sha256_hash(secret.as_bytes()))
+}
+
+/// Restricts routes to requests presenting an API key granted a scope,
+/// looked up in the key store under the storage directory.
+pub fn require_api_key(router: Router, storage_dirpath: &str, scope: ApiKeyScope) -> Router {
+    router.route_layer(middleware::from_fn_with_state(
+        Arc::new((storage_dirpath.to_owned(), scope)),
+        check_api_key,
+    ))
+}
+
+async fn check_api_key<B>(
+    State(config): State<Arc<(String, ApiKeyScope)>>,
+    headers: HeaderMap,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    let (storage_dirpath, scope) = config.as_ref();
+
+    let presented = headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+        })
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let mut fs = StdFileSystem;
+    match ApiKeyStore::new(&mut fs, storage_dirpath).authenticate(presented, *scope) {
+        Ok(_) => Ok(next.run(request).await),
+        Err(ApiKeyError::NotFound) => Err(StatusCode::UNAUTHORIZED),
+        Err(ApiKeyError::Unauthorized(_)) => Err(StatusCode::FORBIDDEN),
+        Err(err) => {
+            tracing::error!("failed to authenticate API key: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Declares the security schemes of API keys in OpenAPI documents
+pub struct ApiKeySecurity;
+
+impl Modify for ApiKeySecurity {
+    fn modify(&self, openapi: &mut OpenApi) {
+        let [header, bearer] = API_KEY_SECURITY_SCHEMES;
+        let components = openapi.components.get_or_insert_with(Components::new);
+
+        components.add_security_scheme(
+            header,
+            SecurityScheme::ApiKey(security::ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))),
+        );
+        components.add_security_scheme(
+            bearer,
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_utils::MemoryFileSystem;
+
+    #[test]
+    fn can_create_authenticate_and_revoke_keys() {
+        let mut fs = MemoryFileSystem::default();
+        let mut store = ApiKeyStore::new(&mut fs, "storage");
+
+        let (key, secret) = store.create("ops", &[ApiKeyScope::Admin], 100).unwrap();
+        assert!(secret.starts_with(&format!("{}.", key.id)));
+        assert!(!key.digest.contains(secret.split_once('.').unwrap().1));
+
+        assert_eq!(
+            store.authenticate(&secret, ApiKeyScope::Admin).unwrap().id,
+            key.id
+        );
+        assert!(matches!(
+            store.authenticate(&secret, ApiKeyScope::Usage),
+            Err(ApiKeyError::Unauthorized(ApiKeyScope::Usage))
+        ));
+        assert!(matches!(
+            store.authenticate(&format!("{}.forged", key.id), ApiKeyScope::Admin),
+            Err(ApiKeyError::NotFound)
+        ));
+
+        // Listed keys do not disclose digests
+        let keys = store.list().unwrap();
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].digest.is_empty());
+
+        store.revoke(&key.id, 200).unwrap();
+        assert!(matches!(
+            store.authenticate(&secret, ApiKeyScope::Admin),
+            Err(ApiKeyError::NotFound)
+        ));
+        assert!(matches!(
+            store.revoke(&key.id, 300),
+            Err(ApiKeyError::NotFound)
+        ));
+        assert_eq!(store.list().unwrap()[0].revoked_time, Some(200));
+    }
+
+    #[test]
+    fn should_not_lose_keys_created_concurrently() {
+        let dirpath = std::env::temp_dir().join(format!("apikeys-{}", uuid::Uuid::new_v4()));
+        let dirpath = dirpath.to_str().unwrap().to_string();
+
+        // Each writer holds its own handles, as separate processes would
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let dirpath = dirpath.clone();
+                std::thread::spawn(move || {
+                    let mut fs = StdFileSystem;
+                    let mut store = ApiKeyStore::new(&mut fs, &dirpath);
+                    store
+                        .create(&format!("ops-{i}"), &[ApiKeyScope::Admin], 100)
+                        .unwrap();
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut fs = StdFileSystem;
+        assert_eq!(ApiKeyStore::new(&mut fs, &dirpath).list().unwrap().len(), 8);
+
+        std::fs::remove_dir_all(&dirpath).unwrap();
+    }
+
+    /// Compares timings, too noisy for shared runners. Run with:
+    /// `cargo test --release -p did-endpoint -- --ignored timing`
+    #[test]
+    #[ignore]
+    fn should_not_reveal_known_keys_through_timing() {
+        let mut fs = MemoryFileSystem::default();
+        let mut store = ApiKeyStore::new(&mut fs, "storage");
+        let keys: Vec<_> = (0..32)
+            .map(|i| store.create(&format!("ops-{i}"), &[ApiKeyScope::Admin], 100))
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let (first, _) = &keys[0];
+        let (last, _) = &keys[31];
+        let attempts = [
+            format!("{}.forged", first.id),
+            format!("{}.forged", last.id),
+            format!("{}.forged", uuid::Uuid::new_v4().simple()),
+        ];
+
+        // Median durations of failed attempts, robust to scheduling noise
+        let durations: Vec<_> = attempts
+            .iter()
+            .map(|presented| {
+                let mut durations: Vec<_> = (0..51)
+                    .map(|_| {
+                        let start = std::time::Instant::now();
+                        let result = store.authenticate(presented, ApiKeyScope::Admin);
+                        assert!(matches!(result, Err(ApiKeyError::NotFound)));
+                        start.elapsed().as_secs_f64()
+                    })
+                    .collect();
+                durations.sort_by(f64::total_cmp);
+                durations[25]
+            })
+            .collect();
+
+        for duration in &durations[1..] {
+            let ratio = durations[0] / duration;
+            assert!((0.33..3.0).contains(&ratio), "{durations:?}");
+        }
+    }
+
+    #[test]
+    fn can_parse_scopes() {
+        assert_eq!(
+            "invitations".parse::<ApiKeyScope>().unwrap(),
+            ApiKeyScope::Invitations
+        );
+        assert_eq!(ApiKeyScope::Usage.to_string(), "usage");
+        assert!(matches!(
+            "metrics".parse::<ApiKeyScope>(),
+            Err(ApiKeyError::InvalidScope(_))
+        ));
+    }
+}
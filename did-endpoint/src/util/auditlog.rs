@@ -0,0 +1,303 @@
+use chrono::Utc;
+use did_utils::{
+    crypto::{ed25519::Ed25519KeyPair, sha256_hash::sha256_multihash, traits::CoreSign},
+    key_jwk::jwk::Jwk,
+};
+use multibase::Base;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::ToSchema;
+
+use super::filesystem::FileSystem;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuditLogError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("parse error")]
+    ParseError(serde_json::Error),
+    #[error("serde error")]
+    SerdeError(serde_json::Error),
+    #[error("unusable signing key")]
+    InvalidSigner,
+    #[error("broken hash chain at entry {0}")]
+    BrokenChain(u64),
+    #[error("invalid signature on entry {0}")]
+    InvalidSignature(u64),
+    #[error("missing signature on entry {0}")]
+    MissingSignature(u64),
+}
+
+/// Kind of keystore mutation
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyEvent {
+    Store,
+    Rotate,
+    Delete,
+    Import,
+}
+
+/// Entry of the audit log, linked to its predecessor by hash.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp: String,
+    pub event: KeyEvent,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub kid: Option<String>,
+    #[schema(value_type = Object)]
+    pub public_key: Jwk,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub prev_hash: Option<String>,
+    pub hash: String,
+
+    /// Signature over the hash, required on every entry. Entries
+    /// without one are read, only to be rejected by [`verify`].
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub signature: Option<String>,
+}
+
+impl AuditEntry {
+    /// Computes the hash of the entry's content, which covers
+    /// the hash of its predecessor.
+    fn compute_hash(&self) -> String {
+        let content = json!({
+            "seq": self.seq,
+            "timestamp": self.timestamp,
+            "event": self.event,
+            "kid": self.kid,
+            "public_key": self.public_key,
+            "prev_hash": self.prev_hash,
+        });
+
+        multibase::encode(
+            Base::Base58Btc,
+            sha256_multihash(content.to_string().as_bytes()),
+        )
+    }
+}
+
+/// Append-only, hash-chained log of keystore mutations.
+///
+/// Entries are stored as JSON lines next to the keystore. Each entry is
+/// signed with the mediator's assertion key, so that neither entries nor
+/// the whole chain can be rewritten without it.
+pub struct AuditLog<'a> {
+    fs: &'a mut dyn FileSystem,
+    dirpath: String,
+}
+
+impl<'a> AuditLog<'a> {
+    /// Constructs audit log of the keystore at the storage location.
+    pub fn new(fs: &'a mut dyn FileSystem, storage_dirpath: &str) -> Self {
+        Self {
+            fs,
+            dirpath: format!("{storage_dirpath}/keystore"),
+        }
+    }
+
+    /// Gets path
+    pub fn path(&self) -> String {
+        format!("{}/audit.jsonl", self.dirpath)
+    }
+
+    /// Reads all entries, oldest first.
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, AuditLogError> {
+        let exists = self
+            .fs
+            .read_dir_files(&self.dirpath)
+            .is_ok_and(|paths| paths.contains(&self.path()));
+        if !exists {
+            return Ok(vec![]);
+        }
+
+        self.fs
+            .read_to_string(&self.path())
+            .map_err(AuditLogError::IoError)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(AuditLogError::ParseError))
+            .collect()
+    }
+
+    /// Appends an entry for a keystore mutation, signed with
+    /// the private JWK of the signer.
+    pub fn record(
+        &mut self,
+        event: KeyEvent,
+        kid: Option<&str>,
+        public_key: &Jwk,
+        signer: &Jwk,
+    ) -> Result<AuditEntry, AuditLogError> {
+        let last = self.entries()?.pop();
+
+        let mut entry = AuditEntry {
+            seq: last.as_ref().map_or(0, |e| e.seq + 1),
+            timestamp: Utc::now().to_rfc3339(),
+            event,
+            kid: kid.map(str::to_owned),
+            public_key: public_key.clone(),
+            prev_hash: last.map(|e| e.hash),
+            hash: String::new(),
+            signature: None,
+        };
+        entry.hash = entry.compute_hash();
+
+        let keypair: Ed25519KeyPair = signer
+            .clone()
+            .try_into()
+            .map_err(|_| AuditLogError::InvalidSigner)?;
+        let signature = keypair
+            .sign(entry.hash.as_bytes())
+            .map_err(|_| AuditLogError::InvalidSigner)?;
+        entry.signature = Some(multibase::encode(Base::Base58Btc, signature));
+
+        let line = serde_json::to_string(&entry).map_err(AuditLogError::SerdeError)?;
+        self.fs
+            .create_dir_all(&self.dirpath)
+            .map_err(AuditLogError::IoError)?;
+        self.fs
+            .append(&self.path(), &format!("{line}\n"))
+            .map_err(AuditLogError::IoError)?;
+
+        Ok(entry)
+    }
+}
+
+/// Checks the hash chain of audit entries and their signatures
+/// against the public key of the signer. Every entry must be signed.
+pub fn verify(entries: &[AuditEntry], signer: &Jwk) -> Result<(), AuditLogError> {
+    let keypair: Ed25519KeyPair = signer
+        .clone()
+        .try_into()
+        .map_err(|_| AuditLogError::InvalidSigner)?;
+
+    let mut prev_hash = None;
+    for (seq, entry) in entries.iter().enumerate() {
+        if entry.seq != seq as u64
+            || entry.prev_hash != prev_hash
+            || entry.hash != entry.compute_hash()
+        {
+            return Err(AuditLogError::BrokenChain(seq as u64));
+        }
+
+        let signature = entry
+            .signature
+            .as_ref()
+            .ok_or(AuditLogError::MissingSignature(entry.seq))?;
+        let (_, signature) =
+            multibase::decode(signature).map_err(|_| AuditLogError::InvalidSignature(entry.seq))?;
+        keypair
+            .verify(entry.hash.as_bytes(), &signature)
+            .map_err(|_| AuditLogError::InvalidSignature(entry.seq))?;
+
+        prev_hash = Some(entry.hash.clone());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::util::test_utils::MemoryFileSystem;
+    use did_utils::crypto::{traits::Generate, x25519::X25519KeyPair};
+
+    fn signer() -> Jwk {
+        Ed25519KeyPair::new_with_seed(&[1; 32])
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    fn public_key() -> Jwk {
+        let jwk: Jwk = X25519KeyPair::new().unwrap().try_into().unwrap();
+        crate::util::keystore::ToPublic::to_public(&jwk)
+    }
+
+    #[test]
+    fn can_record_and_verify_entries() {
+        let mut mock_fs = MemoryFileSystem::new();
+        let mut log = AuditLog::new(&mut mock_fs, "/storage");
+        assert!(log.entries().unwrap().is_empty());
+
+        let first = log
+            .record(KeyEvent::Store, None, &public_key(), &signer())
+            .unwrap();
+        let second = log
+            .record(KeyEvent::Import, Some("key-1"), &public_key(), &signer())
+            .unwrap();
+
+        assert_eq!(second.seq, 1);
+        assert_eq!(second.prev_hash, Some(first.hash));
+        assert!(first.signature.is_some());
+        assert!(second.signature.is_some());
+
+        let entries = log.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(verify(&entries, &signer()).is_ok());
+    }
+
+    #[test]
+    fn should_detect_tampering() {
+        let mut mock_fs = MemoryFileSystem::new();
+        let mut log = AuditLog::new(&mut mock_fs, "/storage");
+        for _ in 0..3 {
+            log.record(KeyEvent::Store, None, &public_key(), &signer())
+                .unwrap();
+        }
+
+        let entries = log.entries().unwrap();
+
+        // Altered content
+        let mut altered = entries.clone();
+        altered[1].event = KeyEvent::Delete;
+        assert!(matches!(
+            verify(&altered, &signer()),
+            Err(AuditLogError::BrokenChain(1))
+        ));
+
+        // Removed entry
+        let mut truncated = entries.clone();
+        truncated.remove(1);
+        assert!(matches!(
+            verify(&truncated, &signer()),
+            Err(AuditLogError::BrokenChain(1))
+        ));
+
+        // Entries signed by another key
+        let other: Jwk = Ed25519KeyPair::new_with_seed(&[2; 32])
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(matches!(
+            verify(&entries, &other),
+            Err(AuditLogError::InvalidSignature(0))
+        ));
+
+        // Stripped signature
+        let mut stripped = entries.clone();
+        stripped[2].signature = None;
+        assert!(matches!(
+            verify(&stripped, &signer()),
+            Err(AuditLogError::MissingSignature(2))
+        ));
+
+        // Chain rewritten without signatures
+        let mut rewritten = entries.clone();
+        let mut prev_hash = None;
+        for entry in &mut rewritten {
+            entry.event = KeyEvent::Delete;
+            entry.prev_hash = prev_hash;
+            entry.hash = entry.compute_hash();
+            entry.signature = None;
+            prev_hash = Some(entry.hash.clone());
+        }
+        assert!(matches!(
+            verify(&rewritten, &signer()),
+            Err(AuditLogError::MissingSignature(0))
+        ));
+    }
+}
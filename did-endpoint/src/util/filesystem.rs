@@ -1,7 +1,6 @@
-use nix::fcntl::{flock, FlockArg};
-use std::fs::OpenOptions;
-use std::io::{Error as IoError, ErrorKind, Result as IoResult};
-use std::os::unix::io::AsRawFd;
+use fd_lock::RwLock;
+use std::fs::{File, OpenOptions};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, Write};
 
 // Define a trait for file system operations
 pub trait FileSystem: Send + 'static {
@@ -10,6 +9,28 @@ pub trait FileSystem: Send + 'static {
     fn read_dir_files(&self, path: &str) -> IoResult<Vec<String>>;
     fn create_dir_all(&mut self, path: &str) -> IoResult<()>;
     fn write_with_lock(&self, path: &str, content: &str) -> IoResult<()>;
+
+    /// Replaces file content such that readers never observe a partial write
+    fn write_atomic(&mut self, path: &str, content: &str) -> IoResult<()> {
+        self.write(path, content)
+    }
+
+    /// Appends content to a file, creating it if missing
+    fn append(&mut self, path: &str, content: &str) -> IoResult<()> {
+        let existing = self.read_to_string(path).unwrap_or_default();
+        self.write(path, &(existing + content))
+    }
+
+    /// Removes a file, if supported
+    fn remove_file(&mut self, _path: &str) -> IoResult<()> {
+        Err(IoError::from(ErrorKind::Unsupported))
+    }
+
+    /// Opens a sidecar file to lock, serializing read-modify-write cycles
+    /// across processes. None if the file system is not shared.
+    fn lockfile(&self, _path: &str) -> IoResult<Option<RwLock<File>>> {
+        Ok(None)
+    }
     // Add other file system operations as needed
 }
 
@@ -51,68 +72,180 @@ impl FileSystem for StdFileSystem {
         options.read(true);
         options.write(true);
         options.create(true);
+        options.truncate(false);
 
-        let file = options.open(path)?;
+        let mut file = RwLock::new(options.open(path)?);
 
-        // Acquire an exclusive lock before writing to the file
-        flock(file.as_raw_fd(), FlockArg::LockExclusive)
+        // Acquire an exclusive lock before writing to the file. It is
+        // released when the guard goes out of scope.
+        let mut guard = file
+            .write()
             .map_err(|_| IoError::new(ErrorKind::Other, "Error acquiring file lock"))?;
 
-        std::fs::write(&path, &content).expect("Error saving base64-encoded image to file");
+        // Write through the locked handle, as locks are mandatory on
+        // Windows and would reject writes from any other handle
+        guard.set_len(0)?;
+        guard.write_all(content.as_bytes())?;
 
-        // Release the lock after writing to the file
-        flock(file.as_raw_fd(), FlockArg::Unlock).expect("Error releasing file lock");
         Ok(())
     }
 
+    fn write_atomic(&mut self, path: &str, content: &str) -> IoResult<()> {
+        // Serialize concurrent writers on a sidecar lock file, since the
+        // target file itself gets replaced and cannot hold the lock
+        let mut lockfile = self.lockfile(&format!("{path}.lock"))?;
+        let _guard = lockfile
+            .as_mut()
+            .map(RwLock::write)
+            .transpose()
+            .map_err(|_| IoError::other("Error acquiring file lock"))?;
+
+        // Write to a temporary file in the same directory, then rename it
+        // over the target, which replaces it atomically on both POSIX and
+        // Windows filesystems
+        let tmp_path = format!("{path}.{}.tmp", std::process::id());
+        let result = (|| {
+            let mut tmp_file = std::fs::File::create(&tmp_path)?;
+            tmp_file.write_all(content.as_bytes())?;
+            tmp_file.sync_all()?;
+            std::fs::rename(&tmp_path, path)
+        })();
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+
+        result
+    }
+
+    fn append(&mut self, path: &str, content: &str) -> IoResult<()> {
+        let mut file = RwLock::new(OpenOptions::new().append(true).create(true).open(path)?);
+
+        let mut guard = file
+            .write()
+            .map_err(|_| IoError::other("Error acquiring file lock"))?;
+
+        guard.write_all(content.as_bytes())?;
+        guard.sync_all()
+    }
+
+    fn remove_file(&mut self, path: &str) -> IoResult<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn lockfile(&self, path: &str) -> IoResult<Option<RwLock<File>>> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        Ok(Some(RwLock::new(file)))
+    }
+
     // Implement other file system operations as needed
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
-
     use super::*;
+    use crate::util::test_utils::MemoryFileSystem;
+
+    #[test]
+    fn can_mock_fs_operations() {
+        let mut mock_fs = MemoryFileSystem::new();
 
-    // Now, for testing, create a mock implementation of the trait
-    #[derive(Default)]
-    struct MockFileSystem {
-        map: HashMap<String, String>,
+        let res = mock_fs.write("/file.txt", "2456535e-a316-4d9e-8ab4-74a33d75d1fa");
+        assert!(res.is_ok());
+
+        let content = mock_fs.read_to_string("/file.txt").unwrap();
+        assert_eq!(&content, "2456535e-a316-4d9e-8ab4-74a33d75d1fa");
     }
 
-    impl FileSystem for MockFileSystem {
-        fn read_to_string(&self, path: &str) -> IoResult<String> {
-            Ok(self.map.get(path).cloned().unwrap_or_default())
-        }
+    #[test]
+    fn can_write_atomically() {
+        let dirpath = std::env::temp_dir().join(format!("atomic-{}", uuid::Uuid::new_v4()));
+        let dirpath = dirpath.to_str().unwrap();
 
-        fn write(&mut self, path: &str, content: &str) -> IoResult<()> {
-            self.map.insert(path.to_string(), content.to_string());
-            Ok(())
-        }
+        let mut fs = StdFileSystem;
+        fs.create_dir_all(dirpath).unwrap();
 
-        fn read_dir_files(&self, _path: &str) -> IoResult<Vec<String>> {
-            Ok(vec![])
-        }
+        let path = format!("{dirpath}/file.txt");
+        fs.write_atomic(&path, "first").unwrap();
+        fs.write_atomic(&path, "second").unwrap();
+        assert_eq!(fs.read_to_string(&path).unwrap(), "second");
 
-        fn create_dir_all(&mut self, _path: &str) -> IoResult<()> {
-            Ok(())
-        }
+        // No temporary file is left behind
+        let files = fs.read_dir_files(dirpath).unwrap();
+        assert!(files.iter().all(|f| !f.ends_with(".tmp")));
 
-        fn write_with_lock(&self, _path: &str, _content: &str) -> IoResult<()>{
-            Ok(())
-        }
+        std::fs::remove_dir_all(dirpath).unwrap();
+    }
+
+    #[test]
+    fn can_write_with_lock() {
+        let dirpath = std::env::temp_dir().join(format!("locked-{}", uuid::Uuid::new_v4()));
+        let dirpath = dirpath.to_str().unwrap();
+
+        let mut fs = StdFileSystem;
+        fs.create_dir_all(dirpath).unwrap();
+
+        let path = format!("{dirpath}/file.txt");
+        fs.write_with_lock(&path, "a longer first content").unwrap();
+        fs.write_with_lock(&path, "second").unwrap();
+        assert_eq!(fs.read_to_string(&path).unwrap(), "second");
+
+        std::fs::remove_dir_all(dirpath).unwrap();
     }
 
+    #[test]
+    fn can_append() {
+        let dirpath = std::env::temp_dir().join(format!("append-{}", uuid::Uuid::new_v4()));
+        let dirpath = dirpath.to_str().unwrap();
+
+        let mut fs = StdFileSystem;
+        fs.create_dir_all(dirpath).unwrap();
 
+        let path = format!("{dirpath}/file.txt");
+        fs.append(&path, "first\n").unwrap();
+        fs.append(&path, "second\n").unwrap();
+        assert_eq!(fs.read_to_string(&path).unwrap(), "first\nsecond\n");
+
+        fs.remove_file(&path).unwrap();
+        assert!(fs.read_to_string(&path).is_err());
+
+        std::fs::remove_dir_all(dirpath).unwrap();
+    }
 
     #[test]
-    fn can_mock_fs_operations() {
-        let mut mock_fs = MockFileSystem::default();
+    fn can_serialize_concurrent_writers() {
+        let dirpath = std::env::temp_dir().join(format!("concurrent-{}", uuid::Uuid::new_v4()));
+        let dirpath = dirpath.to_str().unwrap().to_string();
+
+        StdFileSystem.create_dir_all(&dirpath).unwrap();
+        let path = format!("{dirpath}/file.txt");
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let content = i.to_string().repeat(1024);
+                    StdFileSystem.write_with_lock(&path, &content).unwrap();
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
 
-        let res = mock_fs.write("/file.txt", "2456535e-a316-4d9e-8ab4-74a33d75d1fa");
-        assert!(res.is_ok());
+        // Content must stem from a single writer
+        let content = StdFileSystem.read_to_string(&path).unwrap();
+        assert_eq!(content.len(), 1024);
+        assert!(content
+            .chars()
+            .all(|c| c == content.chars().next().unwrap()));
 
-        let content = mock_fs.read_to_string("/file.txt").unwrap();
-        assert_eq!(&content, "2456535e-a316-4d9e-8ab4-74a33d75d1fa");
+        std::fs::remove_dir_all(&dirpath).unwrap();
     }
 }
@@ -0,0 +1,395 @@
+//! Idempotent submission of messages over HTTP.
+//!
+//! Edge agents retry submissions on network failures, so the same message
+//! may reach the mediator more than once. Submissions identified by an
+//! `Idempotency-Key` header, or else by the `id` of a plaintext message,
+//! have their response recorded for a time to live. Retries get the
+//! recorded response replayed instead of being processed again.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use chrono::Utc;
+use did_utils::crypto::sha256_hash::sha256_hash;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+/// Header identifying a submission across retries
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Header flagging replayed responses
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Time to live of recorded responses by default, in seconds
+const DEFAULT_TTL: i64 = 24 * 3600;
+
+/// Response recorded for replay
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedResponse {
+    pub status: StatusCode,
+    pub content_type: Option<HeaderValue>,
+    pub body: Bytes,
+}
+
+impl IntoResponse for RecordedResponse {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, self.body).into_response();
+        let headers = response.headers_mut();
+
+        if let Some(content_type) = self.content_type {
+            headers.insert(header::CONTENT_TYPE, content_type);
+        }
+        headers.insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+
+        response
+    }
+}
+
+/// Outcome of looking up a submission
+#[derive(Debug, Clone, PartialEq)]
+pub enum Submission {
+    /// First submission, to process and then complete
+    New,
+    /// Same submission, still being processed
+    InFlight,
+    /// Different submission reusing the key
+    Mismatch,
+    /// Same submission, already processed
+    Replay(RecordedResponse),
+}
+
+#[derive(Debug)]
+struct Record {
+    /// Digest of the submitted content
+    digest: [u8; 32],
+    response: Option<RecordedResponse>,
+    expires_time: i64,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    entries: HashMap<String, Record>,
+
+    /// Keys in order of expiry
+    expiries: VecDeque<(i64, String)>,
+}
+
+impl Records {
+    fn purge_expired(&mut self, now: i64) {
+        while let Some((expires_time, _)) = self.expiries.front() {
+            if *expires_time > now {
+                break;
+            }
+
+            let (_, key) = self.expiries.pop_front().unwrap();
+            if self
+                .entries
+                .get(&key)
+                .is_some_and(|record| record.expires_time <= now)
+            {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+/// Responses of recent submissions, keyed by idempotency key.
+/// Clones share the same records.
+#[derive(Debug, Clone)]
+pub struct IdempotencyCache {
+    ttl: i64,
+    records: Arc<Mutex<Records>>,
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL)
+    }
+}
+
+impl IdempotencyCache {
+    /// Creates a cache recording responses for `ttl` seconds
+    pub fn new(ttl: i64) -> Self {
+        Self {
+            ttl,
+            records: Arc::default(),
+        }
+    }
+
+    /// Creates a cache with the time to live in `IDEMPOTENCY_TTL`, if set
+    pub fn from_env() -> Self {
+        server_plugin::reload::var("IDEMPOTENCY_TTL")
+            .and_then(|ttl| ttl.parse().ok())
+            .map(Self::new)
+            .unwrap_or_default()
+    }
+
+    /// Number of recorded submissions, including expired ones not yet purged
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Looks up a submission by key and content, recording it as in
+    /// flight if new
+    pub fn begin(&self, key: &str, content: &[u8], now: i64) -> Submission {
+        let mut records = self.records.lock().unwrap();
+        records.purge_expired(now);
+
+        let digest = sha256_hash(content);
+        if let Some(record) = records.entries.get(key) {
+            return if record.digest != digest {
+                Submission::Mismatch
+            } else {
+                record
+                    .response
+                    .clone()
+                    .map_or(Submission::InFlight, Submission::Replay)
+            };
+        }
+
+        let expires_time = now.saturating_add(self.ttl);
+        records.entries.insert(
+            key.to_owned(),
+            Record {
+                digest,
+                response: None,
+                expires_time,
+            },
+        );
+        records.expiries.push_back((expires_time, key.to_owned()));
+
+        Submission::New
+    }
+
+    /// Records the response to a submission, for retries to replay
+    pub fn complete(&self, key: &str, response: RecordedResponse) {
+        let mut records = self.records.lock().unwrap();
+        if let Some(record) = records.entries.get_mut(key) {
+            record.response = Some(response);
+        }
+    }
+
+    /// Forgets a submission, so that a retry gets processed
+    pub fn abandon(&self, key: &str) {
+        let mut records = self.records.lock().unwrap();
+        if records
+            .entries
+            .get(key)
+            .is_some_and(|record| record.response.is_none())
+        {
+            records.entries.remove(key);
+        }
+    }
+}
+
+/// Abandons submissions whose processing did not complete, e.g. as it
+/// panicked or the client went away
+struct InFlight<'a> {
+    cache: &'a IdempotencyCache,
+    key: &'a str,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.cache.abandon(self.key);
+    }
+}
+
+/// Makes the submission of messages to a router idempotent.
+///
+/// Responses are recorded unless they report server errors, which
+/// retries are expected to overcome. A retry arriving while the first
+/// submission is processed is answered with `409 Conflict`, and a key
+/// reused for different content with `422 Unprocessable Entity`.
+pub fn idempotent(router: Router, cache: IdempotencyCache) -> Router {
+    router.route_layer(middleware::from_fn_with_state(cache, check_idempotency))
+}
+
+async fn check_idempotency(
+    State(cache): State<IdempotencyCache>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let Ok(content) = hyper::body::to_bytes(body).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let key = parts
+        .headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|key| format!("key:{key}"))
+        .or_else(|| {
+            let message: serde_json::Value = serde_json::from_slice(&content).ok()?;
+            message["id"].as_str().map(|id| format!("id:{id}"))
+        });
+
+    let request = Request::from_parts(parts, Body::from(content.clone()));
+    let Some(key) = key else {
+        return next.run(request).await;
+    };
+
+    // Keys are scoped to the submission endpoint
+    let key = format!("{} {}", request.uri().path(), key);
+    match cache.begin(&key, &content, Utc::now().timestamp()) {
+        Submission::New => (),
+        Submission::InFlight => return StatusCode::CONFLICT.into_response(),
+        Submission::Mismatch => return StatusCode::UNPROCESSABLE_ENTITY.into_response(),
+        Submission::Replay(response) => return response.into_response(),
+    }
+
+    let _in_flight = InFlight {
+        cache: &cache,
+        key: &key,
+    };
+
+    let response = next.run(request).await;
+    if response.status().is_server_error() {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let Ok(body) = hyper::body::to_bytes(body).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    cache.complete(
+        &key,
+        RecordedResponse {
+            status: parts.status,
+            content_type: parts.headers.get(header::CONTENT_TYPE).cloned(),
+            body: body.clone(),
+        },
+    );
+
+    Response::from_parts(parts, axum::body::boxed(Body::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::util::ServiceExt;
+
+    fn app(cache: IdempotencyCache) -> (Router, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+
+        let router = Router::new().route(
+            "/submit",
+            post(move |body: Bytes| async move {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                if body.as_ref() == b"fail" {
+                    return (StatusCode::SERVICE_UNAVAILABLE, String::new());
+                }
+                (StatusCode::ACCEPTED, format!("processed #{n}"))
+            }),
+        );
+
+        (idempotent(router, cache), calls)
+    }
+
+    async fn submit(app: &Router, key: Option<&str>, body: &str) -> (StatusCode, String, bool) {
+        let mut request = Request::post("/submit");
+        if let Some(key) = key {
+            request = request.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::from(body.to_owned())).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let replayed = response.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap(), replayed)
+    }
+
+    #[tokio::test]
+    async fn can_replay_retried_submissions() {
+        let (app, calls) = app(IdempotencyCache::default());
+
+        let first = submit(&app, Some("abc"), "packed").await;
+        assert_eq!(
+            first,
+            (StatusCode::ACCEPTED, "processed #0".to_owned(), false)
+        );
+
+        let retry = submit(&app, Some("abc"), "packed").await;
+        assert_eq!(
+            retry,
+            (StatusCode::ACCEPTED, "processed #0".to_owned(), true)
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Reusing the key for another message is rejected
+        let (status, _, _) = submit(&app, Some("abc"), "other").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Plaintext messages are identified by their id
+        let message = r#"{"id":"1234","type":"https://didcomm.org/trust-ping/2.0/ping"}"#;
+        submit(&app, None, message).await;
+        let (_, body, replayed) = submit(&app, None, message).await;
+        assert_eq!((body.as_str(), replayed), ("processed #1", true));
+
+        // Submissions without identification are processed each time
+        submit(&app, None, "packed").await;
+        submit(&app, None, "packed").await;
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn should_not_record_server_errors() {
+        let (app, calls) = app(IdempotencyCache::default());
+
+        for _ in 0..2 {
+            let (status, _, replayed) = submit(&app, Some("abc"), "fail").await;
+            assert_eq!((status, replayed), (StatusCode::SERVICE_UNAVAILABLE, false));
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn can_expire_recorded_responses() {
+        let cache = IdempotencyCache::new(60);
+        let response = RecordedResponse {
+            status: StatusCode::ACCEPTED,
+            content_type: None,
+            body: Bytes::from_static(b"done"),
+        };
+
+        assert_eq!(cache.begin("a", b"msg", 1000), Submission::New);
+        assert_eq!(cache.begin("a", b"msg", 1010), Submission::InFlight);
+        cache.complete("a", response.clone());
+        assert_eq!(
+            cache.begin("a", b"msg", 1020),
+            Submission::Replay(response.clone())
+        );
+
+        assert_eq!(cache.begin("b", b"msg", 1030), Submission::New);
+        cache.abandon("b");
+        assert_eq!(cache.begin("b", b"msg", 1040), Submission::New);
+
+        // Expired records are purged as submissions come in
+        assert_eq!(cache.begin("a", b"msg", 1060), Submission::New);
+        assert_eq!(cache.len(), 2);
+        cache.begin("c", b"msg", 2000);
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,239 @@
+//! Metrics of storage operations.
+//!
+//! [`MeteredStore`] wraps a store, recording the count, errors and latency
+//! of each of its operations into [`StoreMetrics`], labeled by the entity
+//! stored and the operation. Metrics are rendered in the Prometheus text
+//! format, with latencies as histograms, so that storage regressions show
+//! up on dashboards.
+
+use did_utils::methods::cache::{CachedDocument, DIDDocumentStore};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Upper bounds of latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 8] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// Entity label of cached DID documents
+pub const DID_DOCUMENT_ENTITY: &str = "did_document";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct OperationStats {
+    count: u64,
+    errors: u64,
+    /// Counts of latencies within each bucket, not cumulated
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+}
+
+/// Metrics of storage operations, by entity and operation. Clones share
+/// the same metrics.
+#[derive(Debug, Clone, Default)]
+pub struct StoreMetrics {
+    stats: Arc<Mutex<BTreeMap<(String, String), OperationStats>>>,
+}
+
+impl StoreMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an operation, and whether it failed
+    pub fn record(&self, entity: &str, operation: &str, latency: Duration, failed: bool) {
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats
+            .entry((entity.to_owned(), operation.to_owned()))
+            .or_default();
+
+        let seconds = latency.as_secs_f64();
+        stats.count += 1;
+        stats.errors += u64::from(failed);
+        stats.latency_sum += seconds;
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            stats.buckets[bucket] += 1;
+        }
+    }
+
+    /// Number of operations recorded, and how many of them failed
+    pub fn count(&self, entity: &str, operation: &str) -> (u64, u64) {
+        let stats = self.stats.lock().unwrap();
+        stats
+            .get(&(entity.to_owned(), operation.to_owned()))
+            .map_or((0, 0), |stats| (stats.count, stats.errors))
+    }
+
+    /// Renders the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let stats = self.stats.lock().unwrap();
+        let mut output = String::new();
+
+        let _ = writeln!(
+            output,
+            "# HELP mediator_store_operations_total Storage operations\n\
+             # TYPE mediator_store_operations_total counter"
+        );
+        for ((entity, operation), stats) in stats.iter() {
+            let labels = format!("entity=\"{entity}\",operation=\"{operation}\"");
+            let _ = writeln!(
+                output,
+                "mediator_store_operations_total{{{labels}}} {}",
+                stats.count
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP mediator_store_operation_errors_total Failed storage operations\n\
+             # TYPE mediator_store_operation_errors_total counter"
+        );
+        for ((entity, operation), stats) in stats.iter() {
+            let labels = format!("entity=\"{entity}\",operation=\"{operation}\"");
+            let _ = writeln!(
+                output,
+                "mediator_store_operation_errors_total{{{labels}}} {}",
+                stats.errors
+            );
+        }
+
+        let metric = "mediator_store_operation_duration_seconds";
+        let _ = writeln!(
+            output,
+            "# HELP {metric} Latency of storage operations\n# TYPE {metric} histogram"
+        );
+        for ((entity, operation), stats) in stats.iter() {
+            let labels = format!("entity=\"{entity}\",operation=\"{operation}\"");
+            let mut cumulated = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(stats.buckets) {
+                cumulated += count;
+                let _ = writeln!(
+                    output,
+                    "{metric}_bucket{{{labels},le=\"{le}\"}} {cumulated}"
+                );
+            }
+            let _ = writeln!(
+                output,
+                "{metric}_bucket{{{labels},le=\"+Inf\"}} {}",
+                stats.count
+            );
+            let _ = writeln!(output, "{metric}_sum{{{labels}}} {}", stats.latency_sum);
+            let _ = writeln!(output, "{metric}_count{{{labels}}} {}", stats.count);
+        }
+
+        output
+    }
+}
+
+/// Store recording metrics of the operations of another
+pub struct MeteredStore<S> {
+    store: S,
+    entity: &'static str,
+    metrics: StoreMetrics,
+}
+
+impl<S> MeteredStore<S> {
+    pub fn new(store: S, entity: &'static str, metrics: StoreMetrics) -> Self {
+        Self {
+            store,
+            entity,
+            metrics,
+        }
+    }
+
+    fn meter<T, E>(&self, operation: &str, run: impl FnOnce(&S) -> Result<T, E>) -> Result<T, E> {
+        let started = Instant::now();
+        let outcome = run(&self.store);
+        self.metrics
+            .record(self.entity, operation, started.elapsed(), outcome.is_err());
+
+        outcome
+    }
+}
+
+impl<S: DIDDocumentStore> DIDDocumentStore for MeteredStore<S> {
+    fn get(&self, did: &str) -> Option<CachedDocument> {
+        // Missing entries are not failures
+        let started = Instant::now();
+        let entry = self.store.get(did);
+        self.metrics
+            .record(self.entity, "get", started.elapsed(), false);
+
+        entry
+    }
+
+    fn put(&self, did: &str, entry: CachedDocument) -> std::io::Result<()> {
+        self.meter("put", |store| store.put(did, entry))
+    }
+
+    fn evict(&self, did: &str) -> std::io::Result<bool> {
+        self.meter("evict", |store| store.evict(did))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use did_utils::methods::cache::InMemoryDocumentStore;
+    use std::io;
+
+    struct FailingStore;
+
+    impl DIDDocumentStore for FailingStore {
+        fn get(&self, _did: &str) -> Option<CachedDocument> {
+            None
+        }
+
+        fn put(&self, _did: &str, _entry: CachedDocument) -> io::Result<()> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn evict(&self, _did: &str) -> io::Result<bool> {
+            Err(io::Error::other("disk full"))
+        }
+    }
+
+    #[test]
+    fn test_metering_store_operations() {
+        let metrics = StoreMetrics::new();
+
+        let store = MeteredStore::new(
+            InMemoryDocumentStore::default(),
+            DID_DOCUMENT_ENTITY,
+            metrics.clone(),
+        );
+        assert!(store.get("did:web:alice.example").is_none());
+        assert!(!store.evict("did:web:alice.example").unwrap());
+
+        let store = MeteredStore::new(FailingStore, DID_DOCUMENT_ENTITY, metrics.clone());
+        assert!(store.evict("did:web:alice.example").is_err());
+
+        assert_eq!(metrics.count(DID_DOCUMENT_ENTITY, "get"), (1, 0));
+        assert_eq!(metrics.count(DID_DOCUMENT_ENTITY, "evict"), (2, 1));
+        assert_eq!(metrics.count(DID_DOCUMENT_ENTITY, "put"), (0, 0));
+    }
+
+    #[test]
+    fn test_rendering_store_metrics() {
+        let metrics = StoreMetrics::new();
+        metrics.record("did_document", "get", Duration::from_millis(3), false);
+        metrics.record("did_document", "get", Duration::from_millis(20), true);
+        metrics.record("did_document", "get", Duration::from_secs(9), false);
+
+        let output = metrics.render();
+        let labels = "entity=\"did_document\",operation=\"get\"";
+        for line in [
+            format!("mediator_store_operations_total{{{labels}}} 3"),
+            format!("mediator_store_operation_errors_total{{{labels}}} 1"),
+            format!("mediator_store_operation_duration_seconds_bucket{{{labels},le=\"0.001\"}} 0"),
+            format!("mediator_store_operation_duration_seconds_bucket{{{labels},le=\"0.005\"}} 1"),
+            format!("mediator_store_operation_duration_seconds_bucket{{{labels},le=\"0.05\"}} 2"),
+            format!("mediator_store_operation_duration_seconds_bucket{{{labels},le=\"5\"}} 2"),
+            format!("mediator_store_operation_duration_seconds_bucket{{{labels},le=\"+Inf\"}} 3"),
+            format!("mediator_store_operation_duration_seconds_count{{{labels}}} 3"),
+        ] {
+            assert!(output.lines().any(|l| l == line), "missing {line}");
+        }
+    }
+}
@@ -1,6 +1,13 @@
+pub mod apikeys;
+pub mod auditlog;
 pub mod didweb;
 pub mod filesystem;
+pub mod idempotency;
 pub mod keystore;
+pub mod metered;
+
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
 
 #[cfg(test)]
 pub fn dotenv_flow_read(key: &str) -> Option<String> {
@@ -0,0 +1,237 @@
+//! Fixtures shared by the tests of the mediator crates.
+//!
+//! Other crates enable them through the `test-utils` feature of their
+//! did-endpoint dev-dependency.
+
+use axum::{
+    body::Body,
+    http::{header, Request},
+};
+use did_utils::{
+    crypto::{ed25519::Ed25519KeyPair, traits::Generate, x25519::X25519KeyPair},
+    key_jwk::jwk::Jwk,
+    methods::did_key::DIDKeyMethod,
+};
+use std::{
+    collections::HashMap,
+    io::{Error as IoError, ErrorKind, Result as IoResult},
+    sync::{Arc, Mutex},
+};
+
+use super::{
+    apikeys::{self, ApiKeyScope, ApiKeyStore},
+    filesystem::{FileSystem, StdFileSystem},
+};
+
+/// Timestamp tests start at, for reproducible outcomes
+pub const TEST_EPOCH: i64 = 1_700_000_000;
+
+/// In-memory file system. Clones share the same files, so tests can
+/// inspect what a store wrote through its own handle.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFileSystem(Arc<Mutex<HashMap<String, String>>>);
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Content of a file, if written
+    pub fn get(&self, path: &str) -> Option<String> {
+        self.0.lock().unwrap().get(path).cloned()
+    }
+
+    /// Paths of all written files
+    pub fn paths(&self) -> Vec<String> {
+        let mut paths: Vec<_> = self.0.lock().unwrap().keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_to_string(&self, path: &str) -> IoResult<String> {
+        self.get(path)
+            .ok_or(IoError::new(ErrorKind::NotFound, "NotFound"))
+    }
+
+    fn write(&mut self, path: &str, content: &str) -> IoResult<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), content.to_owned());
+        Ok(())
+    }
+
+    fn read_dir_files(&self, path: &str) -> IoResult<Vec<String>> {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        Ok(self
+            .paths()
+            .into_iter()
+            .filter(|p| {
+                p.strip_prefix(&prefix)
+                    .is_some_and(|name| !name.contains('/'))
+            })
+            .collect())
+    }
+
+    fn create_dir_all(&mut self, _path: &str) -> IoResult<()> {
+        Ok(())
+    }
+
+    fn write_with_lock(&self, path: &str, content: &str) -> IoResult<()> {
+        self.clone().write(path, content)
+    }
+
+    fn remove_file(&mut self, path: &str) -> IoResult<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(path)
+            .map(|_| ())
+            .ok_or(IoError::new(ErrorKind::NotFound, "NotFound"))
+    }
+}
+
+/// Parties of test scenarios, each holding deterministic keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Persona {
+    Alice,
+    Bob,
+    Mediator,
+}
+
+impl Persona {
+    fn seed(&self) -> [u8; 32] {
+        let name: &[u8] = match self {
+            Self::Alice => b"alice",
+            Self::Bob => b"bob",
+            Self::Mediator => b"mediator",
+        };
+
+        let mut seed = [b'_'; 32];
+        seed[..name.len()].copy_from_slice(name);
+        seed
+    }
+
+    /// Signing key pair
+    pub fn ed25519(&self) -> Ed25519KeyPair {
+        Ed25519KeyPair::new_with_seed(&self.seed()).unwrap()
+    }
+
+    /// Key agreement key pair, derived from the signing one
+    pub fn x25519(&self) -> X25519KeyPair {
+        self.ed25519().get_x25519().unwrap()
+    }
+
+    /// did:key of the signing key
+    pub fn did(&self) -> String {
+        DIDKeyMethod::from_ed25519_keypair(&self.ed25519()).unwrap()
+    }
+
+    /// Signing key as a private JWK
+    pub fn signing_jwk(&self) -> Jwk {
+        self.ed25519().try_into().unwrap()
+    }
+
+    /// Key agreement key as a private JWK
+    pub fn agreement_jwk(&self) -> Jwk {
+        self.x25519().try_into().unwrap()
+    }
+}
+
+/// Storage directory of web tests, holding an API key granting the given
+/// scopes. The directory is removed when the fixture is dropped.
+#[derive(Debug)]
+pub struct AdminFixture {
+    pub storage_dirpath: String,
+    pub key: String,
+}
+
+impl AdminFixture {
+    pub fn new(scopes: &[ApiKeyScope]) -> Self {
+        let storage_dirpath = std::env::temp_dir()
+            .join(uuid::Uuid::new_v4().to_string())
+            .to_string_lossy()
+            .to_string();
+
+        let mut fs = StdFileSystem;
+        let (_, key) = ApiKeyStore::new(&mut fs, &storage_dirpath)
+            .create("operator", scopes, TEST_EPOCH)
+            .unwrap();
+
+        Self {
+            storage_dirpath,
+            key,
+        }
+    }
+
+    /// Request authenticated with the API key, with a JSON body if any
+    pub fn request(
+        &self,
+        method: &str,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> Request<Body> {
+        let builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(apikeys::API_KEY_HEADER, &self.key);
+
+        match body {
+            Some(body) => builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => builder.body(Body::empty()),
+        }
+        .unwrap()
+    }
+}
+
+impl Drop for AdminFixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.storage_dirpath);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use did_utils::crypto::traits::KeyMaterial;
+
+    #[test]
+    fn personas_have_distinct_deterministic_keys() {
+        let personas = [Persona::Alice, Persona::Bob, Persona::Mediator];
+
+        for persona in personas {
+            assert_eq!(persona.did(), persona.did());
+            assert!(persona.did().starts_with("did:key:z6Mk"));
+            assert_eq!(
+                persona.x25519().public_key_bytes().unwrap(),
+                persona.x25519().public_key_bytes().unwrap()
+            );
+        }
+
+        assert_ne!(Persona::Alice.did(), Persona::Bob.did());
+        assert_ne!(Persona::Bob.did(), Persona::Mediator.did());
+    }
+
+    #[test]
+    fn memory_file_system_lists_directories() {
+        let mut fs = MemoryFileSystem::new();
+        let view = fs.clone();
+
+        fs.write("storage/keystore/1.json", "{}").unwrap();
+        fs.write("storage/keystore/2.json", "{}").unwrap();
+        fs.append("storage/keystore/nested/3.json", "{}").unwrap();
+
+        assert_eq!(
+            view.read_dir_files("storage/keystore").unwrap(),
+            vec!["storage/keystore/1.json", "storage/keystore/2.json"]
+        );
+        assert!(matches!(
+            view.read_to_string("storage/missing.json"),
+            Err(err) if err.kind() == ErrorKind::NotFound
+        ));
+    }
+}
@@ -1,7 +1,14 @@
-use axum::{extract::Query, response::Json, routing::get, Router};
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Json},
+    routing::{delete, get, put},
+    Router,
+};
 use chrono::Utc;
 use did_utils::{
     didcore::{Document, KeyFormat, Proofs},
+    methods::cache::{self, DIDDocumentStore, FileSystemDocumentStore},
     proof::{
         eddsa_jcs_2022::{EdDsaJcs2022, PROOF_TYPE_DATA_INTEGRITY_PROOF},
         model::Proof,
@@ -11,38 +18,133 @@ use did_utils::{
 };
 use hyper::StatusCode;
 use multibase::Base;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
-
-use crate::util::{filesystem::StdFileSystem, keystore::KeyStore};
+use server_plugin::deadline::Deadline;
+use std::{collections::HashMap, sync::Arc};
+use utoipa::{OpenApi, ToSchema};
+
+use crate::util::{
+    apikeys::{self, ApiKey, ApiKeyError, ApiKeyScope, ApiKeySecurity, ApiKeyStore},
+    auditlog::{AuditEntry, AuditLog, KeyEvent},
+    filesystem::StdFileSystem,
+    keystore::KeyStore,
+    metered::{MeteredStore, StoreMetrics, DID_DOCUMENT_ENTITY},
+};
 
 const DEFAULT_CONTEXT_V2: &str = "https://www.w3.org/ns/credentials/v2";
 
+/// Store of the DID document cache managed by operators
+type CacheStore = MeteredStore<FileSystemDocumentStore>;
+
+/// OpenAPI description of the public routes
+#[derive(OpenApi)]
+#[openapi(paths(diddoc, didpop), tags((name = "did-endpoint", description = "DID document of the mediator")))]
+pub struct ApiDoc;
+
+/// OpenAPI description of the administrative routes
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        seed_cached_diddoc,
+        evict_cached_diddoc,
+        keystore_audit_log,
+        storage_metrics,
+        list_api_keys,
+        create_api_key,
+        revoke_api_key
+    ),
+    components(schemas(ApiKey, ApiKeyScope, ApiKeyRequest, CreatedApiKey, AuditEntry, KeyEvent)),
+    modifiers(&ApiKeySecurity),
+    tags((name = "admin", description = "Administration of the mediator, for operators"))
+)]
+pub struct AdminApiDoc;
+
 pub fn routes() -> Router {
     Router::new() //
         .route("/.well-known/did.json", get(diddoc))
         .route("/.well-known/did/pop.json", get(didpop))
 }
 
+/// Administrative routes, which must only be exposed to operators.
+///
+/// They manage the cache of DID documents that resolvers fall back on
+/// when partner DIDs cannot be resolved, e.g. during did:web outages,
+/// expose the audit log of the keystore and metrics of storage
+/// operations, and manage API keys. They require an API key granted the
+/// `admin` scope.
+pub fn admin_routes(storage_dirpath: &str, metrics: StoreMetrics) -> Router {
+    let store = FileSystemDocumentStore::new(format!("{storage_dirpath}/didcache"));
+    let store = MeteredStore::new(store, DID_DOCUMENT_ENTITY, metrics.clone());
+
+    let routes = Router::new()
+        .route(
+            "/admin/didcache/:did",
+            put(seed_cached_diddoc).delete(evict_cached_diddoc),
+        )
+        .with_state(Arc::new(store))
+        .merge(
+            Router::new()
+                .route("/admin/keystore/audit", get(keystore_audit_log))
+                .route("/admin/apikeys", get(list_api_keys).post(create_api_key))
+                .route("/admin/apikeys/:id", delete(revoke_api_key))
+                .with_state(storage_dirpath.to_owned()),
+        )
+        .merge(
+            Router::new()
+                .route("/admin/metrics/storage", get(storage_metrics))
+                .with_state(metrics),
+        );
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Admin)
+}
+
+/// Serves the DID document of the mediator
+#[utoipa::path(
+    get,
+    path = "/.well-known/did.json",
+    tag = "did-endpoint",
+    responses(
+        (status = 200, description = "DID document", body = Value),
+        (status = 404, description = "No DID document was generated"),
+        (status = 503, description = "Request deadline exceeded"),
+    )
+)]
 async fn diddoc() -> Result<Json<Value>, StatusCode> {
-    let storage_dirpath = std::env::var("STORAGE_DIRPATH").map_err(|_| {
+    let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").ok_or_else(|| {
         tracing::error!("STORAGE_DIRPATH env variable required");
         StatusCode::NOT_FOUND
     })?;
 
-    match tokio::fs::read_to_string(&format!("{storage_dirpath}/did.json")).await {
-        Ok(content) => Ok(Json(serde_json::from_str(&content).unwrap())),
-        Err(_) => Err(StatusCode::NOT_FOUND),
+    let read = tokio::fs::read_to_string(format!("{storage_dirpath}/did.json"));
+    match Deadline::within(read).await {
+        Ok(Ok(content)) => Ok(Json(serde_json::from_str(&content).unwrap())),
+        Ok(Err(_)) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::SERVICE_UNAVAILABLE),
     }
 }
 
+/// Proves possession of the keys of the DID document, with a verifiable
+/// presentation signed by each of its non-agreement keys
+#[utoipa::path(
+    get,
+    path = "/.well-known/did/pop.json",
+    tag = "did-endpoint",
+    params(("challenge" = String, Query, description = "Challenge to embed in proofs")),
+    responses(
+        (status = 200, description = "Verifiable presentation of the DID document", body = Value),
+        (status = 400, description = "Missing challenge"),
+        (status = 404, description = "No DID document was generated"),
+        (status = 503, description = "Request deadline exceeded"),
+    )
+)]
 #[axum::debug_handler]
 async fn didpop(Query(params): Query<HashMap<String, String>>) -> Result<Json<Value>, StatusCode> {
     let challenge = params.get("challenge").ok_or(StatusCode::BAD_REQUEST)?;
 
     // Retrieve keystore
 
-    let storage_dirpath = std::env::var("STORAGE_DIRPATH").map_err(|_| {
+    let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").ok_or_else(|| {
         tracing::error!("STORAGE_DIRPATH env variable required");
         StatusCode::NOT_FOUND
     })?;
@@ -93,6 +195,9 @@ async fn didpop(Query(params): Query<HashMap<String, String>>) -> Result<Json<Va
     .unwrap();
 
     for method in methods {
+        // Signing is blocking, so the deadline is checked for each proof
+        Deadline::check().map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+
         // Lookup keypair from keystore
 
         let pubkey = method
@@ -145,6 +250,194 @@ async fn didpop(Query(params): Query<HashMap<String, String>>) -> Result<Json<Va
     Ok(Json(json!(vp)))
 }
 
+/// Seeds the cache with the DID document of a partner
+#[utoipa::path(
+    put,
+    path = "/admin/didcache/{did}",
+    tag = "admin",
+    params(("did" = String, Path, description = "DID the document is cached for")),
+    request_body(content = Value, description = "DID document, identified by the DID"),
+    responses(
+        (status = 204, description = "Document cached"),
+        (status = 400, description = "Document of another DID"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn seed_cached_diddoc(
+    State(store): State<Arc<CacheStore>>,
+    Path(did): Path<String>,
+    Json(diddoc): Json<Document>,
+) -> StatusCode {
+    if diddoc.id != did {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    match cache::seed(store.as_ref(), diddoc) {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(err) => {
+            tracing::error!("failed to seed DID document cache: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Evicts the cached DID document of a partner
+#[utoipa::path(
+    delete,
+    path = "/admin/didcache/{did}",
+    tag = "admin",
+    params(("did" = String, Path, description = "DID the document is cached for")),
+    responses(
+        (status = 204, description = "Document evicted"),
+        (status = 404, description = "No document is cached for the DID"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn evict_cached_diddoc(
+    State(store): State<Arc<CacheStore>>,
+    Path(did): Path<String>,
+) -> StatusCode {
+    match store.evict(&did) {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(err) => {
+            tracing::error!("failed to evict from DID document cache: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Lists the mutations of the keystore, in order
+#[utoipa::path(
+    get,
+    path = "/admin/keystore/audit",
+    tag = "admin",
+    responses((status = 200, description = "Entries of the audit log", body = [AuditEntry])),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn keystore_audit_log(
+    State(storage_dirpath): State<String>,
+) -> Result<Json<Vec<AuditEntry>>, StatusCode> {
+    let mut fs = StdFileSystem;
+    match AuditLog::new(&mut fs, &storage_dirpath).entries() {
+        Ok(entries) => Ok(Json(entries)),
+        Err(err) => {
+            tracing::error!("failed to read keystore audit log: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Renders metrics of storage operations in the Prometheus text format
+#[utoipa::path(
+    get,
+    path = "/admin/metrics/storage",
+    tag = "admin",
+    responses((status = 200, description = "Counts, errors and latencies of storage operations", content_type = "text/plain", body = String)),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn storage_metrics(State(metrics): State<StoreMetrics>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ApiKeyRequest {
+    name: String,
+    scopes: Vec<ApiKeyScope>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CreatedApiKey {
+    #[serde(flatten)]
+    record: ApiKey,
+    key: String,
+}
+
+/// Creates an API key. Its secret is only ever returned here.
+#[utoipa::path(
+    post,
+    path = "/admin/apikeys",
+    tag = "admin",
+    request_body = ApiKeyRequest,
+    responses(
+        (status = 201, description = "Created key, with its secret", body = CreatedApiKey),
+        (status = 400, description = "No scope was requested"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn create_api_key(
+    State(storage_dirpath): State<String>,
+    Json(request): Json<ApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), StatusCode> {
+    if request.scopes.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut fs = StdFileSystem;
+    match ApiKeyStore::new(&mut fs, &storage_dirpath).create(
+        &request.name,
+        &request.scopes,
+        Utc::now().timestamp(),
+    ) {
+        Ok((record, key)) => Ok((StatusCode::CREATED, Json(CreatedApiKey { record, key }))),
+        Err(err) => {
+            tracing::error!("failed to create API key: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Lists API keys, without their secrets
+#[utoipa::path(
+    get,
+    path = "/admin/apikeys",
+    tag = "admin",
+    responses((status = 200, description = "API keys", body = [ApiKey])),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn list_api_keys(
+    State(storage_dirpath): State<String>,
+) -> Result<Json<Vec<ApiKey>>, StatusCode> {
+    let mut fs = StdFileSystem;
+    match ApiKeyStore::new(&mut fs, &storage_dirpath).list() {
+        Ok(keys) => Ok(Json(keys)),
+        Err(err) => {
+            tracing::error!("failed to list API keys: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Revokes an API key
+#[utoipa::path(
+    delete,
+    path = "/admin/apikeys/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Identifier of the key")),
+    responses(
+        (status = 204, description = "Key revoked"),
+        (status = 404, description = "Unknown key"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn revoke_api_key(
+    State(storage_dirpath): State<String>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    let mut fs = StdFileSystem;
+    match ApiKeyStore::new(&mut fs, &storage_dirpath).revoke(&id, Utc::now().timestamp()) {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(ApiKeyError::NotFound) => StatusCode::NOT_FOUND,
+        Err(err) => {
+            tracing::error!("failed to revoke API key: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
 /// Inspects in a DID document the relationship of
 /// a verification method based on its identifier
 fn inspect_vm_relationship(diddoc: &Document, vm_id: &str) -> Option<String> {
@@ -178,7 +471,10 @@ fn inspect_vm_relationship(diddoc: &Document, vm_id: &str) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{didgen, util::dotenv_flow_read};
+    use crate::{
+        didgen,
+        util::{auditlog, dotenv_flow_read},
+    };
 
     use axum::{
         body::Body,
@@ -214,6 +510,22 @@ mod tests {
         std::fs::remove_dir_all(storage_dirpath).unwrap();
     }
 
+    #[test]
+    fn can_describe_admin_routes() {
+        let mut doc = ApiDoc::openapi();
+        doc.merge(AdminApiDoc::openapi());
+
+        assert!(doc.paths.paths.contains_key("/.well-known/did.json"));
+        assert!(doc.paths.paths.contains_key("/admin/apikeys/{id}"));
+
+        // Admin routes reference the security schemes of API keys
+        let components = doc.components.unwrap();
+        for scheme in apikeys::API_KEY_SECURITY_SCHEMES {
+            assert!(components.security_schemes.contains_key(scheme));
+        }
+        assert!(components.schemas.contains_key("ApiKey"));
+    }
+
     #[tokio::test]
     async fn verify_didpop() {
         // Generate test-restricted did.json
@@ -246,8 +558,12 @@ mod tests {
             json_canon::to_string(&expected_diddoc).unwrap()
         );
 
-        let Some(proofs) = &vp.proof else { panic!("Verifiable presentation carries no proof") };
-        let Proofs::SetOfProofs(proofs) = proofs else { unreachable!() };
+        let Some(proofs) = &vp.proof else {
+            panic!("Verifiable presentation carries no proof")
+        };
+        let Proofs::SetOfProofs(proofs) = proofs else {
+            unreachable!()
+        };
         for proof in proofs {
             let pubkey = resolve_vm_for_public_key(&diddoc, &proof.verification_method)
                 .expect("ResolutionError");
@@ -264,16 +580,250 @@ mod tests {
     }
 
     fn resolve_vm_for_public_key(diddoc: &Document, vm_id: &str) -> Option<Jwk> {
-        let Some(methods) = &diddoc.verification_method else { return None };
+        let Some(methods) = &diddoc.verification_method else {
+            return None;
+        };
         let method = methods.iter().find(|m| m.id == vm_id);
 
         match method {
             None => None,
             Some(m) => {
-                let Some(key) = &m.public_key else { return None };
-                let KeyFormat::Jwk(jwk) = key else { return None };
+                let Some(key) = &m.public_key else {
+                    return None;
+                };
+                let KeyFormat::Jwk(jwk) = key else {
+                    return None;
+                };
                 Some(jwk.clone())
             }
         }
     }
+
+    #[tokio::test]
+    async fn can_seed_and_evict_cached_diddocs() {
+        let storage_dirpath = dotenv_flow_read("STORAGE_DIRPATH")
+            .map(|p| format!("{}/{}", p, uuid::Uuid::new_v4()))
+            .unwrap();
+        let metrics = StoreMetrics::new();
+        let app = admin_routes(&storage_dirpath, metrics.clone());
+        let key = admin_api_key(&storage_dirpath);
+
+        let did = "did:web:alice.example.com";
+        let request = |method: &str, did: &str, body: Body| {
+            Request::builder()
+                .method(method)
+                .uri(format!("/admin/didcache/{did}"))
+                .header("content-type", "application/json")
+                .header(apikeys::API_KEY_HEADER, &key)
+                .body(body)
+                .unwrap()
+        };
+        let diddoc = json!({
+            "@context": "https://www.w3.org/ns/did/v1",
+            "id": did,
+        });
+
+        // Seeding and eviction require a key granted the admin scope
+        let mut fs = StdFileSystem;
+        let (_, usage_key) = ApiKeyStore::new(&mut fs, &storage_dirpath)
+            .create("billing", &[ApiKeyScope::Usage], 0)
+            .unwrap();
+        for (method, key, status) in [
+            ("PUT", None, StatusCode::UNAUTHORIZED),
+            ("PUT", Some(&usage_key), StatusCode::FORBIDDEN),
+            ("DELETE", None, StatusCode::UNAUTHORIZED),
+            ("DELETE", Some(&usage_key), StatusCode::FORBIDDEN),
+        ] {
+            let mut builder = Request::builder()
+                .method(method)
+                .uri(format!("/admin/didcache/{did}"))
+                .header("content-type", "application/json");
+            if let Some(key) = key {
+                builder = builder.header(apikeys::API_KEY_HEADER, key);
+            }
+
+            let body = Body::from(diddoc.to_string());
+            let response = app.clone().oneshot(builder.body(body).unwrap());
+            assert_eq!(response.await.unwrap().status(), status);
+        }
+
+        let store = FileSystemDocumentStore::new(format!("{storage_dirpath}/didcache"));
+        assert!(store.get(did).is_none());
+
+        let response = app
+            .clone()
+            .oneshot(request("PUT", did, Body::from(diddoc.to_string())))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(store.get(did).unwrap().did_document.id, did);
+
+        // Documents must be seeded under their own DID
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                "did:web:bob.example.com",
+                Body::from(diddoc.to_string()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", did, Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(store.get(did).is_none());
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", did, Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // Operations of the cache are metered
+        assert_eq!(metrics.count(DID_DOCUMENT_ENTITY, "put"), (1, 0));
+        assert_eq!(metrics.count(DID_DOCUMENT_ENTITY, "evict"), (2, 0));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/metrics/storage")
+                    .header(apikeys::API_KEY_HEADER, &key)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        std::fs::remove_dir_all(storage_dirpath).unwrap();
+    }
+
+    #[tokio::test]
+    async fn can_retrieve_keystore_audit_log() {
+        let storage_dirpath = dotenv_flow_read("STORAGE_DIRPATH")
+            .map(|p| format!("{}/{}", p, uuid::Uuid::new_v4()))
+            .unwrap();
+        let server_public_domain = dotenv_flow_read("SERVER_PUBLIC_DOMAIN").unwrap();
+        let diddoc = didgen::didgen(&storage_dirpath, &server_public_domain).unwrap();
+        let key = admin_api_key(&storage_dirpath);
+
+        let response = admin_routes(&storage_dirpath, StoreMetrics::new())
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/keystore/audit")
+                    .header(apikeys::API_KEY_HEADER, &key)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let entries: Vec<AuditEntry> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        // The log verifies against the assertion key of the DID document
+        let assertion_key = resolve_vm_for_public_key(&diddoc, &format!("{}#keys-2", diddoc.id));
+        assert!(auditlog::verify(&entries, &assertion_key.unwrap()).is_ok());
+
+        std::fs::remove_dir_all(storage_dirpath).unwrap();
+    }
+
+    fn admin_api_key(storage_dirpath: &str) -> String {
+        let mut fs = StdFileSystem;
+        let (_, key) = ApiKeyStore::new(&mut fs, storage_dirpath)
+            .create("test", &[ApiKeyScope::Admin], 0)
+            .unwrap();
+
+        key
+    }
+
+    #[tokio::test]
+    async fn can_manage_api_keys() {
+        let storage_dirpath = dotenv_flow_read("STORAGE_DIRPATH")
+            .map(|p| format!("{}/{}", p, uuid::Uuid::new_v4()))
+            .unwrap();
+        let key = admin_api_key(&storage_dirpath);
+
+        let request = |method: &str, uri: &str, key: Option<&str>, body: Body| {
+            let builder = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json");
+            let builder = match key {
+                Some(key) => builder.header("authorization", format!("Bearer {key}")),
+                None => builder,
+            };
+            builder.body(body).unwrap()
+        };
+
+        let response = admin_routes(&storage_dirpath, StoreMetrics::new())
+            .oneshot(request("GET", "/admin/apikeys", None, Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Only administrative routes are guarded, not unmatched paths
+        let response = admin_routes(&storage_dirpath, StoreMetrics::new())
+            .oneshot(request("GET", "/unknown", None, Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = admin_routes(&storage_dirpath, StoreMetrics::new())
+            .oneshot(request(
+                "POST",
+                "/admin/apikeys",
+                Some(&key),
+                Body::from(r#"{"name": "billing", "scopes": ["usage"]}"#),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let created: Value = serde_json::from_slice(&body).unwrap();
+        let usage_key = created["key"].as_str().unwrap().to_owned();
+        let usage_key_id = created["id"].as_str().unwrap().to_owned();
+
+        // Keys are only granted their own scopes
+        let response = admin_routes(&storage_dirpath, StoreMetrics::new())
+            .oneshot(request(
+                "GET",
+                "/admin/apikeys",
+                Some(&usage_key),
+                Body::empty(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = admin_routes(&storage_dirpath, StoreMetrics::new())
+            .oneshot(request("GET", "/admin/apikeys", Some(&key), Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let keys: Vec<ApiKey> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(keys.len(), 2);
+
+        let uri = format!("/admin/apikeys/{usage_key_id}");
+        for expected in [StatusCode::NO_CONTENT, StatusCode::NOT_FOUND] {
+            let response = admin_routes(&storage_dirpath, StoreMetrics::new())
+                .oneshot(request("DELETE", &uri, Some(&key), Body::empty()))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected);
+        }
+
+        std::fs::remove_dir_all(storage_dirpath).unwrap();
+    }
 }
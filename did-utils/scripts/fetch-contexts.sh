@@ -0,0 +1,23 @@
+#!/bin/sh
+# Replaces the bundled JSON-LD contexts with the documents published at
+# their URLs, byte for byte, and pins their SHA-256 digests in SHA256SUMS.
+# Review the resulting diff: any change alters RDFC output.
+set -eu
+
+cd "$(dirname "$0")/../src/canonicalization/contexts"
+
+fetch() {
+    curl -fsSL -H 'Accept: application/ld+json' -o "$2" "$1"
+}
+
+fetch https://www.w3.org/ns/did/v1 did-v1.jsonld
+fetch https://www.w3.org/ns/credentials/v2 credentials-v2.jsonld
+fetch https://www.w3.org/ns/credentials/examples/v2 credentials-examples-v2.jsonld
+fetch https://w3id.org/security/data-integrity/v1 data-integrity-v1.jsonld
+fetch https://w3id.org/security/data-integrity/v2 data-integrity-v2.jsonld
+fetch https://w3id.org/security/multikey/v1 multikey-v1.jsonld
+fetch https://w3id.org/security/suites/ed25519-2020/v1 ed25519-2020-v1.jsonld
+fetch https://w3id.org/security/suites/x25519-2020/v1 x25519-2020-v1.jsonld
+fetch https://w3id.org/security/suites/jws-2020/v1 jws-2020-v1.jsonld
+
+sha256sum *.jsonld > SHA256SUMS
@@ -0,0 +1,46 @@
+//! JSON Canonicalization Scheme (RFC 8785).
+
+use serde::Serialize;
+
+use super::CanonicalizationError;
+
+/// Serializes a value into its JCS canonical form.
+///
+/// Object members are sorted by their UTF-16 code units, insignificant
+/// whitespace is removed and numbers follow ECMAScript formatting, so
+/// equal JSON values always yield identical strings.
+pub fn canonicalize<T: Serialize>(value: &T) -> Result<String, CanonicalizationError> {
+    json_canon::to_string(value).map_err(CanonicalizationError::SerdeError)
+}
+
+/// Serializes a value into its JCS canonical form, as bytes.
+pub fn canonicalize_to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, CanonicalizationError> {
+    canonicalize(value).map(String::into_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_canonicalize() {
+        let value = json!({
+            "b": [3, 2.5, "x"],
+            "a": {"z": null, "y": true},
+            "\u{20ac}": 1,
+            "\r": 2,
+        });
+
+        assert_eq!(canonicalize(&value).unwrap(), r#"{"\r":2,"a":{"y":true,"z":null},"b":[3,2.5,"x"],"€":1}"#);
+    }
+
+    #[test]
+    fn test_canonicalize_is_order_independent() {
+        let first: serde_json::Value = serde_json::from_str(r#"{"id": "did:web:example", "a": [1, {"y": 1, "x": 2}]}"#).unwrap();
+        let second: serde_json::Value = serde_json::from_str(r#"{ "a": [1, {"x": 2, "y": 1}], "id": "did:web:example" }"#).unwrap();
+
+        assert_eq!(canonicalize(&first).unwrap(), canonicalize(&second).unwrap());
+        assert_eq!(canonicalize_to_vec(&first).unwrap(), canonicalize(&first).unwrap().into_bytes());
+    }
+}
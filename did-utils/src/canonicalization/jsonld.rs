@@ -0,0 +1,952 @@
+//! Conversion of JSON-LD documents into RDF datasets.
+//!
+//! This implements the subset of JSON-LD 1.1 expansion and RDF serialization
+//! used by Data Integrity documents: embedded, imported and remote contexts,
+//! compact IRIs, `@vocab`, type coercion, property- and type-scoped contexts,
+//! lists, `@graph` containers and language maps.
+//!
+//! Processing is done in safe mode: properties and types which do not expand
+//! to absolute IRIs are rejected instead of being silently dropped, since
+//! dropped data would escape signatures.
+//!
+//! See https://www.w3.org/TR/json-ld11-api/
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+use url::Url;
+
+use super::{
+    jcs,
+    rdf::{Dataset, Literal, Quad, Term, XSD_STRING},
+    CanonicalizationError,
+};
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const RDF_FIRST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#first";
+const RDF_REST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#rest";
+const RDF_NIL: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#nil";
+const RDF_JSON: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#JSON";
+const XSD_BOOLEAN: &str = "http://www.w3.org/2001/XMLSchema#boolean";
+const XSD_INTEGER: &str = "http://www.w3.org/2001/XMLSchema#integer";
+const XSD_DOUBLE: &str = "http://www.w3.org/2001/XMLSchema#double";
+
+/// Maximum nesting of remote contexts, guarding against cycles
+const MAX_CONTEXT_DEPTH: usize = 32;
+
+/// Retrieves remote JSON-LD contexts.
+pub trait ContextLoader {
+    /// Returns the context document served at a URL,
+    /// i.e. an object with a `@context` entry.
+    fn load(&self, url: &str) -> Result<Value, CanonicalizationError>;
+}
+
+/// Loader rejecting all remote contexts, for documents
+/// whose contexts are all embedded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoContextLoader;
+
+impl ContextLoader for NoContextLoader {
+    fn load(&self, url: &str) -> Result<Value, CanonicalizationError> {
+        Err(CanonicalizationError::UnknownContext(url.to_owned()))
+    }
+}
+
+/// Converts a JSON-LD document into an RDF dataset.
+pub fn to_rdf(document: &Value, loader: &dyn ContextLoader) -> Result<Dataset, CanonicalizationError> {
+    let mut converter = Converter {
+        loader,
+        dataset: vec![],
+        blank_nodes: HashMap::new(),
+    };
+
+    let active = ActiveContext::default();
+    match document {
+        Value::Array(items) => {
+            for item in items {
+                converter.top_level(item, &active)?;
+            }
+        }
+        document => converter.top_level(document, &active)?,
+    }
+
+    Ok(converter.dataset)
+}
+
+#[derive(Debug, Clone, Default)]
+struct TermDefinition {
+    id: String,
+    type_mapping: Option<String>,
+    container: Vec<String>,
+    language: Option<Option<String>>,
+    context: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ActiveContext {
+    base: Option<String>,
+    vocab: Option<String>,
+    language: Option<String>,
+    /// Term definitions, `None` for terms explicitly mapped to null
+    terms: HashMap<String, Option<TermDefinition>>,
+    /// Context to revert to in nested node objects,
+    /// set by non-propagated (type-scoped) contexts
+    previous: Option<Box<ActiveContext>>,
+}
+
+impl ActiveContext {
+    fn term(&self, term: &str) -> Option<&TermDefinition> {
+        self.terms.get(term).and_then(Option::as_ref)
+    }
+
+    fn reverted(&self) -> ActiveContext {
+        match &self.previous {
+            Some(previous) => *previous.clone(),
+            None => self.clone(),
+        }
+    }
+
+    /// Expands a term, compact IRI or relative IRI.
+    ///
+    /// Returns `None` for terms explicitly mapped to null.
+    fn expand_iri(&self, value: &str, vocab: bool, document_relative: bool) -> Option<String> {
+        if value.starts_with('@') {
+            return is_keyword(value).then(|| value.to_owned());
+        }
+
+        if vocab {
+            if let Some(definition) = self.terms.get(value) {
+                return definition.as_ref().map(|def| def.id.clone());
+            }
+        }
+
+        if let Some((prefix, suffix)) = value.split_once(':') {
+            if prefix == "_" || suffix.starts_with("//") {
+                return Some(value.to_owned());
+            }
+
+            return match self.term(prefix) {
+                Some(def) => Some(format!("{}{suffix}", def.id)),
+                None => Some(value.to_owned()),
+            };
+        }
+
+        if vocab {
+            if let Some(vocab) = &self.vocab {
+                return Some(format!("{vocab}{value}"));
+            }
+        }
+
+        if document_relative {
+            if let Some(base) = self.base.as_deref().and_then(|base| Url::parse(base).ok()) {
+                return base.join(value).ok().map(String::from).or(Some(value.to_owned()));
+            }
+        }
+
+        Some(value.to_owned())
+    }
+}
+
+fn is_keyword(value: &str) -> bool {
+    matches!(
+        value,
+        "@base"
+            | "@container"
+            | "@context"
+            | "@direction"
+            | "@graph"
+            | "@id"
+            | "@import"
+            | "@included"
+            | "@index"
+            | "@json"
+            | "@language"
+            | "@list"
+            | "@nest"
+            | "@none"
+            | "@prefix"
+            | "@propagate"
+            | "@protected"
+            | "@reverse"
+            | "@set"
+            | "@type"
+            | "@value"
+            | "@version"
+            | "@vocab"
+    )
+}
+
+fn invalid(message: impl Into<String>) -> CanonicalizationError {
+    CanonicalizationError::InvalidJsonLd(message.into())
+}
+
+fn as_array(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Array(items) => items.iter().flat_map(as_array).collect(),
+        value => vec![value],
+    }
+}
+
+struct Converter<'a> {
+    loader: &'a dyn ContextLoader,
+    dataset: Dataset,
+    blank_nodes: HashMap<String, String>,
+}
+
+impl Converter<'_> {
+    fn top_level(&mut self, element: &Value, active: &ActiveContext) -> Result<(), CanonicalizationError> {
+        let Value::Object(map) = element else {
+            return Err(invalid("top-level element must be an object"));
+        };
+
+        // A top-level object holding only a graph describes the default graph
+        if map.contains_key("@graph") && map.keys().all(|key| key == "@graph" || key == "@context") {
+            let active = match map.get("@context") {
+                Some(local) => self.process_context(active, local, true, 0)?,
+                None => active.clone(),
+            };
+
+            for item in as_array(&map["@graph"]) {
+                self.element(item, None, &active, None)?;
+            }
+
+            return Ok(());
+        }
+
+        self.node(map, active, None).map(|_| ())
+    }
+
+    fn fresh_blank_node(&mut self) -> Term {
+        let id = format!("b{}", self.blank_nodes.len());
+        self.blank_nodes.insert(format!("#{id}"), id.clone());
+        Term::BlankNode(id)
+    }
+
+    fn labeled_blank_node(&mut self, label: &str) -> Term {
+        let count = self.blank_nodes.len();
+        let id = self.blank_nodes.entry(label.to_owned()).or_insert_with(|| format!("b{count}"));
+        Term::BlankNode(id.clone())
+    }
+
+    fn iri_or_blank_node(&mut self, iri: &str) -> Result<Term, CanonicalizationError> {
+        match iri.strip_prefix("_:") {
+            Some(label) => Ok(self.labeled_blank_node(label)),
+            None if iri.contains(':') => Ok(Term::Iri(iri.to_owned())),
+            None => Err(invalid(format!("relative IRI `{iri}`"))),
+        }
+    }
+
+    fn emit(&mut self, subject: &Term, predicate: &str, object: Term, graph: &Option<Term>) {
+        self.dataset.push(Quad {
+            subject: subject.clone(),
+            predicate: Term::Iri(predicate.to_owned()),
+            object,
+            graph: graph.clone(),
+        });
+    }
+
+    fn process_context(&self, active: &ActiveContext, local: &Value, propagate: bool, depth: usize) -> Result<ActiveContext, CanonicalizationError> {
+        let mut result = active.clone();
+        if !propagate && result.previous.is_none() {
+            result.previous = Some(Box::new(active.clone()));
+        }
+
+        for context in as_array(local) {
+            match context {
+                Value::Null => {
+                    result = ActiveContext {
+                        previous: result.previous.take(),
+                        ..Default::default()
+                    }
+                }
+                Value::String(url) => {
+                    if depth >= MAX_CONTEXT_DEPTH {
+                        return Err(invalid("context overflow"));
+                    }
+
+                    let document = self.loader.load(url)?;
+                    let remote = document.get("@context").ok_or_else(|| invalid(format!("no context found at `{url}`")))?;
+                    result = self.process_context(&result, remote, true, depth + 1)?;
+                }
+                Value::Object(map) => {
+                    let imported;
+                    let map = match map.get("@import") {
+                        Some(Value::String(url)) => {
+                            let document = self.loader.load(url)?;
+                            let Some(Value::Object(remote)) = document.get("@context") else {
+                                return Err(invalid(format!("invalid imported context `{url}`")));
+                            };
+
+                            let mut merged = remote.clone();
+                            merged.extend(map.clone());
+                            imported = merged;
+                            &imported
+                        }
+                        Some(_) => return Err(invalid("invalid @import value")),
+                        None => map,
+                    };
+
+                    self.process_local_context(&mut result, active, map)?;
+                }
+                _ => return Err(invalid("invalid local context")),
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn process_local_context(
+        &self,
+        result: &mut ActiveContext,
+        active: &ActiveContext,
+        map: &Map<String, Value>,
+    ) -> Result<(), CanonicalizationError> {
+        match map.get("@base") {
+            Some(Value::Null) => result.base = None,
+            Some(Value::String(base)) => result.base = result.expand_iri(base, false, true),
+            Some(_) => return Err(invalid("invalid @base value")),
+            None => (),
+        }
+
+        match map.get("@vocab") {
+            Some(Value::Null) => result.vocab = None,
+            Some(Value::String(vocab)) => result.vocab = result.expand_iri(vocab, true, true),
+            Some(_) => return Err(invalid("invalid @vocab value")),
+            None => (),
+        }
+
+        match map.get("@language") {
+            Some(Value::Null) => result.language = None,
+            Some(Value::String(language)) => result.language = Some(language.to_lowercase()),
+            Some(_) => return Err(invalid("invalid @language value")),
+            None => (),
+        }
+
+        if map.get("@propagate") == Some(&Value::Bool(false)) && result.previous.is_none() {
+            result.previous = Some(Box::new(active.clone()));
+        }
+
+        let mut defined = HashMap::new();
+        for term in map.keys().filter(|key| !key.starts_with('@')) {
+            self.define_term(result, map, term, &mut defined)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates the definition of a term of a local context, first
+    /// defining the terms of the same context it depends on.
+    fn define_term(
+        &self,
+        result: &mut ActiveContext,
+        map: &Map<String, Value>,
+        term: &str,
+        defined: &mut HashMap<String, bool>,
+    ) -> Result<(), CanonicalizationError> {
+        match defined.get(term) {
+            Some(true) => return Ok(()),
+            Some(false) => return Err(invalid(format!("cyclic definition of term `{term}`"))),
+            None => defined.insert(term.to_owned(), false),
+        };
+
+        let definition = match &map[term] {
+            Value::Null => Map::from_iter([(String::from("@id"), Value::Null)]),
+            Value::String(id) => Map::from_iter([(String::from("@id"), Value::String(id.clone()))]),
+            Value::Object(definition) => definition.clone(),
+            _ => return Err(invalid(format!("invalid definition of term `{term}`"))),
+        };
+
+        for unsupported in ["@reverse", "@nest"] {
+            if definition.contains_key(unsupported) {
+                return Err(CanonicalizationError::Unsupported(format!(
+                    "{unsupported} in definition of term `{term}`"
+                )));
+            }
+        }
+
+        // Define prefixes of compact IRIs used by the definition
+        let ids = [definition.get("@id"), definition.get("@type")];
+        let candidates = ids.into_iter().flatten().filter_map(Value::as_str).chain([term]);
+        for prefix in candidates.filter_map(|value| value.split_once(':').map(|(prefix, _)| prefix)) {
+            if prefix != term && map.contains_key(prefix) {
+                self.define_term(result, map, prefix, defined)?;
+            }
+        }
+
+        let id = match definition.get("@id") {
+            Some(Value::Null) => None,
+            Some(Value::String(id)) if is_keyword(id) => Some(id.clone()),
+            Some(Value::String(id)) => {
+                if id != term && map.contains_key(id) {
+                    self.define_term(result, map, id, defined)?;
+                }
+
+                match result.expand_iri(id, true, false) {
+                    Some(iri) if !iri.contains(':') && !is_keyword(&iri) => return Err(invalid(format!("invalid IRI mapping of term `{term}`"))),
+                    iri => iri,
+                }
+            }
+            Some(_) => return Err(invalid(format!("invalid IRI mapping of term `{term}`"))),
+            None if term.contains(':') => result.expand_iri(term, false, false),
+            None => Some(
+                result
+                    .vocab
+                    .as_ref()
+                    .map(|vocab| format!("{vocab}{term}"))
+                    .ok_or_else(|| invalid(format!("term `{term}` has no IRI mapping")))?,
+            ),
+        };
+
+        let Some(id) = id else {
+            result.terms.insert(term.to_owned(), None);
+            defined.insert(term.to_owned(), true);
+            return Ok(());
+        };
+
+        let type_mapping = match definition.get("@type") {
+            Some(Value::String(mapping)) if ["@id", "@vocab", "@json", "@none"].contains(&mapping.as_str()) => Some(mapping.clone()),
+            Some(Value::String(mapping)) => Some(
+                result
+                    .expand_iri(mapping, true, false)
+                    .filter(|iri| iri.contains(':'))
+                    .ok_or_else(|| invalid(format!("invalid type mapping of term `{term}`")))?,
+            ),
+            Some(_) => return Err(invalid(format!("invalid type mapping of term `{term}`"))),
+            None => None,
+        };
+
+        let container = as_array(definition.get("@container").unwrap_or(&Value::Null))
+            .into_iter()
+            .filter_map(|value| value.as_str().map(str::to_owned))
+            .collect();
+
+        let language = match definition.get("@language") {
+            Some(Value::Null) => Some(None),
+            Some(Value::String(language)) => Some(Some(language.to_lowercase())),
+            Some(_) => return Err(invalid(format!("invalid language mapping of term `{term}`"))),
+            None => None,
+        };
+
+        result.terms.insert(
+            term.to_owned(),
+            Some(TermDefinition {
+                id,
+                type_mapping,
+                container,
+                language,
+                context: definition.get("@context").cloned(),
+            }),
+        );
+        defined.insert(term.to_owned(), true);
+
+        Ok(())
+    }
+
+    /// Converts a node object, returning its subject.
+    fn node(&mut self, element: &Map<String, Value>, active: &ActiveContext, graph: Option<Term>) -> Result<Term, CanonicalizationError> {
+        let mut active = match element.get("@context") {
+            Some(local) => self.process_context(active, local, true, 0)?,
+            None => active.clone(),
+        };
+
+        // Apply contexts scoped to the node's types, in lexicographical order
+        let type_scoped = active.clone();
+        let mut types = vec![];
+        for (key, value) in element {
+            if type_scoped.expand_iri(key, true, false).as_deref() == Some("@type") {
+                for value in as_array(value) {
+                    let value = value.as_str().ok_or_else(|| invalid("invalid @type value"))?;
+                    types.push(value.to_owned());
+                }
+            }
+        }
+
+        let mut sorted_types = types.clone();
+        sorted_types.sort();
+        for value in &sorted_types {
+            if let Some(local) = type_scoped.term(value).and_then(|def| def.context.as_ref()) {
+                active = self.process_context(&active, local, false, 0)?;
+            }
+        }
+
+        let mut subject = None;
+        for (key, value) in element {
+            if active.expand_iri(key, true, false).as_deref() == Some("@id") {
+                let id = value.as_str().ok_or_else(|| invalid("invalid @id value"))?;
+                let iri = active.expand_iri(id, false, true).ok_or_else(|| invalid("invalid @id value"))?;
+                subject = Some(self.iri_or_blank_node(&iri)?);
+            }
+        }
+        let subject = match subject {
+            Some(subject) => subject,
+            None => self.fresh_blank_node(),
+        };
+
+        for value in &types {
+            let iri = type_scoped
+                .expand_iri(value, true, true)
+                .ok_or_else(|| CanonicalizationError::UndefinedTerm(value.clone()))?;
+            let object = self
+                .iri_or_blank_node(&iri)
+                .map_err(|_| CanonicalizationError::UndefinedTerm(value.clone()))?;
+            self.emit(&subject, RDF_TYPE, object, &graph);
+        }
+
+        for (key, value) in element {
+            let Some(property) = active.expand_iri(key, true, false) else {
+                continue;
+            };
+
+            match property.as_str() {
+                "@id" | "@type" | "@context" | "@index" => continue,
+                "@graph" => {
+                    let graph_name = Some(subject.clone());
+                    for item in as_array(value) {
+                        self.element(item, None, &active.reverted(), graph_name.clone())?;
+                    }
+                }
+                "@included" | "@reverse" | "@nest" => return Err(CanonicalizationError::Unsupported(property)),
+                "@value" | "@list" | "@set" | "@language" => return Err(invalid(format!("unexpected {property} in node object"))),
+                keyword if keyword.starts_with('@') => continue,
+                iri if iri.starts_with("_:") => return Err(CanonicalizationError::Unsupported(format!("blank node property `{key}`"))),
+                iri if !iri.contains(':') => return Err(CanonicalizationError::UndefinedTerm(key.clone())),
+                iri => {
+                    let definition = active.term(key).cloned();
+                    self.property(&subject, iri, definition.as_ref(), value, &active, &graph)?;
+                }
+            }
+        }
+
+        Ok(subject)
+    }
+
+    fn property(
+        &mut self,
+        subject: &Term,
+        predicate: &str,
+        definition: Option<&TermDefinition>,
+        value: &Value,
+        active: &ActiveContext,
+        graph: &Option<Term>,
+    ) -> Result<(), CanonicalizationError> {
+        let container = definition.map(|def| def.container.as_slice()).unwrap_or_default();
+        let has = |kind: &str| container.iter().any(|c| c == kind);
+
+        if has("@id") || has("@type") {
+            return Err(CanonicalizationError::Unsupported(format!("{container:?} container")));
+        }
+
+        if has("@language") {
+            if let Value::Object(map) = value {
+                for (language, values) in map {
+                    for value in as_array(values) {
+                        let Some(value) = value.as_str() else {
+                            return Err(invalid("invalid language map value"));
+                        };
+
+                        let literal = match language.as_str() {
+                            "@none" => Literal::string(value),
+                            language => Literal::lang_string(value, &language.to_lowercase()),
+                        };
+                        self.emit(subject, predicate, Term::Literal(literal), graph);
+                    }
+                }
+
+                return Ok(());
+            }
+        }
+
+        let values = match value {
+            Value::Object(map) if has("@index") && !map.keys().any(|key| key.starts_with('@')) => map.values().flat_map(as_array).collect(),
+            value => as_array(value),
+        };
+
+        if has("@list") {
+            let list = self.list(&values, definition, active, graph)?;
+            self.emit(subject, predicate, list, graph);
+            return Ok(());
+        }
+
+        for value in values {
+            if has("@graph") && value.is_object() && !is_graph_object(value) {
+                let graph_name = self.fresh_blank_node();
+                self.element(value, definition, active, Some(graph_name.clone()))?;
+                self.emit(subject, predicate, graph_name, graph);
+                continue;
+            }
+
+            if let Some(object) = self.element(value, definition, active, graph.clone())? {
+                self.emit(subject, predicate, object, graph);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Converts the value of a property, returning the object
+    /// it denotes or `None` for null values.
+    fn element(
+        &mut self,
+        value: &Value,
+        definition: Option<&TermDefinition>,
+        active: &ActiveContext,
+        graph: Option<Term>,
+    ) -> Result<Option<Term>, CanonicalizationError> {
+        let type_mapping = definition.and_then(|def| def.type_mapping.as_deref());
+
+        if type_mapping == Some("@json") {
+            return Ok(Some(json_literal(value)?));
+        }
+
+        let Value::Object(map) = value else {
+            let active = self.property_scoped(active, definition)?;
+            return self.scalar(value, definition, &active);
+        };
+
+        // Value objects and lists are not affected by type-scoped contexts
+        let keyword = |key: &str| {
+            map.keys()
+                .find(|k| active.expand_iri(k, true, false).as_deref() == Some(key))
+                .map(|k| &map[k])
+        };
+
+        if let Some(value) = keyword("@value") {
+            let active = self.property_scoped(active, definition)?;
+            return self.value_object(value, keyword("@type"), keyword("@language"), &active);
+        }
+
+        if let Some(items) = keyword("@list") {
+            return self.list(&as_array(items), definition, active, &graph).map(Some);
+        }
+
+        if let Some(items) = keyword("@set") {
+            return match as_array(items)[..] {
+                [] => Ok(None),
+                [item] => self.element(item, definition, active, graph),
+                _ => Err(CanonicalizationError::Unsupported(String::from("@set with several values"))),
+            };
+        }
+
+        let active = self.property_scoped(&active.reverted(), definition)?;
+        self.node(map, &active, graph).map(Some)
+    }
+
+    fn property_scoped(&self, active: &ActiveContext, definition: Option<&TermDefinition>) -> Result<ActiveContext, CanonicalizationError> {
+        match definition.and_then(|def| def.context.as_ref()) {
+            Some(local) => self.process_context(active, local, true, 0),
+            None => Ok(active.clone()),
+        }
+    }
+
+    fn scalar(&mut self, value: &Value, definition: Option<&TermDefinition>, active: &ActiveContext) -> Result<Option<Term>, CanonicalizationError> {
+        let type_mapping = definition
+            .and_then(|def| def.type_mapping.as_deref())
+            .filter(|mapping| *mapping != "@none");
+
+        let literal = match (value, type_mapping) {
+            (Value::Null, _) => return Ok(None),
+            (Value::String(iri), Some("@id")) => {
+                let iri = active.expand_iri(iri, false, true).ok_or_else(|| invalid("invalid IRI"))?;
+                return self.iri_or_blank_node(&iri).map(Some);
+            }
+            (Value::String(iri), Some("@vocab")) => {
+                let iri = active.expand_iri(iri, true, true).ok_or_else(|| invalid("invalid IRI"))?;
+                return self.iri_or_blank_node(&iri).map(Some);
+            }
+            (Value::String(value), Some(datatype)) => Literal::typed(value, datatype),
+            (Value::String(value), None) => {
+                let language = match definition.and_then(|def| def.language.clone()) {
+                    Some(language) => language,
+                    None => active.language.clone(),
+                };
+
+                match language {
+                    Some(language) => Literal::lang_string(value, &language),
+                    None => Literal::string(value),
+                }
+            }
+            (value, datatype) => native_literal(value, datatype.filter(|d| !d.starts_with('@')))?,
+        };
+
+        Ok(Some(Term::Literal(literal)))
+    }
+
+    fn value_object(
+        &mut self,
+        value: &Value,
+        datatype: Option<&Value>,
+        language: Option<&Value>,
+        active: &ActiveContext,
+    ) -> Result<Option<Term>, CanonicalizationError> {
+        let datatype = match datatype {
+            Some(Value::String(datatype)) => Some(
+                active
+                    .expand_iri(datatype, true, true)
+                    .filter(|iri| iri.contains(':') || iri == "@json")
+                    .ok_or_else(|| CanonicalizationError::UndefinedTerm(datatype.clone()))?,
+            ),
+            Some(_) => return Err(invalid("invalid @type in value object")),
+            None => None,
+        };
+
+        if datatype.as_deref() == Some("@json") {
+            return json_literal(value).map(Some);
+        }
+
+        let literal = match (value, language) {
+            (Value::Null, _) => return Ok(None),
+            (Value::String(value), Some(Value::String(language))) => Literal::lang_string(value, &language.to_lowercase()),
+            (Value::String(value), _) => Literal::typed(value, datatype.as_deref().unwrap_or(XSD_STRING)),
+            (value, None) => native_literal(value, datatype.as_deref())?,
+            _ => return Err(invalid("language-tagged value must be a string")),
+        };
+
+        Ok(Some(Term::Literal(literal)))
+    }
+
+    fn list(
+        &mut self,
+        items: &[&Value],
+        definition: Option<&TermDefinition>,
+        active: &ActiveContext,
+        graph: &Option<Term>,
+    ) -> Result<Term, CanonicalizationError> {
+        let mut objects = vec![];
+        for item in items {
+            if item.is_array() {
+                return Err(CanonicalizationError::Unsupported(String::from("list of lists")));
+            }
+
+            if let Some(object) = self.element(item, definition, active, graph.clone())? {
+                objects.push(object);
+            }
+        }
+
+        let mut head = Term::Iri(RDF_NIL.to_owned());
+        for object in objects.into_iter().rev() {
+            let node = self.fresh_blank_node();
+            self.emit(&node, RDF_FIRST, object, graph);
+            self.emit(&node, RDF_REST, head, graph);
+            head = node;
+        }
+
+        Ok(head)
+    }
+}
+
+fn is_graph_object(value: &Value) -> bool {
+    value
+        .as_object()
+        .is_some_and(|map| map.contains_key("@graph") && map.keys().all(|key| ["@graph", "@id", "@index", "@context"].contains(&key.as_str())))
+}
+
+fn json_literal(value: &Value) -> Result<Term, CanonicalizationError> {
+    Ok(Term::Literal(Literal::typed(&jcs::canonicalize(value)?, RDF_JSON)))
+}
+
+/// Converts a boolean or number into a literal in canonical lexical form.
+fn native_literal(value: &Value, datatype: Option<&str>) -> Result<Literal, CanonicalizationError> {
+    match value {
+        Value::Bool(value) => Ok(Literal::typed(&value.to_string(), datatype.unwrap_or(XSD_BOOLEAN))),
+        Value::Number(number) => {
+            let double = number.as_f64().unwrap_or_default();
+            let is_integer = number.is_i64() || number.is_u64() || (double.fract() == 0.0 && double.abs() < 1e21);
+
+            if is_integer && datatype != Some(XSD_DOUBLE) {
+                let lexical = match (number.as_i64(), number.as_u64()) {
+                    (Some(n), _) => n.to_string(),
+                    (_, Some(n)) => n.to_string(),
+                    _ => format!("{double:.0}"),
+                };
+                Ok(Literal::typed(&lexical, datatype.unwrap_or(XSD_INTEGER)))
+            } else {
+                Ok(Literal::typed(&canonical_double(double), datatype.unwrap_or(XSD_DOUBLE)))
+            }
+        }
+        _ => Err(invalid("invalid value")),
+    }
+}
+
+/// Formats a double in the canonical `xsd:double` form, e.g. `1.1E-1`.
+fn canonical_double(value: f64) -> String {
+    let formatted = format!("{value:E}");
+    let (mantissa, exponent) = formatted.split_once('E').unwrap_or((&formatted, "0"));
+
+    if mantissa.contains('.') {
+        format!("{mantissa}E{exponent}")
+    } else {
+        format!("{mantissa}.0E{exponent}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canonicalization::{rdf::to_nquads, rdfc::canonicalize_jsonld};
+    use serde_json::json;
+
+    fn canonical_nquads(document: Value) -> Result<String, CanonicalizationError> {
+        canonicalize_jsonld(&document, &NoContextLoader)
+    }
+
+    #[test]
+    fn test_to_rdf() {
+        let document = json!({
+            "@context": {
+                "@vocab": "https://vocab.example/#",
+                "ex": "https://example.com/#",
+                "id": "@id",
+                "type": "@type",
+                "knows": {"@id": "ex:knows", "@type": "@id"},
+                "born": {"@id": "ex:born", "@type": "http://www.w3.org/2001/XMLSchema#date"},
+                "tags": {"@id": "ex:tags", "@container": "@list"},
+                "label": {"@id": "ex:label", "@container": "@language"},
+                "data": {"@id": "ex:data", "@type": "@json"},
+            },
+            "id": "did:example:alice",
+            "type": "Person",
+            "name": "Alice",
+            "knows": "did:example:bob",
+            "born": "1990-01-01",
+            "age": 33,
+            "height": 1.7,
+            "active": true,
+            "tags": ["a", "b"],
+            "label": {"en": "Alice", "fr": "Alice"},
+            "data": {"b": 1, "a": [true, null]},
+            "address": {"ex:city": "Paris"},
+        });
+
+        let expected = r#"<did:example:alice> <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://vocab.example/#Person> .
+<did:example:alice> <https://example.com/#born> "1990-01-01"^^<http://www.w3.org/2001/XMLSchema#date> .
+<did:example:alice> <https://example.com/#data> "{\"a\":[true,null],\"b\":1}"^^<http://www.w3.org/1999/02/22-rdf-syntax-ns#JSON> .
+<did:example:alice> <https://example.com/#knows> <did:example:bob> .
+<did:example:alice> <https://example.com/#label> "Alice"@en .
+<did:example:alice> <https://example.com/#label> "Alice"@fr .
+<did:example:alice> <https://example.com/#tags> _:c14n0 .
+<did:example:alice> <https://vocab.example/#active> "true"^^<http://www.w3.org/2001/XMLSchema#boolean> .
+<did:example:alice> <https://vocab.example/#address> _:c14n2 .
+<did:example:alice> <https://vocab.example/#age> "33"^^<http://www.w3.org/2001/XMLSchema#integer> .
+<did:example:alice> <https://vocab.example/#height> "1.7E0"^^<http://www.w3.org/2001/XMLSchema#double> .
+<did:example:alice> <https://vocab.example/#name> "Alice" .
+_:c14n0 <http://www.w3.org/1999/02/22-rdf-syntax-ns#first> "a" .
+_:c14n0 <http://www.w3.org/1999/02/22-rdf-syntax-ns#rest> _:c14n1 .
+_:c14n1 <http://www.w3.org/1999/02/22-rdf-syntax-ns#first> "b" .
+_:c14n1 <http://www.w3.org/1999/02/22-rdf-syntax-ns#rest> <http://www.w3.org/1999/02/22-rdf-syntax-ns#nil> .
+_:c14n2 <https://example.com/#city> "Paris" .
+"#;
+
+        assert_eq!(canonical_nquads(document).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_scoped_contexts() {
+        let document = json!({
+            "@context": {
+                "@version": 1.1,
+                "id": "@id",
+                "type": "@type",
+                "Credential": {
+                    "@id": "https://example.com/#Credential",
+                    "@context": {"issuer": {"@id": "https://example.com/#issuer", "@type": "@id"}},
+                },
+                "subject": {
+                    "@id": "https://example.com/#subject",
+                    "@context": {"name": "https://example.com/#name"},
+                },
+            },
+            "type": "Credential",
+            "issuer": "did:example:issuer",
+            "subject": {"id": "did:example:alice", "name": "Alice"},
+        });
+
+        let nquads = canonical_nquads(document).unwrap();
+        assert!(nquads.contains("<https://example.com/#issuer> <did:example:issuer> ."));
+        assert!(nquads.contains("<did:example:alice> <https://example.com/#name> \"Alice\" ."));
+
+        // Type-scoped contexts do not propagate to nested nodes
+        let document = json!({
+            "@context": {
+                "type": "@type",
+                "Credential": {
+                    "@id": "https://example.com/#Credential",
+                    "@context": {"name": "https://example.com/#name", "nested": "https://example.com/#nested"},
+                },
+            },
+            "type": "Credential",
+            "nested": {"name": "Alice"},
+        });
+
+        assert!(matches!(canonical_nquads(document), Err(CanonicalizationError::UndefinedTerm(term)) if term == "name"));
+    }
+
+    #[test]
+    fn test_graph_containers() {
+        let document = json!({
+            "@context": {
+                "ex": "https://example.com/#",
+                "credential": {"@id": "ex:credential", "@container": "@graph"},
+            },
+            "@id": "urn:presentation",
+            "credential": {"@id": "urn:credential", "ex:name": "Alice"},
+        });
+
+        let expected = r#"<urn:credential> <https://example.com/#name> "Alice" _:c14n0 .
+<urn:presentation> <https://example.com/#credential> _:c14n0 .
+"#;
+
+        assert_eq!(canonical_nquads(document).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_safe_mode() {
+        let document = json!({
+            "@context": {"name": "https://example.com/#name"},
+            "name": "Alice",
+            "undefined": "dropped by lenient processors",
+        });
+        assert!(matches!(canonical_nquads(document), Err(CanonicalizationError::UndefinedTerm(term)) if term == "undefined"));
+
+        let document = json!({
+            "@context": "https://www.w3.org/ns/credentials/v2",
+            "name": "Alice",
+        });
+        assert!(matches!(canonical_nquads(document), Err(CanonicalizationError::UnknownContext(_))));
+
+        let document = json!({
+            "@context": {"@vocab": "https://example.com/#"},
+            "@id": "relative",
+        });
+        assert!(matches!(canonical_nquads(document), Err(CanonicalizationError::InvalidJsonLd(_))));
+    }
+
+    #[test]
+    fn test_remote_contexts() {
+        struct Loader;
+
+        impl ContextLoader for Loader {
+            fn load(&self, url: &str) -> Result<Value, CanonicalizationError> {
+                match url {
+                    "https://example.com/context" => Ok(json!({"@context": {"name": "https://example.com/#name"}})),
+                    "https://example.com/cycle" => Ok(json!({"@context": "https://example.com/cycle"})),
+                    url => Err(CanonicalizationError::UnknownContext(url.to_owned())),
+                }
+            }
+        }
+
+        let document = json!({"@context": "https://example.com/context", "@id": "urn:alice", "name": "Alice"});
+        let dataset = to_rdf(&document, &Loader).unwrap();
+        assert_eq!(to_nquads(&dataset), "<urn:alice> <https://example.com/#name> \"Alice\" .\n");
+
+        let document = json!({"@context": "https://example.com/cycle", "name": "Alice"});
+        assert!(matches!(to_rdf(&document, &Loader), Err(CanonicalizationError::InvalidJsonLd(_))));
+    }
+
+    #[test]
+    fn test_canonical_double() {
+        assert_eq!(canonical_double(1.7), "1.7E0");
+        assert_eq!(canonical_double(1.0), "1.0E0");
+        assert_eq!(canonical_double(-0.00015), "-1.5E-4");
+        assert_eq!(canonical_double(1.5e21), "1.5E21");
+    }
+}
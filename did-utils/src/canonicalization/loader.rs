@@ -0,0 +1,228 @@
+//! Offline resolution of JSON-LD contexts.
+//!
+//! Processing JSON-LD safely requires the contexts of documents to be known
+//! ahead of time: a remote context could otherwise change, or be withheld,
+//! and silently alter the meaning of signed data. [`DocumentLoader`] serves
+//! a bundled copy of the contexts DID documents and credentials commonly use.
+//!
+//! Bundled contexts must match the published documents byte for byte, as
+//! any difference changes canonical forms and breaks interoperability.
+//! `scripts/fetch-contexts.sh` vendors them, and pins their SHA-256
+//! digests in `contexts/SHA256SUMS`, which tests check.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use serde_json::Value;
+
+use super::{jsonld::ContextLoader, CanonicalizationError};
+
+/// Contexts bundled with the library, by URL
+pub const BUNDLED_CONTEXTS: &[(&str, &str)] = &[
+    ("https://www.w3.org/ns/did/v1", include_str!("contexts/did-v1.jsonld")),
+    ("https://www.w3.org/ns/credentials/v2", include_str!("contexts/credentials-v2.jsonld")),
+    (
+        "https://www.w3.org/ns/credentials/examples/v2",
+        include_str!("contexts/credentials-examples-v2.jsonld"),
+    ),
+    (
+        "https://w3id.org/security/data-integrity/v1",
+        include_str!("contexts/data-integrity-v1.jsonld"),
+    ),
+    (
+        "https://w3id.org/security/data-integrity/v2",
+        include_str!("contexts/data-integrity-v2.jsonld"),
+    ),
+    ("https://w3id.org/security/multikey/v1", include_str!("contexts/multikey-v1.jsonld")),
+    (
+        "https://w3id.org/security/suites/ed25519-2020/v1",
+        include_str!("contexts/ed25519-2020-v1.jsonld"),
+    ),
+    (
+        "https://w3id.org/security/suites/x25519-2020/v1",
+        include_str!("contexts/x25519-2020-v1.jsonld"),
+    ),
+    (
+        "https://w3id.org/security/suites/jws-2020/v1",
+        include_str!("contexts/jws-2020-v1.jsonld"),
+    ),
+];
+
+fn bundled(url: &str) -> Option<&'static Value> {
+    static PARSED: OnceLock<HashMap<&str, Value>> = OnceLock::new();
+
+    PARSED
+        .get_or_init(|| {
+            BUNDLED_CONTEXTS
+                .iter()
+                .map(|(url, content)| (*url, serde_json::from_str(content).expect("bundled contexts are valid JSON")))
+                .collect()
+        })
+        .get(url)
+}
+
+/// Context loader serving bundled and registered contexts.
+///
+/// The loader is strict by default and rejects any other context. With a
+/// fallback loader, unknown contexts are resolved through it instead, and
+/// cached for subsequent loads.
+#[derive(Default)]
+pub struct DocumentLoader {
+    contexts: Mutex<HashMap<String, Value>>,
+    fallback: Option<Box<dyn ContextLoader + Send + Sync>>,
+}
+
+impl DocumentLoader {
+    /// Creates a strict loader serving the bundled contexts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a context document, taking precedence over bundled ones.
+    pub fn with_context(self, url: &str, document: Value) -> Self {
+        self.contexts.lock().unwrap().insert(url.to_owned(), document);
+        self
+    }
+
+    /// Resolves unknown contexts through another loader, e.g. from the network.
+    pub fn with_fallback(mut self, loader: impl ContextLoader + Send + Sync + 'static) -> Self {
+        self.fallback = Some(Box::new(loader));
+        self
+    }
+
+    /// Whether unknown contexts are rejected.
+    pub fn is_strict(&self) -> bool {
+        self.fallback.is_none()
+    }
+}
+
+impl ContextLoader for DocumentLoader {
+    fn load(&self, url: &str) -> Result<Value, CanonicalizationError> {
+        if let Some(document) = self.contexts.lock().unwrap().get(url) {
+            return Ok(document.clone());
+        }
+
+        if let Some(document) = bundled(url) {
+            return Ok(document.clone());
+        }
+
+        let Some(fallback) = &self.fallback else {
+            return Err(CanonicalizationError::UnknownContext(url.to_owned()));
+        };
+
+        let document = fallback.load(url)?;
+        self.contexts.lock().unwrap().insert(url.to_owned(), document.clone());
+
+        Ok(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{canonicalization::canonicalize_jsonld, crypto::sha256_hash::sha256_hash};
+
+    #[test]
+    fn test_bundled_contexts() {
+        let loader = DocumentLoader::new();
+        assert!(loader.is_strict());
+
+        for (url, _) in BUNDLED_CONTEXTS {
+            let document = json!({"@context": url, "@id": "did:example:123"});
+            assert!(canonicalize_jsonld(&document, &loader).is_ok(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_bundled_contexts_are_pinned() {
+        let pinned: Vec<&str> = include_str!("contexts/SHA256SUMS")
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .collect();
+        assert_eq!(pinned.len(), BUNDLED_CONTEXTS.len());
+
+        for (url, content) in BUNDLED_CONTEXTS {
+            let digest = hex::encode(sha256_hash(content.as_bytes()));
+            assert!(pinned.contains(&digest.as_str()), "{url} differs from its pinned digest");
+        }
+    }
+
+    #[test]
+    fn test_canonicalize_did_document() {
+        let diddoc = json!({
+            "@context": [
+                "https://www.w3.org/ns/did/v1",
+                "https://w3id.org/security/suites/ed25519-2020/v1",
+            ],
+            "id": "did:example:123",
+            "verificationMethod": [{
+                "id": "did:example:123#key-1",
+                "type": "Ed25519VerificationKey2020",
+                "controller": "did:example:123",
+                "publicKeyMultibase": "z6MkjLrk3gKS2nnkeWcmcxiZPGskmesDpuwRBorgHxUXfxnG",
+            }],
+            "authentication": ["did:example:123#key-1"],
+        });
+
+        let expected = r#"<did:example:123#key-1> <http://www.w3.org/1999/02/22-rdf-syntax-ns#type> <https://w3id.org/security#Ed25519VerificationKey2020> .
+<did:example:123#key-1> <https://w3id.org/security#controller> <did:example:123> .
+<did:example:123#key-1> <https://w3id.org/security#publicKeyMultibase> "z6MkjLrk3gKS2nnkeWcmcxiZPGskmesDpuwRBorgHxUXfxnG"^^<https://w3id.org/security#multibase> .
+<did:example:123> <https://w3id.org/security#authenticationMethod> <did:example:123#key-1> .
+<did:example:123> <https://w3id.org/security#verificationMethod> <did:example:123#key-1> .
+"#;
+
+        assert_eq!(canonicalize_jsonld(&diddoc, &DocumentLoader::new()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        let document = json!({"@context": "https://example.com/context", "name": "Alice"});
+        let context = json!({"@context": {"name": "https://schema.org/name"}});
+
+        let loader = DocumentLoader::new();
+        assert!(matches!(
+            canonicalize_jsonld(&document, &loader),
+            Err(CanonicalizationError::UnknownContext(url)) if url == "https://example.com/context"
+        ));
+
+        let loader = DocumentLoader::new().with_context("https://example.com/context", context);
+        assert_eq!(
+            canonicalize_jsonld(&document, &loader).unwrap(),
+            "_:c14n0 <https://schema.org/name> \"Alice\" .\n"
+        );
+    }
+
+    #[test]
+    fn test_fallback_results_are_cached() {
+        struct CountingLoader(Arc<AtomicUsize>);
+
+        impl ContextLoader for CountingLoader {
+            fn load(&self, _url: &str) -> Result<Value, CanonicalizationError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(json!({"@context": {"name": "https://schema.org/name"}}))
+            }
+        }
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let loader = DocumentLoader::new().with_fallback(CountingLoader(count.clone()));
+        assert!(!loader.is_strict());
+
+        let document = json!({"@context": "https://example.com/context", "name": "Alice"});
+        for _ in 0..3 {
+            canonicalize_jsonld(&document, &loader).unwrap();
+        }
+
+        // Bundled contexts never reach the fallback
+        let document = json!({"@context": "https://www.w3.org/ns/did/v1", "id": "did:example:123"});
+        canonicalize_jsonld(&document, &loader).unwrap();
+
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,41 @@
+//! Deterministic serialization of documents, for hashing and signing.
+//!
+//! Two algorithms are provided:
+//! - [JSON Canonicalization Scheme](https://www.rfc-editor.org/rfc/rfc8785) (JCS)
+//!   which works on any serializable value.
+//! - [RDF Dataset Canonicalization](https://www.w3.org/TR/rdf-canon/) (RDFC-1.0)
+//!   which works on RDF datasets, e.g. in N-Quads form or converted from
+//!   JSON-LD documents. It is only available with the `rdfc` feature.
+
+pub mod jcs;
+
+#[cfg(feature = "rdfc")]
+pub mod jsonld;
+#[cfg(feature = "rdfc")]
+pub mod loader;
+#[cfg(feature = "rdfc")]
+pub mod rdf;
+#[cfg(feature = "rdfc")]
+pub mod rdfc;
+
+pub use jcs::canonicalize;
+#[cfg(feature = "rdfc")]
+pub use rdfc::{canonicalize_jsonld, canonicalize_nquads};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CanonicalizationError {
+    #[error("serialization error: {0}")]
+    SerdeError(serde_json::Error),
+    #[error("invalid N-Quads at line {line}: {message}")]
+    InvalidNQuads { line: usize, message: String },
+    #[error("dataset too complex to canonicalize")]
+    ComplexityLimitExceeded,
+    #[error("invalid JSON-LD: {0}")]
+    InvalidJsonLd(String),
+    #[error("term `{0}` does not expand to an absolute IRI")]
+    UndefinedTerm(String),
+    #[error("unknown context: {0}")]
+    UnknownContext(String),
+    #[error("unsupported JSON-LD feature: {0}")]
+    Unsupported(String),
+}
@@ -0,0 +1,422 @@
+//! Minimal RDF data model with N-Quads parsing and canonical serialization.
+//!
+//! See https://www.w3.org/TR/n-quads/
+
+use std::fmt::{Display, Formatter, Result as FmtResult, Write};
+
+use super::CanonicalizationError;
+
+pub const XSD_STRING: &str = "http://www.w3.org/2001/XMLSchema#string";
+pub const RDF_LANG_STRING: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#langString";
+
+/// RDF term
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Term {
+    Iri(String),
+    BlankNode(String),
+    Literal(Literal),
+}
+
+/// RDF literal
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Literal {
+    pub value: String,
+    pub datatype: String,
+    pub language: Option<String>,
+}
+
+impl Literal {
+    /// Creates a plain string literal
+    pub fn string(value: &str) -> Self {
+        Self::typed(value, XSD_STRING)
+    }
+
+    /// Creates a literal of some datatype
+    pub fn typed(value: &str, datatype: &str) -> Self {
+        Self {
+            value: value.to_owned(),
+            datatype: datatype.to_owned(),
+            language: None,
+        }
+    }
+
+    /// Creates a language-tagged string literal
+    pub fn lang_string(value: &str, language: &str) -> Self {
+        Self {
+            value: value.to_owned(),
+            datatype: RDF_LANG_STRING.to_owned(),
+            language: Some(language.to_owned()),
+        }
+    }
+}
+
+impl Term {
+    pub fn as_blank_node(&self) -> Option<&str> {
+        match self {
+            Term::BlankNode(id) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+/// RDF quad, i.e. a triple in a graph
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Quad {
+    pub subject: Term,
+    pub predicate: Term,
+    pub object: Term,
+    /// Name of the graph, `None` for the default graph
+    pub graph: Option<Term>,
+}
+
+impl Quad {
+    /// Returns the quad with blank node identifiers mapped
+    pub fn map_blank_nodes(&self, f: impl Fn(&str) -> String) -> Self {
+        let map = |term: &Term| match term {
+            Term::BlankNode(id) => Term::BlankNode(f(id)),
+            term => term.clone(),
+        };
+
+        Quad {
+            subject: map(&self.subject),
+            predicate: self.predicate.clone(),
+            object: map(&self.object),
+            graph: self.graph.as_ref().map(map),
+        }
+    }
+}
+
+/// RDF dataset, as a list of quads
+pub type Dataset = Vec<Quad>;
+
+impl Display for Term {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Term::Iri(iri) => write!(f, "<{iri}>"),
+            Term::BlankNode(id) => write!(f, "_:{id}"),
+            Term::Literal(literal) => {
+                f.write_char('"')?;
+                escape_literal(&literal.value, f)?;
+                f.write_char('"')?;
+
+                match &literal.language {
+                    Some(language) => write!(f, "@{language}"),
+                    None if literal.datatype == XSD_STRING => Ok(()),
+                    None => write!(f, "^^<{}>", literal.datatype),
+                }
+            }
+        }
+    }
+}
+
+/// Serializes quad in canonical N-Quads form, without line terminator
+impl Display for Quad {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{} {} {} ", self.subject, self.predicate, self.object)?;
+        if let Some(graph) = &self.graph {
+            write!(f, "{graph} ")?;
+        }
+
+        f.write_char('.')
+    }
+}
+
+fn escape_literal(value: &str, f: &mut Formatter<'_>) -> FmtResult {
+    for c in value.chars() {
+        match c {
+            '\u{8}' => f.write_str("\\b")?,
+            '\t' => f.write_str("\\t")?,
+            '\n' => f.write_str("\\n")?,
+            '\u{c}' => f.write_str("\\f")?,
+            '\r' => f.write_str("\\r")?,
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\u{0}'..='\u{1f}' | '\u{7f}' => write!(f, "\\u{:04X}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Serializes dataset into N-Quads, one quad per line
+pub fn to_nquads(dataset: &[Quad]) -> String {
+    dataset.iter().map(|quad| format!("{quad}\n")).collect()
+}
+
+/// Parses N-Quads into a dataset
+pub fn parse_nquads(input: &str) -> Result<Dataset, CanonicalizationError> {
+    let mut dataset = vec![];
+
+    for (index, line) in input.lines().enumerate() {
+        let mut parser = Parser {
+            chars: line.chars().collect(),
+            pos: 0,
+        };
+
+        parser.skip_whitespace();
+        if parser.at_end_of_statement() {
+            continue;
+        }
+
+        let quad = parser
+            .parse_quad()
+            .map_err(|message| CanonicalizationError::InvalidNQuads { line: index + 1, message })?;
+        dataset.push(quad);
+    }
+
+    Ok(dataset)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected `{expected}`, found `{c}`")),
+            None => Err(format!("expected `{expected}`, found end of line")),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn at_end_of_statement(&self) -> bool {
+        matches!(self.peek(), None | Some('#'))
+    }
+
+    fn parse_quad(&mut self) -> Result<Quad, String> {
+        let subject = match self.peek() {
+            Some('<') => Term::Iri(self.parse_iri()?),
+            Some('_') => Term::BlankNode(self.parse_blank_node()?),
+            _ => return Err(String::from("invalid subject")),
+        };
+
+        self.skip_whitespace();
+        let predicate = match self.peek() {
+            Some('<') => Term::Iri(self.parse_iri()?),
+            _ => return Err(String::from("invalid predicate")),
+        };
+
+        self.skip_whitespace();
+        let object = match self.peek() {
+            Some('<') => Term::Iri(self.parse_iri()?),
+            Some('_') => Term::BlankNode(self.parse_blank_node()?),
+            Some('"') => Term::Literal(self.parse_literal()?),
+            _ => return Err(String::from("invalid object")),
+        };
+
+        self.skip_whitespace();
+        let graph = match self.peek() {
+            Some('<') => Some(Term::Iri(self.parse_iri()?)),
+            Some('_') => Some(Term::BlankNode(self.parse_blank_node()?)),
+            _ => None,
+        };
+
+        self.skip_whitespace();
+        self.expect('.')?;
+        self.skip_whitespace();
+        if !self.at_end_of_statement() {
+            return Err(String::from("unexpected content after statement"));
+        }
+
+        Ok(Quad {
+            subject,
+            predicate,
+            object,
+            graph,
+        })
+    }
+
+    fn parse_iri(&mut self) -> Result<String, String> {
+        self.expect('<')?;
+
+        let mut iri = String::new();
+        loop {
+            match self.next() {
+                Some('>') => break,
+                Some('\\') => iri.push(self.parse_uchar()?),
+                Some(c) if c.is_whitespace() || "<\"{}|^`".contains(c) => return Err(format!("invalid character `{c}` in IRI")),
+                Some(c) => iri.push(c),
+                None => return Err(String::from("unterminated IRI")),
+            }
+        }
+
+        if !iri.contains(':') {
+            return Err(format!("relative IRI `{iri}`"));
+        }
+
+        Ok(iri)
+    }
+
+    fn parse_blank_node(&mut self) -> Result<String, String> {
+        self.expect('_')?;
+        self.expect(':')?;
+
+        let mut label = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || "_-.".contains(c) {
+                label.push(c);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+
+        // Labels must not end with a dot, which terminates the statement
+        while label.ends_with('.') {
+            label.pop();
+            self.pos -= 1;
+        }
+
+        if label.is_empty() {
+            return Err(String::from("empty blank node label"));
+        }
+
+        Ok(label)
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal, String> {
+        self.expect('"')?;
+
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => break,
+                Some('\\') => match self.peek() {
+                    Some('u' | 'U') => value.push(self.parse_uchar()?),
+                    Some(c) => {
+                        self.pos += 1;
+                        value.push(match c {
+                            'b' => '\u{8}',
+                            't' => '\t',
+                            'n' => '\n',
+                            'f' => '\u{c}',
+                            'r' => '\r',
+                            '"' => '"',
+                            '\'' => '\'',
+                            '\\' => '\\',
+                            c => return Err(format!("invalid escape `\\{c}`")),
+                        });
+                    }
+                    None => return Err(String::from("unterminated literal")),
+                },
+                Some(c) => value.push(c),
+                None => return Err(String::from("unterminated literal")),
+            }
+        }
+
+        match self.peek() {
+            Some('@') => {
+                self.pos += 1;
+                let mut language = String::new();
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_alphanumeric() || c == '-' {
+                        language.push(c);
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+
+                if language.is_empty() {
+                    return Err(String::from("empty language tag"));
+                }
+
+                Ok(Literal::lang_string(&value, &language))
+            }
+            Some('^') => {
+                self.expect('^')?;
+                self.expect('^')?;
+                let datatype = self.parse_iri()?;
+                Ok(Literal::typed(&value, &datatype))
+            }
+            _ => Ok(Literal::string(&value)),
+        }
+    }
+
+    /// Parses the `\uXXXX` or `\UXXXXXXXX` escape following a backslash
+    fn parse_uchar(&mut self) -> Result<char, String> {
+        let len = match self.next() {
+            Some('u') => 4,
+            Some('U') => 8,
+            _ => return Err(String::from("invalid escape")),
+        };
+
+        let hex: String = (0..len).filter_map(|_| self.next()).collect();
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|_| hex.len() == len)
+            .and_then(char::from_u32)
+            .ok_or(format!("invalid escape `{hex}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nquads_round_trip() {
+        let input = r#"<http://example.com/s> <http://example.com/p> <http://example.com/o> .
+_:b0 <http://example.com/p> "plain" <http://example.com/g> .
+_:b0 <http://example.com/p> "tagged"@en-US _:g .
+_:b0 <http://example.com/p> "42"^^<http://www.w3.org/2001/XMLSchema#integer> .
+_:b0 <http://example.com/p> "line\nbreak \"quoted\" back\\slash\ttab\u0001" .
+"#;
+
+        let dataset = parse_nquads(input).unwrap();
+        assert_eq!(dataset.len(), 5);
+        assert_eq!(dataset[1].graph, Some(Term::Iri(String::from("http://example.com/g"))));
+        assert_eq!(dataset[2].object, Term::Literal(Literal::lang_string("tagged", "en-US")));
+        assert_eq!(
+            dataset[4].object,
+            Term::Literal(Literal::string("line\nbreak \"quoted\" back\\slash\ttab\u{1}"))
+        );
+
+        assert_eq!(to_nquads(&dataset), input);
+    }
+
+    #[test]
+    fn test_nquads_canonical_form() {
+        let input = "_:x\t<http://example.com/p>   \"caf\\u00E9\"^^<http://www.w3.org/2001/XMLSchema#string>.  # comment\n\n# comment only\n";
+
+        let dataset = parse_nquads(input).unwrap();
+        assert_eq!(to_nquads(&dataset), "_:x <http://example.com/p> \"café\" .\n");
+    }
+
+    #[test]
+    fn test_nquads_parsing_failures() {
+        for input in [
+            "<http://example.com/s> <http://example.com/p> <http://example.com/o>",
+            "\"literal\" <http://example.com/p> <http://example.com/o> .",
+            "<http://example.com/s> _:p <http://example.com/o> .",
+            "<relative> <http://example.com/p> <http://example.com/o> .",
+            "<http://example.com/s> <http://example.com/p> \"unterminated .",
+            "<http://example.com/s> <http://example.com/p> \"x\"@ .",
+            "<http://example.com/s> <http://example.com/p> <http://example.com/o> . extra",
+        ] {
+            assert!(
+                matches!(parse_nquads(input), Err(CanonicalizationError::InvalidNQuads { line: 1, .. })),
+                "{input}"
+            );
+        }
+    }
+}
@@ -0,0 +1,402 @@
+//! RDF Dataset Canonicalization (RDFC-1.0) with SHA-256.
+//!
+//! See https://www.w3.org/TR/rdf-canon/
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::Value;
+
+use super::{
+    jsonld::{to_rdf, ContextLoader},
+    rdf::{parse_nquads, to_nquads, Dataset, Quad, Term},
+    CanonicalizationError,
+};
+use crate::crypto::sha256_hash::sha256_hash;
+
+/// Maximum number of N-degree hashing steps, guarding against
+/// poison datasets crafted to make canonicalization intractable.
+pub const MAX_HASH_N_DEGREE_STEPS: usize = 1 << 16;
+
+/// Canonicalizes an RDF dataset in N-Quads form.
+///
+/// Blank nodes are relabeled `_:c14n0`, `_:c14n1`, ... and quads
+/// are sorted, so isomorphic datasets yield identical strings.
+pub fn canonicalize_nquads(input: &str) -> Result<String, CanonicalizationError> {
+    canonicalize_dataset(&parse_nquads(input)?).map(|dataset| to_nquads(&dataset))
+}
+
+/// Canonicalizes a JSON-LD document into N-Quads, resolving
+/// remote contexts with the loader.
+pub fn canonicalize_jsonld(document: &Value, loader: &dyn ContextLoader) -> Result<String, CanonicalizationError> {
+    canonicalize_dataset(&to_rdf(document, loader)?).map(|dataset| to_nquads(&dataset))
+}
+
+/// Canonicalizes an RDF dataset, returning its relabeled quads
+/// in canonical order, without duplicates.
+pub fn canonicalize_dataset(dataset: &[Quad]) -> Result<Dataset, CanonicalizationError> {
+    let mut state = State::new(dataset);
+    let canonical_issuer = state.issue_canonical_ids()?;
+
+    let mut quads: Vec<_> = dataset
+        .iter()
+        .map(|quad| {
+            let quad = quad.map_blank_nodes(|id| canonical_issuer.get(id).expect("issued for all blank nodes").to_owned());
+            (quad.to_string(), quad)
+        })
+        .collect();
+
+    quads.sort_by(|(a, _), (b, _)| a.cmp(b));
+    quads.dedup_by(|(a, _), (b, _)| a == b);
+
+    Ok(quads.into_iter().map(|(_, quad)| quad).collect())
+}
+
+/// Issues identifiers for blank nodes, remembering issuance order.
+#[derive(Debug, Clone)]
+struct IdentifierIssuer {
+    prefix: &'static str,
+    issued: HashMap<String, String>,
+    order: Vec<String>,
+}
+
+impl IdentifierIssuer {
+    fn new(prefix: &'static str) -> Self {
+        Self {
+            prefix,
+            issued: HashMap::new(),
+            order: vec![],
+        }
+    }
+
+    fn get(&self, existing: &str) -> Option<&str> {
+        self.issued.get(existing).map(String::as_str)
+    }
+
+    fn issue(&mut self, existing: &str) -> String {
+        if let Some(id) = self.issued.get(existing) {
+            return id.clone();
+        }
+
+        let id = format!("{}{}", self.prefix, self.order.len());
+        self.issued.insert(existing.to_owned(), id.clone());
+        self.order.push(existing.to_owned());
+
+        id
+    }
+}
+
+struct State<'a> {
+    blank_node_to_quads: HashMap<&'a str, Vec<&'a Quad>>,
+    canonical_issuer: IdentifierIssuer,
+    steps: usize,
+}
+
+impl<'a> State<'a> {
+    fn new(dataset: &'a [Quad]) -> Self {
+        let mut blank_node_to_quads: HashMap<&str, Vec<&Quad>> = HashMap::new();
+
+        for quad in dataset {
+            let components = [Some(&quad.subject), Some(&quad.object), quad.graph.as_ref()];
+            for id in components.into_iter().flatten().filter_map(Term::as_blank_node) {
+                let quads = blank_node_to_quads.entry(id).or_default();
+                if !quads.iter().any(|q| std::ptr::eq(*q, quad)) {
+                    quads.push(quad);
+                }
+            }
+        }
+
+        Self {
+            blank_node_to_quads,
+            canonical_issuer: IdentifierIssuer::new("c14n"),
+            steps: 0,
+        }
+    }
+
+    fn issue_canonical_ids(&mut self) -> Result<IdentifierIssuer, CanonicalizationError> {
+        let mut hash_to_blank_nodes: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for id in self.blank_node_to_quads.keys() {
+            hash_to_blank_nodes.entry(self.hash_first_degree_quads(id)).or_default().push(id);
+        }
+
+        // Blank nodes with unique hashes are labeled first
+        hash_to_blank_nodes.retain(|_, ids| {
+            if let [id] = ids[..] {
+                self.canonical_issuer.issue(id);
+                false
+            } else {
+                true
+            }
+        });
+
+        // Remaining blank nodes are labeled by hashing their neighbourhood
+        for ids in hash_to_blank_nodes.into_values() {
+            let mut hash_path_list = vec![];
+
+            for id in ids {
+                if self.canonical_issuer.get(id).is_some() {
+                    continue;
+                }
+
+                let mut temporary_issuer = IdentifierIssuer::new("b");
+                temporary_issuer.issue(id);
+                hash_path_list.push(self.hash_n_degree_quads(id, temporary_issuer)?);
+            }
+
+            hash_path_list.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (_, issuer) in hash_path_list {
+                for existing in &issuer.order {
+                    self.canonical_issuer.issue(existing);
+                }
+            }
+        }
+
+        Ok(self.canonical_issuer.clone())
+    }
+
+    fn hash_first_degree_quads(&self, reference: &str) -> String {
+        let mut nquads: Vec<_> = self.blank_node_to_quads[reference]
+            .iter()
+            .map(|quad| {
+                let quad = quad.map_blank_nodes(|id| String::from(if id == reference { "a" } else { "z" }));
+                format!("{quad}\n")
+            })
+            .collect();
+
+        nquads.sort();
+        hash(&nquads.concat())
+    }
+
+    fn hash_related_blank_node(&self, related: &str, quad: &Quad, issuer: &IdentifierIssuer, position: char) -> String {
+        let mut input = position.to_string();
+        if position != 'g' {
+            input += &quad.predicate.to_string();
+        }
+
+        match self.canonical_issuer.get(related).or(issuer.get(related)) {
+            Some(id) => input += &format!("_:{id}"),
+            None => input += &self.hash_first_degree_quads(related),
+        }
+
+        hash(&input)
+    }
+
+    fn hash_n_degree_quads(&mut self, identifier: &str, mut issuer: IdentifierIssuer) -> Result<(String, IdentifierIssuer), CanonicalizationError> {
+        self.step()?;
+
+        let mut hash_to_related: BTreeMap<String, Vec<&'a str>> = BTreeMap::new();
+        for quad in &self.blank_node_to_quads[identifier] {
+            let components = [('s', Some(&quad.subject)), ('o', Some(&quad.object)), ('g', quad.graph.as_ref())];
+
+            for (position, term) in components {
+                let Some(related) = term.and_then(Term::as_blank_node) else {
+                    continue;
+                };
+
+                if related != identifier {
+                    let hash = self.hash_related_blank_node(related, quad, &issuer, position);
+                    hash_to_related.entry(hash).or_default().push(related);
+                }
+            }
+        }
+
+        let mut data_to_hash = String::new();
+        for (related_hash, mut related) in hash_to_related {
+            data_to_hash += &related_hash;
+
+            let mut chosen: Option<(String, IdentifierIssuer)> = None;
+            related.sort_unstable();
+
+            loop {
+                self.step()?;
+
+                if let Some(candidate) = self.hash_permutation(&related, &issuer, chosen.as_ref().map(|(path, _)| path.as_str()))? {
+                    chosen = Some(candidate);
+                }
+
+                if !next_permutation(&mut related) {
+                    break;
+                }
+            }
+
+            let (chosen_path, chosen_issuer) = chosen.expect("at least one permutation");
+            data_to_hash += &chosen_path;
+            issuer = chosen_issuer;
+        }
+
+        Ok((hash(&data_to_hash), issuer))
+    }
+
+    /// Computes the path for one permutation of related blank nodes,
+    /// or `None` if it cannot beat the chosen path.
+    fn hash_permutation(
+        &mut self,
+        permutation: &[&str],
+        issuer: &IdentifierIssuer,
+        chosen_path: Option<&str>,
+    ) -> Result<Option<(String, IdentifierIssuer)>, CanonicalizationError> {
+        let worse = |path: &str| chosen_path.is_some_and(|chosen| path.len() >= chosen.len() && path > chosen);
+
+        let mut issuer_copy = issuer.clone();
+        let mut path = String::new();
+        let mut recursion_list = vec![];
+
+        for &related in permutation {
+            match self.canonical_issuer.get(related) {
+                Some(id) => path += &format!("_:{id}"),
+                None => {
+                    if issuer_copy.get(related).is_none() {
+                        recursion_list.push(related);
+                    }
+                    path += &format!("_:{}", issuer_copy.issue(related));
+                }
+            }
+
+            if worse(&path) {
+                return Ok(None);
+            }
+        }
+
+        for related in recursion_list {
+            let (result_hash, result_issuer) = self.hash_n_degree_quads(related, issuer_copy.clone())?;
+            path += &format!("_:{}", issuer_copy.issue(related));
+            path += &format!("<{result_hash}>");
+            issuer_copy = result_issuer;
+
+            if worse(&path) {
+                return Ok(None);
+            }
+        }
+
+        match chosen_path {
+            Some(chosen) if path.as_str() >= chosen => Ok(None),
+            _ => Ok(Some((path, issuer_copy))),
+        }
+    }
+
+    fn step(&mut self) -> Result<(), CanonicalizationError> {
+        self.steps += 1;
+        if self.steps > MAX_HASH_N_DEGREE_STEPS {
+            return Err(CanonicalizationError::ComplexityLimitExceeded);
+        }
+
+        Ok(())
+    }
+}
+
+fn hash(input: &str) -> String {
+    hex::encode(sha256_hash(input.as_bytes()))
+}
+
+/// Rearranges items into the next lexicographic permutation,
+/// returning false once all permutations have been visited.
+fn next_permutation<T: Ord>(items: &mut [T]) -> bool {
+    let Some(i) = items.windows(2).rposition(|w| w[0] < w[1]) else {
+        return false;
+    };
+
+    let j = items.iter().rposition(|item| *item > items[i]).expect("greater item exists");
+    items.swap(i, j);
+    items[i + 1..].reverse();
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_degree_hashes() {
+        // Example from the RDFC-1.0 specification, section 4.4.3
+        let input = r#"<http://example.com/#p> <http://example.com/#q> _:e0 .
+<http://example.com/#p> <http://example.com/#r> _:e1 .
+_:e0 <http://example.com/#s> <http://example.com/#u> .
+_:e1 <http://example.com/#t> <http://example.com/#u> .
+"#;
+
+        let dataset = parse_nquads(input).unwrap();
+        let state = State::new(&dataset);
+        assert_eq!(
+            state.hash_first_degree_quads("e0"),
+            "21d1dd5ba21f3dee9d76c0c00c260fa6f5d5d65315099e553026f4828d0dc77a"
+        );
+        assert_eq!(
+            state.hash_first_degree_quads("e1"),
+            "6fa0b9bdb376852b5743ff39ca4cbf7ea14d34966b2828478fbf222e7c764473"
+        );
+
+        let expected = r#"<http://example.com/#p> <http://example.com/#q> _:c14n0 .
+<http://example.com/#p> <http://example.com/#r> _:c14n1 .
+_:c14n0 <http://example.com/#s> <http://example.com/#u> .
+_:c14n1 <http://example.com/#t> <http://example.com/#u> .
+"#;
+        assert_eq!(canonicalize_nquads(input).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_n_degree_hashes() {
+        // Example from the RDFC-1.0 specification, section 4.8.3
+        let input = r#"<http://example.com/#p> <http://example.com/#q> _:e0 .
+<http://example.com/#p> <http://example.com/#q> _:e1 .
+_:e0 <http://example.com/#p> _:e2 .
+_:e1 <http://example.com/#p> _:e3 .
+_:e2 <http://example.com/#r> _:e3 .
+"#;
+
+        let expected = r#"<http://example.com/#p> <http://example.com/#q> _:c14n2 .
+<http://example.com/#p> <http://example.com/#q> _:c14n3 .
+_:c14n0 <http://example.com/#r> _:c14n1 .
+_:c14n2 <http://example.com/#p> _:c14n1 .
+_:c14n3 <http://example.com/#p> _:c14n0 .
+"#;
+        assert_eq!(canonicalize_nquads(input).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_canonicalization_ignores_labels_and_order() {
+        let input = r#"_:a <http://example.com/#knows> _:b .
+_:b <http://example.com/#knows> _:c .
+_:c <http://example.com/#knows> _:a .
+_:a <http://example.com/#name> "Alice" _:g .
+_:a <http://example.com/#name> "Alice" _:g .
+"#;
+        let relabeled = r#"_:x3 <http://example.com/#knows> _:x1 .
+_:x1 <http://example.com/#name> "Alice" _:x0 .
+_:x2 <http://example.com/#knows> _:x3 .
+_:x1 <http://example.com/#knows> _:x2 .
+"#;
+
+        let canonical = canonicalize_nquads(input).unwrap();
+        assert_eq!(canonical, canonicalize_nquads(relabeled).unwrap());
+        assert_eq!(canonical.lines().count(), 4);
+        assert!(canonical.lines().all(|line| !line.contains("_:a") && !line.contains("_:x")));
+    }
+
+    #[test]
+    fn test_complexity_limit() {
+        // A clique of blank nodes connected by the same predicate,
+        // whose automorphisms make canonicalization explode.
+        let nodes = 12;
+        let mut input = String::new();
+        for i in 0..nodes {
+            for j in 0..nodes {
+                if i != j {
+                    input += &format!("_:n{i} <http://example.com/#p> _:n{j} .\n");
+                }
+            }
+        }
+
+        assert!(matches!(canonicalize_nquads(&input), Err(CanonicalizationError::ComplexityLimitExceeded)));
+    }
+
+    #[test]
+    fn test_next_permutation() {
+        let mut items = [1, 2, 3];
+        let mut permutations = vec![items];
+        while next_permutation(&mut items) {
+            permutations.push(items);
+        }
+
+        assert_eq!(permutations, [[1, 2, 3], [1, 3, 2], [2, 1, 3], [2, 3, 1], [3, 1, 2], [3, 2, 1]]);
+    }
+}
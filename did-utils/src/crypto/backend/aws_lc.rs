@@ -0,0 +1,43 @@
+use aws_lc_rs::{
+    agreement::{self, PrivateKey, UnparsedPublicKey, X25519},
+    digest::{digest, SHA256},
+    signature::{self, Ed25519KeyPair, ED25519},
+};
+
+use crate::crypto::traits::{Error, BYTES_LENGTH_32};
+
+const ED25519_SIGNATURE_LENGTH: usize = 64;
+
+pub(crate) const NAME: &str = "aws-lc";
+
+pub(crate) fn fips_mode() -> bool {
+    aws_lc_rs::try_fips_mode().is_ok()
+}
+
+pub(crate) fn ed25519_sign(secret_key: &[u8; BYTES_LENGTH_32], payload: &[u8]) -> Result<Vec<u8>, Error> {
+    let keypair = Ed25519KeyPair::from_seed_unchecked(secret_key).map_err(|_| Error::InvalidSecretKey)?;
+    Ok(keypair.sign(payload).as_ref().to_vec())
+}
+
+pub(crate) fn ed25519_verify(public_key: &[u8; BYTES_LENGTH_32], payload: &[u8], signature: &[u8]) -> Result<(), Error> {
+    if signature.len() != ED25519_SIGNATURE_LENGTH {
+        return Err(Error::CanNotRetrieveSignature);
+    }
+
+    signature::UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(payload, signature)
+        .map_err(|_| Error::VerificationError)
+}
+
+pub(crate) fn x25519_diffie_hellman(secret_key: &[u8; BYTES_LENGTH_32], public_key: &[u8; BYTES_LENGTH_32]) -> Option<[u8; BYTES_LENGTH_32]> {
+    let secret_key = PrivateKey::from_private_key(&X25519, secret_key).ok()?;
+
+    agreement::agree(&secret_key, UnparsedPublicKey::new(&X25519, public_key), (), |shared_secret| {
+        shared_secret.try_into().map_err(|_| ())
+    })
+    .ok()
+}
+
+pub(crate) fn sha256(bytes: &[u8]) -> [u8; 32] {
+    digest(&SHA256, bytes).as_ref().try_into().expect("SHA-256 digests are 32 bytes long")
+}
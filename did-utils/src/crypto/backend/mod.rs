@@ -0,0 +1,82 @@
+//! Providers of the primitive operations behind the key pairs of this module.
+//!
+//! Ed25519 signatures, X25519 key agreement and SHA-256 hashing are computed
+//! by pure-Rust implementations by default. The `aws-lc` feature swaps them
+//! for [AWS-LC](https://github.com/aws/aws-lc), whose FIPS-validated build
+//! deployments can opt into by enabling the `fips` feature of `aws-lc-rs` in
+//! their final binary. Key pair types keep their representation either way.
+
+#[cfg(feature = "aws-lc")]
+mod aws_lc;
+#[cfg(feature = "aws-lc")]
+pub(crate) use aws_lc::*;
+
+#[cfg(not(feature = "aws-lc"))]
+mod rustcrypto;
+#[cfg(not(feature = "aws-lc"))]
+pub(crate) use rustcrypto::*;
+
+/// Name of the active crypto backend
+pub fn name() -> &'static str {
+    NAME
+}
+
+/// Checks whether the active crypto backend runs in FIPS mode
+pub fn is_fips_enabled() -> bool {
+    fips_mode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::traits::Error;
+
+    fn decode<const N: usize>(hex: &str) -> [u8; N] {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    // Test vector 2 from RFC 8032, section 7.1
+    #[test]
+    fn test_ed25519_rfc8032() {
+        let secret = decode("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb");
+        let public = decode("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c");
+        let expected = "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+                        085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00";
+
+        let signature = ed25519_sign(&secret, &[0x72]).unwrap();
+        assert_eq!(hex::encode(&signature), expected);
+
+        assert!(ed25519_verify(&public, &[0x72], &signature).is_ok());
+        assert!(matches!(ed25519_verify(&public, &[0x73], &signature), Err(Error::VerificationError)));
+        assert!(matches!(
+            ed25519_verify(&public, &[0x72], &signature[1..]),
+            Err(Error::CanNotRetrieveSignature)
+        ));
+    }
+
+    // Test vector from RFC 7748, section 6.1
+    #[test]
+    fn test_x25519_rfc7748() {
+        let alice = decode("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob_public = decode("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f");
+
+        let shared = x25519_diffie_hellman(&alice, &bob_public).unwrap();
+        assert_eq!(hex::encode(shared), "4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            hex::encode(sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_backend_identity() {
+        assert_eq!(name(), if cfg!(feature = "aws-lc") { "aws-lc" } else { "rustcrypto" });
+        if !cfg!(feature = "aws-lc") {
+            assert!(!is_fips_enabled());
+        }
+    }
+}
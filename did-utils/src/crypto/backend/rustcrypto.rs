@@ -0,0 +1,34 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::crypto::traits::{Error, BYTES_LENGTH_32};
+
+pub(crate) const NAME: &str = "rustcrypto";
+
+pub(crate) fn fips_mode() -> bool {
+    false
+}
+
+pub(crate) fn ed25519_sign(secret_key: &[u8; BYTES_LENGTH_32], payload: &[u8]) -> Result<Vec<u8>, Error> {
+    SigningKey::from_bytes(secret_key)
+        .try_sign(payload)
+        .map(|signature| signature.to_bytes().to_vec())
+        .map_err(|_| Error::SignatureError)
+}
+
+pub(crate) fn ed25519_verify(public_key: &[u8; BYTES_LENGTH_32], payload: &[u8], signature: &[u8]) -> Result<(), Error> {
+    let signature = Signature::try_from(signature).map_err(|_| Error::CanNotRetrieveSignature)?;
+    let public_key = VerifyingKey::from_bytes(public_key).map_err(|_| Error::InvalidPublicKey)?;
+
+    public_key.verify(payload, &signature).map_err(|_| Error::VerificationError)
+}
+
+pub(crate) fn x25519_diffie_hellman(secret_key: &[u8; BYTES_LENGTH_32], public_key: &[u8; BYTES_LENGTH_32]) -> Option<[u8; BYTES_LENGTH_32]> {
+    let shared_secret = StaticSecret::from(*secret_key).diffie_hellman(&PublicKey::from(*public_key));
+    Some(shared_secret.to_bytes())
+}
+
+pub(crate) fn sha256(bytes: &[u8]) -> [u8; 32] {
+    Sha256::digest(bytes).into()
+}
@@ -0,0 +1,126 @@
+//! Constant-time comparisons and lookups.
+//!
+//! Comparing secrets, or searching entries by secret-dependent values, with
+//! the standard operators returns as soon as a difference or a match is
+//! found, so that response times reveal how much of a guess was right, or
+//! whether and where an entry exists. The functions below take time
+//! depending on the lengths of their inputs only.
+
+use subtle::{Choice, ConditionallySelectable, ConstantTimeEq};
+
+/// Whether byte strings are equal, in time independent of their content
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Position of the first item matching, visiting all items whether or not
+/// and wherever one matches. The predicate should itself run in constant
+/// time, e.g. with [`ct_eq`].
+pub fn ct_position<T>(items: &[T], mut matches: impl FnMut(&T) -> bool) -> Option<usize> {
+    let mut found = Choice::from(0);
+    let mut position = 0u64;
+
+    for (i, item) in items.iter().enumerate() {
+        let first = Choice::from(matches(item) as u8) & !found;
+        position.conditional_assign(&(i as u64), first);
+        found |= first;
+    }
+
+    bool::from(found).then_some(position as usize)
+}
+
+/// First item matching, visiting all items as per [`ct_position`]
+pub fn ct_find<T>(items: &[T], matches: impl FnMut(&T) -> bool) -> Option<&T> {
+    ct_position(items, matches).map(|i| &items[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Median duration of runs of a function, robust to scheduling noise
+    fn median_duration(runs: usize, mut f: impl FnMut()) -> Duration {
+        let mut durations: Vec<_> = (0..runs)
+            .map(|_| {
+                let start = Instant::now();
+                f();
+                start.elapsed()
+            })
+            .collect();
+
+        durations.sort();
+        durations[runs / 2]
+    }
+
+    /// Whether durations are within a factor of each other, loose enough
+    /// for noisy test runners but not for early returns
+    fn similar(a: Duration, b: Duration) -> bool {
+        let ratio = a.as_secs_f64() / b.as_secs_f64();
+        (0.33..3.0).contains(&ratio)
+    }
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(b"secret", b"secret"));
+        assert!(!ct_eq(b"secret", b"secreT"));
+        assert!(!ct_eq(b"secret", b"secrets"));
+        assert!(ct_eq(b"", b""));
+    }
+
+    #[test]
+    fn test_ct_position() {
+        let items = ["a", "b", "c", "b"];
+
+        assert_eq!(ct_position(&items, |item| ct_eq(item.as_bytes(), b"b")), Some(1));
+        assert_eq!(ct_position(&items, |item| ct_eq(item.as_bytes(), b"d")), None);
+        assert_eq!(ct_find(&items, |item| ct_eq(item.as_bytes(), b"c")), Some(&"c"));
+
+        // All items are visited, whether one matches early or not
+        let mut visited = 0;
+        ct_position(&items, |_| {
+            visited += 1;
+            true
+        });
+        assert_eq!(visited, items.len());
+    }
+
+    /// Compares timings, too noisy for shared runners. Run with:
+    /// `cargo test --release -p did-utils -- --ignored timing`
+    #[test]
+    #[ignore]
+    fn test_ct_eq_timing() {
+        let secret = vec![0x5a; 64 * 1024];
+        let mut first_differing = secret.clone();
+        first_differing[0] ^= 1;
+        let mut last_differing = secret.clone();
+        last_differing[secret.len() - 1] ^= 1;
+        let copy = secret.clone();
+
+        let equal = median_duration(101, || assert!(ct_eq(&secret, &copy)));
+        let early = median_duration(101, || assert!(!ct_eq(&secret, &first_differing)));
+        let late = median_duration(101, || assert!(!ct_eq(&secret, &last_differing)));
+
+        assert!(similar(early, late), "{early:?} vs {late:?}");
+        assert!(similar(early, equal), "{early:?} vs {equal:?}");
+    }
+
+    /// Compares timings, too noisy for shared runners. Run with:
+    /// `cargo test --release -p did-utils -- --ignored timing`
+    #[test]
+    #[ignore]
+    fn test_ct_position_timing() {
+        let items: Vec<_> = (0..4096u32).map(|i| i.to_be_bytes()).collect();
+        let lookup = |target: u32| {
+            let target = target.to_be_bytes();
+            median_duration(101, || {
+                ct_position(&items, |item| ct_eq(item, &target));
+            })
+        };
+
+        let (first, last, missing) = (lookup(0), lookup(4095), lookup(u32::MAX));
+
+        assert!(similar(first, last), "{first:?} vs {last:?}");
+        assert!(similar(first, missing), "{first:?} vs {missing:?}");
+    }
+}
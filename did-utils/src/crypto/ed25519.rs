@@ -1,9 +1,9 @@
 use super::traits::{CoreSign, Error, Generate, KeyMaterial, BYTES_LENGTH_32};
 use super::utils::{generate_seed, clone_slice_to_array};
 use super::x25519::X25519KeyPair;
-use super::AsymmetricKey;
+use super::{backend, AsymmetricKey};
 use curve25519_dalek::edwards::CompressedEdwardsY;
-use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use ed25519_dalek::{SigningKey, VerifyingKey};
 use sha2::{Digest, Sha512};
 
 pub type Ed25519KeyPair = AsymmetricKey<VerifyingKey, SigningKey>;
@@ -79,26 +79,13 @@ impl Generate for Ed25519KeyPair {
 impl CoreSign for Ed25519KeyPair {
     fn sign(&self, payload: &[u8]) -> Result<Vec<u8>, Error> {
         match &self.secret_key {
-            Some(sk) => {
-                match sk.try_sign(payload) {
-                    Ok(signature) => Ok(signature.to_bytes().to_vec()),
-                    Err(_) => Err(Error::SignatureError),
-                }
-            },
+            Some(sk) => backend::ed25519_sign(&sk.to_bytes(), payload),
             None => Err(Error::InvalidSecretKey),
         }
     }
 
     fn verify(&self, payload: &[u8], signature: &[u8]) -> Result<(), Error> {
-        match Signature::try_from(signature) {
-            Ok(sig) => {
-                match self.public_key.verify(payload, &sig) {
-                    Ok(_) => Ok(()),
-                    _ => Err(Error::VerificationError),
-                }
-            },
-            Err(_) => Err(Error::CanNotRetrieveSignature),
-        }
+        backend::ed25519_verify(self.public_key.as_bytes(), payload, signature)
     }
 }
 
@@ -1,8 +1,12 @@
+pub mod backend;
+pub mod constant_time;
 pub mod ed25519;
+pub mod shamir;
 pub mod traits;
 pub mod utils;
 pub mod x25519;
 pub mod sha256_hash;
+pub mod conversion;
 
 mod format;
 
@@ -1,12 +1,18 @@
-use sha2::{Sha256, Digest};
+use super::backend;
 
 pub fn sha256_hash(bytes: &[u8]) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(bytes);
+    backend::sha256(bytes)
+}
+
+/// Multihash code of SHA2-256
+pub const SHA2_256_MULTIHASH_CODE: u8 = 0x12;
 
-    let hash = hasher.finalize();
+/// Computes the SHA2-256 hash of bytes in the multihash format
+pub fn sha256_multihash(bytes: &[u8]) -> Vec<u8> {
+    let mut multihash = vec![SHA2_256_MULTIHASH_CODE, 32];
+    multihash.extend_from_slice(&sha256_hash(bytes));
 
-    hash.as_slice()[..32].try_into().unwrap()
+    multihash
 }
 
 #[cfg(test)]
@@ -24,4 +30,13 @@ mod tests {
 
         assert_eq!(expected, hash);
     }
+
+    #[test]
+    fn test_sha256_multihash() {
+        let multihash = sha256_multihash("Hello, world!".as_bytes());
+
+        assert_eq!(multihash.len(), 34);
+        assert_eq!(multihash[..2], [0x12, 0x20]);
+        assert_eq!(multihash[2..], sha256_hash("Hello, world!".as_bytes()));
+    }
 } 
@@ -0,0 +1,316 @@
+//! Shamir secret sharing of keys.
+//!
+//! A secret is split into N shares, any M of which reconstruct it, while
+//! fewer reveal nothing about it. Each byte of the secret is the constant
+//! term of a random polynomial of degree M - 1 over GF(2^8), evaluated at
+//! the index of each share. Arithmetic runs in constant time, so that
+//! splitting and combining do not leak the secret through timing.
+//!
+//! Each share also carries a random identifier of the secret, so that
+//! shares of different secrets are told apart before combining, and a
+//! digest of the secret, keyed by the identifier, so that a reconstruction
+//! from corrupted or forged shares is detected rather than returned. The
+//! digest lets a secret be checked by brute force, and must only be used
+//! for secrets of full entropy, such as keys.
+//!
+//! Shares print as their index, secret identifier, digest and value, all
+//! hex-encoded but the index, e.g. `3-5e1d...-c07a...-9f0c...`, for
+//! operators to keep apart.
+
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+use super::constant_time::ct_eq;
+
+/// Length of the identifiers of secrets
+pub const ID_LENGTH: usize = 8;
+
+/// Length of the digests of secrets, truncated
+pub const DIGEST_LENGTH: usize = 16;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ShamirError {
+    #[error("threshold must be between 2 and the number of shares, at most 255")]
+    InvalidThreshold,
+    #[error("empty secret")]
+    EmptySecret,
+    #[error("{0} shares given, {1} required")]
+    NotEnoughShares(usize, usize),
+    #[error("shares of different secrets")]
+    MismatchedShares,
+    #[error("duplicate share {0}")]
+    DuplicateShare(u8),
+    #[error("malformed share")]
+    MalformedShare,
+    #[error("shares do not reconstruct the secret")]
+    CorruptedShares,
+}
+
+/// Share of a secret, evaluated at a nonzero index
+#[derive(Clone, PartialEq)]
+pub struct Share {
+    pub index: u8,
+
+    /// Identifier of the secret, common to its shares
+    pub id: [u8; ID_LENGTH],
+
+    /// Digest of the secret, keyed by its identifier
+    pub digest: [u8; DIGEST_LENGTH],
+
+    pub value: Vec<u8>,
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+impl fmt::Debug for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Share")
+            .field("index", &self.index)
+            .field("id", &hex::encode(self.id))
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (id, digest) = (hex::encode(self.id), hex::encode(self.digest));
+        write!(f, "{}-{id}-{digest}-{}", self.index, hex::encode(&self.value))
+    }
+}
+
+impl FromStr for Share {
+    type Err = ShamirError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(4, '-');
+        let mut part = || parts.next().ok_or(ShamirError::MalformedShare);
+        let index: u8 = part()?.parse().map_err(|_| ShamirError::MalformedShare)?;
+
+        let mut id = [0; ID_LENGTH];
+        hex::decode_to_slice(part()?, &mut id).map_err(|_| ShamirError::MalformedShare)?;
+        let mut digest = [0; DIGEST_LENGTH];
+        hex::decode_to_slice(part()?, &mut digest).map_err(|_| ShamirError::MalformedShare)?;
+
+        let value = hex::decode(part()?).map_err(|_| ShamirError::MalformedShare)?;
+        if index == 0 || value.is_empty() {
+            return Err(ShamirError::MalformedShare);
+        }
+
+        Ok(Self { index, id, digest, value })
+    }
+}
+
+/// Splits a secret into `shares` shares, any `threshold` of which
+/// reconstruct it
+pub fn split(secret: &[u8], threshold: u8, shares: u8) -> Result<Vec<Share>, ShamirError> {
+    if threshold < 2 || threshold > shares {
+        return Err(ShamirError::InvalidThreshold);
+    }
+    if secret.is_empty() {
+        return Err(ShamirError::EmptySecret);
+    }
+
+    let mut coefficients = vec![0u8; secret.len() * (threshold as usize - 1)];
+    getrandom::getrandom(&mut coefficients).expect("couldn't generate random coefficients");
+    let mut id = [0; ID_LENGTH];
+    getrandom::getrandom(&mut id).expect("couldn't generate secret identifier");
+    let digest = digest(&id, secret);
+
+    let split = (1..=shares)
+        .map(|index| Share {
+            index,
+            id,
+            digest,
+            value: secret
+                .iter()
+                .enumerate()
+                .map(|(i, &byte)| {
+                    let terms = coefficients.chunks(secret.len()).map(|c| c[i]);
+                    evaluate(byte, terms, index)
+                })
+                .collect(),
+        })
+        .collect();
+
+    coefficients.zeroize();
+    Ok(split)
+}
+
+/// Reconstructs a secret from `threshold` of its shares or more, checking
+/// it against the digest the shares carry
+pub fn combine(shares: &[Share], threshold: u8) -> Result<Vec<u8>, ShamirError> {
+    let threshold = threshold as usize;
+    if threshold < 2 {
+        return Err(ShamirError::InvalidThreshold);
+    }
+    if shares.len() < threshold {
+        return Err(ShamirError::NotEnoughShares(shares.len(), threshold));
+    }
+
+    let shares = &shares[..threshold];
+    let (first, length) = (&shares[0], shares[0].value.len());
+    for (i, share) in shares.iter().enumerate() {
+        let mismatched = share.id != first.id || share.digest != first.digest;
+        if mismatched || share.index == 0 || share.value.len() != length {
+            return Err(ShamirError::MismatchedShares);
+        }
+        if shares[..i].iter().any(|other| other.index == share.index) {
+            return Err(ShamirError::DuplicateShare(share.index));
+        }
+    }
+
+    // Lagrange interpolation at zero, where additions and subtractions
+    // are both XOR
+    let weights: Vec<u8> = shares
+        .iter()
+        .map(|share| {
+            shares.iter().filter(|other| other.index != share.index).fold(1, |weight, other| {
+                let term = div(other.index, other.index ^ share.index);
+                mul(weight, term)
+            })
+        })
+        .collect();
+
+    let mut secret: Vec<u8> = (0..length)
+        .map(|i| {
+            shares
+                .iter()
+                .zip(&weights)
+                .fold(0, |secret, (share, &weight)| secret ^ mul(share.value[i], weight))
+        })
+        .collect();
+
+    if !ct_eq(&digest(&first.id, &secret), &first.digest) {
+        secret.zeroize();
+        return Err(ShamirError::CorruptedShares);
+    }
+
+    Ok(secret)
+}
+
+/// Digest of a secret, keyed by its identifier
+fn digest(id: &[u8; ID_LENGTH], secret: &[u8]) -> [u8; DIGEST_LENGTH] {
+    let hash = Sha256::new().chain_update(id).chain_update(secret).finalize();
+    let mut digest = [0; DIGEST_LENGTH];
+    digest.copy_from_slice(&hash[..DIGEST_LENGTH]);
+    digest
+}
+
+/// Evaluates at `x` the polynomial of constant term `secret`, and of
+/// further coefficients in increasing degree
+fn evaluate(secret: u8, coefficients: impl DoubleEndedIterator<Item = u8>, x: u8) -> u8 {
+    // Horner's method, from the highest degree
+    let highest = coefficients.rev().fold(0, |value, coefficient| mul(value, x) ^ coefficient);
+    mul(highest, x) ^ secret
+}
+
+/// Product in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1, without branching
+/// on its operands
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+
+    product
+}
+
+/// Quotient in GF(2^8), multiplying by the inverse of `b`, i.e. `b^254`
+fn div(a: u8, b: u8) -> u8 {
+    let mut inverse = 1;
+    let mut power = b;
+    for bit in 0..8 {
+        if 254 & (1 << bit) != 0 {
+            inverse = mul(inverse, power);
+        }
+        power = mul(power, power);
+    }
+
+    mul(a, inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_arithmetic() {
+        // Example of FIPS 197
+        assert_eq!(mul(0x57, 0x83), 0xc1);
+        for a in 1..=255 {
+            assert_eq!(mul(div(1, a), a), 1, "{a}");
+        }
+    }
+
+    #[test]
+    fn test_splitting_and_combining() {
+        let secret = b"master key of the keystore, 32B!";
+        let shares = split(secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        assert!(shares.iter().all(|share| share.value != secret));
+
+        // Any three shares reconstruct the secret
+        for picked in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let picked: Vec<_> = picked.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(combine(&picked, 3).unwrap(), secret);
+        }
+
+        // Fewer do not, which the digest detects
+        assert_eq!(combine(&shares[..2], 3), Err(ShamirError::NotEnoughShares(2, 3)));
+        assert_eq!(combine(&shares[..2], 2), Err(ShamirError::CorruptedShares));
+
+        // Shares survive printing
+        let printed: Vec<_> = shares.iter().map(Share::to_string).collect();
+        let parsed: Vec<Share> = printed.iter().map(|s| s.parse().unwrap()).collect();
+        assert_eq!(combine(&parsed[2..], 3).unwrap(), secret);
+    }
+
+    #[test]
+    fn test_refusing_invalid_shares() {
+        let shares = split(&[7; 16], 2, 3).unwrap();
+
+        assert_eq!(split(&[7; 16], 1, 3), Err(ShamirError::InvalidThreshold));
+        assert_eq!(split(&[7; 16], 4, 3), Err(ShamirError::InvalidThreshold));
+        assert_eq!(split(&[], 2, 3), Err(ShamirError::EmptySecret));
+
+        let duplicate = [shares[1].clone(), shares[1].clone()];
+        assert_eq!(combine(&duplicate, 2), Err(ShamirError::DuplicateShare(2)));
+
+        // Shares of other secrets, even of the same length, are told apart
+        for other in [split(&[7; 8], 2, 3).unwrap(), split(&[8; 16], 2, 3).unwrap()] {
+            let mismatched = [shares[0].clone(), other[1].clone()];
+            assert_eq!(combine(&mismatched, 2), Err(ShamirError::MismatchedShares));
+        }
+
+        // Corrupted shares do not reconstruct a wrong secret
+        let mut corrupted = [shares[0].clone(), shares[2].clone()];
+        corrupted[1].value[0] ^= 1;
+        assert_eq!(combine(&corrupted, 2), Err(ShamirError::CorruptedShares));
+
+        let id = "00".repeat(ID_LENGTH);
+        let digest = "00".repeat(DIGEST_LENGTH);
+        let valid = format!("1-{id}-{digest}-07");
+        assert!(valid.parse::<Share>().is_ok());
+        for malformed in [
+            "",
+            "1-07",
+            &format!("0-{id}-{digest}-07"),
+            &format!("1-{id}-{digest}-"),
+            &format!("1-{id}-{digest}-zz"),
+            &format!("256-{id}-{digest}-07"),
+            &format!("1-00-{digest}-07"),
+            &format!("1-{id}-00-07"),
+        ] {
+            assert_eq!(malformed.parse::<Share>(), Err(ShamirError::MalformedShare), "{malformed}");
+        }
+    }
+}
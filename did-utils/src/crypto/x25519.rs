@@ -3,6 +3,7 @@ use x25519_dalek::{PublicKey, StaticSecret};
 use super::traits::{BYTES_LENGTH_32, Error};
 use super::utils::{generate_seed, clone_slice_to_array};
 use super::{
+    backend,
     traits::{Generate, KeyMaterial, ECDH},
     AsymmetricKey,
 };
@@ -79,7 +80,8 @@ impl Generate for X25519KeyPair {
 
 impl ECDH for X25519KeyPair {
     fn key_exchange(&self, key: &Self) -> Option<Vec<u8>> {
-        (self.secret_key).as_ref().map(|x| x.diffie_hellman(&key.public_key).as_bytes().to_vec())
+        let secret_key = self.secret_key.as_ref()?;
+        backend::x25519_diffie_hellman(secret_key.as_bytes(), key.public_key.as_bytes()).map(|x| x.to_vec())
     }
 }
 
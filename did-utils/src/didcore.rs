@@ -83,7 +83,11 @@ pub struct Service {
     #[serde(rename = "type")]
     pub service_type: String,
 
-    pub service_endpoint: String,
+    // A string, map, or set composed of one or more strings and/or maps.
+    // Typed views for known service types are available through
+    // `Service::didcomm_messaging` and `Service::linked_domains`.
+    // See https://www.w3.org/TR/did-core/#dfn-serviceendpoint
+    pub service_endpoint: Value,
 
     // === Additional properties ===
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -277,6 +281,140 @@ impl VerificationMethod {
     }
 }
 
+// === Service Endpoints ===
+
+pub const SERVICE_TYPE_DIDCOMM_MESSAGING: &str = "DIDCommMessaging";
+pub const SERVICE_TYPE_LINKED_DOMAINS: &str = "LinkedDomains";
+
+/// Endpoint of a `DIDCommMessaging` service.
+///
+/// Deserializes from both the map form of DIDComm v2.1 and the plain URI
+/// string form, in which case `accept` and `routingKeys` are absent.
+///
+/// See https://identity.foundation/didcomm-messaging/spec/#did-document-service-endpoint
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DIDCommMessagingService {
+    pub uri: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accept: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub routing_keys: Vec<String>,
+}
+
+impl DIDCommMessagingService {
+    /// Whether the endpoint accepts messages of the given profile, e.g. `didcomm/v2`.
+    ///
+    /// Endpoints not advertising accepted profiles are assumed to accept any.
+    pub fn accepts(&self, profile: &str) -> bool {
+        self.accept.as_ref().is_none_or(|accept| accept.iter().any(|p| p == profile))
+    }
+}
+
+impl<'de> Deserialize<'de> for DIDCommMessagingService {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Uri(String),
+            #[serde(rename_all = "camelCase")]
+            Map {
+                uri: String,
+                #[serde(default)]
+                accept: Option<Vec<String>>,
+                #[serde(default)]
+                routing_keys: Vec<String>,
+            },
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Uri(uri) => Self {
+                uri,
+                accept: None,
+                routing_keys: vec![],
+            },
+            Repr::Map { uri, accept, routing_keys } => Self { uri, accept, routing_keys },
+        })
+    }
+}
+
+/// Endpoint of a `LinkedDomains` service, listing web origins of the DID subject.
+///
+/// Deserializes from both a single origin string and a map of `origins`.
+///
+/// See https://identity.foundation/.well-known/resources/did-configuration/#linked-domain-service-endpoint
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LinkedDomains {
+    pub origins: Vec<String>,
+}
+
+impl<'de> Deserialize<'de> for LinkedDomains {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Origin(String),
+            Map { origins: Vec<String> },
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Origin(origin) => Self { origins: vec![origin] },
+            Repr::Map { origins } => Self { origins },
+        })
+    }
+}
+
+impl Service {
+    /// Reads the endpoints of a `DIDCommMessaging` service, in order of preference.
+    ///
+    /// Returns `None` if the service is of another type or malformed. With a plain
+    /// URI endpoint, `accept` and `routingKeys` are read from the service itself,
+    /// as laid out by DIDComm v2.0.
+    pub fn didcomm_messaging(&self) -> Option<Vec<DIDCommMessagingService>> {
+        if self.service_type != SERVICE_TYPE_DIDCOMM_MESSAGING {
+            return None;
+        }
+
+        match &self.service_endpoint {
+            Value::Array(endpoints) => endpoints.iter().map(|endpoint| serde_json::from_value(endpoint.clone()).ok()).collect(),
+            Value::String(uri) => {
+                let property = |key: &str| self.additional_properties.as_ref().and_then(|props| props.get(key)).cloned();
+                Some(vec![DIDCommMessagingService {
+                    uri: uri.clone(),
+                    accept: match property("accept") {
+                        Some(accept) => Some(serde_json::from_value(accept).ok()?),
+                        None => None,
+                    },
+                    routing_keys: match property("routingKeys") {
+                        Some(routing_keys) => serde_json::from_value(routing_keys).ok()?,
+                        None => vec![],
+                    },
+                }])
+            }
+            endpoint => serde_json::from_value(endpoint.clone()).ok().map(|endpoint| vec![endpoint]),
+        }
+    }
+
+    /// Reads the origins of a `LinkedDomains` service.
+    ///
+    /// Returns `None` if the service is of another type or malformed.
+    pub fn linked_domains(&self) -> Option<LinkedDomains> {
+        if self.service_type != SERVICE_TYPE_LINKED_DOMAINS {
+            return None;
+        }
+
+        serde_json::from_value(self.service_endpoint.clone()).ok()
+    }
+}
+
 // === Proof ===
 #[derive(Serialize, Debug, Clone, PartialEq, Deserialize)]
 #[serde(untagged)]
@@ -285,11 +423,302 @@ pub enum Proofs {
     SetOfProofs(Vec<Proof>),
 }
 
+// === Builder ===
+
+/// Errors reported when building a [`Document`] with a [`DocumentBuilder`]
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum DocumentBuilderError {
+    #[error("invalid DID: {0}")]
+    InvalidDid(String),
+    #[error("invalid DID URL: {0}")]
+    InvalidDidUrl(String),
+    #[error("duplicate id: {0}")]
+    DuplicateId(String),
+    #[error("unresolved verification method reference: {0}")]
+    UnresolvedReference(String),
+}
+
+/// Verification relationships of a DID document.
+///
+/// See https://www.w3.org/TR/did-core/#verification-relationships
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Relationship {
+    Authentication,
+    AssertionMethod,
+    KeyAgreement,
+    CapabilityInvocation,
+    CapabilityDelegation,
+}
+
+/// Entry of a verification relationship, either referencing
+/// or embedding a verification method.
+#[derive(Debug, Clone)]
+enum RelationshipEntry {
+    Reference(String),
+    Embedded(Box<VerificationMethod>),
+}
+
+/// Fluent builder for [`Document`], validating the document on [`build`](Self::build).
+///
+/// Verification methods added with [`add_verification_method`](Self::add_verification_method)
+/// are listed under `verificationMethod` and referenced by the given relationships, while
+/// [`embed_verification_method`](Self::embed_verification_method) embeds them directly in
+/// a relationship.
+///
+/// ```
+/// use did_utils::didcore::{DocumentBuilder, Relationship, Service, VerificationMethod};
+///
+/// let did = "did:example:123";
+/// let diddoc = DocumentBuilder::new(did)
+///     .add_verification_method(
+///         VerificationMethod::new(format!("{did}#keys-1"), "JsonWebKey2020".to_string(), did.to_string()),
+///         &[Relationship::Authentication, Relationship::AssertionMethod],
+///     )
+///     .add_service(Service::new("#didcomm".to_string(), "DIDCommMessaging".to_string(), "https://example.com".to_string()))
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(diddoc.authentication.unwrap().len(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DocumentBuilder {
+    context: Context,
+    id: String,
+    controller: Option<Controller>,
+    also_known_as: Vec<String>,
+    verification_methods: Vec<VerificationMethod>,
+    relationships: Vec<(Relationship, RelationshipEntry)>,
+    services: Vec<Service>,
+    additional_properties: HashMap<String, Value>,
+}
+
+impl DocumentBuilder {
+    /// Starts a document for the given DID, in the DID v1 context.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            context: Context::SingleString(String::from("https://www.w3.org/ns/did/v1")),
+            id: id.into(),
+            controller: None,
+            also_known_as: vec![],
+            verification_methods: vec![],
+            relationships: vec![],
+            services: vec![],
+            additional_properties: HashMap::new(),
+        }
+    }
+
+    /// Replaces the context of the document.
+    pub fn context(mut self, context: Context) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Sets the controller(s) of the document.
+    pub fn controller(mut self, controller: Controller) -> Self {
+        self.controller = Some(controller);
+        self
+    }
+
+    /// Adds an alternative identifier for the subject.
+    pub fn also_known_as(mut self, uri: impl Into<String>) -> Self {
+        self.also_known_as.push(uri.into());
+        self
+    }
+
+    /// Lists a verification method under `verificationMethod`,
+    /// referencing it from the given relationships.
+    pub fn add_verification_method(mut self, method: VerificationMethod, relationships: &[Relationship]) -> Self {
+        for relationship in relationships {
+            self.relationships.push((*relationship, RelationshipEntry::Reference(method.id.clone())));
+        }
+
+        self.verification_methods.push(method);
+        self
+    }
+
+    /// Embeds a verification method in a relationship, only usable for that relationship.
+    pub fn embed_verification_method(mut self, relationship: Relationship, method: VerificationMethod) -> Self {
+        self.relationships.push((relationship, RelationshipEntry::Embedded(Box::new(method))));
+        self
+    }
+
+    /// References a verification method from a relationship.
+    ///
+    /// References into the document itself must resolve to a method added
+    /// with [`add_verification_method`](Self::add_verification_method), while
+    /// references into other DID documents are taken as is.
+    pub fn add_reference(mut self, relationship: Relationship, id: impl Into<String>) -> Self {
+        self.relationships.push((relationship, RelationshipEntry::Reference(id.into())));
+        self
+    }
+
+    /// Adds a service endpoint.
+    pub fn add_service(mut self, service: Service) -> Self {
+        self.services.push(service);
+        self
+    }
+
+    /// Adds a property not modeled by [`Document`].
+    pub fn additional_property(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.additional_properties.insert(key.into(), value);
+        self
+    }
+
+    /// Validates and assembles the document.
+    pub fn build(self) -> Result<Document, DocumentBuilderError> {
+        if !is_valid_did(&self.id) {
+            return Err(DocumentBuilderError::InvalidDid(self.id));
+        }
+
+        let controllers = match &self.controller {
+            Some(Controller::SingleString(controller)) => vec![controller],
+            Some(Controller::SetOfString(controllers)) => controllers.iter().collect(),
+            None => vec![],
+        };
+        if let Some(controller) = controllers.into_iter().find(|controller| !is_valid_did(controller)) {
+            return Err(DocumentBuilderError::InvalidDid(controller.clone()));
+        }
+
+        // Identifiers must be unique across verification methods,
+        // embedded or not, and services.
+        let mut ids = std::collections::HashSet::new();
+        let embedded = self.relationships.iter().filter_map(|(_, entry)| match entry {
+            RelationshipEntry::Embedded(method) => Some(method.as_ref()),
+            RelationshipEntry::Reference(_) => None,
+        });
+
+        for method in self.verification_methods.iter().chain(embedded) {
+            if !is_valid_did_url(&method.id) {
+                return Err(DocumentBuilderError::InvalidDidUrl(method.id.clone()));
+            }
+
+            if !is_valid_did(&method.controller) {
+                return Err(DocumentBuilderError::InvalidDid(method.controller.clone()));
+            }
+
+            if !ids.insert(self.absolute(&method.id)) {
+                return Err(DocumentBuilderError::DuplicateId(method.id.clone()));
+            }
+        }
+
+        for service in &self.services {
+            if !is_valid_did_url(&service.id) && url::Url::parse(&service.id).is_err() {
+                return Err(DocumentBuilderError::InvalidDidUrl(service.id.clone()));
+            }
+
+            if !ids.insert(self.absolute(&service.id)) {
+                return Err(DocumentBuilderError::DuplicateId(service.id.clone()));
+            }
+        }
+
+        // References into this document must resolve to a listed verification method
+        let listed: Vec<_> = self.verification_methods.iter().map(|method| self.absolute(&method.id)).collect();
+        for (_, entry) in &self.relationships {
+            let RelationshipEntry::Reference(reference) = entry else {
+                continue;
+            };
+
+            if !is_valid_did_url(reference) {
+                return Err(DocumentBuilderError::InvalidDidUrl(reference.clone()));
+            }
+
+            let reference = self.absolute(reference);
+            let is_local = reference.split(['/', '?', '#']).next() == Some(self.id.as_str());
+            if is_local && !listed.contains(&reference) {
+                return Err(DocumentBuilderError::UnresolvedReference(reference));
+            }
+        }
+
+        let mut diddoc = Document {
+            controller: self.controller,
+            also_known_as: (!self.also_known_as.is_empty()).then_some(self.also_known_as),
+            verification_method: (!self.verification_methods.is_empty()).then_some(self.verification_methods),
+            service: (!self.services.is_empty()).then_some(self.services),
+            additional_properties: (!self.additional_properties.is_empty()).then_some(self.additional_properties),
+            ..Document::new(self.context, self.id)
+        };
+
+        for (relationship, entry) in self.relationships {
+            macro_rules! push {
+                ($field:ident, $variant:ident) => {
+                    diddoc.$field.get_or_insert_with(Vec::new).push(match entry {
+                        RelationshipEntry::Reference(id) => $variant::Reference(id),
+                        RelationshipEntry::Embedded(method) => $variant::Embedded(method),
+                    })
+                };
+            }
+
+            match relationship {
+                Relationship::Authentication => push!(authentication, Authentication),
+                Relationship::AssertionMethod => push!(assertion_method, AssertionMethod),
+                Relationship::KeyAgreement => push!(key_agreement, KeyAgreement),
+                Relationship::CapabilityInvocation => push!(capability_invocation, CapabilityInvocation),
+                Relationship::CapabilityDelegation => push!(capability_delegation, CapabilityDelegation),
+            }
+        }
+
+        Ok(diddoc)
+    }
+
+    /// Resolves a relative DID URL against the document's DID.
+    fn absolute(&self, id: &str) -> String {
+        if id.starts_with(['#', '/', '?']) {
+            format!("{}{id}", self.id)
+        } else {
+            id.to_owned()
+        }
+    }
+}
+
+/// Checks a DID against the syntax of the DID core specification.
+///
+/// See https://www.w3.org/TR/did-core/#did-syntax
+fn is_valid_did(did: &str) -> bool {
+    let Some((method, method_specific_id)) = did.strip_prefix("did:").and_then(|rest| rest.split_once(':')) else {
+        return false;
+    };
+
+    let is_method_char = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+    if method.is_empty() || !method.chars().all(is_method_char) {
+        return false;
+    }
+
+    let bytes = method_specific_id.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                if !bytes.get(i + 1..i + 3).is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit)) {
+                    return false;
+                }
+                i += 3;
+            }
+            c if c.is_ascii_alphanumeric() || matches!(c, b'.' | b'-' | b'_' | b':') => i += 1,
+            _ => return false,
+        }
+    }
+
+    !method_specific_id.is_empty() && !method_specific_id.ends_with(':')
+}
+
+/// Checks a DID URL, possibly relative to the document's DID.
+///
+/// See https://www.w3.org/TR/did-core/#did-url-syntax
+fn is_valid_did_url(did_url: &str) -> bool {
+    if let Some(fragment) = did_url.strip_prefix('#') {
+        return !fragment.is_empty();
+    }
+
+    let did = did_url.split(['/', '?', '#']).next().unwrap_or_default();
+    is_valid_did(did) && !did_url.chars().any(char::is_whitespace)
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
     use crate::key_jwk::key::Key;
     use multibase::Base::Base64Url;
+    use serde_json::json;
 
     // A test that reads the file at ../test_resources/did_example_1.json, uses serde_json to convert
     // the content into a json string uses the json_canon library to canonicalize the json string.
@@ -540,6 +969,190 @@ pub mod tests {
         }
     }
 
+    fn builder_vm(id: &str) -> VerificationMethod {
+        VerificationMethod {
+            public_key: Some(KeyFormat::Multibase("z6MkjLrk3gKS2nnkeWcmcxiZPGskmesDpuwRBorgHxUXfxnG".to_string())),
+            ..VerificationMethod::new(id.to_string(), "Multikey".to_string(), "did:example:123".to_string())
+        }
+    }
+
+    #[test]
+    fn test_document_builder() {
+        let diddoc = DocumentBuilder::new("did:example:123")
+            .also_known_as("https://example.com")
+            .add_verification_method(
+                builder_vm("did:example:123#keys-1"),
+                &[Relationship::Authentication, Relationship::AssertionMethod],
+            )
+            .add_verification_method(builder_vm("#keys-2"), &[])
+            .embed_verification_method(Relationship::KeyAgreement, builder_vm("did:example:123#keys-3"))
+            .add_reference(Relationship::CapabilityInvocation, "did:example:123#keys-2")
+            .add_reference(Relationship::CapabilityDelegation, "did:example:456#keys-1")
+            .add_service(Service::new(
+                "did:example:123#didcomm".to_string(),
+                "DIDCommMessaging".to_string(),
+                "https://example.com/didcomm".to_string(),
+            ))
+            .build()
+            .unwrap();
+
+        assert_eq!(diddoc.also_known_as, Some(vec!["https://example.com".to_string()]));
+        assert_eq!(diddoc.verification_method.as_ref().unwrap().len(), 2);
+        assert_eq!(
+            diddoc.authentication,
+            Some(vec![Authentication::Reference("did:example:123#keys-1".to_string())])
+        );
+        assert_eq!(
+            diddoc.assertion_method,
+            Some(vec![AssertionMethod::Reference("did:example:123#keys-1".to_string())])
+        );
+        assert_eq!(
+            diddoc.key_agreement,
+            Some(vec![KeyAgreement::Embedded(Box::new(builder_vm("did:example:123#keys-3")))])
+        );
+        assert_eq!(diddoc.capability_invocation.as_ref().unwrap().len(), 1);
+        assert_eq!(diddoc.capability_delegation.as_ref().unwrap().len(), 1);
+        assert_eq!(diddoc.service.as_ref().unwrap().len(), 1);
+        assert!(diddoc.additional_properties.is_none());
+
+        // Empty members are omitted
+        let diddoc = DocumentBuilder::new("did:example:123").build().unwrap();
+        assert_eq!(
+            json_canon::to_string(&diddoc).unwrap(),
+            r#"{"@context":"https://www.w3.org/ns/did/v1","id":"did:example:123"}"#
+        );
+    }
+
+    #[test]
+    fn test_document_builder_validation() {
+        let cases = [
+            (
+                DocumentBuilder::new("did:Example:123"),
+                DocumentBuilderError::InvalidDid("did:Example:123".to_string()),
+            ),
+            (
+                DocumentBuilder::new("did:example:"),
+                DocumentBuilderError::InvalidDid("did:example:".to_string()),
+            ),
+            (
+                DocumentBuilder::new("did:example:a%2"),
+                DocumentBuilderError::InvalidDid("did:example:a%2".to_string()),
+            ),
+            (
+                DocumentBuilder::new("did:example:123").controller(Controller::SetOfString(vec!["did:example:456".to_string(), "alice".to_string()])),
+                DocumentBuilderError::InvalidDid("alice".to_string()),
+            ),
+            (
+                DocumentBuilder::new("did:example:123").add_verification_method(builder_vm("keys-1"), &[]),
+                DocumentBuilderError::InvalidDidUrl("keys-1".to_string()),
+            ),
+            (
+                DocumentBuilder::new("did:example:123")
+                    .add_verification_method(builder_vm("did:example:123#keys-1"), &[])
+                    .embed_verification_method(Relationship::Authentication, builder_vm("#keys-1")),
+                DocumentBuilderError::DuplicateId("#keys-1".to_string()),
+            ),
+            (
+                DocumentBuilder::new("did:example:123")
+                    .add_verification_method(builder_vm("#keys-1"), &[])
+                    .add_service(Service::new(
+                        "#keys-1".to_string(),
+                        "LinkedDomains".to_string(),
+                        "https://example.com".to_string(),
+                    )),
+                DocumentBuilderError::DuplicateId("#keys-1".to_string()),
+            ),
+            (
+                DocumentBuilder::new("did:example:123")
+                    .embed_verification_method(Relationship::Authentication, builder_vm("#keys-1"))
+                    .add_reference(Relationship::AssertionMethod, "#keys-1"),
+                DocumentBuilderError::UnresolvedReference("did:example:123#keys-1".to_string()),
+            ),
+        ];
+
+        for (builder, expected) in cases {
+            assert_eq!(builder.build().unwrap_err(), expected);
+        }
+    }
+
+    #[test]
+    fn test_didcomm_messaging_service() {
+        let service: Service = serde_json::from_value(json!({
+            "id": "did:example:123#didcomm",
+            "type": "DIDCommMessaging",
+            "serviceEndpoint": [
+                {
+                    "uri": "https://example.com/didcomm",
+                    "accept": ["didcomm/v2"],
+                    "routingKeys": ["did:example:mediator#key-1"]
+                },
+                "wss://example.com/didcomm"
+            ]
+        }))
+        .unwrap();
+
+        let endpoints = service.didcomm_messaging().unwrap();
+        assert_eq!(
+            endpoints,
+            vec![
+                DIDCommMessagingService {
+                    uri: "https://example.com/didcomm".to_string(),
+                    accept: Some(vec!["didcomm/v2".to_string()]),
+                    routing_keys: vec!["did:example:mediator#key-1".to_string()],
+                },
+                DIDCommMessagingService {
+                    uri: "wss://example.com/didcomm".to_string(),
+                    accept: None,
+                    routing_keys: vec![],
+                },
+            ]
+        );
+        assert!(endpoints[0].accepts("didcomm/v2") && !endpoints[0].accepts("didcomm/aip2;env=rfc19"));
+        assert!(endpoints[1].accepts("didcomm/v2"));
+
+        // Serialized in map form
+        assert_eq!(serde_json::to_value(&endpoints[1]).unwrap(), json!({"uri": "wss://example.com/didcomm"}));
+
+        // DIDComm v2.0 layout, with properties on the service itself
+        let service: Service = serde_json::from_value(json!({
+            "id": "#didcomm",
+            "type": "DIDCommMessaging",
+            "serviceEndpoint": "https://example.com/didcomm",
+            "accept": ["didcomm/v2"],
+            "routingKeys": ["did:example:mediator#key-1"]
+        }))
+        .unwrap();
+        assert_eq!(service.didcomm_messaging().unwrap(), endpoints[..1]);
+
+        // Other service types or malformed endpoints are not interpreted
+        let service = Service::new("#domain".to_string(), "LinkedDomains".to_string(), "https://example.com".to_string());
+        assert!(service.didcomm_messaging().is_none());
+
+        let service = Service {
+            service_endpoint: json!({"accept": ["didcomm/v2"]}),
+            ..Service::new("#didcomm".to_string(), "DIDCommMessaging".to_string(), String::new())
+        };
+        assert!(service.didcomm_messaging().is_none());
+    }
+
+    #[test]
+    fn test_linked_domains_service() {
+        let service = Service::new("#domain".to_string(), "LinkedDomains".to_string(), "https://example.com".to_string());
+        assert_eq!(service.linked_domains().unwrap().origins, vec!["https://example.com"]);
+
+        let service: Service = serde_json::from_value(json!({
+            "id": "#domains",
+            "type": "LinkedDomains",
+            "serviceEndpoint": {"origins": ["https://example.com", "https://example.org"]}
+        }))
+        .unwrap();
+        assert_eq!(
+            service.linked_domains().unwrap().origins,
+            vec!["https://example.com", "https://example.org"]
+        );
+        assert!(service.didcomm_messaging().is_none());
+    }
+
     // read a file given the path as method param and write content to console
     // std::fs::read_to_string() expects the path from the project root.
     fn read_write_did(raw_path: &str, canon_path: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -1,4 +1,6 @@
 // Module: didkit
+use serde_json::Value;
+
 use crate::{didcore::{Document, VerificationMethod, Authentication, AssertionMethod, KeyAgreement, Service}, ldmodel::Context};
 
 
@@ -21,7 +23,7 @@ impl Service {
         Self {
             id,
             service_type,
-            service_endpoint,
+            service_endpoint: Value::String(service_endpoint),
             additional_properties: None,
         }
     }
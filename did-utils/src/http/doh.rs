@@ -0,0 +1,216 @@
+use std::{
+    error::Error,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::{
+    client::connect::dns::{GaiResolver, Name},
+    header::ACCEPT,
+    Body, Request, Uri,
+};
+use serde::Deserialize;
+use tower_service::Service;
+
+use super::{DohClient, HttpClientError};
+
+/// DNS record types, see https://www.iana.org/assignments/dns-parameters
+const RECORD_TYPE_A: u16 = 1;
+const RECORD_TYPE_AAAA: u16 = 28;
+
+/// Resolver of host names for outbound connections
+#[derive(Clone)]
+pub enum DnsResolver {
+    /// Relies on the system's `getaddrinfo`
+    System(GaiResolver),
+    /// Queries a DNS-over-HTTPS endpoint
+    Https(Box<DohResolver>),
+}
+
+impl Service<Name> for DnsResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = Box<dyn Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        match self {
+            DnsResolver::System(resolver) => {
+                let mut resolver = resolver.clone();
+                Box::pin(async move { Ok(resolver.call(name).await?.collect::<Vec<_>>().into_iter()) })
+            }
+            DnsResolver::Https(resolver) => {
+                let resolver = resolver.clone();
+                Box::pin(async move { Ok(resolver.lookup(name.as_str()).await?.into_iter()) })
+            }
+        }
+    }
+}
+
+/// Resolver querying the JSON API of a DNS-over-HTTPS endpoint.
+///
+/// See https://developers.cloudflare.com/1.1.1.1/encryption/dns-over-https/make-api-requests/dns-json
+#[derive(Clone)]
+pub struct DohResolver {
+    endpoint: Uri,
+    client: DohClient,
+}
+
+#[derive(Deserialize)]
+struct DohResponse {
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    data: String,
+}
+
+impl DohResolver {
+    /// Creates resolver querying the endpoint with the given client,
+    /// see [`crate::http::HttpClientConfig::build`]
+    pub fn new(endpoint: Uri, client: DohClient) -> Self {
+        Self { endpoint, client }
+    }
+
+    /// Looks up the IPv4 addresses of a host, falling back to IPv6 ones
+    pub async fn lookup(&self, host: &str) -> Result<Vec<SocketAddr>, HttpClientError> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, 0)]);
+        }
+
+        for record_type in [RECORD_TYPE_A, RECORD_TYPE_AAAA] {
+            let addrs = self.query(host, record_type).await?;
+            if !addrs.is_empty() {
+                return Ok(addrs);
+            }
+        }
+
+        Err(HttpClientError::DnsError(host.to_string()))
+    }
+
+    async fn query(&self, host: &str, record_type: u16) -> Result<Vec<SocketAddr>, HttpClientError> {
+        let name: String = url::form_urlencoded::byte_serialize(host.as_bytes()).collect();
+        let request = Request::get(format!("{}?name={name}&type={record_type}", self.endpoint))
+            .header(ACCEPT, "application/dns-json")
+            .body(Body::empty())
+            .map_err(|_| HttpClientError::InvalidUri(self.endpoint.to_string()))?;
+
+        let response = self.client.request(request).await?;
+        if !response.status().is_success() {
+            return Err(HttpClientError::DnsError(host.to_string()));
+        }
+
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let response: DohResponse = serde_json::from_slice(&body).map_err(|_| HttpClientError::DnsError(host.to_string()))?;
+
+        // Answers may include CNAME records along the way
+        Ok(response
+            .answer
+            .into_iter()
+            .filter(|answer| answer.record_type == record_type)
+            .filter_map(|answer| answer.data.parse::<IpAddr>().ok())
+            .map(|ip| SocketAddr::new(ip, 0))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpClientConfig;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Response, Server,
+    };
+    use std::convert::Infallible;
+
+    async fn mock_server_handler(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let response = match (req.uri().path(), req.uri().query()) {
+            ("/dns-query", Some("name=mediator.test&type=1")) => Response::new(Body::from(
+                r#"{"Status": 0, "Answer": [
+                    {"name": "mediator.test", "type": 5, "TTL": 300, "data": "alias.mediator.test"},
+                    {"name": "alias.mediator.test", "type": 1, "TTL": 300, "data": "127.0.0.1"}
+                ]}"#,
+            )),
+            ("/dns-query", _) => Response::new(Body::from(r#"{"Status": 3}"#)),
+            (path, _) => Response::new(Body::from(format!("{} {path}", req.headers()["host"].to_str().unwrap()))),
+        };
+
+        Ok(response)
+    }
+
+    fn create_mock_server() -> SocketAddr {
+        let make_svc = make_service_fn(|_conn| async { Ok::<_, Infallible>(service_fn(mock_server_handler)) });
+
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let addr = server.local_addr();
+
+        tokio::spawn(async move {
+            server.await.unwrap();
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_doh_lookup() {
+        let addr = create_mock_server();
+        let client = HttpClientConfig::default().doh_client().unwrap();
+        let resolver = DohResolver::new(format!("http://{addr}/dns-query").parse().unwrap(), client);
+
+        let addrs = resolver.lookup("mediator.test").await.unwrap();
+        assert_eq!(addrs, vec![SocketAddr::from(([127, 0, 0, 1], 0))]);
+
+        let addrs = resolver.lookup("::1").await.unwrap();
+        assert_eq!(addrs, vec!["[::1]:0".parse().unwrap()]);
+
+        assert!(matches!(
+            resolver.lookup("unknown.test").await,
+            Err(HttpClientError::DnsError(host)) if host == "unknown.test"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_http_client_resolving_over_doh() {
+        let addr = create_mock_server();
+        let client = HttpClientConfig {
+            doh_endpoint: Some(format!("http://{addr}/dns-query")),
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+
+        let uri = format!("http://mediator.test:{}/.well-known/did.json", addr.port());
+        let response = client.get(uri.parse().unwrap()).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        assert_eq!(body, format!("mediator.test:{} /.well-known/did.json", addr.port()));
+    }
+
+    #[tokio::test]
+    async fn test_doh_queries_going_through_proxy() {
+        let addr = create_mock_server();
+        let config = HttpClientConfig {
+            proxy: Some(format!("http://{addr}")),
+            // Only resolvable by the proxy
+            doh_endpoint: Some(String::from("http://doh.test/dns-query")),
+            ..Default::default()
+        };
+
+        let DnsResolver::Https(resolver) = config.dns_resolver().unwrap() else {
+            panic!("expected DNS-over-HTTPS resolver");
+        };
+
+        let addrs = resolver.lookup("mediator.test").await.unwrap();
+        assert_eq!(addrs, vec![SocketAddr::from(([127, 0, 0, 1], 0))]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Configurable HTTP client for outbound requests, e.g. did:web resolution.
+//!
+//! Deployments behind corporate proxies, trusting private certificate
+//! authorities or requiring DNS privacy configure a single client here,
+//! which is then handed over to the components issuing requests.
+
+pub mod doh;
+
+use hyper::{
+    client::{connect::dns::GaiResolver, HttpConnector},
+    Client,
+};
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_tls::HttpsConnector;
+use native_tls::{Certificate, TlsConnector};
+use thiserror::Error;
+
+use doh::{DnsResolver, DohResolver};
+
+/// Connector of an [`HttpClient`], e.g. to name a
+/// [`crate::methods::did_web::resolver::DidWebResolver`] using it
+pub type HttpClientConnector = ProxyConnector<HttpsConnector<HttpConnector<DnsResolver>>>;
+
+/// HTTP client built from an [`HttpClientConfig`]
+pub type HttpClient = Client<HttpClientConnector>;
+
+/// HTTP client querying the DNS-over-HTTPS endpoint, whose host name is
+/// resolved by the system
+pub type DohClient = Client<ProxyConnector<HttpsConnector<HttpConnector>>>;
+
+#[derive(Debug, Error)]
+pub enum HttpClientError {
+    #[error("invalid URI: {0}")]
+    InvalidUri(String),
+    #[error("TLS error: {0}")]
+    TlsError(#[from] native_tls::Error),
+    #[error("DNS resolution failed for {0}")]
+    DnsError(String),
+    #[error("HTTP error: {0}")]
+    HttpError(#[from] hyper::Error),
+    #[error("failed to read root certificates from {0}: {1}")]
+    CertificatesError(String, std::io::Error),
+}
+
+/// Settings of the HTTP client used for outbound requests
+#[derive(Debug, Clone, Default)]
+pub struct HttpClientConfig {
+    /// Proxy to tunnel all requests through, e.g. `http://proxy.internal:3128`
+    pub proxy: Option<String>,
+
+    /// PEM-encoded certificates of root CAs to trust besides system ones,
+    /// also when querying the DNS-over-HTTPS endpoint
+    pub root_certificates: Vec<String>,
+
+    /// DNS-over-HTTPS endpoint serving the JSON API, e.g.
+    /// `https://cloudflare-dns.com/dns-query`, queried through the proxy
+    /// if any. Host names are resolved by the system if unset.
+    pub doh_endpoint: Option<String>,
+}
+
+impl HttpClientConfig {
+    /// Reads settings from the `HTTPS_PROXY`, `HTTPS_ROOT_CERTIFICATES` and
+    /// `DOH_ENDPOINT` environment variables, the second one being the path
+    /// to a PEM bundle of root CAs
+    pub fn from_env() -> Result<Self, HttpClientError> {
+        let root_certificates = match std::env::var("HTTPS_ROOT_CERTIFICATES") {
+            Ok(path) => {
+                let bundle = std::fs::read_to_string(&path).map_err(|err| HttpClientError::CertificatesError(path, err))?;
+                split_pem_bundle(&bundle)
+            }
+            Err(_) => vec![],
+        };
+
+        Ok(Self {
+            proxy: std::env::var("HTTPS_PROXY").or_else(|_| std::env::var("https_proxy")).ok(),
+            root_certificates,
+            doh_endpoint: std::env::var("DOH_ENDPOINT").ok(),
+        })
+    }
+
+    /// Builds HTTP client, supporting both HTTP and HTTPS requests
+    pub fn build(&self) -> Result<HttpClient, HttpClientError> {
+        Ok(Client::builder().build(self.connector(self.dns_resolver()?)?))
+    }
+
+    fn dns_resolver(&self) -> Result<DnsResolver, HttpClientError> {
+        Ok(match &self.doh_endpoint {
+            // DNS queries must not leak around the proxy, nor trust other CAs
+            Some(endpoint) => DnsResolver::Https(Box::new(DohResolver::new(parse_uri(endpoint)?, self.doh_client()?))),
+            None => DnsResolver::System(GaiResolver::new()),
+        })
+    }
+
+    fn doh_client(&self) -> Result<DohClient, HttpClientError> {
+        Ok(Client::builder().build(self.connector(GaiResolver::new())?))
+    }
+
+    fn connector<R>(&self, resolver: R) -> Result<ProxyConnector<HttpsConnector<HttpConnector<R>>>, HttpClientError> {
+        let mut http = HttpConnector::new_with_resolver(resolver);
+        http.enforce_http(false);
+
+        let mut tls = TlsConnector::builder();
+        for pem in &self.root_certificates {
+            tls.add_root_certificate(Certificate::from_pem(pem.as_bytes())?);
+        }
+        let tls = tls.build()?;
+
+        let https = HttpsConnector::from((http, tls.clone().into()));
+        let mut connector = ProxyConnector::unsecured(https);
+
+        if let Some(proxy) = &self.proxy {
+            connector.add_proxy(Proxy::new(Intercept::All, parse_uri(proxy)?));
+            // Requests to HTTPS hosts are tunneled through the proxy,
+            // which must then not weaken verification of their certificates
+            connector.set_tls(Some(tls));
+        }
+
+        Ok(connector)
+    }
+}
+
+/// Splits a PEM bundle into its certificates
+fn split_pem_bundle(bundle: &str) -> Vec<String> {
+    const END_MARKER: &str = "-----END CERTIFICATE-----";
+
+    bundle
+        .split_inclusive(END_MARKER)
+        .filter(|pem| pem.contains(END_MARKER))
+        .map(|pem| pem.trim().to_string())
+        .collect()
+}
+
+fn parse_uri(uri: &str) -> Result<hyper::Uri, HttpClientError> {
+    uri.parse().map_err(|_| HttpClientError::InvalidUri(uri.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_building_http_client() {
+        let config = HttpClientConfig {
+            proxy: Some(String::from("http://proxy.internal:3128")),
+            doh_endpoint: Some(String::from("https://cloudflare-dns.com/dns-query")),
+            ..Default::default()
+        };
+
+        assert!(config.build().is_ok());
+    }
+
+    #[test]
+    fn test_building_http_client_fails_on_invalid_settings() {
+        let config = HttpClientConfig {
+            proxy: Some(String::from("http://proxy internal")),
+            ..Default::default()
+        };
+        assert!(matches!(config.build(), Err(HttpClientError::InvalidUri(_))));
+
+        let config = HttpClientConfig {
+            root_certificates: vec![String::from("-----BEGIN CERTIFICATE-----\nnot a certificate")],
+            ..Default::default()
+        };
+        assert!(matches!(config.build(), Err(HttpClientError::TlsError(_))));
+    }
+
+    #[test]
+    fn test_splitting_pem_bundle() {
+        let bundle = "# Root A\n-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n\n-----BEGIN CERTIFICATE-----\nBBBB\n-----END CERTIFICATE-----\n";
+
+        assert_eq!(
+            split_pem_bundle(bundle),
+            vec![
+                "# Root A\n-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----",
+                "-----BEGIN CERTIFICATE-----\nBBBB\n-----END CERTIFICATE-----",
+            ]
+        );
+        assert!(split_pem_bundle("").is_empty());
+    }
+}
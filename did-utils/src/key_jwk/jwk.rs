@@ -1,6 +1,9 @@
-use crate::{key_jwk::key::Key, key_jwk::prm::Parameters};
+use crate::{crypto::sha256_hash::sha256_hash, key_jwk::key::Key, key_jwk::prm::Parameters};
 extern crate alloc;
+use base64ct::{Base64UrlUnpadded, Encoding};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 
 /// A set of JSON Web Keys.
 ///
@@ -27,6 +30,65 @@ pub struct Jwk {
     pub prm: Parameters,
 }
 
+impl Jwk {
+    /// Computes the thumbprint of the key, as defined in [RFC7638].
+    ///
+    /// Thumbprints only cover the required members of the public key, so
+    /// they identify key material regardless of parameters such as `kid`.
+    ///
+    /// [RFC7638]: https://datatracker.ietf.org/doc/html/rfc7638
+    pub fn thumbprint(&self) -> Result<String, serde_json::Error> {
+        let members: &[&str] = match &self.key {
+            Key::Ec(_) => &["crv", "kty", "x", "y"],
+            Key::Rsa(_) => &["e", "kty", "n"],
+            Key::Oct(_) => &["k", "kty"],
+            Key::Okp(_) => &["crv", "kty", "x"],
+        };
+
+        let Value::Object(key) = serde_json::to_value(&self.key)? else {
+            unreachable!("keys serialize to objects");
+        };
+
+        // Members in lexicographic order, without whitespace
+        let required: BTreeMap<_, _> = key.into_iter().filter(|(name, _)| members.contains(&name.as_str())).collect();
+        let canonical = serde_json::to_string(&required)?;
+
+        Ok(Base64UrlUnpadded::encode_string(&sha256_hash(canonical.as_bytes())))
+    }
+}
+
+#[cfg(test)]
+mod rfc7638 {
+    use super::*;
+
+    #[test]
+    fn s3_1() {
+        let jwk: Jwk = serde_json::from_value(serde_json::json!({
+            "kty": "RSA",
+            "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            "e": "AQAB",
+            "alg": "RS256",
+            "kid": "2011-04-29"
+        }))
+        .unwrap();
+
+        assert_eq!(jwk.thumbprint().unwrap(), "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs");
+    }
+
+    // Example from RFC8037, Appendix A.3
+    #[test]
+    fn okp() {
+        let jwk: Jwk = serde_json::from_value(serde_json::json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo"
+        }))
+        .unwrap();
+
+        assert_eq!(jwk.thumbprint().unwrap(), "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k");
+    }
+}
+
 #[cfg(test)]
 mod rfc7517 {
     use crate::key_jwk::prm::Signing;
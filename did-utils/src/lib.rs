@@ -1,8 +1,10 @@
+pub mod canonicalization;
 pub mod crypto;
 pub mod didcore;
 pub mod didkit;
+pub mod http;
 pub mod key_jwk;
-pub mod proof;
-pub mod vc;
 pub mod ldmodel;
 pub mod methods;
+pub mod proof;
+pub mod vc;
@@ -0,0 +1,454 @@
+use std::{collections::HashMap, io::Write, path::PathBuf, sync::RwLock, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    crypto::sha256_hash::sha256_hash,
+    didcore::Document as DIDDocument,
+    ldmodel::Context,
+    methods::{
+        errors::DIDResolutionError,
+        traits::{DIDDocumentMetadata, DIDResolutionMetadata, DIDResolutionOptions, DIDResolver, MediaType, ResolutionOutput},
+    },
+};
+
+/// DID document retained from a previous resolution
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedDocument {
+    pub did_document: DIDDocument,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub did_document_metadata: Option<DIDDocumentMetadata>,
+    pub cached_at: DateTime<Utc>,
+}
+
+/// Storage backend for cached DID documents
+pub trait DIDDocumentStore: Send + Sync {
+    fn get(&self, did: &str) -> Option<CachedDocument>;
+    /// Stores entry, failing on documents of another DID
+    fn put(&self, did: &str, entry: CachedDocument) -> std::io::Result<()>;
+    /// Removes entry, returning whether there was one
+    fn evict(&self, did: &str) -> std::io::Result<bool>;
+}
+
+/// Non-persistent store, losing its entries on restarts
+#[derive(Default)]
+pub struct InMemoryDocumentStore {
+    entries: RwLock<HashMap<String, CachedDocument>>,
+}
+
+impl DIDDocumentStore for InMemoryDocumentStore {
+    fn get(&self, did: &str) -> Option<CachedDocument> {
+        self.entries.read().unwrap().get(did).cloned()
+    }
+
+    fn put(&self, did: &str, entry: CachedDocument) -> std::io::Result<()> {
+        check_id(did, &entry)?;
+        self.entries.write().unwrap().insert(did.to_string(), entry);
+        Ok(())
+    }
+
+    fn evict(&self, did: &str) -> std::io::Result<bool> {
+        Ok(self.entries.write().unwrap().remove(did).is_some())
+    }
+}
+
+/// Persistent store keeping an entry per file in a directory
+pub struct FileSystemDocumentStore {
+    dirpath: PathBuf,
+}
+
+impl FileSystemDocumentStore {
+    pub fn new(dirpath: impl Into<PathBuf>) -> Self {
+        Self { dirpath: dirpath.into() }
+    }
+
+    // DIDs are hashed into file names as they may hold path separators
+    fn path(&self, did: &str) -> PathBuf {
+        self.dirpath.join(format!("{}.json", hex::encode(sha256_hash(did.as_bytes()))))
+    }
+}
+
+impl DIDDocumentStore for FileSystemDocumentStore {
+    fn get(&self, did: &str) -> Option<CachedDocument> {
+        let content = std::fs::read_to_string(self.path(did)).ok()?;
+        let entry: CachedDocument = serde_json::from_str(&content).ok()?;
+
+        // Guard against hash collisions and misplaced files
+        (entry.did_document.id == did).then_some(entry)
+    }
+
+    fn put(&self, did: &str, entry: CachedDocument) -> std::io::Result<()> {
+        check_id(did, &entry)?;
+        std::fs::create_dir_all(&self.dirpath)?;
+
+        // Write to a temporary file first so that readers never observe a partial entry
+        let path = self.path(did);
+        let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(&entry)?)?;
+        file.sync_all()?;
+
+        std::fs::rename(&tmp_path, path)
+    }
+
+    fn evict(&self, did: &str) -> std::io::Result<bool> {
+        match std::fs::remove_file(self.path(did)) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Fails on entries holding the document of another DID than theirs
+fn check_id(did: &str, entry: &CachedDocument) -> std::io::Result<()> {
+    if entry.did_document.id != did {
+        let message = format!("document of {} cached for {did}", entry.did_document.id);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message));
+    }
+
+    Ok(())
+}
+
+/// Rules governing when cached documents are served
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    /// Age until which cached documents are served without resolution
+    pub max_age: Duration,
+
+    /// Age until which cached documents are served if resolution fails
+    /// for transient reasons, e.g. an unreachable did:web host.
+    /// Stale documents are served regardless of their age if unset.
+    pub max_stale: Option<Duration>,
+
+    /// Serves cached documents only, never resolving anew
+    pub offline: bool,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::from_secs(3600),
+            max_stale: None,
+            offline: false,
+        }
+    }
+}
+
+/// Resolver caching the documents resolved by another one.
+///
+/// Cached documents are marked as such in the resolution metadata,
+/// along with whether they were served past their maximum age.
+pub struct CachingResolver<R, S> {
+    resolver: R,
+    store: S,
+    policy: CachePolicy,
+}
+
+impl<R, S> CachingResolver<R, S>
+where
+    R: DIDResolver + Send + Sync,
+    S: DIDDocumentStore,
+{
+    pub fn new(resolver: R, store: S, policy: CachePolicy) -> Self {
+        Self { resolver, store, policy }
+    }
+
+    /// Pre-seeds the cache with a DID document obtained out of band
+    pub fn seed(&self, diddoc: DIDDocument) -> std::io::Result<()> {
+        seed(&self.store, diddoc)
+    }
+
+    /// Evicts a DID document from the cache
+    pub fn evict(&self, did: &str) -> std::io::Result<bool> {
+        self.store.evict(did)
+    }
+
+    fn age(entry: &CachedDocument) -> Duration {
+        (Utc::now() - entry.cached_at).to_std().unwrap_or_default()
+    }
+}
+
+/// Inserts a DID document into a store, e.g. to pre-seed a resolver's cache
+pub fn seed(store: &dyn DIDDocumentStore, diddoc: DIDDocument) -> std::io::Result<()> {
+    let did = diddoc.id.clone();
+    let entry = CachedDocument {
+        did_document: diddoc,
+        did_document_metadata: None,
+        cached_at: Utc::now(),
+    };
+
+    store.put(&did, entry)
+}
+
+#[async_trait]
+impl<R, S> DIDResolver for CachingResolver<R, S>
+where
+    R: DIDResolver + Send + Sync,
+    S: DIDDocumentStore,
+{
+    async fn resolve(&self, did: &str, options: &DIDResolutionOptions) -> ResolutionOutput {
+        let cached = match options.no_cache {
+            Some(true) => None,
+            _ => self.store.get(did),
+        };
+
+        if let Some(entry) = &cached {
+            if self.policy.offline || Self::age(entry) <= self.policy.max_age {
+                return from_cache(entry.clone(), false);
+            }
+        }
+
+        if self.policy.offline {
+            return not_found();
+        }
+
+        let output = self.resolver.resolve(did, options).await;
+        let error = output.did_resolution_metadata.as_ref().and_then(|metadata| metadata.error.clone());
+
+        match (error, &output.did_document) {
+            // Documents of other DIDs are never cached, nor served later
+            (None, Some(diddoc)) if diddoc.id != did => output,
+            (None, Some(diddoc)) => {
+                let entry = CachedDocument {
+                    did_document: diddoc.clone(),
+                    did_document_metadata: output.did_document_metadata.clone(),
+                    cached_at: Utc::now(),
+                };
+
+                // Caching is best effort and must not fail resolution
+                let _ = self.store.put(did, entry);
+
+                output
+            }
+            // Fall back on stale documents only if resolution failed for
+            // reasons that do not speak to the validity of the DID itself
+            (Some(DIDResolutionError::InternalError | DIDResolutionError::NonSuccessResponse), _) => match cached {
+                Some(entry) if self.policy.max_stale.map_or(true, |max_stale| Self::age(&entry) <= max_stale) => from_cache(entry, true),
+                _ => output,
+            },
+            _ => output,
+        }
+    }
+}
+
+fn from_cache(entry: CachedDocument, stale: bool) -> ResolutionOutput {
+    ResolutionOutput {
+        context: Context::SingleString(String::from("https://w3id.org/did-resolution/v1")),
+        did_document: Some(entry.did_document),
+        did_resolution_metadata: Some(DIDResolutionMetadata {
+            error: None,
+            content_type: Some(MediaType::DidLdJson.to_string()),
+            additional_properties: Some(HashMap::from([
+                (String::from("cached"), json!(true)),
+                (String::from("stale"), json!(stale)),
+            ])),
+        }),
+        did_document_metadata: entry.did_document_metadata,
+        additional_properties: None,
+    }
+}
+
+fn not_found() -> ResolutionOutput {
+    ResolutionOutput {
+        context: Context::SingleString(String::from("https://w3id.org/did-resolution/v1")),
+        did_document: None,
+        did_resolution_metadata: Some(DIDResolutionMetadata {
+            error: Some(DIDResolutionError::NotFound),
+            content_type: None,
+            additional_properties: None,
+        }),
+        did_document_metadata: None,
+        additional_properties: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    const DID: &str = "did:web:alice.example.com";
+
+    struct MockResolver {
+        error: Mutex<Option<DIDResolutionError>>,
+        served_did: Mutex<Option<String>>,
+        calls: AtomicUsize,
+    }
+
+    impl MockResolver {
+        fn new() -> Self {
+            Self {
+                error: Mutex::new(None),
+                served_did: Mutex::new(None),
+                calls: AtomicUsize::new(0),
+            }
+        }
+
+        fn serve_document_of(&self, did: &str) {
+            *self.served_did.lock().unwrap() = Some(did.to_string());
+        }
+
+        fn fail_with(&self, error: Option<DIDResolutionError>) {
+            *self.error.lock().unwrap() = error;
+        }
+    }
+
+    #[async_trait]
+    impl DIDResolver for MockResolver {
+        async fn resolve(&self, did: &str, _options: &DIDResolutionOptions) -> ResolutionOutput {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+
+            let error = self.error.lock().unwrap().clone();
+            let served_did = self.served_did.lock().unwrap().clone();
+            let served_did = served_did.as_deref().unwrap_or(did);
+            ResolutionOutput {
+                context: Context::SingleString(String::from("https://w3id.org/did-resolution/v1")),
+                did_document: error.is_none().then(|| sample_diddoc(served_did)),
+                did_resolution_metadata: Some(DIDResolutionMetadata {
+                    error,
+                    content_type: None,
+                    additional_properties: None,
+                }),
+                did_document_metadata: None,
+                additional_properties: None,
+            }
+        }
+    }
+
+    fn sample_diddoc(did: &str) -> DIDDocument {
+        serde_json::from_value(json!({
+            "@context": "https://www.w3.org/ns/did/v1",
+            "id": did,
+        }))
+        .unwrap()
+    }
+
+    fn is_stale(output: &ResolutionOutput) -> Option<bool> {
+        let metadata = output.did_resolution_metadata.as_ref()?;
+        metadata.additional_properties.as_ref()?.get("stale")?.as_bool()
+    }
+
+    fn expire(store: &InMemoryDocumentStore, age: Duration) {
+        let mut entry = store.get(DID).unwrap();
+        entry.cached_at = Utc::now() - chrono::Duration::from_std(age).unwrap();
+        store.put(DID, entry).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_serving_fresh_documents_from_cache() {
+        let resolver = CachingResolver::new(MockResolver::new(), InMemoryDocumentStore::default(), CachePolicy::default());
+
+        let output = resolver.resolve(DID, &DIDResolutionOptions::default()).await;
+        assert_eq!(output.did_document.as_ref().unwrap().id, DID);
+        assert_eq!(is_stale(&output), None);
+
+        let output = resolver.resolve(DID, &DIDResolutionOptions::default()).await;
+        assert_eq!(output.did_document.as_ref().unwrap().id, DID);
+        assert_eq!(is_stale(&output), Some(false));
+        assert_eq!(resolver.resolver.calls.load(Ordering::SeqCst), 1);
+
+        // Caching can be bypassed
+        let options = DIDResolutionOptions {
+            no_cache: Some(true),
+            ..Default::default()
+        };
+        resolver.resolve(DID, &options).await;
+        assert_eq!(resolver.resolver.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[async_std::test]
+    async fn test_not_caching_documents_of_other_dids() {
+        let resolver = CachingResolver::new(MockResolver::new(), InMemoryDocumentStore::default(), CachePolicy::default());
+        resolver.resolver.serve_document_of("did:web:mallory.example.com");
+
+        resolver.resolve(DID, &DIDResolutionOptions::default()).await;
+        assert!(resolver.store.get(DID).is_none());
+
+        resolver.resolve(DID, &DIDResolutionOptions::default()).await;
+        assert_eq!(resolver.resolver.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[async_std::test]
+    async fn test_serving_stale_documents_on_outages() {
+        let policy = CachePolicy {
+            max_age: Duration::from_secs(60),
+            max_stale: Some(Duration::from_secs(3600)),
+            offline: false,
+        };
+        let resolver = CachingResolver::new(MockResolver::new(), InMemoryDocumentStore::default(), policy);
+
+        resolver.resolve(DID, &DIDResolutionOptions::default()).await;
+        expire(&resolver.store, Duration::from_secs(120));
+
+        resolver.resolver.fail_with(Some(DIDResolutionError::InternalError));
+        let output = resolver.resolve(DID, &DIDResolutionOptions::default()).await;
+        assert_eq!(output.did_document.as_ref().unwrap().id, DID);
+        assert_eq!(is_stale(&output), Some(true));
+
+        // Failures speaking to the DID itself are authoritative
+        resolver.resolver.fail_with(Some(DIDResolutionError::NotFound));
+        let output = resolver.resolve(DID, &DIDResolutionOptions::default()).await;
+        assert_eq!(output.into_result().unwrap_err(), DIDResolutionError::NotFound);
+
+        // Documents past their maximum staleness are not served
+        expire(&resolver.store, Duration::from_secs(7200));
+        resolver.resolver.fail_with(Some(DIDResolutionError::InternalError));
+        let output = resolver.resolve(DID, &DIDResolutionOptions::default()).await;
+        assert_eq!(output.into_result().unwrap_err(), DIDResolutionError::InternalError);
+    }
+
+    #[async_std::test]
+    async fn test_offline_mode() {
+        let policy = CachePolicy {
+            offline: true,
+            ..Default::default()
+        };
+        let resolver = CachingResolver::new(MockResolver::new(), InMemoryDocumentStore::default(), policy);
+
+        let output = resolver.resolve(DID, &DIDResolutionOptions::default()).await;
+        assert_eq!(output.into_result().unwrap_err(), DIDResolutionError::NotFound);
+
+        resolver.seed(sample_diddoc(DID)).unwrap();
+        expire(&resolver.store, Duration::from_secs(365 * 86400));
+
+        let output = resolver.resolve(DID, &DIDResolutionOptions::default()).await;
+        assert_eq!(output.did_document.as_ref().unwrap().id, DID);
+        assert_eq!(resolver.resolver.calls.load(Ordering::SeqCst), 0);
+
+        assert!(resolver.evict(DID).unwrap());
+        assert!(!resolver.evict(DID).unwrap());
+    }
+
+    #[test]
+    fn test_filesystem_document_store() {
+        let dirpath = std::env::temp_dir().join(format!("didcache-{}", Utc::now().timestamp_nanos_opt().unwrap()));
+        let store = FileSystemDocumentStore::new(&dirpath);
+
+        assert!(store.get(DID).is_none());
+
+        seed(&store, sample_diddoc(DID)).unwrap();
+        assert_eq!(store.get(DID).unwrap().did_document.id, DID);
+        assert!(store.get("did:web:bob.example.com").is_none());
+
+        // Documents of other DIDs are refused
+        let entry = store.get(DID).unwrap();
+        let err = store.put("did:web:bob.example.com", entry).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(store.get("did:web:bob.example.com").is_none());
+
+        assert!(store.evict(DID).unwrap());
+        assert!(store.get(DID).is_none());
+        assert!(!store.evict(DID).unwrap());
+
+        std::fs::remove_dir_all(dirpath).unwrap();
+    }
+}
@@ -22,10 +22,16 @@ pub enum Algorithm {
 
 use Algorithm::*;
 
+/// Prefix of base58btc-encoded multibase values
+const MULTIBASE_BASE58BTC_PREFIX: char = 'z';
+
 // See:
 // - https://w3c-ccg.github.io/did-method-key/#signature-method-creation-algorithm
 // - https://w3c-ccg.github.io/did-method-key/#encryption-method-creation-algorithm
 impl Algorithm {
+    /// All algorithms with a registered multicodec prefix
+    pub const ALL: [Algorithm; 8] = [Ed25519, X25519, Secp256k1, BLS12381, P256, P384, P521, RSA];
+
     pub fn muticodec_prefix(&self) -> [u8; 2] {
         match self {
             Ed25519 => [0xed, 0x01],
@@ -58,14 +64,55 @@ impl Algorithm {
             Ed25519 => Some(32),
             X25519 => Some(32),
             Secp256k1 => Some(33),
-            BLS12381 => None,
+            BLS12381 => Some(96),
             P256 => Some(33),
             P384 => Some(49),
-            P521 => None,
+            P521 => Some(67),
             RSA => None,
         }
     }
 
+    /// Encodes raw public key bytes into a multikey, i.e. the base58btc
+    /// multibase encoding of the bytes prefixed with the multicodec value.
+    ///
+    /// See https://www.w3.org/TR/controller-document/#multikey
+    pub fn encode_multikey(&self, raw_public_key_bytes: &[u8]) -> Result<String, CryptoError> {
+        if let Some(required_length) = self.public_key_length() {
+            if required_length != raw_public_key_bytes.len() {
+                return Err(CryptoError::InvalidKeyLength);
+            }
+        }
+
+        Ok(multibase::encode(
+            multibase::Base::Base58Btc,
+            [&self.muticodec_prefix(), raw_public_key_bytes].concat(),
+        ))
+    }
+
+    /// Decodes a multikey into its algorithm and raw public key bytes
+    pub fn decode_multikey(multikey: &str) -> Result<(Self, Vec<u8>), CryptoError> {
+        if !multikey.starts_with(MULTIBASE_BASE58BTC_PREFIX) {
+            return Err(CryptoError::InvalidPublicKey);
+        }
+
+        let (_, multicodec) = multibase::decode(multikey).map_err(|_| CryptoError::InvalidPublicKey)?;
+        if multicodec.len() < 2 {
+            return Err(CryptoError::InvalidPublicKey);
+        }
+
+        let prefix: &[u8; 2] = &multicodec[..2].try_into().unwrap();
+        let alg = Self::from_muticodec_prefix(prefix).ok_or(CryptoError::Unsupported)?;
+
+        let raw_public_key_bytes = multicodec[2..].to_vec();
+        if let Some(required_length) = alg.public_key_length() {
+            if required_length != raw_public_key_bytes.len() {
+                return Err(CryptoError::InvalidKeyLength);
+            }
+        }
+
+        Ok((alg, raw_public_key_bytes))
+    }
+
     pub fn build_jwk(&self, raw_public_key_bytes: &[u8]) -> Result<Jwk, CryptoError> {
         match self {
             Ed25519 => Ok(Jwk {
@@ -129,9 +176,19 @@ impl Algorithm {
             };
 
             let x = BigInt::from_bytes_be(Sign::Plus, &compressed_key_bytes[1..]);
+            if x >= p {
+                return Err(CryptoError::InvalidPublicKey);
+            }
+
             let y_sq = (x.modpow(&BigInt::from(3u32), &p) + &a * &x + &b) % &p;
             let mut y = y_sq.modpow(&((&p + BigInt::from(1)) / BigInt::from(4)), &p);
 
+            // No point of the curve has this x coordinate unless the square
+            // root is one indeed
+            if y.modpow(&BigInt::from(2u32), &p) != y_sq {
+                return Err(CryptoError::InvalidPublicKey);
+            }
+
             if &y % BigInt::from(2) != (sign % 2).into() {
                 y = &p - &y;
             }
@@ -275,12 +332,92 @@ mod tests {
         assert!(matches!(uncompressed.unwrap_err(), CryptoError::InvalidPublicKey));
     }
 
+    #[test]
+    fn test_multicodec_prefixes_round_trip() {
+        for alg in Algorithm::ALL {
+            assert_eq!(Algorithm::from_muticodec_prefix(&alg.muticodec_prefix()), Some(alg));
+        }
+
+        assert_eq!(Algorithm::from_muticodec_prefix(&[0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn test_multikeys_round_trip() {
+        // See https://w3c-ccg.github.io/did-method-key/#test-vectors
+        let multikeys = [
+            (Ed25519, "z6MkiTBz1ymuepAQ4HEHYSF1H8quG5GLVVQR3djdX3mDooWp"),
+            (X25519, "z6LSeu9HkTHSfLLeUs2nnzUSNedgDUevfNQgQjQC23ZCit6F"),
+            (Secp256k1, "zQ3shokFTS3brHcDQrn82RUDfCZESWL1ZdCEJwekUDPQiYBme"),
+            (
+                BLS12381,
+                concat!(
+                    "zUC7K4ndUaGZgV7Cp2yJy6JtMoUHY6u7tkcSYUvPrEidqBmLCTLmi6d5WvwnUqejscAk",
+                    "ERJ3bfjEiSYtdPkRSE8kSa11hFBr4sTgnbZ95SJj19PN2jdvJjyzpSZgxkyyxNnBNnY"
+                ),
+            ),
+            (P256, "zDnaerDaTF5BXEavCrfRZEk316dpbLsfPDZ3WJ5hRTPFU2169"),
+            (P384, "z82Lm1MpAkeJcix9K8TMiLd5NMAhnwkjjCBeWHXyu3U4oT2MVJJKXkcVBgjGhnLBn2Kaau9"),
+            (
+                P521,
+                "z2J9gaYxrKVpdoG9A4gRnmpnRCcxU6agDtFVVBVdn1JedouoZN7SzcyREXXzWgt3gGiwpoHq7K68X4m32D8HgzG8wv3sY5j7",
+            ),
+        ];
+
+        for (expected_alg, multikey) in multikeys {
+            let (alg, bytes) = Algorithm::decode_multikey(multikey).unwrap();
+            assert_eq!(alg, expected_alg);
+            assert_eq!(Some(bytes.len()), alg.public_key_length());
+            assert_eq!(alg.encode_multikey(&bytes).unwrap(), multikey);
+        }
+    }
+
+    #[test]
+    fn test_multikey_codec_fails_as_expected() {
+        assert!(matches!(Ed25519.encode_multikey(&[0u8; 31]).unwrap_err(), CryptoError::InvalidKeyLength));
+        assert!(matches!(BLS12381.encode_multikey(&[0u8; 48]).unwrap_err(), CryptoError::InvalidKeyLength));
+
+        let encode = |bytes: &[u8]| multibase::encode(multibase::Base::Base58Btc, bytes);
+
+        // Not base58btc-encoded
+        let multikey = multibase::encode(multibase::Base::Base16Lower, [&[0xed, 0x01], &[0u8; 32][..]].concat());
+        assert!(matches!(
+            Algorithm::decode_multikey(&multikey).unwrap_err(),
+            CryptoError::InvalidPublicKey
+        ));
+        assert!(matches!(Algorithm::decode_multikey("z0OIl").unwrap_err(), CryptoError::InvalidPublicKey));
+
+        // Unknown multicodec prefix
+        let multikey = encode(&[&[0x00, 0x00], &[0u8; 32][..]].concat());
+        assert!(matches!(Algorithm::decode_multikey(&multikey).unwrap_err(), CryptoError::Unsupported));
+
+        // Truncated key
+        let multikey = encode(&[&[0xed, 0x01], &[0u8; 31][..]].concat());
+        assert!(matches!(
+            Algorithm::decode_multikey(&multikey).unwrap_err(),
+            CryptoError::InvalidKeyLength
+        ));
+        assert!(matches!(
+            Algorithm::decode_multikey(&encode(&[0xed])).unwrap_err(),
+            CryptoError::InvalidPublicKey
+        ));
+    }
+
     fn decode_multibase_key(key: &str) -> (Algorithm, Vec<u8>) {
-        let (_, multicodec) = multibase::decode(key).unwrap();
+        Algorithm::decode_multikey(key).unwrap()
+    }
 
-        let prefix: &[u8; 2] = &multicodec[..2].try_into().unwrap();
-        let bytes = &multicodec[2..];
+    #[test]
+    fn test_rejects_x_coordinates_off_the_curve() {
+        for (alg, x) in [(Secp256k1, 5), (P256, 1)] {
+            let mut compressed = [0; 33];
+            compressed[0] = 0x02;
+            compressed[32] = x;
+            assert!(alg.uncompress_public_key(&compressed).is_err());
+        }
 
-        (Algorithm::from_muticodec_prefix(prefix).unwrap(), bytes.to_vec())
+        // Coordinates beyond the field are not canonical
+        let mut compressed = [0xff; 33];
+        compressed[0] = 0x02;
+        assert!(P256.uncompress_public_key(&compressed).is_err());
     }
 }
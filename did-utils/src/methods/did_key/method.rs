@@ -53,13 +53,7 @@ impl DIDKeyMethod {
 
     /// Computes did:key address corresponding to raw public key bytes
     pub fn from_raw_public_key(alg: Algorithm, bytes: &[u8]) -> Result<String, CryptoError> {
-        if let Some(required_length) = alg.public_key_length() {
-            if required_length != bytes.len() {
-                return Err(CryptoError::InvalidKeyLength);
-            }
-        }
-
-        let multibase_value = multibase::encode(Base58Btc, [&alg.muticodec_prefix(), bytes].concat());
+        let multibase_value = alg.encode_multikey(bytes)?;
 
         Ok(format!("did:key:{}", multibase_value))
     }
@@ -4,7 +4,7 @@ use crate::{
     ldmodel::Context,
     methods::{
         errors::DIDResolutionError,
-        traits::{DIDResolutionMetadata, DIDResolutionOptions, DIDResolver, MediaType, ResolutionOutput},
+        traits::{DIDDocumentMetadata, DIDResolutionMetadata, DIDResolutionOptions, DIDResolver, MediaType, ResolutionOutput},
     },
 };
 
@@ -25,7 +25,12 @@ impl DIDResolver for DIDKeyMethod {
                     content_type: Some(MediaType::DidLdJson.to_string()),
                     additional_properties: None,
                 }),
-                did_document_metadata: None,
+                // did:key documents derive from the key, which can be
+                // neither updated nor deactivated
+                did_document_metadata: Some(DIDDocumentMetadata {
+                    deactivated: Some(false),
+                    ..Default::default()
+                }),
                 additional_properties: None,
             },
             Err(err) => ResolutionOutput {
@@ -94,7 +99,9 @@ mod tests {
                 "didResolutionMetadata": {
                     "contentType": "application/did+ld+json"
                 },
-                "didDocumentMetadata": null
+                "didDocumentMetadata": {
+                    "deactivated": false
+                }
             }"#,
         )
         .unwrap();
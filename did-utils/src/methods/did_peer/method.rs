@@ -0,0 +1,272 @@
+use multibase::Base::Base58Btc;
+use serde_json::{Map, Value};
+
+use crate::{
+    crypto::sha256_hash::sha256_multihash,
+    didcore::Document as DIDDocument,
+    methods::{
+        errors::{DIDPeerMethodError, DIDResolutionError},
+        traits::DIDMethod,
+    },
+};
+
+/// Prefix of did:peer addresses following numalgo 4
+const NUMALGO_4_PREFIX: &str = "did:peer:4";
+
+/// Multicodec prefix for JSON, varint-encoded
+const MULTICODEC_JSON_PREFIX: [u8; 2] = [0x80, 0x04];
+
+/// Properties of a DID document that may embed verification methods
+const VERIFICATION_METHOD_PROPERTIES: [&str; 6] = [
+    "verificationMethod",
+    "authentication",
+    "assertionMethod",
+    "keyAgreement",
+    "capabilityInvocation",
+    "capabilityDelegation",
+];
+
+#[derive(Default)]
+pub struct DIDPeerMethod;
+
+impl DIDMethod for DIDPeerMethod {
+    fn name() -> String {
+        "did:peer".to_string()
+    }
+}
+
+impl DIDPeerMethod {
+    /// Computes did:peer:4 address in long form, encoding an input document.
+    ///
+    /// The input document must not carry an `id`, as it gets assigned one on
+    /// resolution. Identifiers within it should be relative, e.g. `#key-1`.
+    ///
+    /// See https://identity.foundation/peer-did-method-spec/#creating-a-did-1
+    pub fn create_did_peer_4(diddoc: &Value) -> Result<String, DIDPeerMethodError> {
+        let Some(object) = diddoc.as_object() else {
+            return Err(DIDPeerMethodError::InvalidInputDocument(String::from("not a JSON object")));
+        };
+
+        if object.contains_key("id") {
+            return Err(DIDPeerMethodError::InvalidInputDocument(String::from("must not carry an id")));
+        }
+
+        let encoded_document = multibase::encode(Base58Btc, [&MULTICODEC_JSON_PREFIX[..], &serde_json::to_vec(diddoc)?].concat());
+        let hash = Self::hash(&encoded_document);
+
+        Ok(format!("{NUMALGO_4_PREFIX}{hash}:{encoded_document}"))
+    }
+
+    /// Derives the short form of a did:peer:4 address from its long form
+    pub fn shorten(did: &str) -> Result<String, DIDResolutionError> {
+        let (hash, encoded_document) = Self::split_long_form(did)?;
+        Self::verify_hash(hash, encoded_document)?;
+
+        Ok(format!("{NUMALGO_4_PREFIX}{hash}"))
+    }
+
+    /// Expands did:peer:4 address in long form into DID document.
+    ///
+    /// Short form addresses cannot be expanded without prior knowledge of
+    /// their long form, hence their resolution fails with `notFound`.
+    ///
+    /// See https://identity.foundation/peer-did-method-spec/#resolving-a-did-1
+    pub fn expand(&self, did: &str) -> Result<DIDDocument, DIDResolutionError> {
+        if !did.starts_with("did:peer:") {
+            return Err(DIDResolutionError::InvalidDid);
+        }
+
+        if !did.starts_with(NUMALGO_4_PREFIX) {
+            return Err(DIDResolutionError::MethodNotSupported);
+        }
+
+        let Ok((hash, encoded_document)) = Self::split_long_form(did) else {
+            Self::validate_hash(&did[NUMALGO_4_PREFIX.len()..])?;
+            return Err(DIDResolutionError::NotFound);
+        };
+
+        Self::verify_hash(hash, encoded_document)?;
+
+        let diddoc = Self::decode_document(encoded_document)?;
+        let diddoc = Self::contextualize(diddoc, did, &format!("{NUMALGO_4_PREFIX}{hash}"));
+
+        serde_json::from_value(Value::Object(diddoc)).map_err(|_| DIDResolutionError::InvalidDid)
+    }
+
+    fn split_long_form(did: &str) -> Result<(&str, &str), DIDResolutionError> {
+        did.strip_prefix(NUMALGO_4_PREFIX)
+            .and_then(|suffix| suffix.split_once(':'))
+            .ok_or(DIDResolutionError::InvalidDid)
+    }
+
+    fn hash(encoded_document: &str) -> String {
+        multibase::encode(Base58Btc, sha256_multihash(encoded_document.as_bytes()))
+    }
+
+    fn validate_hash(hash: &str) -> Result<(), DIDResolutionError> {
+        match multibase::decode(hash) {
+            Ok((Base58Btc, multihash)) if multihash.len() == 34 => Ok(()),
+            _ => Err(DIDResolutionError::InvalidDid),
+        }
+    }
+
+    fn verify_hash(hash: &str, encoded_document: &str) -> Result<(), DIDResolutionError> {
+        if Self::hash(encoded_document) != hash {
+            return Err(DIDResolutionError::InvalidDid);
+        }
+
+        Ok(())
+    }
+
+    fn decode_document(encoded_document: &str) -> Result<Map<String, Value>, DIDResolutionError> {
+        let (base, multicodec) = multibase::decode(encoded_document).map_err(|_| DIDResolutionError::InvalidDid)?;
+
+        let json = match multicodec.strip_prefix(&MULTICODEC_JSON_PREFIX[..]) {
+            Some(json) if base == Base58Btc => json,
+            _ => return Err(DIDResolutionError::InvalidDid),
+        };
+
+        match serde_json::from_slice(json) {
+            Ok(Value::Object(diddoc)) if !diddoc.contains_key("id") => Ok(diddoc),
+            _ => Err(DIDResolutionError::InvalidDid),
+        }
+    }
+
+    // See https://identity.foundation/peer-did-method-spec/#contextualizing-the-input-document
+    fn contextualize(mut diddoc: Map<String, Value>, did: &str, short_form: &str) -> Map<String, Value> {
+        diddoc.insert(String::from("id"), Value::from(did));
+
+        let also_known_as = diddoc.entry("alsoKnownAs").or_insert_with(|| Value::Array(vec![]));
+        if let Value::Array(aliases) = also_known_as {
+            aliases.push(Value::from(short_form));
+        }
+
+        for property in VERIFICATION_METHOD_PROPERTIES {
+            let Some(Value::Array(entries)) = diddoc.get_mut(property) else {
+                continue;
+            };
+
+            // Only embedded verification methods are objects, references are strings
+            for entry in entries.iter_mut().filter_map(Value::as_object_mut) {
+                entry.entry("controller").or_insert_with(|| Value::from(did));
+            }
+        }
+
+        diddoc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn input_document() -> Value {
+        json!({
+            "@context": [
+                "https://www.w3.org/ns/did/v1",
+                "https://w3id.org/security/multikey/v1"
+            ],
+            "verificationMethod": [
+                {
+                    "id": "#6LSqPZfn",
+                    "type": "Multikey",
+                    "publicKeyMultibase": "z6LSqPZfn9krvgXma2icTMKf2uVcYhKXsudCmPoUzqGYW24U"
+                },
+                {
+                    "id": "#6MkrCD1c",
+                    "type": "Multikey",
+                    "publicKeyMultibase": "z6MkrCD1csqtgdj8sjrsu8jxcbeyP6m7LiK87NzhfWqio5yr"
+                }
+            ],
+            "authentication": ["#6MkrCD1c"],
+            "keyAgreement": ["#6LSqPZfn"],
+            "service": [
+                {
+                    "id": "#didcommmessaging-0",
+                    "type": "DIDCommMessaging",
+                    "serviceEndpoint": "https://mediator.example.com"
+                }
+            ]
+        })
+    }
+
+    #[test]
+    fn test_did_peer_4_long_and_short_forms() {
+        let did = DIDPeerMethod::create_did_peer_4(&input_document()).unwrap();
+        assert!(did.starts_with("did:peer:4z"));
+
+        let short_form = DIDPeerMethod::shorten(&did).unwrap();
+        assert!(did.starts_with(&format!("{short_form}:z")));
+
+        // Hash is the base58btc-encoded sha2-256 multihash of the encoded document
+        let (_, encoded_document) = did[NUMALGO_4_PREFIX.len()..].split_once(':').unwrap();
+        let (base, multihash) = multibase::decode(&short_form[NUMALGO_4_PREFIX.len()..]).unwrap();
+        assert_eq!(base, Base58Btc);
+        assert_eq!(multihash, sha256_multihash(encoded_document.as_bytes()));
+    }
+
+    #[test]
+    fn test_did_peer_4_expansion() {
+        let did = DIDPeerMethod::create_did_peer_4(&input_document()).unwrap();
+        let short_form = DIDPeerMethod::shorten(&did).unwrap();
+
+        let diddoc = DIDPeerMethod.expand(&did).unwrap();
+        assert_eq!(diddoc.id, did);
+        assert_eq!(diddoc.also_known_as, Some(vec![short_form]));
+
+        let verification_methods = diddoc.verification_method.unwrap();
+        assert_eq!(verification_methods.len(), 2);
+        assert!(verification_methods.iter().all(|vm| vm.controller == did));
+        assert_eq!(verification_methods[0].id, "#6LSqPZfn");
+
+        assert_eq!(diddoc.service.unwrap()[0].id, "#didcommmessaging-0");
+    }
+
+    #[test]
+    fn test_did_peer_4_creation_fails_on_invalid_input_document() {
+        let mut diddoc = input_document();
+        diddoc["id"] = json!("did:example:123");
+
+        assert!(matches!(
+            DIDPeerMethod::create_did_peer_4(&diddoc),
+            Err(DIDPeerMethodError::InvalidInputDocument(_))
+        ));
+        assert!(matches!(
+            DIDPeerMethod::create_did_peer_4(&json!(["not", "an", "object"])),
+            Err(DIDPeerMethodError::InvalidInputDocument(_))
+        ));
+    }
+
+    #[test]
+    fn test_did_peer_4_expansion_fails_on_hash_mismatch() {
+        let did = DIDPeerMethod::create_did_peer_4(&input_document()).unwrap();
+
+        let mut tampered = input_document();
+        tampered["authentication"] = json!(["#6LSqPZfn"]);
+        let tampered = DIDPeerMethod::create_did_peer_4(&tampered).unwrap();
+
+        // Graft the tampered document onto the original hash
+        let (hash, _) = did[NUMALGO_4_PREFIX.len()..].split_once(':').unwrap();
+        let (_, encoded_document) = tampered[NUMALGO_4_PREFIX.len()..].split_once(':').unwrap();
+        let forged = format!("{NUMALGO_4_PREFIX}{hash}:{encoded_document}");
+
+        assert_eq!(DIDPeerMethod.expand(&forged).unwrap_err(), DIDResolutionError::InvalidDid);
+        assert_eq!(DIDPeerMethod::shorten(&forged).unwrap_err(), DIDResolutionError::InvalidDid);
+    }
+
+    #[test]
+    fn test_did_peer_4_expansion_of_short_form() {
+        let did = DIDPeerMethod::create_did_peer_4(&input_document()).unwrap();
+        let short_form = DIDPeerMethod::shorten(&did).unwrap();
+
+        assert_eq!(DIDPeerMethod.expand(&short_form).unwrap_err(), DIDResolutionError::NotFound);
+        assert_eq!(DIDPeerMethod.expand("did:peer:4zInvalid").unwrap_err(), DIDResolutionError::InvalidDid);
+    }
+
+    #[test]
+    fn test_did_peer_expansion_of_unsupported_numalgo() {
+        let did = "did:peer:0z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+        assert_eq!(DIDPeerMethod.expand(did).unwrap_err(), DIDResolutionError::MethodNotSupported);
+    }
+}
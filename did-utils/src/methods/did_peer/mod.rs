@@ -0,0 +1,11 @@
+//! The did:peer method is designed to be used independent of any central source
+//! of truth, and is intended to be cheap, fast, scalable, and secure. Of its
+//! numeric algorithms, only numalgo 4 is supported here, whose long form embeds
+//! a whole input document and whose short form is a hash of the latter.
+//!
+//! See https://identity.foundation/peer-did-method-spec
+
+pub mod method;
+pub mod resolver;
+
+pub use method::DIDPeerMethod;
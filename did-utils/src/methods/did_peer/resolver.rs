@@ -0,0 +1,152 @@
+use async_trait::async_trait;
+
+use crate::{
+    ldmodel::Context,
+    methods::{
+        errors::DIDResolutionError,
+        traits::{DIDDocumentMetadata, DIDResolutionMetadata, DIDResolutionOptions, DIDResolver, MediaType, ResolutionOutput},
+    },
+};
+
+use super::DIDPeerMethod;
+
+#[async_trait]
+impl DIDResolver for DIDPeerMethod {
+    /// Resolves a DID address into its corresponding DID document.
+    async fn resolve(&self, did: &str, _options: &DIDResolutionOptions) -> ResolutionOutput {
+        let context = Context::SingleString(String::from("https://w3id.org/did-resolution/v1"));
+
+        match self.expand(did) {
+            Ok(diddoc) => ResolutionOutput {
+                context,
+                did_document: Some(diddoc),
+                did_resolution_metadata: Some(DIDResolutionMetadata {
+                    error: None,
+                    content_type: Some(MediaType::DidLdJson.to_string()),
+                    additional_properties: None,
+                }),
+                // Long form addresses embed their document, which can be
+                // neither updated nor deactivated, and are equivalent to
+                // their short form
+                did_document_metadata: Some(DIDDocumentMetadata {
+                    deactivated: Some(false),
+                    equivalent_id: DIDPeerMethod::shorten(did).into_iter().collect(),
+                    ..Default::default()
+                }),
+                additional_properties: None,
+            },
+            Err(err) => ResolutionOutput {
+                context,
+                did_document: None,
+                did_resolution_metadata: Some(DIDResolutionMetadata {
+                    error: Some(if !did.starts_with("did:peer:") {
+                        DIDResolutionError::MethodNotSupported
+                    } else {
+                        err
+                    }),
+                    content_type: None,
+                    additional_properties: None,
+                }),
+                did_document_metadata: None,
+                additional_properties: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::methods::traits::DereferencingOptions;
+    use serde_json::{json, Value};
+
+    #[async_std::test]
+    async fn test_did_peer_4_resolution() {
+        let did_method = DIDPeerMethod;
+
+        let did = DIDPeerMethod::create_did_peer_4(&json!({
+            "@context": ["https://www.w3.org/ns/did/v1", "https://w3id.org/security/multikey/v1"],
+            "verificationMethod": [{
+                "id": "#6MkrCD1c",
+                "type": "Multikey",
+                "publicKeyMultibase": "z6MkrCD1csqtgdj8sjrsu8jxcbeyP6m7LiK87NzhfWqio5yr"
+            }],
+            "authentication": ["#6MkrCD1c"]
+        }))
+        .unwrap();
+        let short_form = DIDPeerMethod::shorten(&did).unwrap();
+
+        let expected: Value = json!({
+            "@context": "https://w3id.org/did-resolution/v1",
+            "didDocument": {
+                "@context": ["https://www.w3.org/ns/did/v1", "https://w3id.org/security/multikey/v1"],
+                "id": did,
+                "alsoKnownAs": [short_form],
+                "verificationMethod": [{
+                    "id": "#6MkrCD1c",
+                    "type": "Multikey",
+                    "controller": did,
+                    "publicKeyMultibase": "z6MkrCD1csqtgdj8sjrsu8jxcbeyP6m7LiK87NzhfWqio5yr"
+                }],
+                "authentication": ["#6MkrCD1c"]
+            },
+            "didResolutionMetadata": {
+                "contentType": "application/did+ld+json"
+            },
+            "didDocumentMetadata": {
+                "deactivated": false,
+                "equivalentId": [short_form]
+            }
+        });
+
+        let output = did_method.resolve(&did, &DIDResolutionOptions::default()).await;
+        assert_eq!(
+            json_canon::to_string(&output).unwrap(),   //
+            json_canon::to_string(&expected).unwrap(), //
+        );
+    }
+
+    #[async_std::test]
+    async fn test_did_peer_4_dereferencing() {
+        let did_method = DIDPeerMethod;
+
+        let did = DIDPeerMethod::create_did_peer_4(&json!({
+            "@context": ["https://www.w3.org/ns/did/v1", "https://w3id.org/security/multikey/v1"],
+            "verificationMethod": [{
+                "id": "#6MkrCD1c",
+                "type": "Multikey",
+                "publicKeyMultibase": "z6MkrCD1csqtgdj8sjrsu8jxcbeyP6m7LiK87NzhfWqio5yr"
+            }],
+            "service": [{
+                "id": "#didcomm",
+                "type": "DIDCommMessaging",
+                "serviceEndpoint": "https://mediator.example.com"
+            }]
+        }))
+        .unwrap();
+
+        let output = did_method.dereference(&format!("{did}#6MkrCD1c"), &DereferencingOptions::default()).await;
+        let vm = output.content.unwrap().as_verification_method().unwrap();
+        assert_eq!(vm.controller, did);
+
+        let did_url = format!("{did}?service=didcomm&relativeRef=%2Finbox");
+        let output = did_method.dereference(&did_url, &DereferencingOptions::default()).await;
+        assert_eq!(output.content.unwrap().as_url(), Some("https://mediator.example.com/inbox"));
+    }
+
+    #[async_std::test]
+    async fn test_did_peer_resolution_fails_as_expected() {
+        let did_method = DIDPeerMethod;
+
+        let did = "did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK";
+        let output = did_method.resolve(did, &DIDResolutionOptions::default()).await;
+        assert_eq!(
+            output.did_resolution_metadata.unwrap().error.unwrap(),
+            DIDResolutionError::MethodNotSupported
+        );
+
+        let did = "did:peer:4zQmd8CpeFPci817KDsbSAKWcXAE2mjvCQSasRewvbSF54Bd";
+        let output = did_method.resolve(did, &DIDResolutionOptions::default()).await;
+        assert_eq!(output.did_resolution_metadata.unwrap().error.unwrap(), DIDResolutionError::NotFound);
+    }
+}
@@ -1,38 +1,53 @@
+use std::time::Duration;
+
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use hyper::{
-    client::{ connect::Connect, HttpConnector },
-    http::uri::{ self, Scheme },
-    Body,
-    Client,
-    Uri,
+    body::HttpBody,
+    client::{connect::Connect, HttpConnector},
+    header::{HeaderValue, LAST_MODIFIED, LOCATION},
+    http::uri::{self, Scheme},
+    Body, Client, Uri,
 };
 use hyper_tls::HttpsConnector;
 
 use crate::methods::{
-    errors::DidWebError,
-    traits::{
-        DIDResolutionMetadata,
-        DIDResolutionOptions,
-        DIDResolver,
-        MediaType,
-        ResolutionOutput,
-    },
+    errors::{DIDResolutionError, DidWebError},
+    traits::{DIDDocumentMetadata, DIDResolutionMetadata, DIDResolutionOptions, DIDResolver, MediaType, ResolutionOutput},
 };
 
 use crate::ldmodel::Context;
 
 use crate::didcore::Document as DIDDocument;
 
-pub struct DidWebResolver<C> where C: Connect + Send + Sync + Clone + 'static {
+/// Default time allowed for fetching a DID document, redirects included
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of redirects followed when fetching a DID document
+const MAX_REDIRECTS: usize = 5;
+
+/// Maximum size of a DID document, in bytes
+const MAX_DOCUMENT_SIZE: usize = 1024 * 1024;
+
+pub struct DidWebResolver<C>
+where
+    C: Connect + Send + Sync + Clone + 'static,
+{
     client: Client<C>,
     scheme: Scheme,
+    timeout: Duration,
 }
 
 impl DidWebResolver<HttpConnector> {
+    /// Resolver fetching DID documents over plain HTTP.
+    ///
+    /// This is insecure as the did:web specification mandates HTTPS,
+    /// hence only intended for tests against local servers.
     pub fn http() -> DidWebResolver<HttpConnector> {
         DidWebResolver {
             client: Client::builder().build::<_, Body>(HttpConnector::new()),
             scheme: Scheme::HTTP,
+            timeout: DEFAULT_TIMEOUT,
         }
     }
 }
@@ -42,40 +57,126 @@ impl DidWebResolver<HttpsConnector<HttpConnector>> {
         DidWebResolver {
             client: Client::builder().build::<_, Body>(HttpsConnector::new()),
             scheme: Scheme::HTTPS,
+            timeout: DEFAULT_TIMEOUT,
         }
     }
 }
 
-impl<C> DidWebResolver<C> where C: Connect + Send + Sync + Clone + 'static {
-    async fn fetch_did_document(&self, url: Uri) -> Result<String, DidWebError> {
-        let res = self.client.get(url).await?;
+impl<C> DidWebResolver<C>
+where
+    C: Connect + Send + Sync + Clone + 'static,
+{
+    /// Resolver fetching DID documents over HTTPS with a provided client,
+    /// e.g. one configured for proxies through a [`crate::http::HttpClientConfig`].
+    pub fn with_client(client: Client<C>) -> Self {
+        DidWebResolver {
+            client,
+            scheme: Scheme::HTTPS,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets the time allowed for fetching a DID document
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+}
 
-        if !res.status().is_success() {
-            return Err(DidWebError::NonSuccessResponse(res.status()));
+impl<C> DidWebResolver<C>
+where
+    C: Connect + Send + Sync + Clone + 'static,
+{
+    async fn fetch_did_document(&self, url: Uri) -> Result<(String, DIDDocumentMetadata), DidWebError> {
+        match tokio::time::timeout(self.timeout, self.fetch_following_redirects(url)).await {
+            Ok(result) => result,
+            Err(_) => Err(DidWebError::Timeout),
         }
+    }
 
-        let body = hyper::body::to_bytes(res.into_body()).await?;
+    async fn fetch_following_redirects(&self, mut url: Uri) -> Result<(String, DIDDocumentMetadata), DidWebError> {
+        for _ in 0..=MAX_REDIRECTS {
+            let res = self.client.get(url.clone()).await?;
 
-        String::from_utf8(body.to_vec()).map_err(|err| err.into())
+            if res.status().is_redirection() {
+                url = self.redirect_target(&url, res.headers().get(LOCATION))?;
+                continue;
+            }
+
+            if !res.status().is_success() {
+                return Err(DidWebError::NonSuccessResponse(res.status()));
+            }
+
+            // did:web has no native notion of versioning, but HTTP
+            // caching headers tell when the document last changed
+            let metadata = DIDDocumentMetadata {
+                updated: res
+                    .headers()
+                    .get(LAST_MODIFIED)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+                    .map(|value| value.with_timezone(&Utc)),
+                ..Default::default()
+            };
+
+            // Documents are read up to a bound, whatever their announced size
+            let mut body = res.into_body();
+            let mut content = Vec::new();
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk?;
+                if content.len() + chunk.len() > MAX_DOCUMENT_SIZE {
+                    return Err(DidWebError::DocumentTooLarge);
+                }
+                content.extend_from_slice(&chunk);
+            }
+
+            return Ok((String::from_utf8(content)?, metadata));
+        }
+
+        Err(DidWebError::TooManyRedirects)
     }
-}
 
-impl<C> DidWebResolver<C> where C: Connect + Send + Sync + Clone + 'static {
-    async fn resolver_fetcher(&self, did: &str) -> Result<DIDDocument, DidWebError> {
-        let (path, domain_name) = match parse_did_web_url(did) {
-            Ok((path, domain_name)) => (path, domain_name),
-            Err(err) => {
-                return Err(DidWebError::RepresentationNotSupported(err.to_string()));
+    /// Computes the URL to follow on redirection, refusing scheme changes
+    fn redirect_target(&self, url: &Uri, location: Option<&HeaderValue>) -> Result<Uri, DidWebError> {
+        let location = location
+            .and_then(|location| location.to_str().ok())
+            .ok_or(DidWebError::InvalidRedirect("missing location".to_string()))?;
+
+        let target: Uri = location.parse().map_err(|_| DidWebError::InvalidRedirect(location.to_string()))?;
+
+        // Relative references are resolved against the current URL
+        let target = match target.scheme() {
+            Some(_) => target,
+            None if location.starts_with('/') => uri::Builder::new()
+                .scheme(url.scheme().cloned().unwrap_or(self.scheme.clone()))
+                .authority(url.authority().map(|a| a.as_str()).unwrap_or_default())
+                .path_and_query(location)
+                .build()
+                .map_err(|_| DidWebError::InvalidRedirect(location.to_string()))?,
+            None => {
+                return Err(DidWebError::InvalidRedirect(location.to_string()));
             }
         };
 
-        let url: Uri = match
-            uri::Builder
-                ::new()
-                .scheme(self.scheme.clone())
-                .authority(domain_name)
-                .path_and_query(path)
-                .build()
+        if target.scheme() != Some(&self.scheme) {
+            return Err(DidWebError::InvalidRedirect(location.to_string()));
+        }
+
+        Ok(target)
+    }
+}
+
+impl<C> DidWebResolver<C>
+where
+    C: Connect + Send + Sync + Clone + 'static,
+{
+    async fn resolver_fetcher(&self, did: &str) -> Result<(DIDDocument, DIDDocumentMetadata), DidWebError> {
+        let (path, domain_name) = parse_did_web_url(did)?;
+
+        let url: Uri = match uri::Builder::new()
+            .scheme(self.scheme.clone())
+            .authority(domain_name)
+            .path_and_query(path)
+            .build()
         {
             Ok(url) => url,
             Err(err) => {
@@ -83,12 +184,7 @@ impl<C> DidWebResolver<C> where C: Connect + Send + Sync + Clone + 'static {
             }
         };
 
-        let json_string = match self.fetch_did_document(url).await {
-            Ok(json) => json,
-            Err(err) => {
-                return Err(err);
-            }
-        };
+        let (json_string, metadata) = self.fetch_did_document(url).await?;
 
         let did_document: DIDDocument = match serde_json::from_str(&json_string) {
             Ok(document) => document,
@@ -97,55 +193,92 @@ impl<C> DidWebResolver<C> where C: Connect + Send + Sync + Clone + 'static {
             }
         };
 
-        Ok(did_document)
+        // Hosts, or those they redirect to, may only serve the document of
+        // the DID resolved
+        if did_document.id != did {
+            return Err(DidWebError::IdMismatch(did_document.id));
+        }
+
+        Ok((did_document, metadata))
     }
 }
 
+/// Maps a did:web address to the (path, authority) of its DID document.
+///
+/// Addresses with no path resolve to `/.well-known/did.json` at the
+/// domain's root, others to `did.json` under their colon-delimited path.
+///
+/// See https://w3c-ccg.github.io/did-method-web/#read-resolve
 pub fn parse_did_web_url(did: &str) -> Result<(String, String), DidWebError> {
-    let mut parts = did.split(':').peekable();
-    let domain_name = match (parts.next(), parts.next(), parts.next()) {
-        (Some("did"), Some("web"), Some(domain_name)) => domain_name.replacen("%3A", ":", 1),
-        _ => {
-            return Err(DidWebError::InvalidDid("Invalid DID".to_string()));
-        }
-    };
+    let invalid = || DidWebError::InvalidDid(did.to_string());
 
-    let mut path = match parts.peek() {
-        Some(_) => parts.collect::<Vec<&str>>().join("/"),
-        None => ".well-known".to_string(),
+    let mut parts = did.strip_prefix("did:web:").ok_or_else(invalid)?.split(':');
+
+    // A port, if any, must be percent-encoded in the domain part
+    let domain_name = parts.next().unwrap_or_default().replace("%3A", ":").replace("%3a", ":");
+    let (host, port) = match domain_name.split_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (&domain_name[..], None),
     };
 
-    path = format!("/{}/did.json", path);
+    let is_valid_host = !host.is_empty() && !host.starts_with(['.', '-']) && host.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    let is_valid_port = port.is_none_or(|port| port.parse::<u16>().is_ok_and(|port| port != 0));
+    if !is_valid_host || !is_valid_port {
+        return Err(invalid());
+    }
+
+    let segments: Vec<&str> = parts.collect();
+    for segment in &segments {
+        let is_valid_segment =
+            !segment.is_empty() && *segment != "." && *segment != ".." && segment.chars().all(|c| c.is_ascii_alphanumeric() || ".-_%".contains(c));
+        if !is_valid_segment {
+            return Err(invalid());
+        }
+    }
+
+    let path = match segments.is_empty() {
+        true => "/.well-known/did.json".to_string(),
+        false => format!("/{}/did.json", segments.join("/")),
+    };
 
     Ok((path, domain_name))
 }
 
 #[async_trait]
-impl<C> DIDResolver for DidWebResolver<C> where C: Connect + Send + Sync + Clone + 'static {
+impl<C> DIDResolver for DidWebResolver<C>
+where
+    C: Connect + Send + Sync + Clone + 'static,
+{
     async fn resolve(&self, did: &str, _options: &DIDResolutionOptions) -> ResolutionOutput {
         let context = Context::SingleString(String::from("https://www.w3.org/ns/did/v1"));
 
         match self.resolver_fetcher(did).await {
-            Ok(diddoc) =>
-                ResolutionOutput {
-                    context,
-                    did_document: Some(diddoc),
-                    did_resolution_metadata: Some(DIDResolutionMetadata {
-                        error: None,
-                        content_type: Some(MediaType::DidLdJson.to_string()),
-                        additional_properties: None,
-                    }),
-                    did_document_metadata: None,
+            Ok((diddoc, metadata)) => ResolutionOutput {
+                context,
+                did_document: Some(diddoc),
+                did_resolution_metadata: Some(DIDResolutionMetadata {
+                    error: None,
+                    content_type: Some(MediaType::DidLdJson.to_string()),
                     additional_properties: None,
-                },
-            Err(_err) =>
-                ResolutionOutput {
-                    context,
-                    did_document: None,
-                    did_resolution_metadata: None,
-                    did_document_metadata: None,
+                }),
+                did_document_metadata: (metadata != DIDDocumentMetadata::default()).then_some(metadata),
+                additional_properties: None,
+            },
+            Err(err) => ResolutionOutput {
+                context,
+                did_document: None,
+                did_resolution_metadata: Some(DIDResolutionMetadata {
+                    error: Some(if !did.starts_with("did:web:") {
+                        DIDResolutionError::MethodNotSupported
+                    } else {
+                        err.into()
+                    }),
+                    content_type: None,
                     additional_properties: None,
-                },
+                }),
+                did_document_metadata: None,
+                additional_properties: None,
+            },
         }
     }
 }
@@ -68,10 +68,34 @@ pub enum DidWebError {
     HttpError(#[from] hyper::Error),
     #[error("Non-success server response: {0}")]
     NonSuccessResponse(StatusCode),
+    #[error("Request timed out")]
+    Timeout,
+    #[error("Too many redirects")]
+    TooManyRedirects,
+    #[error("Invalid redirect: {0}")]
+    InvalidRedirect(String),
+    #[error("Document of another DID: {0}")]
+    IdMismatch(String),
+    #[error("Document too large")]
+    DocumentTooLarge,
     #[error(transparent)]
     Other(#[from] Box<dyn std::error::Error + Send + Sync>),
 }
 
+impl From<DidWebError> for DIDResolutionError {
+    fn from(error: DidWebError) -> Self {
+        match error {
+            DidWebError::MethodNotSupported(_) => DIDResolutionError::MethodNotSupported,
+            DidWebError::RepresentationNotSupported(_) | DidWebError::ParsingError(_) => DIDResolutionError::RepresentationNotSupported,
+            DidWebError::InvalidDid(_) => DIDResolutionError::InvalidDid,
+            DidWebError::IdMismatch(_) => DIDResolutionError::NotFound,
+            DidWebError::NonSuccessResponse(status) if status == StatusCode::NOT_FOUND || status == StatusCode::GONE => DIDResolutionError::NotFound,
+            DidWebError::NonSuccessResponse(_) => DIDResolutionError::NonSuccessResponse,
+            _ => DIDResolutionError::InternalError,
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum ParsingErrorSource {
     #[error("JSON parsing error: {0}")]
@@ -91,3 +115,11 @@ impl From<std::string::FromUtf8Error> for DidWebError {
         DidWebError::ParsingError(ParsingErrorSource::Utf8Error(error))
     }
 }
+
+#[derive(Error, Debug)]
+pub enum DIDPeerMethodError {
+    #[error("Invalid input document: {0}")]
+    InvalidInputDocument(String),
+    #[error("JSON parsing error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
@@ -1,7 +1,10 @@
 pub mod errors;
 pub mod traits;
 
+pub mod cache;
+
 pub mod did_key;
+pub mod did_peer;
 pub mod did_web;
 
 mod utils;
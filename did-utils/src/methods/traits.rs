@@ -8,7 +8,11 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::{didcore::Document as DIDDocument, ldmodel::Context, methods::errors::DIDResolutionError};
+use crate::{
+    didcore::{DIDCommMessagingService, Document as DIDDocument, Service, VerificationMethod},
+    ldmodel::Context,
+    methods::errors::DIDResolutionError,
+};
 
 /////////////////////////////////////////////////////////////////////////////////////
 ///  DID METHOD  -----------------------------------------------------------------///
@@ -48,6 +52,23 @@ pub trait DIDResolver {
     /// Resolves a DID address into its corresponding DID document.
     async fn resolve(&self, did: &str, _options: &DIDResolutionOptions) -> ResolutionOutput;
 
+    /// Resolves the DIDComm messaging endpoints of a DID for outbound dispatch.
+    ///
+    /// Endpoints are listed in order of preference and restricted to those
+    /// accepting the `didcomm/v2` profile.
+    async fn resolve_didcomm_endpoints(&self, did: &str, options: &DIDResolutionOptions) -> Result<Vec<DIDCommMessagingService>, DIDResolutionError> {
+        let (diddoc, _) = self.resolve(did, options).await.into_result()?;
+
+        Ok(diddoc
+            .service
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Service::didcomm_messaging)
+            .flatten()
+            .filter(|endpoint| endpoint.accepts("didcomm/v2"))
+            .collect())
+    }
+
     /// Dereferences a DID URL into its corresponding resource.
     async fn dereference(&self, did_url: &str, _options: &DereferencingOptions) -> DereferencingOutput {
         let context = Context::SingleString(String::from("https://w3id.org/did-resolution/v1"));
@@ -175,6 +196,21 @@ pub struct ResolutionOutput {
     pub additional_properties: Option<HashMap<String, Value>>,
 }
 
+impl ResolutionOutput {
+    /// Extracts the resolved DID document and its metadata,
+    /// or the error reported in the resolution metadata.
+    pub fn into_result(self) -> Result<(DIDDocument, Option<DIDDocumentMetadata>), DIDResolutionError> {
+        if let Some(err) = self.did_resolution_metadata.and_then(|metadata| metadata.error) {
+            return Err(err);
+        }
+
+        match self.did_document {
+            Some(diddoc) => Ok((diddoc, self.did_document_metadata)),
+            None => Err(DIDResolutionError::InternalError),
+        }
+    }
+}
+
 /// DID Resolution Metadata.
 ///
 /// See https://www.w3.org/TR/did-core/#did-resolution-metadata
@@ -278,6 +314,32 @@ pub enum Content {
     Data(Value),
 }
 
+impl Content {
+    /// Returns the dereferenced URL, e.g. of a selected service endpoint.
+    pub fn as_url(&self) -> Option<&str> {
+        match self {
+            Content::URL(url) => Some(url),
+            _ => None,
+        }
+    }
+
+    /// Interprets the dereferenced resource as a verification method.
+    pub fn as_verification_method(&self) -> Option<VerificationMethod> {
+        match self {
+            Content::Data(data) if data.get("serviceEndpoint").is_none() => serde_json::from_value(data.clone()).ok(),
+            _ => None,
+        }
+    }
+
+    /// Interprets the dereferenced resource as a service.
+    pub fn as_service(&self) -> Option<Service> {
+        match self {
+            Content::Data(data) => serde_json::from_value(data.clone()).ok(),
+            _ => None,
+        }
+    }
+}
+
 /// Media type for resolution input and output metadata.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[non_exhaustive]
@@ -297,16 +359,26 @@ impl Display for MediaType {
     }
 }
 
+/// Selects the URL of a service endpoint, the first one of typed
+/// services listing several.
+fn service_endpoint_url(service: &Service) -> Option<String> {
+    if let Some(endpoints) = service.didcomm_messaging() {
+        return endpoints.into_iter().next().map(|endpoint| endpoint.uri);
+    }
+
+    if let Some(linked_domains) = service.linked_domains() {
+        return linked_domains.origins.into_iter().next();
+    }
+
+    service.service_endpoint.as_str().map(str::to_owned)
+}
+
 /// Serves derefencing query given a DID document
 fn dereference_did_document(diddoc: &DIDDocument, query: &HashMap<String, String>, fragment: &Option<String>) -> Result<Content, DIDResolutionError> {
     // Primary resource
     if let Some(service) = query.get("service") {
         let entries = diddoc.service.clone().unwrap_or_default();
-        let found: Vec<_> = entries
-            .iter()
-            .filter(|entry| entry.id.ends_with(&format!("#{}", service)))
-            .map(|entry| entry.service_endpoint.clone())
-            .collect();
+        let found: Vec<_> = entries.iter().filter(|entry| entry.id.ends_with(&format!("#{}", service))).collect();
 
         if found.is_empty() {
             return Err(DIDResolutionError::NotFound);
@@ -316,7 +388,7 @@ fn dereference_did_document(diddoc: &DIDDocument, query: &HashMap<String, String
             return Err(DIDResolutionError::NotAllowedLocalDuplicateKey);
         }
 
-        let found = &found[0];
+        let found = &service_endpoint_url(found[0]).ok_or(DIDResolutionError::RepresentationNotSupported)?;
         let relative_ref = query.get("relativeRef");
         if (fragment.is_some() || relative_ref.is_some()) && found.contains('#') {
             return Err(DIDResolutionError::InternalError);
@@ -336,18 +408,24 @@ fn dereference_did_document(diddoc: &DIDDocument, query: &HashMap<String, String
 
     // Secondary resource without primary resource
     if let Some(fragment) = fragment {
-        let needle = format!("{}#{}", diddoc.id, fragment);
+        // Identifiers may be expressed as relative references to the DID
+        // See https://www.w3.org/TR/did-core/#relative-did-urls
+        let needles = [json!(format!("{}#{}", diddoc.id, fragment)), json!(format!("#{fragment}"))];
 
         let haystack = [
             json!(diddoc.authentication.as_ref().unwrap_or(&vec![])),
             json!(diddoc.assertion_method.as_ref().unwrap_or(&vec![])),
             json!(diddoc.key_agreement.as_ref().unwrap_or(&vec![])),
+            json!(diddoc.capability_invocation.as_ref().unwrap_or(&vec![])),
+            json!(diddoc.capability_delegation.as_ref().unwrap_or(&vec![])),
             json!(diddoc.verification_method.as_ref().unwrap_or(&vec![])),
             json!(diddoc.service.as_ref().unwrap_or(&vec![])),
         ];
 
         let flat_haystack = haystack.iter().flat_map(|x| x.as_array().unwrap());
-        let found: Vec<_> = flat_haystack.filter(|vm| vm.get("id") == Some(&json!(&needle))).collect();
+        let found: Vec<_> = flat_haystack
+            .filter(|vm| vm.get("id").is_some_and(|id| needles.contains(id)))
+            .collect();
 
         if found.is_empty() {
             return Err(DIDResolutionError::NotFound);
@@ -611,6 +689,140 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dereference_did_document_relative_references() {
+        let diddoc: DIDDocument = serde_json::from_str(
+            r##"{
+                "@context": "https://www.w3.org/ns/did/v1",
+                "id": "did:example:456",
+                "verificationMethod": [
+                    { "id": "#key-1", "type": "Multikey", "controller": "did:example:456"}
+                ],
+                "capabilityInvocation": [
+                    { "id": "#key-2", "type": "Multikey", "controller": "did:example:456"}
+                ],
+                "service": [
+                    {
+                        "id": "#didcomm",
+                        "type": "DIDCommMessaging",
+                        "serviceEndpoint": "https://example.com/didcomm"
+                    }
+                ]
+            }"##,
+        )
+        .unwrap();
+
+        for fragment in ["key-1", "key-2"] {
+            let result = dereference_did_document(&diddoc, &HashMap::new(), &Some(fragment.to_string())).unwrap();
+            let vm = result.as_verification_method().unwrap();
+            assert_eq!(vm.id, format!("#{fragment}"));
+            assert!(result.as_service().is_none());
+        }
+
+        let result = dereference_did_document(&diddoc, &HashMap::new(), &Some("didcomm".to_string())).unwrap();
+        assert_eq!(result.as_service().unwrap().service_endpoint, "https://example.com/didcomm");
+        assert!(result.as_verification_method().is_none());
+
+        let (_, query, fragment) = parse_did_url("did:example:456?service=didcomm&relativeRef=%2Finbox").unwrap();
+        let result = dereference_did_document(&diddoc, &query, &fragment).unwrap();
+        assert_eq!(result.as_url(), Some("https://example.com/didcomm/inbox"));
+    }
+
+    #[test]
+    fn test_dereference_typed_service_endpoints() {
+        let diddoc: DIDDocument = serde_json::from_value(json!({
+            "@context": "https://www.w3.org/ns/did/v1",
+            "id": "did:example:456",
+            "service": [
+                {
+                    "id": "#didcomm",
+                    "type": "DIDCommMessaging",
+                    "serviceEndpoint": [
+                        {"uri": "https://example.com/didcomm", "accept": ["didcomm/v2"]},
+                        {"uri": "wss://example.com/didcomm"}
+                    ]
+                },
+                {
+                    "id": "#domains",
+                    "type": "LinkedDomains",
+                    "serviceEndpoint": {"origins": ["https://example.com", "https://example.org"]}
+                },
+                {
+                    "id": "#other",
+                    "type": "Other",
+                    "serviceEndpoint": {"url": "https://example.com"}
+                }
+            ]
+        }))
+        .unwrap();
+
+        let cases = [
+            ("did:example:456?service=didcomm", Ok("https://example.com/didcomm")),
+            ("did:example:456?service=domains", Ok("https://example.com")),
+            ("did:example:456?service=other", Err(DIDResolutionError::RepresentationNotSupported)),
+        ];
+
+        for (did_url, expected) in cases {
+            let (_, query, fragment) = parse_did_url(did_url).unwrap();
+            let result = dereference_did_document(&diddoc, &query, &fragment);
+            assert_eq!(result.as_ref().map(|content| content.as_url().unwrap()).map_err(Clone::clone), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_didcomm_endpoints() {
+        struct StaticResolver(DIDDocument);
+
+        #[async_trait]
+        impl DIDResolver for StaticResolver {
+            async fn resolve(&self, _did: &str, _options: &DIDResolutionOptions) -> ResolutionOutput {
+                ResolutionOutput {
+                    context: Context::SingleString(String::from("https://w3id.org/did-resolution/v1")),
+                    did_document: Some(self.0.clone()),
+                    did_resolution_metadata: None,
+                    did_document_metadata: None,
+                    additional_properties: None,
+                }
+            }
+        }
+
+        let resolver = StaticResolver(
+            serde_json::from_value(json!({
+                "@context": "https://www.w3.org/ns/did/v1",
+                "id": "did:example:456",
+                "service": [
+                    {
+                        "id": "#legacy",
+                        "type": "DIDCommMessaging",
+                        "serviceEndpoint": {"uri": "https://example.com/v1", "accept": ["didcomm/aip2;env=rfc19"]}
+                    },
+                    {
+                        "id": "#domain",
+                        "type": "LinkedDomains",
+                        "serviceEndpoint": "https://example.com"
+                    },
+                    {
+                        "id": "#didcomm",
+                        "type": "DIDCommMessaging",
+                        "serviceEndpoint": [
+                            {"uri": "https://example.com/didcomm", "routingKeys": ["did:example:mediator#key-1"]},
+                            "wss://example.com/didcomm"
+                        ]
+                    }
+                ]
+            }))
+            .unwrap(),
+        );
+
+        let endpoints = resolver
+            .resolve_didcomm_endpoints("did:example:456", &DIDResolutionOptions::default())
+            .await
+            .unwrap();
+        let uris: Vec<_> = endpoints.iter().map(|endpoint| endpoint.uri.as_str()).collect();
+        assert_eq!(uris, ["https://example.com/didcomm", "wss://example.com/didcomm"]);
+        assert_eq!(endpoints[0].routing_keys, ["did:example:mediator#key-1"]);
+    }
+
     // Helper function to create a sample DID document for testing
     fn create_sample_did_document() -> DIDDocument {
         serde_json::from_str(
@@ -1,12 +1,13 @@
 use multibase::Base;
 
+use crate::canonicalization::canonicalize;
 use crate::crypto::{
     ed25519::Ed25519KeyPair,
     sha256_hash::sha256_hash,
     traits::{CoreSign, Error},
 };
 
-use super::{model::Proof, traits::CryptoProof};
+use super::{model::Proof, traits::CryptoProof, verification::chained_payload};
 
 pub const CRYPRO_SUITE_EDDSA_JCS_2022: &str = "eddsa-jcs-2022";
 pub const PROOF_TYPE_DATA_INTEGRITY_PROOF: &str = "DataIntegrityProof";
@@ -52,8 +53,9 @@ impl CryptoProof for EdDsaJcs2022 {
                 };
 
                 // Canonicalization
-                let canon_proof = json_canon::to_string(&normalized_proof).map_err(|_| Error::InvalidProof)?;
-                let canon_doc = json_canon::to_string(&payload).map_err(|_| Error::InvalidProof)?;
+                let canon_proof = canonicalize(&normalized_proof).map_err(|_| Error::InvalidProof)?;
+                let payload = chained_payload(payload, &self.proof)?;
+                let canon_doc = canonicalize(&payload).map_err(|_| Error::InvalidProof)?;
 
                 // Compute hash to sign
                 let hash = [sha256_hash(canon_proof.as_bytes()), sha256_hash(canon_doc.as_bytes())].concat();
@@ -81,19 +83,13 @@ impl CryptoProof for EdDsaJcs2022 {
                     ..self.proof.clone()
                 };
 
-                // Strip the proof from the payload if any.
-                let naked_payload = match payload.get("proof") {
-                    None => payload,
-                    Some(_) => {
-                        let mut naked_payload = payload.clone();
-                        naked_payload.as_object_mut().unwrap().remove("proof");
-                        naked_payload
-                    }
-                };
+                // Strip the proofs from the payload if any, except
+                // the previous proofs this proof is chained to.
+                let naked_payload = chained_payload(payload, &self.proof)?;
 
                 // Canonicalization
-                let canon_proof = json_canon::to_string(&normalized_proof).map_err(|_| Error::InvalidProof)?;
-                let canon_doc = json_canon::to_string(&naked_payload).map_err(|_| Error::InvalidProof)?;
+                let canon_proof = canonicalize(&normalized_proof).map_err(|_| Error::InvalidProof)?;
+                let canon_doc = canonicalize(&naked_payload).map_err(|_| Error::InvalidProof)?;
 
                 // Compute hash to verify
                 let hash = [sha256_hash(canon_proof.as_bytes()), sha256_hash(canon_doc.as_bytes())].concat();
@@ -0,0 +1,389 @@
+use multibase::Base;
+use serde_json::Value;
+
+use crate::canonicalization::{canonicalize_jsonld, jsonld::ContextLoader};
+use crate::crypto::{
+    ed25519::Ed25519KeyPair,
+    sha256_hash::sha256_hash,
+    traits::{CoreSign, Error},
+};
+
+use super::{eddsa_jcs_2022::PROOF_TYPE_DATA_INTEGRITY_PROOF, model::Proof, traits::CryptoProof, verification::chained_payload};
+
+pub const CRYPTO_SUITE_EDDSA_RDFC_2022: &str = "eddsa-rdfc-2022";
+
+/// The `eddsa-rdfc-2022` crypto suite, signing the RDF canonical
+/// form of JSON-LD documents with Ed25519.
+///
+/// See https://www.w3.org/TR/vc-di-eddsa/#eddsa-rdfc-2022
+pub struct EdDsaRdfc2022 {
+    /// The proof object
+    ///
+    /// In a proof creation process, it does not contain the proof value, but
+    ///   carries info like challenge, nonce, etc.
+    ///
+    /// In a proof verification process, it contains the proof as found in the
+    ///   secured document, including the proof value
+    pub proof: Proof,
+
+    /// The keypair used to create the proof: in which case the signing key must be present.
+    ///
+    /// The keypair used to verify the proof: in which case only the public key must be present.
+    pub key_pair: Ed25519KeyPair,
+
+    /// The proof value codec. This is important for the encoding of the proof.
+    ///
+    /// For the decoding, codec is automaticaly infered from the string.
+    pub proof_value_codec: Option<Base>,
+
+    /// Resolves the remote contexts of documents and proofs
+    pub context_loader: Box<dyn ContextLoader>,
+}
+
+impl EdDsaRdfc2022 {
+    /// Computes the data to sign, concatenating the hashes of the
+    /// canonical proof configuration and document.
+    fn hash_data(&self, proof: &Proof, document: &Value) -> Result<Vec<u8>, Error> {
+        // The proof configuration is interpreted in the document's context
+        let mut proof_config = serde_json::to_value(proof).map_err(|_| Error::InvalidProof)?;
+        if let (Some(config), Some(context)) = (proof_config.as_object_mut(), document.get("@context")) {
+            config.insert(String::from("@context"), context.clone());
+        }
+
+        let loader = self.context_loader.as_ref();
+        let canon_proof = canonicalize_jsonld(&proof_config, loader).map_err(|_| Error::InvalidProof)?;
+        let canon_doc = canonicalize_jsonld(document, loader).map_err(|_| Error::InvalidProof)?;
+
+        Ok([sha256_hash(canon_proof.as_bytes()), sha256_hash(canon_doc.as_bytes())].concat())
+    }
+}
+
+impl CryptoProof for EdDsaRdfc2022 {
+    fn proof(&self, payload: Value) -> Result<Proof, Error> {
+        let Some(codec) = self.proof_value_codec else {
+            return Err(Error::InvalidCall("proof_value_codec must be set for proof creation".to_string()));
+        };
+
+        let normalized_proof = Proof {
+            proof_type: PROOF_TYPE_DATA_INTEGRITY_PROOF.to_string(),
+            cryptosuite: Some(CRYPTO_SUITE_EDDSA_RDFC_2022.to_string()),
+            created: self.proof.created.or_else(|| Some(chrono::Utc::now())),
+            proof_value: None,
+            ..self.proof.clone()
+        };
+
+        let payload = chained_payload(payload, &self.proof)?;
+        let hash = self.hash_data(&normalized_proof, &payload)?;
+
+        self.key_pair.sign(&hash).map(|signature| Proof {
+            proof_value: Some(multibase::encode(codec, signature)),
+            ..normalized_proof
+        })
+    }
+
+    fn verify(&self, payload: Value) -> Result<(), Error> {
+        let Some(proof_value) = self.proof.proof_value.as_ref() else {
+            return Err(Error::InvalidProof);
+        };
+
+        if self.proof.cryptosuite.as_deref() != Some(CRYPTO_SUITE_EDDSA_RDFC_2022) {
+            return Err(Error::InvalidProof);
+        }
+
+        let normalized_proof = Proof {
+            proof_value: None,
+            ..self.proof.clone()
+        };
+
+        // Strip the proofs from the payload if any, except
+        // the previous proofs this proof is chained to.
+        let naked_payload = chained_payload(payload, &self.proof)?;
+
+        let hash = self.hash_data(&normalized_proof, &naked_payload)?;
+
+        multibase::decode(proof_value)
+            .map_err(|_| Error::InvalidProof)
+            .and_then(|(_, signature)| self.key_pair.verify(&hash, &signature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+    use crate::canonicalization::{jsonld::NoContextLoader, loader::DocumentLoader};
+    use crate::crypto::traits::Generate;
+    use crate::proof::{model::Domain, suite};
+
+    fn context() -> Value {
+        json!({
+            "id": "@id",
+            "type": "@type",
+            "sec": "https://w3id.org/security#",
+            "xsd": "http://www.w3.org/2001/XMLSchema#",
+            "DataIntegrityProof": "sec:DataIntegrityProof",
+            "cryptosuite": {"@id": "sec:cryptosuite", "@type": "sec:cryptosuiteString"},
+            "created": {"@id": "http://purl.org/dc/terms/created", "@type": "xsd:dateTime"},
+            "proofPurpose": {"@id": "sec:proofPurpose", "@type": "@vocab"},
+            "assertionMethod": {"@id": "sec:assertionMethod", "@type": "@id"},
+            "verificationMethod": {"@id": "sec:verificationMethod", "@type": "@id"},
+            "domain": "sec:domain",
+            "challenge": "sec:challenge",
+            "nonce": "sec:nonce",
+            "proofValue": {"@id": "sec:proofValue", "@type": "sec:multibase"},
+            "proof": {"@id": "sec:proof", "@type": "@id", "@container": "@graph"},
+            "schema": "https://schema.org/",
+            "Person": "schema:Person",
+            "name": "schema:name",
+            "age": "schema:age",
+        })
+    }
+
+    fn proof() -> Proof {
+        Proof {
+            id: None,
+            proof_type: "DataIntegrityProof".to_string(),
+            cryptosuite: None,
+            proof_purpose: "assertionMethod".to_string(),
+            verification_method: "https://di.example/issuer#z6MkjLrk3gKS2nnkeWcmcxiZPGskmesDpuwRBorgHxUXfxnG".to_string(),
+            created: Some(chrono::Utc.with_ymd_and_hms(2023, 3, 5, 19, 23, 24).unwrap()),
+            expires: None,
+            domain: Some(Domain::SingleString("vc-demo.adorsys.com".to_string())),
+            challenge: Some("523452345234asfdasdfasdfa".to_string()),
+            proof_value: None,
+            previous_proof: None,
+            nonce: Some("1234567890".to_string()),
+        }
+    }
+
+    fn key_pair() -> Ed25519KeyPair {
+        Ed25519KeyPair::new_with_seed("Sample seed bytes of thirtytwo!b".as_bytes()).unwrap()
+    }
+
+    fn verifier(proof: Proof) -> EdDsaRdfc2022 {
+        EdDsaRdfc2022 {
+            proof,
+            key_pair: Ed25519KeyPair::from_public_key(key_pair().public_key.as_bytes()).unwrap(),
+            proof_value_codec: None,
+            context_loader: Box::new(NoContextLoader),
+        }
+    }
+
+    #[test]
+    fn test_create_verify_proof() {
+        let payload = json!({
+            "@context": context(),
+            "id": "did:example:123456789abcdefghi",
+            "type": "Person",
+            "name": "Alice",
+            "age": 101,
+        });
+
+        let prover = EdDsaRdfc2022 {
+            proof: proof(),
+            key_pair: key_pair(),
+            proof_value_codec: Some(Base::Base58Btc),
+            context_loader: Box::new(NoContextLoader),
+        };
+
+        let secured_proof = prover.proof(payload.clone()).unwrap();
+        assert_eq!(secured_proof.cryptosuite.as_deref(), Some(CRYPTO_SUITE_EDDSA_RDFC_2022));
+        assert!(secured_proof.proof_value.as_ref().unwrap().starts_with('z'));
+
+        let mut secured_doc = payload.clone();
+        secured_doc["proof"] = serde_json::to_value(&secured_proof).unwrap();
+        verifier(secured_proof.clone()).verify(secured_doc).unwrap();
+
+        // The proof holds for any serialization of the same RDF dataset
+        let mut context = context();
+        context["fullName"] = json!("schema:name");
+        let equivalent = json!({
+            "@context": context,
+            "age": 101,
+            "fullName": "Alice",
+            "@type": "schema:Person",
+            "@id": "did:example:123456789abcdefghi",
+        });
+        verifier(secured_proof.clone()).verify(equivalent).unwrap();
+
+        // But not for altered data
+        let mut altered = payload;
+        altered["age"] = json!(102);
+        assert!(verifier(secured_proof.clone()).verify(altered.clone()).is_err());
+
+        // Nor for altered proof options
+        let altered_proof = Proof {
+            challenge: Some("another challenge".to_string()),
+            ..secured_proof
+        };
+        altered["age"] = json!(101);
+        assert!(verifier(altered_proof).verify(altered).is_err());
+    }
+
+    #[test]
+    fn test_reject_undefined_terms() {
+        let payload = json!({
+            "@context": context(),
+            "id": "did:example:123456789abcdefghi",
+            "name": "Alice",
+            "undefined": "not covered by the signature",
+        });
+
+        let prover = EdDsaRdfc2022 {
+            proof: proof(),
+            key_pair: key_pair(),
+            proof_value_codec: Some(Base::Base58Btc),
+            context_loader: Box::new(NoContextLoader),
+        };
+
+        assert!(prover.proof(payload).is_err());
+    }
+
+    #[test]
+    fn test_suite_selection() {
+        let payload = json!({
+            "@context": context(),
+            "id": "did:example:123456789abcdefghi",
+            "name": "Alice",
+        });
+
+        for cryptosuite in ["eddsa-jcs-2022", CRYPTO_SUITE_EDDSA_RDFC_2022] {
+            let proof = Proof {
+                cryptosuite: Some(cryptosuite.to_string()),
+                ..proof()
+            };
+
+            let secured_proof = suite(proof, key_pair(), Some(Base::Base58Btc)).unwrap().proof(payload.clone()).unwrap();
+            assert_eq!(secured_proof.cryptosuite.as_deref(), Some(cryptosuite));
+
+            let verifier = suite(secured_proof, key_pair(), None).unwrap();
+            verifier.verify(payload.clone()).unwrap();
+        }
+
+        let unknown = Proof {
+            cryptosuite: Some("ecdsa-rdfc-2019".to_string()),
+            ..proof()
+        };
+        assert!(matches!(suite(unknown, key_pair(), None), Err(Error::Unsupported)));
+    }
+
+    #[test]
+    fn test_secure_credential_with_bundled_contexts() {
+        let credential = json!({
+            "@context": [
+                "https://www.w3.org/ns/credentials/v2",
+                "https://www.w3.org/ns/credentials/examples/v2",
+            ],
+            "id": "http://university.example/credentials/3732",
+            "type": ["VerifiableCredential", "AlumniCredential"],
+            "name": "Jayden Doe",
+            "issuer": "did:example:issuer",
+            "validFrom": "2023-03-05T19:23:24Z",
+            "credentialSubject": {
+                "id": "did:example:subject",
+                "alumniOf": {"name": "Example University"},
+            },
+        });
+
+        let proof = Proof {
+            cryptosuite: Some(CRYPTO_SUITE_EDDSA_RDFC_2022.to_string()),
+            ..proof()
+        };
+
+        let secured_proof = suite(proof, key_pair(), Some(Base::Base58Btc))
+            .unwrap()
+            .proof(credential.clone())
+            .unwrap();
+
+        let mut secured_credential = credential;
+        secured_credential["proof"] = serde_json::to_value(&secured_proof).unwrap();
+        suite(secured_proof.clone(), key_pair(), None)
+            .unwrap()
+            .verify(secured_credential.clone())
+            .unwrap();
+
+        // Remote contexts are not fetched
+        secured_credential["@context"][1] = json!("https://attacker.example/context");
+        assert!(suite(secured_proof, key_pair(), None).unwrap().verify(secured_credential).is_err());
+    }
+
+    /// Test vectors of the eddsa-rdfc-2022 representation of the
+    /// specification, see https://www.w3.org/TR/vc-di-eddsa/#representation-eddsa-rdfc-2022
+    #[test]
+    fn test_w3c_test_vectors() {
+        // Multikeys, prefixed by their two-byte multicodec
+        let secret_key = multibase::decode("z3u2en7t5LR2WtQH5PfFqMqwVHBeXouLzo6haApm8XHqvjxq").unwrap().1;
+        let key_pair = Ed25519KeyPair::new_with_seed(&secret_key[2..]).unwrap();
+        let public_key = multibase::decode("z6MkrJVnaZkeFzdQyMZu1cgjg7k1pZZ6pvBQ7XJPt4swbTQ2").unwrap().1;
+        assert_eq!(key_pair.public_key.as_bytes(), &public_key[2..]);
+
+        let credential: Value = serde_json::from_str(include_str!("../../test_resources/vc_di_eddsa_rdfc_2022_credential.json")).unwrap();
+        let proof = Proof {
+            id: None,
+            proof_type: "DataIntegrityProof".to_string(),
+            cryptosuite: Some(CRYPTO_SUITE_EDDSA_RDFC_2022.to_string()),
+            proof_purpose: "assertionMethod".to_string(),
+            verification_method: "did:key:z6MkrJVnaZkeFzdQyMZu1cgjg7k1pZZ6pvBQ7XJPt4swbTQ2#z6MkrJVnaZkeFzdQyMZu1cgjg7k1pZZ6pvBQ7XJPt4swbTQ2"
+                .to_string(),
+            created: Some(chrono::Utc.with_ymd_and_hms(2023, 2, 24, 23, 36, 38).unwrap()),
+            expires: None,
+            domain: None,
+            challenge: None,
+            proof_value: None,
+            previous_proof: None,
+            nonce: None,
+        };
+
+        // Canonical forms
+        let loader = DocumentLoader::new();
+        let canon_document = canonicalize_jsonld(&credential, &loader).unwrap();
+        assert_eq!(
+            canon_document,
+            include_str!("../../test_resources/vc_di_eddsa_rdfc_2022_canonical_document.nq")
+        );
+
+        let mut proof_config = serde_json::to_value(&proof).unwrap();
+        proof_config["@context"] = credential["@context"].clone();
+        let canon_proof_config = canonicalize_jsonld(&proof_config, &loader).unwrap();
+        assert_eq!(
+            canon_proof_config,
+            include_str!("../../test_resources/vc_di_eddsa_rdfc_2022_canonical_proof_config.nq")
+        );
+
+        // Hashes
+        let prover = EdDsaRdfc2022 {
+            proof: proof.clone(),
+            key_pair,
+            proof_value_codec: Some(Base::Base58Btc),
+            context_loader: Box::new(loader),
+        };
+        let hash = prover.hash_data(&proof, &credential).unwrap();
+        assert_eq!(
+            hex::encode(hash),
+            concat!(
+                "bea7b7acfbad0126b135104024a5f1733e705108f42d59668b05c0c50004c6b0",
+                "517744132ae165a5349155bef0bb0cf2258fff99dfe1dbd914b938d775a36017",
+            )
+        );
+
+        // Proof value
+        let secured_proof = prover.proof(credential.clone()).unwrap();
+        assert_eq!(
+            secured_proof.proof_value.as_deref(),
+            Some("z2YwC8z3ap7yx1nZYCg4L3j3ApHsF8kgPdSb5xoS1VR7vPG3F561B52hYnQF9iseabecm3ijx4K1FBTQsCZahKZme")
+        );
+
+        let mut secured_credential = credential;
+        secured_credential["proof"] = serde_json::to_value(&secured_proof).unwrap();
+        EdDsaRdfc2022 {
+            proof: secured_proof,
+            key_pair: Ed25519KeyPair::from_public_key(&public_key[2..].try_into().unwrap()).unwrap(),
+            proof_value_codec: None,
+            context_loader: Box::new(DocumentLoader::new()),
+        }
+        .verify(secured_credential)
+        .unwrap();
+    }
+}
@@ -1,3 +1,42 @@
 pub mod model;
 pub mod traits;
-pub mod eddsa_jcs_2022;
\ No newline at end of file
+pub mod eddsa_jcs_2022;
+#[cfg(feature = "rdfc")]
+pub mod eddsa_rdfc_2022;
+pub mod verification;
+
+use multibase::Base;
+
+use crate::crypto::{ed25519::Ed25519KeyPair, traits::Error};
+
+use self::{
+    eddsa_jcs_2022::{EdDsaJcs2022, CRYPRO_SUITE_EDDSA_JCS_2022, PROOF_TYPE_DATA_INTEGRITY_PROOF},
+    model::Proof,
+    traits::CryptoProof,
+};
+
+/// Instantiates the crypto suite identified by the `cryptosuite` of a proof.
+///
+/// The `eddsa-rdfc-2022` suite is only available with the `rdfc` feature,
+/// and only resolves embedded and bundled contexts.
+pub fn suite(proof: Proof, key_pair: Ed25519KeyPair, proof_value_codec: Option<Base>) -> Result<Box<dyn CryptoProof>, Error> {
+    if proof.proof_type != PROOF_TYPE_DATA_INTEGRITY_PROOF {
+        return Err(Error::Unsupported);
+    }
+
+    match proof.cryptosuite.as_deref() {
+        Some(CRYPRO_SUITE_EDDSA_JCS_2022) => Ok(Box::new(EdDsaJcs2022 {
+            proof,
+            key_pair,
+            proof_value_codec,
+        })),
+        #[cfg(feature = "rdfc")]
+        Some(eddsa_rdfc_2022::CRYPTO_SUITE_EDDSA_RDFC_2022) => Ok(Box::new(eddsa_rdfc_2022::EdDsaRdfc2022 {
+            proof,
+            key_pair,
+            proof_value_codec,
+            context_loader: Box::new(crate::canonicalization::loader::DocumentLoader::new()),
+        })),
+        _ => Err(Error::Unsupported),
+    }
+}
@@ -10,7 +10,8 @@ pub trait CryptoProof {
     /// 
     /// The payload is the data to be signed without any proof entry.
     /// Caller must make sure all existing proofs are removed prior to passing
-    /// the payload to this function.
+    /// the payload to this function, except the previous proofs listed by
+    /// `previous_proof` when chaining proofs.
     /// 
     /// Returns the proof object with the proof value added.
     fn proof(&self, payload: Value) -> Result<Proof, Error>;
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::crypto::{ed25519::Ed25519KeyPair, traits::Error};
+
+use super::{
+    model::{PreviousProofs, Proof},
+    suite,
+};
+
+/// Reasons for which a proof of a proof set or chain does not verify
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ProofError {
+    #[error("unsupported proof type or cryptosuite")]
+    UnsupportedSuite,
+    #[error("verification method could not be resolved")]
+    UnresolvedVerificationMethod,
+    #[error("previous proof `{0}` not found")]
+    MissingPreviousProof(String),
+    #[error("previous proof `{0}` does not verify")]
+    InvalidPreviousProof(String),
+    #[error("several proofs have id `{0}`")]
+    AmbiguousProofId(String),
+    #[error("proof chain contains a cycle")]
+    CyclicChain,
+    #[error("invalid proof")]
+    InvalidProof,
+}
+
+/// Outcome of the verification of one proof of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofVerification {
+    pub proof: Proof,
+    pub result: Result<(), ProofError>,
+}
+
+impl ProofVerification {
+    pub fn is_verified(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// Verifies all proofs of a secured document, be they independent (proof set)
+/// or referencing each other through `previousProof` (proof chain).
+///
+/// Each proof is verified with the crypto suite named by its `cryptosuite`,
+/// and the key returned by `resolve_key` for its verification method. A
+/// proof only verifies if the previous proofs it references verify too.
+///
+/// Returns one result per proof, in document order. The document is secured
+/// by all of them if they all verify.
+///
+/// See https://www.w3.org/TR/vc-data-integrity/#proof-sets
+pub fn verify_proofs<F>(document: &Value, resolve_key: F) -> Result<Vec<ProofVerification>, Error>
+where
+    F: Fn(&Proof) -> Option<Ed25519KeyPair>,
+{
+    let entries: Vec<Value> = match document.get("proof") {
+        Some(Value::Array(entries)) if !entries.is_empty() => entries.clone(),
+        Some(entry @ Value::Object(_)) => vec![entry.clone()],
+        _ => return Err(Error::InvalidProof),
+    };
+
+    let proofs = entries
+        .iter()
+        .map(|entry| serde_json::from_value::<Proof>(entry.clone()).map_err(|_| Error::InvalidProof))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut unsecured_document = document.clone();
+    unsecured_document.as_object_mut().ok_or(Error::InvalidProof)?.remove("proof");
+
+    let mut verifier = ChainVerifier {
+        unsecured_document,
+        entries,
+        proofs: &proofs,
+        results: vec![None; proofs.len()],
+        resolve_key,
+    };
+
+    for index in 0..proofs.len() {
+        let _ = verifier.verify(index, &mut vec![]);
+    }
+
+    Ok(proofs
+        .iter()
+        .zip(verifier.results)
+        .map(|(proof, result)| ProofVerification {
+            proof: proof.clone(),
+            result: result.expect("all proofs verified"),
+        })
+        .collect())
+}
+
+struct ChainVerifier<'a, F> {
+    unsecured_document: Value,
+    entries: Vec<Value>,
+    proofs: &'a [Proof],
+    results: Vec<Option<Result<(), ProofError>>>,
+    resolve_key: F,
+}
+
+impl<F> ChainVerifier<'_, F>
+where
+    F: Fn(&Proof) -> Option<Ed25519KeyPair>,
+{
+    fn verify(&mut self, index: usize, visiting: &mut Vec<usize>) -> Result<(), ProofError> {
+        if let Some(result) = &self.results[index] {
+            return result.clone();
+        }
+
+        if visiting.contains(&index) {
+            return Err(ProofError::CyclicChain);
+        }
+
+        visiting.push(index);
+        let result = self.verify_uncached(index, visiting);
+        visiting.pop();
+
+        self.results[index] = Some(result.clone());
+        result
+    }
+
+    fn verify_uncached(&mut self, index: usize, visiting: &mut Vec<usize>) -> Result<(), ProofError> {
+        let proof = &self.proofs[index];
+
+        // Previous proofs must verify before the current one is processed
+        let mut previous_entries = vec![];
+        for id in previous_proof_ids(proof) {
+            let matching: Vec<_> = (0..self.proofs.len()).filter(|&i| self.proofs[i].id.as_deref() == Some(id)).collect();
+
+            let previous = match matching[..] {
+                [] => return Err(ProofError::MissingPreviousProof(id.to_owned())),
+                [previous] => previous,
+                _ => return Err(ProofError::AmbiguousProofId(id.to_owned())),
+            };
+
+            match self.verify(previous, visiting) {
+                Err(ProofError::CyclicChain) => return Err(ProofError::CyclicChain),
+                Err(_) => return Err(ProofError::InvalidPreviousProof(id.to_owned())),
+                Ok(()) => previous_entries.push(self.entries[previous].clone()),
+            }
+        }
+
+        let key_pair = (self.resolve_key)(proof).ok_or(ProofError::UnresolvedVerificationMethod)?;
+        let verifier = suite(proof.clone(), key_pair, None).map_err(|_| ProofError::UnsupportedSuite)?;
+
+        // The proof covers the document along with the proofs it is chained to
+        let mut input_document = self.unsecured_document.clone();
+        if !previous_entries.is_empty() {
+            input_document["proof"] = Value::Array(previous_entries);
+        }
+
+        verifier.verify(input_document).map_err(|_| ProofError::InvalidProof)
+    }
+}
+
+fn previous_proof_ids(proof: &Proof) -> Vec<&str> {
+    match &proof.previous_proof {
+        None => vec![],
+        Some(PreviousProofs::SingleString(id)) => vec![id],
+        Some(PreviousProofs::SetOfString(ids)) => ids.iter().map(String::as_str).collect(),
+    }
+}
+
+/// Strips from a payload the proofs a proof does not reference as previous
+/// proofs, such that it matches the data the proof was created over.
+pub(crate) fn chained_payload(mut payload: Value, proof: &Proof) -> Result<Value, Error> {
+    let Some(map) = payload.as_object_mut() else {
+        return Ok(payload);
+    };
+
+    let Some(entries) = map.remove("proof") else {
+        return Ok(payload);
+    };
+
+    let previous_ids = previous_proof_ids(proof);
+    if previous_ids.is_empty() {
+        return Ok(payload);
+    }
+
+    let mut entries_by_id = HashMap::new();
+    let entries = match entries {
+        Value::Array(entries) => entries,
+        entry => vec![entry],
+    };
+    for entry in entries {
+        if let Some(id) = entry.get("id").and_then(Value::as_str) {
+            entries_by_id.insert(id.to_owned(), entry);
+        }
+    }
+
+    let previous_entries = previous_ids
+        .into_iter()
+        .map(|id| entries_by_id.get(id).cloned().ok_or(Error::InvalidProof))
+        .collect::<Result<Vec<_>, _>>()?;
+    map.insert(String::from("proof"), Value::Array(previous_entries));
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use multibase::Base;
+    use serde_json::json;
+
+    use super::*;
+    use crate::crypto::traits::Generate;
+    use crate::proof::{eddsa_jcs_2022::EdDsaJcs2022, traits::CryptoProof};
+
+    fn key_pair(seed: &str) -> Ed25519KeyPair {
+        Ed25519KeyPair::new_with_seed(seed.as_bytes()).unwrap()
+    }
+
+    fn resolve_key(proof: &Proof) -> Option<Ed25519KeyPair> {
+        match proof.verification_method.as_str() {
+            "did:example:alice#key-1" => Some(key_pair("Seed phrase for alice thirtytwo!")),
+            "did:example:bob#key-1" => Some(key_pair("Seed phrase for bob thirtytwo b!")),
+            _ => None,
+        }
+    }
+
+    fn sign(payload: &Value, id: &str, signer: &str, previous_proof: Option<PreviousProofs>) -> Value {
+        let proof = Proof {
+            id: Some(id.to_string()),
+            proof_type: "DataIntegrityProof".to_string(),
+            cryptosuite: Some("eddsa-jcs-2022".to_string()),
+            proof_purpose: "assertionMethod".to_string(),
+            verification_method: format!("did:example:{signer}#key-1"),
+            created: Some(chrono::Utc.with_ymd_and_hms(2023, 3, 5, 19, 23, 24).unwrap()),
+            expires: None,
+            domain: None,
+            challenge: None,
+            proof_value: None,
+            previous_proof,
+            nonce: None,
+        };
+
+        let prover = EdDsaJcs2022 {
+            key_pair: resolve_key(&proof).unwrap(),
+            proof,
+            proof_value_codec: Some(Base::Base58Btc),
+        };
+
+        serde_json::to_value(prover.proof(payload.clone()).unwrap()).unwrap()
+    }
+
+    fn document() -> Value {
+        json!({
+            "id": "did:example:document",
+            "title": "Hello world!",
+        })
+    }
+
+    #[test]
+    fn test_verify_proof_set() {
+        let alice = sign(&document(), "urn:proof:alice", "alice", None);
+        let bob = sign(&document(), "urn:proof:bob", "bob", None);
+
+        let mut secured_doc = document();
+        secured_doc["proof"] = json!([alice, bob]);
+
+        let results = verify_proofs(&secured_doc, resolve_key).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(ProofVerification::is_verified));
+
+        // A single altered proof does not invalidate the others
+        secured_doc["proof"][1]["created"] = json!("2024-01-01T00:00:00Z");
+        let results = verify_proofs(&secured_doc, resolve_key).unwrap();
+        assert!(results[0].is_verified());
+        assert_eq!(results[1].result, Err(ProofError::InvalidProof));
+    }
+
+    #[test]
+    fn test_verify_proof_chain() {
+        let alice = sign(&document(), "urn:proof:alice", "alice", None);
+
+        let mut chained_doc = document();
+        chained_doc["proof"] = json!([alice]);
+        let previous_proof = Some(PreviousProofs::SingleString("urn:proof:alice".to_string()));
+        let bob = sign(&chained_doc, "urn:proof:bob", "bob", previous_proof);
+
+        // Proofs can be listed in any order
+        let mut secured_doc = document();
+        secured_doc["proof"] = json!([bob, alice]);
+
+        let results = verify_proofs(&secured_doc, resolve_key).unwrap();
+        assert!(results.iter().all(ProofVerification::is_verified));
+        assert_eq!(results[0].proof.id.as_deref(), Some("urn:proof:bob"));
+
+        // The chain breaks if a previous proof does not verify
+        secured_doc["proof"][1]["created"] = json!("2024-01-01T00:00:00Z");
+        let results = verify_proofs(&secured_doc, resolve_key).unwrap();
+        assert_eq!(results[0].result, Err(ProofError::InvalidPreviousProof("urn:proof:alice".to_string())));
+        assert_eq!(results[1].result, Err(ProofError::InvalidProof));
+
+        // Or is missing
+        let mut truncated_doc = document();
+        truncated_doc["proof"] = json!([bob]);
+        let results = verify_proofs(&truncated_doc, resolve_key).unwrap();
+        assert_eq!(results[0].result, Err(ProofError::MissingPreviousProof("urn:proof:alice".to_string())));
+
+        // The chained proof also verifies with its suite, which only
+        // keeps the previous proofs it references
+        let bob: Proof = serde_json::from_value(bob).unwrap();
+        let bob_key = || resolve_key(&bob).unwrap();
+        suite(bob.clone(), bob_key(), None).unwrap().verify(chained_doc).unwrap();
+
+        secured_doc["proof"][1]["created"] = alice["created"].clone();
+        suite(bob.clone(), bob_key(), None).unwrap().verify(secured_doc).unwrap();
+    }
+
+    #[cfg(feature = "rdfc")]
+    #[test]
+    fn test_verify_mixed_suites() {
+        use crate::canonicalization::jsonld::NoContextLoader;
+        use crate::proof::eddsa_rdfc_2022::EdDsaRdfc2022;
+
+        let document = json!({
+            "@context": {
+                "sec": "https://w3id.org/security#",
+                "DataIntegrityProof": "sec:DataIntegrityProof",
+                "id": "@id",
+                "type": "@type",
+                "cryptosuite": "sec:cryptosuite",
+                "created": {"@id": "http://purl.org/dc/terms/created", "@type": "http://www.w3.org/2001/XMLSchema#dateTime"},
+                "proofPurpose": {"@id": "sec:proofPurpose", "@type": "@vocab"},
+                "assertionMethod": {"@id": "sec:assertionMethod", "@type": "@id"},
+                "verificationMethod": {"@id": "sec:verificationMethod", "@type": "@id"},
+                "previousProof": {"@id": "sec:previousProof", "@type": "@id"},
+                "proof": {"@id": "sec:proof", "@type": "@id", "@container": "@graph"},
+                "proofValue": "sec:proofValue",
+                "title": "https://schema.org/title",
+            },
+            "id": "did:example:document",
+            "title": "Hello world!",
+        });
+
+        let alice = sign(&document, "urn:proof:alice", "alice", None);
+
+        let mut chained_doc = document.clone();
+        chained_doc["proof"] = json!([alice]);
+        let mut proof: Proof = serde_json::from_value(alice.clone()).unwrap();
+        proof.id = Some("urn:proof:bob".to_string());
+        proof.verification_method = "did:example:bob#key-1".to_string();
+        proof.previous_proof = Some(PreviousProofs::SingleString("urn:proof:alice".to_string()));
+
+        let prover = EdDsaRdfc2022 {
+            key_pair: resolve_key(&proof).unwrap(),
+            proof,
+            proof_value_codec: Some(Base::Base58Btc),
+            context_loader: Box::new(NoContextLoader),
+        };
+        let bob = prover.proof(chained_doc).unwrap();
+        assert_eq!(bob.cryptosuite.as_deref(), Some("eddsa-rdfc-2022"));
+
+        let mut secured_doc = document;
+        secured_doc["proof"] = json!([alice, bob]);
+
+        let results = verify_proofs(&secured_doc, resolve_key).unwrap();
+        assert!(results.iter().all(ProofVerification::is_verified));
+    }
+
+    #[test]
+    fn test_verify_malformed_chains() {
+        let mut cyclic = sign(&document(), "urn:proof:alice", "alice", None);
+        cyclic["previousProof"] = json!("urn:proof:alice");
+
+        let unresolved = sign(&document(), "urn:proof:carol", "alice", None);
+        let mut unresolved = unresolved.as_object().unwrap().clone();
+        unresolved.insert("verificationMethod".to_string(), json!("did:example:carol#key-1"));
+
+        let mut unsupported = sign(&document(), "urn:proof:bob", "bob", None);
+        unsupported["cryptosuite"] = json!("ecdsa-jcs-2019");
+
+        let mut secured_doc = document();
+        secured_doc["proof"] = json!([cyclic, unresolved, unsupported]);
+
+        let results: Vec<_> = verify_proofs(&secured_doc, resolve_key).unwrap().into_iter().map(|r| r.result).collect();
+        assert_eq!(
+            results,
+            [
+                Err(ProofError::CyclicChain),
+                Err(ProofError::UnresolvedVerificationMethod),
+                Err(ProofError::UnsupportedSuite)
+            ]
+        );
+
+        assert!(verify_proofs(&document(), resolve_key).is_err());
+    }
+}
@@ -9,6 +9,7 @@ use hyper::{ service::{ make_service_fn, service_fn }, Body, Request, Response,
 use serde_json::Value;
 use std::convert::Infallible;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 #[allow(dead_code)]
 async fn mock_server_handler(req: Request<Body>) -> Result<Response<Body>, Infallible> {
@@ -30,8 +31,32 @@ async fn mock_server_handler(req: Request<Body>) -> Result<Response<Body>, Infal
     "assertionMethod": ["did:web:localhost#key1"]
   }"#;
 
+    let redirect = |location: &str| Response::builder().status(302).header("location", location).body(Body::empty()).unwrap();
+
+    // Documents are those of the DIDs of the host and the given path
+    let host = req.headers().get("host").and_then(|host| host.to_str().ok()).unwrap_or_default();
+    let did_json = |path: &str| {
+        let id = format!("did:web:{}{path}", host.replace(':', "%3A"));
+        DID_JSON.replace(r#""id": "did:web:localhost","#, &format!(r#""id": "{id}","#))
+    };
+
     let response = match req.uri().path() {
-        "/.well-known/did.json" | "/user/alice/did.json" => Response::new(Body::from(DID_JSON)),
+        "/.well-known/did.json" => Response::new(Body::from(did_json(""))),
+        "/user/alice/did.json" => Response::new(Body::from(did_json(":user:alice"))),
+        "/user/bob/did.json" => redirect("/user/alice/did.json"),
+        "/user/carol/did.json" => redirect("/user/carol/did.json"),
+        "/user/dave/did.json" => redirect("https://localhost/user/alice/did.json"),
+        "/user/grace/did.json" => redirect("/documents/grace.json"),
+        "/documents/grace.json" => Response::new(Body::from(did_json(":user:grace"))),
+        "/user/frank/did.json" => Response::builder()
+            .header("last-modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .body(Body::from(did_json(":user:frank")))
+            .unwrap(),
+        "/user/eve/did.json" => {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            Response::new(Body::from(did_json(":user:eve")))
+        }
+        "/user/oscar/did.json" => Response::new(Body::from(" ".repeat(2 * 1024 * 1024) + &did_json(":user:oscar"))),
         _ => Response::builder().status(404).body(Body::from("Not Found")).unwrap(),
     };
 
@@ -76,7 +101,7 @@ async fn resolves_document() {
           "didDocument": {
             "@context": "https://www.w3.org/ns/did/v1",
             "assertionMethod": ["did:web:localhost#key1"],
-            "id": "did:web:localhost",
+            "id": "did:web:localhost%3A3000",
             "verificationMethod": [
               {
                 "controller": "did:web:localhost",
@@ -103,7 +128,7 @@ async fn resolves_document() {
 }
 
 use did_utils::methods::did_web::resolver;
-use did_utils::methods::errors::DidWebError;
+use did_utils::methods::errors::{ DIDResolutionError, DidWebError };
 
 #[test]
 fn test_parse_did_web_url() {
@@ -128,3 +153,111 @@ fn test_parse_did_web_url() {
     assert_eq!(domain_name_3, "example.com:3000");
     assert_eq!(path_3, "/user/alice/did.json");
 }
+
+#[tokio::test]
+async fn follows_redirects() {
+    let port = 3001;
+    let host = create_mock_server(port).await;
+
+    let did_web_resolver = DidWebResolver::http();
+
+    let did = format!("did:web:{}%3A{}:user:grace", host, port);
+    let output = did_web_resolver.resolve(&did, &DIDResolutionOptions::default()).await;
+    assert_eq!(output.did_document.unwrap().id, did);
+
+    // Documents of other DIDs are not accepted
+    let did = format!("did:web:{}%3A{}:user:bob", host, port);
+    let output = did_web_resolver.resolve(&did, &DIDResolutionOptions::default()).await;
+    assert!(output.did_document.is_none());
+    assert_eq!(output.did_resolution_metadata.unwrap().error, Some(DIDResolutionError::NotFound));
+
+    // Redirect loops and scheme changes are not followed
+    for user in ["carol", "dave"] {
+        let did = format!("did:web:{}%3A{}:user:{}", host, port, user);
+        let output = did_web_resolver.resolve(&did, &DIDResolutionOptions::default()).await;
+        assert!(output.did_document.is_none());
+        assert_eq!(output.did_resolution_metadata.unwrap().error, Some(DIDResolutionError::InternalError));
+    }
+}
+
+#[tokio::test]
+async fn times_out_on_slow_hosts() {
+    let port = 3002;
+    let host = create_mock_server(port).await;
+
+    let did_web_resolver = DidWebResolver::http().with_timeout(Duration::from_millis(200));
+
+    let did = format!("did:web:{}%3A{}:user:eve", host, port);
+    let output = did_web_resolver.resolve(&did, &DIDResolutionOptions::default()).await;
+    assert!(output.did_document.is_none());
+    assert_eq!(output.did_resolution_metadata.unwrap().error, Some(DIDResolutionError::InternalError));
+}
+
+#[tokio::test]
+async fn rejects_oversized_documents() {
+    let port = 3004;
+    let host = create_mock_server(port).await;
+
+    let did_web_resolver = DidWebResolver::http();
+
+    let did = format!("did:web:{}%3A{}:user:oscar", host, port);
+    let output = did_web_resolver.resolve(&did, &DIDResolutionOptions::default()).await;
+    assert!(output.did_document.is_none());
+    assert_eq!(output.did_resolution_metadata.unwrap().error, Some(DIDResolutionError::InternalError));
+}
+
+#[tokio::test]
+async fn reports_resolution_metadata() {
+    let port = 3003;
+    let host = create_mock_server(port).await;
+
+    let did_web_resolver = DidWebResolver::http();
+
+    let did = format!("did:web:{}%3A{}:user:frank", host, port);
+    let output = did_web_resolver.resolve(&did, &DIDResolutionOptions::default()).await;
+    let (_, metadata) = output.into_result().unwrap();
+    assert_eq!(metadata.unwrap().updated.unwrap().to_rfc3339(), "2015-10-21T07:28:00+00:00");
+
+    let cases = [
+        (format!("did:web:{}%3A{}:user:mallory", host, port), DIDResolutionError::NotFound),
+        (String::from("did:web:example.com:..:alice"), DIDResolutionError::InvalidDid),
+        (String::from("did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK"), DIDResolutionError::MethodNotSupported),
+    ];
+
+    for (did, expected) in cases {
+        let output = did_web_resolver.resolve(&did, &DIDResolutionOptions::default()).await;
+        assert_eq!(output.into_result().unwrap_err(), expected);
+    }
+}
+
+#[test]
+fn test_parse_did_web_url_edge_cases() {
+    let (path, domain_name) = resolver::parse_did_web_url("did:web:example.com%3a8443").unwrap();
+    assert_eq!(domain_name, "example.com:8443");
+    assert_eq!(path, "/.well-known/did.json");
+
+    let (path, domain_name) = resolver::parse_did_web_url("did:web:example.com:a:b:c").unwrap();
+    assert_eq!(domain_name, "example.com");
+    assert_eq!(path, "/a/b/c/did.json");
+
+    let invalid_dids = [
+        "did:key:example.com",
+        "did:web:",
+        "did:web:example.com%3A",
+        "did:web:example.com%3Ahttp",
+        "did:web:example.com%3A99999",
+        "did:web:user@example.com",
+        "did:web:example.com%2Fpath",
+        "did:web:example.com::alice",
+        "did:web:example.com:..:alice",
+        "did:web:example.com:user:alice?query",
+        "did:web:example.com:user:alice#fragment",
+    ];
+
+    for did in invalid_dids {
+        assert!(
+            matches!(resolver::parse_did_web_url(did), Err(DidWebError::InvalidDid(_))),
+            "Expected error on {did}"
+        );
+    }
+}
@@ -0,0 +1,82 @@
+//! Command-line utility backing up and restoring the mediator's keystore.
+//!
+//! ```sh
+//! keystore-backup backup
+//! keystore-backup restore <KEY|FILE> [--force]
+//! ```
+//!
+//! `backup` uploads an encrypted backup of the keystore right away, as
+//! configured for scheduled backups. `restore` decrypts a backup, read from
+//! a local file or else fetched from the bucket by key, with the identities
+//! in `KEYSTORE_BACKUP_IDENTITY_FILE`. Every file is checked against its
+//! hash before any is written to the keystore under `STORAGE_DIRPATH`,
+//! which must be empty unless `--force` is given.
+
+use generic_server::backup::{self, BackupConfig, BackupStore};
+use std::path::Path;
+
+const USAGE: &str = "Usage: keystore-backup backup | keystore-backup restore <KEY|FILE> [--force]";
+
+#[tokio::main]
+async fn main() {
+    // Load dotenv-flow variables
+    dotenv_flow::dotenv_flow().ok();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["backup"] => run_backup().await,
+        ["restore", source] => restore(source, false).await,
+        ["restore", source, "--force"] => restore(source, true).await,
+        _ => Err(String::from(USAGE)),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+fn config() -> Result<BackupConfig, String> {
+    BackupConfig::from_env()
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| String::from("Missing KEYSTORE_BACKUP_RECIPIENT"))
+}
+
+async fn run_backup() -> Result<(), String> {
+    let config = config()?;
+    let now = chrono::Utc::now().timestamp();
+
+    let key = backup::backup(&config, &config.store(), now)
+        .await
+        .map_err(|err| err.to_string())?;
+    println!("Backed up keystore to {key}");
+
+    Ok(())
+}
+
+async fn restore(source: &str, force: bool) -> Result<(), String> {
+    let identities = backup::load_identities().map_err(|err| err.to_string())?;
+
+    let archive = if Path::new(source).is_file() {
+        std::fs::read(source).map_err(|err| format!("{source}: {err}"))?
+    } else {
+        let config = config()?;
+        config
+            .store()
+            .get(source)
+            .await
+            .map_err(|err| format!("{source}: {err}"))?
+    };
+
+    let bundle = backup::open(&archive, &identities).map_err(|err| err.to_string())?;
+    let storage_dirpath =
+        std::env::var("STORAGE_DIRPATH").map_err(|_| String::from("Missing STORAGE_DIRPATH"))?;
+    let count = backup::restore(&bundle, &storage_dirpath, force).map_err(|err| err.to_string())?;
+
+    let taken = chrono::DateTime::from_timestamp(bundle.created_time, 0)
+        .map_or_else(|| bundle.created_time.to_string(), |time| time.to_rfc3339());
+    println!("Restored {count} files of the keystore backed up at {taken}");
+
+    Ok(())
+}
@@ -0,0 +1,646 @@
+use axum::{
+    body::Body,
+    http::{Extensions, HeaderMap, Method, Request, StatusCode, Version},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use did_utils::http::HttpClientConfig;
+use server_plugin::{reload::ReloadableSettings, tasks::BackgroundTasks, Plugin};
+use std::{
+    sync::{Arc, OnceLock},
+    thread,
+    time::Duration,
+};
+use tower::{buffer::Buffer, util::ServiceExt};
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::compression::{
+    predicate::{DefaultPredicate, Predicate, SizeAbove},
+    CompressionLayer,
+};
+use tower_http::trace::TraceLayer;
+use utoipa::OpenApi;
+
+use crate::{
+    deadline,
+    hardening::{self, HardeningConfig, MethodPolicy},
+    openapi,
+    plugin::{container::PluginContainer, PLUGINS},
+    reload::ConfigWatcher,
+    startup::{Readiness, ReadinessApiDoc},
+    versioning::AdminVersioning,
+};
+
+/// Size from which responses are compressed by default, in bytes
+const DEFAULT_COMPRESSION_MIN_SIZE: u16 = 1024;
+
+/// Requests awaiting dispatch to the assembled routes, at most
+const DISPATCH_BUFFER_SIZE: usize = 1024;
+
+/// Routes whose responses are never compressed. They hold secrets, e.g.
+/// minted API keys or invitations, next to data the requester controls,
+/// whose compressed length would disclose them (BREACH).
+const UNCOMPRESSED_ROUTES: [&str; 1] = ["/admin"];
+
+/// Assembles the mediator into a router that can be served standalone
+/// or nested into a host application.
+///
+/// Settings provided here are recorded into the registry of settings
+/// handed to plugins, see [`server_plugin::reload::var`], before they are
+/// mounted. Settings left unset fall back to whatever the environment
+/// holds, which is never modified.
+///
+/// Background tasks of plugins and of the server are spawned into a
+/// [`BackgroundTasks`] registry, which hosts shut down when they stop
+/// serving the mediator.
+#[derive(Default)]
+pub struct MediatorBuilder<'a> {
+    storage_dirpath: Option<String>,
+    public_domain: Option<String>,
+    local_port: Option<String>,
+    route_prefix: Option<String>,
+    compression_min_size: Option<u16>,
+    request_timeout: Option<Duration>,
+    hardening: Option<HardeningConfig>,
+    admin_versioning: Option<AdminVersioning>,
+    reloadable_settings: Option<ReloadableSettings>,
+    background_tasks: Option<BackgroundTasks>,
+    plugins: Option<&'a Vec<Box<dyn Plugin>>>,
+}
+
+impl<'a> MediatorBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Directory holding the DID document, keystore and other artifacts
+    pub fn storage_dirpath(self, storage_dirpath: &str) -> Self {
+        Self {
+            storage_dirpath: Some(storage_dirpath.to_owned()),
+            ..self
+        }
+    }
+
+    /// Public domain the mediator is reachable at, used to derive its did:web
+    pub fn public_domain(self, public_domain: &str) -> Self {
+        Self {
+            public_domain: Some(public_domain.to_owned()),
+            ..self
+        }
+    }
+
+    /// Local port advertised in generated invitations
+    pub fn local_port(self, local_port: u16) -> Self {
+        Self {
+            local_port: Some(local_port.to_string()),
+            ..self
+        }
+    }
+
+    /// Path under which all mediator routes are nested, e.g. `/mediator`
+    pub fn route_prefix(self, route_prefix: &str) -> Self {
+        Self {
+            route_prefix: Some(route_prefix.to_owned()),
+            ..self
+        }
+    }
+
+    /// Size from which responses are compressed, in bytes, for clients
+    /// accepting gzip or zstd encodings. Defaults to the value of
+    /// `RESPONSE_COMPRESSION_MIN_SIZE`, or 1 KiB.
+    pub fn compression_min_size(self, compression_min_size: u16) -> Self {
+        Self {
+            compression_min_size: Some(compression_min_size),
+            ..self
+        }
+    }
+
+    /// Budget within which requests are served, which clients may shorten.
+    /// Defaults to the value of `REQUEST_TIMEOUT_MS`, or 30 seconds.
+    pub fn request_timeout(self, request_timeout: Duration) -> Self {
+        Self {
+            request_timeout: Some(request_timeout),
+            ..self
+        }
+    }
+
+    /// Protections of routes against misuse, e.g. by slow clients.
+    /// Defaults to the values of the environment, as per [`hardening`].
+    pub fn hardening(self, hardening: HardeningConfig) -> Self {
+        Self {
+            hardening: Some(hardening),
+            ..self
+        }
+    }
+
+    /// Versions of the administrative API served, and shims translating
+    /// payloads of older versions. Defaults to serving the first version.
+    pub fn admin_versioning(self, admin_versioning: AdminVersioning) -> Self {
+        Self {
+            admin_versioning: Some(admin_versioning),
+            ..self
+        }
+    }
+
+    /// Registry of settings reloaded at runtime, to which plugins add
+    /// their own. Settings are reloaded from directories listed in
+    /// `CONFIG_WATCH_DIRPATHS`, if any.
+    pub fn reloadable_settings(self, reloadable_settings: ReloadableSettings) -> Self {
+        Self {
+            reloadable_settings: Some(reloadable_settings),
+            ..self
+        }
+    }
+
+    /// Registry into which background tasks are spawned, for the caller
+    /// to shut them down. Tasks run until the process exits otherwise.
+    pub fn background_tasks(self, background_tasks: BackgroundTasks) -> Self {
+        Self {
+            background_tasks: Some(background_tasks),
+            ..self
+        }
+    }
+
+    /// Plugins to load instead of the statically registered ones
+    pub fn plugins(self, plugins: &'a Vec<Box<dyn Plugin>>) -> Self {
+        Self {
+            plugins: Some(plugins),
+            ..self
+        }
+    }
+
+    /// Mount plugins and assemble their routes
+    pub fn build(self) -> Router {
+        let readiness = Readiness::new();
+        let routes = self.assemble(&readiness);
+        readiness.complete();
+
+        routes
+    }
+
+    /// Run startup stages, recording them for readiness probes
+    fn assemble(self, readiness: &Readiness) -> Router {
+        // Mounted settings override the environment, and are overridden by
+        // the ones provided here
+        let settings = self.reloadable_settings.unwrap_or_default();
+        let watcher = ConfigWatcher::from_env();
+        if let Some(watcher) = &watcher {
+            watcher.export(&settings);
+        }
+
+        for (key, value) in [
+            ("STORAGE_DIRPATH", &self.storage_dirpath),
+            ("SERVER_PUBLIC_DOMAIN", &self.public_domain),
+            ("SERVER_LOCAL_PORT", &self.local_port),
+        ] {
+            if let Some(value) = value {
+                settings.record(key, value);
+            }
+        }
+
+        // Plugins failing a stage are kept out of service, while the
+        // others keep serving, even though the mediator is not ready
+        let tasks = self.background_tasks.unwrap_or_default();
+        let mut container = PluginContainer::with_plugins(self.plugins.unwrap_or(&PLUGINS))
+            .with_settings(settings.clone())
+            .with_tasks(tasks.clone());
+
+        // A single client serves outbound requests of all plugins
+        let mut http_client = None;
+        readiness.stage("http-client", || {
+            let client = HttpClientConfig::from_env()
+                .and_then(|config| config.build())
+                .map_err(|e| e.to_string())?;
+            http_client = Some(client);
+            Ok(())
+        });
+        if let Some(client) = http_client {
+            container = container.with_http_client(client);
+        }
+
+        readiness.stage("migrations", || {
+            container.migrate().map_err(|e| e.to_string())
+        });
+        readiness.stage("plugins", || container.load().map_err(|e| e.to_string()));
+        readiness.stage("self-test", || {
+            container.self_test().map_err(|e| e.to_string())
+        });
+
+        if let Some(watcher) = watcher {
+            watcher.watch(settings, &tasks);
+        }
+
+        let route_prefix = match self.route_prefix.as_deref() {
+            None | Some("") | Some("/") => None,
+            Some(prefix) => Some(prefix),
+        };
+
+        let versioning = self.admin_versioning.unwrap_or_default();
+        let routes = container.routes().unwrap_or_default();
+        let routes = routes.merge(readiness.routes());
+        let (routes, methods) = match container.openapi() {
+            Ok(mut doc) => {
+                doc.merge(ReadinessApiDoc::openapi());
+                let methods = MethodPolicy::from_openapi(&doc)
+                    .route(openapi::OPENAPI_PATH, &[Method::GET])
+                    .route(openapi::DOCS_PATH, &[Method::GET]);
+                versioning.describe(&mut doc);
+                (routes.merge(openapi::routes(doc, route_prefix)), methods)
+            }
+            Err(_) => (routes, MethodPolicy::default()),
+        };
+
+        // Routes are hardened at their unversioned paths, as described
+        let hardening = self.hardening.unwrap_or_else(HardeningConfig::from_env);
+        let routes = hardening::harden(routes, methods, &hardening);
+        let routes = versioning.wrap(routes);
+        let routes = routes.layer(middleware::from_fn(exempt_from_compression));
+        let routes = match route_prefix {
+            None => routes,
+            Some(prefix) => Router::new().nest(prefix, routes),
+        };
+
+        let request_timeout = self
+            .request_timeout
+            .unwrap_or_else(deadline::budget_from_env);
+        let routes = deadline::enforce(routes, request_timeout);
+
+        let compression_min_size = self.compression_min_size.unwrap_or_else(|| {
+            server_plugin::reload::var("RESPONSE_COMPRESSION_MIN_SIZE")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_COMPRESSION_MIN_SIZE)
+        });
+        let compression = CompressionLayer::new().compress_when(
+            DefaultPredicate::new()
+                .and(SizeAbove::new(compression_min_size))
+                .and(compressible),
+        );
+
+        routes
+            .layer(compression)
+            .layer(TraceLayer::new_for_http())
+            .layer(CatchPanicLayer::new())
+    }
+}
+
+/// Marker of responses left uncompressed
+#[derive(Clone, Copy)]
+struct Uncompressed;
+
+/// Marks the responses of routes never compressed, as seen from within the
+/// route prefix
+async fn exempt_from_compression(request: Request<Body>, next: Next<Body>) -> Response {
+    let path = request.uri().path();
+    let exempt = UNCOMPRESSED_ROUTES.iter().any(|route| {
+        path.strip_prefix(route)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    });
+
+    let mut response = next.run(request).await;
+    if exempt {
+        response.extensions_mut().insert(Uncompressed);
+    }
+    response
+}
+
+fn compressible(_: StatusCode, _: Version, _: &HeaderMap, extensions: &Extensions) -> bool {
+    extensions.get::<Uncompressed>().is_none()
+}
+
+impl MediatorBuilder<'static> {
+    /// Assemble the mediator on a background thread, answering requests
+    /// right away. Until startup completes, requests other than readiness
+    /// probes are answered with a service unavailable status.
+    pub fn start(self) -> Router {
+        let readiness = Readiness::new();
+        let assembled: Arc<OnceLock<Buffer<Router, Request<Body>>>> = Arc::default();
+
+        let ready = match self.route_prefix.as_deref() {
+            None | Some("") | Some("/") => readiness.routes(),
+            Some(prefix) => Router::new().nest(prefix, readiness.routes()),
+        };
+
+        let (stages, slot) = (readiness.clone(), assembled.clone());
+        thread::spawn(move || {
+            let routes = self.assemble(&stages);
+
+            // Routers cannot be shared between threads, so requests are
+            // handed over to the assembled one through a buffer, set once
+            // and read without locking, whose worker this thread drives
+            let (routes, worker) = Buffer::pair(routes, DISPATCH_BUFFER_SIZE);
+            let _ = slot.set(routes);
+            stages.complete();
+
+            let runtime = tokio::runtime::Builder::new_current_thread().build();
+            runtime.expect("dispatch runtime").block_on(worker);
+        });
+
+        ready.fallback(move |request: Request<Body>| {
+            let routes = assembled.get().cloned();
+            async move {
+                match routes {
+                    Some(routes) => match routes.oneshot(request).await {
+                        Ok(response) => response,
+                        Err(_) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+                    },
+                    None => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        routing::get,
+    };
+    use serde_json::Value;
+    use server_plugin::PluginError;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use utoipa::openapi::{
+        path::{OperationBuilder, PathItemType},
+        OpenApi, OpenApiBuilder, PathItem, PathsBuilder,
+    };
+
+    struct EchoPlugin;
+    impl Plugin for EchoPlugin {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        fn mount(&self) -> Result<(), PluginError> {
+            Ok(())
+        }
+
+        fn unmount(&self) -> Result<(), PluginError> {
+            Ok(())
+        }
+
+        fn routes(&self) -> Router {
+            Router::new()
+                .route("/echo", get(|| async { "echo" }))
+                .route("/admin/echo", get(|| async { "echo" }))
+                .route("/batch", get(|| async { "message".repeat(200) }))
+                .route("/admin/batch", get(|| async { "message".repeat(200) }))
+        }
+
+        fn openapi(&self) -> Option<OpenApi> {
+            let echo = PathItem::new(PathItemType::Get, OperationBuilder::new().build());
+            let paths = PathsBuilder::new()
+                .path("/echo", echo.clone())
+                .path("/admin/echo", echo);
+            Some(OpenApiBuilder::new().paths(paths).build())
+        }
+    }
+
+    async fn status(app: Router, uri: &str) -> StatusCode {
+        app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_building_with_custom_plugins() {
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(EchoPlugin)];
+        let app = MediatorBuilder::new().plugins(&plugins).build();
+
+        assert_eq!(status(app, "/echo").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_building_with_route_prefix() {
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(EchoPlugin)];
+        let app = MediatorBuilder::new()
+            .plugins(&plugins)
+            .route_prefix("/mediator")
+            .build();
+
+        assert_eq!(status(app.clone(), "/mediator/echo").await, StatusCode::OK);
+        assert_eq!(status(app, "/echo").await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_building_with_versioned_admin_routes() {
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(EchoPlugin)];
+        let app = MediatorBuilder::new()
+            .plugins(&plugins)
+            .route_prefix("/mediator")
+            .build();
+
+        let uri = "/mediator/admin/v1/echo";
+        assert_eq!(status(app.clone(), uri).await, StatusCode::OK);
+
+        let uri = "/mediator/admin/v2/echo";
+        assert_eq!(status(app.clone(), uri).await, StatusCode::NOT_FOUND);
+
+        // Unversioned routes keep working, flagged as deprecated
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/mediator/admin/echo")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["deprecation"], "true");
+        assert_eq!(
+            response.headers()[header::LINK],
+            "</mediator/admin/v1/echo>; rel=\"successor-version\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_starting_with_readiness_gating() {
+        static RELEASED: AtomicBool = AtomicBool::new(false);
+
+        struct GatedPlugin;
+        impl Plugin for GatedPlugin {
+            fn name(&self) -> &'static str {
+                "gated"
+            }
+
+            fn mount(&self) -> Result<(), PluginError> {
+                while !RELEASED.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                Ok(())
+            }
+
+            fn unmount(&self) -> Result<(), PluginError> {
+                Ok(())
+            }
+
+            fn routes(&self) -> Router {
+                Router::new()
+            }
+        }
+
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(EchoPlugin), Box::new(GatedPlugin)];
+        let app = MediatorBuilder::new()
+            .plugins(Box::leak(Box::new(plugins)))
+            .route_prefix("/mediator")
+            .start();
+
+        let ready = "/mediator/health/ready";
+        assert_eq!(
+            status(app.clone(), ready).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status(app.clone(), "/mediator/echo").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        RELEASED.store(true, Ordering::Relaxed);
+        for _ in 0..200 {
+            if status(app.clone(), ready).await == StatusCode::OK {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(status(app.clone(), ready).await, StatusCode::OK);
+        assert_eq!(status(app, "/mediator/echo").await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_building_with_background_tasks() {
+        struct TickingPlugin;
+        impl Plugin for TickingPlugin {
+            fn name(&self) -> &'static str {
+                "ticking"
+            }
+
+            fn mount(&self) -> Result<(), PluginError> {
+                Ok(())
+            }
+
+            fn unmount(&self) -> Result<(), PluginError> {
+                Ok(())
+            }
+
+            fn routes(&self) -> Router {
+                Router::new()
+            }
+
+            fn provide(&self, state: &mut server_plugin::state::StateMap) {
+                let tasks = state.get::<BackgroundTasks>().unwrap();
+                tasks.every("tick", Duration::from_secs(3600), || ());
+            }
+        }
+
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(TickingPlugin)];
+        let tasks = BackgroundTasks::new();
+        let _app = MediatorBuilder::new()
+            .plugins(&plugins)
+            .background_tasks(tasks.clone())
+            .build();
+        assert_eq!(tasks.names(), ["tick"]);
+
+        tasks.shutdown();
+        assert!(tasks.is_stopped());
+        assert!(tasks.names().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_building_with_openapi_docs() {
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(EchoPlugin)];
+        let app = MediatorBuilder::new()
+            .plugins(&plugins)
+            .route_prefix("/mediator")
+            .build();
+
+        assert_eq!(status(app.clone(), "/mediator/docs").await, StatusCode::OK);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/mediator/openapi.json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let doc: Value = serde_json::from_slice(&body).unwrap();
+
+        // Paths of plugins, and of the server itself
+        assert!(doc["paths"]["/echo"]["get"].is_object());
+        assert!(doc["paths"]["/admin/v1/echo"]["get"].is_object());
+        assert!(doc["paths"]["/health/plugins"]["get"].is_object());
+        assert!(doc["paths"]["/health/ready"]["get"].is_object());
+        assert_eq!(doc["servers"][0]["url"], "/mediator");
+    }
+
+    #[tokio::test]
+    async fn test_building_with_hardened_routes() {
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(EchoPlugin)];
+        let app = MediatorBuilder::new().plugins(&plugins).build();
+
+        let call = |method: &str, uri: &str| {
+            let request = Request::builder().method(method).uri(uri);
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        // Described routes, at their versioned paths as well
+        for uri in ["/echo", "/admin/v1/echo", "/docs"] {
+            let response = call("GET", uri).await.unwrap();
+            assert!(response
+                .headers()
+                .contains_key(header::CONTENT_SECURITY_POLICY));
+            assert_eq!(
+                response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+                "nosniff"
+            );
+        }
+
+        let response = call("TRACE", "/admin/v1/echo").await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        // Routes left undescribed are taken for DIDComm endpoints
+        let response = call("GET", "/batch").await.unwrap();
+        assert!(response.headers().get(header::X_FRAME_OPTIONS).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_building_with_response_compression() {
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(EchoPlugin)];
+        let app = MediatorBuilder::new()
+            .plugins(&plugins)
+            .compression_min_size(512)
+            .build();
+
+        let encoding = |uri: &str, accepted: &str| {
+            let request = Request::builder()
+                .uri(uri)
+                .header(header::ACCEPT_ENCODING, accepted)
+                .body(Body::empty())
+                .unwrap();
+
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                response.headers().get(header::CONTENT_ENCODING).cloned()
+            }
+        };
+
+        assert_eq!(encoding("/batch", "zstd").await.unwrap(), "zstd");
+        assert_eq!(encoding("/batch", "gzip").await.unwrap(), "gzip");
+        assert!(encoding("/batch", "identity").await.is_none());
+        assert!(encoding("/echo", "zstd").await.is_none());
+
+        // Administrative responses may hold secrets
+        assert_eq!(status(app.clone(), "/admin/batch").await, StatusCode::OK);
+        assert!(encoding("/admin/batch", "zstd").await.is_none());
+        assert!(encoding("/admin/v1/batch", "gzip").await.is_none());
+    }
+}
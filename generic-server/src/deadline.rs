@@ -0,0 +1,188 @@
+//! Deadlines of HTTP requests.
+//!
+//! Each request is served within a budget, `REQUEST_TIMEOUT_MS` by default,
+//! which clients may shorten with the `X-Request-Timeout` header, in
+//! milliseconds, but not extend. The resulting [`Deadline`] is scoped to
+//! the task serving the request, for handlers to pass on to the calls they
+//! make. Requests still being served past their deadline are abandoned and
+//! answered with a problem report.
+//!
+//! An abandoned request may have been partly processed, e.g. a message
+//! forwarded but not yet acknowledged. Retrying is therefore safe only for
+//! idempotent requests: those of idempotent methods, and submissions
+//! carrying an `Idempotency-Key` to routes recording their responses by
+//! it. Only these are answered with a `Retry-After` header.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    Router,
+};
+use protocols_registry::PROBLEM_REPORT_2_0;
+use serde_json::json;
+use server_plugin::deadline::Deadline;
+use std::time::Duration;
+
+/// Header with which clients shorten the budget of their requests
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout";
+
+/// Problem code of requests abandoned at their deadline. Its scope is the
+/// message, which may be retried as is only if the request is idempotent.
+pub const REQUEST_TIMEOUT_CODE: &str = "e.m.req.time";
+
+/// Header identifying submissions across retries
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Budget of requests, by default
+const DEFAULT_BUDGET: Duration = Duration::from_secs(30);
+
+/// Budget of requests, as configured by `REQUEST_TIMEOUT_MS`
+pub fn budget_from_env() -> Duration {
+    server_plugin::reload::var("REQUEST_TIMEOUT_MS")
+        .and_then(|v| v.parse().ok())
+        .filter(|&millis| millis > 0)
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_BUDGET)
+}
+
+/// Serve requests within a budget
+pub fn enforce(router: Router, budget: Duration) -> Router {
+    router.layer(middleware::from_fn_with_state(budget, serve_within))
+}
+
+async fn serve_within(
+    State(budget): State<Duration>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let requested = request
+        .headers()
+        .get(REQUEST_TIMEOUT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_millis);
+    let budget = requested.map_or(budget, |requested| requested.min(budget));
+    let retryable =
+        request.method().is_idempotent() || request.headers().contains_key(IDEMPOTENCY_KEY_HEADER);
+
+    let deadline = Deadline::after(budget);
+    match tokio::time::timeout(budget, deadline.scope(next.run(request))).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!("request abandoned after {} ms", budget.as_millis());
+            timeout_report(retryable)
+        }
+    }
+}
+
+/// Problem report of a request abandoned at its deadline, inviting retries
+/// of idempotent requests only
+fn timeout_report(retryable: bool) -> Response {
+    let report = json!({
+        "id": uuid::Uuid::new_v4().to_string(),
+        "type": PROBLEM_REPORT_2_0.as_str(),
+        "body": {
+            "code": REQUEST_TIMEOUT_CODE,
+            "comment": "Request could not be served in time",
+        },
+    });
+
+    let mut response = (StatusCode::SERVICE_UNAVAILABLE, Json(report)).into_response();
+    if retryable {
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tower::util::ServiceExt;
+
+    fn app() -> Router {
+        let routes = Router::new()
+            .route(
+                "/remaining",
+                get(|| async {
+                    let remaining = Deadline::current().unwrap().remaining();
+                    remaining.as_millis().to_string()
+                }),
+            )
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "done"
+                })
+                .post(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "done"
+                }),
+            );
+
+        enforce(routes, Duration::from_millis(200))
+    }
+
+    async fn call(uri: &str, timeout: Option<&str>) -> Response {
+        let mut request = Request::builder().uri(uri);
+        if let Some(timeout) = timeout {
+            request = request.header(REQUEST_TIMEOUT_HEADER, timeout);
+        }
+
+        app()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn remaining(timeout: Option<&str>) -> u64 {
+        let response = call("/remaining", timeout).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap().parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_propagating_deadlines() {
+        assert!((100..=200).contains(&remaining(None).await));
+
+        // Clients may shorten budgets, not extend them
+        assert!(remaining(Some("50")).await <= 50);
+        assert!((100..=200).contains(&remaining(Some("60000")).await));
+        assert!((100..=200).contains(&remaining(Some("soon")).await));
+    }
+
+    #[tokio::test]
+    async fn test_abandoning_requests_at_deadline() {
+        let response = call("/slow", None).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["body"]["code"], REQUEST_TIMEOUT_CODE);
+    }
+
+    #[tokio::test]
+    async fn test_inviting_retries_of_idempotent_requests_only() {
+        let submit = |key: Option<&str>| {
+            let mut request = Request::builder().method("POST").uri("/slow");
+            if let Some(key) = key {
+                request = request.header(IDEMPOTENCY_KEY_HEADER, key);
+            }
+            app().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        let response = submit(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+
+        let response = submit(Some("1f0e")).await.unwrap();
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    }
+}
@@ -0,0 +1,517 @@
+//! Hardening of the HTTP surface of the mediator.
+//!
+//! Routes that are not DIDComm endpoints, i.e. the ones plugins describe in
+//! their OpenAPI documents, are answered with strict security headers, and
+//! only at the methods they describe. DIDComm endpoints are left as they
+//! are, their clients being agents rather than browsers.
+//!
+//! All requests are protected from slow clients holding connections open
+//! (slow-loris): headers must be received within
+//! `HTTP_HEADER_READ_TIMEOUT_MS`, and bodies within
+//! `HTTP_BODY_READ_TIMEOUT_MS`. At most `HTTP_MAX_CONNECTIONS` connections
+//! are served at once, further ones being accepted as others close.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use hyper::{
+    body::HttpBody,
+    server::{conn::AddrIncoming, Builder},
+    Server,
+};
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+use tower::Service;
+use utoipa::openapi::{path::PathItemType, OpenApi};
+
+/// Security headers of responses of non-DIDComm routes, unless set by
+/// their handlers, e.g. to relax the content security policy of a page
+const SECURITY_HEADERS: [(HeaderName, &str); 6] = [
+    (
+        header::STRICT_TRANSPORT_SECURITY,
+        "max-age=63072000; includeSubDomains",
+    ),
+    (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+    (header::X_FRAME_OPTIONS, "DENY"),
+    (
+        header::CONTENT_SECURITY_POLICY,
+        "default-src 'none'; frame-ancestors 'none'",
+    ),
+    (header::REFERRER_POLICY, "no-referrer"),
+    (
+        HeaderName::from_static("cross-origin-opener-policy"),
+        "same-origin",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HardeningConfig {
+    /// Time within which clients must send the headers of requests
+    pub header_read_timeout: Duration,
+
+    /// Time within which clients must send the bodies of requests, once
+    /// their headers are received
+    pub body_read_timeout: Duration,
+
+    /// Connections served at once
+    pub max_connections: usize,
+}
+
+impl Default for HardeningConfig {
+    fn default() -> Self {
+        Self {
+            header_read_timeout: Duration::from_secs(10),
+            body_read_timeout: Duration::from_secs(30),
+            max_connections: 1024,
+        }
+    }
+}
+
+impl HardeningConfig {
+    /// Configuration from `HTTP_HEADER_READ_TIMEOUT_MS`,
+    /// `HTTP_BODY_READ_TIMEOUT_MS` and `HTTP_MAX_CONNECTIONS`
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let millis = |key| {
+            server_plugin::reload::var(key)
+                .and_then(|v| v.parse().ok())
+                .filter(|&millis| millis > 0)
+                .map(Duration::from_millis)
+        };
+        let max_connections = server_plugin::reload::var("HTTP_MAX_CONNECTIONS")
+            .and_then(|v| v.parse().ok())
+            .filter(|&max| max > 0);
+
+        Self {
+            header_read_timeout: millis("HTTP_HEADER_READ_TIMEOUT_MS")
+                .unwrap_or(default.header_read_timeout),
+            body_read_timeout: millis("HTTP_BODY_READ_TIMEOUT_MS")
+                .unwrap_or(default.body_read_timeout),
+            max_connections: max_connections.unwrap_or(default.max_connections),
+        }
+    }
+}
+
+/// Methods non-DIDComm routes are served at, by path template
+#[derive(Debug, Clone, Default)]
+pub struct MethodPolicy {
+    routes: Vec<(String, Vec<Method>)>,
+}
+
+impl MethodPolicy {
+    /// Methods of the operations of an OpenAPI document, routes served at
+    /// `GET` being served at `HEAD` as well
+    pub fn from_openapi(doc: &OpenApi) -> Self {
+        let routes = doc
+            .paths
+            .paths
+            .iter()
+            .map(|(path, item)| {
+                let methods = item.operations.keys().map(method).collect();
+                (path.clone(), methods)
+            })
+            .collect();
+
+        Self { routes }.with_head()
+    }
+
+    /// Adds a route served at given methods, e.g. one left undescribed
+    pub fn route(mut self, path: &str, methods: &[Method]) -> Self {
+        self.routes.push((path.to_owned(), methods.to_vec()));
+        self.with_head()
+    }
+
+    /// Methods a path is served at, if it is that of a non-DIDComm route.
+    /// Paths matching several templates are served at all their methods.
+    pub fn allowed(&self, path: &str) -> Option<Vec<Method>> {
+        let mut matched = false;
+        let mut allowed: Vec<Method> = vec![];
+        for (template, methods) in &self.routes {
+            if matches_template(template, path) {
+                matched = true;
+                for method in methods {
+                    if !allowed.contains(method) {
+                        allowed.push(method.clone());
+                    }
+                }
+            }
+        }
+
+        matched.then_some(allowed)
+    }
+
+    fn with_head(mut self) -> Self {
+        for (_, methods) in &mut self.routes {
+            if methods.contains(&Method::GET) && !methods.contains(&Method::HEAD) {
+                methods.push(Method::HEAD);
+            }
+        }
+
+        self
+    }
+}
+
+fn method(item_type: &PathItemType) -> Method {
+    match item_type {
+        PathItemType::Get => Method::GET,
+        PathItemType::Post => Method::POST,
+        PathItemType::Put => Method::PUT,
+        PathItemType::Delete => Method::DELETE,
+        PathItemType::Options => Method::OPTIONS,
+        PathItemType::Head => Method::HEAD,
+        PathItemType::Patch => Method::PATCH,
+        PathItemType::Trace => Method::TRACE,
+        PathItemType::Connect => Method::CONNECT,
+    }
+}
+
+/// Whether a path matches an OpenAPI path template, e.g. `/admin/{id}`
+fn matches_template(template: &str, path: &str) -> bool {
+    let mut segments = path.split('/');
+    let matched = template.split('/').all(|expected| match segments.next() {
+        Some(segment) if expected.starts_with('{') && expected.ends_with('}') => {
+            !segment.is_empty()
+        }
+        Some(segment) => segment == expected,
+        None => false,
+    });
+
+    matched && segments.next().is_none()
+}
+
+/// Harden routes, restricting non-DIDComm ones to the methods of a policy
+pub fn harden(router: Router, policy: MethodPolicy, config: &HardeningConfig) -> Router {
+    router
+        .layer(middleware::from_fn_with_state(Arc::new(policy), restrict))
+        .layer(middleware::from_fn_with_state(
+            config.body_read_timeout,
+            read_within,
+        ))
+}
+
+async fn restrict(
+    State(policy): State<Arc<MethodPolicy>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    // DIDComm endpoints are left as they are
+    let Some(allowed) = policy.allowed(request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    let mut response = if allowed.contains(request.method()) {
+        next.run(request).await
+    } else {
+        let allow: Vec<_> = allowed.iter().map(Method::as_str).collect();
+        let allow = HeaderValue::from_str(&allow.join(", ")).expect("methods are valid");
+        (StatusCode::METHOD_NOT_ALLOWED, [(header::ALLOW, allow)]).into_response()
+    };
+
+    secure(response.headers_mut());
+    response
+}
+
+fn secure(headers: &mut HeaderMap) {
+    for (name, value) in SECURITY_HEADERS {
+        headers
+            .entry(name)
+            .or_insert_with(|| HeaderValue::from_static(value));
+    }
+}
+
+/// Forwards bodies of requests as they are received, cutting them short
+/// once their time elapsed. Handlers then fail to read them.
+async fn read_within(
+    State(timeout): State<Duration>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if request.body().is_end_stream() {
+        return next.run(request).await;
+    }
+
+    let (parts, mut body) = request.into_parts();
+    let (mut sender, forwarded) = Body::channel();
+    let deadline = tokio::time::Instant::now() + timeout;
+    tokio::spawn(async move {
+        loop {
+            match tokio::time::timeout_at(deadline, body.data()).await {
+                Ok(Some(Ok(chunk))) => {
+                    if sender.send_data(chunk).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(Some(Err(_))) => return sender.abort(),
+                Ok(None) => return,
+                Err(_) => {
+                    tracing::warn!("request body not received in {} ms", timeout.as_millis());
+                    return sender.abort();
+                }
+            }
+        }
+    });
+
+    next.run(Request::from_parts(parts, forwarded)).await
+}
+
+/// Server bound to an address, timing out clients slow to send headers
+pub fn bind(addr: &SocketAddr, config: &HardeningConfig) -> Builder<AddrIncoming> {
+    Server::bind(addr).http1_header_read_timeout(config.header_read_timeout)
+}
+
+type Acquiring = Pin<Box<dyn Future<Output = Result<OwnedSemaphorePermit, AcquireError>> + Send>>;
+
+/// Service making services of connections, up to a number of connections
+/// at once. The server waits for a connection to close before accepting
+/// further ones.
+pub struct ConnectionLimit<M> {
+    inner: M,
+    semaphore: Arc<Semaphore>,
+    acquiring: Option<Acquiring>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<M> ConnectionLimit<M> {
+    pub fn new(inner: M, max_connections: usize) -> Self {
+        Self {
+            inner,
+            semaphore: Arc::new(Semaphore::new(max_connections)),
+            acquiring: None,
+            permit: None,
+        }
+    }
+}
+
+impl<M: Clone> Clone for ConnectionLimit<M> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            semaphore: self.semaphore.clone(),
+            acquiring: None,
+            permit: None,
+        }
+    }
+}
+
+impl<M, T> Service<T> for ConnectionLimit<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = Admitted<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, M::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.permit.is_none() {
+            let semaphore = self.semaphore.clone();
+            let acquiring = self
+                .acquiring
+                .get_or_insert_with(|| Box::pin(semaphore.acquire_owned()));
+            match acquiring.as_mut().poll(cx) {
+                Poll::Ready(permit) => {
+                    self.acquiring = None;
+                    self.permit = Some(permit.expect("semaphore is never closed"));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let permit = self.permit.take().expect("poll_ready must be called first");
+        let making = self.inner.call(target);
+        Box::pin(async move {
+            let inner = making.await?;
+            Ok(Admitted {
+                inner,
+                _permit: permit,
+            })
+        })
+    }
+}
+
+/// Service of a connection, which is counted until it closes
+pub struct Admitted<S> {
+    inner: S,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<S, R> Service<R> for Admitted<S>
+where
+    S: Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Bytes,
+        routing::{get, post},
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tower::util::ServiceExt;
+    use utoipa::openapi::{path::OperationBuilder, OpenApiBuilder, PathItem, PathsBuilder};
+
+    fn app(config: &HardeningConfig) -> Router {
+        let routes = Router::new()
+            .route("/admin/lists/:list", get(|| async { "list" }))
+            .route(
+                "/page",
+                get(|| async { ([(header::CONTENT_SECURITY_POLICY, "img-src data:")], "page") }),
+            )
+            .route("/mediate", post(|body: Bytes| async move { body }));
+
+        let item = |item_type| PathItem::new(item_type, OperationBuilder::new().build());
+        let paths = PathsBuilder::new()
+            .path("/admin/lists/{list}", item(PathItemType::Get))
+            .path("/page", item(PathItemType::Get));
+        let doc = OpenApiBuilder::new().paths(paths).build();
+
+        harden(routes, MethodPolicy::from_openapi(&doc), config)
+    }
+
+    async fn call(config: &HardeningConfig, method: Method, uri: &str, body: Body) -> Response {
+        let request = Request::builder().method(method).uri(uri).body(body);
+        app(config).oneshot(request.unwrap()).await.unwrap()
+    }
+
+    #[test]
+    fn test_matching_path_templates() {
+        assert!(matches_template("/admin/lists/{list}", "/admin/lists/team"));
+        assert!(matches_template("/", "/"));
+        assert!(!matches_template("/admin/lists/{list}", "/admin/lists/"));
+        assert!(!matches_template(
+            "/admin/lists/{list}",
+            "/admin/lists/team/members"
+        ));
+        assert!(!matches_template("/admin/lists", "/admin/lists/team"));
+    }
+
+    #[tokio::test]
+    async fn test_securing_non_didcomm_routes() {
+        let config = HardeningConfig::default();
+
+        let response = call(&config, Method::GET, "/admin/lists/team", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        for (name, value) in SECURITY_HEADERS {
+            assert_eq!(response.headers()[name], value);
+        }
+
+        // Handlers may relax headers for their own responses
+        let response = call(&config, Method::GET, "/page", Body::empty()).await;
+        assert_eq!(
+            response.headers()[header::CONTENT_SECURITY_POLICY],
+            "img-src data:"
+        );
+        assert_eq!(response.headers()[header::X_FRAME_OPTIONS], "DENY");
+
+        // DIDComm endpoints are left as they are
+        let response = call(&config, Method::POST, "/mediate", Body::from("msg")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::X_FRAME_OPTIONS).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_restricting_methods_per_route() {
+        let config = HardeningConfig::default();
+
+        let response = call(&config, Method::HEAD, "/admin/lists/team", Body::empty()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for method in [Method::TRACE, Method::DELETE] {
+            let response = call(&config, method, "/admin/lists/team", Body::empty()).await;
+            assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(response.headers()[header::ALLOW], "GET, HEAD");
+            assert_eq!(
+                response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+                "nosniff"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cutting_slow_bodies_short() {
+        let config = HardeningConfig {
+            body_read_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let response = call(&config, Method::POST, "/mediate", Body::from("msg")).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"msg");
+
+        // A body left unfinished is not waited on past its time
+        let (mut sender, body) = Body::channel();
+        sender.send_data(Bytes::from("ms")).await.unwrap();
+        let response = call(&config, Method::POST, "/mediate", body).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        drop(sender);
+    }
+
+    #[tokio::test]
+    async fn test_limiting_connections() {
+        let make_service = tower::service_fn(|_: ()| async { Ok::<_, ()>(()) });
+        let mut limited = ConnectionLimit::new(make_service, 2);
+
+        let first = limited.ready().await.unwrap().call(()).await.unwrap();
+        let second = limited.ready().await.unwrap().call(()).await.unwrap();
+
+        // Further connections wait for others to close
+        let waiting = tokio::time::timeout(Duration::from_millis(50), limited.ready()).await;
+        assert!(waiting.is_err());
+
+        drop(first);
+        let third = limited.ready().await.unwrap().call(()).await;
+        assert!(third.is_ok());
+        drop(second);
+    }
+
+    #[tokio::test]
+    async fn test_timing_out_slow_headers() {
+        let config = HardeningConfig {
+            header_read_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let router = Router::new().route("/", get(|| async { "ok" }));
+        let server =
+            bind(&"127.0.0.1:0".parse().unwrap(), &config).serve(router.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: ").await.unwrap();
+
+        // The connection is closed once headers are overdue
+        let mut buf = vec![];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut buf));
+        assert!(read.await.is_ok());
+    }
+}
@@ -1,18 +1,21 @@
+pub mod backup;
+pub mod builder;
+pub mod deadline;
+pub mod hardening;
+pub mod openapi;
 pub mod plugin;
+pub mod reload;
+pub mod secrets;
+pub mod startup;
 pub mod util;
+pub mod versioning;
 
-use plugin::container::PluginContainer;
+pub use builder::MediatorBuilder;
 
 use axum::Router;
-use tower_http::catch_panic::CatchPanicLayer;
-use tower_http::trace::TraceLayer;
 
+/// Assemble the mediator as configured by the process environment, its
+/// routes hardened as per [`hardening`]
 pub fn app() -> Router {
-    let mut container = PluginContainer::default();
-    let _ = container.load();
-
-    Router::new() //
-        .merge(container.routes().unwrap_or_default())
-        .layer(TraceLayer::new_for_http())
-        .layer(CatchPanicLayer::new())
+    MediatorBuilder::new().build()
 }
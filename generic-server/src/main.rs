@@ -1,38 +1,96 @@
-use generic_server::app;
+use generic_server::{
+    hardening::{self, ConnectionLimit, HardeningConfig},
+    MediatorBuilder,
+};
 
-use axum::Server;
+use server_plugin::reload::ReloadableSettings;
 use std::net::SocketAddr;
+use tracing::Level;
 
 #[tokio::main]
 async fn main() {
     // Load dotenv-flow variables
     dotenv_flow::dotenv_flow().ok();
 
-    // Enable logging
-    config_tracing();
+    // Decrypt secrets committed encrypted with SOPS, recording them as
+    // settings rather than exporting them to the environment
+    let settings = ReloadableSettings::new();
+    match generic_server::secrets::load_encrypted_env() {
+        Ok(secrets) => {
+            for (key, value) in secrets {
+                settings.record(&key, &value);
+            }
+        }
+        Err(err) => {
+            eprintln!("Failed to load encrypted secrets: {err}");
+            std::process::exit(1);
+        }
+    }
 
-    // Start server
-    let port = std::env::var("SERVER_LOCAL_PORT").unwrap_or("3000".to_owned());
+    // Enable logging, at a level reloaded at runtime
+    config_tracing(&settings);
+
+    // Back up the keystore periodically, if configured
+    match generic_server::backup::BackupConfig::from_env() {
+        Ok(Some(config)) => {
+            generic_server::backup::schedule(config);
+        }
+        Ok(None) => (),
+        Err(err) => {
+            eprintln!("Failed to configure keystore backups: {err}");
+            std::process::exit(1);
+        }
+    }
+
+    // Start server, gating readiness on plugins being mounted
+    let port = settings
+        .get("SERVER_LOCAL_PORT")
+        .unwrap_or("3000".to_owned());
     let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
     tracing::info!("listening on {addr}");
-    Server::bind(&addr)
-        .serve(app().into_make_service())
+    let hardening = HardeningConfig::from_env();
+    let app = MediatorBuilder::new()
+        .reloadable_settings(settings)
+        .hardening(hardening)
+        .start();
+    hardening::bind(&addr, &hardening)
+        .serve(ConnectionLimit::new(
+            app.into_make_service(),
+            hardening.max_connections,
+        ))
         .await
         .unwrap();
 }
 
-fn config_tracing() {
-    use tracing::Level;
-    use tracing_subscriber::{filter, layer::SubscriberExt, util::SubscriberInitExt};
+fn config_tracing(settings: &ReloadableSettings) {
+    use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt};
+
+    let level = settings
+        .get("LOG_LEVEL")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(Level::DEBUG);
 
     let tracing_layer = tracing_subscriber::fmt::layer();
-    let filter = filter::Targets::new()
-        .with_target("hyper::proto", Level::INFO)
-        .with_target("tower_http::trace", Level::DEBUG)
-        .with_default(Level::DEBUG);
+    let (filter, handle) = reload::Layer::new(log_filter(level));
 
     tracing_subscriber::registry()
         .with(tracing_layer)
         .with(filter)
         .init();
+
+    settings.register("LOG_LEVEL", move |value| {
+        let level: Level = value
+            .parse()
+            .map_err(|_| format!("unknown log level {value}"))?;
+        handle
+            .reload(log_filter(level))
+            .map_err(|err| err.to_string())
+    });
+}
+
+fn log_filter(level: Level) -> tracing_subscriber::filter::Targets {
+    tracing_subscriber::filter::Targets::new()
+        .with_target("hyper::proto", Level::INFO.min(level))
+        .with_target("tower_http::trace", Level::DEBUG.min(level))
+        .with_default(level)
 }
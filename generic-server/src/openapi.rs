@@ -0,0 +1,69 @@
+//! OpenAPI description of the HTTP surface of the mediator.
+//!
+//! Plugins describe the endpoints they export that are not DIDComm
+//! endpoints, e.g. the DID document or administrative routes. Their merged
+//! description is served at `/openapi.json`, and can be explored with
+//! Swagger UI at `/docs`.
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{Html, IntoResponse, Json},
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+use utoipa::openapi::{OpenApi, Server};
+
+pub const OPENAPI_PATH: &str = "/openapi.json";
+pub const DOCS_PATH: &str = "/docs";
+
+/// Swagger UI page, loading the description relative to its own path so
+/// that it keeps working under route prefixes
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title>DIDComm mediator API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui.css">
+</head>
+<body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui-bundle.js"></script>
+    <script>
+        window.ui = SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });
+    </script>
+</body>
+</html>
+"##;
+
+/// Content security policy of the Swagger UI page, loading its assets from
+/// the CDN and the description from the mediator
+const SWAGGER_UI_CSP: &str = "default-src 'none'; \
+    script-src https://unpkg.com 'unsafe-inline'; \
+    style-src https://unpkg.com 'unsafe-inline'; \
+    img-src 'self' data:; connect-src 'self'; frame-ancestors 'none'";
+
+/// Routes serving a description, whose paths are relative to the route
+/// prefix the mediator is nested under, if any
+pub fn routes(mut doc: OpenApi, route_prefix: Option<&str>) -> Router {
+    if let Some(prefix) = route_prefix {
+        doc.servers = Some(vec![Server::new(prefix)]);
+    }
+
+    Router::new()
+        .route(OPENAPI_PATH, get(openapi))
+        .route(DOCS_PATH, get(docs))
+        .with_state(Arc::new(doc))
+}
+
+async fn openapi(State(doc): State<Arc<OpenApi>>) -> Json<OpenApi> {
+    Json(doc.as_ref().clone())
+}
+
+async fn docs() -> impl IntoResponse {
+    (
+        [(header::CONTENT_SECURITY_POLICY, SWAGGER_UI_CSP)],
+        Html(SWAGGER_UI),
+    )
+}
@@ -1,21 +1,66 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    panic::{self, AssertUnwindSafe},
+};
 
+use axum::Extension;
 use axum::Router;
-use server_plugin::{Plugin, PluginError};
+use did_utils::{
+    http::{HttpClient, HttpClientConnector},
+    methods::did_web::resolver::DidWebResolver,
+};
+use server_plugin::{
+    flags::FeatureFlags, reload::ReloadableSettings, state::StateMap, tasks::BackgroundTasks,
+    Plugin, PluginError,
+};
+use utoipa::{
+    openapi::{InfoBuilder, OpenApi, OpenApiBuilder},
+    OpenApi as _,
+};
 
-use super::PLUGINS;
+use super::{
+    health::{panic_message, HealthApiDoc, PluginHealth},
+    registry::{Conflict, PluginEvent, PluginRegistry, RegistryApiDoc, SERVER_OWNER},
+    PLUGINS,
+};
 
 #[derive(Debug, PartialEq)]
 pub enum PluginContainerError {
     DuplicateEntry,
     Unloaded,
+    /// Two plugins claim the same route or message type
+    Conflict(Conflict),
     PluginErrorMap(HashMap<String, PluginError>),
 }
 
+impl std::fmt::Display for PluginContainerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DuplicateEntry => write!(f, "duplicate entries in plugin registry"),
+            Self::Unloaded => write!(f, "plugins not loaded"),
+            Self::Conflict(conflict) => write!(f, "conflicting plugins: {conflict}"),
+            Self::PluginErrorMap(errors) => {
+                let mut names: Vec<_> = errors.keys().map(String::as_str).collect();
+                names.sort();
+                write!(f, "failed plugins: {}", names.join(", "))
+            }
+        }
+    }
+}
+
 pub struct PluginContainer<'a> {
     loaded: bool,
     collected_routes: Vec<Router>,
+    collected_docs: Vec<OpenApi>,
+    mounted: Vec<&'static str>,
     plugins: &'a Vec<Box<dyn Plugin>>,
+    flags: FeatureFlags,
+    health: PluginHealth,
+    registry: PluginRegistry,
+    state: StateMap,
+    settings: ReloadableSettings,
+    tasks: BackgroundTasks,
+    http_client: Option<HttpClient>,
 }
 
 impl<'a> Default for PluginContainer<'a> {
@@ -27,13 +72,72 @@ impl<'a> Default for PluginContainer<'a> {
 impl<'a> PluginContainer<'a> {
     /// Instantiate an object aware of all statically registered plugins
     pub fn new() -> Self {
+        Self::with_plugins(&PLUGINS)
+    }
+
+    /// Instantiate an object aware of a custom set of plugins
+    pub fn with_plugins(plugins: &'a Vec<Box<dyn Plugin>>) -> Self {
         Self {
             loaded: false,
             collected_routes: vec![],
-            plugins: &*PLUGINS,
+            collected_docs: vec![],
+            mounted: vec![],
+            plugins,
+            flags: FeatureFlags::from_env(),
+            health: PluginHealth::from_env(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
         }
     }
 
+    /// Consult custom feature flags to tell which plugins to mount
+    pub fn with_flags(self, flags: FeatureFlags) -> Self {
+        Self { flags, ..self }
+    }
+
+    /// Register settings reloaded at runtime into a shared registry
+    pub fn with_settings(self, settings: ReloadableSettings) -> Self {
+        Self { settings, ..self }
+    }
+
+    /// Spawn background tasks of plugins into a shared registry
+    pub fn with_tasks(self, tasks: BackgroundTasks) -> Self {
+        Self { tasks, ..self }
+    }
+
+    /// Provide plugins with a client for outbound requests, e.g. resolving
+    /// did:web, shared among them
+    pub fn with_http_client(self, http_client: HttpClient) -> Self {
+        Self {
+            http_client: Some(http_client),
+            ..self
+        }
+    }
+
+    /// Settings plugins reload at runtime
+    pub fn settings(&self) -> &ReloadableSettings {
+        &self.settings
+    }
+
+    /// Health of plugins, as affected by their failures
+    pub fn health(&self) -> &PluginHealth {
+        &self.health
+    }
+
+    /// Owners of routes and message types, and counts of lifecycle events
+    /// of plugins
+    pub fn registry(&self) -> &PluginRegistry {
+        &self.registry
+    }
+
+    /// Services registered by loaded plugins
+    pub fn state(&self) -> &StateMap {
+        &self.state
+    }
+
     /// Search loaded plugin based on name string
     pub fn find_plugin(&self, name: &str) -> Option<&dyn Plugin> {
         self.plugins
@@ -44,9 +148,20 @@ impl<'a> PluginContainer<'a> {
     /// Load referenced plugins
     ///
     /// This entails mounting them and merging their routes internally (only
-    /// upon successful initialization). An error is returned if plugins
-    /// bearing the same name are found. Also, all plugins failing to be
-    /// initialized are returned in a map with respectively raised errors.
+    /// upon successful initialization). Plugins disabled by feature flags
+    /// are skipped. An error is returned if plugins bearing the same name
+    /// are found. Also, all plugins failing to be initialized are returned
+    /// in a map with respectively raised errors.
+    ///
+    /// Enabled plugins first register the services they provide. A plugin
+    /// consuming a service no plugin provides is not mounted.
+    ///
+    /// Routes are contained per plugin, so that a plugin panicking
+    /// repeatedly gets quarantined without affecting the others.
+    ///
+    /// Enabled plugins must not declare the same paths or message types,
+    /// nor paths served by the container itself. No plugin is loaded
+    /// otherwise. Plugins failing to mount release their claims.
     pub fn load(&mut self) -> Result<(), PluginContainerError> {
         tracing::debug!("loading plugin container");
 
@@ -57,22 +172,106 @@ impl<'a> PluginContainer<'a> {
             return Err(PluginContainerError::DuplicateEntry);
         }
 
-        // Reset collection of routes
+        // Reset collection of routes and services
         self.collected_routes.truncate(0);
+        self.collected_docs.truncate(0);
+        self.mounted.truncate(0);
+        self.state = StateMap::new();
 
-        // Mount plugins and collect routes on successful status
-        let errors: HashMap<_, _> = self
+        // Plugins register the settings they reload along with services
+        self.state.insert(self.settings.clone());
+
+        // and spawn their background tasks for the host to stop them
+        self.state.insert(self.tasks.clone());
+
+        // Outbound requests honour the proxy and roots of the deployment
+        if let Some(client) = &self.http_client {
+            self.state.insert(client.clone());
+            self.state
+                .insert(DidWebResolver::<HttpClientConnector>::with_client(
+                    client.clone(),
+                ));
+        }
+
+        let enabled: Vec<_> = self
             .plugins
             .iter()
-            .filter_map(|plugin| match plugin.mount() {
-                Ok(_) => {
-                    tracing::info!("mounted plugin {}", plugin.name());
-                    self.collected_routes.push(plugin.routes());
-                    None
+            .filter(|plugin| {
+                let enabled = self.flags.is_plugin_enabled(plugin.name());
+                if !enabled {
+                    tracing::info!("plugin {} disabled by feature flags", plugin.name());
+                }
+                enabled
+            })
+            .collect();
+
+        // Claim routes and message types, reserving those of the container
+        self.registry.clear();
+        let reserved: Vec<_> = [HealthApiDoc::openapi(), RegistryApiDoc::openapi()]
+            .into_iter()
+            .flat_map(|doc| doc.paths.paths.into_keys())
+            .collect();
+        let _ = self.registry.claim(SERVER_OWNER, &reserved, &[]);
+        for plugin in &enabled {
+            let claimed =
+                self.registry
+                    .claim(plugin.name(), &plugin.paths(), &plugin.message_types());
+            if let Err(conflict) = claimed {
+                tracing::error!("plugin {} is in conflict: {conflict}", plugin.name());
+                self.registry.clear();
+                return Err(PluginContainerError::Conflict(conflict));
+            }
+        }
+
+        // Register services provided to other plugins
+        for plugin in &enabled {
+            plugin.provide(&mut self.state);
+        }
+
+        // Mount plugins and collect routes on successful status
+        let errors: HashMap<_, _> = enabled
+            .into_iter()
+            .filter_map(|plugin| {
+                let missing = plugin
+                    .consumes()
+                    .into_iter()
+                    .find(|key| !self.state.contains_key(key));
+                if let Some(key) = missing {
+                    tracing::error!("plugin {} consumes missing service {key}", plugin.name());
+                    self.registry.release(plugin.name());
+                    self.registry
+                        .record(plugin.name(), PluginEvent::MountFailed);
+                    return Some((
+                        plugin.name().to_string(),
+                        PluginError::MissingService(key.to_string()),
+                    ));
                 }
-                Err(err) => {
-                    tracing::error!("error mounting plugin {}", plugin.name());
-                    Some((plugin.name().to_string(), err))
+
+                // A plugin panicking while mounting is not to abort loading
+                let mounted = panic::catch_unwind(AssertUnwindSafe(|| plugin.mount()))
+                    .unwrap_or_else(|err| {
+                        let reason = format!("panicked while mounting: {}", panic_message(&*err));
+                        self.health.quarantine(plugin.name(), &reason);
+                        Err(PluginError::InitError)
+                    });
+
+                match mounted {
+                    Ok(_) => {
+                        tracing::info!("mounted plugin {}", plugin.name());
+                        let routes = self.health.contain(plugin.name(), plugin.routes());
+                        self.collected_routes.push(routes);
+                        self.collected_docs.extend(plugin.openapi());
+                        self.mounted.push(plugin.name());
+                        self.registry.record(plugin.name(), PluginEvent::Mounted);
+                        None
+                    }
+                    Err(err) => {
+                        tracing::error!("error mounting plugin {}", plugin.name());
+                        self.registry.release(plugin.name());
+                        self.registry
+                            .record(plugin.name(), PluginEvent::MountFailed);
+                        Some((plugin.name().to_string(), err))
+                    }
                 }
             })
             .collect();
@@ -89,23 +288,145 @@ impl<'a> PluginContainer<'a> {
         }
     }
 
+    /// Migrate persisted data of enabled plugins, before loading them.
+    /// All plugins failing to migrate are returned in a map with
+    /// respectively raised errors.
+    pub fn migrate(&self) -> Result<(), PluginContainerError> {
+        let errors: HashMap<_, _> = self
+            .plugins
+            .iter()
+            .filter(|plugin| self.flags.is_plugin_enabled(plugin.name()))
+            .filter_map(|plugin| {
+                let err = plugin.migrate().err()?;
+                tracing::error!("error migrating plugin {}", plugin.name());
+                Some((plugin.name().to_string(), err))
+            })
+            .collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(PluginContainerError::PluginErrorMap(errors))
+        }
+    }
+
+    /// Self-test plugins successfully mounted. All plugins failing their
+    /// self-test are returned in a map with respectively raised errors.
+    pub fn self_test(&self) -> Result<(), PluginContainerError> {
+        if !self.loaded {
+            return Err(PluginContainerError::Unloaded);
+        }
+
+        let errors: HashMap<_, _> = self
+            .plugins
+            .iter()
+            .filter(|plugin| self.mounted.contains(&plugin.name()))
+            .filter_map(|plugin| {
+                let err = plugin.self_test().err()?;
+                tracing::error!("plugin {} failed its self-test", plugin.name());
+                Some((plugin.name().to_string(), err))
+            })
+            .collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(PluginContainerError::PluginErrorMap(errors))
+        }
+    }
+
+    /// Unmount plugins successfully mounted, releasing their claims. All
+    /// plugins failing to unmount are returned in a map with respectively
+    /// raised errors, and are no longer considered mounted either.
+    pub fn unmount(&mut self) -> Result<(), PluginContainerError> {
+        if !self.loaded {
+            return Err(PluginContainerError::Unloaded);
+        }
+
+        let errors: HashMap<_, _> = self
+            .plugins
+            .iter()
+            .filter(|plugin| self.mounted.contains(&plugin.name()))
+            .filter_map(|plugin| {
+                self.registry.release(plugin.name());
+                match plugin.unmount() {
+                    Ok(_) => {
+                        tracing::info!("unmounted plugin {}", plugin.name());
+                        self.registry.record(plugin.name(), PluginEvent::Unmounted);
+                        None
+                    }
+                    Err(err) => {
+                        tracing::error!("error unmounting plugin {}", plugin.name());
+                        self.registry
+                            .record(plugin.name(), PluginEvent::UnmountFailed);
+                        Some((plugin.name().to_string(), err))
+                    }
+                }
+            })
+            .collect();
+
+        self.collected_routes.truncate(0);
+        self.collected_docs.truncate(0);
+        self.mounted.truncate(0);
+        self.loaded = false;
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(PluginContainerError::PluginErrorMap(errors))
+        }
+    }
+
     /// Merge collected routes from all plugins successfully initialized.
+    /// Handlers can extract registered services as an `Extension<StateMap>`.
     pub fn routes(&self) -> Result<Router, PluginContainerError> {
         if self.loaded {
             Ok(self
                 .collected_routes
                 .iter()
-                .fold(Router::new(), |acc, e| acc.merge(e.clone())))
+                .fold(
+                    self.health.routes().merge(self.registry.routes()),
+                    |acc, e| acc.merge(e.clone()),
+                )
+                .layer(Extension(self.state.clone())))
         } else {
             Err(PluginContainerError::Unloaded)
         }
     }
+
+    /// Merge OpenAPI descriptions from all plugins successfully
+    /// initialized, along with the health and registry routes of plugins.
+    pub fn openapi(&self) -> Result<OpenApi, PluginContainerError> {
+        if !self.loaded {
+            return Err(PluginContainerError::Unloaded);
+        }
+
+        let info = InfoBuilder::new()
+            .title("DIDComm mediator")
+            .version(env!("CARGO_PKG_VERSION"))
+            .description(Some(
+                "HTTP endpoints of the mediator that are not DIDComm endpoints",
+            ))
+            .build();
+        let mut doc = OpenApiBuilder::new().info(info).build();
+
+        doc.merge(HealthApiDoc::openapi());
+        doc.merge(RegistryApiDoc::openapi());
+        for plugin_doc in &self.collected_docs {
+            doc.merge(plugin_doc.clone());
+        }
+
+        Ok(doc)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use axum::routing::get;
+    use crate::plugin::health::HealthStatus;
+    use axum::{body::Body, http::Request, routing::get};
+    use did_utils::http::HttpClientConfig;
+    use tower::util::ServiceExt;
 
     struct FirstPlugin;
     impl Plugin for FirstPlugin {
@@ -183,11 +504,149 @@ mod tests {
         }
     }
 
+    struct UnfitPlugin;
+    impl Plugin for UnfitPlugin {
+        fn name(&self) -> &'static str {
+            "unfit"
+        }
+
+        fn migrate(&self) -> Result<(), PluginError> {
+            Err(PluginError::InitError)
+        }
+
+        fn mount(&self) -> Result<(), PluginError> {
+            Ok(())
+        }
+
+        fn self_test(&self) -> Result<(), PluginError> {
+            Err(PluginError::InitError)
+        }
+
+        fn unmount(&self) -> Result<(), PluginError> {
+            Ok(())
+        }
+
+        fn routes(&self) -> Router {
+            Router::new().route("/unfit", get(|| async {}))
+        }
+    }
+
+    struct PanickingPlugin;
+    impl Plugin for PanickingPlugin {
+        fn name(&self) -> &'static str {
+            "panicking"
+        }
+
+        fn mount(&self) -> Result<(), PluginError> {
+            panic!("no configuration")
+        }
+
+        fn unmount(&self) -> Result<(), PluginError> {
+            Ok(())
+        }
+
+        fn routes(&self) -> Router {
+            Router::new().route("/panicking", get(|| async {}))
+        }
+    }
+
+    struct Greeting(&'static str);
+
+    struct ProviderPlugin;
+    impl Plugin for ProviderPlugin {
+        fn name(&self) -> &'static str {
+            "provider"
+        }
+
+        fn mount(&self) -> Result<(), PluginError> {
+            Ok(())
+        }
+
+        fn unmount(&self) -> Result<(), PluginError> {
+            Ok(())
+        }
+
+        fn routes(&self) -> Router {
+            Router::new()
+        }
+
+        fn provide(&self, state: &mut StateMap) {
+            state.insert(Greeting("hello"));
+        }
+    }
+
+    struct ConsumerPlugin;
+    impl Plugin for ConsumerPlugin {
+        fn name(&self) -> &'static str {
+            "consumer"
+        }
+
+        fn mount(&self) -> Result<(), PluginError> {
+            Ok(())
+        }
+
+        fn unmount(&self) -> Result<(), PluginError> {
+            Ok(())
+        }
+
+        fn routes(&self) -> Router {
+            Router::new().route(
+                "/greet",
+                get(|Extension(state): Extension<StateMap>| async move {
+                    state.get::<Greeting>().unwrap().0
+                }),
+            )
+        }
+
+        fn consumes(&self) -> Vec<&'static str> {
+            vec![StateMap::key::<Greeting>()]
+        }
+    }
+
+    const PICKUP: &str = "https://didcomm.org/messagepickup/3.0/delivery-request";
+
+    /// Plugin declaring the paths and message types it is built with
+    struct ClaimingPlugin(&'static str, &'static str, &'static [&'static str]);
+    impl Plugin for ClaimingPlugin {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn mount(&self) -> Result<(), PluginError> {
+            Ok(())
+        }
+
+        fn unmount(&self) -> Result<(), PluginError> {
+            Err(PluginError::InitError)
+        }
+
+        fn routes(&self) -> Router {
+            Router::new().route(self.1, get(|| async {}))
+        }
+
+        fn paths(&self) -> Vec<String> {
+            vec![self.1.to_owned()]
+        }
+
+        fn message_types(&self) -> Vec<&'static str> {
+            self.2.to_vec()
+        }
+    }
+
     #[test]
     fn test_loading() {
         let mut container = PluginContainer {
             loaded: false,
             collected_routes: vec![],
+            collected_docs: vec![],
+            mounted: vec![],
+            flags: FeatureFlags::default(),
+            health: PluginHealth::default(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
             plugins: &vec![Box::new(FirstPlugin {}), Box::new(SecondPlugin {})],
         };
 
@@ -210,6 +669,15 @@ mod tests {
         let mut container = PluginContainer {
             loaded: false,
             collected_routes: vec![],
+            collected_docs: vec![],
+            mounted: vec![],
+            flags: FeatureFlags::default(),
+            health: PluginHealth::default(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
             plugins: &vec![Box::new(FirstPlugin {}), Box::new(SecondPlugin {})],
         };
 
@@ -224,6 +692,15 @@ mod tests {
         let mut container = PluginContainer {
             loaded: false,
             collected_routes: vec![],
+            collected_docs: vec![],
+            mounted: vec![],
+            flags: FeatureFlags::default(),
+            health: PluginHealth::default(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
             plugins: &vec![Box::new(SecondPlugin {}), Box::new(SecondAgainPlugin {})],
         };
 
@@ -238,6 +715,15 @@ mod tests {
         let mut container = PluginContainer {
             loaded: false,
             collected_routes: vec![],
+            collected_docs: vec![],
+            mounted: vec![],
+            flags: FeatureFlags::default(),
+            health: PluginHealth::default(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
             plugins: &vec![Box::new(FirstPlugin {}), Box::new(FaultyPlugin {})],
         };
 
@@ -255,11 +741,188 @@ mod tests {
         assert_eq!(container.collected_routes.len(), 1);
     }
 
+    #[test]
+    fn test_loading_with_panicking_plugin() {
+        let mut container = PluginContainer {
+            loaded: false,
+            collected_routes: vec![],
+            collected_docs: vec![],
+            mounted: vec![],
+            flags: FeatureFlags::default(),
+            health: PluginHealth::default(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
+            plugins: &vec![Box::new(FirstPlugin {}), Box::new(PanickingPlugin {})],
+        };
+
+        let err = container.load().unwrap_err();
+
+        assert_eq!(
+            err,
+            PluginContainerError::PluginErrorMap(
+                [("panicking".to_string(), PluginError::InitError)]
+                    .into_iter()
+                    .collect()
+            )
+        );
+
+        assert_eq!(container.collected_routes.len(), 1);
+        assert_eq!(
+            container.health().status("panicking"),
+            HealthStatus::Quarantined
+        );
+    }
+
+    #[test]
+    fn test_migrating_and_self_testing() {
+        let plugins: Vec<Box<dyn Plugin>> = vec![
+            Box::new(FirstPlugin {}),
+            Box::new(UnfitPlugin {}),
+            Box::new(FaultyPlugin {}),
+        ];
+        let mut container = PluginContainer::with_plugins(&plugins);
+
+        let errors = || {
+            PluginContainerError::PluginErrorMap(
+                [("unfit".to_string(), PluginError::InitError)]
+                    .into_iter()
+                    .collect(),
+            )
+        };
+        assert_eq!(container.migrate(), Err(errors()));
+        assert_eq!(container.self_test(), Err(PluginContainerError::Unloaded));
+
+        // Plugins failing to mount are not self-tested
+        assert!(container.load().is_err());
+        assert_eq!(container.self_test(), Err(errors()));
+
+        let container = container.with_flags(FeatureFlags::parse("plugin.unfit=off"));
+        assert!(container.migrate().is_ok());
+    }
+
+    #[test]
+    fn test_loading_with_disabled_plugin() {
+        let mut container = PluginContainer {
+            loaded: false,
+            collected_routes: vec![],
+            collected_docs: vec![],
+            mounted: vec![],
+            flags: FeatureFlags::parse("plugin.faulty=off"),
+            health: PluginHealth::default(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
+            plugins: &vec![Box::new(FirstPlugin {}), Box::new(FaultyPlugin {})],
+        };
+
+        assert!(container.load().is_ok());
+        assert_eq!(container.collected_routes.len(), 1);
+
+        let mut container =
+            container.with_flags(FeatureFlags::parse("plugin.first=off,plugin.faulty=on"));
+        assert!(container.load().is_err());
+        assert!(container.collected_routes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_loading_with_shared_state() {
+        let mut container = PluginContainer {
+            loaded: false,
+            collected_routes: vec![],
+            collected_docs: vec![],
+            mounted: vec![],
+            flags: FeatureFlags::default(),
+            health: PluginHealth::default(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
+            plugins: &vec![Box::new(ConsumerPlugin {}), Box::new(ProviderPlugin {})],
+        };
+
+        assert!(container.load().is_ok());
+        assert!(container.state().get::<Greeting>().is_some());
+
+        let response = container
+            .routes()
+            .unwrap()
+            .oneshot(
+                Request::builder()
+                    .uri("/greet")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"hello");
+    }
+
+    #[test]
+    fn test_loading_with_http_client() {
+        let plugins: Vec<Box<dyn Plugin>> = vec![Box::new(FirstPlugin {})];
+        let client = HttpClientConfig::default().build().unwrap();
+        let mut container = PluginContainer::with_plugins(&plugins).with_http_client(client);
+
+        assert!(container.load().is_ok());
+        assert!(container.state().get::<HttpClient>().is_some());
+        assert!(container
+            .state()
+            .get::<DidWebResolver<HttpClientConnector>>()
+            .is_some());
+    }
+
+    #[test]
+    fn test_loading_with_missing_service() {
+        let mut container = PluginContainer {
+            loaded: false,
+            collected_routes: vec![],
+            collected_docs: vec![],
+            mounted: vec![],
+            flags: FeatureFlags::parse("plugin.provider=off"),
+            health: PluginHealth::default(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
+            plugins: &vec![Box::new(ConsumerPlugin {}), Box::new(ProviderPlugin {})],
+        };
+
+        assert_eq!(
+            container.load().unwrap_err(),
+            PluginContainerError::PluginErrorMap(
+                [(
+                    "consumer".to_string(),
+                    PluginError::MissingService(StateMap::key::<Greeting>().to_string())
+                )]
+                .into_iter()
+                .collect()
+            )
+        );
+        assert!(container.collected_routes.is_empty());
+    }
+
     #[test]
     fn test_route_extraction_without_loading() {
         let container = PluginContainer {
             loaded: false,
             collected_routes: vec![],
+            collected_docs: vec![],
+            mounted: vec![],
+            flags: FeatureFlags::default(),
+            health: PluginHealth::default(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
             plugins: &vec![Box::new(FirstPlugin {}), Box::new(SecondPlugin {})],
         };
 
@@ -267,5 +930,125 @@ mod tests {
             container.routes().unwrap_err(),
             PluginContainerError::Unloaded
         );
+        assert!(matches!(
+            container.openapi(),
+            Err(PluginContainerError::Unloaded)
+        ));
+    }
+
+    #[test]
+    fn test_openapi_extraction() {
+        let mut container = PluginContainer {
+            loaded: false,
+            collected_routes: vec![],
+            collected_docs: vec![],
+            mounted: vec![],
+            flags: FeatureFlags::default(),
+            health: PluginHealth::default(),
+            registry: PluginRegistry::new(),
+            state: StateMap::new(),
+            settings: ReloadableSettings::new(),
+            tasks: BackgroundTasks::new(),
+            http_client: None,
+            plugins: &vec![Box::new(FirstPlugin {}), Box::new(FaultyPlugin {})],
+        };
+
+        let _ = container.load();
+        let doc = container.openapi().unwrap();
+
+        // Plugins describing no route only contribute their routes
+        assert_eq!(doc.paths.paths.len(), 3);
+        assert!(doc.paths.paths.contains_key("/health/plugins"));
+        assert!(doc.paths.paths.contains_key("/health/plugins/registry"));
+    }
+
+    #[test]
+    fn test_loading_with_conflicting_plugins() {
+        let plugins: Vec<Box<dyn Plugin>> = vec![
+            Box::new(ClaimingPlugin("pickup", "/pickup", &[PICKUP])),
+            Box::new(ClaimingPlugin("shadow", "/shadow", &[PICKUP])),
+        ];
+        let mut container =
+            PluginContainer::with_plugins(&plugins).with_flags(FeatureFlags::default());
+
+        let err = container.load().unwrap_err();
+        assert_eq!(
+            err,
+            PluginContainerError::Conflict(Conflict::MessageType(
+                PICKUP.to_owned(),
+                ["pickup".to_owned(), "shadow".to_owned()]
+            ))
+        );
+        assert!(container.collected_routes.is_empty());
+        assert_eq!(container.registry().route_owner("/pickup"), None);
+        assert_eq!(
+            container.registry().count("shadow", PluginEvent::Conflict),
+            1
+        );
+
+        // Plugins may not shadow routes of the container either
+        let plugins: Vec<Box<dyn Plugin>> =
+            vec![Box::new(ClaimingPlugin("shadow", "/health/plugins", &[]))];
+        let mut container =
+            PluginContainer::with_plugins(&plugins).with_flags(FeatureFlags::default());
+        assert_eq!(
+            container.load().unwrap_err().to_string(),
+            "conflicting plugins: route /health/plugins of plugin generic-server claimed by plugin shadow"
+        );
+
+        // Conflicts with disabled plugins do not matter
+        let plugins: Vec<Box<dyn Plugin>> = vec![
+            Box::new(ClaimingPlugin("pickup", "/pickup", &[PICKUP])),
+            Box::new(ClaimingPlugin("shadow", "/pickup", &[])),
+        ];
+        let mut container = PluginContainer::with_plugins(&plugins)
+            .with_flags(FeatureFlags::parse("plugin.shadow=off"));
+        assert!(container.load().is_ok());
+        assert_eq!(container.registry().route_owner("/pickup"), Some("pickup"));
+    }
+
+    #[tokio::test]
+    async fn test_listing_owners_and_lifecycle_events() {
+        let plugins: Vec<Box<dyn Plugin>> = vec![
+            Box::new(ClaimingPlugin("pickup", "/pickup", &[PICKUP])),
+            Box::new(FaultyPlugin {}),
+        ];
+        let mut container =
+            PluginContainer::with_plugins(&plugins).with_flags(FeatureFlags::default());
+        assert!(container.load().is_err());
+
+        let response = container
+            .routes()
+            .unwrap()
+            .oneshot(
+                Request::builder()
+                    .uri("/health/plugins/registry")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["routes"]["/pickup"], "pickup");
+        assert_eq!(body["routes"]["/health/plugins"], "generic-server");
+        assert_eq!(body["messageTypes"][PICKUP], "pickup");
+        assert_eq!(body["events"]["pickup"]["mounted"], 1);
+        assert_eq!(body["events"]["faulty"]["mount_failed"], 1);
+
+        // Plugins failing to unmount are unmounted nonetheless
+        let registry = container.registry().clone();
+        assert_eq!(
+            container.unmount(),
+            Err(PluginContainerError::PluginErrorMap(
+                [("pickup".to_string(), PluginError::InitError)]
+                    .into_iter()
+                    .collect()
+            ))
+        );
+        assert_eq!(container.unmount(), Err(PluginContainerError::Unloaded));
+        assert_eq!(registry.route_owner("/pickup"), None);
+        assert_eq!(registry.count("pickup", PluginEvent::UnmountFailed), 1);
+        assert_eq!(registry.count("faulty", PluginEvent::Unmounted), 0);
     }
 }
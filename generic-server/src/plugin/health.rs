@@ -0,0 +1,302 @@
+//! Containment of plugin failures.
+//!
+//! Each plugin's routes are isolated from the others: a panic raised while
+//! handling one of its requests is caught at the plugin boundary, answered
+//! with an internal error and recorded against the plugin. Plugins that
+//! panic repeatedly are quarantined, and their routes answer with a service
+//! unavailable status until the server restarts, while other plugins keep
+//! serving.
+
+use axum::{
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use serde_json::{json, Value};
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tower_http::catch_panic::CatchPanicLayer;
+use utoipa::OpenApi;
+
+/// OpenAPI description of the health route of plugins
+#[derive(OpenApi)]
+#[openapi(paths(report), tags((name = "generic-server", description = "Server hosting the plugins")))]
+pub struct HealthApiDoc;
+
+/// Number of panics after which a plugin is quarantined, by default
+const DEFAULT_MAX_PANICS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// No failure recorded
+    Healthy,
+    /// Some failures recorded, still serving
+    Degraded,
+    /// Too many failures, no longer serving
+    Quarantined,
+}
+
+impl HealthStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Healthy => "healthy",
+            Self::Degraded => "degraded",
+            Self::Quarantined => "quarantined",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginState {
+    pub status: HealthStatus,
+    pub panics: usize,
+    pub last_failure: Option<String>,
+}
+
+impl Default for PluginState {
+    fn default() -> Self {
+        Self {
+            status: HealthStatus::Healthy,
+            panics: 0,
+            last_failure: None,
+        }
+    }
+}
+
+/// Health of plugins, shared by their contained routes
+#[derive(Debug, Clone)]
+pub struct PluginHealth {
+    states: Arc<Mutex<HashMap<String, PluginState>>>,
+    max_panics: usize,
+}
+
+impl Default for PluginHealth {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PANICS)
+    }
+}
+
+impl PluginHealth {
+    /// Track health, quarantining plugins after a number of panics
+    pub fn new(max_panics: usize) -> Self {
+        Self {
+            states: Arc::default(),
+            max_panics: max_panics.max(1),
+        }
+    }
+
+    /// Track health with the panic threshold set by `PLUGIN_MAX_PANICS`
+    pub fn from_env() -> Self {
+        let max_panics = server_plugin::reload::var("PLUGIN_MAX_PANICS")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_PANICS);
+
+        Self::new(max_panics)
+    }
+
+    /// Current status of a plugin
+    pub fn status(&self, name: &str) -> HealthStatus {
+        self.states
+            .lock()
+            .unwrap()
+            .get(name)
+            .map_or(HealthStatus::Healthy, |state| state.status)
+    }
+
+    /// Record a panic raised by a plugin, returning its updated status
+    pub fn record_panic(&self, name: &str, message: &str) -> HealthStatus {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(name.to_owned()).or_default();
+
+        state.panics += 1;
+        state.last_failure = Some(message.to_owned());
+        state.status = if state.panics >= self.max_panics {
+            HealthStatus::Quarantined
+        } else {
+            HealthStatus::Degraded
+        };
+
+        tracing::error!(
+            "plugin {name} panicked ({} of {}): {message}",
+            state.panics,
+            self.max_panics
+        );
+
+        state.status
+    }
+
+    /// Quarantine a plugin right away
+    pub fn quarantine(&self, name: &str, reason: &str) {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(name.to_owned()).or_default();
+
+        state.status = HealthStatus::Quarantined;
+        state.last_failure = Some(reason.to_owned());
+
+        tracing::error!("plugin {name} quarantined: {reason}");
+    }
+
+    /// Status of all plugins with recorded failures
+    pub fn snapshot(&self) -> HashMap<String, PluginState> {
+        self.states.lock().unwrap().clone()
+    }
+
+    /// Isolate the routes of a plugin so that its panics are contained
+    pub fn contain(&self, name: &'static str, router: Router) -> Router {
+        let health = self.clone();
+        let on_panic = move |err: Box<dyn Any + Send + 'static>| {
+            health.record_panic(name, &panic_message(err.as_ref()));
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        };
+
+        router
+            .layer(CatchPanicLayer::custom(on_panic))
+            .layer(middleware::from_fn_with_state(
+                (self.clone(), name),
+                check_quarantine,
+            ))
+    }
+
+    /// Route disclosing the health of plugins
+    pub fn routes(&self) -> Router {
+        Router::new()
+            .route("/health/plugins", axum::routing::get(report))
+            .with_state(self.clone())
+    }
+}
+
+async fn check_quarantine<B>(
+    State((health, name)): State<(PluginHealth, &'static str)>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    match health.status(name) {
+        HealthStatus::Quarantined => Err(StatusCode::SERVICE_UNAVAILABLE),
+        _ => Ok(next.run(request).await),
+    }
+}
+
+/// Reports the health status of each plugin, along with its failures
+#[utoipa::path(
+    get,
+    path = "/health/plugins",
+    tag = "generic-server",
+    responses((status = 200, description = "Health of plugins, keyed by name", body = Value))
+)]
+async fn report(State(health): State<PluginHealth>) -> Json<Value> {
+    let plugins: serde_json::Map<_, _> = health
+        .snapshot()
+        .into_iter()
+        .map(|(name, state)| {
+            let value = json!({
+                "status": state.status.as_str(),
+                "panics": state.panics,
+                "lastFailure": state.last_failure,
+            });
+            (name, value)
+        })
+        .collect();
+
+    Json(json!({ "plugins": plugins }))
+}
+
+/// Extract the message carried by a panic payload
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get};
+    use tower::util::ServiceExt;
+
+    async fn status(app: Router, uri: &str) -> StatusCode {
+        app.oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_panics_are_contained_per_plugin() {
+        let health = PluginHealth::new(2);
+
+        let faulty = Router::new().route("/faulty", get(|| async { panic!("boom") as StatusCode }));
+        let sound = Router::new().route("/sound", get(|| async {}));
+
+        let app = Router::new()
+            .merge(health.contain("faulty", faulty))
+            .merge(health.contain("sound", sound))
+            .merge(health.routes());
+
+        assert_eq!(
+            status(app.clone(), "/faulty").await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(health.status("faulty"), HealthStatus::Degraded);
+        assert_eq!(
+            status(app.clone(), "/faulty").await,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(health.status("faulty"), HealthStatus::Quarantined);
+
+        // Quarantined routes no longer run, others keep serving
+        assert_eq!(
+            status(app.clone(), "/faulty").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(status(app.clone(), "/sound").await, StatusCode::OK);
+        assert_eq!(health.status("sound"), HealthStatus::Healthy);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/health/plugins")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(
+            body,
+            json!({
+                "plugins": {
+                    "faulty": {
+                        "status": "quarantined",
+                        "panics": 2,
+                        "lastFailure": "boom",
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_quarantining_right_away() {
+        let health = PluginHealth::default();
+        health.quarantine("faulty", "panicked while mounting");
+
+        let state = &health.snapshot()["faulty"];
+        assert_eq!(state.status, HealthStatus::Quarantined);
+        assert_eq!(state.panics, 0);
+        assert_eq!(
+            state.last_failure.as_deref(),
+            Some("panicked while mounting")
+        );
+    }
+}
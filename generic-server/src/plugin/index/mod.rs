@@ -2,6 +2,7 @@ mod web;
 
 use axum::Router;
 use server_plugin::{Plugin, PluginError};
+use utoipa::{openapi::OpenApi as OpenApiDoc, OpenApi};
 
 #[derive(Default)]
 pub struct IndexPlugin;
@@ -22,4 +23,8 @@ impl Plugin for IndexPlugin {
     fn routes(&self) -> Router {
         web::routes()
     }
+
+    fn openapi(&self) -> Option<OpenApiDoc> {
+        Some(web::ApiDoc::openapi())
+    }
 }
@@ -2,14 +2,27 @@ use axum::{response::Json, routing::get, Router};
 use chrono::{DateTime, Utc};
 use serde_json::{json, Value};
 use std::time::SystemTime;
+use utoipa::OpenApi;
 
 use crate::util::crate_name;
 
+/// OpenAPI description of the index route
+#[derive(OpenApi)]
+#[openapi(paths(index), tags((name = "generic-server", description = "Server hosting the plugins")))]
+pub struct ApiDoc;
+
 pub fn routes() -> Router {
     Router::new() //
         .route("/about", get(index))
 }
 
+/// Identifies the server, along with its clock
+#[utoipa::path(
+    get,
+    path = "/about",
+    tag = "generic-server",
+    responses((status = 200, description = "Name and current time of the server", body = Value))
+)]
 pub async fn index() -> Json<Value> {
     let now: DateTime<Utc> = SystemTime::now().into();
 
@@ -1,10 +1,14 @@
 pub mod container;
+pub mod health;
+pub mod registry;
 
 use lazy_static::lazy_static;
 use server_plugin::Plugin;
 
 #[cfg(feature = "plugin-index")]
 mod index;
+#[cfg(feature = "plugin-wasm")]
+pub mod wasm;
 
 lazy_static! {
     pub static ref PLUGINS: Vec<Box<dyn Plugin>> = vec![
@@ -14,5 +18,9 @@ lazy_static! {
         Box::<did_endpoint::plugin::DidEndpointPlugin>::default(),
         #[cfg(feature = "plugin-oob_messages")]
         Box::<oob_messages::plugin::OOBMessagesPlugin>::default(),
+        #[cfg(feature = "plugin-mediator_coordination")]
+        Box::<mediator_coordination::plugin::MediatorCoordinationPlugin>::default(),
+        #[cfg(feature = "plugin-wasm")]
+        Box::<wasm::WasmPlugin>::default(),
     ];
 }
@@ -0,0 +1,345 @@
+//! Ownership of routes and message types by plugins.
+//!
+//! Routers of plugins are merged into one, so that two plugins serving the
+//! same path, or handling the same message type, would shadow one another
+//! or abort the server. Plugins declare what they serve, and each path or
+//! type is claimed by one plugin on load, conflicting claims failing the
+//! load. Claims are listed at `/health/plugins/registry`, along with counts
+//! of the lifecycle events of plugins, which are also published in the
+//! Prometheus text format at `/health/plugins/metrics`.
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+use utoipa::OpenApi;
+
+/// Owner of the routes served by the container itself
+pub const SERVER_OWNER: &str = "generic-server";
+
+/// Path listing the owners of routes and message types
+pub const REGISTRY_PATH: &str = "/health/plugins/registry";
+
+/// Path publishing the counts of lifecycle events of plugins
+pub const METRICS_PATH: &str = "/health/plugins/metrics";
+
+/// OpenAPI description of the registry routes
+#[derive(OpenApi)]
+#[openapi(paths(registry, metrics))]
+pub struct RegistryApiDoc;
+
+/// Lifecycle event of a plugin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PluginEvent {
+    Mounted,
+    MountFailed,
+    Unmounted,
+    UnmountFailed,
+    /// A route or message type was claimed by another plugin
+    Conflict,
+}
+
+impl PluginEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mounted => "mounted",
+            Self::MountFailed => "mount_failed",
+            Self::Unmounted => "unmounted",
+            Self::UnmountFailed => "unmount_failed",
+            Self::Conflict => "conflict",
+        }
+    }
+}
+
+/// Claim conflicting with that of another plugin
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict {
+    /// A path claimed by two plugins, the owner first
+    Route(String, [String; 2]),
+    /// A message type claimed by two plugins, the owner first
+    MessageType(String, [String; 2]),
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (kind, claimed, [owner, other]) = match self {
+            Self::Route(path, plugins) => ("route", path, plugins),
+            Self::MessageType(message_type, plugins) => ("message type", message_type, plugins),
+        };
+        write!(
+            f,
+            "{kind} {claimed} of plugin {owner} claimed by plugin {other}"
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Claims {
+    routes: BTreeMap<String, &'static str>,
+    message_types: BTreeMap<&'static str, &'static str>,
+    events: BTreeMap<(&'static str, PluginEvent), u64>,
+}
+
+/// Owners of routes and message types, and counts of lifecycle events, by
+/// plugin. Clones share the same claims and counts.
+#[derive(Debug, Clone, Default)]
+pub struct PluginRegistry {
+    claims: Arc<Mutex<Claims>>,
+}
+
+impl PluginRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Claims paths and message types for a plugin. Claims are all or
+    /// nothing: none is recorded if any conflicts with those of another
+    /// plugin.
+    pub fn claim(
+        &self,
+        plugin: &'static str,
+        paths: &[String],
+        message_types: &[&'static str],
+    ) -> Result<(), Conflict> {
+        let mut claims = self.claims.lock().unwrap();
+
+        let conflict = |owner: &str| [owner.to_owned(), plugin.to_owned()];
+        for path in paths {
+            match claims.routes.get(path.as_str()) {
+                Some(&owner) if owner != plugin => {
+                    *claims
+                        .events
+                        .entry((plugin, PluginEvent::Conflict))
+                        .or_default() += 1;
+                    return Err(Conflict::Route(path.clone(), conflict(owner)));
+                }
+                _ => (),
+            }
+        }
+        for message_type in message_types {
+            match claims.message_types.get(message_type) {
+                Some(&owner) if owner != plugin => {
+                    *claims
+                        .events
+                        .entry((plugin, PluginEvent::Conflict))
+                        .or_default() += 1;
+                    let plugins = conflict(owner);
+                    return Err(Conflict::MessageType(message_type.to_string(), plugins));
+                }
+                _ => (),
+            }
+        }
+
+        for path in paths {
+            claims.routes.insert(path.clone(), plugin);
+        }
+        for message_type in message_types {
+            claims.message_types.insert(message_type, plugin);
+        }
+
+        Ok(())
+    }
+
+    /// Releases the claims of a plugin, e.g. when it fails to mount
+    pub fn release(&self, plugin: &str) {
+        let mut claims = self.claims.lock().unwrap();
+        claims.routes.retain(|_, owner| *owner != plugin);
+        claims.message_types.retain(|_, owner| *owner != plugin);
+    }
+
+    /// Releases all claims, keeping counts of events
+    pub fn clear(&self) {
+        let mut claims = self.claims.lock().unwrap();
+        claims.routes.clear();
+        claims.message_types.clear();
+    }
+
+    pub fn record(&self, plugin: &'static str, event: PluginEvent) {
+        let mut claims = self.claims.lock().unwrap();
+        *claims.events.entry((plugin, event)).or_default() += 1;
+    }
+
+    /// Number of times a plugin went through an event
+    pub fn count(&self, plugin: &str, event: PluginEvent) -> u64 {
+        let claims = self.claims.lock().unwrap();
+        claims
+            .events
+            .iter()
+            .find(|((name, e), _)| *name == plugin && *e == event)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    /// Plugin owning a path, if any
+    pub fn route_owner(&self, path: &str) -> Option<&'static str> {
+        self.claims.lock().unwrap().routes.get(path).copied()
+    }
+
+    /// Plugin owning a message type, if any
+    pub fn message_type_owner(&self, message_type: &str) -> Option<&'static str> {
+        let claims = self.claims.lock().unwrap();
+        claims.message_types.get(message_type).copied()
+    }
+
+    /// Renders counts of events in the Prometheus text format
+    pub fn render(&self) -> String {
+        let claims = self.claims.lock().unwrap();
+        let metric = "mediator_plugin_events_total";
+
+        let mut output = String::new();
+        let _ = writeln!(output, "# HELP {metric} Lifecycle events of plugins");
+        let _ = writeln!(output, "# TYPE {metric} counter");
+        for ((plugin, event), count) in &claims.events {
+            let event = event.as_str();
+            let _ = writeln!(
+                output,
+                "{metric}{{plugin=\"{plugin}\",event=\"{event}\"}} {count}"
+            );
+        }
+
+        output
+    }
+
+    /// Routes disclosing claims and counts of events
+    pub fn routes(&self) -> Router {
+        Router::new()
+            .route(REGISTRY_PATH, axum::routing::get(registry))
+            .route(METRICS_PATH, axum::routing::get(metrics))
+            .with_state(self.clone())
+    }
+
+    fn describe(&self) -> Value {
+        let claims = self.claims.lock().unwrap();
+
+        let mut events = serde_json::Map::new();
+        for ((plugin, event), count) in &claims.events {
+            let plugin = events
+                .entry(plugin.to_string())
+                .or_insert_with(|| json!({}));
+            plugin[event.as_str()] = json!(count);
+        }
+
+        json!({
+            "routes": claims.routes,
+            "messageTypes": claims.message_types,
+            "events": events,
+        })
+    }
+}
+
+/// Lists the plugin owning each route and message type, and counts of the
+/// lifecycle events of plugins
+#[utoipa::path(
+    get,
+    path = "/health/plugins/registry",
+    tag = "generic-server",
+    responses((status = 200, description = "Owners of routes and message types, keyed by path and type", body = Value))
+)]
+async fn registry(State(registry): State<PluginRegistry>) -> Json<Value> {
+    Json(registry.describe())
+}
+
+/// Renders counts of the lifecycle events of plugins in the Prometheus text
+/// format
+#[utoipa::path(
+    get,
+    path = "/health/plugins/metrics",
+    tag = "generic-server",
+    responses((status = 200, description = "Counts of lifecycle events of plugins", content_type = "text/plain", body = String))
+)]
+async fn metrics(State(registry): State<PluginRegistry>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        registry.render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use tower::util::ServiceExt;
+
+    const PICKUP: &str = "https://didcomm.org/messagepickup/3.0/delivery-request";
+
+    #[test]
+    fn test_claiming_routes_and_message_types() {
+        let registry = PluginRegistry::new();
+        let paths = |paths: &[&str]| paths.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        registry
+            .claim("first", &paths(&["/first", "/shared"]), &[PICKUP])
+            .unwrap();
+        // Claims of a plugin may overlap
+        registry.claim("first", &paths(&["/first"]), &[]).unwrap();
+
+        assert_eq!(
+            registry.claim("second", &paths(&["/second", "/shared"]), &[]),
+            Err(Conflict::Route(
+                "/shared".to_owned(),
+                ["first".to_owned(), "second".to_owned()]
+            ))
+        );
+        let conflict = registry.claim("third", &[], &[PICKUP]).unwrap_err();
+        assert_eq!(
+            conflict.to_string(),
+            format!("message type {PICKUP} of plugin first claimed by plugin third")
+        );
+
+        // Conflicting claims are not recorded
+        assert_eq!(registry.route_owner("/second"), None);
+        assert_eq!(registry.route_owner("/shared"), Some("first"));
+        assert_eq!(registry.message_type_owner(PICKUP), Some("first"));
+        assert_eq!(registry.count("second", PluginEvent::Conflict), 1);
+
+        registry.release("first");
+        assert_eq!(registry.route_owner("/shared"), None);
+        registry
+            .claim("second", &paths(&["/second", "/shared"]), &[])
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disclosing_claims_and_events() {
+        let registry = PluginRegistry::new();
+        registry
+            .claim("first", &["/first".to_owned()], &[PICKUP])
+            .unwrap();
+        registry.record("first", PluginEvent::Mounted);
+        registry.record("first", PluginEvent::Unmounted);
+        registry.record("first", PluginEvent::Mounted);
+
+        let get = |uri: &str| {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            registry.routes().oneshot(request)
+        };
+
+        let response = get(REGISTRY_PATH).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "routes": {"/first": "first"},
+                "messageTypes": {PICKUP: "first"},
+                "events": {"first": {"mounted": 2, "unmounted": 1}},
+            })
+        );
+
+        let response = get(METRICS_PATH).await.unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("# TYPE mediator_plugin_events_total counter\n"));
+        assert!(
+            body.contains("mediator_plugin_events_total{plugin=\"first\",event=\"mounted\"} 2\n")
+        );
+    }
+}
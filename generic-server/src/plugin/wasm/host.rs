@@ -0,0 +1,430 @@
+//! Host running message handlers compiled to WebAssembly.
+//!
+//! Handlers are untrusted. Each invocation runs in a fresh instance, with
+//! bounded memory and fuel, and may only use the narrow host API imported
+//! from the `mediator` module:
+//!
+//! - `message_len() -> i32`: length of the message being handled
+//! - `read_message(ptr, len) -> i32`: copy the message into guest memory,
+//!   returning the number of bytes copied
+//! - `write_response(ptr, len)`: set the response to return
+//! - `kv_get(key_ptr, key_len, out_ptr, out_len) -> i32`: copy the value of
+//!   a key into guest memory, returning its full length or -1 if unset
+//! - `kv_set(key_ptr, key_len, value_ptr, value_len) -> i32`: set the value
+//!   of a key, returning zero, or -1 if the entry is rejected
+//!
+//! Handlers export their `memory` and a `handle() -> i32` function returning
+//! zero on success. Key-value entries are private to each handler, whose
+//! store is capped in key and value sizes, entries and total bytes held.
+//! Entries exceeding a cap are rejected, leaving the store unchanged.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+use wasmtime::{
+    Caller, Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+/// Fuel granted to an invocation, roughly a number of instructions
+const DEFAULT_FUEL: u64 = 10_000_000;
+
+/// Memory an instance may grow to, in bytes
+const DEFAULT_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Maximum size of keys held in handlers' key-value stores
+const MAX_KV_KEY_SIZE: usize = 256;
+
+/// Maximum size of values held in handlers' key-value stores
+const MAX_KV_VALUE_SIZE: usize = 64 * 1024;
+
+/// Entries a handler may hold in its key-value store
+const DEFAULT_KV_ENTRIES: usize = 1024;
+
+/// Bytes of keys and values a handler may hold in its key-value store
+const DEFAULT_KV_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum WasmError {
+    #[error("failed to compile module: {0}")]
+    CompileError(String),
+    #[error("handler trapped: {0}")]
+    Trap(String),
+    #[error("handler exhausted its fuel")]
+    OutOfFuel,
+    #[error("handler rejected message with code {0}")]
+    Rejected(i32),
+}
+
+/// Resources granted to each handler invocation
+#[derive(Debug, Clone, Copy)]
+pub struct SandboxLimits {
+    pub fuel: u64,
+    pub memory: usize,
+    /// Entries a handler may hold in its key-value store
+    pub kv_entries: usize,
+    /// Bytes of keys and values a handler may hold in its key-value store
+    pub kv_size: usize,
+}
+
+impl Default for SandboxLimits {
+    fn default() -> Self {
+        Self {
+            fuel: DEFAULT_FUEL,
+            memory: DEFAULT_MEMORY_LIMIT,
+            kv_entries: DEFAULT_KV_ENTRIES,
+            kv_size: DEFAULT_KV_SIZE,
+        }
+    }
+}
+
+/// Compiles and runs sandboxed handlers
+#[derive(Clone)]
+pub struct WasmHost {
+    engine: Engine,
+    linker: Arc<Linker<HostState>>,
+    limits: SandboxLimits,
+}
+
+/// A message handler compiled to WebAssembly
+#[derive(Clone)]
+pub struct WasmHandler {
+    host: WasmHost,
+    module: Module,
+    kv: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+}
+
+struct HostState {
+    message: Vec<u8>,
+    response: Vec<u8>,
+    kv: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    sandbox: SandboxLimits,
+    limits: StoreLimits,
+}
+
+impl WasmHost {
+    pub fn new(limits: SandboxLimits) -> Result<Self, WasmError> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+
+        let engine =
+            Engine::new(&config).map_err(|err| WasmError::CompileError(err.to_string()))?;
+        let linker = host_api(&engine).map_err(|err| WasmError::CompileError(err.to_string()))?;
+
+        Ok(Self {
+            engine,
+            linker: Arc::new(linker),
+            limits,
+        })
+    }
+
+    /// Compile a handler from its binary or text representation
+    pub fn compile(&self, bytes: &[u8]) -> Result<WasmHandler, WasmError> {
+        let module = Module::new(&self.engine, bytes)
+            .map_err(|err| WasmError::CompileError(err.to_string()))?;
+
+        Ok(WasmHandler {
+            host: self.clone(),
+            module,
+            kv: Arc::default(),
+        })
+    }
+}
+
+impl WasmHandler {
+    /// Run the handler on a message, returning its response
+    pub fn handle(&self, message: &[u8]) -> Result<Vec<u8>, WasmError> {
+        let state = HostState {
+            message: message.to_vec(),
+            response: vec![],
+            kv: self.kv.clone(),
+            sandbox: self.host.limits,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.host.limits.memory)
+                .instances(1)
+                .build(),
+        };
+
+        let mut store = Store::new(&self.host.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store
+            .set_fuel(self.host.limits.fuel)
+            .map_err(|err| WasmError::Trap(err.to_string()))?;
+
+        let code = self
+            .host
+            .linker
+            .instantiate(&mut store, &self.module)
+            .and_then(|instance| instance.get_typed_func::<(), i32>(&mut store, "handle"))
+            .and_then(|handle| handle.call(&mut store, ()))
+            .map_err(|err| match err.downcast_ref::<wasmtime::Trap>() {
+                Some(wasmtime::Trap::OutOfFuel) => WasmError::OutOfFuel,
+                _ => WasmError::Trap(format!("{err:#}")),
+            })?;
+
+        match code {
+            0 => Ok(store.into_data().response),
+            code => Err(WasmError::Rejected(code)),
+        }
+    }
+}
+
+fn host_api(engine: &Engine) -> wasmtime::Result<Linker<HostState>> {
+    let mut linker = Linker::new(engine);
+
+    linker.func_wrap(
+        "mediator",
+        "message_len",
+        |caller: Caller<'_, HostState>| caller.data().message.len() as i32,
+    )?;
+
+    linker.func_wrap(
+        "mediator",
+        "read_message",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+            let memory = memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+
+            let count = state.message.len().min(len.max(0) as usize);
+            guest_slice_mut(data, ptr, count as i32)?.copy_from_slice(&state.message[..count]);
+
+            Ok(count as i32)
+        },
+    )?;
+
+    linker.func_wrap(
+        "mediator",
+        "write_response",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+            let memory = memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+
+            state.response = guest_slice(data, ptr, len)?.to_vec();
+
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        "mediator",
+        "kv_get",
+        |mut caller: Caller<'_, HostState>,
+         key_ptr: i32,
+         key_len: i32,
+         out_ptr: i32,
+         out_len: i32| {
+            let memory = memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+
+            let key = guest_slice(data, key_ptr, key_len)?.to_vec();
+            let Some(value) = state.kv.lock().unwrap().get(&key).cloned() else {
+                return Ok(-1);
+            };
+
+            let count = value.len().min(out_len.max(0) as usize);
+            guest_slice_mut(data, out_ptr, count as i32)?.copy_from_slice(&value[..count]);
+
+            Ok(value.len() as i32)
+        },
+    )?;
+
+    linker.func_wrap(
+        "mediator",
+        "kv_set",
+        |mut caller: Caller<'_, HostState>,
+         key_ptr: i32,
+         key_len: i32,
+         value_ptr: i32,
+         value_len: i32| {
+            let memory = memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+
+            let key = guest_slice(data, key_ptr, key_len)?;
+            let value = guest_slice(data, value_ptr, value_len)?;
+            if key.len() > MAX_KV_KEY_SIZE || value.len() > MAX_KV_VALUE_SIZE {
+                return Ok(-1);
+            }
+
+            // Replaced entries no longer count towards the caps
+            let mut kv = state.kv.lock().unwrap();
+            let replaced = kv.get(key).map(|previous| key.len() + previous.len());
+            let entries = kv.len() + usize::from(replaced.is_none());
+            let size = kv.iter().map(|(k, v)| k.len() + v.len()).sum::<usize>()
+                - replaced.unwrap_or_default()
+                + key.len()
+                + value.len();
+            if entries > state.sandbox.kv_entries || size > state.sandbox.kv_size {
+                return Ok(-1);
+            }
+
+            kv.insert(key.to_vec(), value.to_vec());
+            Ok(0)
+        },
+    )?;
+
+    Ok(linker)
+}
+
+fn memory(caller: &mut Caller<'_, HostState>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmtime::Error::msg("handler exports no memory"))
+}
+
+fn guest_range(data: &[u8], ptr: i32, len: i32) -> wasmtime::Result<std::ops::Range<usize>> {
+    let (ptr, len) = (u32::try_from(ptr)? as usize, u32::try_from(len)? as usize);
+
+    ptr.checked_add(len)
+        .filter(|end| *end <= data.len())
+        .map(|end| ptr..end)
+        .ok_or_else(|| wasmtime::Error::msg("out of bounds memory access"))
+}
+
+fn guest_slice(data: &[u8], ptr: i32, len: i32) -> wasmtime::Result<&[u8]> {
+    let range = guest_range(data, ptr, len)?;
+    Ok(&data[range])
+}
+
+fn guest_slice_mut(data: &mut [u8], ptr: i32, len: i32) -> wasmtime::Result<&mut [u8]> {
+    let range = guest_range(data, ptr, len)?;
+    Ok(&mut data[range])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Echoes messages, counting invocations in its key-value store
+    const ECHO: &str = r#"
+        (module
+            (import "mediator" "message_len" (func $message_len (result i32)))
+            (import "mediator" "read_message" (func $read_message (param i32 i32) (result i32)))
+            (import "mediator" "write_response" (func $write_response (param i32 i32)))
+            (import "mediator" "kv_get" (func $kv_get (param i32 i32 i32 i32) (result i32)))
+            (import "mediator" "kv_set" (func $kv_set (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "count")
+            (func (export "handle") (result i32)
+                (local $len i32)
+                ;; Increment counter stored at offset 16
+                (if (i32.lt_s (call $kv_get (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 4)) (i32.const 0))
+                    (then (i32.store (i32.const 16) (i32.const 0))))
+                (i32.store (i32.const 16) (i32.add (i32.load (i32.const 16)) (i32.const 1)))
+                (drop (call $kv_set (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 4)))
+                ;; Echo message read at offset 1024
+                (local.set $len (call $read_message (i32.const 1024) (call $message_len)))
+                (call $write_response (i32.const 1024) (local.get $len))
+                (i32.const 0)))
+    "#;
+
+    fn count(handler: &WasmHandler) -> u32 {
+        let kv = handler.kv.lock().unwrap();
+        u32::from_le_bytes(kv[b"count".as_slice()].as_slice().try_into().unwrap())
+    }
+
+    #[test]
+    fn test_handling_messages() {
+        let host = WasmHost::new(SandboxLimits::default()).unwrap();
+        let handler = host.compile(ECHO.as_bytes()).unwrap();
+
+        assert_eq!(handler.handle(b"hello").unwrap(), b"hello");
+        assert_eq!(handler.handle(b"").unwrap(), b"");
+        assert_eq!(count(&handler), 2);
+
+        // Key-value entries are private to each handler
+        let other = host.compile(ECHO.as_bytes()).unwrap();
+        other.handle(b"hi").unwrap();
+        assert_eq!(count(&other), 1);
+        assert_eq!(count(&handler), 2);
+    }
+
+    /// Stores an entry keyed by the message, returning whether it was set
+    const STORE: &str = r#"
+        (module
+            (import "mediator" "message_len" (func $message_len (result i32)))
+            (import "mediator" "read_message" (func $read_message (param i32 i32) (result i32)))
+            (import "mediator" "kv_set" (func $kv_set (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "handle") (result i32)
+                (local $len i32)
+                (local.set $len (call $read_message (i32.const 1024) (call $message_len)))
+                (call $kv_set (i32.const 1024) (local.get $len) (i32.const 0) (i32.const 100))))
+    "#;
+
+    #[test]
+    fn test_capping_key_value_stores() {
+        let limits = SandboxLimits {
+            kv_entries: 2,
+            kv_size: 250,
+            ..Default::default()
+        };
+        let host = WasmHost::new(limits).unwrap();
+        let handler = host.compile(STORE.as_bytes()).unwrap();
+
+        assert!(handler.handle(b"a").is_ok());
+        assert!(handler.handle(b"b").is_ok());
+
+        // Entries beyond the cap are rejected, but may be replaced
+        assert!(matches!(handler.handle(b"c"), Err(WasmError::Rejected(-1))));
+        assert!(handler.handle(b"a").is_ok());
+        assert_eq!(handler.kv.lock().unwrap().len(), 2);
+
+        // as are entries exceeding the total size held
+        let handler = host.compile(STORE.as_bytes()).unwrap();
+        assert!(handler.handle(&[b'k'; 100]).is_ok());
+        assert!(matches!(
+            handler.handle(&[b'l'; 100]),
+            Err(WasmError::Rejected(-1))
+        ));
+
+        // or keys too long
+        let key = [b'k'; MAX_KV_KEY_SIZE + 1];
+        assert!(matches!(handler.handle(&key), Err(WasmError::Rejected(-1))));
+        assert_eq!(handler.kv.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_rejecting_messages() {
+        let host = WasmHost::new(SandboxLimits::default()).unwrap();
+        let handler = host
+            .compile(br#"(module (memory (export "memory") 1) (func (export "handle") (result i32) (i32.const 7)))"#)
+            .unwrap();
+
+        assert!(matches!(
+            handler.handle(b"hello"),
+            Err(WasmError::Rejected(7))
+        ));
+    }
+
+    #[test]
+    fn test_sandbox_limits() {
+        let host = WasmHost::new(SandboxLimits::default()).unwrap();
+
+        let looping = host
+            .compile(br#"(module (memory (export "memory") 1) (func (export "handle") (result i32) (loop (br 0)) (i32.const 0)))"#)
+            .unwrap();
+        assert!(matches!(looping.handle(b""), Err(WasmError::OutOfFuel)));
+
+        let greedy = host
+            .compile(br#"(module (memory (export "memory") 1) (func (export "handle") (result i32) (if (i32.lt_s (memory.grow (i32.const 1024)) (i32.const 0)) (then unreachable)) (i32.const 0)))"#)
+            .unwrap();
+        assert!(matches!(greedy.handle(b""), Err(WasmError::Trap(_))));
+
+        let prying = host
+            .compile(br#"(module (import "mediator" "write_response" (func $write_response (param i32 i32))) (memory (export "memory") 1) (func (export "handle") (result i32) (call $write_response (i32.const 65000) (i32.const 1000)) (i32.const 0)))"#)
+            .unwrap();
+        assert!(
+            matches!(prying.handle(b""), Err(WasmError::Trap(err)) if err.contains("out of bounds"))
+        );
+
+        let foreign = host.compile(br#"(module (import "wasi" "fd_write" (func (param i32))) (func (export "handle") (result i32) (i32.const 0)))"#);
+        assert!(foreign.is_ok_and(|handler| matches!(handler.handle(b""), Err(WasmError::Trap(_)))));
+
+        assert!(matches!(
+            host.compile(b"not wasm"),
+            Err(WasmError::CompileError(_))
+        ));
+    }
+}
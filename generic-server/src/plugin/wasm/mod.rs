@@ -0,0 +1,95 @@
+//! Sandboxed message handlers compiled to WebAssembly.
+//!
+//! Community protocol handlers can be deployed without being trusted with
+//! the mediator's process: each `*.wasm` (or `*.wat`) module found in the
+//! directory at `WASM_HANDLERS_DIRPATH` is served at `POST /wasm/<name>`,
+//! where `<name>` is the module's file stem. See [`host`] for the API
+//! handlers are given.
+//!
+//! Submissions are idempotent: retries carrying the same `Idempotency-Key`
+//! header, or the same plaintext message id, get the recorded response.
+
+pub mod host;
+mod web;
+
+use std::{collections::HashMap, path::Path, sync::Mutex};
+
+use axum::Router;
+use did_endpoint::util::idempotency::{self, IdempotencyCache};
+use server_plugin::{Plugin, PluginError};
+use utoipa::{openapi::OpenApi as OpenApiDoc, OpenApi};
+
+use host::{SandboxLimits, WasmHandler, WasmHost};
+
+#[derive(Default)]
+pub struct WasmPlugin {
+    handlers: Mutex<HashMap<String, WasmHandler>>,
+}
+
+impl Plugin for WasmPlugin {
+    fn name(&self) -> &'static str {
+        "wasm"
+    }
+
+    fn mount(&self) -> Result<(), PluginError> {
+        let dirpath = server_plugin::reload::var("WASM_HANDLERS_DIRPATH").ok_or_else(|| {
+            tracing::error!("WASM_HANDLERS_DIRPATH env variable required");
+            PluginError::InitError
+        })?;
+
+        let handlers = load_handlers(Path::new(&dirpath)).map_err(|err| {
+            tracing::error!("failed to load WASM handlers: {err}");
+            PluginError::InitError
+        })?;
+
+        tracing::info!("loaded {} WASM handlers", handlers.len());
+        *self.handlers.lock().unwrap() = handlers;
+
+        Ok(())
+    }
+
+    fn unmount(&self) -> Result<(), PluginError> {
+        self.handlers.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn routes(&self) -> Router {
+        idempotency::idempotent(
+            web::routes(self.handlers.lock().unwrap().clone()),
+            IdempotencyCache::from_env(),
+        )
+    }
+
+    fn openapi(&self) -> Option<OpenApiDoc> {
+        Some(web::ApiDoc::openapi())
+    }
+}
+
+/// Compile all handlers in a directory, by file stem
+fn load_handlers(dirpath: &Path) -> Result<HashMap<String, WasmHandler>, String> {
+    let host = WasmHost::new(SandboxLimits::default()).map_err(|err| err.to_string())?;
+
+    let entries = std::fs::read_dir(dirpath).map_err(|err| err.to_string())?;
+    let mut handlers = HashMap::new();
+
+    for entry in entries {
+        let path = entry.map_err(|err| err.to_string())?.path();
+
+        let is_module = path
+            .extension()
+            .is_some_and(|ext| ext == "wasm" || ext == "wat");
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        if is_module {
+            let bytes = std::fs::read(&path).map_err(|err| err.to_string())?;
+            let handler = host
+                .compile(&bytes)
+                .map_err(|err| format!("{}: {err}", path.display()))?;
+            handlers.insert(name.to_owned(), handler);
+        }
+    }
+
+    Ok(handlers)
+}
@@ -0,0 +1,126 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::post,
+    Router,
+};
+use std::{collections::HashMap, sync::Arc};
+use utoipa::OpenApi;
+
+use super::host::{WasmError, WasmHandler};
+
+/// OpenAPI description of the route of WASM handlers
+#[derive(OpenApi)]
+#[openapi(paths(handle), tags((name = "wasm", description = "Sandboxed message handlers")))]
+pub struct ApiDoc;
+
+pub fn routes(handlers: HashMap<String, WasmHandler>) -> Router {
+    Router::new() //
+        .route("/wasm/:name", post(handle))
+        .with_state(Arc::new(handlers))
+}
+
+/// Submits a message to a WASM handler, returning its response.
+/// Submissions are idempotent.
+#[utoipa::path(
+    post,
+    path = "/wasm/{name}",
+    tag = "wasm",
+    params(
+        ("name" = String, Path, description = "File stem of the handler's module"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Key identifying retries of a submission"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "Response of the handler", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 400, description = "Message rejected by the handler"),
+        (status = 404, description = "Unknown handler"),
+        (status = 409, description = "Submission with the same key in progress"),
+        (status = 422, description = "Key reused for another submission"),
+    )
+)]
+async fn handle(
+    State(handlers): State<Arc<HashMap<String, WasmHandler>>>,
+    Path(name): Path<String>,
+    message: Bytes,
+) -> Result<Vec<u8>, StatusCode> {
+    let handler = handlers.get(&name).cloned().ok_or(StatusCode::NOT_FOUND)?;
+
+    // Handlers are compute-bound, keep them off the async workers
+    let result = tokio::task::spawn_blocking(move || handler.handle(&message))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    result.map_err(|err| {
+        tracing::warn!("WASM handler {name} failed: {err}");
+        match err {
+            WasmError::Rejected(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugin::wasm::host::{SandboxLimits, WasmHost};
+
+    use axum::{body::Body, http::Request};
+    use tower::util::ServiceExt;
+
+    const UPPERCASE: &str = r#"
+        (module
+            (import "mediator" "read_message" (func $read_message (param i32 i32) (result i32)))
+            (import "mediator" "write_response" (func $write_response (param i32 i32)))
+            (memory (export "memory") 1)
+            (func (export "handle") (result i32)
+                (local $len i32) (local $i i32) (local $c i32)
+                (local.set $len (call $read_message (i32.const 0) (i32.const 4096)))
+                (block $done
+                    (loop $next
+                        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+                        (local.set $c (i32.load8_u (local.get $i)))
+                        (if (i32.and (i32.ge_u (local.get $c) (i32.const 97)) (i32.le_u (local.get $c) (i32.const 122)))
+                            (then (i32.store8 (local.get $i) (i32.sub (local.get $c) (i32.const 32)))))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br $next)))
+                (call $write_response (i32.const 0) (local.get $len))
+                (i32.const 0)))
+    "#;
+
+    #[tokio::test]
+    async fn test_routing_messages_to_handlers() {
+        let host = WasmHost::new(SandboxLimits::default()).unwrap();
+        let handlers = [
+            ("uppercase".to_owned(), host.compile(UPPERCASE.as_bytes()).unwrap()),
+            (
+                "reject".to_owned(),
+                host.compile(br#"(module (memory (export "memory") 1) (func (export "handle") (result i32) (i32.const 1)))"#)
+                    .unwrap(),
+            ),
+        ];
+        let app = routes(handlers.into_iter().collect());
+
+        let request = |uri: &str| {
+            Request::post(uri)
+                .body(Body::from("hello didcomm"))
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request("/wasm/uppercase"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"HELLO DIDCOMM");
+
+        let response = app.clone().oneshot(request("/wasm/reject")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app.oneshot(request("/wasm/unknown")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
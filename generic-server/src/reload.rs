@@ -0,0 +1,241 @@
+//! Reloading of configuration mounted from files.
+//!
+//! In Kubernetes, ConfigMaps and Secrets mounted as volumes expose each of
+//! their keys as a file, which the kubelet updates in place when they
+//! change. Directories listed in `CONFIG_WATCH_DIRPATHS` are read as such,
+//! each file holding the value of the setting it is named after, e.g.
+//! `LOG_LEVEL`. Settings found there at startup are recorded before
+//! plugins are mounted, overriding the environment for readers of
+//! [`server_plugin::reload::var`].
+//!
+//! The directories are then polled every `CONFIG_WATCH_INTERVAL` seconds.
+//! Changed settings registered as [`ReloadableSettings`] are revalidated
+//! and applied, while changes to other settings are only logged, as they
+//! take effect after a restart.
+
+use server_plugin::{reload::ReloadableSettings, tasks::BackgroundTasks};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Interval between polls of watched directories, by default
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Outcome of a poll, listing changed settings by name
+#[derive(Debug, Default, PartialEq)]
+pub struct ReloadReport {
+    /// Settings applied at runtime
+    pub applied: Vec<String>,
+    /// Settings whose new value failed validation, leaving the previous one
+    /// in effect
+    pub rejected: Vec<String>,
+    /// Settings taking effect after a restart
+    pub restart_required: Vec<String>,
+}
+
+/// Watcher of directories of mounted settings
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    dirpaths: Vec<PathBuf>,
+    interval: Duration,
+    current: BTreeMap<String, String>,
+}
+
+impl ConfigWatcher {
+    /// Watch directories, reading the settings they currently hold
+    pub fn new(dirpaths: Vec<PathBuf>) -> Self {
+        let current = read_settings(&dirpaths);
+        Self {
+            dirpaths,
+            interval: DEFAULT_INTERVAL,
+            current,
+        }
+    }
+
+    /// Watcher configured by the process environment, if any directory is
+    pub fn from_env() -> Option<Self> {
+        let dirpaths: Vec<PathBuf> = std::env::var("CONFIG_WATCH_DIRPATHS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|dirpath| !dirpath.is_empty())
+            .map(PathBuf::from)
+            .collect();
+        if dirpaths.is_empty() {
+            return None;
+        }
+
+        let interval = std::env::var("CONFIG_WATCH_INTERVAL")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_INTERVAL);
+
+        Some(Self::new(dirpaths).interval(interval))
+    }
+
+    pub fn interval(self, interval: Duration) -> Self {
+        Self { interval, ..self }
+    }
+
+    /// Settings last read, by name
+    pub fn settings(&self) -> &BTreeMap<String, String> {
+        &self.current
+    }
+
+    /// Record settings, and apply the reloadable ones already registered
+    pub fn export(&self, settings: &ReloadableSettings) {
+        for (key, value) in &self.current {
+            settings.record(key, value);
+            if let Some(Err(reason)) = settings.apply(key, value) {
+                tracing::error!("invalid setting {key}: {reason}");
+            }
+        }
+    }
+
+    /// Read watched directories again, and apply changed settings
+    pub fn poll(&mut self, settings: &ReloadableSettings) -> ReloadReport {
+        let latest = read_settings(&self.dirpaths);
+
+        let keys: BTreeSet<_> = self.current.keys().chain(latest.keys()).cloned().collect();
+        let mut report = ReloadReport::default();
+        for key in keys {
+            let value = latest.get(&key);
+            if value == self.current.get(&key) {
+                continue;
+            }
+
+            // Removed settings cannot be applied, as defaults are only
+            // known to those reading them at startup
+            let outcome = value.and_then(|value| settings.apply(&key, value));
+            match outcome {
+                Some(Ok(())) => {
+                    tracing::info!("reloaded setting {key}");
+                    report.applied.push(key);
+                }
+                Some(Err(reason)) => {
+                    tracing::error!("rejected new value of setting {key}: {reason}");
+                    report.rejected.push(key);
+                }
+                None => {
+                    tracing::warn!("setting {key} changed, and requires a restart to apply");
+                    report.restart_required.push(key);
+                }
+            }
+        }
+
+        // Each change is reported once, even if rejected
+        self.current = latest;
+        report
+    }
+
+    /// Poll watched directories on a background task
+    pub fn watch(mut self, settings: ReloadableSettings, tasks: &BackgroundTasks) {
+        tasks.every("config-watcher", self.interval, move || {
+            self.poll(&settings);
+        });
+    }
+}
+
+/// Read settings from regular files of directories, ignoring hidden
+/// entries such as the `..data` links maintained by the kubelet. Values
+/// of files found in several directories are taken from the last one.
+fn read_settings(dirpaths: &[PathBuf]) -> BTreeMap<String, String> {
+    let mut settings = BTreeMap::new();
+    for dirpath in dirpaths {
+        let entries = match std::fs::read_dir(dirpath) {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::warn!("failed to read settings from {}: {err}", dirpath.display());
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let Ok(key) = entry.file_name().into_string() else {
+                continue;
+            };
+
+            // Metadata of symlinks is the one of their targets
+            let path = entry.path();
+            let is_file = std::fs::metadata(&path).is_ok_and(|meta| meta.is_file());
+            if key.starts_with('.') || !is_file {
+                continue;
+            }
+
+            match std::fs::read_to_string(&path) {
+                Ok(value) => {
+                    settings.insert(key, value.trim_end().to_owned());
+                }
+                Err(err) => tracing::warn!("failed to read setting {key}: {err}"),
+            }
+        }
+    }
+
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server_plugin::reload::var;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_reloading_changed_settings() {
+        let dir = std::env::temp_dir().join(format!("config-watch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("..data")).unwrap();
+        std::fs::write(dir.join("CONFIG_WATCH_TEST_LEVEL"), "info\n").unwrap();
+        std::fs::write(dir.join("CONFIG_WATCH_TEST_PORT"), "3000").unwrap();
+
+        let level = Arc::new(Mutex::new(String::new()));
+        let settings = ReloadableSettings::new();
+        let applied = level.clone();
+        settings.register("CONFIG_WATCH_TEST_LEVEL", move |value| match value {
+            "debug" | "info" | "warn" => {
+                *applied.lock().unwrap() = value.to_owned();
+                Ok(())
+            }
+            _ => Err(format!("unknown level {value}")),
+        });
+
+        let mut watcher = ConfigWatcher::new(vec![dir.clone()]);
+        assert_eq!(watcher.settings().len(), 2);
+
+        watcher.export(&settings);
+        assert_eq!(*level.lock().unwrap(), "info");
+        assert_eq!(var("CONFIG_WATCH_TEST_PORT").unwrap(), "3000");
+        assert!(std::env::var("CONFIG_WATCH_TEST_PORT").is_err());
+
+        assert_eq!(watcher.poll(&settings), ReloadReport::default());
+
+        std::fs::write(dir.join("CONFIG_WATCH_TEST_LEVEL"), "warn").unwrap();
+        std::fs::write(dir.join("CONFIG_WATCH_TEST_PORT"), "3001").unwrap();
+        assert_eq!(
+            watcher.poll(&settings),
+            ReloadReport {
+                applied: vec!["CONFIG_WATCH_TEST_LEVEL".to_owned()],
+                rejected: vec![],
+                restart_required: vec!["CONFIG_WATCH_TEST_PORT".to_owned()],
+            }
+        );
+        assert_eq!(*level.lock().unwrap(), "warn");
+        assert_eq!(var("CONFIG_WATCH_TEST_LEVEL").unwrap(), "warn");
+        assert_eq!(var("CONFIG_WATCH_TEST_PORT").unwrap(), "3000");
+
+        // Invalid values are rejected once, keeping the previous one
+        std::fs::write(dir.join("CONFIG_WATCH_TEST_LEVEL"), "loud").unwrap();
+        assert_eq!(
+            watcher.poll(&settings).rejected,
+            vec!["CONFIG_WATCH_TEST_LEVEL"]
+        );
+        assert_eq!(watcher.poll(&settings), ReloadReport::default());
+        assert_eq!(*level.lock().unwrap(), "warn");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
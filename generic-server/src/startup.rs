@@ -0,0 +1,225 @@
+//! Readiness of the mediator, gated on its startup.
+//!
+//! Startup runs in stages: migrating persisted data, mounting plugins,
+//! then self-testing them, e.g. checking that the mediator controls the
+//! keys it advertises. Each stage is timed and logged. `/health/ready`
+//! answers with a service unavailable status until all stages completed
+//! successfully, so that orchestrators hold traffic back meanwhile.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json},
+    routing::get,
+    Router,
+};
+use serde_json::{json, Value};
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use utoipa::OpenApi;
+
+/// Path readiness is reported at
+pub const READY_PATH: &str = "/health/ready";
+
+/// OpenAPI description of the readiness route
+#[derive(OpenApi)]
+#[openapi(paths(ready))]
+pub struct ReadinessApiDoc;
+
+/// Outcome of a startup stage
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageReport {
+    pub name: String,
+    pub elapsed_ms: u64,
+
+    /// Reason the stage failed, if it did
+    pub error: Option<String>,
+}
+
+/// Readiness of the mediator. Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct Readiness {
+    state: Arc<Mutex<ReadinessState>>,
+}
+
+#[derive(Debug)]
+struct ReadinessState {
+    started: Instant,
+    ready: bool,
+    stages: Vec<StageReport>,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        let state = ReadinessState {
+            started: Instant::now(),
+            ready: false,
+            stages: vec![],
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Run a startup stage, recording how long it took and whether it
+    /// succeeded
+    pub fn stage<F>(&self, name: &str, run: F) -> bool
+    where
+        F: FnOnce() -> Result<(), String>,
+    {
+        let started = Instant::now();
+        let outcome = run();
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        let succeeded = outcome.is_ok();
+
+        match &outcome {
+            Ok(()) => tracing::info!("startup stage {name} completed in {elapsed_ms} ms"),
+            Err(err) => {
+                tracing::error!("startup stage {name} failed after {elapsed_ms} ms: {err}")
+            }
+        }
+
+        let report = StageReport {
+            name: name.to_owned(),
+            elapsed_ms,
+            error: outcome.err(),
+        };
+        self.state.lock().unwrap().stages.push(report);
+
+        succeeded
+    }
+
+    /// Flag the mediator as ready, unless a stage failed
+    pub fn complete(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.ready = state.stages.iter().all(|stage| stage.error.is_none());
+
+        let elapsed_ms = state.started.elapsed().as_millis();
+        if state.ready {
+            tracing::info!("mediator ready after {elapsed_ms} ms");
+        } else {
+            tracing::error!("mediator not ready after failed startup stages");
+        }
+
+        state.ready
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state.lock().unwrap().ready
+    }
+
+    /// Stages run so far
+    pub fn stages(&self) -> Vec<StageReport> {
+        self.state.lock().unwrap().stages.clone()
+    }
+
+    /// Route reporting readiness
+    pub fn routes(&self) -> Router {
+        Router::new()
+            .route(READY_PATH, get(ready))
+            .with_state(self.clone())
+    }
+}
+
+/// Reports whether the mediator is ready to serve, along with the startup
+/// stages run so far
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "generic-server",
+    responses(
+        (status = 200, description = "Startup completed", body = Value),
+        (status = 503, description = "Startup in progress, or failed", body = Value),
+    )
+)]
+async fn ready(State(readiness): State<Readiness>) -> impl IntoResponse {
+    let ready = readiness.is_ready();
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let stages: Vec<Value> = readiness
+        .stages()
+        .into_iter()
+        .map(|stage| {
+            let mut value = json!({
+                "name": stage.name,
+                "elapsedMs": stage.elapsed_ms,
+            });
+            if let Some(error) = stage.error {
+                value["error"] = Value::String(error);
+            }
+            value
+        })
+        .collect();
+
+    let body = json!({
+        "ready": ready,
+        "stages": stages,
+    });
+
+    (status, Json(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use tower::util::ServiceExt;
+
+    async fn report(readiness: &Readiness) -> (StatusCode, Value) {
+        let response = readiness
+            .routes()
+            .oneshot(
+                Request::builder()
+                    .uri(READY_PATH)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_gating_readiness_on_stages() {
+        let readiness = Readiness::new();
+        assert!(readiness.stage("migrations", || Ok(())));
+
+        let (status, body) = report(&readiness).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["stages"][0]["name"], "migrations");
+
+        assert!(readiness.complete());
+        let (status, body) = report(&readiness).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+    }
+
+    #[tokio::test]
+    async fn test_failed_stages_prevent_readiness() {
+        let readiness = Readiness::new();
+        assert!(!readiness.stage("plugins", || Err("failed plugins: faulty".to_owned())));
+        assert!(readiness.stage("self-test", || Ok(())));
+        assert!(!readiness.complete());
+
+        let (status, body) = report(&readiness).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["stages"][0]["error"], "failed plugins: faulty");
+        assert!(body["stages"][1].get("error").is_none());
+    }
+}
@@ -0,0 +1,520 @@
+//! Versioning of the administrative API.
+//!
+//! Plugins export their administrative routes under `/admin`. Operators
+//! address them at an explicit version, either with a path prefix, e.g.
+//! `/admin/v1/apikeys`, or with a versioned media type in the `Accept`
+//! header, e.g. `application/vnd.mediator.admin.v1+json`. Unversioned
+//! requests are served at the first version, the one admin payloads had
+//! before versioning, and flagged as deprecated.
+//!
+//! Handlers only ever speak the current version. When an admin payload
+//! changes in a breaking way, a [`Shim`] translates requests made at the
+//! previous version up to the new one, and responses back down, so that
+//! tooling built against older versions keeps working while they remain
+//! supported.
+
+use axum::{
+    body::{self, Body, Full},
+    extract::{OriginalUri, State},
+    http::{
+        header::{self, HeaderValue},
+        uri::PathAndQuery,
+        Request, StatusCode, Uri,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use serde_json::Value;
+use std::{collections::HashMap, sync::Arc};
+use tower::Layer;
+use utoipa::openapi::OpenApi;
+
+/// Path under which plugins export administrative routes
+pub const ADMIN_PREFIX: &str = "/admin";
+
+/// Version served to unversioned requests
+pub const FIRST_VERSION: u16 = 1;
+
+/// Prefix of versioned media types, followed by the version and `+json`
+const MEDIA_TYPE_PREFIX: &str = "application/vnd.mediator.admin.v";
+
+/// Translates admin payloads of some version from and to the next version
+#[derive(Clone)]
+pub struct Shim {
+    version: u16,
+    path: String,
+    request: Option<fn(Value) -> Value>,
+    response: Option<fn(Value) -> Value>,
+}
+
+impl Shim {
+    /// Shim for payloads of `version` on routes under `path`, an
+    /// unversioned admin path such as `/admin/apikeys`
+    pub fn new(version: u16, path: &str) -> Self {
+        Self {
+            version,
+            path: path.to_owned(),
+            request: None,
+            response: None,
+        }
+    }
+
+    /// Upgrades JSON request bodies to the next version
+    pub fn request(self, upgrade: fn(Value) -> Value) -> Self {
+        Self {
+            request: Some(upgrade),
+            ..self
+        }
+    }
+
+    /// Downgrades JSON response bodies from the next version
+    pub fn response(self, downgrade: fn(Value) -> Value) -> Self {
+        Self {
+            response: Some(downgrade),
+            ..self
+        }
+    }
+
+    fn applies_to(&self, version: u16, path: &str) -> bool {
+        self.version >= version
+            && path
+                .strip_prefix(self.path.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// Versions of the administrative API served, and how older versions are
+/// translated to the current one
+#[derive(Clone)]
+pub struct AdminVersioning {
+    current: u16,
+    oldest: u16,
+    sunsets: HashMap<u16, String>,
+    shims: Arc<Vec<Shim>>,
+}
+
+impl Default for AdminVersioning {
+    fn default() -> Self {
+        Self::new(FIRST_VERSION)
+    }
+}
+
+/// Version a request is made at, and the unversioned path it targets
+#[derive(Debug, PartialEq)]
+struct Negotiated {
+    version: u16,
+    path: String,
+    explicit: bool,
+}
+
+impl AdminVersioning {
+    /// Serves all versions up to `current`, which handlers speak
+    pub fn new(current: u16) -> Self {
+        Self {
+            current,
+            oldest: FIRST_VERSION,
+            sunsets: HashMap::new(),
+            shims: Arc::new(vec![]),
+        }
+    }
+
+    /// Retires versions older than `oldest`, which are then gone
+    pub fn oldest(self, oldest: u16) -> Self {
+        Self { oldest, ..self }
+    }
+
+    /// Announces the date a version will be retired on, as an HTTP-date
+    pub fn sunset(mut self, version: u16, date: &str) -> Self {
+        self.sunsets.insert(version, date.to_owned());
+        self
+    }
+
+    /// Registers a shim translating payloads of an older version
+    pub fn shim(mut self, shim: Shim) -> Self {
+        Arc::make_mut(&mut self.shims).push(shim);
+        self
+    }
+
+    /// Negotiates the version of admin requests before they are routed
+    pub fn wrap(&self, routes: Router) -> Router {
+        let versioned = middleware::from_fn_with_state(self.clone(), negotiate_version);
+        Router::new().fallback_service(versioned.layer(routes))
+    }
+
+    /// Describes admin routes at their current versioned paths
+    pub fn describe(&self, doc: &mut OpenApi) {
+        let paths = std::mem::take(&mut doc.paths.paths);
+        doc.paths.paths = paths
+            .into_iter()
+            .map(|(path, item)| match admin_subpath(&path) {
+                Some(rest) => (format!("{ADMIN_PREFIX}/v{}{rest}", self.current), item),
+                None => (path, item),
+            })
+            .collect();
+    }
+
+    fn supports(&self, version: u16) -> Result<(), StatusCode> {
+        if version > self.current || version < FIRST_VERSION {
+            Err(StatusCode::NOT_FOUND)
+        } else if version < self.oldest {
+            Err(StatusCode::GONE)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn negotiate(
+        &self,
+        path: &str,
+        accept: Option<&str>,
+    ) -> Result<Option<Negotiated>, StatusCode> {
+        let Some(rest) = admin_subpath(path) else {
+            return Ok(None);
+        };
+
+        // Versions in paths take precedence over media types
+        if let Some((version, rest)) = path_version(rest) {
+            self.supports(version)?;
+            let path = format!("{ADMIN_PREFIX}{rest}");
+            return Ok(Some(Negotiated {
+                version,
+                path,
+                explicit: true,
+            }));
+        }
+
+        let negotiated = match accept.and_then(media_type_version) {
+            Some(version) => {
+                self.supports(version)
+                    .map_err(|_| StatusCode::NOT_ACCEPTABLE)?;
+                Negotiated {
+                    version,
+                    path: path.to_owned(),
+                    explicit: true,
+                }
+            }
+            None => {
+                self.supports(FIRST_VERSION)?;
+                Negotiated {
+                    version: FIRST_VERSION,
+                    path: path.to_owned(),
+                    explicit: false,
+                }
+            }
+        };
+
+        Ok(Some(negotiated))
+    }
+
+    /// Shims translating payloads between a version and the current one,
+    /// from the oldest version up
+    fn shims(&self, version: u16, path: &str) -> Vec<&Shim> {
+        let mut shims: Vec<_> = self
+            .shims
+            .iter()
+            .filter(|shim| shim.version < self.current && shim.applies_to(version, path))
+            .collect();
+        shims.sort_by_key(|shim| shim.version);
+        shims
+    }
+}
+
+async fn negotiate_version(
+    State(versioning): State<AdminVersioning>,
+    mut request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let accept = request.headers().get(header::ACCEPT);
+    let accept = accept.and_then(|v| v.to_str().ok());
+    let negotiated = match versioning.negotiate(request.uri().path(), accept) {
+        Ok(Some(negotiated)) => negotiated,
+        Ok(None) => return next.run(request).await,
+        Err(status) => return status.into_response(),
+    };
+
+    // Prefix the mediator is nested under, if any, for successor links
+    let prefix = request
+        .extensions()
+        .get::<OriginalUri>()
+        .and_then(|original| {
+            let path = original.path().strip_suffix(request.uri().path())?;
+            Some(path.to_owned())
+        })
+        .unwrap_or_default();
+
+    if let Some(uri) = with_path(request.uri(), &negotiated.path) {
+        *request.uri_mut() = uri;
+    }
+
+    let shims = versioning.shims(negotiated.version, &negotiated.path);
+
+    // Upgrade requests from the oldest version up
+    let upgrades: Vec<_> = shims.iter().filter_map(|shim| shim.request).collect();
+    if !upgrades.is_empty() {
+        let (mut parts, body) = request.into_parts();
+        let body = match translate(body, &upgrades).await {
+            Ok(body) => body,
+            Err(status) => return status.into_response(),
+        };
+        parts.headers.remove(header::CONTENT_LENGTH);
+        request = Request::from_parts(parts, Body::from(body));
+    }
+
+    let mut response = next.run(request).await;
+
+    // and downgrade responses from the current version down
+    let downgrades: Vec<_> = shims
+        .iter()
+        .rev()
+        .filter_map(|shim| shim.response)
+        .collect();
+    if !downgrades.is_empty() && is_json(&response) {
+        let (mut parts, body) = response.into_parts();
+        let body = match translate(body, &downgrades).await {
+            Ok(body) => body,
+            Err(status) => return status.into_response(),
+        };
+        parts.headers.remove(header::CONTENT_LENGTH);
+        response = Response::from_parts(parts, body::boxed(Full::from(body)));
+    }
+
+    let headers = response.headers_mut();
+    if !negotiated.explicit || negotiated.version < versioning.current {
+        headers.insert("deprecation", HeaderValue::from_static("true"));
+
+        let successor = format!(
+            "<{prefix}{ADMIN_PREFIX}/v{}{}>; rel=\"successor-version\"",
+            versioning.current,
+            admin_subpath(&negotiated.path).unwrap_or_default()
+        );
+        if let Ok(link) = HeaderValue::from_str(&successor) {
+            headers.insert(header::LINK, link);
+        }
+    }
+    if let Some(date) = versioning.sunsets.get(&negotiated.version) {
+        if let Ok(date) = HeaderValue::from_str(date) {
+            headers.insert("sunset", date);
+        }
+    }
+
+    response
+}
+
+/// Applies translations to a JSON body, leaving empty bodies untouched
+async fn translate<B>(body: B, translations: &[fn(Value) -> Value]) -> Result<Vec<u8>, StatusCode>
+where
+    B: axum::body::HttpBody,
+{
+    let bytes = hyper::body::to_bytes(body)
+        .await
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    if bytes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut value: Value = serde_json::from_slice(&bytes).map_err(|_| StatusCode::BAD_REQUEST)?;
+    for translate in translations {
+        value = translate(value);
+    }
+
+    serde_json::to_vec(&value).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn is_json(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+/// Part of an admin path following the admin prefix, if any
+fn admin_subpath(path: &str) -> Option<&str> {
+    path.strip_prefix(ADMIN_PREFIX)
+        .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Version of a path such as `/v1/apikeys`, and the remaining `/apikeys`
+fn path_version(rest: &str) -> Option<(u16, &str)> {
+    let rest = rest.strip_prefix("/v")?;
+    let end = rest.find('/').unwrap_or(rest.len());
+    let version = rest[..end].parse().ok()?;
+
+    Some((version, &rest[end..]))
+}
+
+/// First version requested among the media types of an `Accept` header
+fn media_type_version(accept: &str) -> Option<u16> {
+    accept.split(',').find_map(|media_type| {
+        let media_type = media_type.split(';').next()?.trim();
+        media_type
+            .strip_prefix(MEDIA_TYPE_PREFIX)?
+            .strip_suffix("+json")?
+            .parse()
+            .ok()
+    })
+}
+
+fn with_path(uri: &Uri, path: &str) -> Option<Uri> {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_owned(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Json};
+    use serde_json::json;
+    use tower::util::ServiceExt;
+
+    fn routes() -> Router {
+        Router::new()
+            .route(
+                "/admin/keys",
+                get(|| async { Json(json!({"keys": ["a"]})) }),
+            )
+            .route("/health", get(|| async { "ok" }))
+    }
+
+    async fn send(app: &Router, uri: &str, accept: Option<&str>) -> Response {
+        let mut request = Request::builder().uri(uri);
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+
+        app.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn json(response: Response) -> Value {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn can_negotiate_versions() {
+        let versioning = AdminVersioning::new(2);
+        let negotiated = |path, accept| versioning.negotiate(path, accept);
+
+        assert_eq!(negotiated("/health", None), Ok(None));
+        assert_eq!(negotiated("/administrator", None), Ok(None));
+        assert_eq!(
+            negotiated(
+                "/admin/v2/keys",
+                Some("application/vnd.mediator.admin.v1+json")
+            ),
+            Ok(Some(Negotiated {
+                version: 2,
+                path: "/admin/keys".to_owned(),
+                explicit: true
+            }))
+        );
+        assert_eq!(
+            negotiated(
+                "/admin/keys",
+                Some("text/html, application/vnd.mediator.admin.v2+json")
+            ),
+            Ok(Some(Negotiated {
+                version: 2,
+                path: "/admin/keys".to_owned(),
+                explicit: true
+            }))
+        );
+        assert_eq!(
+            negotiated("/admin/keys", Some("application/json")),
+            Ok(Some(Negotiated {
+                version: 1,
+                path: "/admin/keys".to_owned(),
+                explicit: false
+            }))
+        );
+
+        assert_eq!(
+            negotiated("/admin/v3/keys", None),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            negotiated(
+                "/admin/keys",
+                Some("application/vnd.mediator.admin.v3+json")
+            ),
+            Err(StatusCode::NOT_ACCEPTABLE)
+        );
+
+        let versioning = versioning.oldest(2);
+        assert_eq!(
+            versioning.negotiate("/admin/v1/keys", None),
+            Err(StatusCode::GONE)
+        );
+        assert_eq!(
+            versioning.negotiate("/admin/keys", None),
+            Err(StatusCode::GONE)
+        );
+    }
+
+    #[tokio::test]
+    async fn can_route_versioned_requests() {
+        let app = AdminVersioning::default().wrap(routes());
+
+        let response = send(&app, "/admin/v1/keys", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("deprecation").is_none());
+
+        let response = send(&app, "/admin/keys", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["deprecation"], "true");
+        assert_eq!(
+            response.headers()[header::LINK],
+            "</admin/v1/keys>; rel=\"successor-version\""
+        );
+
+        let response = send(&app, "/admin/v2/keys", None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = send(&app, "/health", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("deprecation").is_none());
+    }
+
+    #[tokio::test]
+    async fn can_shim_older_versions() {
+        // Keys were listed bare in version 1, then as items in version 2
+        let app = AdminVersioning::new(3)
+            .sunset(1, "Sat, 01 May 2027 00:00:00 GMT")
+            .shim(Shim::new(1, "/admin/keys").response(|mut value| value["items"].take()))
+            .shim(
+                Shim::new(2, "/admin/keys")
+                    .response(|mut value| json!({"items": value["keys"].take()})),
+            )
+            .wrap(routes());
+
+        let response = send(&app, "/admin/v3/keys", None).await;
+        assert!(response.headers().get("deprecation").is_none());
+        assert_eq!(json(response).await, json!({"keys": ["a"]}));
+
+        let response = send(&app, "/admin/v2/keys", None).await;
+        assert_eq!(response.headers()["deprecation"], "true");
+        assert_eq!(json(response).await, json!({"items": ["a"]}));
+
+        let response = send(
+            &app,
+            "/admin/keys",
+            Some("application/vnd.mediator.admin.v1+json"),
+        )
+        .await;
+        assert_eq!(
+            response.headers()["sunset"],
+            "Sat, 01 May 2027 00:00:00 GMT"
+        );
+        assert_eq!(json(response).await, json!(["a"]));
+    }
+}
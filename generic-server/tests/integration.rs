@@ -0,0 +1,204 @@
+//! End-to-end tests of the assembled mediator over real HTTP.
+//!
+//! They boot the server with all plugins on an ephemeral port and a scratch
+//! storage directory, and are only built with the `integration` feature:
+//!
+//! ```sh
+//! cargo test -p generic-server --features integration --test integration
+//! ```
+#![cfg(feature = "integration")]
+
+use axum::Server;
+use did_endpoint::util::{
+    apikeys::{ApiKeyScope, ApiKeyStore, API_KEY_HEADER},
+    filesystem::StdFileSystem,
+};
+use generic_server::MediatorBuilder;
+use hyper::{body, Body, Client, Method, Request, StatusCode};
+use mediator_coordination::{client::coord, degradation::LoadShedder, onboarding};
+use oob_messages::invitations::InvitationStore;
+use serde_json::{json, Value};
+use std::{
+    net::TcpListener,
+    path::PathBuf,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+struct TestServer {
+    base_url: String,
+    storage_dirpath: String,
+}
+
+/// Boot a single server shared by all tests, on its own runtime so that
+/// it outlives the runtimes of individual tests.
+fn server() -> &'static TestServer {
+    static SERVER: OnceLock<TestServer> = OnceLock::new();
+
+    SERVER.get_or_init(|| {
+        let storage_dirpath: PathBuf =
+            std::env::temp_dir().join(format!("generic-server-integration-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&storage_dirpath);
+        std::fs::create_dir_all(&storage_dirpath).unwrap();
+        let storage_dirpath = storage_dirpath.to_str().unwrap().to_owned();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        std::env::set_var("ADMIN_API_ENABLED", "true");
+
+        // Unconfigured optional plugins would fail startup, if built
+        std::env::set_var("FEATURE_FLAGS", "plugin.wasm=off");
+        let app = MediatorBuilder::new()
+            .storage_dirpath(&storage_dirpath)
+            .public_domain("http://localhost")
+            .local_port(port)
+            .build();
+
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async {
+                Server::from_tcp(listener)
+                    .unwrap()
+                    .serve(app.into_make_service())
+                    .await
+                    .unwrap();
+            })
+        });
+
+        TestServer {
+            base_url: format!("http://127.0.0.1:{port}"),
+            storage_dirpath,
+        }
+    })
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+fn api_key(scopes: &[ApiKeyScope]) -> String {
+    let mut fs = StdFileSystem;
+    let (_, key) = ApiKeyStore::new(&mut fs, &server().storage_dirpath)
+        .create("integration", scopes, now())
+        .unwrap();
+    key
+}
+
+async fn send(
+    method: Method,
+    path: &str,
+    api_key: Option<&str>,
+    payload: Option<Value>,
+) -> (StatusCode, Vec<u8>) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(format!("{}{path}", server().base_url));
+    if let Some(key) = api_key {
+        request = request.header(API_KEY_HEADER, key);
+    }
+
+    let request = match payload {
+        Some(payload) => request
+            .header("content-type", "application/json")
+            .body(Body::from(payload.to_string())),
+        None => request.body(Body::empty()),
+    };
+
+    let response = Client::new().request(request.unwrap()).await.unwrap();
+    let status = response.status();
+    let body = body::to_bytes(response.into_body()).await.unwrap();
+
+    (status, body.to_vec())
+}
+
+async fn get_json(path: &str) -> Value {
+    let (status, body) = send(Method::GET, path, None, None).await;
+    assert_eq!(status, StatusCode::OK, "GET {path}");
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_serving_mediator_identity() {
+    let diddoc = get_json("/.well-known/did.json").await;
+    assert_eq!(diddoc["id"], "did:web:localhost");
+
+    let (status, body) = send(Method::GET, "/oob_url", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(String::from_utf8(body).unwrap().contains("?_oob="));
+
+    let health = get_json("/health/plugins").await;
+    assert_eq!(health, json!({"plugins": {}}));
+
+    // Startup completed, including the identity self-test
+    let readiness = get_json("/health/ready").await;
+    assert_eq!(readiness["ready"], true);
+    assert_eq!(readiness["stages"][3]["name"], "self-test");
+}
+
+#[tokio::test]
+async fn test_admin_routes_require_scoped_keys() {
+    let usage_key = api_key(&[ApiKeyScope::Usage]);
+
+    let (status, _) = send(Method::GET, "/admin/invitations", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(Method::GET, "/admin/invitations", Some("unknown.key"), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(Method::GET, "/admin/invitations", Some(&usage_key), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(Method::GET, "/admin/v1/usage", Some(&usage_key), None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_onboarding_with_scoped_invitation() {
+    let key = api_key(&[ApiKeyScope::Invitations]);
+
+    // Mint a single-use invitation over the admin API
+    let (status, body) = send(
+        Method::POST,
+        "/admin/invitations",
+        Some(&key),
+        Some(json!({"max_uses": 1})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let minted: Value = serde_json::from_slice(&body).unwrap();
+    let invitation_id = minted["id"].as_str().unwrap().to_owned();
+    assert!(minted["oob_url"].as_str().unwrap().contains("?_oob="));
+
+    let (status, body) = send(Method::GET, "/admin/invitations", Some(&key), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let listed: Value = serde_json::from_slice(&body).unwrap();
+    assert!(listed
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|inv| inv["id"] == invitation_id));
+
+    // A mediation request responding to it is admitted once
+    let request = coord::mediate_request_with_invitation(&invitation_id);
+    let mut fs = StdFileSystem;
+    let mut store = InvitationStore::new(&mut fs, &server().storage_dirpath);
+    let shedder = LoadShedder::default();
+
+    assert!(onboarding::admit_mediation_request(&mut store, &shedder, &request, now()).is_ok());
+    let report =
+        onboarding::admit_mediation_request(&mut store, &shedder, &request, now()).unwrap_err();
+    assert_eq!(report.body.code, onboarding::INVITATION_REJECTED_CODE);
+
+    let (status, _) = send(
+        Method::DELETE,
+        &format!("/admin/invitations/{invitation_id}"),
+        Some(&key),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,182 @@
+//! Procedural macros of the mediator coordination plugin.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, Error, Expr, FnArg, Ident, ItemFn, Token, Type,
+};
+
+/// Arguments of [`macro@didcomm_handler`]: the type of messages handled,
+/// and that of replies, if any
+struct HandlerArgs {
+    message_type: Expr,
+    reply: Option<Expr>,
+}
+
+impl Parse for HandlerArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let message_type = input.parse()?;
+        let mut reply = None;
+
+        if input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+            let key: Ident = input.parse()?;
+            if key != "reply" {
+                return Err(Error::new(key.span(), "expected `reply = <message type>`"));
+            }
+            input.parse::<Token![=]>()?;
+            reply = Some(input.parse()?);
+            input.parse::<Option<Token![,]>>()?;
+        }
+
+        Ok(Self {
+            message_type,
+            reply,
+        })
+    }
+}
+
+/// Generates a `MessageHandler` from a function handling the typed body of
+/// requests of a message type, e.g.
+///
+/// ```ignore
+/// #[didcomm_handler(DELIVERY_WINDOWS_GET_1_0, reply = DELIVERY_WINDOWS_1_0)]
+/// fn get_windows(
+///     windows: &DeliveryWindows,
+///     sender: &str,
+///     body: DeliveryWindowsGetBody,
+/// ) -> Result<DeliveryWindowsBody, WindowsError> {
+///     Ok(windows.current(sender))
+/// }
+/// ```
+///
+/// generates a `GetWindows(DeliveryWindows)` handler, named after the
+/// function and holding its state, if any. The handler deserializes
+/// requests, answers with the returned body in a reply of the given type,
+/// in the thread of the request, and converts errors into problem reports
+/// in that thread. Without a reply type, the returned value is the reply,
+/// placed in the thread of the request unless it has one.
+///
+/// Functions take the state of the handler by reference, if any, the DID
+/// of the sender and the body of the request. Their errors convert into
+/// problem reports.
+///
+/// Generated code refers to items of `mediator_coordination` by absolute
+/// paths, through `mediator_coordination::handler::__private`, so that
+/// handlers may be generated in other crates as well.
+#[proc_macro_attribute]
+pub fn didcomm_handler(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as HandlerArgs);
+    let function = parse_macro_input!(item as ItemFn);
+
+    match expand(args, function) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(args: HandlerArgs, function: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let mut types = vec![];
+    for input in &function.sig.inputs {
+        match input {
+            FnArg::Typed(input) => types.push(input.ty.as_ref().clone()),
+            FnArg::Receiver(receiver) => {
+                return Err(Error::new_spanned(
+                    receiver,
+                    "handlers must be free functions",
+                ))
+            }
+        }
+    }
+
+    let (state, body) = match types.as_slice() {
+        [state, _sender, body] => match state {
+            Type::Reference(state) => (Some(state.elem.as_ref().clone()), body.clone()),
+            state => {
+                return Err(Error::new_spanned(
+                    state,
+                    "state must be taken by reference",
+                ))
+            }
+        },
+        [_sender, body] => (None, body.clone()),
+        _ => {
+            return Err(Error::new_spanned(
+                &function.sig,
+                "expected arguments `(state, sender, body)` or `(sender, body)`",
+            ))
+        }
+    };
+
+    let vis = &function.vis;
+    let name = &function.sig.ident;
+    let handler = pascal_case(name);
+    let message_type = &args.message_type;
+    let reply = match &args.reply {
+        Some(reply) => quote!(::std::option::Option::Some(#reply)),
+        None => quote!(::std::option::Option::None),
+    };
+
+    let doc = format!("Handler of messages generated from [`{name}`]");
+    let (definition, call) = match &state {
+        Some(state) => (
+            quote!(#vis struct #handler(pub #state);),
+            quote!(#name(&self.0, sender, body)),
+        ),
+        None => (quote!(#vis struct #handler;), quote!(#name(sender, body))),
+    };
+
+    Ok(quote! {
+        #function
+
+        #[doc = #doc]
+        #definition
+
+        impl ::mediator_coordination::handler::__private::MessageHandler for #handler {
+            fn message_type(&self) -> &'static str {
+                #message_type
+            }
+
+            #[allow(clippy::result_large_err)]
+            fn handle(
+                &self,
+                sender: &str,
+                message: &::mediator_coordination::handler::__private::Value,
+            ) -> ::std::result::Result<
+                ::mediator_coordination::handler::__private::Value,
+                ::mediator_coordination::handler::__private::ProblemReport,
+            > {
+                let mut request = ::mediator_coordination::handler::__private::request::<#body>(
+                    #message_type,
+                    message,
+                )?;
+                let body = ::std::mem::take(&mut request.body);
+                let reply = #call.map_err(|err| {
+                    ::mediator_coordination::handler::__private::problem(&request, err)
+                })?;
+
+                Ok(::mediator_coordination::handler::__private::reply(
+                    &request, #reply, reply,
+                ))
+            }
+        }
+    })
+}
+
+/// Name of a handler generated from a function, e.g. `GetWindows` from
+/// `get_windows`
+fn pascal_case(name: &Ident) -> Ident {
+    let pascal: String = name
+        .to_string()
+        .split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().map(|c| c.to_ascii_uppercase());
+            first.into_iter().chain(chars).collect::<String>()
+        })
+        .collect();
+
+    format_ident!("{}", pascal, span = Span::call_site())
+}
@@ -0,0 +1,33 @@
+//! Embeds the git commit the mediator is built from, attested to by the
+//! mediator at runtime. `GIT_COMMIT` takes precedence, for builds outside
+//! of a git checkout, e.g. from a source archive.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+
+    // Commits move the branch checked out, rather than HEAD
+    if let Ok(head) = std::fs::read_to_string("../.git/HEAD") {
+        if let Some(branch) = head.trim().strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed=../.git/{branch}");
+        }
+    }
+
+    let commit = std::env::var("GIT_COMMIT").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        String::from_utf8(output.stdout)
+            .ok()
+            .map(|commit| commit.trim().to_owned())
+    });
+
+    println!(
+        "cargo:rustc-env=GIT_COMMIT={}",
+        commit.unwrap_or_else(|| String::from("unknown"))
+    );
+}
@@ -0,0 +1,435 @@
+//! Detection of anomalous traffic.
+//!
+//! Envelopes are described to [`AnomalyDetector`]s by their metadata only,
+//! as the mediator cannot read forwarded messages. Detectors flag suspicious
+//! envelopes, e.g. of unusual size or from senders flooding the mediator,
+//! and may request the sender to be throttled. The [`AnomalyMonitor`] runs a
+//! set of detectors and records flagged envelopes to the anomaly log.
+
+use did_endpoint::util::filesystem::FileSystem;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+/// Metadata of a received envelope
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EnvelopeMetadata {
+    /// DID of the sender, unknown for anonymously encrypted envelopes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+
+    /// DID or key identifier of the recipient
+    pub recipient: String,
+
+    /// Size of the envelope, in bytes
+    pub size: u64,
+
+    /// Time of reception, as a UNIX timestamp
+    pub received_time: i64,
+}
+
+/// Outcome of the inspection of an envelope
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "verdict", rename_all = "snake_case")]
+pub enum Verdict {
+    Normal,
+    Suspicious {
+        /// Human-readable explanation
+        reason: String,
+
+        /// Whether the sender should be rate limited
+        throttle: bool,
+    },
+}
+
+impl Verdict {
+    fn suspicious(reason: String, throttle: bool) -> Self {
+        Self::Suspicious { reason, throttle }
+    }
+
+    pub fn is_suspicious(&self) -> bool {
+        matches!(self, Self::Suspicious { .. })
+    }
+
+    /// Whether the sender should be rate limited
+    pub fn should_throttle(&self) -> bool {
+        matches!(self, Self::Suspicious { throttle: true, .. })
+    }
+}
+
+/// Inspects envelopes for anomalies
+pub trait AnomalyDetector: Send + Sync {
+    fn inspect(&self, envelope: &EnvelopeMetadata) -> Verdict;
+}
+
+/// Thresholds of the [`BaselineDetector`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaselineConfig {
+    /// Number of envelopes observed before sizes are judged
+    pub warmup: u64,
+
+    /// Deviations from the mean size beyond which envelopes are suspicious
+    pub size_deviations: f64,
+
+    /// Length of the window over which envelopes are counted per sender, in seconds
+    pub window: i64,
+
+    /// Number of envelopes a sender may send within a window
+    pub max_per_window: usize,
+
+    /// Entropy per character below which sender DIDs are suspicious, in bits
+    pub min_did_entropy: f64,
+}
+
+impl Default for BaselineConfig {
+    fn default() -> Self {
+        Self {
+            warmup: 100,
+            size_deviations: 4.0,
+            window: 60,
+            max_per_window: 120,
+            min_did_entropy: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Baseline {
+    /// Running statistics of sizes (Welford's algorithm)
+    count: u64,
+    mean: f64,
+    m2: f64,
+
+    /// Reception times of recent envelopes, per sender
+    recent: HashMap<String, VecDeque<i64>>,
+}
+
+/// Statistical baseline, flagging envelopes whose size deviates from the
+/// mean, senders exceeding a rate, and sender DIDs of low entropy, which
+/// are typical of crafted identifiers.
+#[derive(Debug, Default)]
+pub struct BaselineDetector {
+    config: BaselineConfig,
+    baseline: Mutex<Baseline>,
+}
+
+impl BaselineDetector {
+    pub fn new(config: BaselineConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+}
+
+impl AnomalyDetector for BaselineDetector {
+    fn inspect(&self, envelope: &EnvelopeMetadata) -> Verdict {
+        let mut baseline = self.baseline.lock().unwrap();
+        let mut reasons = vec![];
+        let mut throttle = false;
+
+        // Sizes are judged against the baseline before being folded in
+        let size = envelope.size as f64;
+        if baseline.count >= self.config.warmup {
+            let stddev = (baseline.m2 / baseline.count as f64).sqrt();
+            if size > baseline.mean + self.config.size_deviations * stddev.max(1.0) {
+                reasons.push(format!(
+                    "size {} deviates from mean {:.0}",
+                    envelope.size, baseline.mean
+                ));
+            }
+        }
+
+        baseline.count += 1;
+        let delta = size - baseline.mean;
+        baseline.mean += delta / baseline.count as f64;
+        baseline.m2 += delta * (size - baseline.mean);
+
+        if let Some(sender) = &envelope.sender {
+            let now = envelope.received_time;
+            let window_start = now - self.config.window;
+
+            let recent = baseline.recent.entry(sender.clone()).or_default();
+            recent.retain(|time| *time > window_start);
+            recent.push_back(now);
+
+            if recent.len() > self.config.max_per_window {
+                reasons.push(format!(
+                    "{} envelopes from sender within {}s",
+                    recent.len(),
+                    self.config.window
+                ));
+                throttle = true;
+            }
+
+            // Forget idle senders not to grow unbounded
+            baseline
+                .recent
+                .retain(|_, recent| recent.back().is_some_and(|time| *time > window_start));
+
+            let entropy = did_entropy(sender);
+            if entropy < self.config.min_did_entropy {
+                reasons.push(format!("sender DID of low entropy ({entropy:.2} bits)"));
+            }
+        }
+
+        if reasons.is_empty() {
+            Verdict::Normal
+        } else {
+            Verdict::suspicious(reasons.join("; "), throttle)
+        }
+    }
+}
+
+/// Shannon entropy per character of the method-specific identifier of a DID
+fn did_entropy(did: &str) -> f64 {
+    let id = did.splitn(3, ':').nth(2).unwrap_or(did);
+    if id.is_empty() {
+        return 0.0;
+    }
+
+    let mut frequencies: HashMap<char, usize> = HashMap::new();
+    for c in id.chars() {
+        *frequencies.entry(c).or_default() += 1;
+    }
+
+    let length = id.chars().count() as f64;
+    frequencies
+        .values()
+        .map(|count| {
+            let p = *count as f64 / length;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Record of a flagged envelope
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AnomalyReport {
+    #[serde(flatten)]
+    pub envelope: EnvelopeMetadata,
+
+    #[serde(flatten)]
+    pub verdict: Verdict,
+}
+
+/// Runs detectors over envelopes, recording flagged ones to the anomaly
+/// log, `audit/anomalies.jsonl` under the storage directory.
+pub struct AnomalyMonitor {
+    detectors: Vec<Box<dyn AnomalyDetector>>,
+    log: Option<Mutex<(Box<dyn FileSystem>, String)>>,
+}
+
+impl AnomalyMonitor {
+    pub fn new() -> Self {
+        Self {
+            detectors: vec![],
+            log: None,
+        }
+    }
+
+    /// Registers a detector
+    pub fn with_detector(mut self, detector: impl AnomalyDetector + 'static) -> Self {
+        self.detectors.push(Box::new(detector));
+        self
+    }
+
+    /// Records flagged envelopes to the anomaly log
+    pub fn with_log(mut self, fs: impl FileSystem, storage_dirpath: &str) -> Self {
+        let dirpath = format!("{storage_dirpath}/audit");
+        self.log = Some(Mutex::new((Box::new(fs), dirpath)));
+        self
+    }
+
+    /// Inspects an envelope with all detectors, merging their verdicts
+    pub fn inspect(&self, envelope: &EnvelopeMetadata) -> Verdict {
+        let mut reasons = vec![];
+        let mut throttle = false;
+
+        for detector in &self.detectors {
+            if let Verdict::Suspicious {
+                reason,
+                throttle: t,
+            } = detector.inspect(envelope)
+            {
+                reasons.push(reason);
+                throttle |= t;
+            }
+        }
+
+        if reasons.is_empty() {
+            return Verdict::Normal;
+        }
+
+        let verdict = Verdict::suspicious(reasons.join("; "), throttle);
+        self.record(AnomalyReport {
+            envelope: envelope.clone(),
+            verdict: verdict.clone(),
+        });
+
+        verdict
+    }
+
+    fn record(&self, report: AnomalyReport) {
+        tracing::warn!(
+            "suspicious envelope for {}: {:?}",
+            report.envelope.recipient,
+            report.verdict
+        );
+
+        let Some(log) = &self.log else {
+            return;
+        };
+
+        let mut log = log.lock().unwrap();
+        let (fs, dirpath) = &mut *log;
+        let line = match serde_json::to_string(&report) {
+            Ok(line) => line + "\n",
+            Err(_) => return,
+        };
+
+        let result = fs
+            .create_dir_all(dirpath)
+            .and_then(|_| fs.append(&format!("{dirpath}/anomalies.jsonl"), &line));
+        if let Err(err) = result {
+            tracing::error!("failed to record anomaly: {err}");
+        }
+    }
+}
+
+fn anomaly_log_path(storage_dirpath: &str) -> String {
+    format!("{storage_dirpath}/audit/anomalies.jsonl")
+}
+
+/// Reads the anomaly log under a storage directory
+pub fn read_anomaly_log(
+    fs: &dyn FileSystem,
+    storage_dirpath: &str,
+) -> std::io::Result<Vec<AnomalyReport>> {
+    let content = match fs.read_to_string(&anomaly_log_path(storage_dirpath)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err),
+    };
+
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(std::io::Error::other))
+        .collect()
+}
+
+/// Replaces the anomaly log under a storage directory, e.g. to anonymize
+/// its reports
+pub fn rewrite_anomaly_log(
+    fs: &mut dyn FileSystem,
+    storage_dirpath: &str,
+    reports: &[AnomalyReport],
+) -> std::io::Result<()> {
+    let mut lines = String::new();
+    for report in reports {
+        lines += &serde_json::to_string(report).map_err(std::io::Error::other)?;
+        lines.push('\n');
+    }
+
+    fs.create_dir_all(&format!("{storage_dirpath}/audit"))?;
+    fs.write_atomic(&anomaly_log_path(storage_dirpath), &lines)
+}
+
+impl Default for AnomalyMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use did_endpoint::util::test_utils::MemoryFileSystem;
+
+    fn envelope(sender: Option<&str>, size: u64, received_time: i64) -> EnvelopeMetadata {
+        EnvelopeMetadata {
+            sender: sender.map(String::from),
+            recipient: String::from("did:key:z6MkrQT3VKYGkbPaYuJeBv31gNgpmVtRWP5yTocLDBgPpayM"),
+            size,
+            received_time,
+        }
+    }
+
+    #[test]
+    fn can_flag_unusual_sizes() {
+        let detector = BaselineDetector::new(BaselineConfig {
+            warmup: 10,
+            ..Default::default()
+        });
+
+        for i in 0..10 {
+            let verdict = detector.inspect(&envelope(None, 1000 + i * 10, i as i64));
+            assert_eq!(verdict, Verdict::Normal);
+        }
+
+        assert_eq!(detector.inspect(&envelope(None, 1100, 10)), Verdict::Normal);
+
+        let verdict = detector.inspect(&envelope(None, 1_000_000, 11));
+        assert!(verdict.is_suspicious());
+        assert!(!verdict.should_throttle());
+    }
+
+    #[test]
+    fn can_flag_flooding_senders() {
+        let detector = BaselineDetector::new(BaselineConfig {
+            window: 10,
+            max_per_window: 3,
+            ..Default::default()
+        });
+
+        let sender = Some("did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK");
+        for time in 0..3 {
+            assert_eq!(
+                detector.inspect(&envelope(sender, 100, time)),
+                Verdict::Normal
+            );
+        }
+
+        let verdict = detector.inspect(&envelope(sender, 100, 3));
+        assert!(verdict.should_throttle());
+
+        // The window slides
+        assert_eq!(
+            detector.inspect(&envelope(sender, 100, 20)),
+            Verdict::Normal
+        );
+    }
+
+    #[test]
+    fn can_flag_low_entropy_dids() {
+        assert!(did_entropy("did:example:aaaaaaaaaaaaaaaa") < 1.0);
+        assert!(did_entropy("did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK") > 4.0);
+
+        let detector = BaselineDetector::default();
+        let verdict = detector.inspect(&envelope(Some("did:example:aaaaaaaaaaaa"), 100, 0));
+        assert!(verdict.is_suspicious());
+    }
+
+    #[test]
+    fn can_record_flagged_envelopes() {
+        let fs = MemoryFileSystem::default();
+        let monitor = AnomalyMonitor::new()
+            .with_detector(BaselineDetector::default())
+            .with_log(fs.clone(), "storage");
+
+        let sender = Some("did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK");
+        assert_eq!(monitor.inspect(&envelope(sender, 100, 0)), Verdict::Normal);
+        assert!(fs.read_to_string("storage/audit/anomalies.jsonl").is_err());
+
+        let flagged = envelope(Some("did:example:aaaaaaaaaaaa"), 100, 0);
+        assert!(monitor.inspect(&flagged).is_suspicious());
+
+        let log = fs.read_to_string("storage/audit/anomalies.jsonl").unwrap();
+        let report: AnomalyReport = serde_json::from_str(log.lines().next().unwrap()).unwrap();
+        assert_eq!(report.envelope, flagged);
+        assert!(report.verdict.is_suspicious());
+    }
+}
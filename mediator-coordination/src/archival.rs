@@ -0,0 +1,275 @@
+//! Archival of revoked connections.
+//!
+//! Revoking a connection archives it rather than deleting it: archived
+//! connections are left out of queries unless asked for, but they keep
+//! their keylist and queued messages. Operators may restore them until
+//! their retention expires, after which they are purged along with their
+//! queued messages.
+
+use server_plugin::tasks::BackgroundTasks;
+use std::{sync::Arc, time::Duration};
+use thiserror::Error;
+
+use crate::{
+    model::connection::Connection,
+    pickup::PickupQueue,
+    repository::{update_with_retry, Repository, RepositoryError},
+};
+
+/// Interval between purges of connections whose retention expired
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Times an archival or restore is attempted on concurrent updates
+const UPDATE_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ArchivalError {
+    #[error("connection {0} is not archived")]
+    NotArchived(String),
+    #[error("retention of connection {0} expired")]
+    RetentionExpired(String),
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArchivalConfig {
+    /// Seconds archived connections are retained for
+    pub retention: i64,
+}
+
+impl Default for ArchivalConfig {
+    fn default() -> Self {
+        Self {
+            retention: 30 * 24 * 3600,
+        }
+    }
+}
+
+impl ArchivalConfig {
+    /// Configuration from `CONNECTION_RETENTION_SECS`
+    pub fn from_env() -> Self {
+        let retention = server_plugin::reload::var("CONNECTION_RETENTION_SECS")
+            .and_then(|v| v.parse().ok())
+            .filter(|&secs: &i64| secs >= 0);
+
+        Self {
+            retention: retention.unwrap_or(Self::default().retention),
+        }
+    }
+}
+
+/// Connections, archived on revocation. Clones share the same repository.
+#[derive(Clone)]
+pub struct Connections {
+    repository: Arc<dyn Repository<Connection>>,
+    config: ArchivalConfig,
+}
+
+impl Connections {
+    pub fn new(repository: Arc<dyn Repository<Connection>>, config: ArchivalConfig) -> Self {
+        Self { repository, config }
+    }
+
+    /// Active connection of a client
+    pub fn find(&self, client_did: &str) -> Option<Connection> {
+        self.repository
+            .find(client_did)
+            .filter(|connection| !connection.is_archived())
+    }
+
+    /// Connection of a client, archived or not
+    pub fn get(&self, client_did: &str) -> Option<Connection> {
+        self.repository.find(client_did)
+    }
+
+    /// Deletes a connection right away, archived or not
+    pub fn delete(&self, client_did: &str) -> Result<(), ArchivalError> {
+        Ok(self.repository.delete(client_did)?)
+    }
+
+    /// Connections by client DID, leaving out archived ones unless asked
+    pub fn list(&self, include_archived: bool) -> Vec<Connection> {
+        let mut connections: Vec<_> = self
+            .repository
+            .all()
+            .into_iter()
+            .filter(|connection| include_archived || !connection.is_archived())
+            .collect();
+
+        connections.sort_by(|a, b| a.client_did.cmp(&b.client_did));
+        connections
+    }
+
+    /// Archives a connection. Archiving an archived connection leaves its
+    /// retention unchanged.
+    pub fn archive(&self, client_did: &str, now: i64) -> Result<Connection, ArchivalError> {
+        let (connection, _) = update_with_retry(
+            &*self.repository,
+            client_did,
+            UPDATE_ATTEMPTS,
+            |connection| {
+                connection.archived_time.get_or_insert(now);
+            },
+        )?;
+
+        tracing::info!("archived connection {client_did}");
+        Ok(connection)
+    }
+
+    /// Restores an archived connection, as it was on archival
+    pub fn restore(&self, client_did: &str, now: i64) -> Result<Connection, ArchivalError> {
+        let connection = self
+            .repository
+            .find(client_did)
+            .ok_or_else(|| RepositoryError::NotFound(client_did.to_owned()))?;
+
+        match connection.archived_time {
+            None => return Err(ArchivalError::NotArchived(client_did.to_owned())),
+            Some(t) if self.is_expired(t, now) => {
+                return Err(ArchivalError::RetentionExpired(client_did.to_owned()))
+            }
+            Some(_) => (),
+        }
+
+        // Fails on conflicts rather than restoring a connection purged or
+        // archived anew in the meantime
+        let restored = self.repository.update(Connection {
+            archived_time: None,
+            ..connection
+        })?;
+
+        tracing::info!("restored connection {client_did}");
+        Ok(restored)
+    }
+
+    /// Deletes connections whose retention expired, along with their
+    /// queued messages, returning their client DIDs
+    pub fn purge(&self, queue: &PickupQueue, now: i64) -> Vec<String> {
+        let expired: Vec<_> = self
+            .repository
+            .all()
+            .into_iter()
+            .filter(|connection| {
+                connection
+                    .archived_time
+                    .is_some_and(|t| self.is_expired(t, now))
+            })
+            .map(|connection| connection.client_did)
+            .collect();
+
+        for client_did in &expired {
+            if let Err(err) = self.repository.delete(client_did) {
+                tracing::warn!("failed to purge connection {client_did}: {err}");
+                continue;
+            }
+
+            let dropped = queue.purge(client_did);
+            tracing::info!("purged connection {client_did} and {dropped} queued messages");
+        }
+
+        expired
+    }
+
+    /// Purges connections whose retention expired on a fixed interval
+    pub fn purge_periodically(&self, tasks: &BackgroundTasks, queue: Arc<PickupQueue>) {
+        let connections = self.clone();
+
+        tasks.every("connection-purge", PURGE_INTERVAL, move || {
+            let now = chrono::Utc::now().timestamp();
+            connections.purge(&queue, now);
+        });
+    }
+
+    fn is_expired(&self, archived_time: i64, now: i64) -> bool {
+        now >= archived_time.saturating_add(self.config.retention)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pickup::QueuedMessage, repository::MemoryRepository};
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+    const BOB: &str = "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7";
+
+    fn connections() -> Connections {
+        let repository = MemoryRepository::new();
+        for client_did in [ALICE, BOB] {
+            let connection = Connection {
+                client_did: client_did.to_owned(),
+                keylist: vec![format!("{client_did}#key-0")],
+                ..Default::default()
+            };
+            repository.insert(connection).unwrap();
+        }
+
+        Connections::new(Arc::new(repository), ArchivalConfig { retention: 100 })
+    }
+
+    #[test]
+    fn can_archive_and_restore() {
+        let connections = connections();
+
+        let archived = connections.archive(ALICE, 1000).unwrap();
+        assert_eq!(archived.archived_time, Some(1000));
+        assert_eq!(connections.find(ALICE), None);
+
+        // Archived connections are filtered out by default
+        let clients = |include_archived| -> Vec<_> {
+            let connections = connections.list(include_archived);
+            connections.into_iter().map(|c| c.client_did).collect()
+        };
+        assert_eq!(clients(false), [BOB]);
+        assert_eq!(clients(true), [BOB, ALICE]);
+
+        // Archiving again keeps the original retention
+        connections.archive(ALICE, 1050).unwrap();
+
+        let restored = connections.restore(ALICE, 1099).unwrap();
+        assert_eq!(restored.archived_time, None);
+        assert_eq!(restored.keylist, [format!("{ALICE}#key-0")]);
+        assert_eq!(connections.find(ALICE), Some(restored));
+
+        assert_eq!(
+            connections.restore(ALICE, 1100),
+            Err(ArchivalError::NotArchived(ALICE.to_owned()))
+        );
+    }
+
+    #[test]
+    fn should_purge_once_retention_expired() {
+        let connections = connections();
+        let queue = PickupQueue::default();
+        for client_did in [ALICE, BOB] {
+            let message = QueuedMessage {
+                id: format!("msg-{client_did}"),
+                recipient_did: client_did.to_owned(),
+                ..Default::default()
+            };
+            queue.enqueue(client_did, message);
+        }
+
+        connections.archive(ALICE, 1000).unwrap();
+
+        // Queued messages survive the archival
+        assert!(connections.purge(&queue, 1099).is_empty());
+        assert_eq!(queue.messages(ALICE).len(), 1);
+
+        assert_eq!(
+            connections.restore(ALICE, 1100),
+            Err(ArchivalError::RetentionExpired(ALICE.to_owned()))
+        );
+        assert_eq!(connections.purge(&queue, 1100), [ALICE]);
+        assert!(queue.messages(ALICE).is_empty());
+        assert_eq!(queue.messages(BOB).len(), 1);
+
+        assert_eq!(
+            connections.restore(ALICE, 1100),
+            Err(ArchivalError::Repository(RepositoryError::NotFound(
+                ALICE.to_owned()
+            )))
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Attestation of the identity of the mediator.
+//!
+//! The mediator states which software it runs (version and git commit),
+//! which protocols it serves and which policy it discloses, and signs the
+//! statement with its assertion key. Wallets and auditors fetch it from
+//! [`ATTESTATION_PATH`] and verify it against the DID document of the
+//! mediator, as with [`verify_attestation`]. The statement is signed again
+//! whenever the disclosed policy changes.
+
+use did_endpoint::util::keystore::KeyStore;
+use did_utils::didcore::Document;
+use protocols_registry::MessageType;
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    model::{attestation::Attestation, policy::MediatorPolicy},
+    policy::{self, PolicyError},
+    web,
+};
+
+/// Path the signed attestation is served at
+pub const ATTESTATION_PATH: &str = "/.well-known/didcomm/attestation.json";
+
+/// Name of the software, as attested
+pub const SOFTWARE: &str = "didcomm-mediator-rs";
+
+/// Version of the software, as attested
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Git commit the software was built from, as attested
+pub const COMMIT: &str = env!("GIT_COMMIT");
+
+/// Identifiers of the protocols served, derived from the message types
+/// the mediator supports
+pub fn enabled_protocols() -> Vec<String> {
+    let protocols: BTreeSet<_> = web::validator()
+        .specs()
+        .filter_map(|spec| spec.message_type.parse::<MessageType>().ok())
+        .map(|message_type| message_type.protocol_id().to_string())
+        .collect();
+
+    protocols.into_iter().collect()
+}
+
+/// Signed attestation served to clients, replaced when the disclosed
+/// policy changes. Clones share the same attestation.
+#[derive(Debug, Clone, Default)]
+pub struct DisclosedAttestation(Arc<RwLock<Option<Attestation>>>);
+
+impl DisclosedAttestation {
+    pub fn get(&self) -> Option<Attestation> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Signs an attestation of the disclosed policy, if any, in place of
+    /// the current one, which is kept if signing fails
+    pub fn sign(
+        &self,
+        policy: Option<&MediatorPolicy>,
+        diddoc: &Document,
+        keystore: &KeyStore,
+    ) -> Result<(), PolicyError> {
+        let signed = sign_attestation(policy, diddoc, keystore)?;
+        *self.0.write().unwrap() = Some(signed);
+        Ok(())
+    }
+}
+
+/// Attests to the software run by the mediator and to the policy it
+/// discloses, with its assertion key
+pub fn sign_attestation(
+    policy: Option<&MediatorPolicy>,
+    diddoc: &Document,
+    keystore: &KeyStore,
+) -> Result<Attestation, PolicyError> {
+    let attestation = Attestation {
+        issuer: diddoc.id.clone(),
+        issued: chrono::Utc::now(),
+        software: SOFTWARE.to_owned(),
+        version: VERSION.to_owned(),
+        commit: COMMIT.to_owned(),
+        protocols: enabled_protocols(),
+        policy_digest: policy.map(policy::policy_digest).transpose()?,
+        proof: None,
+    };
+
+    let payload = serde_json::to_value(&attestation).map_err(PolicyError::ParseError)?;
+    let proof = policy::assertion_proof(payload, diddoc, keystore)?;
+
+    Ok(Attestation {
+        proof: Some(proof),
+        ..attestation
+    })
+}
+
+/// Verifies an attestation against the DID document of the mediator that
+/// served it
+pub fn verify_attestation(attestation: &Attestation, diddoc: &Document) -> Result<(), PolicyError> {
+    if attestation.issuer != diddoc.id {
+        return Err(PolicyError::InvalidSignature(diddoc.id.clone()));
+    }
+
+    let payload = serde_json::to_value(attestation).map_err(PolicyError::ParseError)?;
+    policy::verify_assertion_proof(payload, attestation.proof.clone(), diddoc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{self, mock_fs};
+
+    #[test]
+    fn can_sign_and_verify_attestation() {
+        let mut mock_fs = mock_fs();
+        let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+
+        let policy = policy::sign_policy(MediatorPolicy::default(), &diddoc, &keystore).unwrap();
+        let attestation = sign_attestation(Some(&policy), &diddoc, &keystore).unwrap();
+        verify_attestation(&attestation, &diddoc).unwrap();
+
+        assert_eq!(attestation.version, VERSION);
+        assert!(!attestation.commit.is_empty());
+        assert_eq!(
+            attestation.policy_digest,
+            Some(policy::policy_digest(&policy).unwrap())
+        );
+
+        let protocols = &attestation.protocols;
+        assert!(protocols.contains(&String::from("https://didcomm.org/messagepickup/3.0")));
+        assert!(protocols.iter().all(|p| !p.ends_with("/status-request")));
+
+        // Claims of other builds do not verify
+        let altered = Attestation {
+            commit: String::from("0000000"),
+            ..attestation
+        };
+        assert!(matches!(
+            verify_attestation(&altered, &diddoc),
+            Err(PolicyError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn can_replace_disclosed_attestation() {
+        let mut mock_fs = mock_fs();
+        let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+
+        let disclosed = DisclosedAttestation::default();
+        assert_eq!(disclosed.get(), None);
+
+        disclosed.sign(None, &diddoc, &keystore).unwrap();
+        assert_eq!(disclosed.get().unwrap().policy_digest, None);
+
+        // Attestations failing to sign leave the disclosed one in place
+        let mut unsigning = diddoc.clone();
+        unsigning.assertion_method = None;
+        assert!(matches!(
+            disclosed.sign(None, &unsigning, &keystore),
+            Err(PolicyError::MissingAssertionKey)
+        ));
+        verify_attestation(&disclosed.get().unwrap(), &diddoc).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+//! Developer tool replaying captured traffic against an in-process
+//! mediator, to reproduce incidents.
+//!
+//! ```sh
+//! cargo run -p mediator-coordination --features replay --bin replay -- <DIR> [MEDIATOR_DID]
+//! ```
+//!
+//! Captures in `DIR` are replayed as described in
+//! [`mediator_coordination::replay`], against a mediator of
+//! `MEDIATOR_DID`, `did:web:localhost` by default. Responses are printed,
+//! and the tool fails if any does not match the expected one.
+
+use mediator_coordination::{
+    local::LocalMediator,
+    replay::{self, Outcome},
+};
+use std::path::Path;
+
+const USAGE: &str = "Usage: replay <DIR> [MEDIATOR_DID]";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (dirpath, did) = match args.as_slice() {
+        [dirpath] => (dirpath, "did:web:localhost"),
+        [dirpath, did] => (dirpath, did.as_str()),
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let captures = match replay::load_captures(Path::new(dirpath)) {
+        Ok(captures) => captures,
+        Err(err) => {
+            eprintln!("{dirpath}: {err}");
+            std::process::exit(2);
+        }
+    };
+
+    let mediator = LocalMediator::new(did);
+    let mut mismatches = 0;
+    for replayed in replay::replay(&mediator, &captures) {
+        let response = replayed
+            .response
+            .map_or_else(|| String::from("no response"), |r| r.to_string());
+
+        match replayed.outcome {
+            Outcome::Matched => println!("ok        {}: {response}", replayed.name),
+            Outcome::Unchecked => println!("replayed  {}: {response}", replayed.name),
+            Outcome::Mismatched { expected, .. } => {
+                mismatches += 1;
+                println!("MISMATCH  {}: {response}", replayed.name);
+                println!("          expected {expected}");
+            }
+        }
+    }
+
+    println!(
+        "{} captures replayed, {mismatches} mismatched",
+        captures.len()
+    );
+    if mismatches > 0 {
+        std::process::exit(1);
+    }
+}
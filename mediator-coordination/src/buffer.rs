@@ -0,0 +1,329 @@
+//! Memory-bounded message buffering.
+//!
+//! A [`SpillBuffer`] queues messages in memory up to a byte budget. Bursts
+//! beyond it are spilled to segment files on disk, and restored once the
+//! messages in memory have been consumed, so that traffic spikes degrade
+//! throughput instead of exhausting memory. Messages are always consumed
+//! in the order they were queued.
+
+use did_endpoint::util::filesystem::{FileSystem, StdFileSystem};
+use serde::Serialize;
+use std::collections::VecDeque;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BufferError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("corrupted spill segment: {0}")]
+    ParseError(serde_json::Error),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferConfig {
+    /// Size of the messages held in memory, in bytes, beyond which
+    /// messages are spilled to disk
+    pub memory_budget: usize,
+
+    /// Number of messages per segment file
+    pub segment_size: usize,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            memory_budget: 16 * 1024 * 1024,
+            segment_size: 1024,
+        }
+    }
+}
+
+/// Spill activity since the buffer was created
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SpillStats {
+    /// Number of messages written to disk
+    pub spilled_messages: u64,
+
+    /// Size of the messages written to disk, in bytes
+    pub spilled_bytes: u64,
+
+    /// Number of messages read back from disk
+    pub restored_messages: u64,
+
+    /// Number of segment files currently on disk
+    pub segments: usize,
+}
+
+#[derive(Debug)]
+struct Segment {
+    path: String,
+    len: usize,
+}
+
+/// FIFO queue of messages, spilling to disk beyond its memory budget
+pub struct SpillBuffer {
+    config: BufferConfig,
+    fs: Box<dyn FileSystem>,
+    dirpath: String,
+
+    memory: VecDeque<String>,
+    memory_bytes: usize,
+
+    /// Spilled messages, all queued after those in memory
+    segments: VecDeque<Segment>,
+    spilled_len: usize,
+    next_segment: u64,
+
+    stats: SpillStats,
+}
+
+impl SpillBuffer {
+    /// Creates a buffer spilling to segment files under `dirpath`
+    pub fn new(config: BufferConfig, fs: impl FileSystem, dirpath: &str) -> Self {
+        Self {
+            config,
+            fs: Box::new(fs),
+            dirpath: dirpath.to_owned(),
+            memory: VecDeque::new(),
+            memory_bytes: 0,
+            segments: VecDeque::new(),
+            spilled_len: 0,
+            next_segment: 0,
+            stats: SpillStats::default(),
+        }
+    }
+
+    /// Creates a buffer spilling to a fresh directory of temporary files
+    pub fn in_temp_dir(config: BufferConfig) -> Self {
+        let dirpath = std::env::temp_dir().join(format!("mediator-spill-{}", uuid::Uuid::new_v4()));
+        Self::new(config, StdFileSystem, &dirpath.to_string_lossy())
+    }
+
+    /// Number of queued messages
+    pub fn len(&self) -> usize {
+        self.memory.len() + self.spilled_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Size of the messages held in memory, in bytes
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_bytes
+    }
+
+    pub fn stats(&self) -> SpillStats {
+        self.stats
+    }
+
+    /// Queues a message, spilling it to disk if the memory budget is
+    /// exhausted or earlier messages were already spilled
+    pub fn push(&mut self, message: String) -> Result<(), BufferError> {
+        if self.segments.is_empty()
+            && self.memory_bytes + message.len() <= self.config.memory_budget
+        {
+            self.memory_bytes += message.len();
+            self.memory.push_back(message);
+            return Ok(());
+        }
+
+        self.spill(message)
+    }
+
+    /// Dequeues the oldest message. Once messages in memory are consumed,
+    /// the oldest segment is restored from disk, possibly exceeding the
+    /// memory budget by at most one segment.
+    pub fn pop(&mut self) -> Result<Option<String>, BufferError> {
+        if self.memory.is_empty() {
+            self.restore()?;
+        }
+
+        let message = self.memory.pop_front();
+        if let Some(message) = &message {
+            self.memory_bytes -= message.len();
+        }
+
+        Ok(message)
+    }
+
+    fn spill(&mut self, message: String) -> Result<(), BufferError> {
+        let segment_size = self.config.segment_size.max(1);
+        if self
+            .segments
+            .back()
+            .is_none_or(|segment| segment.len >= segment_size)
+        {
+            if self.segments.is_empty() {
+                tracing::warn!(
+                    "memory budget exhausted, spilling messages to {}",
+                    self.dirpath
+                );
+            }
+
+            self.fs
+                .create_dir_all(&self.dirpath)
+                .map_err(BufferError::IoError)?;
+
+            let path = format!("{}/segment-{}.jsonl", self.dirpath, self.next_segment);
+            self.next_segment += 1;
+            self.segments.push_back(Segment { path, len: 0 });
+            self.stats.segments += 1;
+        }
+
+        let segment = self.segments.back_mut().expect("segment opened above");
+        let line = serde_json::to_string(&message).map_err(BufferError::ParseError)?;
+        self.fs
+            .append(&segment.path, &(line + "\n"))
+            .map_err(BufferError::IoError)?;
+
+        segment.len += 1;
+        self.spilled_len += 1;
+        self.stats.spilled_messages += 1;
+        self.stats.spilled_bytes += message.len() as u64;
+
+        Ok(())
+    }
+
+    fn restore(&mut self) -> Result<(), BufferError> {
+        let Some(segment) = self.segments.front() else {
+            return Ok(());
+        };
+
+        let content = self
+            .fs
+            .read_to_string(&segment.path)
+            .map_err(BufferError::IoError)?;
+        let messages = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(BufferError::ParseError)?;
+
+        let segment = self.segments.pop_front().expect("segment read above");
+        self.remove_segment(&segment);
+
+        self.spilled_len -= segment.len;
+        self.stats.restored_messages += messages.len() as u64;
+        self.memory_bytes += messages.iter().map(String::len).sum::<usize>();
+        self.memory.extend(messages);
+
+        Ok(())
+    }
+
+    fn remove_segment(&mut self, segment: &Segment) {
+        if let Err(err) = self.fs.remove_file(&segment.path) {
+            tracing::warn!("failed to remove spill segment {}: {err}", segment.path);
+        }
+        self.stats.segments -= 1;
+    }
+}
+
+impl Drop for SpillBuffer {
+    /// Spilled messages do not outlive the buffer
+    fn drop(&mut self) {
+        while let Some(segment) = self.segments.pop_front() {
+            self.remove_segment(&segment);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use did_endpoint::util::test_utils::MemoryFileSystem;
+
+    fn buffer(fs: &MemoryFileSystem) -> SpillBuffer {
+        let config = BufferConfig {
+            memory_budget: 10,
+            segment_size: 2,
+        };
+
+        SpillBuffer::new(config, fs.clone(), "spill")
+    }
+
+    #[test]
+    fn can_buffer_within_memory_budget() {
+        let fs = MemoryFileSystem::new();
+        let mut buffer = buffer(&fs);
+
+        buffer.push("hello".to_string()).unwrap();
+        buffer.push("world".to_string()).unwrap();
+
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.memory_bytes(), 10);
+        assert_eq!(buffer.stats(), SpillStats::default());
+        assert!(fs.paths().is_empty());
+
+        assert_eq!(buffer.pop().unwrap().as_deref(), Some("hello"));
+        assert_eq!(buffer.pop().unwrap().as_deref(), Some("world"));
+        assert_eq!(buffer.pop().unwrap(), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn can_spill_bursts_to_disk_in_order() {
+        let fs = MemoryFileSystem::new();
+        let mut buffer = buffer(&fs);
+
+        let messages: Vec<_> = (0..7).map(|i| format!("msg\n{i}")).collect();
+        for message in &messages {
+            buffer.push(message.clone()).unwrap();
+        }
+
+        // Two messages fit in memory, the others span three segments
+        assert_eq!(buffer.memory_bytes(), 10);
+        assert_eq!(buffer.len(), 7);
+        assert_eq!(
+            fs.paths(),
+            [
+                "spill/segment-0.jsonl",
+                "spill/segment-1.jsonl",
+                "spill/segment-2.jsonl"
+            ]
+        );
+        assert_eq!(
+            buffer.stats(),
+            SpillStats {
+                spilled_messages: 5,
+                spilled_bytes: 25,
+                restored_messages: 0,
+                segments: 3,
+            }
+        );
+
+        // Messages queued meanwhile still come after spilled ones
+        let mut popped = vec![];
+        for _ in 0..3 {
+            popped.push(buffer.pop().unwrap().unwrap());
+        }
+        buffer.push("late".to_string()).unwrap();
+
+        while let Some(message) = buffer.pop().unwrap() {
+            popped.push(message);
+        }
+
+        let mut expected = messages;
+        expected.push("late".to_string());
+        assert_eq!(popped, expected);
+
+        assert_eq!(buffer.stats().restored_messages, 6);
+        assert_eq!(buffer.stats().segments, 0);
+        assert!(fs.paths().is_empty());
+    }
+
+    #[test]
+    fn can_clean_up_segments_on_drop() {
+        let fs = MemoryFileSystem::new();
+        let mut buffer = buffer(&fs);
+
+        for _ in 0..5 {
+            buffer.push("oversized message".to_string()).unwrap();
+        }
+        assert_eq!(fs.paths().len(), 3);
+
+        drop(buffer);
+        assert!(fs.paths().is_empty());
+    }
+}
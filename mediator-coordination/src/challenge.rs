@@ -0,0 +1,593 @@
+//! Recipient authentication challenge for pickup over plain HTTP.
+//!
+//! Delivery requests received over authcrypt are attributed to their
+//! sender by the encryption itself. Over connections without such
+//! authentication, a replayed envelope would otherwise suffice to drain the
+//! queue of a connection. When enabled, delivery requests over these
+//! connections are answered with a challenge instead: a single-use nonce
+//! the recipient signs with a key in the keylist of the connection. Once
+//! the response is verified, the recipient is handed a session token, and
+//! delivery requests of the connection carrying it in their
+//! `pickup_session` header are honored for a while. Requests of the
+//! connection without the token, e.g. replayed by a third party, are
+//! challenged anew.
+//!
+//! Challenges are issued and verified as messages are dispatched, by
+//! [`crate::handler::MessageHandlers::dispatch_unauthenticated`].
+//!
+//! Proofs are compact JWS over `{"nonce": .., "sub": <connection>}`, whose
+//! `kid` header is a did:key, or a key identifier thereof, in the keylist.
+
+use did_utils::{
+    crypto::constant_time::ct_eq,
+    didcore::KeyFormat,
+    key_jwk::jwk::Jwk,
+    methods::did_key::{method::PublicKeyFormat, DIDKeyMethod},
+};
+use multibase::Base::Base64Url;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+use crate::{
+    constants::*,
+    didcomm::{
+        problem_report::ProblemReport,
+        validation::{MessageValidator, INVALID_MESSAGE_CODE, UNSUPPORTED_MESSAGE_CODE},
+    },
+    jose::jws::{read_jws_header, verify_compact_jws},
+    model::{challenge::*, coord::CoordMessage},
+};
+
+/// Code of problem reports on failed challenges
+pub const CHALLENGE_FAILED_CODE: &str = "e.p.req.challenge-failed";
+
+/// Header of delivery requests carrying the session token of an answered
+/// challenge
+pub const SESSION_TOKEN_HEADER: &str = "pickup_session";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ChallengeError {
+    #[error("no challenge pending for the connection")]
+    NoChallenge,
+    #[error("challenge expired")]
+    Expired,
+    #[error("malformed proof")]
+    MalformedProof,
+    #[error("key {0} is not in the keylist of the connection")]
+    UnknownKey(String),
+    #[error("invalid proof signature")]
+    InvalidSignature,
+    #[error("proof does not answer the pending challenge")]
+    Mismatch,
+}
+
+impl ChallengeError {
+    pub fn to_problem_report(&self) -> ProblemReport {
+        ProblemReport::new(
+            CHALLENGE_FAILED_CODE,
+            Some("Challenge failed: {1}"),
+            Some(vec![self.to_string()]),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChallengeConfig {
+    /// Whether delivery requests over unauthenticated connections are
+    /// challenged
+    pub required: bool,
+
+    /// Seconds a challenge may be answered within
+    pub challenge_ttl: i64,
+
+    /// Seconds a connection remains authenticated once it answered
+    pub authentication_ttl: i64,
+}
+
+impl Default for ChallengeConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            challenge_ttl: 120,
+            authentication_ttl: 3600,
+        }
+    }
+}
+
+impl ChallengeConfig {
+    /// Configuration from `PICKUP_CHALLENGE`, `PICKUP_CHALLENGE_TTL_SECS`
+    /// and `PICKUP_AUTH_TTL_SECS`
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let secs = |var: &str| {
+            server_plugin::reload::var(var)
+                .and_then(|v| v.parse().ok())
+                .filter(|&secs: &i64| secs > 0)
+        };
+
+        Self {
+            required: server_plugin::reload::var("PICKUP_CHALLENGE")
+                .map(|v| v.eq_ignore_ascii_case("true"))
+                .unwrap_or(default.required),
+            challenge_ttl: secs("PICKUP_CHALLENGE_TTL_SECS").unwrap_or(default.challenge_ttl),
+            authentication_ttl: secs("PICKUP_AUTH_TTL_SECS").unwrap_or(default.authentication_ttl),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Nonce and expiry of the challenge pending per connection
+    pending: HashMap<String, (String, i64)>,
+
+    /// Session token and expiry of the authentication per connection
+    authenticated: HashMap<String, (String, i64)>,
+}
+
+/// Challenges of connections picking up over plain HTTP.
+/// Clones share the same challenges.
+#[derive(Debug, Clone, Default)]
+pub struct PickupChallenges {
+    config: ChallengeConfig,
+    state: Arc<Mutex<State>>,
+}
+
+impl PickupChallenges {
+    pub fn new(config: ChallengeConfig) -> Self {
+        Self {
+            config,
+            state: Arc::default(),
+        }
+    }
+
+    /// Challenge to answer in place of a message, if any. Only delivery
+    /// requests authenticated neither by authcrypt nor by the session token
+    /// of an answered challenge are challenged.
+    pub fn admit(
+        &self,
+        connection: &str,
+        message: &Value,
+        authcrypt: bool,
+        now: i64,
+    ) -> Option<Value> {
+        let token = message[SESSION_TOKEN_HEADER].as_str().unwrap_or_default();
+        let challenged = self.config.required
+            && !authcrypt
+            && message["type"].as_str() == Some(DELIVERY_REQUEST_3_0)
+            && !self.is_authenticated(connection, token, now);
+        if !challenged {
+            return None;
+        }
+
+        let mut challenge = self.issue(connection, now);
+        challenge.thid = message["id"].as_str().map(String::from);
+        Some(json!(challenge))
+    }
+
+    /// Issues a challenge to a connection, replacing any pending one
+    pub fn issue(&self, connection: &str, now: i64) -> Challenge {
+        let nonce = random_token();
+        let expires_time = now + self.config.challenge_ttl;
+
+        let mut state = self.state.lock().unwrap();
+        state
+            .pending
+            .insert(connection.to_owned(), (nonce.clone(), expires_time));
+
+        Challenge::new(
+            PICKUP_CHALLENGE_1_0,
+            ChallengeBody {
+                nonce,
+                expires_time,
+            },
+        )
+    }
+
+    /// Verifies the proof answering the challenge of a connection, returning
+    /// the recipient whose key signed it along with a fresh session token.
+    /// The challenge is consumed once answered, so that each nonce is
+    /// answered at most once, but survives invalid proofs, which would
+    /// otherwise let anyone cancel the challenges of others.
+    pub fn verify(
+        &self,
+        connection: &str,
+        keylist: &[String],
+        proof: &str,
+        now: i64,
+    ) -> Result<AuthenticatedBody, ChallengeError> {
+        let (nonce, expires_time) = self
+            .state
+            .lock()
+            .unwrap()
+            .pending
+            .get(connection)
+            .cloned()
+            .ok_or(ChallengeError::NoChallenge)?;
+        if now >= expires_time {
+            self.state.lock().unwrap().pending.remove(connection);
+            return Err(ChallengeError::Expired);
+        }
+
+        let kid = read_jws_header(proof)
+            .map_err(|_| ChallengeError::MalformedProof)?
+            .kid
+            .ok_or(ChallengeError::MalformedProof)?;
+        let did = did_of(&kid);
+        if !keylist.iter().any(|key| did_of(key) == did) {
+            return Err(ChallengeError::UnknownKey(kid));
+        }
+
+        let jwk = resolve_key(did).ok_or_else(|| ChallengeError::UnknownKey(kid.clone()))?;
+        verify_compact_jws(proof, &jwk).map_err(|_| ChallengeError::InvalidSignature)?;
+
+        let payload = read_payload(proof).ok_or(ChallengeError::MalformedProof)?;
+        let answered = payload["nonce"].as_str().unwrap_or_default();
+        if !ct_eq(answered.as_bytes(), nonce.as_bytes())
+            || payload["sub"].as_str() != Some(connection)
+        {
+            return Err(ChallengeError::Mismatch);
+        }
+
+        // The nonce may have been answered or replaced concurrently
+        let mut state = self.state.lock().unwrap();
+        match state.pending.get(connection) {
+            Some((pending, _)) if *pending == nonce => state.pending.remove(connection),
+            _ => return Err(ChallengeError::NoChallenge),
+        };
+
+        let session_token = random_token();
+        let expires_time = now + self.config.authentication_ttl;
+        state
+            .authenticated
+            .insert(connection.to_owned(), (session_token.clone(), expires_time));
+
+        Ok(AuthenticatedBody {
+            recipient_did: did.to_owned(),
+            session_token,
+            expires_time,
+        })
+    }
+
+    /// Whether a session token is that of a connection which answered a
+    /// challenge still valid
+    pub fn is_authenticated(&self, connection: &str, token: &str, now: i64) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .authenticated
+            .get(connection)
+            .is_some_and(|(session_token, expires_time)| {
+                now < *expires_time && ct_eq(token.as_bytes(), session_token.as_bytes())
+            })
+    }
+
+    /// Forgets the challenge and authentication of a connection
+    pub fn forget(&self, connection: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(connection);
+        state.authenticated.remove(connection);
+    }
+
+    /// Handles a plaintext challenge response from a connection, given the
+    /// keylist of the connection, returning the response message to send
+    /// back.
+    #[allow(clippy::result_large_err)]
+    pub fn handle(
+        &self,
+        connection: &str,
+        keylist: &[String],
+        message: &Value,
+        now: i64,
+    ) -> Result<Value, ProblemReport> {
+        MessageValidator::new(message_specs()).check(message)?;
+
+        let pthid = message.get("id").and_then(Value::as_str);
+        match message["type"].as_str().unwrap_or_default() {
+            PICKUP_CHALLENGE_RESPONSE_1_0 => {
+                let response: ChallengeResponse = deserialize(message)?;
+                let body = self
+                    .verify(connection, keylist, &response.body.proof, now)
+                    .map_err(|err| {
+                        tracing::warn!("challenge of connection {connection} failed: {err}");
+                        err.to_problem_report().with_pthid(pthid)
+                    })?;
+
+                Ok(json!(Authenticated::reply_to(
+                    &response,
+                    PICKUP_AUTHENTICATED_1_0,
+                    body
+                )))
+            }
+            // Challenges are issued, not handled, by the mediator
+            t => Err(ProblemReport::new(
+                UNSUPPORTED_MESSAGE_CODE,
+                Some("Unsupported message type {1}"),
+                Some(vec![t.to_owned()]),
+            )
+            .with_pthid(pthid)),
+        }
+    }
+}
+
+/// Random token of 244 bits, those of two UUIDv4, base64url-encoded
+fn random_token() -> String {
+    Base64Url.encode(
+        [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()]
+            .iter()
+            .flat_map(|uuid| *uuid.as_bytes())
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// DID of a DID or key identifier
+fn did_of(kid: &str) -> &str {
+    kid.split('#').next().unwrap_or(kid)
+}
+
+/// Public key of a did:key
+fn resolve_key(did: &str) -> Option<Jwk> {
+    let method = DIDKeyMethod {
+        key_format: PublicKeyFormat::Jwk,
+        ..Default::default()
+    };
+
+    let diddoc = method.expand(did).ok()?;
+    diddoc
+        .verification_method?
+        .into_iter()
+        .find_map(|method| match method.public_key {
+            Some(KeyFormat::Jwk(jwk)) => Some(jwk),
+            _ => None,
+        })
+}
+
+fn read_payload(compact_jws: &str) -> Option<Value> {
+    let payload = compact_jws.split('.').nth(1)?;
+    serde_json::from_slice(&Base64Url.decode(payload).ok()?).ok()
+}
+
+#[allow(clippy::result_large_err)]
+fn deserialize<B: DeserializeOwned + Default>(
+    message: &Value,
+) -> Result<CoordMessage<B>, ProblemReport> {
+    serde_json::from_value(message.clone()).map_err(|_| {
+        let pthid = message.get("id").and_then(Value::as_str);
+        ProblemReport::new(INVALID_MESSAGE_CODE, Some("Malformed message"), None).with_pthid(pthid)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        handler::MessageHandlers,
+        jose::jws::{make_compact_jws, JwsHeader},
+    };
+    use did_endpoint::util::test_utils::{Persona, TEST_EPOCH};
+
+    const CONNECTION: &str = "http-conn-1";
+
+    fn challenges() -> PickupChallenges {
+        PickupChallenges::new(ChallengeConfig {
+            required: true,
+            challenge_ttl: 60,
+            authentication_ttl: 600,
+        })
+    }
+
+    fn proof(persona: Persona, nonce: &str, connection: &str) -> String {
+        let did = persona.did();
+        let header = JwsHeader {
+            kid: Some(format!("{did}#{}", did.trim_start_matches("did:key:"))),
+            ..Default::default()
+        };
+
+        make_compact_jws(
+            &header,
+            json!({"nonce": nonce, "sub": connection}),
+            &persona.signing_jwk(),
+        )
+        .unwrap()
+    }
+
+    fn delivery_request() -> Value {
+        json!({
+            "id": "req-1",
+            "type": DELIVERY_REQUEST_3_0,
+            "body": {"limit": 10}
+        })
+    }
+
+    #[test]
+    fn test_challenging_delivery_requests() {
+        let challenges = challenges();
+        let keylist = vec![Persona::Alice.did()];
+
+        // Authcrypt connections and other messages are not challenged
+        assert!(challenges
+            .admit(CONNECTION, &delivery_request(), true, TEST_EPOCH)
+            .is_none());
+        let status_request = json!({"id": "req-0", "type": STATUS_REQUEST_3_0, "body": {}});
+        assert!(challenges
+            .admit(CONNECTION, &status_request, false, TEST_EPOCH)
+            .is_none());
+
+        let challenge = challenges
+            .admit(CONNECTION, &delivery_request(), false, TEST_EPOCH)
+            .unwrap();
+        assert_eq!(challenge["type"], PICKUP_CHALLENGE_1_0);
+        assert_eq!(challenge["thid"], "req-1");
+        assert_eq!(challenge["body"]["expires_time"], TEST_EPOCH + 60);
+
+        let nonce = challenge["body"]["nonce"].as_str().unwrap();
+        let response = json!({
+            "id": "resp-1",
+            "thid": challenge["id"],
+            "type": PICKUP_CHALLENGE_RESPONSE_1_0,
+            "body": {"proof": proof(Persona::Alice, nonce, CONNECTION)}
+        });
+        let authenticated = challenges
+            .handle(CONNECTION, &keylist, &response, TEST_EPOCH + 10)
+            .unwrap();
+        assert_eq!(authenticated["type"], PICKUP_AUTHENTICATED_1_0);
+        assert_eq!(authenticated["body"]["recipient_did"], Persona::Alice.did());
+
+        // Delivery requests carrying the session token are honored until
+        // the authentication expires
+        let mut request = delivery_request();
+        request[SESSION_TOKEN_HEADER] = authenticated["body"]["session_token"].clone();
+        assert!(challenges
+            .admit(CONNECTION, &request, false, TEST_EPOCH + 609)
+            .is_none());
+        assert!(challenges
+            .admit(CONNECTION, &request, false, TEST_EPOCH + 610)
+            .is_some());
+
+        // Replayed responses are rejected
+        let report = challenges
+            .handle(CONNECTION, &keylist, &response, TEST_EPOCH + 20)
+            .unwrap_err();
+        assert_eq!(report.body.code, CHALLENGE_FAILED_CODE);
+    }
+
+    #[test]
+    fn test_rejecting_invalid_proofs() {
+        let challenges = challenges();
+        let keylist = vec![Persona::Alice.did()];
+        let verify = |persona, connection, now| {
+            let nonce = challenges.issue(CONNECTION, TEST_EPOCH).body.nonce;
+            challenges.verify(
+                CONNECTION,
+                &keylist,
+                &proof(persona, &nonce, connection),
+                now,
+            )
+        };
+
+        assert_eq!(
+            verify(Persona::Bob, CONNECTION, TEST_EPOCH),
+            Err(ChallengeError::UnknownKey(format!(
+                "{}#{}",
+                Persona::Bob.did(),
+                Persona::Bob.did().trim_start_matches("did:key:")
+            )))
+        );
+        assert_eq!(
+            verify(Persona::Alice, "http-conn-2", TEST_EPOCH),
+            Err(ChallengeError::Mismatch)
+        );
+        assert_eq!(
+            verify(Persona::Alice, CONNECTION, TEST_EPOCH + 60),
+            Err(ChallengeError::Expired)
+        );
+        assert!(!challenges.is_authenticated(CONNECTION, "", TEST_EPOCH));
+
+        // Proofs signed by another key than their kid
+        let nonce = challenges.issue(CONNECTION, TEST_EPOCH).body.nonce;
+        let header = JwsHeader {
+            kid: Some(Persona::Alice.did()),
+            ..Default::default()
+        };
+        let forged = make_compact_jws(
+            &header,
+            json!({"nonce": nonce, "sub": CONNECTION}),
+            &Persona::Bob.signing_jwk(),
+        )
+        .unwrap();
+        assert_eq!(
+            challenges.verify(CONNECTION, &keylist, &forged, TEST_EPOCH),
+            Err(ChallengeError::InvalidSignature)
+        );
+
+        // which leave the challenge to be answered by the recipient
+        let genuine = proof(Persona::Alice, &nonce, CONNECTION);
+        challenges
+            .verify(CONNECTION, &keylist, &genuine, TEST_EPOCH)
+            .unwrap();
+        assert_eq!(
+            challenges.verify(CONNECTION, &keylist, &genuine, TEST_EPOCH),
+            Err(ChallengeError::NoChallenge)
+        );
+    }
+
+    #[test]
+    fn test_binding_authentication_to_session_tokens() {
+        let challenges = challenges();
+        let keylist = vec![Persona::Alice.did()];
+
+        let nonce = challenges.issue(CONNECTION, TEST_EPOCH).body.nonce;
+        let proof = proof(Persona::Alice, &nonce, CONNECTION);
+        let body = challenges
+            .verify(CONNECTION, &keylist, &proof, TEST_EPOCH)
+            .unwrap();
+        assert!(challenges.is_authenticated(CONNECTION, &body.session_token, TEST_EPOCH));
+
+        // Requests without the token, or with another, are challenged
+        assert!(challenges
+            .admit(CONNECTION, &delivery_request(), false, TEST_EPOCH)
+            .is_some());
+        let mut request = delivery_request();
+        request[SESSION_TOKEN_HEADER] = json!("forged");
+        assert!(challenges
+            .admit(CONNECTION, &request, false, TEST_EPOCH)
+            .is_some());
+
+        // as are requests carrying it over other connections
+        request[SESSION_TOKEN_HEADER] = json!(body.session_token);
+        assert!(challenges
+            .admit("http-conn-2", &request, false, TEST_EPOCH)
+            .is_some());
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)]
+    fn test_challenging_dispatched_delivery_requests() {
+        let handlers = MessageHandlers::new()
+            .with_challenges(challenges())
+            .register_fallback(|_, message| {
+                Ok(Some(json!({"thid": message["id"], "type": DELIVERY_3_0})))
+            });
+        let keylist = vec![Persona::Alice.did()];
+
+        // Senders authenticated by authcrypt are served
+        let reply = handlers.dispatch(CONNECTION, &delivery_request());
+        assert_eq!(reply.unwrap().unwrap()["type"], DELIVERY_3_0);
+
+        // while other connections must answer a challenge first
+        let challenge = handlers
+            .dispatch_unauthenticated(CONNECTION, &keylist, &delivery_request())
+            .unwrap()
+            .unwrap();
+        assert_eq!(challenge["type"], PICKUP_CHALLENGE_1_0);
+
+        let nonce = challenge["body"]["nonce"].as_str().unwrap();
+        let response = json!({
+            "id": "resp-1",
+            "thid": challenge["id"],
+            "type": PICKUP_CHALLENGE_RESPONSE_1_0,
+            "body": {"proof": proof(Persona::Alice, nonce, CONNECTION)}
+        });
+        let authenticated = handlers
+            .dispatch_unauthenticated(CONNECTION, &keylist, &response)
+            .unwrap()
+            .unwrap();
+        assert_eq!(authenticated["type"], PICKUP_AUTHENTICATED_1_0);
+
+        let mut request = delivery_request();
+        request[SESSION_TOKEN_HEADER] = authenticated["body"]["session_token"].clone();
+        let reply = handlers.dispatch_unauthenticated(CONNECTION, &keylist, &request);
+        assert_eq!(reply.unwrap().unwrap()["type"], DELIVERY_3_0);
+    }
+
+    #[test]
+    fn test_challenges_are_optional() {
+        let challenges = PickupChallenges::default();
+        assert!(challenges
+            .admit(CONNECTION, &delivery_request(), false, TEST_EPOCH)
+            .is_none());
+    }
+}
@@ -0,0 +1,299 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{
+    constants::*,
+    didcomm::{
+        problem_report::ProblemReport,
+        validation::{MessageValidator, ValidationError},
+    },
+    model::coord::*,
+};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ClientError {
+    #[error("invalid response: {0}")]
+    InvalidResponse(#[from] ValidationError),
+    #[error("response does not belong to the request's thread")]
+    ThreadMismatch,
+    #[error("unexpected response type `{0}`")]
+    UnexpectedType(String),
+    #[error("mediator reported a problem: {}", .0.body.code)]
+    ProblemReport(Box<ProblemReport>),
+}
+
+/// Outcome of a mediation request
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediationResponse {
+    Grant(MediateGrant),
+    Deny(MediateDeny),
+}
+
+/// Builds a request for mediation
+pub fn mediate_request() -> MediateRequest {
+    MediateRequest::new(MEDIATE_REQUEST_2_0, EmptyBody {})
+}
+
+/// Builds a request for mediation responding to an out-of-band invitation
+pub fn mediate_request_with_invitation(invitation_id: &str) -> MediateRequest {
+    MediateRequest {
+        pthid: Some(invitation_id.to_owned()),
+        ..mediate_request()
+    }
+}
+
+/// Builds a request to update the keys routed through the mediator
+pub fn keylist_update(updates: Vec<KeylistUpdateItem>) -> KeylistUpdate {
+    KeylistUpdate::new(KEYLIST_UPDATE_2_0, KeylistUpdateBody { updates })
+}
+
+/// Builds a request to query the keys routed through the mediator
+pub fn keylist_query(paginate: Option<KeylistQueryPaginate>) -> KeylistQuery {
+    KeylistQuery::new(KEYLIST_QUERY_2_0, KeylistQueryBody { paginate })
+}
+
+/// Parses the response to a mediation request
+pub fn parse_mediation_response(
+    request: &MediateRequest,
+    response: &Value,
+) -> Result<MediationResponse, ClientError> {
+    match check_response(request, response)? {
+        MEDIATE_GRANT_2_0 => Ok(MediationResponse::Grant(deserialize(response)?)),
+        MEDIATE_DENY_2_0 => Ok(MediationResponse::Deny(deserialize(response)?)),
+        t => Err(ClientError::UnexpectedType(t.to_owned())),
+    }
+}
+
+/// Parses the response to a keylist update
+pub fn parse_keylist_update_response(
+    request: &KeylistUpdate,
+    response: &Value,
+) -> Result<KeylistUpdateResponse, ClientError> {
+    match check_response(request, response)? {
+        KEYLIST_UPDATE_RESPONSE_2_0 => deserialize(response),
+        t => Err(ClientError::UnexpectedType(t.to_owned())),
+    }
+}
+
+/// Parses the response to a keylist query
+pub fn parse_keylist(request: &KeylistQuery, response: &Value) -> Result<Keylist, ClientError> {
+    match check_response(request, response)? {
+        KEYLIST_2_0 => deserialize(response),
+        t => Err(ClientError::UnexpectedType(t.to_owned())),
+    }
+}
+
+/// Checks that a response is well-formed and correlated to the request,
+/// returning its message type.
+fn check_response<'r, B>(
+    request: &CoordMessage<B>,
+    response: &'r Value,
+) -> Result<&'r str, ClientError> {
+    let message_type = response.get("type").and_then(Value::as_str);
+
+    let thid = match message_type {
+        Some(PROBLEM_REPORT_2_0) => response.get("pthid"),
+        _ => response.get("thid"),
+    };
+    if thid.and_then(Value::as_str) != Some(request.thid.as_ref().unwrap_or(&request.id)) {
+        return Err(ClientError::ThreadMismatch);
+    }
+
+    if message_type == Some(PROBLEM_REPORT_2_0) {
+        let report = serde_json::from_value(response.clone())
+            .map_err(|_| ValidationError::InvalidHeader(String::from("body")))?;
+        return Err(ClientError::ProblemReport(Box::new(report)));
+    }
+
+    MessageValidator::new(message_specs()).validate(response)?;
+
+    Ok(message_type.unwrap_or_default())
+}
+
+fn deserialize<T: DeserializeOwned>(response: &Value) -> Result<T, ClientError> {
+    serde_json::from_value(response.clone())
+        .map_err(|_| ValidationError::InvalidHeader(String::from("body")).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn can_build_requests() {
+        let request = mediate_request();
+        assert_eq!(
+            json!(request),
+            json!({
+                "id": request.id,
+                "type": MEDIATE_REQUEST_2_0,
+                "body": {}
+            })
+        );
+
+        let request = keylist_update(vec![KeylistUpdateItem {
+            recipient_did: String::from("did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH"),
+            action: KeylistUpdateAction::Add,
+        }]);
+        assert_eq!(
+            json!(request),
+            json!({
+                "id": request.id,
+                "type": KEYLIST_UPDATE_2_0,
+                "body": {
+                    "updates": [{
+                        "recipient_did": "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH",
+                        "action": "add"
+                    }]
+                }
+            })
+        );
+
+        let request = keylist_query(Some(KeylistQueryPaginate {
+            limit: 30,
+            offset: 0,
+        }));
+        assert_eq!(
+            json!(request),
+            json!({
+                "id": request.id,
+                "type": KEYLIST_QUERY_2_0,
+                "body": {
+                    "paginate": {"limit": 30, "offset": 0}
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn can_parse_mediation_responses() {
+        let request = mediate_request();
+
+        let response = json!({
+            "id": "123456781",
+            "thid": request.id,
+            "type": MEDIATE_GRANT_2_0,
+            "body": {
+                "routing_did": ["did:web:mediators-r-us.com"]
+            }
+        });
+        match parse_mediation_response(&request, &response).unwrap() {
+            MediationResponse::Grant(grant) => {
+                assert_eq!(grant.body.routing_did, vec!["did:web:mediators-r-us.com"])
+            }
+            _ => panic!("expected mediation grant"),
+        }
+
+        let response = json!({
+            "id": "123456781",
+            "thid": request.id,
+            "type": MEDIATE_DENY_2_0,
+        });
+        assert!(matches!(
+            parse_mediation_response(&request, &response).unwrap(),
+            MediationResponse::Deny(_)
+        ));
+    }
+
+    #[test]
+    fn can_parse_keylist_responses() {
+        let request = keylist_update(vec![KeylistUpdateItem {
+            recipient_did: String::from("did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH"),
+            action: KeylistUpdateAction::Add,
+        }]);
+        let response = json!({
+            "id": "123456781",
+            "thid": request.id,
+            "type": KEYLIST_UPDATE_RESPONSE_2_0,
+            "body": {
+                "updated": [{
+                    "recipient_did": "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH",
+                    "action": "add",
+                    "result": "success"
+                }]
+            }
+        });
+        let response = parse_keylist_update_response(&request, &response).unwrap();
+        assert_eq!(
+            response.body.updated[0].result,
+            KeylistUpdateResult::Success
+        );
+
+        let request = keylist_query(None);
+        let response = json!({
+            "id": "123456782",
+            "thid": request.id,
+            "type": KEYLIST_2_0,
+            "body": {
+                "keys": [{
+                    "recipient_did": "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH"
+                }],
+                "pagination": {"count": 1, "offset": 0, "remaining": 0}
+            }
+        });
+        let keylist = parse_keylist(&request, &response).unwrap();
+        assert_eq!(keylist.body.keys.len(), 1);
+    }
+
+    #[test]
+    fn should_err_on_uncorrelated_response() {
+        let request = mediate_request();
+        let response = json!({
+            "id": "123456781",
+            "thid": "another-thread",
+            "type": MEDIATE_DENY_2_0,
+        });
+
+        assert_eq!(
+            parse_mediation_response(&request, &response).unwrap_err(),
+            ClientError::ThreadMismatch
+        );
+    }
+
+    #[test]
+    fn should_err_on_unexpected_response_type() {
+        let request = keylist_query(None);
+        let response = json!({
+            "id": "123456781",
+            "thid": request.id,
+            "type": MEDIATE_DENY_2_0,
+        });
+
+        assert_eq!(
+            parse_keylist(&request, &response).unwrap_err(),
+            ClientError::UnexpectedType(String::from(MEDIATE_DENY_2_0))
+        );
+    }
+
+    #[test]
+    fn should_err_on_malformed_response() {
+        let request = mediate_request();
+        let response = json!({
+            "id": "123456781",
+            "thid": request.id,
+            "type": MEDIATE_GRANT_2_0,
+            "body": {}
+        });
+
+        assert_eq!(
+            parse_mediation_response(&request, &response).unwrap_err(),
+            ClientError::InvalidResponse(ValidationError::MissingField(String::from(
+                "routing_did"
+            )))
+        );
+    }
+
+    #[test]
+    fn should_surface_problem_reports() {
+        let request = mediate_request();
+        let report =
+            ProblemReport::new("e.p.req.not-authorized", None, None).with_pthid(Some(&request.id));
+
+        match parse_mediation_response(&request, &json!(report)).unwrap_err() {
+            ClientError::ProblemReport(received) => assert_eq!(*received, report),
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+}
@@ -1 +1,2 @@
+pub mod coord;
 pub mod dic;
@@ -0,0 +1,241 @@
+//! Compression of stored message payloads.
+//!
+//! Forwarded payloads are held until recipients pick them up, and large
+//! ones compress well. A [`PayloadCodec`] compresses payloads above a size
+//! threshold with zstd into [`StoredPayload`]s, which decode back to the
+//! original payload on pickup. Payloads are kept uncompressed whenever
+//! compression would not make them smaller.
+
+use multibase::Base::Base64Url;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CompressionError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("compressed data is not valid base64url")]
+    InvalidData,
+    #[error("decompressed payload is not valid UTF-8")]
+    InvalidPayload,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionConfig {
+    /// Whether payloads get compressed at all
+    pub enabled: bool,
+
+    /// Size from which payloads are compressed, in bytes
+    pub min_size: usize,
+
+    /// zstd compression level, from 1 to 22
+    pub level: i32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size: 1024,
+            level: 3,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Reads `PAYLOAD_COMPRESSION` (`zstd` or `off`),
+    /// `PAYLOAD_COMPRESSION_MIN_SIZE` and `PAYLOAD_COMPRESSION_LEVEL`,
+    /// falling back to defaults for unset or invalid values.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |key: &str| server_plugin::reload::var(key);
+
+        Self {
+            enabled: var("PAYLOAD_COMPRESSION").map_or(default.enabled, |v| v != "off"),
+            min_size: var("PAYLOAD_COMPRESSION_MIN_SIZE")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.min_size),
+            level: var("PAYLOAD_COMPRESSION_LEVEL")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.level),
+        }
+    }
+}
+
+/// Payload as stored, possibly compressed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "encoding", content = "data", rename_all = "lowercase")]
+pub enum StoredPayload {
+    Identity(String),
+    /// zstd-compressed payload, encoded as base64url
+    Zstd(String),
+}
+
+impl StoredPayload {
+    /// Size of the payload as stored, in bytes
+    pub fn stored_size(&self) -> usize {
+        match self {
+            Self::Identity(data) | Self::Zstd(data) => data.len(),
+        }
+    }
+}
+
+/// Compression activity since the codec was created
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressionStats {
+    /// Number of payloads stored compressed
+    pub compressed_payloads: u64,
+
+    /// Size of the payloads submitted for storage, in bytes
+    pub original_bytes: u64,
+
+    /// Size of the payloads as stored, in bytes
+    pub stored_bytes: u64,
+}
+
+impl CompressionStats {
+    pub fn bytes_saved(&self) -> u64 {
+        self.original_bytes.saturating_sub(self.stored_bytes)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PayloadCodec {
+    config: CompressionConfig,
+    stats: Mutex<CompressionStats>,
+}
+
+impl PayloadCodec {
+    pub fn new(config: CompressionConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn stats(&self) -> CompressionStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Prepares a payload for storage
+    pub fn encode(&self, payload: &str) -> StoredPayload {
+        let stored = if self.config.enabled && payload.len() >= self.config.min_size {
+            self.compress(payload)
+                .unwrap_or_else(|| StoredPayload::Identity(payload.to_owned()))
+        } else {
+            StoredPayload::Identity(payload.to_owned())
+        };
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.original_bytes += payload.len() as u64;
+        stats.stored_bytes += stored.stored_size() as u64;
+        if matches!(stored, StoredPayload::Zstd(_)) {
+            stats.compressed_payloads += 1;
+        }
+
+        stored
+    }
+
+    /// Restores a stored payload, whatever the configuration it was
+    /// stored under
+    pub fn decode(&self, stored: &StoredPayload) -> Result<String, CompressionError> {
+        match stored {
+            StoredPayload::Identity(payload) => Ok(payload.clone()),
+            StoredPayload::Zstd(data) => {
+                let compressed = Base64Url
+                    .decode(data)
+                    .map_err(|_| CompressionError::InvalidData)?;
+                let bytes = zstd::decode_all(&compressed[..]).map_err(CompressionError::IoError)?;
+
+                String::from_utf8(bytes).map_err(|_| CompressionError::InvalidPayload)
+            }
+        }
+    }
+
+    fn compress(&self, payload: &str) -> Option<StoredPayload> {
+        let compressed = zstd::encode_all(payload.as_bytes(), self.config.level)
+            .map_err(|err| tracing::warn!("failed to compress payload: {err}"))
+            .ok()?;
+
+        let data = Base64Url.encode(compressed);
+        (data.len() < payload.len()).then_some(StoredPayload::Zstd(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codec() -> PayloadCodec {
+        PayloadCodec::new(CompressionConfig {
+            min_size: 64,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn can_compress_large_payloads_transparently() {
+        let codec = codec();
+        let payload = r#"{"ciphertext":"AAAA"}"#.repeat(100);
+
+        let stored = codec.encode(&payload);
+        assert!(matches!(stored, StoredPayload::Zstd(_)));
+        assert_eq!(codec.decode(&stored).unwrap(), payload);
+
+        let stats = codec.stats();
+        assert_eq!(stats.compressed_payloads, 1);
+        assert_eq!(stats.original_bytes, payload.len() as u64);
+        assert!(stats.bytes_saved() > payload.len() as u64 / 2);
+    }
+
+    #[test]
+    fn can_keep_payloads_uncompressed() {
+        let codec = codec();
+
+        // Below the threshold
+        let stored = codec.encode("short");
+        assert_eq!(stored, StoredPayload::Identity("short".to_string()));
+
+        // Not worth compressing, as ciphertexts are
+        let random: Vec<u8> = (0..20)
+            .flat_map(|_| *uuid::Uuid::new_v4().as_bytes())
+            .collect();
+        let payload = Base64Url.encode(random);
+        assert_eq!(
+            codec.encode(&payload),
+            StoredPayload::Identity(payload.clone())
+        );
+
+        // Compression disabled
+        let codec = PayloadCodec::new(CompressionConfig {
+            enabled: false,
+            ..Default::default()
+        });
+        let payload = "a".repeat(4096);
+        assert_eq!(
+            codec.encode(&payload),
+            StoredPayload::Identity(payload.clone())
+        );
+        assert_eq!(codec.stats().bytes_saved(), 0);
+    }
+
+    #[test]
+    fn can_serialize_stored_payloads() {
+        let codec = codec();
+        let stored = codec.encode(&"a".repeat(1000));
+
+        let json = serde_json::to_value(&stored).unwrap();
+        assert_eq!(json["encoding"], "zstd");
+
+        let stored: StoredPayload = serde_json::from_value(json).unwrap();
+        assert_eq!(codec.decode(&stored).unwrap(), "a".repeat(1000));
+
+        let stored = StoredPayload::Zstd("!".to_string());
+        assert!(matches!(
+            codec.decode(&stored),
+            Err(CompressionError::InvalidData)
+        ));
+    }
+}
@@ -0,0 +1,47 @@
+//! Types of the messages handled, as declared by the registry of protocols
+
+use protocols_registry as registry;
+
+pub const MEDIATE_REQUEST_2_0: &str = registry::MEDIATE_REQUEST_2_0.as_str();
+pub const MEDIATE_DENY_2_0: &str = registry::MEDIATE_DENY_2_0.as_str();
+pub const MEDIATE_GRANT_2_0: &str = registry::MEDIATE_GRANT_2_0.as_str();
+pub const KEYLIST_UPDATE_2_0: &str = registry::KEYLIST_UPDATE_2_0.as_str();
+pub const KEYLIST_UPDATE_RESPONSE_2_0: &str = registry::KEYLIST_UPDATE_RESPONSE_2_0.as_str();
+pub const KEYLIST_QUERY_2_0: &str = registry::KEYLIST_QUERY_2_0.as_str();
+pub const KEYLIST_2_0: &str = registry::KEYLIST_2_0.as_str();
+
+pub const STATUS_REQUEST_3_0: &str = registry::STATUS_REQUEST_3_0.as_str();
+pub const STATUS_3_0: &str = registry::STATUS_3_0.as_str();
+pub const DELIVERY_REQUEST_3_0: &str = registry::DELIVERY_REQUEST_3_0.as_str();
+pub const DELIVERY_3_0: &str = registry::DELIVERY_3_0.as_str();
+pub const MESSAGES_RECEIVED_3_0: &str = registry::MESSAGES_RECEIVED_3_0.as_str();
+
+pub const FORWARD_2_0: &str = registry::FORWARD_2_0.as_str();
+
+pub const PROBLEM_REPORT_2_0: &str = registry::PROBLEM_REPORT_2_0.as_str();
+
+pub const DISCOVER_QUERIES_2_0: &str = registry::DISCOVER_QUERIES_2_0.as_str();
+pub const DISCOVER_DISCLOSE_2_0: &str = registry::DISCOVER_DISCLOSE_2_0.as_str();
+
+pub const STORAGE_PUT_1_0: &str = registry::STORAGE_PUT_1_0.as_str();
+pub const STORAGE_STORED_1_0: &str = registry::STORAGE_STORED_1_0.as_str();
+pub const STORAGE_GET_1_0: &str = registry::STORAGE_GET_1_0.as_str();
+pub const STORAGE_VALUE_1_0: &str = registry::STORAGE_VALUE_1_0.as_str();
+pub const STORAGE_DELETE_1_0: &str = registry::STORAGE_DELETE_1_0.as_str();
+pub const STORAGE_DELETED_1_0: &str = registry::STORAGE_DELETED_1_0.as_str();
+pub const STORAGE_LIST_1_0: &str = registry::STORAGE_LIST_1_0.as_str();
+pub const STORAGE_KEYS_1_0: &str = registry::STORAGE_KEYS_1_0.as_str();
+
+pub const DELIVERY_STATUS_QUERY_1_0: &str = registry::DELIVERY_STATUS_QUERY_1_0.as_str();
+pub const DELIVERY_STATUS_1_0: &str = registry::DELIVERY_STATUS_1_0.as_str();
+
+pub const MIGRATION_REQUEST_1_0: &str = registry::MIGRATION_REQUEST_1_0.as_str();
+pub const MIGRATION_EXPORT_1_0: &str = registry::MIGRATION_EXPORT_1_0.as_str();
+
+pub const DELIVERY_WINDOWS_SET_1_0: &str = registry::DELIVERY_WINDOWS_SET_1_0.as_str();
+pub const DELIVERY_WINDOWS_GET_1_0: &str = registry::DELIVERY_WINDOWS_GET_1_0.as_str();
+pub const DELIVERY_WINDOWS_1_0: &str = registry::DELIVERY_WINDOWS_1_0.as_str();
+
+pub const PICKUP_CHALLENGE_1_0: &str = registry::PICKUP_CHALLENGE_1_0.as_str();
+pub const PICKUP_CHALLENGE_RESPONSE_1_0: &str = registry::PICKUP_CHALLENGE_RESPONSE_1_0.as_str();
+pub const PICKUP_AUTHENTICATED_1_0: &str = registry::PICKUP_AUTHENTICATED_1_0.as_str();
@@ -0,0 +1,431 @@
+//! Load shedding through explicit degradation levels.
+//!
+//! Rather than timing out on everything under pressure, the mediator sheds
+//! its least essential work first. Health signals, such as storage latency,
+//! queue depth and memory usage, are compared against thresholds to pick a
+//! [`DegradationLevel`], which protocol handlers consult before serving.
+//! Levels are entered as soon as a threshold is crossed, but left only once
+//! signals have stayed below it for a recovery period, to avoid flapping.
+//!
+//! Signals the mediator observes by itself are sampled on a timer, so that
+//! levels follow load whether or not health probes are made.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use server_plugin::tasks::BackgroundTasks;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    constants::{FORWARD_2_0, MEDIATE_REQUEST_2_0},
+    didcomm::problem_report::ProblemReport,
+    pickup::PickupQueue,
+};
+
+/// Problem code for requests shed at the current degradation level
+pub const DEGRADED_SERVICE_CODE: &str = "e.p.me.res.degraded";
+
+/// Interval between samples of the signals observed by the mediator
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Service levels by increasing severity. Each level also sheds the work
+/// shed by the ones below it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DegradationLevel {
+    /// All features served
+    #[default]
+    Full,
+    /// Messages are queued for pickup instead of being pushed live
+    NoLiveDelivery,
+    /// Only forwarded messages are accepted, into queues
+    QueueOnly,
+    /// Additionally, new mediation requests are rejected
+    RejectNewMediations,
+}
+
+impl DegradationLevel {
+    pub fn allows_live_delivery(&self) -> bool {
+        *self < Self::NoLiveDelivery
+    }
+
+    /// Whether requests other than forwards are served, e.g. keylist
+    /// updates or pickups
+    pub fn allows_non_forward_requests(&self) -> bool {
+        *self < Self::QueueOnly
+    }
+
+    pub fn allows_new_mediations(&self) -> bool {
+        *self < Self::RejectNewMediations
+    }
+
+    /// Whether a message of a type is served, forwards being served at
+    /// all levels
+    pub fn allows(&self, message_type: &str) -> bool {
+        match message_type {
+            FORWARD_2_0 => true,
+            MEDIATE_REQUEST_2_0 => {
+                self.allows_new_mediations() && self.allows_non_forward_requests()
+            }
+            _ => self.allows_non_forward_requests(),
+        }
+    }
+}
+
+/// Latest known health signals, unknown ones being ignored
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthSignals {
+    /// Latency of storage operations, in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_latency_ms: Option<u64>,
+
+    /// Number of messages awaiting delivery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_depth: Option<u64>,
+
+    /// Fraction of memory in use, from 0 to 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_usage: Option<f64>,
+}
+
+impl HealthSignals {
+    /// Sample the signals the mediator can observe by itself
+    pub fn sample() -> Self {
+        Self {
+            memory_usage: system_memory_usage(),
+            ..Default::default()
+        }
+    }
+
+    /// Combine with more recent signals, keeping known values otherwise
+    pub fn merge(self, other: HealthSignals) -> Self {
+        Self {
+            storage_latency_ms: other.storage_latency_ms.or(self.storage_latency_ms),
+            queue_depth: other.queue_depth.or(self.queue_depth),
+            memory_usage: other.memory_usage.or(self.memory_usage),
+        }
+    }
+}
+
+/// Signal values from which a level is entered
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Thresholds {
+    pub storage_latency_ms: u64,
+    pub queue_depth: u64,
+    pub memory_usage: f64,
+}
+
+impl Thresholds {
+    fn exceeded_by(&self, signals: &HealthSignals) -> bool {
+        signals
+            .storage_latency_ms
+            .is_some_and(|v| v >= self.storage_latency_ms)
+            || signals.queue_depth.is_some_and(|v| v >= self.queue_depth)
+            || signals.memory_usage.is_some_and(|v| v >= self.memory_usage)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DegradationConfig {
+    pub no_live_delivery: Thresholds,
+    pub queue_only: Thresholds,
+    pub reject_new_mediations: Thresholds,
+
+    /// Seconds signals must stay below thresholds before recovering
+    pub recovery_period: i64,
+}
+
+impl Default for DegradationConfig {
+    fn default() -> Self {
+        Self {
+            no_live_delivery: Thresholds {
+                storage_latency_ms: 250,
+                queue_depth: 10_000,
+                memory_usage: 0.75,
+            },
+            queue_only: Thresholds {
+                storage_latency_ms: 1_000,
+                queue_depth: 50_000,
+                memory_usage: 0.85,
+            },
+            reject_new_mediations: Thresholds {
+                storage_latency_ms: 5_000,
+                queue_depth: 100_000,
+                memory_usage: 0.95,
+            },
+            recovery_period: 60,
+        }
+    }
+}
+
+impl DegradationConfig {
+    /// Most severe level whose thresholds are exceeded
+    pub fn level_for(&self, signals: &HealthSignals) -> DegradationLevel {
+        [
+            (
+                DegradationLevel::RejectNewMediations,
+                &self.reject_new_mediations,
+            ),
+            (DegradationLevel::QueueOnly, &self.queue_only),
+            (DegradationLevel::NoLiveDelivery, &self.no_live_delivery),
+        ]
+        .into_iter()
+        .find_map(|(level, thresholds)| thresholds.exceeded_by(signals).then_some(level))
+        .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Default)]
+struct ShedderState {
+    level: DegradationLevel,
+    signals: HealthSignals,
+    /// Since when signals call for a less severe level
+    calm_since: Option<i64>,
+}
+
+/// Tracks the degradation level from observed health signals. Clones
+/// share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct LoadShedder {
+    config: Arc<DegradationConfig>,
+    state: Arc<Mutex<ShedderState>>,
+}
+
+impl LoadShedder {
+    pub fn new(config: DegradationConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            state: Arc::default(),
+        }
+    }
+
+    /// Current level
+    pub fn level(&self) -> DegradationLevel {
+        self.state.lock().unwrap().level
+    }
+
+    /// Latest known signals
+    pub fn signals(&self) -> HealthSignals {
+        self.state.lock().unwrap().signals
+    }
+
+    /// Checks that a message is served at the current level, returning the
+    /// problem report to send back otherwise
+    #[allow(clippy::result_large_err)]
+    pub fn admit(&self, message: &Value) -> Result<(), ProblemReport> {
+        let message_type = message.get("type").and_then(Value::as_str).unwrap_or("");
+        let level = self.level();
+        if level.allows(message_type) {
+            return Ok(());
+        }
+
+        tracing::debug!("shedding {message_type} at level {level:?}");
+        Err(ProblemReport::new(
+            DEGRADED_SERVICE_CODE,
+            Some("Service degraded to level {1}, try again later"),
+            Some(vec![format!("{level:?}")]),
+        )
+        .with_pthid(message.get("id").and_then(Value::as_str)))
+    }
+
+    /// Samples the signals observed by the mediator, including the depth
+    /// of a queue, on a background task
+    pub fn sample_periodically(&self, tasks: &BackgroundTasks, queue: Arc<PickupQueue>) {
+        let shedder = self.clone();
+
+        tasks.every("degradation-sampling", SAMPLE_INTERVAL, move || {
+            let signals = HealthSignals {
+                queue_depth: Some(queue.totals().depth),
+                ..HealthSignals::sample()
+            };
+            shedder.observe(signals, chrono::Utc::now().timestamp());
+        });
+    }
+
+    /// Record health signals, switching levels as they call for.
+    /// Returns the level in effect.
+    pub fn observe(&self, signals: HealthSignals, now: i64) -> DegradationLevel {
+        let mut state = self.state.lock().unwrap();
+
+        state.signals = state.signals.merge(signals);
+        let target = self.config.level_for(&state.signals);
+
+        if target >= state.level {
+            state.calm_since = None;
+            if target > state.level {
+                tracing::warn!("degrading service from {:?} to {target:?}", state.level);
+                state.level = target;
+            }
+        } else {
+            let calm_since = *state.calm_since.get_or_insert(now);
+            if now - calm_since >= self.config.recovery_period {
+                tracing::info!("recovering service from {:?} to {target:?}", state.level);
+                state.level = target;
+                state.calm_since = None;
+            }
+        }
+
+        state.level
+    }
+}
+
+/// Fraction of system memory in use, where observable
+fn system_memory_usage() -> Option<f64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+
+    let field = |name: &str| -> Option<f64> {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name))?
+            .trim_start_matches(':')
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    };
+
+    let (total, available) = (field("MemTotal")?, field("MemAvailable")?);
+    (total > 0.0).then(|| 1.0 - available / total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signals(latency: u64, depth: u64, memory: f64) -> HealthSignals {
+        HealthSignals {
+            storage_latency_ms: Some(latency),
+            queue_depth: Some(depth),
+            memory_usage: Some(memory),
+        }
+    }
+
+    #[test]
+    fn can_map_signals_to_levels() {
+        let config = DegradationConfig::default();
+
+        assert_eq!(
+            config.level_for(&HealthSignals::default()),
+            DegradationLevel::Full
+        );
+        assert_eq!(
+            config.level_for(&signals(10, 0, 0.5)),
+            DegradationLevel::Full
+        );
+        assert_eq!(
+            config.level_for(&signals(300, 0, 0.5)),
+            DegradationLevel::NoLiveDelivery
+        );
+        assert_eq!(
+            config.level_for(&signals(10, 60_000, 0.5)),
+            DegradationLevel::QueueOnly
+        );
+        assert_eq!(
+            config.level_for(&signals(10, 60_000, 0.97)),
+            DegradationLevel::RejectNewMediations
+        );
+    }
+
+    #[test]
+    fn can_tell_what_levels_allow() {
+        let full = DegradationLevel::Full;
+        assert!(
+            full.allows_live_delivery()
+                && full.allows_non_forward_requests()
+                && full.allows_new_mediations()
+        );
+
+        let level = DegradationLevel::NoLiveDelivery;
+        assert!(!level.allows_live_delivery());
+        assert!(level.allows_non_forward_requests() && level.allows_new_mediations());
+
+        let level = DegradationLevel::QueueOnly;
+        assert!(!level.allows_non_forward_requests());
+        assert!(level.allows_new_mediations());
+
+        assert!(!DegradationLevel::RejectNewMediations.allows_new_mediations());
+
+        // Forwards are queued at all levels
+        assert!(level.allows(FORWARD_2_0));
+        assert!(!level.allows(MEDIATE_REQUEST_2_0));
+        assert!(DegradationLevel::NoLiveDelivery.allows(MEDIATE_REQUEST_2_0));
+        assert_eq!(
+            serde_json::to_string(&DegradationLevel::NoLiveDelivery).unwrap(),
+            r#""no-live-delivery""#
+        );
+    }
+
+    #[test]
+    fn can_degrade_at_once_and_recover_gradually() {
+        let shedder = LoadShedder::new(DegradationConfig {
+            recovery_period: 30,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            shedder.observe(signals(10, 0, 0.5), 0),
+            DegradationLevel::Full
+        );
+        assert_eq!(
+            shedder.observe(
+                HealthSignals {
+                    queue_depth: Some(60_000),
+                    ..Default::default()
+                },
+                10
+            ),
+            DegradationLevel::QueueOnly
+        );
+
+        // Known signals are kept until updated
+        assert_eq!(shedder.signals().storage_latency_ms, Some(10));
+
+        // Calm signals only lower the level after the recovery period
+        let calm = HealthSignals {
+            queue_depth: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(shedder.observe(calm, 20), DegradationLevel::QueueOnly);
+        assert_eq!(shedder.observe(calm, 40), DegradationLevel::QueueOnly);
+
+        // A relapse resets the recovery period
+        let overloaded = HealthSignals {
+            queue_depth: Some(60_000),
+            ..Default::default()
+        };
+        assert_eq!(shedder.observe(overloaded, 45), DegradationLevel::QueueOnly);
+
+        // Recovery may be partial
+        let busy = HealthSignals {
+            queue_depth: Some(20_000),
+            ..Default::default()
+        };
+        assert_eq!(shedder.observe(busy, 50), DegradationLevel::QueueOnly);
+        assert_eq!(shedder.observe(busy, 79), DegradationLevel::QueueOnly);
+        assert_eq!(shedder.observe(busy, 80), DegradationLevel::NoLiveDelivery);
+
+        assert_eq!(shedder.observe(calm, 85), DegradationLevel::NoLiveDelivery);
+        assert_eq!(shedder.clone().observe(calm, 115), DegradationLevel::Full);
+        assert_eq!(shedder.level(), DegradationLevel::Full);
+    }
+
+    #[test]
+    fn can_shed_requests() {
+        let shedder = LoadShedder::default();
+        let forward = serde_json::json!({"id": "1", "type": FORWARD_2_0});
+        let request = serde_json::json!({"id": "2", "type": MEDIATE_REQUEST_2_0});
+        assert!(shedder.admit(&request).is_ok());
+
+        shedder.observe(signals(10, 60_000, 0.5), 0);
+        assert!(shedder.admit(&forward).is_ok());
+
+        let report = shedder.admit(&request).unwrap_err();
+        assert_eq!(report.body.code, DEGRADED_SERVICE_CODE);
+        assert_eq!(report.pthid.as_deref(), Some("2"));
+    }
+}
@@ -0,0 +1,352 @@
+//! Delivery status tracking for forwarded messages.
+//!
+//! Forwarding is fire-and-forget by default: once a message is queued for
+//! its recipient, its sender learns nothing more about it. Senders wanting
+//! delivery guarantees set the `please_ack` header on the forward message.
+//! The mediator then tracks the message, under the forward's identifier,
+//! as it gets queued, delivered and acknowledged by the recipient through
+//! message pickup, and reports its state to the sender on request.
+//!
+//! Statuses are reported in `delivery-status` messages, which acknowledge
+//! the messages already acknowledged by their recipient with the `ack`
+//! header.
+
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    constants::*,
+    didcomm::{
+        problem_report::ProblemReport,
+        validation::{MessageValidator, INVALID_MESSAGE_CODE, UNSUPPORTED_MESSAGE_CODE},
+    },
+    model::delivery::*,
+};
+
+/// Time statuses are retained after their last transition, in seconds
+const DEFAULT_RETENTION: i64 = 7 * 24 * 3600;
+
+/// Maximum number of messages reported on per query
+const MAX_QUERIED_MESSAGES: usize = 100;
+
+#[derive(Debug)]
+struct Record {
+    /// Authenticated sender of the forward message
+    sender: String,
+    state: DeliveryState,
+    updated_time: i64,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    by_id: HashMap<String, Record>,
+
+    /// Transitions in chronological order, to expire records
+    transitions: VecDeque<(i64, String)>,
+}
+
+impl Records {
+    fn purge_expired(&mut self, retention: i64, now: i64) {
+        while let Some((time, _)) = self.transitions.front() {
+            if time.saturating_add(retention) > now {
+                break;
+            }
+
+            let (_, id) = self.transitions.pop_front().unwrap();
+            if self
+                .by_id
+                .get(&id)
+                .is_some_and(|record| record.updated_time.saturating_add(retention) <= now)
+            {
+                self.by_id.remove(&id);
+            }
+        }
+    }
+}
+
+/// Delivery states of messages whose sender requested acknowledgement.
+/// Clones share the same records.
+#[derive(Debug, Clone)]
+pub struct DeliveryTracker {
+    retention: i64,
+    records: Arc<Mutex<Records>>,
+}
+
+impl Default for DeliveryTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_RETENTION)
+    }
+}
+
+impl DeliveryTracker {
+    /// Creates a tracker retaining statuses for `retention` seconds after
+    /// their last transition
+    pub fn new(retention: i64) -> Self {
+        Self {
+            retention,
+            records: Arc::default(),
+        }
+    }
+
+    /// Whether a message requests acknowledgement
+    pub fn requests_ack(message: &Value) -> bool {
+        message
+            .get("please_ack")
+            .is_some_and(|value| !value.is_null())
+    }
+
+    /// Starts tracking a forward message from an authenticated sender if
+    /// it requests acknowledgement, returning whether it is tracked.
+    /// Other messages are left to fire and forget.
+    pub fn track(&self, sender: &str, message: &Value, now: i64) -> bool {
+        let Some(id) = message["id"].as_str() else {
+            return false;
+        };
+        if !Self::requests_ack(message) {
+            return false;
+        }
+
+        self.track_id(sender, id, now);
+        true
+    }
+
+    /// Starts tracking a message from an authenticated sender under an
+    /// identifier, e.g. a copy of a forward fanned out to several queues
+    pub fn track_id(&self, sender: &str, id: &str, now: i64) {
+        let mut records = self.records.lock().unwrap();
+        records.purge_expired(self.retention, now);
+
+        records.by_id.insert(
+            id.to_owned(),
+            Record {
+                sender: sender.to_owned(),
+                state: DeliveryState::Queued,
+                updated_time: now,
+            },
+        );
+        records.transitions.push_back((now, id.to_owned()));
+    }
+
+    /// Moves tracked messages to a state. Messages never move back, e.g.
+    /// when redelivered.
+    pub fn advance<'a>(
+        &self,
+        message_ids: impl IntoIterator<Item = &'a str>,
+        state: DeliveryState,
+        now: i64,
+    ) {
+        let mut records = self.records.lock().unwrap();
+        let records = &mut *records;
+
+        for id in message_ids {
+            if let Some(record) = records.by_id.get_mut(id) {
+                if record.state < state {
+                    record.state = state;
+                    record.updated_time = now;
+                    records.transitions.push_back((now, id.to_owned()));
+                }
+            }
+        }
+    }
+
+    /// Reports the states of messages to their sender. Messages forwarded
+    /// by others are reported as unknown.
+    pub fn report(&self, sender: &str, message_ids: &[String], now: i64) -> Vec<DeliveryReport> {
+        let mut records = self.records.lock().unwrap();
+        records.purge_expired(self.retention, now);
+
+        message_ids
+            .iter()
+            .map(|id| {
+                let record = records
+                    .by_id
+                    .get(id)
+                    .filter(|record| record.sender == sender);
+
+                DeliveryReport {
+                    message_id: id.clone(),
+                    state: record.map_or(DeliveryState::Unknown, |record| record.state),
+                    updated_time: record.map(|record| record.updated_time),
+                }
+            })
+            .collect()
+    }
+
+    /// Reports the states of the messages tracked for a sender
+    pub fn of_sender(&self, sender: &str, now: i64) -> Vec<DeliveryReport> {
+        let mut records = self.records.lock().unwrap();
+        records.purge_expired(self.retention, now);
+
+        let mut reports: Vec<_> = records
+            .by_id
+            .iter()
+            .filter(|(_, record)| record.sender == sender)
+            .map(|(id, record)| DeliveryReport {
+                message_id: id.clone(),
+                state: record.state,
+                updated_time: Some(record.updated_time),
+            })
+            .collect();
+        reports.sort_by(|a, b| a.message_id.cmp(&b.message_id));
+        reports
+    }
+
+    /// Stops tracking the messages of a sender, returning how many were
+    /// tracked
+    pub fn forget(&self, sender: &str) -> usize {
+        let mut records = self.records.lock().unwrap();
+        let count = records.by_id.len();
+        records.by_id.retain(|_, record| record.sender != sender);
+        count - records.by_id.len()
+    }
+
+    /// Handles a plaintext delivery status query from an authenticated
+    /// sender, returning the response message to send back.
+    #[allow(clippy::result_large_err)]
+    pub fn handle(&self, sender: &str, message: &Value) -> Result<Value, ProblemReport> {
+        self.handle_at(sender, message, Utc::now().timestamp())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn handle_at(&self, sender: &str, message: &Value, now: i64) -> Result<Value, ProblemReport> {
+        MessageValidator::new(message_specs()).check(message)?;
+
+        let pthid = message.get("id").and_then(Value::as_str);
+        match message["type"].as_str().unwrap_or_default() {
+            DELIVERY_STATUS_QUERY_1_0 => {
+                let query: DeliveryStatusQuery =
+                    serde_json::from_value(message.clone()).map_err(|_| {
+                        ProblemReport::new(INVALID_MESSAGE_CODE, Some("Malformed message"), None)
+                            .with_pthid(pthid)
+                    })?;
+
+                let message_ids = &query.body.message_id_list;
+                if message_ids.len() > MAX_QUERIED_MESSAGES {
+                    return Err(ProblemReport::new(
+                        INVALID_MESSAGE_CODE,
+                        Some("At most {1} messages may be queried at once"),
+                        Some(vec![MAX_QUERIED_MESSAGES.to_string()]),
+                    )
+                    .with_pthid(pthid));
+                }
+
+                let deliveries = self.report(sender, message_ids, now);
+                let acknowledged = deliveries
+                    .iter()
+                    .filter(|report| report.state == DeliveryState::Acknowledged)
+                    .map(|report| report.message_id.clone())
+                    .collect::<Vec<_>>();
+
+                let status = DeliveryStatus::reply_to(
+                    &query,
+                    DELIVERY_STATUS_1_0,
+                    DeliveryStatusBody { deliveries },
+                );
+                let status = match acknowledged.is_empty() {
+                    true => status,
+                    false => status.with_ack(acknowledged),
+                };
+
+                Ok(json!(status))
+            }
+            // Responses are not handled by the mediator
+            t => Err(ProblemReport::new(
+                UNSUPPORTED_MESSAGE_CODE,
+                Some("Unsupported message type {1}"),
+                Some(vec![t.to_owned()]),
+            )
+            .with_pthid(pthid)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+    const BOB: &str = "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7";
+
+    fn forward(id: &str, please_ack: bool) -> Value {
+        let mut message = json!({
+            "id": id,
+            "type": "https://didcomm.org/routing/2.0/forward",
+            "body": {"next": BOB}
+        });
+        if please_ack {
+            message["please_ack"] = json!([""]);
+        }
+        message
+    }
+
+    fn states(tracker: &DeliveryTracker, sender: &str, now: i64) -> Vec<DeliveryState> {
+        let ids = ["fwd-0", "fwd-1"].map(String::from);
+        let reports = tracker.report(sender, &ids, now);
+        reports.into_iter().map(|report| report.state).collect()
+    }
+
+    #[test]
+    fn can_track_messages_requesting_ack() {
+        let tracker = DeliveryTracker::new(3600);
+
+        assert!(tracker.track(ALICE, &forward("fwd-0", true), 1000));
+        assert!(!tracker.track(ALICE, &forward("fwd-1", false), 1000));
+        assert_eq!(
+            states(&tracker, ALICE, 1000),
+            [DeliveryState::Queued, DeliveryState::Unknown]
+        );
+
+        tracker.advance(["fwd-0"], DeliveryState::Delivered, 1010);
+        tracker.advance(["fwd-0"], DeliveryState::Acknowledged, 1020);
+
+        // Redeliveries do not move acknowledged messages back
+        tracker.advance(["fwd-0"], DeliveryState::Delivered, 1030);
+
+        let reports = tracker.report(ALICE, &["fwd-0".to_owned()], 1030);
+        assert_eq!(reports[0].state, DeliveryState::Acknowledged);
+        assert_eq!(reports[0].updated_time, Some(1020));
+
+        // Statuses are only disclosed to the sender
+        assert_eq!(states(&tracker, BOB, 1030)[0], DeliveryState::Unknown);
+
+        // and expire after their last transition
+        assert_eq!(
+            states(&tracker, ALICE, 4619)[0],
+            DeliveryState::Acknowledged
+        );
+        assert_eq!(states(&tracker, ALICE, 4620)[0], DeliveryState::Unknown);
+    }
+
+    #[test]
+    fn can_handle_status_queries() {
+        let tracker = DeliveryTracker::default();
+        tracker.track(ALICE, &forward("fwd-0", true), 1000);
+        tracker.track(ALICE, &forward("fwd-1", true), 1000);
+        tracker.advance(["fwd-0"], DeliveryState::Acknowledged, 1010);
+
+        let query = json!({
+            "id": "123456780",
+            "type": DELIVERY_STATUS_QUERY_1_0,
+            "body": {"message_id_list": ["fwd-0", "fwd-1"]}
+        });
+
+        let status = tracker.handle_at(ALICE, &query, 1020).unwrap();
+        assert_eq!(status["type"], DELIVERY_STATUS_1_0);
+        assert_eq!(status["thid"], "123456780");
+        assert_eq!(status["ack"], json!(["fwd-0"]));
+        assert_eq!(status["body"]["deliveries"][0]["state"], "acknowledged");
+        assert_eq!(status["body"]["deliveries"][1]["state"], "queued");
+
+        let invalid = json!({
+            "id": "123456781",
+            "type": DELIVERY_STATUS_QUERY_1_0,
+            "body": {}
+        });
+        let report = tracker.handle_at(ALICE, &invalid, 1020).unwrap_err();
+        assert_eq!(report.body.code, INVALID_MESSAGE_CODE);
+    }
+}
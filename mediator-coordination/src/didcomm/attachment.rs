@@ -0,0 +1,389 @@
+use did_utils::crypto::sha256_hash::{sha256_hash, sha256_multihash, SHA2_256_MULTIHASH_CODE};
+use multibase::Base::{Base58Btc, Base64Url, Base64UrlPad};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+/// Digest length of SHA2-256
+const SHA2_256_LEN: u8 = 0x20;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum AttachmentError {
+    #[error("invalid base64 data")]
+    InvalidBase64,
+    #[error("invalid hash encoding")]
+    InvalidHash,
+    #[error("missing hash for links attachment")]
+    MissingHash,
+    #[error("hash does not match content")]
+    HashMismatch,
+    #[error("unsupported hash algorithm")]
+    UnsupportedHashAlgorithm,
+}
+
+/// DIDComm attachment, as per the DIDComm Messaging v2 specification.
+///
+/// See https://identity.foundation/didcomm-messaging/spec/#attachments
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Attachment {
+    /// Attachment identifier, unique within the enclosing message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// Human-readable description of the content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// Hint about the name that might be used to save the content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+
+    /// Media type of the attached content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+
+    /// Further describes the format of the attachment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+
+    /// Last modification time, in seconds since the Unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lastmod_time: Option<u64>,
+
+    /// Size of the content in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byte_count: Option<u64>,
+
+    /// Attached content
+    pub data: AttachmentData,
+}
+
+/// Content of an attachment, in one of the three supported data formats.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum AttachmentData {
+    /// Content fetchable from remote locations, integrity-protected by hash
+    Links { links: Vec<String>, hash: String },
+
+    /// Content inlined as base64url-encoded bytes
+    Base64 {
+        base64: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        hash: Option<String>,
+    },
+
+    /// Content inlined as a JSON value
+    Json {
+        json: Value,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        hash: Option<String>,
+    },
+}
+
+/// Decoded content of an attachment.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttachmentContent<'a> {
+    Bytes(Vec<u8>),
+    Json(&'a Value),
+    Links(&'a [String]),
+}
+
+impl Attachment {
+    /// Creates an attachment inlining bytes in the base64 format
+    pub fn from_base64(content: &[u8]) -> Self {
+        Self::new(AttachmentData::Base64 {
+            base64: Base64Url.encode(content),
+            hash: None,
+        })
+    }
+
+    /// Creates an attachment inlining a JSON value
+    pub fn from_json(content: Value) -> Self {
+        Self::new(AttachmentData::Json {
+            json: content,
+            hash: None,
+        })
+    }
+
+    /// Creates an attachment referencing remote content.
+    ///
+    /// The content is only used to compute the hash the recipient
+    /// will check the fetched content against.
+    pub fn from_links(links: Vec<String>, content: &[u8]) -> Self {
+        Self {
+            byte_count: Some(content.len() as u64),
+            ..Self::new(AttachmentData::Links {
+                links,
+                hash: multihash_sha256(content),
+            })
+        }
+    }
+
+    fn new(data: AttachmentData) -> Self {
+        Self {
+            id: None,
+            description: None,
+            filename: None,
+            media_type: None,
+            format: None,
+            lastmod_time: None,
+            byte_count: None,
+            data,
+        }
+    }
+
+    /// Sets the attachment identifier
+    pub fn with_id(self, id: &str) -> Self {
+        Self {
+            id: Some(id.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the media type of the attached content
+    pub fn with_media_type(self, media_type: &str) -> Self {
+        Self {
+            media_type: Some(media_type.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the format of the attached content
+    pub fn with_format(self, format: &str) -> Self {
+        Self {
+            format: Some(format.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the filename hint of the attached content
+    pub fn with_filename(self, filename: &str) -> Self {
+        Self {
+            filename: Some(filename.to_owned()),
+            ..self
+        }
+    }
+
+    /// Sets the description of the attached content
+    pub fn with_description(self, description: &str) -> Self {
+        Self {
+            description: Some(description.to_owned()),
+            ..self
+        }
+    }
+
+    /// Decodes the attached content.
+    ///
+    /// Inlined base64 content is checked against its hash if one is present.
+    pub fn content(&self) -> Result<AttachmentContent<'_>, AttachmentError> {
+        match &self.data {
+            AttachmentData::Base64 { base64, hash } => {
+                let bytes = Base64Url
+                    .decode(base64)
+                    .or_else(|_| Base64UrlPad.decode(base64))
+                    .map_err(|_| AttachmentError::InvalidBase64)?;
+
+                if let Some(hash) = hash {
+                    verify_multihash(hash, &bytes)?;
+                }
+
+                Ok(AttachmentContent::Bytes(bytes))
+            }
+            AttachmentData::Json { json, .. } => Ok(AttachmentContent::Json(json)),
+            AttachmentData::Links { links, .. } => Ok(AttachmentContent::Links(links)),
+        }
+    }
+
+    /// Verifies content fetched from one of the links of a links attachment
+    pub fn verify_fetched_content(&self, content: &[u8]) -> Result<(), AttachmentError> {
+        let hash = match &self.data {
+            AttachmentData::Links { hash, .. } => Some(hash),
+            AttachmentData::Base64 { hash, .. } | AttachmentData::Json { hash, .. } => {
+                hash.as_ref()
+            }
+        };
+
+        verify_multihash(hash.ok_or(AttachmentError::MissingHash)?, content)
+    }
+}
+
+/// Computes the multibase-encoded (base58btc) SHA2-256 multihash of content
+pub fn multihash_sha256(content: &[u8]) -> String {
+    multibase::encode(Base58Btc, sha256_multihash(content))
+}
+
+/// Checks that a multibase-encoded multihash matches content
+pub fn verify_multihash(hash: &str, content: &[u8]) -> Result<(), AttachmentError> {
+    let (_, multihash) = multibase::decode(hash).map_err(|_| AttachmentError::InvalidHash)?;
+
+    match multihash.as_slice() {
+        [SHA2_256_MULTIHASH_CODE, SHA2_256_LEN, digest @ ..]
+            if digest.len() == SHA2_256_LEN as usize =>
+        {
+            if digest == sha256_hash(content) {
+                Ok(())
+            } else {
+                Err(AttachmentError::HashMismatch)
+            }
+        }
+        [SHA2_256_MULTIHASH_CODE, ..] => Err(AttachmentError::InvalidHash),
+        _ => Err(AttachmentError::UnsupportedHashAlgorithm),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn can_create_and_parse_base64_attachment() {
+        let attachment = Attachment::from_base64(b"Hello, world!")
+            .with_id("1")
+            .with_media_type("text/plain");
+
+        let expected = json!({
+            "id": "1",
+            "media_type": "text/plain",
+            "data": {
+                "base64": "SGVsbG8sIHdvcmxkIQ"
+            }
+        });
+        assert_eq!(json!(attachment), expected);
+
+        let attachment: Attachment = serde_json::from_value(expected).unwrap();
+        assert_eq!(
+            attachment.content().unwrap(),
+            AttachmentContent::Bytes(b"Hello, world!".to_vec())
+        );
+    }
+
+    #[test]
+    fn can_parse_padded_base64_attachment() {
+        let attachment: Attachment = serde_json::from_value(json!({
+            "data": {
+                "base64": "SGVsbG8sIHdvcmxkIQ=="
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            attachment.content().unwrap(),
+            AttachmentContent::Bytes(b"Hello, world!".to_vec())
+        );
+    }
+
+    #[test]
+    fn can_create_and_parse_json_attachment() {
+        let attachment = Attachment::from_json(json!({"content": "value"}));
+
+        let expected = json!({
+            "data": {
+                "json": {"content": "value"}
+            }
+        });
+        assert_eq!(json!(attachment), expected);
+
+        let attachment: Attachment = serde_json::from_value(expected).unwrap();
+        assert_eq!(
+            attachment.content().unwrap(),
+            AttachmentContent::Json(&json!({"content": "value"}))
+        );
+    }
+
+    #[test]
+    fn can_create_and_parse_links_attachment() {
+        let links = vec![String::from("https://example.com/hello.txt")];
+        let attachment = Attachment::from_links(links.clone(), b"Hello, world!");
+
+        let expected = json!({
+            "byte_count": 13,
+            "data": {
+                "links": ["https://example.com/hello.txt"],
+                "hash": "zQmRfP2G7Nb6SiPZqQxMxtZ1f4hBjY2JGkWvuxvUhkWm6ca"
+            }
+        });
+        assert_eq!(json!(attachment), expected);
+
+        let attachment: Attachment = serde_json::from_value(expected).unwrap();
+        assert_eq!(
+            attachment.content().unwrap(),
+            AttachmentContent::Links(&links)
+        );
+    }
+
+    #[test]
+    fn can_verify_fetched_content_of_links_attachment() {
+        let attachment = Attachment::from_links(
+            vec![String::from("https://example.com/hello.txt")],
+            b"Hello, world!",
+        );
+
+        assert!(attachment.verify_fetched_content(b"Hello, world!").is_ok());
+        assert_eq!(
+            attachment
+                .verify_fetched_content(b"Hello, world?")
+                .unwrap_err(),
+            AttachmentError::HashMismatch
+        );
+    }
+
+    #[test]
+    fn should_err_on_links_attachment_without_hash() {
+        let attachment: Result<Attachment, _> = serde_json::from_value(json!({
+            "data": {
+                "links": ["https://example.com/hello.txt"]
+            }
+        }));
+
+        assert!(attachment.is_err());
+    }
+
+    #[test]
+    fn should_err_on_base64_attachment_with_non_matching_hash() {
+        let attachment: Attachment = serde_json::from_value(json!({
+            "data": {
+                "base64": "SGVsbG8sIHdvcmxkPw",
+                "hash": multihash_sha256(b"Hello, world!")
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            attachment.content().unwrap_err(),
+            AttachmentError::HashMismatch
+        );
+    }
+
+    #[test]
+    fn should_err_on_invalid_base64_attachment() {
+        let attachment: Attachment = serde_json::from_value(json!({
+            "data": {
+                "base64": "*****"
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            attachment.content().unwrap_err(),
+            AttachmentError::InvalidBase64
+        );
+    }
+
+    #[test]
+    fn should_err_on_invalid_multihashes() {
+        let entries = [
+            ("not multibase", AttachmentError::InvalidHash),
+            ("f12200102", AttachmentError::InvalidHash),
+            (
+                // SHA2-512 multihash code
+                "z8VweiGai8Q5PuYyqu6R4wMQPrYERfWKrzyC3YW2zBy5ZFvPr8MWLdiFWxVWFUaXSqi9fY71HrpG6mMhLErFBTUjNAc",
+                AttachmentError::UnsupportedHashAlgorithm,
+            ),
+        ];
+
+        for (hash, err) in entries {
+            assert_eq!(verify_multihash(hash, b"Hello, world!").unwrap_err(), err);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Custom headers of DIDComm messages.
+//!
+//! Typed message models only know the headers of their protocol, and
+//! would drop the others when a message is decoded then encoded again.
+//! [`CustomHeaders`] keep them, so that extensions such as hop traces,
+//! acknowledgement requests or policy hints survive mediation.
+//!
+//! Ephemeral headers are specific to the message carrying them: envelope
+//! headers set when packing it, acknowledgement requests, delivery timing,
+//! disclosures of supported protocols and hop traces.
+//! They are preserved along with the message, but neither carried over to
+//! the messages replying to it nor stored. Other headers are persistent.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    negotiation::SUPPORTED_PROTOCOLS_HEADER,
+    packing::PACKING_HEADER,
+    timing::{DELIVER_AFTER_HEADER, EXPIRY_NOTICE_HEADER},
+    trace::TRACE_HEADER,
+};
+
+/// Headers specific to the message carrying them
+pub const EPHEMERAL_HEADERS: &[&str] = &[
+    "from",
+    "to",
+    "created_time",
+    "expires_time",
+    "from_prior",
+    "please_ack",
+    PACKING_HEADER,
+    SUPPORTED_PROTOCOLS_HEADER,
+    DELIVER_AFTER_HEADER,
+    EXPIRY_NOTICE_HEADER,
+    TRACE_HEADER,
+];
+
+/// Whether a header is specific to the message carrying it
+pub fn is_ephemeral(name: &str) -> bool {
+    EPHEMERAL_HEADERS.contains(&name)
+}
+
+/// Headers of a message besides the ones of its model, by name
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(transparent)]
+pub struct CustomHeaders(Map<String, Value>);
+
+impl CustomHeaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    /// Sets a header, returning its previous value
+    pub fn insert(&mut self, name: &str, value: Value) -> Option<Value> {
+        self.0.insert(name.to_owned(), value)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.0.remove(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.0.iter()
+    }
+
+    /// Persistent headers only
+    pub fn persistent(&self) -> Self {
+        let headers = self
+            .0
+            .iter()
+            .filter(|(name, _)| !is_ephemeral(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        Self(headers)
+    }
+
+    /// Removes ephemeral headers, e.g. before storing a message
+    pub fn strip_ephemeral(&mut self) {
+        self.0.retain(|name, _| !is_ephemeral(name));
+    }
+
+    /// Sets headers onto a plaintext message, without overriding the ones
+    /// it already has
+    pub fn inject(&self, message: &mut Value) {
+        let Some(message) = message.as_object_mut() else {
+            return;
+        };
+
+        for (name, value) in &self.0 {
+            if !message.contains_key(name) {
+                message.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+impl FromIterator<(String, Value)> for CustomHeaders {
+    fn from_iter<I: IntoIterator<Item = (String, Value)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn headers() -> CustomHeaders {
+        CustomHeaders::from_iter([
+            ("policy_hint".to_owned(), json!("retain-7d")),
+            ("please_ack".to_owned(), json!([""])),
+            (TRACE_HEADER.to_owned(), json!([])),
+        ])
+    }
+
+    #[test]
+    fn test_telling_ephemeral_headers() {
+        let mut headers = headers();
+
+        let persistent = headers.persistent();
+        assert_eq!(persistent.iter().count(), 1);
+        assert_eq!(persistent.get("policy_hint"), Some(&json!("retain-7d")));
+
+        headers.strip_ephemeral();
+        assert_eq!(headers, persistent);
+    }
+
+    #[test]
+    fn test_injecting_headers() {
+        let mut message = json!({"id": "123", "policy_hint": "retain-1d"});
+        headers().inject(&mut message);
+
+        assert_eq!(message["policy_hint"], "retain-1d");
+        assert_eq!(message["please_ack"], json!([""]));
+        assert_eq!(message["trace"], json!([]));
+
+        let mut message = json!("not an object");
+        headers().inject(&mut message);
+        assert_eq!(message, json!("not an object"));
+    }
+}
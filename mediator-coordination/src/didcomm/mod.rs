@@ -0,0 +1,5 @@
+pub mod attachment;
+pub mod headers;
+pub mod problem_report;
+pub mod resolution;
+pub mod validation;
@@ -0,0 +1,163 @@
+use did_utils::methods::errors::DIDResolutionError;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::PROBLEM_REPORT_2_0;
+
+/// Problem code descriptor for failures related to DIDs
+pub const DID_PROBLEM_CODE_PREFIX: &str = "e.p.did";
+
+/// Problem report message, as per the Report Problem 2.0 protocol.
+///
+/// See https://identity.foundation/didcomm-messaging/spec/#problem-reports
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProblemReport {
+    /// Message identifier
+    pub id: String,
+
+    /// Message type
+    #[serde(rename = "type")]
+    pub message_type: String,
+
+    /// Identifier of the thread in which the problem occurred
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pthid: Option<String>,
+
+    /// Message body
+    pub body: ProblemReportBody,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProblemReportBody {
+    /// Problem code, e.g. `e.p.msg.invalid`
+    pub code: String,
+
+    /// Human-readable description, with `{n}` placeholders for args
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+
+    /// Values to interpolate into the comment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+
+    /// URI where additional help on the issue can be obtained
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub escalate_to: Option<String>,
+}
+
+impl ProblemReport {
+    /// Creates a problem report with a fresh identifier
+    pub fn new(code: &str, comment: Option<&str>, args: Option<Vec<String>>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            message_type: PROBLEM_REPORT_2_0.to_owned(),
+            pthid: None,
+            body: ProblemReportBody {
+                code: code.to_owned(),
+                comment: comment.map(String::from),
+                args,
+                escalate_to: None,
+            },
+        }
+    }
+
+    /// Creates a problem report for a message that could not be processed
+    /// because resolving one of its DIDs failed.
+    ///
+    /// The code derives from the DID resolution error, e.g. `notFound`
+    /// yields `e.p.did.not-found`.
+    pub fn from_resolution_error(did: &str, err: &DIDResolutionError) -> Self {
+        let error = serde_json::to_value(err)
+            .ok()
+            .and_then(|value| value.as_str().map(String::from))
+            .unwrap_or_default();
+
+        // Problem code tokens are kebab-cased
+        let token = error.chars().fold(String::new(), |mut token, c| {
+            if c.is_ascii_uppercase() {
+                token.push('-');
+            }
+            token.push(c.to_ascii_lowercase());
+            token
+        });
+
+        Self::new(
+            &format!("{DID_PROBLEM_CODE_PREFIX}.{token}"),
+            Some("Failed to resolve DID {1}: {2}"),
+            Some(vec![did.to_owned(), error]),
+        )
+    }
+
+    /// Attaches the report to the thread of the offending message
+    pub fn with_pthid(self, pthid: Option<&str>) -> Self {
+        Self {
+            pthid: pthid.map(String::from),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn can_serde_problem_report() {
+        let report = ProblemReport::new(
+            "e.p.msg.invalid",
+            Some("Invalid field {1}"),
+            Some(vec![String::from("updates")]),
+        )
+        .with_pthid(Some("1e513ad4-48c9-444e-9e7e-5b8b45c5e325"));
+
+        let mut value = json!(report);
+        assert!(value["id"].as_str().is_some());
+
+        value["id"] = Value::Null;
+        assert_eq!(
+            value,
+            json!({
+                "id": null,
+                "type": "https://didcomm.org/report-problem/2.0/problem-report",
+                "pthid": "1e513ad4-48c9-444e-9e7e-5b8b45c5e325",
+                "body": {
+                    "code": "e.p.msg.invalid",
+                    "comment": "Invalid field {1}",
+                    "args": ["updates"]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn can_report_resolution_errors() {
+        let did = "did:web:alice.example.com";
+
+        let cases = [
+            (
+                DIDResolutionError::NotFound,
+                "e.p.did.not-found",
+                "notFound",
+            ),
+            (
+                DIDResolutionError::InvalidDid,
+                "e.p.did.invalid-did",
+                "invalidDid",
+            ),
+            (
+                DIDResolutionError::RepresentationNotSupported,
+                "e.p.did.representation-not-supported",
+                "representationNotSupported",
+            ),
+        ];
+
+        for (err, code, error) in cases {
+            let report = ProblemReport::from_resolution_error(did, &err);
+            assert_eq!(report.body.code, code);
+            assert_eq!(
+                report.body.args,
+                Some(vec![String::from(did), String::from(error)])
+            );
+        }
+    }
+}
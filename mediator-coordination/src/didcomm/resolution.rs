@@ -0,0 +1,92 @@
+//! Resolution of the DIDs referred to by messages.
+//!
+//! DIDs of the methods resolvable offline, did:key and did:peer, are
+//! resolved as messages referring to them are processed, so that DIDs no
+//! party could ever reach, e.g. with malformed keys, are rejected upfront
+//! with a problem report detailing the resolution error. DIDs which cannot
+//! be resolved offline, e.g. did:web or short form did:peer addresses, are
+//! accepted as they are.
+
+use did_utils::methods::{
+    did_key::DIDKeyMethod, did_peer::DIDPeerMethod, errors::DIDResolutionError,
+};
+
+use super::problem_report::ProblemReport;
+
+/// Checks that a DID, or the DID of a DID URL, resolves, as far as it can
+/// be resolved offline
+pub fn check(did_url: &str) -> Result<(), DIDResolutionError> {
+    let did = did_url.split(['/', '?', '#']).next().unwrap_or_default();
+
+    let resolved = if did.starts_with("did:key:") {
+        DIDKeyMethod::default().expand(did).map(|_| ())
+    } else if did.starts_with("did:peer:") {
+        DIDPeerMethod.expand(did).map(|_| ())
+    } else {
+        Ok(())
+    };
+
+    match resolved {
+        Err(DIDResolutionError::NotFound | DIDResolutionError::MethodNotSupported) => Ok(()),
+        resolved => resolved,
+    }
+}
+
+/// Checks that the DIDs referred to by a message resolve, reporting the
+/// first failure in the thread of the message
+#[allow(clippy::result_large_err)]
+pub fn check_all<'a>(
+    did_urls: impl IntoIterator<Item = &'a str>,
+    pthid: Option<&str>,
+) -> Result<(), ProblemReport> {
+    for did_url in did_urls {
+        check(did_url).map_err(|err| {
+            tracing::debug!("failed to resolve {did_url}: {err}");
+            ProblemReport::from_resolution_error(did_url, &err).with_pthid(pthid)
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+
+    #[test]
+    fn can_check_resolution_of_dids() {
+        assert_eq!(check(ALICE), Ok(()));
+        assert_eq!(check(&format!("{ALICE}#key-1")), Ok(()));
+        assert_eq!(
+            check("did:key:z6MkInvalid#key-1"),
+            Err(DIDResolutionError::InvalidDid)
+        );
+
+        // DIDs which cannot be resolved offline are accepted
+        assert_eq!(check("did:web:alice.example.com#key-1"), Ok(()));
+        assert_eq!(
+            check("did:peer:4zQmd8CpeFPci817KDsbSAKWcXAE2mjvCQSasRewvbSF54Bd"),
+            Ok(())
+        );
+        assert_eq!(
+            check("did:peer:2.Ez6LSbysY2xFMRpGMhb7tFTLMpeuPRaqaWM1yECx2AtzE3KCc"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn should_report_resolution_failures() {
+        let report = check_all([ALICE, "did:key:zInvalid"], Some("1")).unwrap_err();
+        assert_eq!(report.body.code, "e.p.did.invalid-did");
+        assert_eq!(
+            report.body.args,
+            Some(vec![
+                String::from("did:key:zInvalid"),
+                String::from("invalidDid")
+            ])
+        );
+        assert_eq!(report.pthid.as_deref(), Some("1"));
+    }
+}
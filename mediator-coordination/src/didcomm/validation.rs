@@ -0,0 +1,510 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use thiserror::Error;
+
+use super::problem_report::ProblemReport;
+
+/// Problem code for messages failing validation
+pub const INVALID_MESSAGE_CODE: &str = "e.p.msg.invalid";
+
+/// Problem code for messages of unregistered types
+pub const UNSUPPORTED_MESSAGE_CODE: &str = "e.p.msg.unsupported";
+
+/// JSON Schema dialect of generated schemas
+pub const JSON_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ValidationError {
+    #[error("message is not a JSON object")]
+    NotAnObject,
+    #[error("missing header `{0}`")]
+    MissingHeader(String),
+    #[error("invalid header `{0}`")]
+    InvalidHeader(String),
+    #[error("unsupported message type `{0}`")]
+    UnsupportedType(String),
+    #[error("missing body field `{0}`")]
+    MissingField(String),
+    #[error("invalid body field `{path}`: expected {expected}")]
+    InvalidField { path: String, expected: String },
+}
+
+/// Expected shape of a JSON value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldKind {
+    Any,
+    String,
+    Integer,
+    Boolean,
+    /// String among a fixed set of values
+    Enum(&'static [&'static str]),
+    /// Array whose items all have the given shape
+    Array(&'static FieldKind),
+    /// Object with the given fields
+    Object(&'static [FieldSpec]),
+}
+
+/// Declaration of a field of a message body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSpec {
+    pub name: &'static str,
+    pub kind: FieldKind,
+    pub required: bool,
+}
+
+/// Declaration of the expected shape of a message type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageSpec {
+    /// Message type URI
+    pub message_type: &'static str,
+
+    /// Headers required in addition to `id` and `type`
+    pub required_headers: &'static [&'static str],
+
+    /// Fields of the message body
+    pub body: &'static [FieldSpec],
+}
+
+/// Registry of message specs against which incoming messages are
+/// checked before being handed over to protocol handlers.
+#[derive(Debug, Clone, Default)]
+pub struct MessageValidator {
+    specs: HashMap<&'static str, MessageSpec>,
+}
+
+impl MessageValidator {
+    /// Creates a validator from a set of message specs
+    pub fn new(specs: impl IntoIterator<Item = MessageSpec>) -> Self {
+        let mut validator = Self::default();
+        for spec in specs {
+            validator.register(spec);
+        }
+
+        validator
+    }
+
+    /// Registers a message spec, replacing any prior one for the same type
+    pub fn register(&mut self, spec: MessageSpec) {
+        self.specs.insert(spec.message_type, spec);
+    }
+
+    /// Looks up the spec registered for a message type
+    pub fn spec(&self, message_type: &str) -> Option<&MessageSpec> {
+        self.specs.get(message_type)
+    }
+
+    /// Iterates over registered specs
+    pub fn specs(&self) -> impl Iterator<Item = &MessageSpec> {
+        self.specs.values()
+    }
+
+    /// Validates a plaintext message against its registered spec
+    pub fn validate(&self, message: &Value) -> Result<(), ValidationError> {
+        let headers = message.as_object().ok_or(ValidationError::NotAnObject)?;
+
+        for header in ["id", "type"] {
+            match headers.get(header) {
+                None => return Err(ValidationError::MissingHeader(header.to_owned())),
+                Some(Value::String(_)) => (),
+                Some(_) => return Err(ValidationError::InvalidHeader(header.to_owned())),
+            }
+        }
+
+        let message_type = headers["type"].as_str().unwrap_or_default();
+        let spec = self
+            .spec(message_type)
+            .ok_or_else(|| ValidationError::UnsupportedType(message_type.to_owned()))?;
+
+        for header in spec.required_headers {
+            match headers.get(*header) {
+                None | Some(Value::Null) => {
+                    return Err(ValidationError::MissingHeader(header.to_string()))
+                }
+                Some(_) => (),
+            }
+        }
+
+        let body = headers.get("body").unwrap_or(&Value::Null);
+        let empty = serde_json::Map::new();
+        let body = match body {
+            Value::Object(body) => body,
+            Value::Null if spec.body.iter().all(|field| !field.required) => &empty,
+            Value::Null => return Err(ValidationError::MissingHeader(String::from("body"))),
+            _ => return Err(ValidationError::InvalidHeader(String::from("body"))),
+        };
+
+        validate_fields(body, spec.body, "")
+    }
+
+    /// Validates a plaintext message, reporting failures as problem reports
+    #[allow(clippy::result_large_err)]
+    pub fn check(&self, message: &Value) -> Result<(), ProblemReport> {
+        self.validate(message).map_err(|err| {
+            let pthid = message
+                .get("thid")
+                .or_else(|| message.get("id"))
+                .and_then(Value::as_str);
+
+            err.to_problem_report().with_pthid(pthid)
+        })
+    }
+}
+
+impl ValidationError {
+    /// Describes the error as a problem report
+    pub fn to_problem_report(&self) -> ProblemReport {
+        let (code, comment, args) = match self {
+            Self::NotAnObject => (INVALID_MESSAGE_CODE, "Message is not a JSON object", vec![]),
+            Self::MissingHeader(h) => (INVALID_MESSAGE_CODE, "Missing header {1}", vec![h.clone()]),
+            Self::InvalidHeader(h) => (INVALID_MESSAGE_CODE, "Invalid header {1}", vec![h.clone()]),
+            Self::UnsupportedType(t) => (
+                UNSUPPORTED_MESSAGE_CODE,
+                "Unsupported message type {1}",
+                vec![t.clone()],
+            ),
+            Self::MissingField(f) => (
+                INVALID_MESSAGE_CODE,
+                "Missing body field {1}",
+                vec![f.clone()],
+            ),
+            Self::InvalidField { path, expected } => (
+                INVALID_MESSAGE_CODE,
+                "Invalid body field {1}: expected {2}",
+                vec![path.clone(), expected.clone()],
+            ),
+        };
+
+        ProblemReport::new(code, Some(comment), (!args.is_empty()).then_some(args))
+    }
+}
+
+fn validate_fields(
+    object: &serde_json::Map<String, Value>,
+    fields: &[FieldSpec],
+    prefix: &str,
+) -> Result<(), ValidationError> {
+    for field in fields {
+        let path = format!("{prefix}{}", field.name);
+        match object.get(field.name) {
+            None | Some(Value::Null) if field.required => {
+                return Err(ValidationError::MissingField(path))
+            }
+            None | Some(Value::Null) => (),
+            Some(value) => validate_value(value, &field.kind, &path)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_value(value: &Value, kind: &FieldKind, path: &str) -> Result<(), ValidationError> {
+    let invalid = |expected: String| ValidationError::InvalidField {
+        path: path.to_owned(),
+        expected,
+    };
+
+    match (kind, value) {
+        (FieldKind::Any, _) => Ok(()),
+        (FieldKind::String, Value::String(_)) => Ok(()),
+        (FieldKind::Integer, Value::Number(n)) if n.is_i64() || n.is_u64() => Ok(()),
+        (FieldKind::Boolean, Value::Bool(_)) => Ok(()),
+        (FieldKind::Enum(variants), Value::String(s)) if variants.contains(&s.as_str()) => Ok(()),
+        (FieldKind::Array(item), Value::Array(items)) => items
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, value)| validate_value(value, item, &format!("{path}/{i}"))),
+        (FieldKind::Object(fields), Value::Object(object)) => {
+            validate_fields(object, fields, &format!("{path}/"))
+        }
+        _ => Err(invalid(kind.describe())),
+    }
+}
+
+impl FieldKind {
+    fn describe(&self) -> String {
+        match self {
+            Self::Any => String::from("any value"),
+            Self::String => String::from("string"),
+            Self::Integer => String::from("integer"),
+            Self::Boolean => String::from("boolean"),
+            Self::Enum(variants) => format!("one of [{}]", variants.join(", ")),
+            Self::Array(item) => format!("array of {}", item.describe()),
+            Self::Object(_) => String::from("object"),
+        }
+    }
+
+    /// Describes the expected shape as a JSON Schema
+    pub fn json_schema(&self) -> Value {
+        match self {
+            Self::Any => json!({}),
+            Self::String => json!({"type": "string"}),
+            Self::Integer => json!({"type": "integer"}),
+            Self::Boolean => json!({"type": "boolean"}),
+            Self::Enum(variants) => json!({"type": "string", "enum": variants}),
+            Self::Array(item) => json!({"type": "array", "items": item.json_schema()}),
+            Self::Object(fields) => object_json_schema(fields),
+        }
+    }
+}
+
+impl MessageSpec {
+    /// Describes the expected shape of messages as a JSON Schema
+    pub fn json_schema(&self) -> Value {
+        let mut required = vec!["id", "type"];
+        required.extend(self.required_headers);
+
+        let mut properties = serde_json::Map::new();
+        properties.insert(String::from("id"), json!({"type": "string"}));
+        properties.insert(String::from("type"), json!({"const": self.message_type}));
+        for header in self.required_headers {
+            properties.insert(header.to_string(), json!({}));
+        }
+
+        if self.body.iter().any(|field| field.required) {
+            required.push("body");
+        }
+        properties.insert(String::from("body"), object_json_schema(self.body));
+
+        json!({
+            "$schema": JSON_SCHEMA_DIALECT,
+            "$id": self.message_type,
+            "type": "object",
+            "required": required,
+            "properties": properties,
+        })
+    }
+}
+
+fn object_json_schema(fields: &[FieldSpec]) -> Value {
+    let required: Vec<_> = fields
+        .iter()
+        .filter_map(|field| field.required.then_some(field.name))
+        .collect();
+    let properties: serde_json::Map<_, _> = fields
+        .iter()
+        .map(|field| (field.name.to_owned(), field.kind.json_schema()))
+        .collect();
+
+    json!({
+        "type": "object",
+        "required": required,
+        "properties": properties,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPDATE_FIELDS: &[FieldSpec] = &[
+        FieldSpec {
+            name: "recipient_did",
+            kind: FieldKind::String,
+            required: true,
+        },
+        FieldSpec {
+            name: "action",
+            kind: FieldKind::Enum(&["add", "remove"]),
+            required: true,
+        },
+    ];
+
+    fn validator() -> MessageValidator {
+        MessageValidator::new([
+            MessageSpec {
+                message_type: "https://example.com/protocol/1.0/ping",
+                required_headers: &[],
+                body: &[],
+            },
+            MessageSpec {
+                message_type: "https://example.com/protocol/1.0/update",
+                required_headers: &["from"],
+                body: &[
+                    FieldSpec {
+                        name: "updates",
+                        kind: FieldKind::Array(&FieldKind::Object(UPDATE_FIELDS)),
+                        required: true,
+                    },
+                    FieldSpec {
+                        name: "limit",
+                        kind: FieldKind::Integer,
+                        required: false,
+                    },
+                ],
+            },
+        ])
+    }
+
+    #[test]
+    fn can_validate_conforming_messages() {
+        let validator = validator();
+
+        let messages = [
+            json!({
+                "id": "1",
+                "type": "https://example.com/protocol/1.0/ping"
+            }),
+            json!({
+                "id": "2",
+                "type": "https://example.com/protocol/1.0/update",
+                "from": "did:example:alice",
+                "body": {
+                    "updates": [{"recipient_did": "did:key:z6Mk", "action": "add"}]
+                }
+            }),
+        ];
+
+        for message in messages {
+            assert_eq!(validator.validate(&message), Ok(()));
+        }
+    }
+
+    #[test]
+    fn should_err_on_nonconforming_messages() {
+        let validator = validator();
+
+        let entries = [
+            (json!([]), ValidationError::NotAnObject),
+            (
+                json!({"type": "https://example.com/protocol/1.0/ping"}),
+                ValidationError::MissingHeader(String::from("id")),
+            ),
+            (
+                json!({"id": 1, "type": "https://example.com/protocol/1.0/ping"}),
+                ValidationError::InvalidHeader(String::from("id")),
+            ),
+            (
+                json!({"id": "1", "type": "https://example.com/protocol/1.0/unknown"}),
+                ValidationError::UnsupportedType(String::from(
+                    "https://example.com/protocol/1.0/unknown",
+                )),
+            ),
+            (
+                json!({
+                    "id": "1",
+                    "type": "https://example.com/protocol/1.0/update",
+                    "body": {"updates": []}
+                }),
+                ValidationError::MissingHeader(String::from("from")),
+            ),
+            (
+                json!({
+                    "id": "1",
+                    "type": "https://example.com/protocol/1.0/update",
+                    "from": "did:example:alice",
+                }),
+                ValidationError::MissingHeader(String::from("body")),
+            ),
+            (
+                json!({
+                    "id": "1",
+                    "type": "https://example.com/protocol/1.0/update",
+                    "from": "did:example:alice",
+                    "body": {}
+                }),
+                ValidationError::MissingField(String::from("updates")),
+            ),
+            (
+                json!({
+                    "id": "1",
+                    "type": "https://example.com/protocol/1.0/update",
+                    "from": "did:example:alice",
+                    "body": {
+                        "updates": [
+                            {"recipient_did": "did:key:z6Mk", "action": "add"},
+                            {"recipient_did": "did:key:z6Mk", "action": "replace"}
+                        ]
+                    }
+                }),
+                ValidationError::InvalidField {
+                    path: String::from("updates/1/action"),
+                    expected: String::from("one of [add, remove]"),
+                },
+            ),
+            (
+                json!({
+                    "id": "1",
+                    "type": "https://example.com/protocol/1.0/update",
+                    "from": "did:example:alice",
+                    "body": {"updates": [], "limit": "ten"}
+                }),
+                ValidationError::InvalidField {
+                    path: String::from("limit"),
+                    expected: String::from("integer"),
+                },
+            ),
+        ];
+
+        for (message, err) in entries {
+            assert_eq!(validator.validate(&message).unwrap_err(), err);
+        }
+    }
+
+    #[test]
+    fn can_report_validation_failures_as_problem_reports() {
+        let validator = validator();
+
+        let message = json!({
+            "id": "1",
+            "thid": "0",
+            "type": "https://example.com/protocol/1.0/update",
+            "from": "did:example:alice",
+            "body": {"updates": [{"action": "add"}]}
+        });
+
+        let report = validator.check(&message).unwrap_err();
+        assert_eq!(report.pthid.as_deref(), Some("0"));
+        assert_eq!(report.body.code, INVALID_MESSAGE_CODE);
+        assert_eq!(
+            report.body.args,
+            Some(vec![String::from("updates/0/recipient_did")])
+        );
+
+        let message = json!({"id": "1", "type": "https://example.com/protocol/1.0/unknown"});
+
+        let report = validator.check(&message).unwrap_err();
+        assert_eq!(report.pthid.as_deref(), Some("1"));
+        assert_eq!(report.body.code, UNSUPPORTED_MESSAGE_CODE);
+    }
+
+    #[test]
+    fn can_describe_message_specs_as_json_schemas() {
+        let validator = validator();
+        let spec = validator
+            .spec("https://example.com/protocol/1.0/update")
+            .unwrap();
+
+        assert_eq!(
+            spec.json_schema(),
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "$id": "https://example.com/protocol/1.0/update",
+                "type": "object",
+                "required": ["id", "type", "from", "body"],
+                "properties": {
+                    "id": {"type": "string"},
+                    "type": {"const": "https://example.com/protocol/1.0/update"},
+                    "from": {},
+                    "body": {
+                        "type": "object",
+                        "required": ["updates"],
+                        "properties": {
+                            "updates": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "required": ["recipient_did", "action"],
+                                    "properties": {
+                                        "recipient_did": {"type": "string"},
+                                        "action": {"type": "string", "enum": ["add", "remove"]}
+                                    }
+                                }
+                            },
+                            "limit": {"type": "integer"}
+                        }
+                    }
+                }
+            })
+        );
+    }
+}
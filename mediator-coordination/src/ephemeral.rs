@@ -0,0 +1,345 @@
+//! Session-bound ephemeral keys of live delivery channels.
+//!
+//! Messages pushed over a long-lived channel, e.g. a WebSocket session,
+//! are packed with an X25519 key generated for the session rather than
+//! with the static key agreement of the mediator. The key is announced to
+//! the peer when the session opens, rotated periodically, and discarded
+//! along with the session, so that a compromised session exposes neither
+//! the messages of other sessions nor those of its own past periods.
+//!
+//! Keys rotated out remain usable for a grace period, for messages packed
+//! by the peer before it learned of the rotation. No sessions are opened
+//! while live delivery is shed, messages being queued for pickup instead.
+
+use did_utils::{
+    crypto::{
+        traits::{Generate, KeyMaterial, ECDH},
+        x25519::X25519KeyPair,
+    },
+    key_jwk::{
+        jwk::Jwk,
+        key::Key,
+        okp::{Okp, OkpCurves},
+        prm::Parameters,
+        Bytes,
+    },
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+use crate::degradation::LoadShedder;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SessionKeyError {
+    #[error("no open session {0}")]
+    UnknownSession(String),
+    #[error("no usable key {0} in session")]
+    UnknownKey(String),
+    #[error("failed to generate session key")]
+    GenerationError,
+    #[error("key agreement failed")]
+    KeyExchangeError,
+    #[error("live delivery unavailable under load")]
+    Degraded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionKeyConfig {
+    /// Seconds a key is used for before being rotated
+    pub rotation_interval: i64,
+
+    /// Seconds a key rotated out remains usable
+    pub grace_period: i64,
+}
+
+impl Default for SessionKeyConfig {
+    fn default() -> Self {
+        Self {
+            rotation_interval: 3600,
+            grace_period: 60,
+        }
+    }
+}
+
+impl SessionKeyConfig {
+    /// Configuration from `SESSION_KEY_ROTATION_SECS` and
+    /// `SESSION_KEY_GRACE_SECS`
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let secs = |var: &str| {
+            server_plugin::reload::var(var)
+                .and_then(|v| v.parse().ok())
+                .filter(|&secs: &i64| secs > 0)
+        };
+
+        Self {
+            rotation_interval: secs("SESSION_KEY_ROTATION_SECS")
+                .unwrap_or(default.rotation_interval),
+            grace_period: secs("SESSION_KEY_GRACE_SECS").unwrap_or(default.grace_period),
+        }
+    }
+}
+
+/// Ephemeral key of a session
+struct SessionKey {
+    kid: String,
+    keypair: X25519KeyPair,
+
+    /// Time the key was generated, as a UNIX timestamp
+    created: i64,
+}
+
+impl SessionKey {
+    fn generate(session_id: &str, serial: u32, now: i64) -> Result<Self, SessionKeyError> {
+        Ok(Self {
+            kid: format!("{session_id}#ephemeral-{serial}"),
+            keypair: X25519KeyPair::new().map_err(|_| SessionKeyError::GenerationError)?,
+            created: now,
+        })
+    }
+
+    /// Public key, to be announced to the peer
+    fn public_jwk(&self) -> Jwk {
+        let x = self.keypair.public_key_bytes().unwrap_or_default();
+
+        Jwk {
+            key: Key::Okp(Okp {
+                crv: OkpCurves::X25519,
+                x: Bytes::from(x.to_vec()),
+                d: None,
+            }),
+            prm: Parameters {
+                kid: Some(self.kid.clone()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Keys of a session: the current one, and the one it replaced
+struct SessionKeys {
+    serial: u32,
+    current: SessionKey,
+    previous: Option<SessionKey>,
+}
+
+/// Current key of a session, as announced to its peer
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnouncedKey {
+    pub jwk: Jwk,
+
+    /// Whether the key was rotated in, and must be announced anew
+    pub rotated: bool,
+}
+
+/// Ephemeral keys of open sessions. Clones share the same keys.
+#[derive(Clone, Default)]
+pub struct SessionKeyRing {
+    config: SessionKeyConfig,
+    shedder: LoadShedder,
+    sessions: Arc<Mutex<HashMap<String, SessionKeys>>>,
+}
+
+impl SessionKeyRing {
+    pub fn new(config: SessionKeyConfig) -> Self {
+        Self {
+            config,
+            shedder: LoadShedder::default(),
+            sessions: Arc::default(),
+        }
+    }
+
+    /// Refuses to open sessions at levels shedding live delivery
+    pub fn with_shedder(self, shedder: LoadShedder) -> Self {
+        Self { shedder, ..self }
+    }
+
+    /// Opens a session, returning the key to announce to its peer. Keys of
+    /// a session reopened under the same identifier are replaced.
+    /// Sessions are refused while live delivery is shed.
+    pub fn open(&self, session_id: &str, now: i64) -> Result<Jwk, SessionKeyError> {
+        if !self.shedder.level().allows_live_delivery() {
+            return Err(SessionKeyError::Degraded);
+        }
+
+        let keys = SessionKeys {
+            serial: 0,
+            current: SessionKey::generate(session_id, 0, now)?,
+            previous: None,
+        };
+        let jwk = keys.current.public_jwk();
+
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(session_id.to_owned(), keys);
+
+        Ok(jwk)
+    }
+
+    /// Key to pack the messages of a session with, rotated if due
+    pub fn current(&self, session_id: &str, now: i64) -> Result<AnnouncedKey, SessionKeyError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let keys = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| SessionKeyError::UnknownSession(session_id.to_owned()))?;
+
+        let rotated = now - keys.current.created >= self.config.rotation_interval;
+        if rotated {
+            let fresh = SessionKey::generate(session_id, keys.serial + 1, now)?;
+            keys.serial += 1;
+            keys.previous = Some(std::mem::replace(&mut keys.current, fresh));
+            tracing::debug!("rotated ephemeral key of session {session_id}");
+        }
+
+        Ok(AnnouncedKey {
+            jwk: keys.current.public_jwk(),
+            rotated,
+        })
+    }
+
+    /// Agrees on a shared secret between a key of a session and a key of
+    /// its peer. Keys rotated out are usable for the grace period only.
+    pub fn key_exchange(
+        &self,
+        session_id: &str,
+        kid: &str,
+        their_public: &[u8; 32],
+        now: i64,
+    ) -> Result<Vec<u8>, SessionKeyError> {
+        let sessions = self.sessions.lock().unwrap();
+        let keys = sessions
+            .get(session_id)
+            .ok_or_else(|| SessionKeyError::UnknownSession(session_id.to_owned()))?;
+
+        // The previous key was rotated out when the current one was created
+        let in_grace = now - keys.current.created < self.config.grace_period;
+        let key = if keys.current.kid == kid {
+            &keys.current
+        } else {
+            keys.previous
+                .as_ref()
+                .filter(|key| key.kid == kid && in_grace)
+                .ok_or_else(|| SessionKeyError::UnknownKey(kid.to_owned()))?
+        };
+
+        let theirs = X25519KeyPair::from_public_key(their_public)
+            .map_err(|_| SessionKeyError::KeyExchangeError)?;
+        key.keypair
+            .key_exchange(&theirs)
+            .ok_or(SessionKeyError::KeyExchangeError)
+    }
+
+    /// Closes a session, discarding its keys
+    pub fn close(&self, session_id: &str) -> bool {
+        self.sessions.lock().unwrap().remove(session_id).is_some()
+    }
+
+    /// Number of open sessions
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use did_endpoint::util::test_utils::TEST_EPOCH;
+
+    const SESSION: &str = "ws-1";
+
+    fn ring() -> SessionKeyRing {
+        SessionKeyRing::new(SessionKeyConfig {
+            rotation_interval: 600,
+            grace_period: 30,
+        })
+    }
+
+    fn public_bytes(jwk: &Jwk) -> [u8; 32] {
+        match &jwk.key {
+            Key::Okp(okp) => okp.x.to_vec().try_into().unwrap(),
+            _ => panic!("not an OKP key"),
+        }
+    }
+
+    #[test]
+    fn test_agreeing_on_session_keys() {
+        let ring = ring();
+        let announced = ring.open(SESSION, TEST_EPOCH).unwrap();
+        let kid = announced.prm.kid.clone().unwrap();
+        assert_eq!(kid, "ws-1#ephemeral-0");
+        assert!(matches!(&announced.key, Key::Okp(okp) if okp.d.is_none()));
+
+        // Both ends derive the same secret
+        let peer = X25519KeyPair::new().unwrap();
+        let ours = ring
+            .key_exchange(SESSION, &kid, &peer.public_key_bytes().unwrap(), TEST_EPOCH)
+            .unwrap();
+        let session_public = X25519KeyPair::from_public_key(&public_bytes(&announced)).unwrap();
+        assert_eq!(ours, peer.key_exchange(&session_public).unwrap());
+
+        // Keys are not shared across sessions
+        let other = ring.open("ws-2", TEST_EPOCH).unwrap();
+        assert_ne!(public_bytes(&other), public_bytes(&announced));
+        assert_eq!(ring.len(), 2);
+
+        assert!(ring.close(SESSION));
+        assert_eq!(
+            ring.current(SESSION, TEST_EPOCH),
+            Err(SessionKeyError::UnknownSession(SESSION.to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_rotating_session_keys() {
+        let ring = ring();
+        let first = ring.open(SESSION, TEST_EPOCH).unwrap();
+        let peer = X25519KeyPair::new().unwrap().public_key_bytes().unwrap();
+
+        let current = ring.current(SESSION, TEST_EPOCH + 599).unwrap();
+        assert!(!current.rotated);
+        assert_eq!(current.jwk, first);
+
+        let current = ring.current(SESSION, TEST_EPOCH + 600).unwrap();
+        assert!(current.rotated);
+        assert_eq!(current.jwk.prm.kid.as_deref(), Some("ws-1#ephemeral-1"));
+
+        // Keys rotated out remain usable for the grace period only
+        let first_kid = first.prm.kid.unwrap();
+        ring.key_exchange(SESSION, &first_kid, &peer, TEST_EPOCH + 629)
+            .unwrap();
+        assert_eq!(
+            ring.key_exchange(SESSION, &first_kid, &peer, TEST_EPOCH + 630),
+            Err(SessionKeyError::UnknownKey(first_kid))
+        );
+        ring.key_exchange(SESSION, "ws-1#ephemeral-1", &peer, TEST_EPOCH + 630)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_refusing_sessions_when_degraded() {
+        let shedder = LoadShedder::default();
+        let ring = ring().with_shedder(shedder.clone());
+        ring.open(SESSION, TEST_EPOCH).unwrap();
+
+        shedder.observe(
+            crate::degradation::HealthSignals {
+                storage_latency_ms: Some(300),
+                ..Default::default()
+            },
+            TEST_EPOCH,
+        );
+        assert_eq!(
+            ring.open("ws-2", TEST_EPOCH),
+            Err(SessionKeyError::Degraded)
+        );
+
+        // Open sessions are left to close
+        assert!(ring.current(SESSION, TEST_EPOCH).is_ok());
+    }
+}
@@ -0,0 +1,527 @@
+//! Active/passive failover between regions.
+//!
+//! A mediator runs either as the active node of a deployment, serving all
+//! requests, or as a standby in another region. A standby serves requests
+//! that only read state, e.g. pickup status or delivery requests, but
+//! queues the writes it receives rather than applying them, since the
+//! state it reads is replicated from the active node. Once the active
+//! node is lost, operators promote the standby, which replays the queued
+//! writes in the order they were received and starts serving all requests.
+//! Messages are admitted as they are dispatched, queued writes being
+//! acknowledged with a warning of code [`QUEUED_WRITE_CODE`].
+//!
+//! In the [`StorageMode::Replicated`] mode, queued writes are kept under
+//! the storage directory rather than in memory, so that no state is local
+//! to the node and a standby restarted before promotion loses none of
+//! them. Messages lost on failover are then bounded by the replication lag
+//! of the storage directory. Queued writes are numbered by a sequence
+//! persisted along with them, rather than ordered by clocks that may
+//! step back or tick coarser than writes arrive.
+
+use did_endpoint::util::filesystem::FileSystem;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    io::ErrorKind,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::{constants::*, didcomm::problem_report::ProblemReport, pickup::PickupQueue};
+
+/// Code of the warnings acknowledging writes queued until promotion
+pub const QUEUED_WRITE_CODE: &str = "w.p.req.queued";
+
+#[derive(Debug, Error)]
+pub enum FailoverError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("corrupted pending write: {0}")]
+    ParseError(serde_json::Error),
+    #[error("corrupted sequence of pending writes: {0}")]
+    SequenceError(String),
+    #[error("node is already active")]
+    AlreadyActive,
+}
+
+/// Role of the node within its deployment
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Serves all requests
+    #[default]
+    Active,
+    /// Serves reads, and queues writes until promoted
+    Standby,
+}
+
+/// Where state held by the node is kept
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageMode {
+    /// In memory where possible
+    #[default]
+    Local,
+    /// Under the storage directory, which is replicated across regions
+    Replicated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FailoverConfig {
+    pub role: Role,
+    pub storage_mode: StorageMode,
+}
+
+impl FailoverConfig {
+    /// Configuration from `MEDIATOR_ROLE` and `STORAGE_MODE`, which default
+    /// to `active` and `local`
+    pub fn from_env() -> Self {
+        fn parse<T: DeserializeOwned>(var: &str) -> Option<T> {
+            let value = server_plugin::reload::var(var)?.to_lowercase();
+            serde_json::from_value(Value::String(value)).ok()
+        }
+
+        Self {
+            role: parse("MEDIATOR_ROLE").unwrap_or_default(),
+            storage_mode: parse("STORAGE_MODE").unwrap_or_default(),
+        }
+    }
+}
+
+/// Whether messages of a type only read state, and may be served by a
+/// standby
+pub fn is_read(message_type: &str) -> bool {
+    [
+        KEYLIST_QUERY_2_0,
+        STATUS_REQUEST_3_0,
+        DELIVERY_REQUEST_3_0,
+        STORAGE_GET_1_0,
+        STORAGE_LIST_1_0,
+        DELIVERY_STATUS_QUERY_1_0,
+        DELIVERY_WINDOWS_GET_1_0,
+    ]
+    .contains(&message_type)
+}
+
+/// Write received by a standby, awaiting promotion
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PendingWrite {
+    pub id: String,
+    pub sender: String,
+    pub message: Value,
+
+    /// Time the write was received, as a UNIX timestamp
+    pub received_time: i64,
+}
+
+impl PendingWrite {
+    /// Acknowledgement of the write, in the thread of its message
+    pub fn acknowledgement(&self) -> Value {
+        let report = ProblemReport::new(
+            QUEUED_WRITE_CODE,
+            Some("Write {1} queued until the node is promoted"),
+            Some(vec![self.id.clone()]),
+        );
+        let pthid = self.message.get("id").and_then(Value::as_str);
+        json!(report.with_pthid(pthid))
+    }
+}
+
+/// Outcome of admitting a message
+#[derive(Debug, PartialEq)]
+pub enum Admission {
+    /// The message is to be served now
+    Serve,
+    /// The message was queued until promotion
+    Queued(PendingWrite),
+}
+
+/// Applies writes queued by a standby upon promotion
+pub trait Replayer: Send + Sync {
+    fn replay(&self, write: &PendingWrite) -> Result<(), String>;
+}
+
+/// Acknowledgements of picked up messages are the writes of the pickup
+/// protocol
+impl Replayer for PickupQueue {
+    fn replay(&self, write: &PendingWrite) -> Result<(), String> {
+        self.handle(&write.sender, &write.message)
+            .map(|_| ())
+            .map_err(|report| report.body.code)
+    }
+}
+
+/// Outcome of promoting a standby
+#[derive(Debug, Serialize, ToSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PromotionReport {
+    /// Queued writes applied
+    pub replayed: usize,
+    /// Queued writes rejected by their handler, which are dropped
+    pub rejected: usize,
+}
+
+#[derive(Debug, Default)]
+struct FailoverState {
+    role: Role,
+    /// Writes queued in memory, in the local storage mode
+    pending: Vec<PendingWrite>,
+}
+
+/// Role of the node, and writes queued while on standby. Clones share the
+/// same state.
+#[derive(Debug, Clone, Default)]
+pub struct Failover {
+    storage_mode: StorageMode,
+    storage_dirpath: String,
+    state: Arc<Mutex<FailoverState>>,
+}
+
+impl Failover {
+    pub fn new(config: FailoverConfig, storage_dirpath: &str) -> Self {
+        let state = FailoverState {
+            role: config.role,
+            pending: vec![],
+        };
+
+        Self {
+            storage_mode: config.storage_mode,
+            storage_dirpath: storage_dirpath.to_owned(),
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.state.lock().unwrap().role
+    }
+
+    pub fn storage_mode(&self) -> StorageMode {
+        self.storage_mode
+    }
+
+    /// Decides whether a message is served now, queuing writes received
+    /// while on standby
+    pub fn admit(
+        &self,
+        fs: &mut dyn FileSystem,
+        sender: &str,
+        message: &Value,
+        now: i64,
+    ) -> Result<Admission, FailoverError> {
+        let mut state = self.state.lock().unwrap();
+
+        let message_type = message.get("type").and_then(Value::as_str).unwrap_or("");
+        if state.role == Role::Active || is_read(message_type) {
+            return Ok(Admission::Serve);
+        }
+
+        let write = PendingWrite {
+            id: uuid::Uuid::new_v4().to_string(),
+            sender: sender.to_owned(),
+            message: message.clone(),
+            received_time: now,
+        };
+
+        match self.storage_mode {
+            StorageMode::Local => state.pending.push(write.clone()),
+            StorageMode::Replicated => {
+                let content = serde_json::to_string(&write).map_err(FailoverError::ParseError)?;
+                fs.create_dir_all(&self.dirpath())
+                    .map_err(FailoverError::IoError)?;
+
+                // The sequence is advanced first, so that it is never
+                // reused even if the write fails to persist
+                let sequence = self.read_sequence(fs)? + 1;
+                fs.write_atomic(&self.sequence_path(), &sequence.to_string())
+                    .map_err(FailoverError::IoError)?;
+                fs.write_atomic(&self.write_path(sequence, &write.id), &content)
+                    .map_err(FailoverError::IoError)?;
+            }
+        }
+
+        Ok(Admission::Queued(write))
+    }
+
+    /// Writes awaiting promotion, oldest first
+    pub fn pending(&self, fs: &dyn FileSystem) -> Result<Vec<PendingWrite>, FailoverError> {
+        let state = self.state.lock().unwrap();
+        let pending = self.read_pending(&state, fs)?;
+        Ok(pending.into_iter().map(|(_, write)| write).collect())
+    }
+
+    /// Drops the writes awaiting promotion of the senders matching a
+    /// predicate, returning how many were dropped
+    pub fn forget(
+        &self,
+        fs: &mut dyn FileSystem,
+        mut matches: impl FnMut(&str) -> bool,
+    ) -> Result<usize, FailoverError> {
+        let mut state = self.state.lock().unwrap();
+
+        let mut forgotten = 0;
+        for (path, write) in self.read_pending(&state, fs)? {
+            if !matches(&write.sender) {
+                continue;
+            }
+            if let Some(path) = path {
+                fs.remove_file(&path).map_err(FailoverError::IoError)?;
+            }
+            forgotten += 1;
+        }
+        state.pending.retain(|write| !matches(&write.sender));
+
+        Ok(forgotten)
+    }
+
+    /// Promotes the node to active, replaying queued writes in the order
+    /// they were received. Writes rejected by their handler are dropped.
+    /// Should storage fail, writes not yet replayed are kept, and the node
+    /// stays a standby.
+    pub fn promote(
+        &self,
+        fs: &mut dyn FileSystem,
+        replayer: &dyn Replayer,
+    ) -> Result<PromotionReport, FailoverError> {
+        let mut state = self.state.lock().unwrap();
+        if state.role == Role::Active {
+            return Err(FailoverError::AlreadyActive);
+        }
+
+        let mut report = PromotionReport::default();
+        for (path, write) in self.read_pending(&state, fs)? {
+            match replayer.replay(&write) {
+                Ok(()) => report.replayed += 1,
+                Err(reason) => {
+                    tracing::warn!("rejected pending write {} on promotion: {reason}", write.id);
+                    report.rejected += 1;
+                }
+            }
+
+            if let Some(path) = path {
+                fs.remove_file(&path).map_err(FailoverError::IoError)?;
+            }
+        }
+        state.pending.clear();
+
+        tracing::info!(
+            "promoted to active after replaying {} pending writes",
+            report.replayed
+        );
+        state.role = Role::Active;
+
+        Ok(report)
+    }
+
+    /// Writes awaiting promotion, oldest first, along with the paths they
+    /// are persisted at in the replicated mode
+    fn read_pending(
+        &self,
+        state: &FailoverState,
+        fs: &dyn FileSystem,
+    ) -> Result<Vec<(Option<String>, PendingWrite)>, FailoverError> {
+        if self.storage_mode == StorageMode::Local {
+            let pending = state.pending.iter().cloned();
+            return Ok(pending.map(|write| (None, write)).collect());
+        }
+
+        let mut paths = match fs.read_dir_files(&self.dirpath()) {
+            Ok(paths) => paths,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(FailoverError::IoError(err)),
+        };
+        paths.retain(|path| path.ends_with(".json"));
+        paths.sort();
+
+        paths
+            .into_iter()
+            .map(|path| {
+                let content = fs.read_to_string(&path).map_err(FailoverError::IoError)?;
+                let write = serde_json::from_str(&content).map_err(FailoverError::ParseError)?;
+                Ok((Some(path), write))
+            })
+            .collect()
+    }
+
+    /// Sequence number of the last write queued, zero if none was
+    fn read_sequence(&self, fs: &dyn FileSystem) -> Result<u64, FailoverError> {
+        match fs.read_to_string(&self.sequence_path()) {
+            Ok(content) => content
+                .trim()
+                .parse()
+                .map_err(|_| FailoverError::SequenceError(content)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(FailoverError::IoError(err)),
+        }
+    }
+
+    fn dirpath(&self) -> String {
+        format!("{}/failover/pending", self.storage_dirpath)
+    }
+
+    fn sequence_path(&self) -> String {
+        format!("{}/failover/sequence", self.storage_dirpath)
+    }
+
+    // Names sort writes by sequence number
+    fn write_path(&self, sequence: u64, id: &str) -> String {
+        format!("{}/{sequence:020}-{id}.json", self.dirpath())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::MessageHandlers;
+    use did_endpoint::util::test_utils::{MemoryFileSystem, TEST_EPOCH};
+
+    const STORAGE: &str = "/storage";
+
+    #[derive(Default)]
+    struct RecordingReplayer {
+        replayed: Mutex<Vec<String>>,
+    }
+
+    impl Replayer for RecordingReplayer {
+        fn replay(&self, write: &PendingWrite) -> Result<(), String> {
+            let id = write.message["id"].as_str().unwrap().to_owned();
+            if id == "invalid" {
+                return Err(String::from("e.p.msg.invalid"));
+            }
+
+            self.replayed.lock().unwrap().push(id);
+            Ok(())
+        }
+    }
+
+    fn message(message_type: &str, id: &str) -> Value {
+        json!({"id": id, "type": message_type, "body": {}})
+    }
+
+    fn standby(storage_mode: StorageMode) -> Failover {
+        let config = FailoverConfig {
+            role: Role::Standby,
+            storage_mode,
+        };
+        Failover::new(config, STORAGE)
+    }
+
+    #[test]
+    fn test_admitting_messages() {
+        let mut fs = MemoryFileSystem::new();
+
+        let active = Failover::new(FailoverConfig::default(), STORAGE);
+        let write = message(MESSAGES_RECEIVED_3_0, "ack");
+        assert_eq!(
+            active.admit(&mut fs, "alice", &write, TEST_EPOCH).unwrap(),
+            Admission::Serve
+        );
+
+        let standby = standby(StorageMode::Local);
+        let read = message(STATUS_REQUEST_3_0, "status");
+        assert_eq!(
+            standby.admit(&mut fs, "alice", &read, TEST_EPOCH).unwrap(),
+            Admission::Serve
+        );
+        assert!(matches!(
+            standby.admit(&mut fs, "alice", &write, TEST_EPOCH).unwrap(),
+            Admission::Queued(queued) if queued.message == write
+        ));
+
+        assert_eq!(standby.pending(&fs).unwrap().len(), 1);
+        assert!(fs.get("/storage/failover/pending").is_none());
+
+        // Migrations issue tokens, and are writes
+        assert!(!is_read(MIGRATION_REQUEST_1_0));
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)]
+    fn test_queuing_dispatched_writes_on_standby() {
+        let standby = standby(StorageMode::Local);
+        let handlers = MessageHandlers::new()
+            .with_failover(standby.clone())
+            .register_fallback(|_, message| Ok(Some(json!({"thid": message["id"]}))));
+
+        let read = message(STATUS_REQUEST_3_0, "status");
+        let reply = handlers.dispatch("alice", &read).unwrap().unwrap();
+        assert_eq!(reply["thid"], "status");
+
+        // Writes are acknowledged, not handled, until promotion
+        let write = message(KEYLIST_UPDATE_2_0, "update");
+        let ack = handlers.dispatch("alice", &write).unwrap().unwrap();
+        assert_eq!(ack["type"], PROBLEM_REPORT_2_0);
+        assert_eq!(ack["pthid"], "update");
+        assert_eq!(ack["body"]["code"], QUEUED_WRITE_CODE);
+
+        let pending = standby.pending(&MemoryFileSystem::new()).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].message, write);
+        assert_eq!(ack["body"]["args"], json!([pending[0].id]));
+    }
+
+    #[test]
+    fn test_ordering_pending_writes_by_sequence() {
+        let mut fs = MemoryFileSystem::new();
+        let standby = standby(StorageMode::Replicated);
+
+        // Clocks may step back, or not tick between writes
+        for (id, time) in [("first", 30), ("second", 10), ("third", 10)] {
+            let write = message(STORAGE_PUT_1_0, id);
+            standby.admit(&mut fs, "alice", &write, time).unwrap();
+        }
+
+        // The sequence carries over restarts
+        let restarted = self::standby(StorageMode::Replicated);
+        let write = message(STORAGE_PUT_1_0, "fourth");
+        restarted.admit(&mut fs, "alice", &write, 0).unwrap();
+
+        let ids: Vec<_> = restarted
+            .pending(&fs)
+            .unwrap()
+            .into_iter()
+            .map(|write| write.message["id"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(ids, ["first", "second", "third", "fourth"]);
+        assert_eq!(fs.get("/storage/failover/sequence").unwrap(), "4");
+    }
+
+    #[test]
+    fn test_promoting_standby() {
+        for storage_mode in [StorageMode::Local, StorageMode::Replicated] {
+            let mut fs = MemoryFileSystem::new();
+            let standby = standby(storage_mode);
+
+            for (id, time) in [("first", 10), ("invalid", 20), ("second", 30)] {
+                let write = message(STORAGE_PUT_1_0, id);
+                standby.admit(&mut fs, "alice", &write, time).unwrap();
+            }
+
+            // Queued writes outlive the node in the replicated mode
+            let restarted = self::standby(storage_mode);
+            let expected = match storage_mode {
+                StorageMode::Local => 0,
+                StorageMode::Replicated => 3,
+            };
+            assert_eq!(restarted.pending(&fs).unwrap().len(), expected);
+
+            let replayer = RecordingReplayer::default();
+            let report = standby.promote(&mut fs, &replayer).unwrap();
+            assert_eq!(
+                report,
+                PromotionReport {
+                    replayed: 2,
+                    rejected: 1
+                }
+            );
+            assert_eq!(*replayer.replayed.lock().unwrap(), ["first", "second"]);
+
+            assert_eq!(standby.role(), Role::Active);
+            assert!(standby.pending(&fs).unwrap().is_empty());
+            assert!(matches!(
+                standby.promote(&mut fs, &replayer),
+                Err(FailoverError::AlreadyActive)
+            ));
+        }
+    }
+}
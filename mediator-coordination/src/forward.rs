@@ -0,0 +1,318 @@
+//! Forwards of messages to the recipients of connections.
+//!
+//! A forward names in `next` the DID or key identifier its attached message
+//! is for. It is queued for pickup only if `next` is in the keylist of a
+//! connection, the connection is not archived, i.e. suspended until
+//! restored, its queue has room left, and the attachment carries a message.
+//! Each failure is reported with a problem code of its own, so that senders
+//! may tell recipients gone for good from those to retry later.
+
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{
+    didcomm::{
+        attachment::{Attachment, AttachmentContent},
+        problem_report::ProblemReport,
+        validation::{MessageValidator, INVALID_MESSAGE_CODE},
+    },
+    model::{
+        connection::Connection,
+        forward::{message_specs, Forward},
+    },
+    pickup::{PickupQueue, QueuedMessage},
+    repository::Repository,
+};
+
+/// Problem code for forwards to recipients of no connection
+pub const UNKNOWN_RECIPIENT_CODE: &str = "e.p.forward.unknown-recipient";
+
+/// Problem code for forwards to recipients of suspended connections
+pub const SUSPENDED_CONNECTION_CODE: &str = "e.p.forward.suspended";
+
+/// Problem code for forwards to connections with full queues
+pub const FORWARD_QUOTA_EXCEEDED_CODE: &str = "e.p.forward.quota-exceeded";
+
+/// Problem code for forwards not carrying exactly one message
+pub const MALFORMED_ATTACHMENT_CODE: &str = "e.p.forward.malformed-attachment";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ForwardError {
+    #[error("no connection routes messages to {0}")]
+    UnknownRecipient(String),
+    #[error("the connection of {0} is suspended")]
+    SuspendedConnection(String),
+    #[error("{0} messages already await pickup")]
+    QuotaExceeded(u64),
+    #[error("{0}")]
+    MalformedAttachment(&'static str),
+}
+
+impl ForwardError {
+    /// Describes the error as a problem report
+    pub fn to_problem_report(&self) -> ProblemReport {
+        let (code, comment, args) = match self {
+            Self::UnknownRecipient(next) => (
+                UNKNOWN_RECIPIENT_CODE,
+                "No connection routes messages to {1}",
+                vec![next.clone()],
+            ),
+            Self::SuspendedConnection(next) => (
+                SUSPENDED_CONNECTION_CODE,
+                "The connection of {1} is suspended",
+                vec![next.clone()],
+            ),
+            Self::QuotaExceeded(max) => (
+                FORWARD_QUOTA_EXCEEDED_CODE,
+                "Queue of {1} messages full, please retry later",
+                vec![max.to_string()],
+            ),
+            Self::MalformedAttachment(reason) => (
+                MALFORMED_ATTACHMENT_CODE,
+                "Malformed attachment: {1}",
+                vec![reason.to_string()],
+            ),
+        };
+
+        ProblemReport::new(code, Some(comment), Some(args))
+    }
+}
+
+/// Limits applied to forwards
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForwardConfig {
+    /// Messages a connection may have awaiting pickup, held back or not
+    pub max_queued_messages: u64,
+}
+
+impl Default for ForwardConfig {
+    fn default() -> Self {
+        Self {
+            max_queued_messages: 10_000,
+        }
+    }
+}
+
+impl ForwardConfig {
+    /// Reads `FORWARD_MAX_QUEUED_MESSAGES`, falling back to the default for
+    /// unset or invalid values.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        Self {
+            max_queued_messages: server_plugin::reload::var("FORWARD_MAX_QUEUED_MESSAGES")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.max_queued_messages),
+        }
+    }
+}
+
+/// Queues the message attached to a plaintext forward for pickup by the
+/// connection routing its `next` recipient, returning the DID of the client
+/// of that connection.
+#[allow(clippy::result_large_err)]
+pub fn handle(
+    repository: &dyn Repository<Connection>,
+    queue: &PickupQueue,
+    config: &ForwardConfig,
+    message: &Value,
+    now: i64,
+) -> Result<String, ProblemReport> {
+    MessageValidator::new(message_specs()).check(message)?;
+
+    let pthid = message.get("id").and_then(Value::as_str);
+    let forward: Forward = serde_json::from_value(message.clone()).map_err(|_| {
+        ProblemReport::new(INVALID_MESSAGE_CODE, Some("Malformed message"), None).with_pthid(pthid)
+    })?;
+
+    let queued = route(repository, queue, config, &forward, now).map_err(|err| {
+        tracing::debug!("refused forward {}: {err}", forward.id);
+        err.to_problem_report().with_pthid(pthid)
+    })?;
+
+    Ok(queued)
+}
+
+fn route(
+    repository: &dyn Repository<Connection>,
+    queue: &PickupQueue,
+    config: &ForwardConfig,
+    forward: &Forward,
+    now: i64,
+) -> Result<String, ForwardError> {
+    let next = &forward.body.next;
+    let connection = repository
+        .all()
+        .into_iter()
+        .find(|connection| connection.keylist.contains(next))
+        .ok_or_else(|| ForwardError::UnknownRecipient(next.clone()))?;
+
+    if connection.is_archived() {
+        return Err(ForwardError::SuspendedConnection(next.clone()));
+    }
+
+    let payload = match forward.attachments.as_deref() {
+        Some([attachment]) => payload(attachment)?,
+        Some([_, _, ..]) => {
+            return Err(ForwardError::MalformedAttachment(
+                "more than one attachment",
+            ))
+        }
+        _ => return Err(ForwardError::MalformedAttachment("no attachment")),
+    };
+
+    if queue.depth(&connection.client_did) >= config.max_queued_messages {
+        return Err(ForwardError::QuotaExceeded(config.max_queued_messages));
+    }
+
+    queue.enqueue(
+        &connection.client_did,
+        QueuedMessage {
+            id: forward.id.clone(),
+            recipient_did: next.clone(),
+            payload,
+            received_time: now,
+            ..Default::default()
+        },
+    );
+
+    Ok(connection.client_did)
+}
+
+/// Packed message attached to a forward
+fn payload(attachment: &Attachment) -> Result<String, ForwardError> {
+    match attachment.content() {
+        Ok(AttachmentContent::Json(json)) if json.is_object() => Ok(json.to_string()),
+        Ok(AttachmentContent::Json(_)) => Err(ForwardError::MalformedAttachment(
+            "content is not a message",
+        )),
+        Ok(AttachmentContent::Bytes(bytes)) => String::from_utf8(bytes)
+            .map_err(|_| ForwardError::MalformedAttachment("content is not a message")),
+        Ok(AttachmentContent::Links(_)) => Err(ForwardError::MalformedAttachment(
+            "linked content is not forwarded",
+        )),
+        Err(_) => Err(ForwardError::MalformedAttachment("content does not decode")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{constants::FORWARD_2_0, repository::MemoryRepository};
+    use serde_json::json;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+    const ALICE_KEY: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH#key-1";
+    const BOB: &str = "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7";
+    const BOB_KEY: &str = "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7#key-1";
+
+    fn repository() -> MemoryRepository<Connection> {
+        let repository = MemoryRepository::new();
+        for (client_did, key, archived_time) in
+            [(ALICE, ALICE_KEY, None), (BOB, BOB_KEY, Some(1000))]
+        {
+            let connection = Connection {
+                client_did: client_did.to_owned(),
+                keylist: vec![key.to_owned()],
+                archived_time,
+                ..Default::default()
+            };
+            repository.insert(connection).unwrap();
+        }
+
+        repository
+    }
+
+    fn forward(id: &str, body: Value, attachments: Value) -> Value {
+        json!({
+            "id": id,
+            "type": FORWARD_2_0,
+            "body": body,
+            "attachments": attachments
+        })
+    }
+
+    #[test]
+    fn can_queue_forwarded_messages() {
+        let repository = repository();
+        let queue = PickupQueue::default();
+        let config = ForwardConfig::default();
+
+        let jwe = json!({"protected": "eyJ0eXAiOiJKV00ifQ", "ciphertext": "..."});
+        let message = forward(
+            "fwd-0",
+            json!({"next": ALICE_KEY}),
+            json!([{"data": {"json": jwe}}]),
+        );
+        let client_did = handle(&repository, &queue, &config, &message, 2000).unwrap();
+        assert_eq!(client_did, ALICE);
+
+        let message = forward(
+            "fwd-1",
+            json!({"next": ALICE_KEY}),
+            json!([{"data": {"base64": "eyJhIjoxfQ"}}]),
+        );
+        handle(&repository, &queue, &config, &message, 2001).unwrap();
+
+        let queued = queue.messages(ALICE);
+        assert_eq!(queued.len(), 2);
+        assert_eq!(queued[0].id, "fwd-0");
+        assert_eq!(queued[0].recipient_did, ALICE_KEY);
+        assert_eq!(
+            serde_json::from_str::<Value>(&queued[0].payload).unwrap(),
+            jwe
+        );
+        assert_eq!(queued[1].payload, r#"{"a":1}"#);
+        assert_eq!(queued[1].received_time, 2001);
+    }
+
+    #[test]
+    fn should_report_refused_forwards_with_distinct_codes() {
+        let repository = repository();
+        let queue = PickupQueue::default();
+        let config = ForwardConfig {
+            max_queued_messages: 1,
+        };
+        let attachments = json!([{"data": {"json": {"ciphertext": "..."}}}]);
+
+        let message = forward("fwd-0", json!({}), attachments.clone());
+        let report = handle(&repository, &queue, &config, &message, 2000).unwrap_err();
+        assert_eq!(report.body.code, INVALID_MESSAGE_CODE);
+        assert_eq!(report.pthid.as_deref(), Some("fwd-0"));
+
+        let message = forward(
+            "fwd-1",
+            json!({"next": "did:example:carol"}),
+            attachments.clone(),
+        );
+        let report = handle(&repository, &queue, &config, &message, 2000).unwrap_err();
+        assert_eq!(report.body.code, UNKNOWN_RECIPIENT_CODE);
+        assert_eq!(report.pthid.as_deref(), Some("fwd-1"));
+
+        let message = forward("fwd-2", json!({"next": BOB_KEY}), attachments.clone());
+        let report = handle(&repository, &queue, &config, &message, 2000).unwrap_err();
+        assert_eq!(report.body.code, SUSPENDED_CONNECTION_CODE);
+
+        for attachments in [
+            json!([]),
+            json!([{"data": {"json": "text"}}]),
+            json!([{"data": {"base64": "!!"}}]),
+            json!([{"data": {"links": ["https://example.com/msg"], "hash": "..."}}]),
+            json!([attachments[0], attachments[0]]),
+        ] {
+            let message = forward("fwd-3", json!({"next": ALICE_KEY}), attachments.clone());
+            let report = handle(&repository, &queue, &config, &message, 2000).unwrap_err();
+            assert_eq!(report.body.code, MALFORMED_ATTACHMENT_CODE, "{attachments}");
+        }
+        let message = json!({"id": "fwd-4", "type": FORWARD_2_0, "body": {"next": ALICE_KEY}});
+        let report = handle(&repository, &queue, &config, &message, 2000).unwrap_err();
+        assert_eq!(report.body.code, MALFORMED_ATTACHMENT_CODE);
+
+        let message = forward("fwd-5", json!({"next": ALICE_KEY}), attachments.clone());
+        handle(&repository, &queue, &config, &message, 2000).unwrap();
+        let message = forward("fwd-6", json!({"next": ALICE_KEY}), attachments);
+        let report = handle(&repository, &queue, &config, &message, 2000).unwrap_err();
+        assert_eq!(report.body.code, FORWARD_QUOTA_EXCEEDED_CODE);
+        assert_eq!(queue.depth(ALICE), 1);
+    }
+}
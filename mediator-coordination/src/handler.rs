@@ -0,0 +1,680 @@
+//! Handlers of the messages of DIDComm protocols.
+//!
+//! A [`MessageHandler`] answers plaintext messages of one type from
+//! authenticated senders, and [`MessageHandlers`] dispatch messages to the
+//! handler of their type. Handlers are best written as functions of the
+//! typed bodies of requests, annotated with [`didcomm_handler`], which
+//! generates the deserialization of requests, the threading of replies and
+//! problem reports, and the conversion of errors.
+//!
+//! Messages other than forwards are refused while shed by the
+//! [`LoadShedder`], before reaching handlers. Delivery requests over
+//! connections not authenticated by authcrypt are answered with a
+//! challenge, as per the [`PickupChallenges`], until the recipient proves
+//! control of a key in the keylist of the connection. Writes received
+//! while the node is a standby are queued until promotion, as per the
+//! [`Failover`], and acknowledged rather than handled.
+//!
+//! Panics of handlers are contained: they are logged and answered with a
+//! problem report of code [`INTERNAL_ERROR_CODE`], so that a handler
+//! failing on some message does not take down the others.
+//!
+//! Messages of types no handler is registered for are passed to a fallback
+//! handler, if any, or else dropped, reported as unsupported, or kept as
+//! dead letters for analysis, as per the [`UnknownTypePolicy`]. They are
+//! counted in the persistent [`UNKNOWN_MESSAGE_TYPES`] counter.
+
+use did_endpoint::util::filesystem::StdFileSystem;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::{
+    any::Any,
+    collections::{HashMap, VecDeque},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+};
+
+pub use mediator_coordination_macros::didcomm_handler;
+
+use crate::{
+    challenge::PickupChallenges,
+    constants::PICKUP_CHALLENGE_RESPONSE_1_0,
+    degradation::LoadShedder,
+    didcomm::{
+        problem_report::ProblemReport,
+        validation::{MessageValidator, INVALID_MESSAGE_CODE, UNSUPPORTED_MESSAGE_CODE},
+    },
+    failover::{Admission, Failover},
+    metrics::{PersistentCounters, UNKNOWN_MESSAGE_TYPES},
+    model::coord::CoordMessage,
+};
+
+/// Problem code for messages whose handler failed unexpectedly
+pub const INTERNAL_ERROR_CODE: &str = "e.p.me";
+
+/// Handler of messages of types no other handler is registered for,
+/// returning the response to send back, if any
+pub type FallbackHandler =
+    Box<dyn Fn(&str, &Value) -> Result<Option<Value>, ProblemReport> + Send + Sync>;
+
+/// Handler of the messages of a type
+pub trait MessageHandler: Send + Sync {
+    /// Type of the messages handled, e.g. [`crate::constants::KEYLIST_QUERY_2_0`]
+    fn message_type(&self) -> &'static str;
+
+    /// Handles a plaintext message from an authenticated sender, returning
+    /// the response message to send back.
+    #[allow(clippy::result_large_err)]
+    fn handle(&self, sender: &str, message: &Value) -> Result<Value, ProblemReport>;
+}
+
+/// What becomes of messages of unknown types, without a fallback handler
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum UnknownTypePolicy {
+    /// Dropped without response
+    Drop,
+    /// Answered with a problem report of code `e.p.msg.unsupported`
+    #[default]
+    Report,
+    /// Kept as dead letters, without response
+    DeadLetter,
+}
+
+impl UnknownTypePolicy {
+    /// Reads `UNKNOWN_MESSAGE_POLICY`, `drop`, `report` or `dead-letter`,
+    /// falling back to reports for unset or invalid values.
+    pub fn from_env() -> Self {
+        match server_plugin::reload::var("UNKNOWN_MESSAGE_POLICY").as_deref() {
+            Some("drop") => Self::Drop,
+            Some("dead-letter") => Self::DeadLetter,
+            _ => Self::Report,
+        }
+    }
+}
+
+/// Message of an unknown type, kept for analysis
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub sender: String,
+    pub message: Value,
+}
+
+/// Most recent dead letters, the oldest being dropped beyond capacity.
+/// Clones share the same letters.
+#[derive(Debug, Clone)]
+pub struct DeadLetters {
+    capacity: usize,
+    letters: Arc<Mutex<VecDeque<DeadLetter>>>,
+}
+
+impl Default for DeadLetters {
+    fn default() -> Self {
+        Self::new(1000)
+    }
+}
+
+impl DeadLetters {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            letters: Default::default(),
+        }
+    }
+
+    fn push(&self, letter: DeadLetter) {
+        let mut letters = self.letters.lock().unwrap();
+        if letters.len() >= self.capacity {
+            letters.pop_front();
+        }
+        if self.capacity > 0 {
+            letters.push_back(letter);
+        }
+    }
+
+    /// Dead letters, oldest first
+    pub fn all(&self) -> Vec<DeadLetter> {
+        self.letters.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Removes and returns the dead letters, oldest first
+    pub fn take(&self) -> Vec<DeadLetter> {
+        self.letters.lock().unwrap().drain(..).collect()
+    }
+
+    /// Removes the dead letters matching a predicate, returning how many
+    /// were removed
+    pub fn remove(&self, mut matches: impl FnMut(&DeadLetter) -> bool) -> usize {
+        let mut letters = self.letters.lock().unwrap();
+        let count = letters.len();
+        letters.retain(|letter| !matches(letter));
+        count - letters.len()
+    }
+}
+
+/// Handlers by the type of messages they handle
+#[derive(Default)]
+pub struct MessageHandlers {
+    handlers: HashMap<&'static str, Box<dyn MessageHandler>>,
+    fallback: Option<FallbackHandler>,
+    policy: UnknownTypePolicy,
+    dead_letters: DeadLetters,
+    counters: PersistentCounters,
+    shedder: LoadShedder,
+    validator: MessageValidator,
+    challenges: PickupChallenges,
+    failover: Failover,
+}
+
+impl MessageHandlers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler, in place of any handling the same type
+    pub fn register(mut self, handler: impl MessageHandler + 'static) -> Self {
+        self.handlers
+            .insert(handler.message_type(), Box::new(handler));
+        self
+    }
+
+    /// Registers the handler of messages of unknown types, in place of the
+    /// unknown type policy
+    pub fn register_fallback(
+        mut self,
+        fallback: impl Fn(&str, &Value) -> Result<Option<Value>, ProblemReport> + Send + Sync + 'static,
+    ) -> Self {
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    /// Sets what becomes of messages of unknown types, without a fallback
+    pub fn with_unknown_type_policy(self, policy: UnknownTypePolicy) -> Self {
+        Self { policy, ..self }
+    }
+
+    /// Keeps dead letters in a shared collection
+    pub fn with_dead_letters(self, dead_letters: DeadLetters) -> Self {
+        Self {
+            dead_letters,
+            ..self
+        }
+    }
+
+    /// Counts messages of unknown types in shared counters
+    pub fn with_counters(self, counters: PersistentCounters) -> Self {
+        Self { counters, ..self }
+    }
+
+    /// Sheds messages at the degradation level of a shared shedder
+    pub fn with_shedder(self, shedder: LoadShedder) -> Self {
+        Self { shedder, ..self }
+    }
+
+    /// Validates messages of the types specified, before handling them
+    pub fn with_validator(self, validator: MessageValidator) -> Self {
+        Self { validator, ..self }
+    }
+
+    /// Challenges delivery requests over unauthenticated connections with
+    /// shared challenges
+    pub fn with_challenges(self, challenges: PickupChallenges) -> Self {
+        Self { challenges, ..self }
+    }
+
+    /// Admits messages as per the role of the node in a shared failover,
+    /// queuing writes while on standby
+    pub fn with_failover(self, failover: Failover) -> Self {
+        Self { failover, ..self }
+    }
+
+    pub fn dead_letters(&self) -> &DeadLetters {
+        &self.dead_letters
+    }
+
+    /// Types of the messages handled
+    pub fn message_types(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.handlers.keys().copied()
+    }
+
+    /// Handles a message with the handler of its type, if any
+    #[allow(clippy::result_large_err)]
+    pub fn handle(&self, sender: &str, message: &Value) -> Result<Value, ProblemReport> {
+        self.handle_registered(sender, message).unwrap_or_else(|| {
+            let message_type = message["type"].as_str().unwrap_or_default();
+            Err(unsupported(message_type, message))
+        })
+    }
+
+    /// Validates a message against the spec of its type, if any, and
+    /// handles it with the handler of its type, if any is registered
+    #[allow(clippy::result_large_err)]
+    fn handle_registered(
+        &self,
+        sender: &str,
+        message: &Value,
+    ) -> Option<Result<Value, ProblemReport>> {
+        let message_type = message["type"].as_str().unwrap_or_default();
+        let handler = self.handlers.get(message_type)?;
+
+        if self.validator.spec(message_type).is_some() {
+            if let Err(report) = self.validator.check(message) {
+                return Some(Err(report));
+            }
+        }
+
+        Some(contain(message_type, message, || {
+            handler.handle(sender, message)
+        }))
+    }
+
+    /// Dispatches a message from a sender authenticated by authcrypt to
+    /// the handler of its type, if any, or else to the fallback handler or
+    /// as per the unknown type policy, returning the response to send
+    /// back, if any. Messages shed at the current degradation level are
+    /// refused, and messages of a specified type are validated before
+    /// reaching their handler.
+    #[allow(clippy::result_large_err)]
+    pub fn dispatch(&self, sender: &str, message: &Value) -> Result<Option<Value>, ProblemReport> {
+        self.dispatch_over(sender, None, message)
+    }
+
+    /// Dispatches a message received over a connection not authenticated
+    /// by authcrypt, given the keylist of the connection. Delivery requests
+    /// are answered with a challenge unless the connection answered one
+    /// before, and challenge responses are verified against the keylist.
+    #[allow(clippy::result_large_err)]
+    pub fn dispatch_unauthenticated(
+        &self,
+        connection: &str,
+        keylist: &[String],
+        message: &Value,
+    ) -> Result<Option<Value>, ProblemReport> {
+        self.dispatch_over(connection, Some(keylist), message)
+    }
+
+    /// Dispatches a message from a sender authenticated by authcrypt, or
+    /// else over a connection of the given keylist
+    #[allow(clippy::result_large_err)]
+    fn dispatch_over(
+        &self,
+        sender: &str,
+        keylist: Option<&[String]>,
+        message: &Value,
+    ) -> Result<Option<Value>, ProblemReport> {
+        self.shedder.admit(message)?;
+
+        let now = chrono::Utc::now().timestamp();
+        if let Some(keylist) = keylist {
+            if message["type"].as_str() == Some(PICKUP_CHALLENGE_RESPONSE_1_0) {
+                let response = self.challenges.handle(sender, keylist, message, now);
+                return response.map(Some);
+            }
+        }
+        let authcrypt = keylist.is_none();
+        if let Some(challenge) = self.challenges.admit(sender, message, authcrypt, now) {
+            return Ok(Some(challenge));
+        }
+
+        let admission = self
+            .failover
+            .admit(&mut StdFileSystem, sender, message, now);
+        match admission {
+            Ok(Admission::Serve) => {}
+            Ok(Admission::Queued(write)) => return Ok(Some(write.acknowledgement())),
+            Err(err) => {
+                tracing::error!("failed to queue write from {sender}: {err}");
+                let message_type = message["type"].as_str().unwrap_or_default();
+                return Err(ProblemReport::new(
+                    INTERNAL_ERROR_CODE,
+                    Some("Failed to handle message of type {1}"),
+                    Some(vec![message_type.to_owned()]),
+                )
+                .with_pthid(message.get("id").and_then(Value::as_str)));
+            }
+        }
+
+        if let Some(outcome) = self.handle_registered(sender, message) {
+            return outcome.map(Some);
+        }
+
+        let message_type = message["type"].as_str().unwrap_or_default();
+        self.counters.increment(UNKNOWN_MESSAGE_TYPES, 1);
+        if let Some(fallback) = &self.fallback {
+            return contain(message_type, message, || fallback(sender, message));
+        }
+
+        match self.policy {
+            UnknownTypePolicy::Drop => {
+                tracing::debug!("dropped message of unknown type {message_type} from {sender}");
+                Ok(None)
+            }
+            UnknownTypePolicy::Report => Err(unsupported(message_type, message)),
+            UnknownTypePolicy::DeadLetter => {
+                self.dead_letters.push(DeadLetter {
+                    sender: sender.to_owned(),
+                    message: message.clone(),
+                });
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Items referred to by the code [`didcomm_handler`] generates, by
+/// absolute paths that also resolve outside of this crate
+#[doc(hidden)]
+pub mod __private {
+    pub use super::{problem, reply, request, MessageHandler};
+    pub use crate::didcomm::problem_report::ProblemReport;
+    pub use serde_json::Value;
+}
+
+/// Runs the handling of a message, answering panics with a problem
+/// report rather than unwinding into the caller
+#[allow(clippy::result_large_err)]
+fn contain<T>(
+    message_type: &str,
+    message: &Value,
+    handle: impl FnOnce() -> Result<T, ProblemReport>,
+) -> Result<T, ProblemReport> {
+    let payload = match panic::catch_unwind(AssertUnwindSafe(handle)) {
+        Ok(outcome) => return outcome,
+        Err(payload) => payload,
+    };
+
+    let reason = panic_message(payload.as_ref());
+    tracing::error!("handler of {message_type} panicked: {reason}");
+
+    Err(ProblemReport::new(
+        INTERNAL_ERROR_CODE,
+        Some("Failed to handle message of type {1}"),
+        Some(vec![message_type.to_owned()]),
+    )
+    .with_pthid(message.get("id").and_then(Value::as_str)))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("unknown panic")
+    }
+}
+
+/// Typed request of a message type, failing with a problem report on
+/// messages of other types or with malformed bodies
+#[allow(clippy::result_large_err)]
+pub fn request<B: DeserializeOwned + Default>(
+    message_type: &str,
+    message: &Value,
+) -> Result<CoordMessage<B>, ProblemReport> {
+    let pthid = message.get("id").and_then(Value::as_str);
+    if message["type"].as_str() != Some(message_type) {
+        let message_type = message["type"].as_str().unwrap_or_default();
+        return Err(unsupported(message_type, message));
+    }
+
+    serde_json::from_value(message.clone()).map_err(|_| {
+        ProblemReport::new(INVALID_MESSAGE_CODE, Some("Malformed message"), None).with_pthid(pthid)
+    })
+}
+
+/// Reply to a request, in its thread. Bodies are wrapped in a message of
+/// the reply type, if any, or else sent as they are.
+pub fn reply<B, R: Serialize>(
+    request: &CoordMessage<B>,
+    message_type: Option<&str>,
+    reply: R,
+) -> Value {
+    let Some(message_type) = message_type else {
+        let mut reply = json!(reply);
+        if let Some(reply) = reply.as_object_mut() {
+            let thid = request.thid.as_ref().unwrap_or(&request.id);
+            reply.entry("thid").or_insert_with(|| json!(thid));
+        }
+
+        return reply;
+    };
+
+    json!(CoordMessage::reply_to(request, message_type, reply))
+}
+
+/// Problem report of a request failing, in its thread
+pub fn problem<B, E: Into<ProblemReport>>(request: &CoordMessage<B>, err: E) -> ProblemReport {
+    err.into().with_pthid(Some(&request.id))
+}
+
+pub(crate) fn unsupported(message_type: &str, message: &Value) -> ProblemReport {
+    let pthid = message.get("id").and_then(Value::as_str);
+    ProblemReport::new(
+        UNSUPPORTED_MESSAGE_CODE,
+        Some("Unsupported message type {1}"),
+        Some(vec![message_type.to_owned()]),
+    )
+    .with_pthid(pthid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::didcomm::validation::{FieldKind, FieldSpec, MessageSpec};
+    use serde::Deserialize;
+    use std::sync::{Arc, Mutex};
+
+    const PING: &str = "https://example.com/ping/1.0/ping";
+    const PONG: &str = "https://example.com/ping/1.0/pong";
+    const ECHO: &str = "https://example.com/ping/1.0/echo";
+    const CRASH: &str = "https://example.com/ping/1.0/crash";
+
+    #[derive(Debug, Serialize, Deserialize, Default)]
+    struct PingBody {
+        times: u32,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, Default)]
+    struct PongBody {
+        times: u32,
+        total: u32,
+    }
+
+    /// Pings counted across senders
+    #[derive(Clone, Default)]
+    struct Pings(Arc<Mutex<u32>>);
+
+    #[didcomm_handler(PING, reply = PONG)]
+    #[allow(clippy::result_large_err)]
+    fn ping(pings: &Pings, _sender: &str, body: PingBody) -> Result<PongBody, ProblemReport> {
+        if body.times == 0 {
+            return Err(ProblemReport::new(INVALID_MESSAGE_CODE, None, None));
+        }
+
+        let mut total = pings.0.lock().unwrap();
+        *total += body.times;
+        Ok(PongBody {
+            times: body.times,
+            total: *total,
+        })
+    }
+
+    #[didcomm_handler(ECHO)]
+    #[allow(clippy::result_large_err)]
+    fn echo(sender: &str, body: Value) -> Result<Value, ProblemReport> {
+        Ok(json!({"type": ECHO, "to": [sender], "body": body}))
+    }
+
+    #[didcomm_handler(CRASH)]
+    #[allow(clippy::result_large_err)]
+    fn crash(_sender: &str, body: Value) -> Result<Value, ProblemReport> {
+        panic!("cannot handle {body}")
+    }
+
+    fn handlers() -> MessageHandlers {
+        MessageHandlers::new()
+            .register(Ping(Pings::default()))
+            .register(Echo)
+    }
+
+    #[test]
+    fn test_generating_handlers() {
+        let handlers = handlers();
+        let mut types: Vec<_> = handlers.message_types().collect();
+        types.sort();
+        assert_eq!(types, [ECHO, PING]);
+
+        let message = json!({"id": "1", "type": PING, "body": {"times": 2}});
+        handlers.handle("did:example:alice", &message).unwrap();
+        let reply = handlers.handle("did:example:bob", &message).unwrap();
+        assert_eq!(reply["type"], PONG);
+        assert_eq!(reply["thid"], "1");
+        assert_eq!(reply["body"], json!({"times": 2, "total": 4}));
+
+        // Replies sent as they are are threaded as well
+        let message = json!({"id": "2", "thid": "0", "type": ECHO, "body": {"n": 1}});
+        let reply = handlers.handle("did:example:alice", &message).unwrap();
+        assert_eq!(reply["to"], json!(["did:example:alice"]));
+        assert_eq!(reply["thid"], "0");
+        assert_eq!(reply["body"], json!({"n": 1}));
+    }
+
+    #[test]
+    fn should_report_problems_in_thread() {
+        let handlers = handlers();
+
+        let message = json!({"id": "3", "type": PING, "body": {"times": 0}});
+        let report = handlers.handle("did:example:alice", &message).unwrap_err();
+        assert_eq!(report.body.code, INVALID_MESSAGE_CODE);
+        assert_eq!(report.pthid.as_deref(), Some("3"));
+
+        let message = json!({"id": "4", "type": PING, "body": {"times": "twice"}});
+        let report = handlers.handle("did:example:alice", &message).unwrap_err();
+        assert_eq!(report.body.comment.as_deref(), Some("Malformed message"));
+        assert_eq!(report.pthid.as_deref(), Some("4"));
+
+        let message = json!({"id": "5", "type": PONG, "body": {}});
+        let report = handlers.handle("did:example:alice", &message).unwrap_err();
+        assert_eq!(report.body.code, UNSUPPORTED_MESSAGE_CODE);
+
+        // Handlers used on their own reject messages of other types
+        let report = Echo.handle("did:example:alice", &message).unwrap_err();
+        assert_eq!(report.body.args, Some(vec![PONG.to_owned()]));
+    }
+
+    #[test]
+    fn can_apply_unknown_type_policies() {
+        let counters = PersistentCounters::new();
+        let unknown = json!({"id": "6", "type": PONG, "body": {}});
+
+        let reports = handlers().with_counters(counters.clone());
+        let reply = reports.dispatch("did:example:alice", &json!({"id": "7", "type": ECHO}));
+        assert_eq!(reply.unwrap().unwrap()["thid"], "7");
+        let report = reports.dispatch("did:example:alice", &unknown).unwrap_err();
+        assert_eq!(report.body.code, UNSUPPORTED_MESSAGE_CODE);
+
+        let drops = handlers()
+            .with_counters(counters.clone())
+            .with_unknown_type_policy(UnknownTypePolicy::Drop);
+        assert_eq!(drops.dispatch("did:example:alice", &unknown), Ok(None));
+
+        let dead_letters = DeadLetters::new(1);
+        let keeps = handlers()
+            .with_counters(counters.clone())
+            .with_unknown_type_policy(UnknownTypePolicy::DeadLetter)
+            .with_dead_letters(dead_letters.clone());
+        assert_eq!(keeps.dispatch("did:example:alice", &unknown), Ok(None));
+        assert_eq!(keeps.dispatch("did:example:bob", &unknown), Ok(None));
+
+        // Only the most recent letters are kept
+        let letters = dead_letters.take();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].sender, "did:example:bob");
+        assert_eq!(letters[0].message, unknown);
+        assert!(keeps.dead_letters().all().is_empty());
+
+        assert_eq!(counters.total(UNKNOWN_MESSAGE_TYPES), 4);
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)]
+    fn can_register_fallback_handler() {
+        let handlers = handlers()
+            .with_unknown_type_policy(UnknownTypePolicy::DeadLetter)
+            .register_fallback(|sender, message| {
+                Ok(Some(
+                    json!({"type": ECHO, "to": [sender], "body": message["body"]}),
+                ))
+            });
+
+        let unknown = json!({"id": "8", "type": PONG, "body": {"n": 1}});
+        let reply = handlers
+            .dispatch("did:example:alice", &unknown)
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply["body"], json!({"n": 1}));
+        assert!(handlers.dead_letters().all().is_empty());
+
+        // Registered handlers take precedence
+        let message = json!({"id": "9", "type": PING, "body": {"times": 1}});
+        let reply = handlers
+            .dispatch("did:example:alice", &message)
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply["type"], PONG);
+    }
+
+    #[test]
+    fn should_validate_messages_before_handling_them() {
+        let spec = MessageSpec {
+            message_type: PING,
+            required_headers: &[],
+            body: &[FieldSpec {
+                name: "times",
+                kind: FieldKind::Integer,
+                required: true,
+            }],
+        };
+        let handlers = handlers().with_validator(MessageValidator::new([spec]));
+
+        let message = json!({"id": "13", "type": PING, "body": {}});
+        let report = handlers
+            .dispatch("did:example:alice", &message)
+            .unwrap_err();
+        assert_eq!(report.body.code, INVALID_MESSAGE_CODE);
+        assert_eq!(report.body.args, Some(vec![String::from("times")]));
+        assert_eq!(report.pthid.as_deref(), Some("13"));
+
+        // Messages of types without specs are handled as they are
+        let message = json!({"id": "14", "type": ECHO, "body": {"n": 1}});
+        let reply = handlers.dispatch("did:example:alice", &message).unwrap();
+        assert_eq!(reply.unwrap()["body"], json!({"n": 1}));
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)]
+    fn should_contain_panics_of_handlers() {
+        let handlers = handlers()
+            .register(Crash)
+            .register_fallback(|_, _| panic!("no fallback"));
+
+        let message = json!({"id": "10", "type": CRASH, "body": {}});
+        let report = handlers
+            .dispatch("did:example:alice", &message)
+            .unwrap_err();
+        assert_eq!(report.body.code, INTERNAL_ERROR_CODE);
+        assert_eq!(report.pthid.as_deref(), Some("10"));
+        let report = handlers.handle("did:example:alice", &message).unwrap_err();
+        assert_eq!(report.body.code, INTERNAL_ERROR_CODE);
+
+        let unknown = json!({"id": "11", "type": PONG, "body": {}});
+        let report = handlers
+            .dispatch("did:example:alice", &unknown)
+            .unwrap_err();
+        assert_eq!(report.body.code, INTERNAL_ERROR_CODE);
+
+        // Other handlers keep serving
+        let message = json!({"id": "12", "type": PING, "body": {"times": 1}});
+        let reply = handlers.dispatch("did:example:alice", &message).unwrap();
+        assert_eq!(reply.unwrap()["type"], PONG);
+    }
+}
@@ -0,0 +1,157 @@
+//! Golden-file regression tests for signed messages.
+//!
+//! Representative messages are signed with the pinned keys of the test
+//! storage and compared against files under `test/golden/jws`. Since
+//! Ed25519 signatures are deterministic, any change to header or payload
+//! serialization shows up as a difference, which would otherwise silently
+//! break verification by deployed wallets.
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite golden files after an intended
+//! format change, and review the resulting diff.
+
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+use did_endpoint::util::keystore::ToPublic;
+use did_utils::key_jwk::jwk::Jwk;
+use multibase::Base::Base64Url;
+
+use crate::{
+    client::dic::{make_compact_jwt_presentation, PresentationMetadata},
+    jose::jws::{self, JwsAlg, JwsHeader},
+    model::dic::{DDICPayload, DICPayload, DICServiceLevel, JwtAssertable},
+    util::{self, mock_fs},
+};
+
+/// Pinned signing key and its identifier
+fn pinned_key() -> (String, Jwk) {
+    let mut mock_fs = mock_fs();
+
+    let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+    let (kid, pubkey) = util::extract_assertion_key(&diddoc).unwrap();
+
+    let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+    (kid, keystore.find_keypair(&pubkey).unwrap())
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("test/golden/jws/{name}.json"))
+}
+
+fn decode_segment(segment: &str) -> Value {
+    serde_json::from_slice(&Base64Url.decode(segment).unwrap()).unwrap()
+}
+
+/// Compares a compact JWS against its golden file, reporting which of the
+/// header, payload or signature departs from it.
+fn assert_golden(name: &str, compact: &str) {
+    let parts: Vec<_> = compact.split('.').collect();
+    assert_eq!(parts.len(), 3, "{name}: not a compact JWS");
+
+    let actual = json!({
+        "header": decode_segment(parts[0]),
+        "payload": decode_segment(parts[1]),
+        "jws": compact,
+    });
+
+    let path = golden_path(name);
+    if std::env::var("UPDATE_GOLDEN").is_ok_and(|v| v == "1") {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+        return;
+    }
+
+    let golden: Value = serde_json::from_str(
+        &std::fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("{name}: missing golden file {}", path.display())),
+    )
+    .unwrap();
+
+    assert_eq!(
+        actual["header"], golden["header"],
+        "{name}: header departs from golden file"
+    );
+    assert_eq!(
+        actual["payload"], golden["payload"],
+        "{name}: payload departs from golden file"
+    );
+    assert_eq!(
+        actual["jws"], golden["jws"],
+        "{name}: serialization departs from golden file, although structurally equal"
+    );
+
+    // Golden messages must remain verifiable
+    let (_, jwk) = pinned_key();
+    jws::verify_compact_jws(golden["jws"].as_str().unwrap(), &jwk.to_public()).unwrap();
+}
+
+fn dic_payload() -> DICPayload {
+    DICPayload {
+        subject: "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7".to_owned(),
+        issuer: "did:web:alice-mediator.com:alice_mediator_pub".to_owned(),
+        service_level: DICServiceLevel::Gold,
+        nonce: Some("43f84868-0632-4471-b6dd-d63fa12c21f6".to_owned()),
+        additional_properties: None,
+    }
+}
+
+#[test]
+fn golden_dic() {
+    let (kid, jwk) = pinned_key();
+    let jwt = dic_payload().sign(&jwk, Some(kid)).unwrap();
+
+    assert_golden("dic", &jwt);
+}
+
+#[test]
+fn golden_ddic() {
+    let (kid, jwk) = pinned_key();
+    let payload = DDICPayload {
+        subject: "did:key:z6MkwKfUdSSeCb1ReSfKaY8jVEPoWGrVNBCEo1GXtnM9ocKv".to_owned(),
+        dic_subject: "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7".to_owned(),
+        issuer: "did:web:alice-mediator.com:alice_mediator_pub".to_owned(),
+        nonce: None,
+        additional_properties: None,
+    };
+
+    assert_golden("ddic", &payload.sign(&jwk, Some(kid)).unwrap());
+}
+
+#[test]
+fn golden_dic_presentation() {
+    let (kid, jwk) = pinned_key();
+    let dic = dic_payload().sign(&jwk, Some(kid.clone())).unwrap();
+
+    let metadata = PresentationMetadata {
+        issuer: "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7".to_owned(),
+        subject: "did:web:bob-mediator.com".to_owned(),
+        nonce: Some("0bd2e5f8-6f5b-4d6e-a7b7-70a2a0bb7bb1".to_owned()),
+        additional_properties: None,
+    };
+    let jwt = make_compact_jwt_presentation(&dic, &jwk, Some(&kid), metadata).unwrap();
+
+    assert_golden("dic_presentation", &jwt);
+}
+
+#[test]
+fn golden_custom_header() {
+    let (_, jwk) = pinned_key();
+    let header = JwsHeader {
+        typ: Some("application/didcomm-signed+json".to_owned()),
+        kid: None,
+        alg: JwsAlg::EdDSA,
+        additional_properties: Some(
+            [("cty".to_owned(), json!("application/didcomm-plain+json"))].into(),
+        ),
+    };
+    let payload = json!({
+        "id": "1234567890",
+        "type": "https://didcomm.org/coordinate-mediation/2.0/mediate-request",
+        "body": {},
+    });
+
+    assert_golden(
+        "custom_header",
+        &jws::make_compact_jws(&header, payload, &jwk).unwrap(),
+    );
+}
@@ -0,0 +1,511 @@
+//! Validation of the protected headers of JSON Web Encryption (JWE).
+//!
+//! Encrypted DIDComm messages bind their key agreement to the parties
+//! involved: `apv` carries the digest of the key ids of all recipients,
+//! and `apu`, for authenticated encryption, the key id of the sender,
+//! which `skid` repeats. Headers are checked against these bindings and
+//! against allowlists of algorithms before unpacking, so that messages
+//! re-targeted at other recipients, attributed to other senders or
+//! downgraded to weaker algorithms are rejected. Rejections are surfaced
+//! to senders as problem reports detailing the reason.
+//!
+//! See https://identity.foundation/didcomm-messaging/spec/#message-encryption
+
+use did_utils::crypto::sha256_hash::sha256_hash;
+use multibase::Base::Base64Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use thiserror::Error;
+
+use super::policy::{CryptoPolicy, Direction};
+use crate::didcomm::problem_report::ProblemReport;
+
+/// Problem code for encrypted messages whose headers fail validation
+pub const INVALID_JWE_HEADER_CODE: &str = "e.p.crypto.invalid-header";
+
+/// Problem code for encrypted messages using disallowed algorithms
+pub const UNSUPPORTED_JWE_ALGORITHM_CODE: &str = "e.p.crypto.unsupported-alg";
+
+/// Media type of encrypted DIDComm messages
+pub const DIDCOMM_ENCRYPTED_TYPE: &str = "application/didcomm-encrypted+json";
+
+/// Key agreement algorithm of anonymous encryption
+pub const ECDH_ES_A256KW: &str = "ECDH-ES+A256KW";
+
+/// Key agreement algorithm of authenticated encryption
+pub const ECDH_1PU_A256KW: &str = "ECDH-1PU+A256KW";
+
+/// Content encryption algorithm required by authenticated encryption
+pub const A256CBC_HS512: &str = "A256CBC-HS512";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum JweHeaderError {
+    #[error("missing header parameter `{0}`")]
+    MissingParameter(&'static str),
+    #[error("header parameter `{0}` is not valid base64url")]
+    InvalidEncoding(&'static str),
+    #[error("key agreement algorithm `{0}` is not allowed")]
+    DisallowedAlgorithm(String),
+    #[error("content encryption algorithm `{0}` is not allowed")]
+    DisallowedEncryption(String),
+    #[error("curve `{0}` is not allowed")]
+    DisallowedCurve(String),
+    #[error("content encryption `{1}` is not allowed with key agreement `{0}`")]
+    DisallowedCombination(String, String),
+    #[error("unexpected media type `{0}`")]
+    UnexpectedType(String),
+    #[error("`apv` does not match the recipients of the message")]
+    RecipientsMismatch,
+    #[error("`apu` does not match sender key `{0}`")]
+    SenderMismatch(String),
+    #[error("sender key `{0}` does not belong to sender `{1}`")]
+    SenderKeyMismatch(String, String),
+    #[error("anonymous encryption must not identify a sender")]
+    UnexpectedSender,
+}
+
+impl JweHeaderError {
+    /// Describes the error as a problem report, detailing the reason of
+    /// the rejection
+    pub fn to_problem_report(&self) -> ProblemReport {
+        let code = match self {
+            Self::DisallowedAlgorithm(_)
+            | Self::DisallowedEncryption(_)
+            | Self::DisallowedCurve(_)
+            | Self::DisallowedCombination(..) => UNSUPPORTED_JWE_ALGORITHM_CODE,
+            _ => INVALID_JWE_HEADER_CODE,
+        };
+
+        ProblemReport::new(
+            code,
+            Some("Encrypted message rejected: {1}"),
+            Some(vec![self.to_string()]),
+        )
+    }
+}
+
+/// How strictly headers are validated, by increasing strictness. Each
+/// level also performs the checks of the ones below it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strictness {
+    /// Only algorithms are checked against allowlists
+    Lenient,
+    /// Key agreement parameters present must be consistent with the
+    /// parties involved, and `apv` is required
+    #[default]
+    Standard,
+    /// Additionally, authenticated encryption requires `apu` and
+    /// `A256CBC-HS512`, and the media type must be declared
+    Strict,
+}
+
+impl Strictness {
+    /// Strictness configured by `JWE_VALIDATION`, `standard` by default
+    pub fn from_env() -> Self {
+        server_plugin::reload::var("JWE_VALIDATION")
+            .and_then(|v| serde_json::from_value(Value::String(v.to_lowercase())).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Protected header of an encrypted message
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct JweHeader {
+    /// Media type
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>,
+
+    /// Key agreement algorithm
+    pub alg: String,
+
+    /// Content encryption algorithm
+    pub enc: String,
+
+    /// Key id of the sender, for authenticated encryption
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skid: Option<String>,
+
+    /// Agreement PartyUInfo, the base64url-encoded key id of the sender
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apu: Option<String>,
+
+    /// Agreement PartyVInfo, the base64url-encoded SHA-256 digest of the
+    /// sorted key ids of the recipients, joined by dots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apv: Option<String>,
+
+    /// Ephemeral public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epk: Option<Value>,
+
+    /// Dynamic properties
+    #[serde(flatten)]
+    pub additional_properties: Option<HashMap<String, Value>>,
+}
+
+/// `apv` expected of a message encrypted for the given recipients
+pub fn expected_apv<S: AsRef<str>>(recipient_kids: &[S]) -> String {
+    let mut kids: Vec<_> = recipient_kids.iter().map(AsRef::as_ref).collect();
+    kids.sort_unstable();
+
+    Base64Url.encode(sha256_hash(kids.join(".").as_bytes()))
+}
+
+/// Validates protected headers of encrypted messages before unpacking
+#[derive(Debug, Clone, PartialEq)]
+pub struct JweHeaderValidator {
+    pub strictness: Strictness,
+
+    /// Key agreement algorithms allowed
+    pub algorithms: Vec<String>,
+
+    /// Content encryption algorithms allowed
+    pub encryptions: Vec<String>,
+
+    /// Curves of ephemeral keys allowed
+    pub curves: Vec<String>,
+}
+
+impl Default for JweHeaderValidator {
+    fn default() -> Self {
+        Self::from_policy(Strictness::default(), &CryptoPolicy::default())
+    }
+}
+
+impl JweHeaderValidator {
+    pub fn new(strictness: Strictness) -> Self {
+        Self {
+            strictness,
+            ..Default::default()
+        }
+    }
+
+    /// Validator allowing the algorithms a crypto policy allows inbound
+    pub fn from_policy(strictness: Strictness, policy: &CryptoPolicy) -> Self {
+        let allowed = policy.lists(Direction::Inbound).allowed();
+
+        Self {
+            strictness,
+            algorithms: allowed.key_agreements,
+            encryptions: allowed.encryptions,
+            curves: allowed.curves,
+        }
+    }
+
+    /// Validates the header of a message encrypted for the given
+    /// recipients, identified by the key ids of the JWE
+    pub fn validate<S: AsRef<str>>(
+        &self,
+        header: &JweHeader,
+        recipient_kids: &[S],
+    ) -> Result<(), JweHeaderError> {
+        if !self.algorithms.contains(&header.alg) {
+            return Err(JweHeaderError::DisallowedAlgorithm(header.alg.clone()));
+        }
+        if !self.encryptions.contains(&header.enc) {
+            return Err(JweHeaderError::DisallowedEncryption(header.enc.clone()));
+        }
+        let crv = header.epk.as_ref().and_then(|epk| epk.get("crv"));
+        if let Some(crv) = crv.and_then(Value::as_str) {
+            if !self.curves.iter().any(|c| c == crv) {
+                return Err(JweHeaderError::DisallowedCurve(crv.to_owned()));
+            }
+        }
+        if self.strictness == Strictness::Lenient {
+            return Ok(());
+        }
+
+        let apv = header
+            .apv
+            .as_deref()
+            .ok_or(JweHeaderError::MissingParameter("apv"))?;
+        if apv != expected_apv(recipient_kids) {
+            return Err(JweHeaderError::RecipientsMismatch);
+        }
+
+        let authenticated = header.alg == ECDH_1PU_A256KW;
+        if !authenticated {
+            return match (&header.skid, &header.apu) {
+                (None, None) => self.validate_type(header),
+                _ => Err(JweHeaderError::UnexpectedSender),
+            };
+        }
+
+        let skid = header
+            .skid
+            .as_deref()
+            .ok_or(JweHeaderError::MissingParameter("skid"))?;
+        match header.apu.as_deref() {
+            Some(apu) => {
+                let decoded = Base64Url
+                    .decode(apu)
+                    .map_err(|_| JweHeaderError::InvalidEncoding("apu"))?;
+                if decoded != skid.as_bytes() {
+                    return Err(JweHeaderError::SenderMismatch(skid.to_owned()));
+                }
+            }
+            None if self.strictness == Strictness::Strict => {
+                return Err(JweHeaderError::MissingParameter("apu"));
+            }
+            None => (),
+        }
+
+        if self.strictness == Strictness::Strict && header.enc != A256CBC_HS512 {
+            return Err(JweHeaderError::DisallowedCombination(
+                header.alg.clone(),
+                header.enc.clone(),
+            ));
+        }
+
+        self.validate_type(header)
+    }
+
+    /// Checks that the sender key of an authenticated message belongs to
+    /// the sender declared by the unpacked message, in its `from` header
+    pub fn validate_sender(&self, header: &JweHeader, from: &str) -> Result<(), JweHeaderError> {
+        let Some(skid) = header.skid.as_deref() else {
+            return Ok(());
+        };
+
+        let did = skid.split_once('#').map_or(skid, |(did, _)| did);
+        if did != from {
+            return Err(JweHeaderError::SenderKeyMismatch(
+                skid.to_owned(),
+                from.to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_type(&self, header: &JweHeader) -> Result<(), JweHeaderError> {
+        match header.typ.as_deref() {
+            Some(DIDCOMM_ENCRYPTED_TYPE) => Ok(()),
+            None if self.strictness < Strictness::Strict => Ok(()),
+            None => Err(JweHeaderError::MissingParameter("typ")),
+            Some(typ) => Err(JweHeaderError::UnexpectedType(typ.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE_KID: &str = "did:example:alice#key-x25519-1";
+    const BOB_KIDS: [&str; 2] = [
+        "did:example:bob#key-x25519-2",
+        "did:example:bob#key-x25519-1",
+    ];
+
+    fn authcrypt() -> JweHeader {
+        JweHeader {
+            typ: Some(DIDCOMM_ENCRYPTED_TYPE.to_owned()),
+            alg: ECDH_1PU_A256KW.to_owned(),
+            enc: A256CBC_HS512.to_owned(),
+            skid: Some(ALICE_KID.to_owned()),
+            apu: Some(Base64Url.encode(ALICE_KID)),
+            apv: Some(expected_apv(&BOB_KIDS)),
+            ..Default::default()
+        }
+    }
+
+    fn anoncrypt() -> JweHeader {
+        JweHeader {
+            alg: ECDH_ES_A256KW.to_owned(),
+            enc: "XC20P".to_owned(),
+            apv: Some(expected_apv(&BOB_KIDS)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn can_serde_jwe_header() {
+        let msg = r#"{
+            "typ": "application/didcomm-encrypted+json",
+            "alg": "ECDH-1PU+A256KW",
+            "enc": "A256CBC-HS512",
+            "skid": "did:example:alice#key-x25519-1",
+            "apu": "ZGlkOmV4YW1wbGU6YWxpY2Uja2V5LXgyNTUxOS0x",
+            "apv": "NcsuAnrRfPK69A-rkZ0L9XWUG4jMvNC3Zg74BPz53PA",
+            "epk": {"kty": "OKP", "crv": "X25519", "x": "GFcMopJljf4pLZfch4a_GhTM_YAf6iNI1dWDGyVCaw0"}
+        }"#;
+
+        let header: JweHeader = serde_json::from_str(msg).unwrap();
+        assert_eq!(header.apu, authcrypt().apu);
+        assert!(header.epk.is_some());
+    }
+
+    #[test]
+    fn test_apv_ignores_order_of_recipients() {
+        let reversed = [BOB_KIDS[1], BOB_KIDS[0]];
+        assert_eq!(expected_apv(&BOB_KIDS), expected_apv(&reversed));
+        assert_ne!(expected_apv(&BOB_KIDS), expected_apv(&BOB_KIDS[..1]));
+    }
+
+    #[test]
+    fn test_validating_well_formed_headers() {
+        for strictness in [
+            Strictness::Lenient,
+            Strictness::Standard,
+            Strictness::Strict,
+        ] {
+            let validator = JweHeaderValidator::new(strictness);
+            validator.validate(&authcrypt(), &BOB_KIDS).unwrap();
+            validator
+                .validate_sender(&authcrypt(), "did:example:alice")
+                .unwrap();
+        }
+
+        let validator = JweHeaderValidator::default();
+        validator.validate(&anoncrypt(), &BOB_KIDS).unwrap();
+    }
+
+    #[test]
+    fn test_rejecting_downgrades() {
+        let validator = JweHeaderValidator::new(Strictness::Lenient);
+
+        let header = JweHeader {
+            alg: "ECDH-ES".to_owned(),
+            ..anoncrypt()
+        };
+        assert_eq!(
+            validator.validate(&header, &BOB_KIDS),
+            Err(JweHeaderError::DisallowedAlgorithm("ECDH-ES".to_owned()))
+        );
+
+        let header = JweHeader {
+            enc: "A128GCM".to_owned(),
+            ..anoncrypt()
+        };
+        assert_eq!(
+            validator.validate(&header, &BOB_KIDS),
+            Err(JweHeaderError::DisallowedEncryption("A128GCM".to_owned()))
+        );
+
+        // Authenticated encryption is bound to A256CBC-HS512 when strict
+        let header = JweHeader {
+            enc: "A256GCM".to_owned(),
+            ..authcrypt()
+        };
+        JweHeaderValidator::default()
+            .validate(&header, &BOB_KIDS)
+            .unwrap();
+        assert!(matches!(
+            JweHeaderValidator::new(Strictness::Strict).validate(&header, &BOB_KIDS),
+            Err(JweHeaderError::DisallowedCombination(..))
+        ));
+    }
+
+    #[test]
+    fn test_validating_against_crypto_policy() {
+        let policy = CryptoPolicy::parse(
+            r#"{"inbound": {"curves": {"deny": ["P-256"]}, "keyAgreements": {"deny": ["ECDH-ES+A256KW"]}}}"#,
+        )
+        .unwrap();
+        let validator = JweHeaderValidator::from_policy(Strictness::Standard, &policy);
+
+        assert_eq!(
+            validator.validate(&anoncrypt(), &BOB_KIDS),
+            Err(JweHeaderError::DisallowedAlgorithm(
+                ECDH_ES_A256KW.to_owned()
+            ))
+        );
+
+        let header = JweHeader {
+            epk: Some(serde_json::json!({"kty": "EC", "crv": "P-256"})),
+            ..authcrypt()
+        };
+        assert_eq!(
+            validator.validate(&header, &BOB_KIDS),
+            Err(JweHeaderError::DisallowedCurve(String::from("P-256")))
+        );
+
+        let header = JweHeader {
+            epk: Some(serde_json::json!({"kty": "OKP", "crv": "X25519"})),
+            ..authcrypt()
+        };
+        validator.validate(&header, &BOB_KIDS).unwrap();
+    }
+
+    #[test]
+    fn test_rejecting_inconsistent_parties() {
+        let validator = JweHeaderValidator::default();
+
+        // Messages re-targeted at other recipients
+        assert_eq!(
+            validator.validate(&authcrypt(), &BOB_KIDS[..1]),
+            Err(JweHeaderError::RecipientsMismatch)
+        );
+
+        let header = JweHeader {
+            apv: None,
+            ..authcrypt()
+        };
+        assert_eq!(
+            validator.validate(&header, &BOB_KIDS),
+            Err(JweHeaderError::MissingParameter("apv"))
+        );
+
+        // Messages attributed to other senders
+        let header = JweHeader {
+            skid: Some("did:example:mallory#key-1".to_owned()),
+            ..authcrypt()
+        };
+        assert_eq!(
+            validator.validate(&header, &BOB_KIDS),
+            Err(JweHeaderError::SenderMismatch(
+                "did:example:mallory#key-1".to_owned()
+            ))
+        );
+        assert!(matches!(
+            validator.validate_sender(&authcrypt(), "did:example:mallory"),
+            Err(JweHeaderError::SenderKeyMismatch(..))
+        ));
+
+        let header = JweHeader {
+            apu: Some("not base64!".to_owned()),
+            ..authcrypt()
+        };
+        assert_eq!(
+            validator.validate(&header, &BOB_KIDS),
+            Err(JweHeaderError::InvalidEncoding("apu"))
+        );
+
+        let header = JweHeader {
+            skid: Some(ALICE_KID.to_owned()),
+            ..anoncrypt()
+        };
+        assert_eq!(
+            validator.validate(&header, &BOB_KIDS),
+            Err(JweHeaderError::UnexpectedSender)
+        );
+
+        // Strictness decides whether omitted parameters are tolerated
+        let header = JweHeader {
+            apu: None,
+            typ: None,
+            ..authcrypt()
+        };
+        validator.validate(&header, &BOB_KIDS).unwrap();
+        assert_eq!(
+            JweHeaderValidator::new(Strictness::Strict).validate(&header, &BOB_KIDS),
+            Err(JweHeaderError::MissingParameter("apu"))
+        );
+    }
+
+    #[test]
+    fn test_reporting_rejections() {
+        let report = JweHeaderError::RecipientsMismatch.to_problem_report();
+        assert_eq!(report.body.code, INVALID_JWE_HEADER_CODE);
+        assert_eq!(
+            report.body.args.unwrap(),
+            ["`apv` does not match the recipients of the message"]
+        );
+
+        let report = JweHeaderError::DisallowedAlgorithm("ECDH-ES".to_owned()).to_problem_report();
+        assert_eq!(report.body.code, UNSUPPORTED_JWE_ALGORITHM_CODE);
+    }
+}
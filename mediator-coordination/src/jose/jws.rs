@@ -8,6 +8,8 @@ use serde_json::Value;
 use std::collections::HashMap;
 use thiserror::Error;
 
+use super::policy::{CryptoPolicy, Direction};
+
 #[derive(Debug, Error, PartialEq)]
 #[allow(unused)]
 pub enum JwsError {
@@ -37,6 +39,8 @@ pub enum JwsError {
     UnsupportedAlgorithm,
     #[error("unsupported payload type")]
     UnsupportedPayloadType,
+    #[error("{0}")]
+    DisallowedByPolicy(String),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
@@ -105,6 +109,18 @@ pub fn make_compact_jws(header: &JwsHeader, payload: Value, jwk: &Jwk) -> Result
     }
 }
 
+/// Issues a JSON Web Signature (JWS), provided the crypto policy allows
+/// its algorithm and the curve of the signing key
+pub fn make_compact_jws_with_policy(
+    policy: &CryptoPolicy,
+    header: &JwsHeader,
+    payload: Value,
+    jwk: &Jwk,
+) -> Result<String, JwsError> {
+    check_policy(policy, Direction::Outbound, &header.alg, jwk)?;
+    make_compact_jws(header, payload, jwk)
+}
+
 pub fn make_compact_jws_ed25519(phrase: String, jwk: &Jwk) -> Result<String, JwsError> {
     let keypair: Ed25519KeyPair = jwk
         .clone()
@@ -136,6 +152,38 @@ pub fn verify_compact_jws(jws: &str, jwk: &Jwk) -> Result<(), JwsError> {
     }
 }
 
+/// Verifies a JSON Web Signature (JWS), provided the crypto policy allows
+/// its algorithm and the curve of the verifying key
+pub fn verify_compact_jws_with_policy(
+    policy: &CryptoPolicy,
+    jws: &str,
+    jwk: &Jwk,
+) -> Result<(), JwsError> {
+    if jws.is_empty() {
+        return Err(JwsError::EmptyInput);
+    }
+
+    let header = read_jws_header(jws)?;
+    check_policy(policy, Direction::Inbound, &header.alg, jwk)?;
+    verify_compact_jws(jws, jwk)
+}
+
+fn check_policy(
+    policy: &CryptoPolicy,
+    direction: Direction,
+    alg: &JwsAlg,
+    jwk: &Jwk,
+) -> Result<(), JwsError> {
+    let alg = match alg {
+        JwsAlg::EdDSA => "EdDSA",
+        JwsAlg::Unknown(alg) => alg,
+    };
+
+    policy
+        .check_signature(direction, alg, jwk)
+        .map_err(|err| JwsError::DisallowedByPolicy(err.to_string()))
+}
+
 fn verify_compact_jws_ed25519(jws: &str, jwk: &Jwk) -> Result<(), JwsError> {
     let parts: Vec<_> = jws.split('.').collect();
     if parts.len() != 3 {
@@ -184,10 +232,10 @@ mod tests {
     use multibase::Base::Base64Url;
     use serde_json::json;
 
-    use crate::util::{self, MockFileSystem};
+    use crate::util::{self, mock_fs};
 
     fn setup() -> Jwk {
-        let mut mock_fs = MockFileSystem;
+        let mut mock_fs = mock_fs();
 
         let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
         let (_, pubkey) = util::extract_assertion_key(&diddoc).unwrap();
@@ -246,6 +294,38 @@ mod tests {
         assert_eq!(jws, expected_jws);
     }
 
+    #[test]
+    fn can_restrict_jws_by_policy() {
+        let jwk = setup();
+        let header = JwsHeader::default();
+        let payload = json!({"content": "restricted"});
+
+        let permissive = CryptoPolicy::default();
+        let jws =
+            make_compact_jws_with_policy(&permissive, &header, payload.clone(), &jwk).unwrap();
+        verify_compact_jws_with_policy(&permissive, &jws, &jwk.to_public()).unwrap();
+
+        // Ed25519 signatures may be received, but not issued
+        let policy = CryptoPolicy::parse(
+            r#"{"inbound": {}, "outbound": {"curves": {"deny": ["Ed25519"]}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            make_compact_jws_with_policy(&policy, &header, payload, &jwk),
+            Err(JwsError::DisallowedByPolicy(_))
+        ));
+        verify_compact_jws_with_policy(&policy, &jws, &jwk.to_public()).unwrap();
+
+        let policy =
+            CryptoPolicy::parse(r#"{"inbound": {"signatures": {"deny": ["EdDSA"]}}}"#).unwrap();
+        assert_eq!(
+            verify_compact_jws_with_policy(&policy, &jws, &jwk.to_public()),
+            Err(JwsError::DisallowedByPolicy(String::from(
+                "signature algorithm `EdDSA` is not allowed by the crypto policy"
+            )))
+        );
+    }
+
     #[test]
     fn can_make_compact_jws_v2() {
         let jwk = setup();
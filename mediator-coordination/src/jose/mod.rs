@@ -1 +1,6 @@
+pub mod jwe;
 pub mod jws;
+pub mod policy;
+
+#[cfg(test)]
+mod golden;
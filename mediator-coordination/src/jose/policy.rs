@@ -0,0 +1,317 @@
+//! Cryptographic algorithm policy.
+//!
+//! Operators restrict the curves, key agreement, content encryption and
+//! signature algorithms the mediator uses, e.g. to comply with a security
+//! baseline, through allow and deny lists in the `CRYPTO_POLICY` setting.
+//! Lists apply to messages received (inbound) and to messages the mediator
+//! packs or signs (outbound), the latter defaulting to the former. Only
+//! algorithms the mediator supports may be allowed, and only those allowed
+//! for both directions are advertised to peers.
+//!
+//! e.g.:
+//! ```json
+//! {
+//!   "inbound": {
+//!     "curves": {"deny": ["P-256"]},
+//!     "encryptions": {"allow": ["A256CBC-HS512", "XC20P"]}
+//!   }
+//! }
+//! ```
+
+use did_utils::key_jwk::jwk::Jwk;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::jwe::{A256CBC_HS512, ECDH_1PU_A256KW, ECDH_ES_A256KW};
+
+/// Curves the mediator supports
+pub const SUPPORTED_CURVES: [&str; 4] = ["Ed25519", "X25519", "P-256", "secp256k1"];
+
+/// Key agreement algorithms the mediator supports
+pub const SUPPORTED_KEY_AGREEMENTS: [&str; 2] = [ECDH_ES_A256KW, ECDH_1PU_A256KW];
+
+/// Content encryption algorithms the mediator supports
+pub const SUPPORTED_ENCRYPTIONS: [&str; 3] = [A256CBC_HS512, "A256GCM", "XC20P"];
+
+/// Signature algorithms the mediator supports
+pub const SUPPORTED_SIGNATURES: [&str; 1] = ["EdDSA"];
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CryptoPolicyError {
+    #[error("invalid crypto policy: {0}")]
+    ParseError(String),
+    #[error("{0} `{1}` is not allowed by the crypto policy")]
+    Disallowed(&'static str, String),
+}
+
+/// Direction of the messages an algorithm is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Messages received by the mediator
+    Inbound,
+    /// Messages packed or signed by the mediator
+    Outbound,
+}
+
+/// Allow and deny lists of algorithms. Algorithms are allowed if listed
+/// in `allow`, or if it is missing, and not listed in `deny`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AlgorithmList {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl AlgorithmList {
+    pub fn permits(&self, name: &str) -> bool {
+        let allowed = self
+            .allow
+            .as_ref()
+            .is_none_or(|allow| allow.iter().any(|a| a == name));
+
+        allowed && !self.deny.iter().any(|d| d == name)
+    }
+
+    /// Supported algorithms permitted by the list, in order of preference
+    pub fn filter(&self, supported: &[&str]) -> Vec<String> {
+        supported
+            .iter()
+            .filter(|name| self.permits(name))
+            .map(|name| name.to_string())
+            .collect()
+    }
+}
+
+/// Lists of algorithms applying to one direction
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AlgorithmPolicy {
+    #[serde(default)]
+    pub curves: AlgorithmList,
+
+    #[serde(default)]
+    pub key_agreements: AlgorithmList,
+
+    #[serde(default)]
+    pub encryptions: AlgorithmList,
+
+    #[serde(default)]
+    pub signatures: AlgorithmList,
+}
+
+/// Suites allowed by a policy, as advertised to peers
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AllowedSuites {
+    pub curves: Vec<String>,
+    pub key_agreements: Vec<String>,
+    pub encryptions: Vec<String>,
+    pub signatures: Vec<String>,
+}
+
+impl AlgorithmPolicy {
+    /// Supported suites this policy allows
+    pub fn allowed(&self) -> AllowedSuites {
+        AllowedSuites {
+            curves: self.curves.filter(&SUPPORTED_CURVES),
+            key_agreements: self.key_agreements.filter(&SUPPORTED_KEY_AGREEMENTS),
+            encryptions: self.encryptions.filter(&SUPPORTED_ENCRYPTIONS),
+            signatures: self.signatures.filter(&SUPPORTED_SIGNATURES),
+        }
+    }
+}
+
+/// Policy of cryptographic algorithms, by direction
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CryptoPolicy {
+    #[serde(default)]
+    pub inbound: AlgorithmPolicy,
+
+    /// Lists for outbound messages, those for inbound ones if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outbound: Option<AlgorithmPolicy>,
+}
+
+impl CryptoPolicy {
+    /// Policy configured by `CRYPTO_POLICY`, allowing all supported
+    /// algorithms if missing. Invalid policies are rejected, rather than
+    /// falling back to a more permissive one.
+    pub fn from_env() -> Result<Self, CryptoPolicyError> {
+        match server_plugin::reload::var("CRYPTO_POLICY") {
+            Some(content) => Self::parse(&content),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, CryptoPolicyError> {
+        serde_json::from_str(content).map_err(|err| CryptoPolicyError::ParseError(err.to_string()))
+    }
+
+    /// Lists applying to a direction
+    pub fn lists(&self, direction: Direction) -> &AlgorithmPolicy {
+        match direction {
+            Direction::Inbound => &self.inbound,
+            Direction::Outbound => self.outbound.as_ref().unwrap_or(&self.inbound),
+        }
+    }
+
+    /// Suites advertised to peers, i.e. allowed in both directions
+    pub fn advertised(&self) -> AllowedSuites {
+        let inbound = self.inbound.allowed();
+        let outbound = self.lists(Direction::Outbound).allowed();
+        let both = |inbound: Vec<String>, outbound: &[String]| {
+            inbound
+                .into_iter()
+                .filter(|name| outbound.contains(name))
+                .collect()
+        };
+
+        AllowedSuites {
+            curves: both(inbound.curves, &outbound.curves),
+            key_agreements: both(inbound.key_agreements, &outbound.key_agreements),
+            encryptions: both(inbound.encryptions, &outbound.encryptions),
+            signatures: both(inbound.signatures, &outbound.signatures),
+        }
+    }
+
+    /// Checks a signature algorithm, and the curve of the key it is used
+    /// with, against the policy
+    pub fn check_signature(
+        &self,
+        direction: Direction,
+        alg: &str,
+        jwk: &Jwk,
+    ) -> Result<(), CryptoPolicyError> {
+        let allowed = self.lists(direction).allowed();
+        if !allowed.signatures.iter().any(|a| a == alg) {
+            return Err(CryptoPolicyError::Disallowed(
+                "signature algorithm",
+                alg.to_owned(),
+            ));
+        }
+
+        match curve(jwk) {
+            Some(crv) if !allowed.curves.contains(&crv) => {
+                Err(CryptoPolicyError::Disallowed("curve", crv))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Curve of a key, if any
+pub fn curve(jwk: &Jwk) -> Option<String> {
+    let jwk = serde_json::to_value(jwk).ok()?;
+    jwk.get("crv")?.as_str().map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn policy() -> CryptoPolicy {
+        CryptoPolicy::parse(
+            r#"{
+                "inbound": {
+                    "curves": {"deny": ["P-256"]},
+                    "encryptions": {"allow": ["A256CBC-HS512", "XC20P", "A128GCM"]}
+                },
+                "outbound": {
+                    "encryptions": {"allow": ["A256CBC-HS512"]}
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_applying_lists() {
+        let list = AlgorithmList {
+            allow: Some(vec![String::from("XC20P"), String::from("A256GCM")]),
+            deny: vec![String::from("A256GCM")],
+        };
+        assert!(list.permits("XC20P"));
+        assert!(!list.permits("A256GCM"));
+        assert!(!list.permits("A256CBC-HS512"));
+
+        assert!(AlgorithmList::default().permits("anything"));
+        assert_eq!(list.filter(&SUPPORTED_ENCRYPTIONS), ["XC20P"]);
+    }
+
+    #[test]
+    fn test_allowing_suites_by_direction() {
+        let policy = policy();
+
+        // Unsupported algorithms are never allowed
+        let inbound = policy.lists(Direction::Inbound).allowed();
+        assert_eq!(inbound.encryptions, ["A256CBC-HS512", "XC20P"]);
+        assert_eq!(inbound.curves, ["Ed25519", "X25519", "secp256k1"]);
+
+        let outbound = policy.lists(Direction::Outbound).allowed();
+        assert_eq!(outbound.encryptions, ["A256CBC-HS512"]);
+        assert_eq!(outbound.curves.len(), SUPPORTED_CURVES.len());
+
+        let advertised = policy.advertised();
+        assert_eq!(advertised.encryptions, ["A256CBC-HS512"]);
+        assert_eq!(advertised.curves, ["Ed25519", "X25519", "secp256k1"]);
+        assert_eq!(advertised.signatures, ["EdDSA"]);
+
+        // Outbound lists default to inbound ones
+        let policy = CryptoPolicy {
+            outbound: None,
+            ..policy
+        };
+        assert_eq!(
+            policy.lists(Direction::Outbound),
+            policy.lists(Direction::Inbound)
+        );
+    }
+
+    #[test]
+    fn test_checking_signatures() {
+        let policy =
+            CryptoPolicy::parse(r#"{"inbound": {"curves": {"deny": ["Ed25519"]}}}"#).unwrap();
+        let jwk: Jwk = serde_json::from_value(json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": "tjOTPcs4OEMNrmn2ScYZDS-aCCbRFhJgaAmGnRsdmEo"
+        }))
+        .unwrap();
+
+        assert_eq!(curve(&jwk).as_deref(), Some("Ed25519"));
+        assert_eq!(
+            policy.check_signature(Direction::Inbound, "EdDSA", &jwk),
+            Err(CryptoPolicyError::Disallowed(
+                "curve",
+                String::from("Ed25519")
+            ))
+        );
+        assert!(matches!(
+            policy.check_signature(Direction::Inbound, "ES256", &jwk),
+            Err(CryptoPolicyError::Disallowed("signature algorithm", _))
+        ));
+
+        CryptoPolicy::default()
+            .check_signature(Direction::Outbound, "EdDSA", &jwk)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_rejecting_invalid_policies() {
+        for content in [
+            "{",
+            r#"{"inbound": {"ciphers": {}}}"#,
+            r#"{"inbound": {"curves": {"allowed": []}}}"#,
+        ] {
+            assert!(matches!(
+                CryptoPolicy::parse(content),
+                Err(CryptoPolicyError::ParseError(_))
+            ));
+        }
+    }
+}
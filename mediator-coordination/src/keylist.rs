@@ -0,0 +1,248 @@
+//! Keylist updates of connections.
+//!
+//! Updates are applied to the latest version of the connection, so that
+//! concurrent updates of a keylist, e.g. from several devices of a client,
+//! are not lost: an update conflicting with another one is applied again
+//! on top of it, up to [`UPDATE_ATTEMPTS`] times.
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::{
+    constants::*,
+    didcomm::{
+        problem_report::ProblemReport,
+        resolution,
+        validation::{MessageValidator, INVALID_MESSAGE_CODE, UNSUPPORTED_MESSAGE_CODE},
+    },
+    model::{
+        connection::Connection,
+        coord::{
+            message_specs, CoordMessage, KeylistUpdate, KeylistUpdateAction, KeylistUpdateItem,
+            KeylistUpdateResponse, KeylistUpdateResponseBody, KeylistUpdateResponseItem,
+            KeylistUpdateResult,
+        },
+    },
+    repository::{update_with_retry, Repository},
+};
+
+/// Times a keylist update is attempted before reporting a conflict
+pub const UPDATE_ATTEMPTS: usize = 5;
+
+/// Applies updates to the keylist of a connection, returning their results
+pub fn apply(
+    connection: &mut Connection,
+    updates: &[KeylistUpdateItem],
+) -> Vec<KeylistUpdateResponseItem> {
+    updates
+        .iter()
+        .map(|update| {
+            let known = connection.keylist.contains(&update.recipient_did);
+            let result = match (update.action, known) {
+                (KeylistUpdateAction::Add, false) => {
+                    connection.keylist.push(update.recipient_did.clone());
+                    KeylistUpdateResult::Success
+                }
+                (KeylistUpdateAction::Remove, true) => {
+                    connection
+                        .keylist
+                        .retain(|key| key != &update.recipient_did);
+                    KeylistUpdateResult::Success
+                }
+                _ => KeylistUpdateResult::NoChange,
+            };
+
+            KeylistUpdateResponseItem {
+                recipient_did: update.recipient_did.clone(),
+                action: update.action,
+                result,
+            }
+        })
+        .collect()
+}
+
+/// Handles a keylist update from the client of a connection, returning the
+/// keylist update response to send back.
+#[allow(clippy::result_large_err)]
+pub fn handle(
+    repository: &dyn Repository<Connection>,
+    client_did: &str,
+    message: &Value,
+) -> Result<Value, ProblemReport> {
+    MessageValidator::new(message_specs()).check(message)?;
+
+    let pthid = message.get("id").and_then(Value::as_str);
+    match message["type"].as_str().unwrap_or_default() {
+        KEYLIST_UPDATE_2_0 => {
+            let update: KeylistUpdate = deserialize(message)?;
+            let added = update.body.updates.iter().filter_map(|update| {
+                (update.action == KeylistUpdateAction::Add).then_some(update.recipient_did.as_str())
+            });
+            resolution::check_all(added, pthid)?;
+
+            let (connection, updated) =
+                update_with_retry(repository, client_did, UPDATE_ATTEMPTS, |connection| {
+                    apply(connection, &update.body.updates)
+                })
+                .map_err(|err| {
+                    tracing::warn!("keylist update of {client_did} failed: {err}");
+                    err.to_problem_report().with_pthid(pthid)
+                })?;
+
+            tracing::debug!(
+                "updated keylist of {client_did} to version {}",
+                connection.version
+            );
+            Ok(json!(KeylistUpdateResponse::reply_to(
+                &update,
+                KEYLIST_UPDATE_RESPONSE_2_0,
+                KeylistUpdateResponseBody { updated }
+            )))
+        }
+        t => Err(ProblemReport::new(
+            UNSUPPORTED_MESSAGE_CODE,
+            Some("Unsupported message type {1}"),
+            Some(vec![t.to_owned()]),
+        )
+        .with_pthid(pthid)),
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn deserialize<B: DeserializeOwned + Default>(
+    message: &Value,
+) -> Result<CoordMessage<B>, ProblemReport> {
+    serde_json::from_value(message.clone()).map_err(|_| {
+        let pthid = message.get("id").and_then(Value::as_str);
+        ProblemReport::new(INVALID_MESSAGE_CODE, Some("Malformed message"), None).with_pthid(pthid)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{MemoryRepository, RepositoryError, CONFLICT_CODE};
+    use std::{
+        sync::{Arc, Barrier},
+        thread,
+    };
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+
+    fn repository() -> MemoryRepository<Connection> {
+        let repository = MemoryRepository::new();
+        let connection = Connection {
+            client_did: ALICE.to_owned(),
+            keylist: vec![format!("{ALICE}#key-0")],
+            ..Default::default()
+        };
+        repository.insert(connection).unwrap();
+        repository
+    }
+
+    fn keylist_update(id: &str, updates: Value) -> Value {
+        json!({
+            "id": id,
+            "type": KEYLIST_UPDATE_2_0,
+            "body": { "updates": updates }
+        })
+    }
+
+    #[test]
+    fn can_update_keylist() {
+        let repository = repository();
+        let message = keylist_update(
+            "update-0",
+            json!([
+                { "recipient_did": format!("{ALICE}#key-1"), "action": "add" },
+                { "recipient_did": format!("{ALICE}#key-0"), "action": "remove" },
+                { "recipient_did": format!("{ALICE}#key-2"), "action": "remove" },
+            ]),
+        );
+
+        let response: KeylistUpdateResponse =
+            serde_json::from_value(handle(&repository, ALICE, &message).unwrap()).unwrap();
+        assert_eq!(response.message_type, KEYLIST_UPDATE_RESPONSE_2_0);
+        assert_eq!(response.thid.as_deref(), Some("update-0"));
+
+        let results: Vec<_> = response
+            .body
+            .updated
+            .iter()
+            .map(|item| item.result)
+            .collect();
+        assert_eq!(
+            results,
+            [
+                KeylistUpdateResult::Success,
+                KeylistUpdateResult::Success,
+                KeylistUpdateResult::NoChange
+            ]
+        );
+
+        let connection = repository.find(ALICE).unwrap();
+        assert_eq!(connection.keylist, [format!("{ALICE}#key-1")]);
+        assert_eq!(connection.version, 2);
+    }
+
+    #[test]
+    fn should_not_lose_concurrent_keylist_updates() {
+        const CLIENTS: usize = 4;
+        const UPDATES: usize = 10;
+
+        let repository = repository();
+        let barrier = Arc::new(Barrier::new(CLIENTS));
+
+        let handles: Vec<_> = (0..CLIENTS)
+            .map(|client| {
+                let (repository, barrier) = (repository.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    (0..UPDATES).all(|update| {
+                        let key = format!("{ALICE}#key-{client}-{update}");
+                        let message = keylist_update(
+                            &format!("update-{client}-{update}"),
+                            json!([{ "recipient_did": key, "action": "add" }]),
+                        );
+                        // Conflicts exhausting the attempts are reported, and
+                        // retried by the client
+                        loop {
+                            match handle(&repository, ALICE, &message) {
+                                Ok(_) => break true,
+                                Err(report) if report.body.code == CONFLICT_CODE => continue,
+                                Err(_) => break false,
+                            }
+                        }
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+
+        let connection = repository.find(ALICE).unwrap();
+        assert_eq!(connection.keylist.len(), 1 + CLIENTS * UPDATES);
+        for client in 0..CLIENTS {
+            for update in 0..UPDATES {
+                assert!(connection
+                    .keylist
+                    .contains(&format!("{ALICE}#key-{client}-{update}")));
+            }
+        }
+    }
+
+    #[test]
+    fn should_report_unknown_connections() {
+        let repository = MemoryRepository::new();
+        let message = keylist_update(
+            "update-0",
+            json!([{ "recipient_did": ALICE, "action": "add" }]),
+        );
+
+        let report = handle(&repository, ALICE, &message).unwrap_err();
+        let expected = RepositoryError::NotFound(ALICE.to_owned()).to_problem_report();
+        assert_eq!(report.body.code, expected.body.code);
+        assert_eq!(report.pthid.as_deref(), Some("update-0"));
+    }
+}
@@ -0,0 +1,415 @@
+//! Selection of the mediator's keys per protocol and tenant.
+//!
+//! By default, the mediator signs with its first assertion method and
+//! encrypts with its first key agreement. Operators may dedicate other
+//! verification methods to some protocols or tenants in
+//! `key_selection.json` under the storage directory, e.g.:
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     {
+//!       "protocol": "https://didcomm.org/messagepickup/3.0",
+//!       "encryption": "did:web:mediator.example#keys-4"
+//!     },
+//!     {
+//!       "tenant": "acme",
+//!       "signing": "did:web:mediator.example#keys-5"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Signing and encryption are kept apart: signing keys are only selected
+//! among assertion methods, and encryption keys among key agreements.
+
+use did_endpoint::util::{
+    filesystem::FileSystem,
+    keystore::{KeyStore, ToPublic},
+};
+use did_utils::{
+    didcore::{AssertionMethod, Authentication, Document, KeyAgreement, KeyFormat},
+    key_jwk::jwk::Jwk,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::util;
+
+#[derive(Debug, Error)]
+pub enum KeySelectionError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("invalid key selection: {0}")]
+    ParseError(serde_json::Error),
+    #[error("{0} is not a {1:?} key of the DID document")]
+    InvalidMethod(String, KeyPurpose),
+}
+
+/// Domain a key is used in
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyPurpose {
+    /// Signatures, with assertion methods
+    Signing,
+    /// Encryption, with key agreements
+    Encryption,
+}
+
+/// Verification methods dedicated to a protocol, a tenant or both
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct KeyRule {
+    /// Protocol the rule applies to, as a prefix of message types
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+
+    /// Tenant the rule applies to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+
+    /// Verification method to sign with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing: Option<String>,
+
+    /// Verification method to encrypt with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<String>,
+}
+
+impl KeyRule {
+    fn method(&self, purpose: KeyPurpose) -> Option<&str> {
+        match purpose {
+            KeyPurpose::Signing => self.signing.as_deref(),
+            KeyPurpose::Encryption => self.encryption.as_deref(),
+        }
+    }
+
+    fn matches(&self, message_type: &str, tenant: Option<&str>) -> bool {
+        self.protocol
+            .as_deref()
+            .is_none_or(|protocol| message_type.starts_with(protocol))
+            && self.tenant.as_deref().is_none_or(|t| Some(t) == tenant)
+    }
+}
+
+/// Rules selecting the keys of the mediator
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct KeySelectionPolicy {
+    #[serde(default)]
+    pub rules: Vec<KeyRule>,
+}
+
+impl KeySelectionPolicy {
+    /// Loads the rules configured by the operator, if any.
+    pub fn load(fs: &dyn FileSystem, storage_dirpath: &str) -> Result<Self, KeySelectionError> {
+        match fs.read_to_string(&format!("{storage_dirpath}/key_selection.json")) {
+            Ok(content) => serde_json::from_str(&content).map_err(KeySelectionError::ParseError),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(KeySelectionError::IoError(err)),
+        }
+    }
+
+    /// Checks that rules only reference keys of the DID document in the
+    /// domain of their purpose
+    pub fn validate(&self, diddoc: &Document) -> Result<(), KeySelectionError> {
+        for rule in &self.rules {
+            for purpose in [KeyPurpose::Signing, KeyPurpose::Encryption] {
+                if let Some(vm_id) = rule.method(purpose) {
+                    find_key(diddoc, purpose, vm_id).ok_or_else(|| {
+                        KeySelectionError::InvalidMethod(vm_id.to_owned(), purpose)
+                    })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Selects the key to use for a message, returning its verification
+    /// method ID and public JWK.
+    ///
+    /// Rules for a tenant prevail over rules for all tenants, then rules
+    /// for longer protocol prefixes prevail. Messages matching no rule get
+    /// the first key of the purpose's domain.
+    pub fn select(
+        &self,
+        diddoc: &Document,
+        purpose: KeyPurpose,
+        message_type: &str,
+        tenant: Option<&str>,
+    ) -> Option<(String, Jwk)> {
+        let rule = self
+            .rules
+            .iter()
+            .filter(|rule| rule.method(purpose).is_some() && rule.matches(message_type, tenant))
+            .max_by_key(|rule| {
+                (
+                    rule.tenant.is_some(),
+                    rule.protocol.as_ref().map_or(0, String::len),
+                )
+            });
+
+        match (rule.and_then(|rule| rule.method(purpose)), purpose) {
+            (Some(vm_id), _) => find_key(diddoc, purpose, vm_id),
+            (None, KeyPurpose::Signing) => util::extract_assertion_key(diddoc),
+            (None, KeyPurpose::Encryption) => util::extract_agreement_key(diddoc),
+        }
+    }
+}
+
+fn find_key(diddoc: &Document, purpose: KeyPurpose, vm_id: &str) -> Option<(String, Jwk)> {
+    match purpose {
+        KeyPurpose::Signing => util::find_assertion_key(diddoc, vm_id),
+        KeyPurpose::Encryption => util::find_agreement_key(diddoc, vm_id),
+    }
+}
+
+/// Resolves the private keys of the mediator from the key identifiers
+/// found in incoming messages.
+///
+/// Secrets are indexed by key identifier when the resolver is built, so
+/// that lookups need not search the keystore, and cover every key of the
+/// DID document held in the keystore, e.g. several key agreements. The
+/// index is rebuilt by [`SecretsResolver::reload`] when the DID document or
+/// the keystore change.
+#[derive(Clone, Default)]
+pub struct SecretsResolver {
+    did: String,
+    secrets: HashMap<String, Jwk>,
+}
+
+impl SecretsResolver {
+    pub fn new(diddoc: &Document, keystore: &KeyStore) -> Self {
+        let mut resolver = Self::default();
+        resolver.reload(diddoc, keystore);
+
+        resolver
+    }
+
+    /// Indexes the secrets of the keys of a DID document by verification
+    /// method ID, and the secrets of all keys held by the thumbprint of
+    /// their public key.
+    pub fn reload(&mut self, diddoc: &Document, keystore: &KeyStore) {
+        let mut secrets = HashMap::new();
+
+        for keypair in keystore.keypairs() {
+            if let Ok(thumbprint) = keypair.to_public().thumbprint() {
+                secrets.insert(thumbprint, keypair.clone());
+            }
+        }
+
+        let embedded = diddoc
+            .authentication
+            .iter()
+            .flatten()
+            .filter_map(|method| match method {
+                Authentication::Embedded(vm) => Some(&**vm),
+                Authentication::Reference(_) => None,
+            })
+            .chain(
+                diddoc
+                    .assertion_method
+                    .iter()
+                    .flatten()
+                    .filter_map(|method| match method {
+                        AssertionMethod::Embedded(vm) => Some(&**vm),
+                        AssertionMethod::Reference(_) => None,
+                    }),
+            )
+            .chain(
+                diddoc
+                    .key_agreement
+                    .iter()
+                    .flatten()
+                    .filter_map(|method| match method {
+                        KeyAgreement::Embedded(vm) => Some(&**vm),
+                        KeyAgreement::Reference(_) => None,
+                    }),
+            );
+        let methods = diddoc.verification_method.iter().flatten().chain(embedded);
+
+        for vm in methods {
+            let Some(KeyFormat::Jwk(pubkey)) = &vm.public_key else {
+                continue;
+            };
+            if let Some(secret) = keystore.find_keypair(pubkey) {
+                secrets.insert(absolute(&diddoc.id, &vm.id), secret);
+            }
+        }
+
+        self.did.clone_from(&diddoc.id);
+        self.secrets = secrets;
+    }
+
+    /// Finds the private key identified by a verification method ID,
+    /// absolute or relative to the mediator's DID, or else by the
+    /// thumbprint of its public key.
+    ///
+    /// Thumbprints keep identifying keys across rotations, even after
+    /// they are renamed or retired from the DID document.
+    pub fn find_secret(&self, kid: &str) -> Option<Jwk> {
+        self.secrets
+            .get(&absolute(&self.did, kid))
+            .or_else(|| self.secrets.get(kid))
+            .cloned()
+    }
+
+    /// Number of key identifiers resolved
+    pub fn len(&self) -> usize {
+        self.secrets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+}
+
+/// Verification method ID made absolute, if relative to a DID
+fn absolute(did: &str, vm_id: &str) -> String {
+    match vm_id.starts_with('#') {
+        true => format!("{did}{vm_id}"),
+        false => vm_id.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{constants::*, util::mock_fs};
+    use did_utils::didcore::AssertionMethod;
+    use serde_json::json;
+
+    const DID: &str = "did:web:mediators-r-us.com";
+
+    fn diddoc() -> Document {
+        let mut diddoc = util::read_diddoc(&mock_fs(), "").unwrap();
+
+        // Dedicate the authentication key to assertions as well
+        diddoc
+            .assertion_method
+            .as_mut()
+            .unwrap()
+            .push(AssertionMethod::Reference(format!("{DID}#keys-1")));
+        diddoc
+    }
+
+    fn policy() -> KeySelectionPolicy {
+        serde_json::from_value(json!({
+            "rules": [
+                {
+                    "protocol": "https://didcomm.org/messagepickup/3.0",
+                    "signing": format!("{DID}#keys-1")
+                },
+                {
+                    "tenant": "acme",
+                    "signing": format!("{DID}#keys-2")
+                }
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn can_select_keys_per_protocol_and_tenant() {
+        let diddoc = diddoc();
+        let policy = policy();
+        policy.validate(&diddoc).unwrap();
+
+        let signing_key = |message_type, tenant| {
+            policy
+                .select(&diddoc, KeyPurpose::Signing, message_type, tenant)
+                .map(|(vm_id, _)| vm_id)
+                .unwrap()
+        };
+
+        assert_eq!(signing_key(STATUS_3_0, None), format!("{DID}#keys-1"));
+        assert_eq!(
+            signing_key(MEDIATE_GRANT_2_0, None),
+            format!("{DID}#keys-2")
+        );
+
+        // Tenant rules prevail
+        assert_eq!(
+            signing_key(STATUS_3_0, Some("acme")),
+            format!("{DID}#keys-2")
+        );
+
+        // Without rules for a purpose, the first key of its domain is used
+        let (vm_id, _) = policy
+            .select(&diddoc, KeyPurpose::Encryption, STATUS_3_0, None)
+            .unwrap();
+        assert_eq!(vm_id, format!("{DID}#keys-3"));
+    }
+
+    #[test]
+    fn should_keep_signing_and_encryption_apart() {
+        let diddoc = util::read_diddoc(&mock_fs(), "").unwrap();
+
+        // The authentication key is not an assertion method
+        assert!(matches!(
+            policy().validate(&diddoc),
+            Err(KeySelectionError::InvalidMethod(_, KeyPurpose::Signing))
+        ));
+
+        let policy = KeySelectionPolicy {
+            rules: vec![KeyRule {
+                encryption: Some(format!("{DID}#keys-2")),
+                ..Default::default()
+            }],
+        };
+        assert!(matches!(
+            policy.validate(&diddoc),
+            Err(KeySelectionError::InvalidMethod(_, KeyPurpose::Encryption))
+        ));
+        assert!(policy
+            .select(&diddoc, KeyPurpose::Encryption, STATUS_3_0, None)
+            .is_none());
+    }
+
+    #[test]
+    fn can_resolve_secrets_by_kid_or_thumbprint() {
+        let mut mock_fs = mock_fs();
+        let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+        let resolver = SecretsResolver::new(&diddoc, &keystore);
+
+        let (vm_id, pubkey) = util::extract_agreement_key(&diddoc).unwrap();
+        let secret = resolver.find_secret(&vm_id).unwrap();
+        assert!(secret.key != pubkey.key);
+        assert_eq!(resolver.find_secret("#keys-3"), Some(secret.clone()));
+
+        let thumbprint = pubkey.thumbprint().unwrap();
+        assert_eq!(resolver.find_secret(&thumbprint), Some(secret));
+
+        assert_eq!(resolver.find_secret(&format!("{DID}#keys-9")), None);
+    }
+
+    #[test]
+    fn can_resolve_secrets_of_several_key_agreements() {
+        let mut mock_fs = mock_fs();
+        let mut diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+        let mut resolver = SecretsResolver::new(&diddoc, &keystore);
+        let indexed = resolver.len();
+
+        // A second key agreement, embedded with a relative ID
+        let (_, pubkey) = util::extract_agreement_key(&diddoc).unwrap();
+        let mut vm = diddoc.verification_method.as_ref().unwrap()[0].clone();
+        vm.id = "#keys-4".to_owned();
+        vm.public_key = Some(KeyFormat::Jwk(pubkey.clone()));
+        diddoc
+            .key_agreement
+            .as_mut()
+            .unwrap()
+            .push(KeyAgreement::Embedded(Box::new(vm)));
+
+        assert_eq!(resolver.find_secret("#keys-4"), None);
+        resolver.reload(&diddoc, &keystore);
+        assert_eq!(resolver.len(), indexed + 1);
+
+        let secret = resolver.find_secret(&format!("{DID}#keys-4")).unwrap();
+        assert_eq!(resolver.find_secret("#keys-3"), Some(secret.clone()));
+        assert_eq!(secret.to_public().key, pubkey.key);
+    }
+}
@@ -1,7 +1,53 @@
+// Lets code generated by `didcomm_handler` refer to this crate by name,
+// within this crate as well as in others
+extern crate self as mediator_coordination;
+
+pub mod anomaly;
+pub mod archival;
+pub mod attestation;
+pub mod buffer;
+pub mod challenge;
 pub mod client;
+pub mod compression;
+pub mod constants;
+pub mod degradation;
+pub mod delivery;
+pub mod didcomm;
+pub mod ephemeral;
+pub mod failover;
+pub mod forward;
+pub mod handler;
+pub mod jose;
+pub mod keylist;
+pub mod keys;
+pub mod lists;
+#[cfg(feature = "local")]
+pub mod local;
+pub mod metering;
+pub mod metrics;
+pub mod migration;
+pub mod model;
+pub mod negotiation;
+pub mod onboarding;
+pub mod outbox;
+pub mod packing;
+pub mod pickup;
 pub mod plugin;
+pub mod policy;
+pub mod privacy;
+pub mod query;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod repository;
+pub mod retry;
+pub mod scheduler;
+pub mod storage;
+pub mod subject;
+pub mod timeseries;
+pub mod timing;
+pub mod trace;
+pub mod windows;
+pub mod workers;
 
-mod jose;
-mod model;
 mod util;
 mod web;
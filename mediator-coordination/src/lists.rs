@@ -0,0 +1,302 @@
+//! Distribution lists, fanning out forwarded messages to their members.
+//!
+//! Operators define lists under a DID of their choosing, along with the
+//! members to deliver to and the senders allowed to use them. A message
+//! forwarded by an allowed sender to the DID of a list is queued once per
+//! member, each copy under an identifier of its own, so that its delivery
+//! is tracked per member through the delivery status protocol.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::{
+    delivery::DeliveryTracker,
+    model::delivery::DeliveryReport,
+    pickup::{PickupQueue, QueuedMessage},
+};
+
+/// Maximum number of members per list
+pub const MAX_MEMBERS: usize = 1000;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ListError {
+    #[error("no distribution list {0}")]
+    UnknownList(String),
+    #[error("{0} may not send to the list")]
+    Unauthorized(String),
+    #[error("invalid DID {0}")]
+    InvalidDid(String),
+    #[error("lists may have at most {0} members")]
+    TooManyMembers(usize),
+}
+
+/// Members of a list, and the senders allowed to use it
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Default)]
+pub struct DistributionList {
+    /// DIDs of the recipients messages are fanned out to
+    pub members: BTreeSet<String>,
+
+    /// DIDs of the senders allowed to forward to the list
+    pub senders: BTreeSet<String>,
+}
+
+impl DistributionList {
+    fn validate(&self) -> Result<(), ListError> {
+        if self.members.len() > MAX_MEMBERS {
+            return Err(ListError::TooManyMembers(MAX_MEMBERS));
+        }
+
+        match self
+            .members
+            .iter()
+            .chain(&self.senders)
+            .find(|did| !did.starts_with("did:"))
+        {
+            Some(did) => Err(ListError::InvalidDid(did.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Identifier of the copy of a message queued for a member
+pub fn member_message_id(message_id: &str, member: &str) -> String {
+    format!("{message_id}/{member}")
+}
+
+/// Distribution lists, by DID. Clones share the same lists.
+#[derive(Debug, Clone, Default)]
+pub struct DistributionLists {
+    lists: Arc<Mutex<BTreeMap<String, DistributionList>>>,
+}
+
+impl DistributionLists {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Defines a list, replacing any list under the same DID
+    pub fn set(&self, did: &str, list: DistributionList) -> Result<(), ListError> {
+        if !did.starts_with("did:") {
+            return Err(ListError::InvalidDid(did.to_owned()));
+        }
+        list.validate()?;
+
+        self.lists.lock().unwrap().insert(did.to_owned(), list);
+        Ok(())
+    }
+
+    pub fn get(&self, did: &str) -> Option<DistributionList> {
+        self.lists.lock().unwrap().get(did).cloned()
+    }
+
+    pub fn remove(&self, did: &str) -> Option<DistributionList> {
+        self.lists.lock().unwrap().remove(did)
+    }
+
+    /// All lists, by DID
+    pub fn all(&self) -> BTreeMap<String, DistributionList> {
+        self.lists.lock().unwrap().clone()
+    }
+
+    /// Removes a DID from the members and senders of all lists, returning
+    /// the DIDs of the lists it was removed from
+    pub fn forget(&self, did: &str) -> Vec<String> {
+        let mut lists = self.lists.lock().unwrap();
+
+        lists
+            .iter_mut()
+            .filter_map(|(list_did, list)| {
+                let member = list.members.remove(did);
+                let sender = list.senders.remove(did);
+                (member || sender).then(|| list_did.clone())
+            })
+            .collect()
+    }
+
+    /// Whether messages forwarded to a DID are to be fanned out
+    pub fn is_list(&self, did: &str) -> bool {
+        self.lists.lock().unwrap().contains_key(did)
+    }
+
+    /// Queues a message forwarded by an authenticated sender to a list
+    /// for each of its members, tracking the delivery of each copy.
+    /// Returns the identifiers of the copies, by member.
+    pub fn fan_out(
+        &self,
+        queue: &PickupQueue,
+        tracker: &DeliveryTracker,
+        sender: &str,
+        list_did: &str,
+        message: QueuedMessage,
+        now: i64,
+    ) -> Result<BTreeMap<String, String>, ListError> {
+        let list = self
+            .get(list_did)
+            .ok_or_else(|| ListError::UnknownList(list_did.to_owned()))?;
+        if !list.senders.contains(sender) {
+            return Err(ListError::Unauthorized(sender.to_owned()));
+        }
+
+        let copies: BTreeMap<_, _> = list
+            .members
+            .into_iter()
+            .map(|member| {
+                let id = member_message_id(&message.id, &member);
+                (member, id)
+            })
+            .collect();
+
+        // Members are expected to pick up under their DID
+        for (member, id) in &copies {
+            tracker.track_id(sender, id, now);
+            queue.enqueue(
+                member,
+                QueuedMessage {
+                    id: id.clone(),
+                    recipient_did: member.clone(),
+                    ..message.clone()
+                },
+            );
+        }
+
+        tracing::debug!("fanned out {} to {} members", message.id, copies.len());
+        Ok(copies)
+    }
+
+    /// Reports the delivery of a message fanned out by a sender to the
+    /// members of a list
+    pub fn deliveries(
+        &self,
+        tracker: &DeliveryTracker,
+        sender: &str,
+        list_did: &str,
+        message_id: &str,
+        now: i64,
+    ) -> Result<Vec<DeliveryReport>, ListError> {
+        let list = self
+            .get(list_did)
+            .ok_or_else(|| ListError::UnknownList(list_did.to_owned()))?;
+
+        let ids: Vec<_> = list
+            .members
+            .iter()
+            .map(|member| member_message_id(message_id, member))
+            .collect();
+
+        Ok(tracker.report(sender, &ids, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::delivery::DeliveryState;
+
+    const LIST: &str = "did:web:mediator.example:lists:team";
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+    const BOB: &str = "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7";
+    const CAROL: &str = "did:example:carol";
+
+    fn lists() -> DistributionLists {
+        let lists = DistributionLists::new();
+        let list = DistributionList {
+            members: BTreeSet::from([ALICE.to_owned(), BOB.to_owned()]),
+            senders: BTreeSet::from([CAROL.to_owned()]),
+        };
+        lists.set(LIST, list).unwrap();
+        lists
+    }
+
+    fn message() -> QueuedMessage {
+        QueuedMessage {
+            id: "fwd-0".to_owned(),
+            recipient_did: LIST.to_owned(),
+            payload: String::from(r#"{"ciphertext":"..."}"#),
+            received_time: 1000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn can_fan_out_to_members() {
+        let lists = lists();
+        let (queue, tracker) = (PickupQueue::default(), DeliveryTracker::default());
+
+        let copies = lists
+            .fan_out(&queue, &tracker, CAROL, LIST, message(), 1000)
+            .unwrap();
+        assert_eq!(copies[ALICE], format!("fwd-0/{ALICE}"));
+        assert_eq!(copies.len(), 2);
+
+        for member in [ALICE, BOB] {
+            let queued = queue.messages(member);
+            assert_eq!(queued.len(), 1);
+            assert_eq!(queued[0].id, copies[member]);
+            assert_eq!(queued[0].recipient_did, member);
+            assert_eq!(queued[0].payload, message().payload);
+        }
+
+        // Deliveries are tracked per member
+        let queue = queue.with_tracker(tracker.clone());
+        queue.next_batch(ALICE, None, 10, 1010);
+
+        let reports = lists
+            .deliveries(&tracker, CAROL, LIST, "fwd-0", 1010)
+            .unwrap();
+        let states: Vec<_> = reports.iter().map(|report| report.state).collect();
+        assert_eq!(states, [DeliveryState::Queued, DeliveryState::Delivered]);
+        assert_eq!(reports[1].message_id, copies[ALICE]);
+    }
+
+    #[test]
+    fn should_only_fan_out_for_allowed_senders() {
+        let lists = lists();
+        let (queue, tracker) = (PickupQueue::default(), DeliveryTracker::default());
+
+        assert_eq!(
+            lists.fan_out(&queue, &tracker, ALICE, LIST, message(), 1000),
+            Err(ListError::Unauthorized(ALICE.to_owned()))
+        );
+        assert_eq!(
+            lists.fan_out(&queue, &tracker, CAROL, "did:example:none", message(), 1000),
+            Err(ListError::UnknownList("did:example:none".to_owned()))
+        );
+        assert!(queue.messages(ALICE).is_empty());
+    }
+
+    #[test]
+    fn should_reject_invalid_lists() {
+        let lists = DistributionLists::new();
+
+        assert_eq!(
+            lists.set("team", DistributionList::default()),
+            Err(ListError::InvalidDid("team".to_owned()))
+        );
+
+        let list = DistributionList {
+            members: BTreeSet::from(["alice".to_owned()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            lists.set(LIST, list),
+            Err(ListError::InvalidDid("alice".to_owned()))
+        );
+
+        let list = DistributionList {
+            members: (0..=MAX_MEMBERS)
+                .map(|i| format!("did:example:{i}"))
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            lists.set(LIST, list),
+            Err(ListError::TooManyMembers(MAX_MEMBERS))
+        );
+        assert!(!lists.is_list(LIST));
+    }
+}
@@ -0,0 +1,347 @@
+//! Mediator running in-process on in-memory state.
+//!
+//! A [`LocalMediator`] handles the plaintext messages of authenticated
+//! senders as the mediator does once they are unpacked, routing them by
+//! protocol to the same handlers, but without HTTP, persistence or keys.
+//! It serves tools and tests reproducing exchanges with the mediator, e.g.
+//! the replay of captured traffic.
+//!
+//! Messages go through [`MessageHandlers::dispatch`], as those of the
+//! mediator do, so that they are admitted at the current degradation level
+//! and panics of their handlers are contained. Mediation is granted to any
+//! sender requesting it whose DID resolves, unless new mediations are shed.
+//! Messages of senders not authenticated by authcrypt go through
+//! [`MessageHandlers::dispatch_unauthenticated`] instead, and delivery
+//! requests among them are challenged when challenges are required.
+
+use serde_json::Value;
+use std::sync::Arc;
+
+use protocols_registry::{MessageType, Protocol};
+
+use crate::{
+    challenge::PickupChallenges,
+    constants::{MEDIATE_GRANT_2_0, MEDIATE_REQUEST_2_0},
+    degradation::LoadShedder,
+    delivery::DeliveryTracker,
+    didcomm::{problem_report::ProblemReport, resolution},
+    forward::{self, ForwardConfig},
+    handler::{self, MessageHandlers},
+    keylist,
+    model::{
+        connection::Connection,
+        coord::{MediateGrantBody, MediateRequest},
+    },
+    pickup::PickupQueue,
+    repository::{MemoryRepository, Repository},
+    storage::{BlobStore, StorageQuota},
+    windows::DeliveryWindows,
+};
+
+/// Mediator handling messages in-process, on in-memory state
+pub struct LocalMediator {
+    state: LocalState,
+    shedder: LoadShedder,
+    challenges: PickupChallenges,
+    handlers: MessageHandlers,
+}
+
+/// State of a local mediator, clones sharing the same
+#[derive(Clone)]
+struct LocalState {
+    did: String,
+    connections: MemoryRepository<Connection>,
+    queue: Arc<PickupQueue>,
+    forward: ForwardConfig,
+    tracker: DeliveryTracker,
+    windows: DeliveryWindows,
+    blobs: BlobStore,
+}
+
+impl LocalMediator {
+    /// Mediator of a DID, which it grants senders as routing DID
+    pub fn new(did: &str) -> Self {
+        let tracker = DeliveryTracker::default();
+        let queue = PickupQueue::default().with_tracker(tracker.clone());
+        let connections = MemoryRepository::new();
+        let blobs = BlobStore::new(
+            StorageQuota::default(),
+            Arc::new(MemoryRepository::new()),
+            Arc::new(connections.clone()),
+        );
+
+        let state = LocalState {
+            did: did.to_owned(),
+            connections,
+            queue: Arc::new(queue),
+            forward: ForwardConfig::default(),
+            tracker,
+            windows: DeliveryWindows::new(),
+            blobs,
+        };
+        Self::assemble(state, LoadShedder::default(), PickupChallenges::default())
+    }
+
+    /// Dispatches messages as the mediator does, routing them by protocol
+    /// once admitted, with panics of handlers contained
+    #[allow(clippy::result_large_err)]
+    fn assemble(state: LocalState, shedder: LoadShedder, challenges: PickupChallenges) -> Self {
+        let routes = state.clone();
+        let handlers = MessageHandlers::new()
+            .with_shedder(shedder.clone())
+            .with_challenges(challenges.clone())
+            .register_fallback(move |sender, message| routes.route(sender, message));
+
+        Self {
+            state,
+            shedder,
+            challenges,
+            handlers,
+        }
+    }
+
+    /// Limits forwards as configured, in place of the defaults
+    pub fn with_forward_config(self, forward: ForwardConfig) -> Self {
+        let state = LocalState {
+            forward,
+            ..self.state
+        };
+        Self::assemble(state, self.shedder, self.challenges)
+    }
+
+    /// Sheds messages at the degradation level of a shared shedder
+    pub fn with_shedder(self, shedder: LoadShedder) -> Self {
+        Self::assemble(self.state, shedder, self.challenges)
+    }
+
+    /// Challenges delivery requests of unauthenticated senders with shared
+    /// challenges
+    pub fn with_challenges(self, challenges: PickupChallenges) -> Self {
+        Self::assemble(self.state, self.shedder, challenges)
+    }
+
+    pub fn did(&self) -> &str {
+        &self.state.did
+    }
+
+    pub fn connections(&self) -> &MemoryRepository<Connection> {
+        &self.state.connections
+    }
+
+    pub fn queue(&self) -> &PickupQueue {
+        &self.state.queue
+    }
+
+    /// Handles a plaintext message from an authenticated sender, returning
+    /// the response message to send back, if any. Forwards are queued for
+    /// pickup without response, at all degradation levels.
+    #[allow(clippy::result_large_err)]
+    pub fn handle(&self, sender: &str, message: &Value) -> Result<Option<Value>, ProblemReport> {
+        self.handlers.dispatch(sender, message)
+    }
+
+    /// Handles a plaintext message from a sender not authenticated by
+    /// authcrypt, challenging its delivery requests against the keylist of
+    /// its connection, if any
+    #[allow(clippy::result_large_err)]
+    pub fn handle_unauthenticated(
+        &self,
+        sender: &str,
+        message: &Value,
+    ) -> Result<Option<Value>, ProblemReport> {
+        let connection = self.state.connections.find(sender);
+        let keylist = connection.map(|c| c.keylist).unwrap_or_default();
+        self.handlers
+            .dispatch_unauthenticated(sender, &keylist, message)
+    }
+}
+
+impl LocalState {
+    /// Routes a message to the handlers of its protocol
+    #[allow(clippy::result_large_err)]
+    fn route(&self, sender: &str, message: &Value) -> Result<Option<Value>, ProblemReport> {
+        let message_type = message["type"].as_str().unwrap_or_default();
+        let protocol = message_type.parse::<MessageType>().map(|t| t.protocol());
+
+        match protocol {
+            Ok(Protocol::CoordinateMediation) if message_type == MEDIATE_REQUEST_2_0 => {
+                self.grant(sender, message).map(Some)
+            }
+            Ok(Protocol::CoordinateMediation) => {
+                keylist::handle(&self.connections, sender, message).map(Some)
+            }
+            Ok(Protocol::MessagePickup) => self.queue.handle(sender, message).map(Some),
+            Ok(Protocol::Routing) => {
+                let now = chrono::Utc::now().timestamp();
+                forward::handle(&self.connections, &self.queue, &self.forward, message, now)
+                    .map(|_| None)
+            }
+            Ok(Protocol::Storage) => self.blobs.handle(sender, message).map(Some),
+            Ok(Protocol::DeliveryStatus) => self.tracker.handle(sender, message).map(Some),
+            Ok(Protocol::DeliveryWindows) => self.windows.handle(sender, message).map(Some),
+            _ => Err(handler::unsupported(message_type, message)),
+        }
+    }
+
+    /// Grants mediation to the sender, keeping its connection if granted
+    /// before
+    #[allow(clippy::result_large_err)]
+    fn grant(&self, sender: &str, message: &Value) -> Result<Value, ProblemReport> {
+        let request: MediateRequest = handler::request(MEDIATE_REQUEST_2_0, message)?;
+        resolution::check_all([sender], Some(&request.id))?;
+
+        if self.connections.find(sender).is_none() {
+            let connection = Connection {
+                client_did: sender.to_owned(),
+                ..Default::default()
+            };
+            self.connections
+                .insert(connection)
+                .map_err(|err| err.to_problem_report().with_pthid(Some(&request.id)))?;
+        }
+
+        let body = MediateGrantBody {
+            routing_did: vec![self.did.clone()],
+        };
+        Ok(handler::reply(&request, Some(MEDIATE_GRANT_2_0), body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        challenge::ChallengeConfig,
+        constants::*,
+        degradation::{HealthSignals, DEGRADED_SERVICE_CODE},
+        didcomm::validation::UNSUPPORTED_MESSAGE_CODE,
+    };
+    use serde_json::json;
+
+    const MEDIATOR: &str = "did:web:mediators-r-us.com";
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+
+    #[test]
+    fn can_mediate_in_process() {
+        let mediator = LocalMediator::new(MEDIATOR);
+
+        let request = json!({"id": "1", "type": MEDIATE_REQUEST_2_0, "body": {}});
+        let grant = mediator.handle(ALICE, &request).unwrap().unwrap();
+        assert_eq!(grant["type"], MEDIATE_GRANT_2_0);
+        assert_eq!(grant["thid"], "1");
+        assert_eq!(grant["body"]["routing_did"], json!([MEDIATOR]));
+
+        let update = json!({
+            "id": "2",
+            "type": KEYLIST_UPDATE_2_0,
+            "body": {"updates": [{"recipient_did": format!("{ALICE}#key-1"), "action": "add"}]}
+        });
+        let response = mediator.handle(ALICE, &update).unwrap().unwrap();
+        assert_eq!(response["body"]["updated"][0]["result"], "success");
+
+        let forward = json!({
+            "id": "3",
+            "type": FORWARD_2_0,
+            "body": {"next": format!("{ALICE}#key-1")},
+            "attachments": [{"data": {"json": {"ciphertext": "..."}}}]
+        });
+        assert_eq!(mediator.handle("did:example:bob", &forward), Ok(None));
+        assert_eq!(mediator.queue().messages(ALICE).len(), 1);
+
+        let status_request = json!({"id": "4", "type": STATUS_REQUEST_3_0, "body": {}});
+        let status = mediator.handle(ALICE, &status_request).unwrap().unwrap();
+        assert_eq!(status["body"]["message_count"], 1);
+
+        // Mediation is granted once
+        mediator.handle(ALICE, &request).unwrap();
+        let connection = mediator.connections().find(ALICE).unwrap();
+        assert_eq!(connection.keylist, [format!("{ALICE}#key-1")]);
+
+        let unknown = json!({"id": "5", "type": "https://example.com/ping/1.0/ping"});
+        let report = mediator.handle(ALICE, &unknown).unwrap_err();
+        assert_eq!(report.body.code, UNSUPPORTED_MESSAGE_CODE);
+        assert_eq!(report.pthid.as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn should_challenge_unauthenticated_pickup() {
+        let challenges = PickupChallenges::new(ChallengeConfig {
+            required: true,
+            ..Default::default()
+        });
+        let mediator = LocalMediator::new(MEDIATOR).with_challenges(challenges);
+
+        let request = json!({"id": "1", "type": DELIVERY_REQUEST_3_0, "body": {"limit": 10}});
+        let reply = mediator.handle_unauthenticated(ALICE, &request).unwrap();
+        assert_eq!(reply.unwrap()["type"], PICKUP_CHALLENGE_1_0);
+
+        let reply = mediator.handle(ALICE, &request).unwrap();
+        assert_eq!(reply.unwrap()["type"], STATUS_3_0);
+    }
+
+    #[test]
+    fn should_report_unresolvable_dids() {
+        let mediator = LocalMediator::new(MEDIATOR);
+        let unresolvable = "did:key:z6MkInvalid";
+
+        let request = json!({"id": "1", "type": MEDIATE_REQUEST_2_0, "body": {}});
+        let report = mediator.handle(unresolvable, &request).unwrap_err();
+        assert_eq!(report.body.code, "e.p.did.invalid-did");
+        assert_eq!(report.body.args.unwrap()[0], unresolvable);
+        assert_eq!(report.pthid.as_deref(), Some("1"));
+        assert!(mediator.connections().find(unresolvable).is_none());
+
+        mediator.handle(ALICE, &request).unwrap();
+        let update = json!({
+            "id": "2",
+            "type": KEYLIST_UPDATE_2_0,
+            "body": {"updates": [{"recipient_did": format!("{unresolvable}#key-1"), "action": "add"}]}
+        });
+        let report = mediator.handle(ALICE, &update).unwrap_err();
+        assert_eq!(report.body.code, "e.p.did.invalid-did");
+        assert_eq!(report.pthid.as_deref(), Some("2"));
+        assert!(mediator
+            .connections()
+            .find(ALICE)
+            .unwrap()
+            .keylist
+            .is_empty());
+    }
+
+    #[test]
+    fn can_shed_requests_but_forwards() {
+        let shedder = LoadShedder::default();
+        let mediator = LocalMediator::new(MEDIATOR).with_shedder(shedder.clone());
+        let request = json!({"id": "1", "type": MEDIATE_REQUEST_2_0, "body": {}});
+        mediator.handle(ALICE, &request).unwrap();
+        let update = json!({
+            "id": "2",
+            "type": KEYLIST_UPDATE_2_0,
+            "body": {"updates": [{"recipient_did": format!("{ALICE}#key-1"), "action": "add"}]}
+        });
+        mediator.handle(ALICE, &update).unwrap();
+
+        shedder.observe(
+            HealthSignals {
+                queue_depth: Some(60_000),
+                ..Default::default()
+            },
+            0,
+        );
+
+        let forward = json!({
+            "id": "3",
+            "type": FORWARD_2_0,
+            "body": {"next": format!("{ALICE}#key-1")},
+            "attachments": [{"data": {"json": {"ciphertext": "..."}}}]
+        });
+        assert_eq!(mediator.handle("did:example:bob", &forward), Ok(None));
+        assert_eq!(mediator.queue().messages(ALICE).len(), 1);
+
+        let delivery_request =
+            json!({"id": "4", "type": DELIVERY_REQUEST_3_0, "body": {"limit": 10}});
+        let report = mediator.handle(ALICE, &delivery_request).unwrap_err();
+        assert_eq!(report.body.code, DEGRADED_SERVICE_CODE);
+        assert_eq!(report.pthid.as_deref(), Some("4"));
+    }
+}
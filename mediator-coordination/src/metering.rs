@@ -0,0 +1,457 @@
+//! Metering of usage per connection, for commercial operators.
+//!
+//! The [`Meter`] accumulates the messages, bytes and storage each connection
+//! consumes, and closes them into [`UsageRecord`]s at the end of each billing
+//! period. Records are appended to their own ledger, from which they can be
+//! exported as JSON or CSV, and handed to [`BillingHook`]s so that external
+//! billing systems can be fed as periods close.
+
+use did_endpoint::util::filesystem::FileSystem;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, sync::Mutex};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::{query::Filter, storage::BlobStore};
+
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+#[derive(Debug, Error)]
+pub enum MeteringError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("corrupted usage ledger: {0}")]
+    ParseError(serde_json::Error),
+}
+
+/// Usage of a connection over a billing period
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct UsageRecord {
+    /// DID of the connection
+    pub connection: String,
+
+    /// Start of the period, as a UNIX timestamp
+    pub period_start: i64,
+
+    /// End of the period, as a UNIX timestamp
+    pub period_end: i64,
+
+    /// Number of messages processed
+    pub messages: u64,
+
+    /// Size of the messages processed, in bytes
+    pub bytes: u64,
+
+    /// Storage held over the period, in byte-days
+    pub storage_byte_days: f64,
+}
+
+/// Receives usage records as billing periods close,
+/// e.g. to forward them to an external billing system.
+pub trait BillingHook: Send + Sync {
+    fn on_period_closed(&self, records: &[UsageRecord]);
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    messages: u64,
+    bytes: u64,
+    storage_byte_seconds: u128,
+    stored_bytes: u64,
+    sampled_time: i64,
+}
+
+impl Usage {
+    fn accrue_storage(&mut self, now: i64) {
+        let elapsed = now.saturating_sub(self.sampled_time).max(0) as u128;
+        self.storage_byte_seconds += self.stored_bytes as u128 * elapsed;
+        self.sampled_time = now;
+    }
+
+    fn is_idle(&self) -> bool {
+        self.messages == 0 && self.bytes == 0 && self.storage_byte_seconds == 0
+    }
+}
+
+#[derive(Debug)]
+struct Period {
+    start: i64,
+    usage: HashMap<String, Usage>,
+}
+
+/// Accumulator of usage over the current billing period
+pub struct Meter {
+    period: Mutex<Period>,
+    hooks: Vec<Box<dyn BillingHook>>,
+}
+
+impl Meter {
+    /// Creates a meter whose first period starts now
+    pub fn new(now: i64) -> Self {
+        Self {
+            period: Mutex::new(Period {
+                start: now,
+                usage: HashMap::new(),
+            }),
+            hooks: vec![],
+        }
+    }
+
+    /// Registers a hook notified as periods close
+    pub fn with_hook(mut self, hook: impl BillingHook + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Records a message processed on behalf of a connection
+    pub fn record_message(&self, connection: &str, bytes: u64, now: i64) {
+        let mut period = self.period.lock().unwrap();
+        let start = period.start;
+        let usage = period
+            .usage
+            .entry(connection.to_owned())
+            .or_insert_with(|| Usage {
+                sampled_time: start.max(now),
+                ..Default::default()
+            });
+
+        usage.messages += 1;
+        usage.bytes += bytes;
+    }
+
+    /// Records the size of the storage held by a connection from now on
+    pub fn record_storage(&self, connection: &str, stored_bytes: u64, now: i64) {
+        let mut period = self.period.lock().unwrap();
+        let start = period.start;
+        let usage = period
+            .usage
+            .entry(connection.to_owned())
+            .or_insert_with(|| Usage {
+                sampled_time: start.max(now),
+                ..Default::default()
+            });
+
+        usage.accrue_storage(now);
+        usage.stored_bytes = stored_bytes;
+    }
+
+    /// Samples the storage held by all connections of a blob store
+    pub fn sample_storage(&self, store: &BlobStore, now: i64) {
+        let footprint = store.footprint(now);
+
+        let connections: Vec<_> = {
+            let period = self.period.lock().unwrap();
+            period.usage.keys().cloned().collect()
+        };
+
+        // Connections no longer holding entries stop accruing storage
+        for connection in connections {
+            if !footprint.contains_key(&connection) {
+                self.record_storage(&connection, 0, now);
+            }
+        }
+
+        for (connection, stored_bytes) in footprint {
+            self.record_storage(&connection, stored_bytes, now);
+        }
+    }
+
+    /// Closes the current period, returning the usage records of connections
+    /// active over it. Storage held carries over to the next period.
+    pub fn close_period(&self, now: i64) -> Vec<UsageRecord> {
+        let mut period = self.period.lock().unwrap();
+        let start = period.start;
+
+        let mut records: Vec<_> = period
+            .usage
+            .iter_mut()
+            .filter_map(|(connection, usage)| {
+                usage.accrue_storage(now);
+                (!usage.is_idle()).then(|| UsageRecord {
+                    connection: connection.clone(),
+                    period_start: start,
+                    period_end: now,
+                    messages: usage.messages,
+                    bytes: usage.bytes,
+                    storage_byte_days: usage.storage_byte_seconds as f64 / SECONDS_PER_DAY,
+                })
+            })
+            .collect();
+        records.sort_by(|a, b| a.connection.cmp(&b.connection));
+
+        period.start = now;
+        period.usage.retain(|_, usage| usage.stored_bytes > 0);
+        for usage in period.usage.values_mut() {
+            *usage = Usage {
+                stored_bytes: usage.stored_bytes,
+                sampled_time: now,
+                ..Default::default()
+            };
+        }
+        drop(period);
+
+        for hook in &self.hooks {
+            hook.on_period_closed(&records);
+        }
+
+        records
+    }
+}
+
+/// File-based ledger of usage records, one JSON record per line
+pub struct UsageLedger<'a> {
+    fs: &'a mut dyn FileSystem,
+    dirpath: String,
+}
+
+impl<'a> UsageLedger<'a> {
+    pub fn new(fs: &'a mut dyn FileSystem, storage_dirpath: &str) -> Self {
+        Self {
+            fs,
+            dirpath: format!("{storage_dirpath}/usage"),
+        }
+    }
+
+    /// Persists the records of a closed period
+    pub fn append(&mut self, records: &[UsageRecord]) -> Result<(), MeteringError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let mut lines = String::new();
+        for record in records {
+            lines += &serde_json::to_string(record).map_err(MeteringError::ParseError)?;
+            lines.push('\n');
+        }
+
+        self.fs
+            .create_dir_all(&self.dirpath)
+            .map_err(MeteringError::IoError)?;
+        self.fs
+            .append(&self.path(), &lines)
+            .map_err(MeteringError::IoError)
+    }
+
+    /// Replaces all records, e.g. to anonymize them
+    pub fn rewrite(&mut self, records: &[UsageRecord]) -> Result<(), MeteringError> {
+        let mut lines = String::new();
+        for record in records {
+            lines += &serde_json::to_string(record).map_err(MeteringError::ParseError)?;
+            lines.push('\n');
+        }
+
+        self.fs
+            .create_dir_all(&self.dirpath)
+            .map_err(MeteringError::IoError)?;
+        self.fs
+            .write_atomic(&self.path(), &lines)
+            .map_err(MeteringError::IoError)
+    }
+
+    /// Reads the records of periods ending within a time range
+    pub fn records(
+        &self,
+        from: Option<i64>,
+        to: Option<i64>,
+    ) -> Result<Vec<UsageRecord>, MeteringError> {
+        let content = match self.fs.read_to_string(&self.path()) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(MeteringError::IoError(err)),
+        };
+
+        let mut filter = Filter::all();
+        if let Some(from) = from {
+            filter = filter.and(Filter::gt("period_end", from));
+        }
+        if let Some(to) = to {
+            filter = filter.and(Filter::lte("period_end", to));
+        }
+
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(MeteringError::ParseError))
+            .filter(|record: &Result<Value, _>| match record {
+                Ok(record) => filter.matches(record),
+                Err(_) => true,
+            })
+            .map(|record| serde_json::from_value(record?).map_err(MeteringError::ParseError))
+            .collect()
+    }
+
+    fn path(&self) -> String {
+        format!("{}/records.jsonl", self.dirpath)
+    }
+}
+
+/// Hook persisting records to the usage ledger
+pub struct LedgerHook {
+    storage_dirpath: String,
+    fs: Mutex<Box<dyn FileSystem>>,
+}
+
+impl LedgerHook {
+    pub fn new(fs: impl FileSystem, storage_dirpath: &str) -> Self {
+        Self {
+            storage_dirpath: storage_dirpath.to_owned(),
+            fs: Mutex::new(Box::new(fs)),
+        }
+    }
+}
+
+impl BillingHook for LedgerHook {
+    fn on_period_closed(&self, records: &[UsageRecord]) {
+        let mut fs = self.fs.lock().unwrap();
+        if let Err(err) = UsageLedger::new(fs.as_mut(), &self.storage_dirpath).append(records) {
+            tracing::error!("failed to persist usage records: {err}");
+        }
+    }
+}
+
+/// Exports usage records as CSV, with a header row
+pub fn to_csv(records: &[UsageRecord]) -> String {
+    let mut csv =
+        String::from("connection,period_start,period_end,messages,bytes,storage_byte_days\n");
+
+    for record in records {
+        // DIDs may contain commas in their path
+        let connection = if record.connection.contains([',', '"']) {
+            format!("\"{}\"", record.connection.replace('"', "\"\""))
+        } else {
+            record.connection.clone()
+        };
+
+        csv += &format!(
+            "{},{},{},{},{},{}\n",
+            connection,
+            record.period_start,
+            record.period_end,
+            record.messages,
+            record.bytes,
+            record.storage_byte_days
+        );
+    }
+
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::connection::Connection,
+        repository::{MemoryRepository, Repository},
+        storage::StorageQuota,
+    };
+    use did_endpoint::util::test_utils::MemoryFileSystem;
+    use std::sync::Arc;
+
+    struct CollectingHook(Arc<Mutex<Vec<UsageRecord>>>);
+
+    impl BillingHook for CollectingHook {
+        fn on_period_closed(&self, records: &[UsageRecord]) {
+            self.0.lock().unwrap().extend_from_slice(records);
+        }
+    }
+
+    #[test]
+    fn can_meter_usage_per_connection() {
+        let collected = Arc::new(Mutex::new(vec![]));
+        let meter = Meter::new(0).with_hook(CollectingHook(collected.clone()));
+
+        meter.record_message("did:example:alice", 100, 10);
+        meter.record_message("did:example:alice", 50, 20);
+        meter.record_message("did:example:bob", 10, 30);
+
+        let connections = MemoryRepository::new();
+        let alice = Connection {
+            client_did: String::from("did:example:alice"),
+            ..Default::default()
+        };
+        connections.insert(alice).unwrap();
+        let store = BlobStore::new(
+            StorageQuota::default(),
+            Arc::new(MemoryRepository::new()),
+            Arc::new(connections),
+        );
+        store
+            .put("did:example:alice", "backup", vec![0; 1000], None, 0)
+            .unwrap();
+        meter.sample_storage(&store, 0);
+
+        let records = meter.close_period(43200);
+        assert_eq!(
+            records,
+            vec![
+                UsageRecord {
+                    connection: String::from("did:example:alice"),
+                    period_start: 0,
+                    period_end: 43200,
+                    messages: 2,
+                    bytes: 150,
+                    storage_byte_days: 500.0,
+                },
+                UsageRecord {
+                    connection: String::from("did:example:bob"),
+                    period_start: 0,
+                    period_end: 43200,
+                    messages: 1,
+                    bytes: 10,
+                    storage_byte_days: 0.0,
+                },
+            ]
+        );
+        assert_eq!(*collected.lock().unwrap(), records);
+
+        // Storage carries over to the next period until released
+        store.delete("did:example:alice", "backup", 86400).unwrap();
+        meter.sample_storage(&store, 86400);
+        meter.sample_storage(&store, 172800);
+
+        let records = meter.close_period(172800);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].period_start, 43200);
+        assert_eq!(records[0].messages, 0);
+        assert_eq!(records[0].storage_byte_days, 500.0);
+
+        assert!(meter.close_period(259200).is_empty());
+    }
+
+    #[test]
+    fn can_persist_and_export_usage_records() {
+        let record = |connection: &str, period_end| UsageRecord {
+            connection: connection.to_owned(),
+            period_start: period_end - 100,
+            period_end,
+            messages: 3,
+            bytes: 300,
+            storage_byte_days: 1.5,
+        };
+
+        let mut fs = MemoryFileSystem::default();
+        let mut ledger = UsageLedger::new(&mut fs, "storage");
+        assert!(ledger.records(None, None).unwrap().is_empty());
+
+        ledger.append(&[record("did:example:alice", 100)]).unwrap();
+        ledger
+            .append(&[
+                record("did:example:alice", 200),
+                record("did:web:example.com:a,b", 200),
+            ])
+            .unwrap();
+
+        assert_eq!(ledger.records(None, None).unwrap().len(), 3);
+        assert_eq!(ledger.records(Some(100), None).unwrap().len(), 2);
+        assert_eq!(ledger.records(None, Some(100)).unwrap().len(), 1);
+
+        let csv = to_csv(&ledger.records(Some(100), None).unwrap());
+        assert_eq!(
+            csv,
+            "connection,period_start,period_end,messages,bytes,storage_byte_days\n\
+             did:example:alice,100,200,3,300,1.5\n\
+             \"did:web:example.com:a,b\",100,200,3,300,1.5\n"
+        );
+    }
+}
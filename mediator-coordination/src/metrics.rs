@@ -0,0 +1,219 @@
+//! Protocol counters persisted across restarts.
+//!
+//! In-process counters reset whenever the mediator restarts, losing the
+//! long-horizon totals operators rely on. [`PersistentCounters`] are
+//! periodically snapshotted to `metrics_snapshot.json` under the storage
+//! directory, and restored from it at startup. Their totals are published
+//! as separate `_total_persistent` metrics, in the Prometheus text format.
+//!
+//! Increments made after the last snapshot are lost on crashes, so the
+//! snapshot interval bounds how far totals may lag behind.
+
+use did_endpoint::util::filesystem::{FileSystem, StdFileSystem};
+use serde::{Deserialize, Serialize};
+use server_plugin::tasks::BackgroundTasks;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+
+/// Mediations granted to recipients
+pub const MEDIATIONS_GRANTED: &str = "mediations_granted";
+
+/// Messages forwarded to recipients
+pub const MESSAGES_ROUTED: &str = "messages_routed";
+
+/// Messages of types no handler is registered for
+pub const UNKNOWN_MESSAGE_TYPES: &str = "unknown_message_types";
+
+/// Counters persisted across restarts, with their descriptions
+const PERSISTENT_COUNTERS: [(&str, &str); 3] = [
+    (MEDIATIONS_GRANTED, "Mediations granted, across restarts"),
+    (MESSAGES_ROUTED, "Messages routed, across restarts"),
+    (
+        UNKNOWN_MESSAGE_TYPES,
+        "Messages of unknown types received, across restarts",
+    ),
+];
+
+/// Prefix of the names of published metrics
+const METRIC_PREFIX: &str = "mediator_";
+
+#[derive(Debug, Error)]
+pub enum MetricsError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("corrupted metrics snapshot: {0}")]
+    ParseError(serde_json::Error),
+}
+
+/// Totals of counters as of some time
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct MetricsSnapshot {
+    /// Time the snapshot was taken, as a UNIX timestamp
+    pub taken_time: i64,
+
+    pub counters: BTreeMap<String, u64>,
+}
+
+/// Counters whose totals survive restarts. Clones share the same counts.
+#[derive(Debug, Clone, Default)]
+pub struct PersistentCounters {
+    totals: Arc<Mutex<BTreeMap<String, u64>>>,
+}
+
+impl PersistentCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Increments a counter, e.g. [`MESSAGES_ROUTED`]
+    pub fn increment(&self, name: &str, by: u64) {
+        let mut totals = self.totals.lock().unwrap();
+        let total = totals.entry(name.to_owned()).or_default();
+        *total = total.saturating_add(by);
+    }
+
+    pub fn total(&self, name: &str) -> u64 {
+        let totals = self.totals.lock().unwrap();
+        totals.get(name).copied().unwrap_or_default()
+    }
+
+    /// Adds the totals of the last snapshot, if any, returning its time.
+    /// Counts made since startup are kept.
+    pub fn restore(
+        &self,
+        fs: &dyn FileSystem,
+        storage_dirpath: &str,
+    ) -> Result<Option<i64>, MetricsError> {
+        let content = match fs.read_to_string(&snapshot_path(storage_dirpath)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(MetricsError::IoError(err)),
+        };
+        let snapshot: MetricsSnapshot =
+            serde_json::from_str(&content).map_err(MetricsError::ParseError)?;
+
+        for (name, total) in snapshot.counters {
+            self.increment(&name, total);
+        }
+
+        Ok(Some(snapshot.taken_time))
+    }
+
+    /// Persists the current totals, replacing the previous snapshot
+    pub fn snapshot(
+        &self,
+        fs: &mut dyn FileSystem,
+        storage_dirpath: &str,
+        now: i64,
+    ) -> Result<(), MetricsError> {
+        let snapshot = MetricsSnapshot {
+            taken_time: now,
+            counters: self.totals.lock().unwrap().clone(),
+        };
+        let content = serde_json::to_string_pretty(&snapshot).map_err(MetricsError::ParseError)?;
+
+        fs.write_atomic(&snapshot_path(storage_dirpath), &content)
+            .map_err(MetricsError::IoError)
+    }
+
+    /// Snapshots the totals every `interval` on a background task
+    pub fn snapshot_periodically(
+        &self,
+        tasks: &BackgroundTasks,
+        storage_dirpath: &str,
+        interval: Duration,
+    ) {
+        let counters = self.clone();
+        let storage_dirpath = storage_dirpath.to_owned();
+
+        tasks.every("metrics-snapshot", interval, move || {
+            let now = chrono::Utc::now().timestamp();
+            if let Err(err) = counters.snapshot(&mut StdFileSystem, &storage_dirpath, now) {
+                tracing::error!("failed to snapshot metrics: {err}");
+            }
+        });
+    }
+
+    /// Renders the totals in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        for (name, help) in PERSISTENT_COUNTERS {
+            let metric = format!("{METRIC_PREFIX}{name}_total_persistent");
+            let _ = writeln!(output, "# HELP {metric} {help}");
+            let _ = writeln!(output, "# TYPE {metric} counter");
+            let _ = writeln!(output, "{metric} {}", self.total(name));
+        }
+
+        output
+    }
+}
+
+/// Reads `METRICS_SNAPSHOT_INTERVAL`, in seconds, defaulting to a minute
+pub fn snapshot_interval_from_env() -> Duration {
+    let seconds = server_plugin::reload::var("METRICS_SNAPSHOT_INTERVAL")
+        .and_then(|v| v.parse().ok())
+        .filter(|&seconds| seconds > 0)
+        .unwrap_or(60);
+
+    Duration::from_secs(seconds)
+}
+
+fn snapshot_path(storage_dirpath: &str) -> String {
+    format!("{storage_dirpath}/metrics_snapshot.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use did_endpoint::util::test_utils::MemoryFileSystem;
+
+    #[test]
+    fn can_restore_totals_after_restart() {
+        let mut fs = MemoryFileSystem::default();
+
+        let counters = PersistentCounters::new();
+        assert_eq!(counters.restore(&fs, "").unwrap(), None);
+
+        counters.increment(MEDIATIONS_GRANTED, 1);
+        counters.increment(MESSAGES_ROUTED, 40);
+        counters.snapshot(&mut fs, "", 1000).unwrap();
+
+        // Counts after the last snapshot are lost
+        counters.increment(MESSAGES_ROUTED, 5);
+
+        let restarted = PersistentCounters::new();
+        restarted.increment(MESSAGES_ROUTED, 2);
+        assert_eq!(restarted.restore(&fs, "").unwrap(), Some(1000));
+
+        assert_eq!(restarted.total(MEDIATIONS_GRANTED), 1);
+        assert_eq!(restarted.total(MESSAGES_ROUTED), 42);
+    }
+
+    #[test]
+    fn can_render_persistent_metrics() {
+        let counters = PersistentCounters::new();
+        counters.increment(MESSAGES_ROUTED, 42);
+
+        let output = counters.render();
+        assert!(output.contains("# TYPE mediator_messages_routed_total_persistent counter\n"));
+        assert!(output.contains("\nmediator_messages_routed_total_persistent 42\n"));
+        assert!(output.contains("\nmediator_mediations_granted_total_persistent 0\n"));
+    }
+
+    #[test]
+    fn should_reject_corrupted_snapshots() {
+        let mut fs = MemoryFileSystem::default();
+        fs.write("/metrics_snapshot.json", "{").unwrap();
+
+        let counters = PersistentCounters::new();
+        assert!(matches!(
+            counters.restore(&fs, ""),
+            Err(MetricsError::ParseError(_))
+        ));
+    }
+}
@@ -0,0 +1,463 @@
+//! Recipient-initiated migration to another mediator.
+//!
+//! Recipients switching mediators ask for an export of their connection:
+//! its keylist and the messages awaiting pickup, bundled and signed by the
+//! mediator so that the new mediator can check where they come from. Only
+//! the authenticated recipient of a connection may request its export,
+//! which stands for its consent.
+//!
+//! Exported messages remain queued until acknowledged through message
+//! pickup, so that a lost bundle can be requested again. Recipients may
+//! also have messages arriving after the export forwarded to their new
+//! mediator for a grace period, while senders catch up with the change.
+
+use chrono::Utc;
+use did_endpoint::util::keystore::KeyStore;
+use did_utils::{
+    crypto::ed25519::Ed25519KeyPair,
+    didcore::Document,
+    proof::{eddsa_jcs_2022::EdDsaJcs2022, traits::CryptoProof},
+};
+use multibase::Base;
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Mutex};
+use thiserror::Error;
+
+use crate::{
+    constants::*,
+    didcomm::{
+        attachment::Attachment,
+        problem_report::ProblemReport,
+        validation::{MessageValidator, INVALID_MESSAGE_CODE, UNSUPPORTED_MESSAGE_CODE},
+    },
+    model::migration::*,
+    pickup::{PickupQueue, QueuedMessage},
+    policy, util,
+};
+
+/// Problem code for exports the mediator failed to sign
+pub const MIGRATION_EXPORT_FAILED_CODE: &str = "e.p.migration.export-failed";
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("invalid bundle: {0}")]
+    ParseError(serde_json::Error),
+    #[error("no assertion key in DID document")]
+    MissingAssertionKey,
+    #[error("assertion key not found in keystore")]
+    MissingSigningKey,
+    #[error("failed to sign bundle")]
+    SigningError,
+    #[error("bundle not signed by {0}")]
+    InvalidSignature(String),
+}
+
+/// Limits applied to migrations
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationConfig {
+    /// Maximum time to forward messages to a new mediator for, in seconds
+    pub max_grace_period: u64,
+}
+
+impl Default for MigrationConfig {
+    fn default() -> Self {
+        Self {
+            max_grace_period: 30 * 24 * 3600,
+        }
+    }
+}
+
+/// State of the mediator a connection is exported from
+pub struct ExportSource<'a, 'k> {
+    /// Recipient keys registered by the connection
+    pub keylist: &'a [String],
+    pub queue: &'a PickupQueue,
+    pub diddoc: &'a Document,
+    pub keystore: &'a KeyStore<'k>,
+}
+
+#[derive(Debug, Clone)]
+struct Redirect {
+    forward_to: String,
+    forward_until: i64,
+}
+
+/// Migrations requested by recipients, with their forwarding redirects
+#[derive(Debug, Default)]
+pub struct Migrations {
+    config: MigrationConfig,
+    redirects: Mutex<HashMap<String, Redirect>>,
+}
+
+impl Migrations {
+    pub fn new(config: MigrationConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Bundles the keylist and queued messages of a connection, and
+    /// redirects its messages to the new mediator if requested, replacing
+    /// any earlier redirect. The bundle is left unsigned.
+    pub fn export(
+        &self,
+        connection: &str,
+        keylist: &[String],
+        queue: &PickupQueue,
+        request: &MigrationRequestBody,
+        now: i64,
+    ) -> MigrationBundle {
+        let redirect = request.forward_to.as_ref().map(|forward_to| {
+            let grace_period = request
+                .grace_period
+                .unwrap_or(self.config.max_grace_period)
+                .min(self.config.max_grace_period);
+
+            Redirect {
+                forward_to: forward_to.clone(),
+                forward_until: now.saturating_add(grace_period as i64),
+            }
+        });
+
+        let mut redirects = self.redirects.lock().unwrap();
+        match &redirect {
+            Some(redirect) => redirects.insert(connection.to_owned(), redirect.clone()),
+            None => redirects.remove(connection),
+        };
+
+        MigrationBundle {
+            issuer: String::new(),
+            connection: connection.to_owned(),
+            created_time: now,
+            keylist: keylist.to_vec(),
+            messages: queue
+                .messages(connection)
+                .iter()
+                .map(BundledMessage::from)
+                .collect(),
+            forward_to: redirect.as_ref().map(|r| r.forward_to.clone()),
+            forward_until: redirect.as_ref().map(|r| r.forward_until),
+            proof: None,
+        }
+    }
+
+    /// Mediator to forward the messages of a connection to instead of
+    /// queueing them, while its grace period lasts
+    pub fn forward_target(&self, connection: &str, now: i64) -> Option<String> {
+        let mut redirects = self.redirects.lock().unwrap();
+        match redirects.get(connection) {
+            Some(redirect) if redirect.forward_until > now => Some(redirect.forward_to.clone()),
+            Some(_) => {
+                redirects.remove(connection);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Handles a plaintext migration request from an authenticated
+    /// recipient, returning the response message to send back.
+    #[allow(clippy::result_large_err)]
+    pub fn handle(
+        &self,
+        sender: &str,
+        message: &Value,
+        source: &ExportSource,
+    ) -> Result<Value, ProblemReport> {
+        self.handle_at(sender, message, source, Utc::now().timestamp())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn handle_at(
+        &self,
+        sender: &str,
+        message: &Value,
+        source: &ExportSource,
+        now: i64,
+    ) -> Result<Value, ProblemReport> {
+        MessageValidator::new(message_specs()).check(message)?;
+
+        let pthid = message.get("id").and_then(Value::as_str);
+        match message["type"].as_str().unwrap_or_default() {
+            MIGRATION_REQUEST_1_0 => {
+                let request: MigrationRequest =
+                    serde_json::from_value(message.clone()).map_err(|_| {
+                        ProblemReport::new(INVALID_MESSAGE_CODE, Some("Malformed message"), None)
+                            .with_pthid(pthid)
+                    })?;
+
+                if let Some(forward_to) = &request.body.forward_to {
+                    if !forward_to.starts_with("did:") {
+                        return Err(ProblemReport::new(
+                            INVALID_MESSAGE_CODE,
+                            Some("Messages may only be forwarded to a DID, not {1}"),
+                            Some(vec![forward_to.clone()]),
+                        )
+                        .with_pthid(pthid));
+                    }
+                }
+
+                let bundle = self.export(sender, source.keylist, source.queue, &request.body, now);
+                let bundle =
+                    sign_bundle(bundle, source.diddoc, source.keystore).map_err(|err| {
+                        tracing::error!("failed to sign migration bundle: {err}");
+                        ProblemReport::new(
+                            MIGRATION_EXPORT_FAILED_CODE,
+                            Some("Failed to export connection"),
+                            None,
+                        )
+                        .with_pthid(pthid)
+                    })?;
+
+                let body = MigrationExportBody {
+                    message_count: bundle.messages.len() as u64,
+                    forward_until: bundle.forward_until,
+                };
+                let attachment = Attachment::from_json(json!(bundle))
+                    .with_id("bundle")
+                    .with_media_type("application/json");
+
+                Ok(json!(MigrationExport::reply_to(
+                    &request,
+                    MIGRATION_EXPORT_1_0,
+                    body
+                )
+                .with_attachments(vec![attachment])))
+            }
+            // Responses are not handled by the mediator
+            t => Err(ProblemReport::new(
+                UNSUPPORTED_MESSAGE_CODE,
+                Some("Unsupported message type {1}"),
+                Some(vec![t.to_owned()]),
+            )
+            .with_pthid(pthid)),
+        }
+    }
+}
+
+impl From<&QueuedMessage> for BundledMessage {
+    fn from(message: &QueuedMessage) -> Self {
+        Self {
+            id: message.id.clone(),
+            recipient_did: message.recipient_did.clone(),
+            payload: message.payload.clone(),
+            priority: message.priority,
+            received_time: message.received_time,
+            expires_time: message.expires_time,
+            deliver_after: message.deliver_after,
+            expiry_notice_to: message.expiry_notice_to.clone(),
+        }
+    }
+}
+
+impl From<BundledMessage> for QueuedMessage {
+    fn from(message: BundledMessage) -> Self {
+        Self {
+            id: message.id,
+            recipient_did: message.recipient_did,
+            payload: message.payload,
+            priority: message.priority,
+            received_time: message.received_time,
+            expires_time: message.expires_time,
+            deliver_after: message.deliver_after,
+            expiry_notice_to: message.expiry_notice_to,
+        }
+    }
+}
+
+/// Signs a bundle on behalf of the mediator, with its assertion key.
+pub fn sign_bundle(
+    bundle: MigrationBundle,
+    diddoc: &Document,
+    keystore: &KeyStore,
+) -> Result<MigrationBundle, MigrationError> {
+    let (vm_id, pubkey) =
+        util::extract_assertion_key(diddoc).ok_or(MigrationError::MissingAssertionKey)?;
+    let jwk = keystore
+        .find_keypair(&pubkey)
+        .ok_or(MigrationError::MissingSigningKey)?;
+
+    let bundle = MigrationBundle {
+        issuer: diddoc.id.clone(),
+        proof: None,
+        ..bundle
+    };
+
+    let prover = EdDsaJcs2022 {
+        proof: policy::proof_options(vm_id),
+        key_pair: jwk.try_into().map_err(|_| MigrationError::SigningError)?,
+        proof_value_codec: Some(Base::Base58Btc),
+    };
+
+    let payload = serde_json::to_value(&bundle).map_err(MigrationError::ParseError)?;
+    let proof = prover
+        .proof(payload)
+        .map_err(|_| MigrationError::SigningError)?;
+
+    Ok(MigrationBundle {
+        proof: Some(proof),
+        ..bundle
+    })
+}
+
+/// Verifies a bundle against the DID document of the mediator that
+/// exported it, e.g. when importing it into the new mediator.
+pub fn verify_bundle(bundle: &MigrationBundle, diddoc: &Document) -> Result<(), MigrationError> {
+    let invalid_signature = || MigrationError::InvalidSignature(diddoc.id.clone());
+
+    if bundle.issuer != diddoc.id {
+        return Err(invalid_signature());
+    }
+
+    let proof = bundle.proof.clone().ok_or_else(invalid_signature)?;
+    let (vm_id, pubkey) =
+        util::extract_assertion_key(diddoc).ok_or(MigrationError::MissingAssertionKey)?;
+    if proof.verification_method != vm_id {
+        return Err(invalid_signature());
+    }
+
+    let key_pair: Ed25519KeyPair = pubkey.try_into().map_err(|_| invalid_signature())?;
+    let verifier = EdDsaJcs2022 {
+        proof,
+        key_pair,
+        proof_value_codec: None,
+    };
+
+    let payload = serde_json::to_value(bundle).map_err(MigrationError::ParseError)?;
+    verifier.verify(payload).map_err(|_| invalid_signature())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::mock_fs;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+    const ALICE_KEY: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH#key-1";
+    const NEW_MEDIATOR: &str = "did:web:new-mediator.example";
+
+    fn queue() -> PickupQueue {
+        let queue = PickupQueue::default();
+        for i in 0..3 {
+            queue.enqueue(
+                ALICE,
+                QueuedMessage {
+                    id: format!("msg-{i}"),
+                    recipient_did: ALICE_KEY.to_owned(),
+                    payload: format!(r#"{{"ciphertext":"{i}"}}"#),
+                    priority: 0,
+                    received_time: 1000 + i,
+                    ..Default::default()
+                },
+            );
+        }
+        queue
+    }
+
+    #[test]
+    fn can_export_connection_with_grace_period() {
+        let migrations = Migrations::new(MigrationConfig {
+            max_grace_period: 3600,
+        });
+        let queue = queue();
+        let keylist = [ALICE_KEY.to_owned()];
+
+        let request = MigrationRequestBody {
+            forward_to: Some(NEW_MEDIATOR.to_owned()),
+            grace_period: Some(7200),
+        };
+        let bundle = migrations.export(ALICE, &keylist, &queue, &request, 2000);
+
+        assert_eq!(bundle.keylist, keylist);
+        let ids: Vec<_> = bundle.messages.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["msg-0", "msg-1", "msg-2"]);
+
+        // Exported messages remain queued
+        assert_eq!(queue.messages(ALICE).len(), 3);
+
+        // The grace period is capped
+        assert_eq!(bundle.forward_until, Some(5600));
+        assert_eq!(
+            migrations.forward_target(ALICE, 5599).as_deref(),
+            Some(NEW_MEDIATOR)
+        );
+        assert_eq!(migrations.forward_target(ALICE, 5600), None);
+        assert_eq!(migrations.forward_target("did:example:bob", 2000), None);
+
+        // Bundles restore to queued messages
+        let restored: QueuedMessage = bundle.messages[0].clone().into();
+        assert_eq!(restored, queue.messages(ALICE)[0]);
+    }
+
+    #[test]
+    fn can_sign_and_verify_bundle() {
+        let mut mock_fs = mock_fs();
+        let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+
+        let bundle = Migrations::default().export(
+            ALICE,
+            &[ALICE_KEY.to_owned()],
+            &queue(),
+            &MigrationRequestBody::default(),
+            2000,
+        );
+        let signed = sign_bundle(bundle, &diddoc, &keystore).unwrap();
+        verify_bundle(&signed, &diddoc).unwrap();
+
+        // Tampered messages do not verify
+        let mut tampered = signed.clone();
+        tampered.messages[0].payload = String::from("forged");
+        assert!(matches!(
+            verify_bundle(&tampered, &diddoc),
+            Err(MigrationError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn can_handle_migration_requests() {
+        let mut mock_fs = mock_fs();
+        let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+
+        let queue = queue();
+        let keylist = [ALICE_KEY.to_owned()];
+        let source = ExportSource {
+            keylist: &keylist,
+            queue: &queue,
+            diddoc: &diddoc,
+            keystore: &keystore,
+        };
+        let migrations = Migrations::default();
+
+        let request = json!({
+            "id": "123456780",
+            "type": MIGRATION_REQUEST_1_0,
+            "body": {"forward_to": NEW_MEDIATOR, "grace_period": 600}
+        });
+
+        let export = migrations
+            .handle_at(ALICE, &request, &source, 2000)
+            .unwrap();
+        assert_eq!(export["type"], MIGRATION_EXPORT_1_0);
+        assert_eq!(export["thid"], "123456780");
+        assert_eq!(export["body"]["message_count"], 3);
+        assert_eq!(export["body"]["forward_until"], 2600);
+
+        let bundle = &export["attachments"][0]["data"]["json"];
+        let bundle: MigrationBundle = serde_json::from_value(bundle.clone()).unwrap();
+        assert_eq!(bundle.connection, ALICE);
+        verify_bundle(&bundle, &diddoc).unwrap();
+
+        let invalid = json!({
+            "id": "123456781",
+            "type": MIGRATION_REQUEST_1_0,
+            "body": {"forward_to": "https://new-mediator.example"}
+        });
+        let report = migrations
+            .handle_at(ALICE, &invalid, &source, 2000)
+            .unwrap_err();
+        assert_eq!(report.body.code, INVALID_MESSAGE_CODE);
+    }
+}
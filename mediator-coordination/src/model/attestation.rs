@@ -0,0 +1,53 @@
+use chrono::{DateTime, Utc};
+use did_utils::proof::model::Proof;
+use serde::{Deserialize, Serialize};
+
+// region: --- Model
+
+/// Statement of the mediator on the code and policy it runs, signed with
+/// its assertion key so that wallets and auditors can verify it.
+///
+/// e.g.:
+/// ```json
+/// {
+///   "issuer": "did:web:mediator.example",
+///   "issued": "2024-01-01T00:00:00Z",
+///   "software": "didcomm-mediator-rs",
+///   "version": "0.1.0",
+///   "commit": "2a28f64c0a1e6f3d9d5b3c9f1e0b7a4c8d2e6f10",
+///   "protocols": ["https://didcomm.org/coordinate-mediation/2.0"],
+///   "policyDigest": "z4oey6rEB8y2B7UU1nQrVfgDcJqdQ4f5mFBeE1q3mLzLX"
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Attestation {
+    /// DID of the mediator
+    pub issuer: String,
+
+    /// Date the statement was signed
+    pub issued: DateTime<Utc>,
+
+    /// Name of the software run by the mediator
+    pub software: String,
+
+    /// Version of the software
+    pub version: String,
+
+    /// Git commit the software was built from, or `unknown`
+    pub commit: String,
+
+    /// Identifiers of the protocols served, e.g.
+    /// `https://didcomm.org/messagepickup/3.0`
+    pub protocols: Vec<String>,
+
+    /// Digest of the disclosed mediator policy, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_digest: Option<String>,
+
+    /// Signature of the mediator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
+}
+
+// endregion: --- Model
@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::*,
+    didcomm::validation::{FieldKind, FieldSpec, MessageSpec},
+    model::coord::CoordMessage,
+};
+
+// region: --- Model
+
+pub type Challenge = CoordMessage<ChallengeBody>;
+pub type ChallengeResponse = CoordMessage<ChallengeResponseBody>;
+pub type Authenticated = CoordMessage<AuthenticatedBody>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ChallengeBody {
+    /// Single-use nonce the recipient must sign
+    pub nonce: String,
+
+    /// Expiry of the nonce, as a UNIX timestamp
+    pub expires_time: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ChallengeResponseBody {
+    /// Compact JWS over `{"nonce", "sub"}`, `sub` being the connection,
+    /// signed by a key in the keylist of the connection
+    pub proof: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct AuthenticatedBody {
+    /// Recipient whose key signed the challenge
+    pub recipient_did: String,
+
+    /// Token later delivery requests must carry in their `pickup_session`
+    /// header to be honored without a challenge
+    pub session_token: String,
+
+    /// Expiry of the authentication, as a UNIX timestamp
+    pub expires_time: i64,
+}
+
+// endregion: --- Model
+
+// region: --- Specs
+
+/// Specs of the messages of the pickup authentication protocol
+pub fn message_specs() -> Vec<MessageSpec> {
+    vec![
+        MessageSpec {
+            message_type: PICKUP_CHALLENGE_1_0,
+            required_headers: &[],
+            body: &[
+                FieldSpec {
+                    name: "nonce",
+                    kind: FieldKind::String,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "expires_time",
+                    kind: FieldKind::Integer,
+                    required: true,
+                },
+            ],
+        },
+        MessageSpec {
+            message_type: PICKUP_CHALLENGE_RESPONSE_1_0,
+            required_headers: &["thid"],
+            body: &[FieldSpec {
+                name: "proof",
+                kind: FieldKind::String,
+                required: true,
+            }],
+        },
+        MessageSpec {
+            message_type: PICKUP_AUTHENTICATED_1_0,
+            required_headers: &["thid"],
+            body: &[
+                FieldSpec {
+                    name: "recipient_did",
+                    kind: FieldKind::String,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "session_token",
+                    kind: FieldKind::String,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "expires_time",
+                    kind: FieldKind::Integer,
+                    required: true,
+                },
+            ],
+        },
+    ]
+}
+
+// endregion: --- Specs
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Mediation granted to a client, along with the keys routed to it
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Default)]
+pub struct Connection {
+    /// DID of the client the mediation was granted to
+    pub client_did: String,
+
+    /// DIDs or key identifiers whose forwards are queued for the client
+    pub keylist: Vec<String>,
+
+    /// Version of the record, bumped on every update, against which
+    /// concurrent updates are detected
+    #[serde(default)]
+    pub version: u64,
+
+    /// When the connection was revoked, as a UNIX timestamp. Archived
+    /// connections are restorable until their retention expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_time: Option<i64>,
+
+    /// Protocols and headers the client disclosed supporting, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disclosures: Option<Disclosures>,
+}
+
+/// Features the client of a connection disclosed supporting
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq, Default)]
+pub struct Disclosures {
+    /// Protocols, at the versions supported, e.g.
+    /// `https://didcomm.org/messagepickup/3.0`
+    pub protocols: Vec<String>,
+
+    /// Headers supported, e.g. `from_prior`, if disclosed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headers: Option<Vec<String>>,
+
+    /// When the features were last disclosed, as a UNIX timestamp
+    pub disclosed_time: i64,
+}
+
+impl Connection {
+    pub fn is_archived(&self) -> bool {
+        self.archived_time.is_some()
+    }
+}
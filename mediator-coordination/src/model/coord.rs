@@ -0,0 +1,478 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::*,
+    didcomm::{
+        attachment::Attachment,
+        headers::CustomHeaders,
+        validation::{FieldKind, FieldSpec, MessageSpec},
+    },
+    model::policy::PolicyReference,
+};
+
+// region: --- Model
+
+/// Coordinate mediation message with a typed body.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CoordMessage<B> {
+    /// Message identifier
+    pub id: String,
+
+    /// Message type
+    #[serde(rename = "type")]
+    pub message_type: String,
+
+    /// Thread identifier, set on responses to the request's identifier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thid: Option<String>,
+
+    /// Parent thread identifier, e.g. of the out-of-band invitation a request responds to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pthid: Option<String>,
+
+    /// Reference to the mediator policy, set on mediation grants
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mediator_policy: Option<PolicyReference>,
+
+    /// Identifiers of the messages acknowledged by this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ack: Option<Vec<String>>,
+
+    /// Message body
+    #[serde(default)]
+    pub body: B,
+
+    /// Attachments, e.g. of messages delivered on pickup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<Vec<Attachment>>,
+
+    /// Headers unknown to the model, preserved when repacking the message
+    #[serde(flatten)]
+    pub headers: CustomHeaders,
+}
+
+impl<B> CoordMessage<B> {
+    /// Creates a message with a fresh identifier
+    pub fn new(message_type: &str, body: B) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            message_type: message_type.to_owned(),
+            thid: None,
+            pthid: None,
+            mediator_policy: None,
+            ack: None,
+            body,
+            attachments: None,
+            headers: CustomHeaders::new(),
+        }
+    }
+
+    /// Creates a message responding to another within its thread, carrying
+    /// over its persistent custom headers
+    pub fn reply_to<T>(request: &CoordMessage<T>, message_type: &str, body: B) -> Self {
+        Self {
+            thid: Some(request.thid.clone().unwrap_or(request.id.clone())),
+            headers: request.headers.persistent(),
+            ..Self::new(message_type, body)
+        }
+    }
+
+    /// Attaches content to the message
+    pub fn with_attachments(self, attachments: Vec<Attachment>) -> Self {
+        Self {
+            attachments: Some(attachments),
+            ..self
+        }
+    }
+
+    /// Acknowledges messages, as requested with `please_ack`
+    pub fn with_ack(self, message_ids: Vec<String>) -> Self {
+        Self {
+            ack: Some(message_ids),
+            ..self
+        }
+    }
+
+    /// Sets a custom header, e.g. of an extension
+    pub fn with_header(mut self, name: &str, value: serde_json::Value) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Discloses the mediator policy the message is subject to
+    pub fn with_mediator_policy(self, reference: PolicyReference) -> Self {
+        Self {
+            mediator_policy: Some(reference),
+            ..self
+        }
+    }
+}
+
+pub type MediateRequest = CoordMessage<EmptyBody>;
+pub type MediateDeny = CoordMessage<EmptyBody>;
+pub type MediateGrant = CoordMessage<MediateGrantBody>;
+pub type KeylistUpdate = CoordMessage<KeylistUpdateBody>;
+pub type KeylistUpdateResponse = CoordMessage<KeylistUpdateResponseBody>;
+pub type KeylistQuery = CoordMessage<KeylistQueryBody>;
+pub type Keylist = CoordMessage<KeylistBody>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct EmptyBody {}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct MediateGrantBody {
+    /// DIDs to use as routing keys by senders
+    pub routing_did: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct KeylistUpdateBody {
+    pub updates: Vec<KeylistUpdateItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct KeylistUpdateItem {
+    pub recipient_did: String,
+    pub action: KeylistUpdateAction,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeylistUpdateAction {
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct KeylistUpdateResponseBody {
+    pub updated: Vec<KeylistUpdateResponseItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct KeylistUpdateResponseItem {
+    pub recipient_did: String,
+    pub action: KeylistUpdateAction,
+    pub result: KeylistUpdateResult,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeylistUpdateResult {
+    ClientError,
+    ServerError,
+    NoChange,
+    Success,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct KeylistQueryBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paginate: Option<KeylistQueryPaginate>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct KeylistQueryPaginate {
+    pub limit: u64,
+    pub offset: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct KeylistBody {
+    pub keys: Vec<KeylistEntry>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagination: Option<KeylistPagination>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct KeylistEntry {
+    pub recipient_did: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct KeylistPagination {
+    pub count: u64,
+    pub offset: u64,
+    pub remaining: u64,
+}
+
+// endregion: --- Model
+
+// region: --- Specs
+
+const KEYLIST_UPDATE_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        name: "recipient_did",
+        kind: FieldKind::String,
+        required: true,
+    },
+    FieldSpec {
+        name: "action",
+        kind: FieldKind::Enum(&["add", "remove"]),
+        required: true,
+    },
+];
+
+const KEYLIST_UPDATE_RESPONSE_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        name: "recipient_did",
+        kind: FieldKind::String,
+        required: true,
+    },
+    FieldSpec {
+        name: "action",
+        kind: FieldKind::Enum(&["add", "remove"]),
+        required: true,
+    },
+    FieldSpec {
+        name: "result",
+        kind: FieldKind::Enum(&["client_error", "server_error", "no_change", "success"]),
+        required: true,
+    },
+];
+
+const KEYLIST_QUERY_PAGINATE_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        name: "limit",
+        kind: FieldKind::Integer,
+        required: true,
+    },
+    FieldSpec {
+        name: "offset",
+        kind: FieldKind::Integer,
+        required: true,
+    },
+];
+
+const KEYLIST_KEY_FIELDS: &[FieldSpec] = &[FieldSpec {
+    name: "recipient_did",
+    kind: FieldKind::String,
+    required: true,
+}];
+
+const KEYLIST_PAGINATION_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        name: "count",
+        kind: FieldKind::Integer,
+        required: true,
+    },
+    FieldSpec {
+        name: "offset",
+        kind: FieldKind::Integer,
+        required: true,
+    },
+    FieldSpec {
+        name: "remaining",
+        kind: FieldKind::Integer,
+        required: true,
+    },
+];
+
+/// Specs of the messages of the Coordinate Mediation 2.0 protocol
+pub fn message_specs() -> Vec<MessageSpec> {
+    vec![
+        MessageSpec {
+            message_type: MEDIATE_REQUEST_2_0,
+            required_headers: &[],
+            body: &[],
+        },
+        MessageSpec {
+            message_type: MEDIATE_DENY_2_0,
+            required_headers: &["thid"],
+            body: &[],
+        },
+        MessageSpec {
+            message_type: MEDIATE_GRANT_2_0,
+            required_headers: &["thid"],
+            body: &[FieldSpec {
+                name: "routing_did",
+                kind: FieldKind::Array(&FieldKind::String),
+                required: true,
+            }],
+        },
+        MessageSpec {
+            message_type: KEYLIST_UPDATE_2_0,
+            required_headers: &[],
+            body: &[FieldSpec {
+                name: "updates",
+                kind: FieldKind::Array(&FieldKind::Object(KEYLIST_UPDATE_FIELDS)),
+                required: true,
+            }],
+        },
+        MessageSpec {
+            message_type: KEYLIST_UPDATE_RESPONSE_2_0,
+            required_headers: &["thid"],
+            body: &[FieldSpec {
+                name: "updated",
+                kind: FieldKind::Array(&FieldKind::Object(KEYLIST_UPDATE_RESPONSE_FIELDS)),
+                required: true,
+            }],
+        },
+        MessageSpec {
+            message_type: KEYLIST_QUERY_2_0,
+            required_headers: &[],
+            body: &[FieldSpec {
+                name: "paginate",
+                kind: FieldKind::Object(KEYLIST_QUERY_PAGINATE_FIELDS),
+                required: false,
+            }],
+        },
+        MessageSpec {
+            message_type: KEYLIST_2_0,
+            required_headers: &["thid"],
+            body: &[
+                FieldSpec {
+                    name: "keys",
+                    kind: FieldKind::Array(&FieldKind::Object(KEYLIST_KEY_FIELDS)),
+                    required: true,
+                },
+                FieldSpec {
+                    name: "pagination",
+                    kind: FieldKind::Object(KEYLIST_PAGINATION_FIELDS),
+                    required: false,
+                },
+            ],
+        },
+    ]
+}
+
+// endregion: --- Specs
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::didcomm::validation::{MessageValidator, ValidationError};
+    use serde_json::json;
+
+    #[test]
+    fn can_serde_coordinate_mediation_messages() {
+        let request = MediateRequest::new(MEDIATE_REQUEST_2_0, EmptyBody {});
+        let grant = MediateGrant::reply_to(
+            &request,
+            MEDIATE_GRANT_2_0,
+            MediateGrantBody {
+                routing_did: vec![String::from("did:web:mediators-r-us.com")],
+            },
+        );
+
+        assert_eq!(
+            json!(grant),
+            json!({
+                "id": grant.id,
+                "type": MEDIATE_GRANT_2_0,
+                "thid": request.id,
+                "body": {
+                    "routing_did": ["did:web:mediators-r-us.com"]
+                }
+            })
+        );
+
+        let update: KeylistUpdate = serde_json::from_value(json!({
+            "id": "123456782",
+            "type": KEYLIST_UPDATE_2_0,
+            "body": {
+                "updates": [{
+                    "recipient_did": "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH",
+                    "action": "remove"
+                }]
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(update.body.updates[0].action, KeylistUpdateAction::Remove);
+    }
+
+    #[test]
+    fn can_pass_custom_headers_through() {
+        let message = json!({
+            "id": "123456780",
+            "type": MEDIATE_REQUEST_2_0,
+            "from": "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH",
+            "please_ack": [""],
+            "policy_hint": {"retention": "7d"},
+            "body": {}
+        });
+
+        let request: MediateRequest = serde_json::from_value(message.clone()).unwrap();
+        assert_eq!(
+            request.headers.get("policy_hint"),
+            Some(&message["policy_hint"])
+        );
+        assert_eq!(json!(request), message);
+
+        // Replies only carry over persistent headers
+        let grant = MediateGrant::reply_to(&request, MEDIATE_GRANT_2_0, Default::default())
+            .with_header("trace", json!([]));
+        let grant = json!(grant);
+        assert_eq!(grant["policy_hint"], message["policy_hint"]);
+        assert_eq!(grant["trace"], json!([]));
+        assert!(grant.get("from").is_none());
+        assert!(grant.get("please_ack").is_none());
+    }
+
+    #[test]
+    fn can_validate_coordinate_mediation_messages() {
+        let validator = MessageValidator::new(message_specs());
+
+        let messages = [
+            json!({
+                "id": "123456780",
+                "type": MEDIATE_REQUEST_2_0,
+                "body": {}
+            }),
+            json!({
+                "id": "123456781",
+                "thid": "123456780",
+                "type": MEDIATE_GRANT_2_0,
+                "body": {
+                    "routing_did": ["did:peer:z6Mkfriq1MqLBoPWecGoDLjguo1sB9brj6wT3qZ5BxkKpuP6"]
+                }
+            }),
+            json!({
+                "id": "123456782",
+                "type": KEYLIST_UPDATE_2_0,
+                "body": {
+                    "updates": [{
+                        "recipient_did": "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH",
+                        "action": "add"
+                    }]
+                }
+            }),
+            json!({
+                "id": "123456783",
+                "type": KEYLIST_QUERY_2_0,
+                "body": {
+                    "paginate": {"limit": 30, "offset": 0}
+                }
+            }),
+        ];
+
+        for message in messages {
+            assert_eq!(validator.validate(&message), Ok(()));
+        }
+    }
+
+    #[test]
+    fn should_err_on_keylist_update_with_unknown_action() {
+        let validator = MessageValidator::new(message_specs());
+
+        let message = json!({
+            "id": "123456782",
+            "type": KEYLIST_UPDATE_2_0,
+            "body": {
+                "updates": [{
+                    "recipient_did": "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH",
+                    "action": "update"
+                }]
+            }
+        });
+
+        assert_eq!(
+            validator.validate(&message).unwrap_err(),
+            ValidationError::InvalidField {
+                path: String::from("updates/0/action"),
+                expected: String::from("one of [add, remove]"),
+            }
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::*,
+    didcomm::validation::{FieldKind, FieldSpec, MessageSpec},
+    model::coord::CoordMessage,
+};
+
+// region: --- Model
+
+pub type DeliveryStatusQuery = CoordMessage<DeliveryStatusQueryBody>;
+pub type DeliveryStatus = CoordMessage<DeliveryStatusBody>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct DeliveryStatusQueryBody {
+    /// Identifiers of the forwarded messages to report on
+    pub message_id_list: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct DeliveryStatusBody {
+    pub deliveries: Vec<DeliveryReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeliveryReport {
+    pub message_id: String,
+    pub state: DeliveryState,
+
+    /// Time of the last transition, as a UNIX timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_time: Option<i64>,
+}
+
+/// Delivery state of a forwarded message, in order of progress
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    /// Not tracked, or no longer
+    Unknown,
+    /// Awaiting pickup by the recipient
+    Queued,
+    /// Delivered to the recipient, awaiting acknowledgement
+    Delivered,
+    /// Acknowledged by the recipient
+    Acknowledged,
+    /// Dropped unacknowledged once expired
+    Expired,
+}
+
+// endregion: --- Model
+
+// region: --- Specs
+
+const MESSAGE_ID_LIST_FIELD: FieldSpec = FieldSpec {
+    name: "message_id_list",
+    kind: FieldKind::Array(&FieldKind::String),
+    required: true,
+};
+
+/// Specs of the messages of the delivery status protocol
+pub fn message_specs() -> Vec<MessageSpec> {
+    vec![
+        MessageSpec {
+            message_type: DELIVERY_STATUS_QUERY_1_0,
+            required_headers: &[],
+            body: &[MESSAGE_ID_LIST_FIELD],
+        },
+        MessageSpec {
+            message_type: DELIVERY_STATUS_1_0,
+            required_headers: &["thid"],
+            body: &[FieldSpec {
+                name: "deliveries",
+                kind: FieldKind::Array(&FieldKind::Object(&[
+                    FieldSpec {
+                        name: "message_id",
+                        kind: FieldKind::String,
+                        required: true,
+                    },
+                    FieldSpec {
+                        name: "state",
+                        kind: FieldKind::Enum(&[
+                            "unknown",
+                            "queued",
+                            "delivered",
+                            "acknowledged",
+                            "expired",
+                        ]),
+                        required: true,
+                    },
+                    FieldSpec {
+                        name: "updated_time",
+                        kind: FieldKind::Integer,
+                        required: false,
+                    },
+                ])),
+                required: true,
+            }],
+        },
+    ]
+}
+
+// endregion: --- Specs
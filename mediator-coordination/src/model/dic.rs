@@ -103,10 +103,10 @@ mod tests {
 
     use did_endpoint::util::keystore::ToPublic;
 
-    use crate::util::{self, MockFileSystem};
+    use crate::util::{self, mock_fs};
 
     fn setup() -> Jwk {
-        let mut mock_fs = MockFileSystem;
+        let mut mock_fs = mock_fs();
 
         let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
         let (_, pubkey) = util::extract_assertion_key(&diddoc).unwrap();
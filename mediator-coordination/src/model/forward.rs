@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::*,
+    didcomm::validation::{FieldKind, FieldSpec, MessageSpec},
+    model::coord::CoordMessage,
+};
+
+// region: --- Model
+
+pub type Forward = CoordMessage<ForwardBody>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ForwardBody {
+    /// DID or key identifier of the recipient of the attached message
+    pub next: String,
+}
+
+// endregion: --- Model
+
+// region: --- Specs
+
+/// Specs of the messages of the Routing 2.0 protocol
+pub fn message_specs() -> Vec<MessageSpec> {
+    vec![MessageSpec {
+        message_type: FORWARD_2_0,
+        required_headers: &[],
+        body: &[FieldSpec {
+            name: "next",
+            kind: FieldKind::String,
+            required: true,
+        }],
+    }]
+}
+
+// endregion: --- Specs
@@ -0,0 +1,130 @@
+use did_utils::proof::model::Proof;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::*,
+    didcomm::validation::{FieldKind, FieldSpec, MessageSpec},
+    model::coord::CoordMessage,
+};
+
+// region: --- Model
+
+pub type MigrationRequest = CoordMessage<MigrationRequestBody>;
+pub type MigrationExport = CoordMessage<MigrationExportBody>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct MigrationRequestBody {
+    /// DID of the new mediator, to forward messages to after the export
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward_to: Option<String>,
+
+    /// Time to keep forwarding messages for, in seconds, capped by the
+    /// mediator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grace_period: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct MigrationExportBody {
+    /// Number of queued messages in the attached bundle
+    pub message_count: u64,
+
+    /// End of forwarding to the new mediator, as a UNIX timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward_until: Option<i64>,
+}
+
+/// Portable export of a connection, signed by the mediator so that the
+/// new mediator can check its origin.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct MigrationBundle {
+    /// DID of the exporting mediator, set when the bundle is signed
+    #[serde(default)]
+    pub issuer: String,
+
+    /// DID of the recipient whose connection is exported
+    pub connection: String,
+
+    /// Time of the export, as a UNIX timestamp
+    pub created_time: i64,
+
+    /// Recipient keys the mediator routes messages for
+    pub keylist: Vec<String>,
+
+    /// Messages awaiting pickup, in reception order
+    pub messages: Vec<BundledMessage>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward_to: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward_until: Option<i64>,
+
+    /// Signature of the mediator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct BundledMessage {
+    pub id: String,
+    pub recipient_did: String,
+
+    /// Packed message, as forwarded
+    pub payload: String,
+    pub priority: u8,
+    pub received_time: i64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_time: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deliver_after: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry_notice_to: Option<String>,
+}
+
+// endregion: --- Model
+
+// region: --- Specs
+
+/// Specs of the messages of the migration protocol
+pub fn message_specs() -> Vec<MessageSpec> {
+    vec![
+        MessageSpec {
+            message_type: MIGRATION_REQUEST_1_0,
+            required_headers: &[],
+            body: &[
+                FieldSpec {
+                    name: "forward_to",
+                    kind: FieldKind::String,
+                    required: false,
+                },
+                FieldSpec {
+                    name: "grace_period",
+                    kind: FieldKind::Integer,
+                    required: false,
+                },
+            ],
+        },
+        MessageSpec {
+            message_type: MIGRATION_EXPORT_1_0,
+            required_headers: &["thid"],
+            body: &[
+                FieldSpec {
+                    name: "message_count",
+                    kind: FieldKind::Integer,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "forward_until",
+                    kind: FieldKind::Integer,
+                    required: false,
+                },
+            ],
+        },
+    ]
+}
+
+// endregion: --- Specs
@@ -1 +1,12 @@
+pub mod attestation;
+pub mod challenge;
+pub mod connection;
+pub mod coord;
+pub mod delivery;
 pub mod dic;
+pub mod forward;
+pub mod migration;
+pub mod pickup;
+pub mod policy;
+pub mod storage;
+pub mod windows;
@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::*,
+    didcomm::validation::{FieldKind, FieldSpec, MessageSpec},
+    model::coord::CoordMessage,
+};
+
+// region: --- Model
+
+pub type StatusRequest = CoordMessage<StatusRequestBody>;
+pub type Status = CoordMessage<StatusBody>;
+pub type DeliveryRequest = CoordMessage<DeliveryRequestBody>;
+pub type Delivery = CoordMessage<DeliveryBody>;
+pub type MessagesReceived = CoordMessage<MessagesReceivedBody>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StatusRequestBody {
+    /// Restricts the status to messages for this recipient
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipient_did: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StatusBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipient_did: Option<String>,
+
+    /// Number of messages awaiting pickup
+    pub message_count: u64,
+
+    /// Age of the oldest message, in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longest_waited_seconds: Option<u64>,
+
+    /// Reception of the newest message, as a UNIX timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub newest_received_time: Option<i64>,
+
+    /// Reception of the oldest message, as a UNIX timestamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oldest_received_time: Option<i64>,
+
+    /// Size of the messages awaiting pickup, in bytes
+    pub total_bytes: u64,
+
+    /// Whether messages are delivered live over the connection
+    pub live_delivery: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct DeliveryRequestBody {
+    /// Maximum number of messages to deliver
+    pub limit: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipient_did: Option<String>,
+
+    /// Token of a previous delivery, to resume from where it ended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct DeliveryBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipient_did: Option<String>,
+
+    /// Opaque token resuming deliveries after this one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct MessagesReceivedBody {
+    /// Identifiers of the delivered attachments, i.e. messages
+    pub message_id_list: Vec<String>,
+}
+
+// endregion: --- Model
+
+// region: --- Specs
+
+const RECIPIENT_FIELD: FieldSpec = FieldSpec {
+    name: "recipient_did",
+    kind: FieldKind::String,
+    required: false,
+};
+
+const RESUME_TOKEN_FIELD: FieldSpec = FieldSpec {
+    name: "resume_token",
+    kind: FieldKind::String,
+    required: false,
+};
+
+/// Specs of the messages of the Message Pickup 3.0 protocol
+pub fn message_specs() -> Vec<MessageSpec> {
+    vec![
+        MessageSpec {
+            message_type: STATUS_REQUEST_3_0,
+            required_headers: &[],
+            body: &[RECIPIENT_FIELD],
+        },
+        MessageSpec {
+            message_type: STATUS_3_0,
+            required_headers: &["thid"],
+            body: &[
+                RECIPIENT_FIELD,
+                FieldSpec {
+                    name: "message_count",
+                    kind: FieldKind::Integer,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "longest_waited_seconds",
+                    kind: FieldKind::Integer,
+                    required: false,
+                },
+                FieldSpec {
+                    name: "newest_received_time",
+                    kind: FieldKind::Integer,
+                    required: false,
+                },
+                FieldSpec {
+                    name: "oldest_received_time",
+                    kind: FieldKind::Integer,
+                    required: false,
+                },
+                FieldSpec {
+                    name: "total_bytes",
+                    kind: FieldKind::Integer,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "live_delivery",
+                    kind: FieldKind::Boolean,
+                    required: true,
+                },
+            ],
+        },
+        MessageSpec {
+            message_type: DELIVERY_REQUEST_3_0,
+            required_headers: &[],
+            body: &[
+                FieldSpec {
+                    name: "limit",
+                    kind: FieldKind::Integer,
+                    required: true,
+                },
+                RECIPIENT_FIELD,
+                RESUME_TOKEN_FIELD,
+            ],
+        },
+        MessageSpec {
+            message_type: DELIVERY_3_0,
+            required_headers: &["thid"],
+            body: &[RECIPIENT_FIELD, RESUME_TOKEN_FIELD],
+        },
+        MessageSpec {
+            message_type: MESSAGES_RECEIVED_3_0,
+            required_headers: &[],
+            body: &[FieldSpec {
+                name: "message_id_list",
+                kind: FieldKind::Array(&FieldKind::String),
+                required: true,
+            }],
+        },
+    ]
+}
+
+// endregion: --- Specs
@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use did_utils::proof::model::Proof;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::storage::StorageQuota;
+
+// region: --- Model
+
+/// Terms under which the mediator serves its recipients, disclosed so that
+/// wallets can show users what they agree to when requesting mediation.
+///
+/// e.g.:
+/// ```json
+/// {
+///   "issuer": "did:web:mediator.example",
+///   "validFrom": "2024-01-01T00:00:00Z",
+///   "retentionPeriod": 2592000,
+///   "quotas": {"maxStorageEntries": 64},
+///   "jurisdiction": "DE",
+///   "pricing": "free",
+///   "termsOfService": "https://mediator.example/tos"
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MediatorPolicy {
+    /// DID of the mediator, set when the policy is signed
+    #[serde(default)]
+    pub issuer: String,
+
+    /// Date from which the policy applies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<DateTime<Utc>>,
+
+    /// Time undelivered messages are retained for, in seconds
+    pub retention_period: u64,
+
+    /// Limits applied to each connection
+    #[serde(default)]
+    pub quotas: PolicyQuotas,
+
+    /// Jurisdiction the mediator operates under, e.g. an ISO 3166 country code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jurisdiction: Option<String>,
+
+    /// Pricing of the service, e.g. `free` or a URL to a price list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<String>,
+
+    /// URL of the human-readable terms of service
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terms_of_service: Option<String>,
+
+    /// Dynamic properties
+    #[serde(flatten)]
+    pub additional_properties: Option<HashMap<String, Value>>,
+
+    /// Signature of the mediator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof: Option<Proof>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyQuotas {
+    /// Maximum size of a forwarded message, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_message_size: Option<u64>,
+
+    /// Maximum number of stored entries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_storage_entries: Option<u64>,
+
+    /// Maximum size of all stored entries, in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_storage_size: Option<u64>,
+}
+
+impl From<&StorageQuota> for PolicyQuotas {
+    fn from(quota: &StorageQuota) -> Self {
+        Self {
+            max_message_size: None,
+            max_storage_entries: Some(quota.max_entries as u64),
+            max_storage_size: Some(quota.max_total_size),
+        }
+    }
+}
+
+/// Reference to the mediator policy, carried by mediation grants
+/// in the `mediator_policy` extension header.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PolicyReference {
+    /// URL the signed policy is served at
+    pub url: String,
+
+    /// Multibase-encoded SHA-256 digest of the canonical signed policy,
+    /// pinning the version the recipient is presented
+    pub digest: String,
+}
+
+// endregion: --- Model
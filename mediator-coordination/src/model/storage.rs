@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    constants::*,
+    didcomm::validation::{FieldKind, FieldSpec, MessageSpec},
+    model::coord::{CoordMessage, EmptyBody},
+};
+
+// region: --- Model
+
+pub type StoragePut = CoordMessage<StoragePutBody>;
+pub type StorageStored = CoordMessage<StorageEntryBody>;
+pub type StorageGet = CoordMessage<StorageKeyBody>;
+pub type StorageValue = CoordMessage<StorageValueBody>;
+pub type StorageDelete = CoordMessage<StorageKeyBody>;
+pub type StorageDeleted = CoordMessage<StorageKeyBody>;
+pub type StorageList = CoordMessage<EmptyBody>;
+pub type StorageKeys = CoordMessage<StorageKeysBody>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StoragePutBody {
+    pub key: String,
+
+    /// Opaque blob, encrypted by the recipient, as base64url
+    pub data: String,
+
+    /// Requested time to live in seconds, capped by the mediator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StorageKeyBody {
+    pub key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StorageEntryBody {
+    pub key: String,
+
+    /// Size of the blob in bytes
+    pub size: u64,
+
+    /// Expiry as a UNIX timestamp in seconds
+    pub expires_time: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StorageValueBody {
+    pub key: String,
+    pub data: String,
+    pub expires_time: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StorageKeysBody {
+    pub keys: Vec<StorageEntryBody>,
+
+    /// Bytes used by the connection's entries
+    pub used: u64,
+
+    /// Bytes the connection may use in total
+    pub quota: u64,
+}
+
+/// Blobs stored under a connection, persisted as a single entity
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StoredBlobs {
+    /// DID of the connection the blobs are stored under
+    pub connection: String,
+
+    /// Blobs by key
+    pub entries: BTreeMap<String, StoredBlob>,
+
+    #[serde(default)]
+    pub version: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct StoredBlob {
+    pub data: Vec<u8>,
+
+    /// Expiry as a UNIX timestamp in seconds
+    pub expires_time: i64,
+}
+
+// endregion: --- Model
+
+// region: --- Specs
+
+const STORAGE_ENTRY_FIELDS: &[FieldSpec] = &[
+    FieldSpec {
+        name: "key",
+        kind: FieldKind::String,
+        required: true,
+    },
+    FieldSpec {
+        name: "size",
+        kind: FieldKind::Integer,
+        required: true,
+    },
+    FieldSpec {
+        name: "expires_time",
+        kind: FieldKind::Integer,
+        required: true,
+    },
+];
+
+const STORAGE_KEY_FIELDS: &[FieldSpec] = &[FieldSpec {
+    name: "key",
+    kind: FieldKind::String,
+    required: true,
+}];
+
+/// Specs of the messages of the storage protocol
+pub fn message_specs() -> Vec<MessageSpec> {
+    vec![
+        MessageSpec {
+            message_type: STORAGE_PUT_1_0,
+            required_headers: &[],
+            body: &[
+                FieldSpec {
+                    name: "key",
+                    kind: FieldKind::String,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "data",
+                    kind: FieldKind::String,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "ttl",
+                    kind: FieldKind::Integer,
+                    required: false,
+                },
+            ],
+        },
+        MessageSpec {
+            message_type: STORAGE_STORED_1_0,
+            required_headers: &["thid"],
+            body: STORAGE_ENTRY_FIELDS,
+        },
+        MessageSpec {
+            message_type: STORAGE_GET_1_0,
+            required_headers: &[],
+            body: STORAGE_KEY_FIELDS,
+        },
+        MessageSpec {
+            message_type: STORAGE_VALUE_1_0,
+            required_headers: &["thid"],
+            body: &[
+                FieldSpec {
+                    name: "key",
+                    kind: FieldKind::String,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "data",
+                    kind: FieldKind::String,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "expires_time",
+                    kind: FieldKind::Integer,
+                    required: true,
+                },
+            ],
+        },
+        MessageSpec {
+            message_type: STORAGE_DELETE_1_0,
+            required_headers: &[],
+            body: STORAGE_KEY_FIELDS,
+        },
+        MessageSpec {
+            message_type: STORAGE_DELETED_1_0,
+            required_headers: &["thid"],
+            body: STORAGE_KEY_FIELDS,
+        },
+        MessageSpec {
+            message_type: STORAGE_LIST_1_0,
+            required_headers: &[],
+            body: &[],
+        },
+        MessageSpec {
+            message_type: STORAGE_KEYS_1_0,
+            required_headers: &["thid"],
+            body: &[
+                FieldSpec {
+                    name: "keys",
+                    kind: FieldKind::Array(&FieldKind::Object(STORAGE_ENTRY_FIELDS)),
+                    required: true,
+                },
+                FieldSpec {
+                    name: "used",
+                    kind: FieldKind::Integer,
+                    required: true,
+                },
+                FieldSpec {
+                    name: "quota",
+                    kind: FieldKind::Integer,
+                    required: true,
+                },
+            ],
+        },
+    ]
+}
+
+// endregion: --- Specs
@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::*,
+    didcomm::validation::{FieldKind, FieldSpec, MessageSpec},
+    model::coord::CoordMessage,
+};
+
+// region: --- Model
+
+pub type DeliveryWindowsSet = CoordMessage<DeliveryWindowsBody>;
+pub type DeliveryWindowsGet = CoordMessage<DeliveryWindowsGetBody>;
+pub type DeliveryWindows = CoordMessage<DeliveryWindowsBody>;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct DeliveryWindowsBody {
+    /// IANA time zone of the recipient, e.g. `Europe/Berlin`
+    pub timezone: String,
+
+    /// Windows within which messages are pushed, all times when empty
+    pub windows: Vec<DeliveryWindow>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct DeliveryWindow {
+    /// Local opening time, as `HH:MM`
+    pub start: String,
+
+    /// Local closing time, as `HH:MM`. Windows closing before they open
+    /// span midnight.
+    pub end: String,
+
+    /// Days the window opens on, e.g. `mon`, every day when absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct DeliveryWindowsGetBody {}
+
+// endregion: --- Model
+
+// region: --- Specs
+
+const TIMEZONE_FIELD: FieldSpec = FieldSpec {
+    name: "timezone",
+    kind: FieldKind::String,
+    required: true,
+};
+
+const WINDOWS_FIELD: FieldSpec = FieldSpec {
+    name: "windows",
+    kind: FieldKind::Array(&FieldKind::Object(&[
+        FieldSpec {
+            name: "start",
+            kind: FieldKind::String,
+            required: true,
+        },
+        FieldSpec {
+            name: "end",
+            kind: FieldKind::String,
+            required: true,
+        },
+        FieldSpec {
+            name: "days",
+            kind: FieldKind::Array(&FieldKind::String),
+            required: false,
+        },
+    ])),
+    required: true,
+};
+
+/// Specs of the messages of the delivery windows protocol
+pub fn message_specs() -> Vec<MessageSpec> {
+    vec![
+        MessageSpec {
+            message_type: DELIVERY_WINDOWS_SET_1_0,
+            required_headers: &[],
+            body: &[TIMEZONE_FIELD, WINDOWS_FIELD],
+        },
+        MessageSpec {
+            message_type: DELIVERY_WINDOWS_GET_1_0,
+            required_headers: &[],
+            body: &[],
+        },
+        MessageSpec {
+            message_type: DELIVERY_WINDOWS_1_0,
+            required_headers: &["thid"],
+            body: &[TIMEZONE_FIELD, WINDOWS_FIELD],
+        },
+    ]
+}
+
+// endregion: --- Specs
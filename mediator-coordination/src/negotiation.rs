@@ -0,0 +1,288 @@
+//! Protocols and headers supported by the clients of connections.
+//!
+//! Clients disclose the features they support in Discover Features 2.0
+//! `disclose` messages, or list the protocols they support in the
+//! [`SUPPORTED_PROTOCOLS_HEADER`] of any message. Disclosures are recorded
+//! on their connection, replacing earlier ones, so that messages sent to
+//! the client adapt to them, e.g. problem reports and pickup deliveries:
+//! - their types are set to the minor version of their protocol the client
+//!   supports, if earlier than the one served, and messages of protocols
+//!   the client only supports at other major versions are not sent;
+//! - DID rotations are only announced in `from_prior` headers to clients
+//!   supporting them.
+//!
+//! Clients who disclosed nothing, or nothing about a protocol, are sent
+//! messages as served.
+
+use protocols_registry::{MessageType, Protocol, ProtocolId};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::{
+    constants::DISCOVER_DISCLOSE_2_0,
+    keylist::UPDATE_ATTEMPTS,
+    model::connection::{Connection, Disclosures},
+    repository::{update_with_retry, Repository, RepositoryError},
+};
+
+/// Header listing the protocols supported by the sender of a message
+pub const SUPPORTED_PROTOCOLS_HEADER: &str = "supported_protocols";
+
+/// Header announcing the rotation of the DID of the sender of a message
+pub const FROM_PRIOR_HEADER: &str = "from_prior";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum NegotiationError {
+    #[error("the client does not support {0}")]
+    UnsupportedProtocol(ProtocolId),
+}
+
+/// Features disclosed by a message, if any
+pub fn disclosed(message: &Value, now: i64) -> Option<Disclosures> {
+    let message_type = message["type"]
+        .as_str()
+        .and_then(|t| t.parse::<MessageType>().ok());
+
+    if message_type.map(|t| t.as_str()) == Some(DISCOVER_DISCLOSE_2_0) {
+        let disclosures = message["body"]["disclosures"].as_array()?;
+        let ids = |feature_type: &str| -> Vec<String> {
+            disclosures
+                .iter()
+                .filter(|disclosure| disclosure["feature-type"] == feature_type)
+                .filter_map(|disclosure| disclosure["id"].as_str().map(String::from))
+                .collect()
+        };
+
+        return Some(Disclosures {
+            protocols: ids("protocol"),
+            headers: Some(ids("header")),
+            disclosed_time: now,
+        });
+    }
+
+    let protocols = message[SUPPORTED_PROTOCOLS_HEADER].as_array()?;
+    Some(Disclosures {
+        protocols: protocols
+            .iter()
+            .filter_map(|id| id.as_str().map(String::from))
+            .collect(),
+        headers: None,
+        disclosed_time: now,
+    })
+}
+
+/// Records the features disclosed by a message from the client of a
+/// connection, returning the disclosures of the connection if updated.
+/// Headers are only replaced by messages disclosing headers.
+pub fn record(
+    repository: &dyn Repository<Connection>,
+    client_did: &str,
+    message: &Value,
+    now: i64,
+) -> Result<Option<Disclosures>, RepositoryError> {
+    let Some(disclosed) = disclosed(message, now) else {
+        return Ok(None);
+    };
+
+    let (connection, _) =
+        update_with_retry(repository, client_did, UPDATE_ATTEMPTS, |connection| {
+            let earlier = connection.disclosures.take().and_then(|d| d.headers);
+            connection.disclosures = Some(Disclosures {
+                headers: disclosed.headers.clone().or(earlier),
+                ..disclosed.clone()
+            });
+        })?;
+
+    tracing::debug!("recorded features disclosed by {client_did}");
+    Ok(connection.disclosures)
+}
+
+/// Version of a protocol to send messages of to the client of a connection
+pub fn negotiate(
+    connection: &Connection,
+    protocol: Protocol,
+) -> Result<ProtocolId, NegotiationError> {
+    let served = ProtocolId::from(protocol);
+    let Some(disclosures) = &connection.disclosures else {
+        return Ok(served);
+    };
+
+    let disclosed: Vec<ProtocolId> = disclosures
+        .protocols
+        .iter()
+        .filter_map(|uri| uri.parse::<ProtocolId>().ok())
+        .filter(|id| id.protocol == protocol)
+        .collect();
+    if disclosed.is_empty() {
+        return Ok(served);
+    }
+
+    disclosed
+        .into_iter()
+        .filter(ProtocolId::is_supported)
+        .map(|id| id.version)
+        .max()
+        .map(|version| ProtocolId {
+            version: version.min(served.version),
+            ..served
+        })
+        .ok_or(NegotiationError::UnsupportedProtocol(served))
+}
+
+/// Whether the client of a connection supports a header, assumed unless
+/// it disclosed the headers it supports
+pub fn supports_header(connection: &Connection, header: &str) -> bool {
+    let disclosures = connection.disclosures.as_ref();
+    match disclosures.and_then(|d| d.headers.as_ref()) {
+        Some(headers) => headers.iter().any(|h| h == header),
+        None => true,
+    }
+}
+
+/// Adapts a message to the features disclosed by the client of the
+/// connection it is sent to. Messages of types foreign to the registry
+/// are left as is.
+pub fn adapt(connection: &Connection, message: &mut Value) -> Result<(), NegotiationError> {
+    let message_type = message["type"]
+        .as_str()
+        .and_then(|t| t.parse::<MessageType>().ok());
+    if let Some(message_type) = message_type {
+        let id = negotiate(connection, message_type.protocol())?;
+        message["type"] = json!(format!("{id}/{}", message_type.kind()));
+    }
+
+    if !supports_header(connection, FROM_PRIOR_HEADER) {
+        if let Some(message) = message.as_object_mut() {
+            message.remove(FROM_PRIOR_HEADER);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::*, didcomm::problem_report::ProblemReport, repository::MemoryRepository,
+    };
+    use protocols_registry::Version;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+
+    fn repository() -> MemoryRepository<Connection> {
+        let repository = MemoryRepository::new();
+        let connection = Connection {
+            client_did: ALICE.to_owned(),
+            ..Default::default()
+        };
+        repository.insert(connection).unwrap();
+
+        repository
+    }
+
+    #[test]
+    fn can_record_disclosed_features() {
+        let repository = repository();
+
+        let disclose = json!({
+            "id": "1",
+            "type": "https://didcomm.org/discover-features/2.1/disclose",
+            "body": {"disclosures": [
+                {"feature-type": "protocol", "id": "https://didcomm.org/messagepickup/3.0", "roles": ["recipient"]},
+                {"feature-type": "protocol", "id": "https://didcomm.org/report-problem/2.0"},
+                {"feature-type": "header", "id": "from_prior"},
+                {"feature-type": "goal-code", "id": "aries.vc.issue"}
+            ]}
+        });
+        let disclosures = record(&repository, ALICE, &disclose, 1000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            disclosures.protocols,
+            [
+                "https://didcomm.org/messagepickup/3.0",
+                "https://didcomm.org/report-problem/2.0"
+            ]
+        );
+        assert_eq!(disclosures.headers, Some(vec!["from_prior".to_owned()]));
+
+        // Headers are kept when protocols are disclosed in a header
+        let status_request = json!({
+            "id": "2",
+            "type": STATUS_REQUEST_3_0,
+            SUPPORTED_PROTOCOLS_HEADER: ["https://didcomm.org/messagepickup/2.0"],
+            "body": {}
+        });
+        record(&repository, ALICE, &status_request, 2000).unwrap();
+        let connection = repository.find(ALICE).unwrap();
+        assert_eq!(
+            connection.disclosures,
+            Some(Disclosures {
+                protocols: vec!["https://didcomm.org/messagepickup/2.0".to_owned()],
+                headers: Some(vec!["from_prior".to_owned()]),
+                disclosed_time: 2000,
+            })
+        );
+
+        // Messages disclosing nothing leave disclosures as they are
+        let ping = json!({"id": "3", "type": STATUS_REQUEST_3_0, "body": {}});
+        assert_eq!(record(&repository, ALICE, &ping, 3000), Ok(None));
+        assert_eq!(repository.find(ALICE).unwrap().version, 3);
+
+        assert!(record(&repository, "did:example:bob", &disclose, 3000).is_err());
+    }
+
+    #[test]
+    fn can_adapt_messages_to_disclosed_features() {
+        let mut connection = Connection {
+            client_did: ALICE.to_owned(),
+            ..Default::default()
+        };
+
+        // Messages are sent as served to clients who disclosed nothing
+        let mut status =
+            json!({"id": "1", "type": STATUS_3_0, FROM_PRIOR_HEADER: "ey..", "body": {}});
+        adapt(&connection, &mut status).unwrap();
+        assert_eq!(status["type"], STATUS_3_0);
+        assert_eq!(status[FROM_PRIOR_HEADER], "ey..");
+
+        connection.disclosures = Some(Disclosures {
+            protocols: vec![
+                "https://didcomm.org/messagepickup/2.0".to_owned(),
+                "https://didcomm.org/report-problem/2.1".to_owned(),
+            ],
+            headers: Some(vec![]),
+            disclosed_time: 1000,
+        });
+
+        // Pickup is not possible at another major version
+        let pickup = negotiate(&connection, Protocol::MessagePickup);
+        assert_eq!(
+            pickup,
+            Err(NegotiationError::UnsupportedProtocol(
+                Protocol::MessagePickup.into()
+            ))
+        );
+        assert_eq!(
+            adapt(&connection, &mut status).unwrap_err().to_string(),
+            "the client does not support https://didcomm.org/messagepickup/3.0"
+        );
+
+        // Problem reports are sent at the earlier of the minor versions,
+        // without announcing rotations
+        let report = ProblemReport::new("e.p.msg.invalid", None, None);
+        let mut report = json!(report);
+        report[FROM_PRIOR_HEADER] = json!("ey..");
+        adapt(&connection, &mut report).unwrap();
+        assert_eq!(report["type"], PROBLEM_REPORT_2_0);
+        assert_eq!(report.get(FROM_PRIOR_HEADER), None);
+        let problem_report = negotiate(&connection, Protocol::ReportProblem).unwrap();
+        assert_eq!(problem_report.version, Version::new(2, 0));
+
+        // Protocols not disclosed either way are served
+        let mut grant = json!({"id": "2", "type": MEDIATE_GRANT_2_0, "body": {}});
+        adapt(&connection, &mut grant).unwrap();
+        assert_eq!(grant["type"], MEDIATE_GRANT_2_0);
+    }
+}
@@ -0,0 +1,153 @@
+//! Controlled onboarding through scoped invitations.
+//!
+//! Operators may restrict mediation to recipients holding an out-of-band
+//! invitation minted through the admin API. Such invitations are bound to
+//! a policy, e.g. a single use for the `request-mediate` goal, and mediation
+//! requests reference them as their parent thread (`pthid`).
+
+use oob_messages::invitations::{
+    InvitationError, InvitationStore, ScopedInvitation, GOAL_CODE_REQUEST_MEDIATE,
+};
+
+use crate::{
+    degradation::{LoadShedder, DEGRADED_SERVICE_CODE},
+    didcomm::problem_report::ProblemReport,
+    model::coord::MediateRequest,
+};
+
+/// Problem code for mediation requests referencing no invitation
+pub const INVITATION_REQUIRED_CODE: &str = "e.p.req.invitation-required";
+
+/// Problem code for mediation requests referencing an unusable invitation
+pub const INVITATION_REJECTED_CODE: &str = "e.p.req.invitation-rejected";
+
+/// Problem code for failures to look up invitations
+pub const INVITATION_UNAVAILABLE_CODE: &str = "e.p.me.invitation-unavailable";
+
+/// Admits a mediation request on the basis of the invitation it responds to,
+/// consuming one use of the invitation. Requests are rejected before any
+/// invitation is consumed while new mediations are shed.
+///
+/// The returned problem report is to be sent back to the requester in place
+/// of a grant.
+#[allow(clippy::result_large_err)]
+pub fn admit_mediation_request(
+    store: &mut InvitationStore,
+    shedder: &LoadShedder,
+    request: &MediateRequest,
+    now: i64,
+) -> Result<ScopedInvitation, ProblemReport> {
+    let level = shedder.level();
+    if !level.allows_new_mediations() {
+        return Err(ProblemReport::new(
+            DEGRADED_SERVICE_CODE,
+            Some("New mediations are not accepted at level {1}"),
+            Some(vec![format!("{level:?}")]),
+        )
+        .with_pthid(Some(&request.id)));
+    }
+
+    let Some(invitation_id) = request.pthid.as_deref() else {
+        return Err(ProblemReport::new(
+            INVITATION_REQUIRED_CODE,
+            Some("Mediation requires an invitation"),
+            None,
+        )
+        .with_pthid(Some(&request.id)));
+    };
+
+    store
+        .redeem(invitation_id, GOAL_CODE_REQUEST_MEDIATE, now)
+        .map_err(|err| {
+            let code = match err {
+                InvitationError::IoError(_) | InvitationError::ParseError(_) => {
+                    tracing::error!("failed to redeem invitation: {err}");
+                    INVITATION_UNAVAILABLE_CODE
+                }
+                _ => INVITATION_REJECTED_CODE,
+            };
+
+            ProblemReport::new(
+                code,
+                Some("Invitation {1} rejected: {2}"),
+                Some(vec![invitation_id.to_owned(), err.to_string()]),
+            )
+            .with_pthid(Some(&request.id))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::coord::{mediate_request, mediate_request_with_invitation},
+        degradation::HealthSignals,
+    };
+
+    use did_endpoint::util::test_utils::MemoryFileSystem;
+    use oob_messages::invitations::InvitationPolicy;
+
+    #[test]
+    fn test_admit_mediation_request() {
+        let mut fs = MemoryFileSystem::default();
+        let mut store = InvitationStore::new(&mut fs, "storage");
+        let shedder = LoadShedder::default();
+
+        let invitation = store.mint(&InvitationPolicy::default(), 0).unwrap();
+        let request = mediate_request_with_invitation(&invitation.id);
+        assert_eq!(request.pthid.as_deref(), Some(invitation.id.as_str()));
+
+        let admitted = admit_mediation_request(&mut store, &shedder, &request, 10).unwrap();
+        assert_eq!(admitted.id, invitation.id);
+
+        // Single-use invitations cannot be replayed
+        let request = mediate_request_with_invitation(&invitation.id);
+        let report = admit_mediation_request(&mut store, &shedder, &request, 20).unwrap_err();
+        assert_eq!(report.body.code, INVITATION_REJECTED_CODE);
+        assert_eq!(report.pthid, Some(request.id));
+    }
+
+    #[test]
+    fn test_reject_mediation_request_without_invitation() {
+        let mut fs = MemoryFileSystem::default();
+        let mut store = InvitationStore::new(&mut fs, "storage");
+        let shedder = LoadShedder::default();
+
+        let report =
+            admit_mediation_request(&mut store, &shedder, &mediate_request(), 0).unwrap_err();
+        assert_eq!(report.body.code, INVITATION_REQUIRED_CODE);
+
+        // Invitations for other goals do not grant mediation
+        let policy = InvitationPolicy {
+            goal_code: String::from("connect"),
+            ..Default::default()
+        };
+        let invitation = store.mint(&policy, 0).unwrap();
+        let request = mediate_request_with_invitation(&invitation.id);
+        let report = admit_mediation_request(&mut store, &shedder, &request, 0).unwrap_err();
+        assert_eq!(report.body.code, INVITATION_REJECTED_CODE);
+    }
+
+    #[test]
+    fn test_reject_mediation_request_when_degraded() {
+        let mut fs = MemoryFileSystem::default();
+        let mut store = InvitationStore::new(&mut fs, "storage");
+        let shedder = LoadShedder::default();
+        shedder.observe(
+            HealthSignals {
+                memory_usage: Some(0.99),
+                ..Default::default()
+            },
+            0,
+        );
+
+        let invitation = store.mint(&InvitationPolicy::default(), 0).unwrap();
+        let request = mediate_request_with_invitation(&invitation.id);
+        let report = admit_mediation_request(&mut store, &shedder, &request, 10).unwrap_err();
+        assert_eq!(report.body.code, DEGRADED_SERVICE_CODE);
+
+        // The invitation remains usable once load goes down
+        let shedder = LoadShedder::default();
+        assert!(admit_mediation_request(&mut store, &shedder, &request, 20).is_ok());
+    }
+}
@@ -0,0 +1,496 @@
+//! Outbox coupling state changes with outbound sends.
+//!
+//! Handlers changing stored state and sending messages as a consequence,
+//! e.g. webhooks or relays, would lose the sends if the mediator crashed
+//! between the two. Instead, they gather both into a [`Transaction`], which
+//! is committed by first journaling it as a whole, then applying its writes
+//! and queuing its sends into the outbox, then dropping the journal.
+//! Each [`Outbox`] journals under a name of its own, so that handlers and
+//! relays committing concurrently never overwrite each other's journal.
+//! Transactions interrupted by a crash are completed from their journals
+//! at startup, with [`Outbox::recover`].
+//!
+//! A relay dispatches the entries of the outbox in the background,
+//! removing them once dispatched, and rescheduling them as per a
+//! [`RetryPolicy`] otherwise. Entries may be dispatched more than once,
+//! e.g. if the mediator crashes before removing them, but never lost.
+//! Entries are written atomically, so unreadable ones are corrupted rather
+//! than partially written, and are moved to `outbox/quarantine` for
+//! operators to inspect.
+//!
+//! The mediator itself only recovers interrupted transactions at startup.
+//! No handler commits transactions yet, and the mediator has no outbound
+//! sender to dispatch entries with, so it implements no [`Dispatcher`] and
+//! schedules no relay. Plugins sending messages out are to provide one,
+//! and relay the outbox with [`relay_periodically`].
+
+use did_endpoint::util::filesystem::{FileSystem, StdFileSystem};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use server_plugin::tasks::BackgroundTasks;
+use std::{io::ErrorKind, sync::Arc, time::Duration};
+use thiserror::Error;
+
+use crate::retry::RetryPolicy;
+
+#[derive(Debug, Error)]
+pub enum OutboxError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("corrupted outbox: {0}")]
+    ParseError(serde_json::Error),
+}
+
+/// Message awaiting dispatch
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OutboxEntry {
+    pub id: String,
+
+    /// Where the message is sent, e.g. a webhook URL or a recipient DID
+    pub destination: String,
+
+    pub message: Value,
+
+    /// Time the entry was queued, as a UNIX timestamp
+    pub created_time: i64,
+
+    /// Failed dispatch attempts so far
+    pub attempts: u32,
+
+    /// Time from which the entry is due, as a UNIX timestamp
+    pub next_attempt_time: i64,
+}
+
+/// Writes to stored state, and the sends they entail, committed together
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Transaction {
+    /// Paths of files along with their new content
+    writes: Vec<(String, String)>,
+    sends: Vec<OutboxEntry>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the content of a file
+    pub fn write(&mut self, path: &str, content: &str) {
+        self.writes.push((path.to_owned(), content.to_owned()));
+    }
+
+    /// Sends a message once the transaction is committed
+    pub fn send(&mut self, destination: &str, message: Value, now: i64) {
+        self.sends.push(OutboxEntry {
+            id: uuid::Uuid::new_v4().to_string(),
+            destination: destination.to_owned(),
+            message,
+            created_time: now,
+            attempts: 0,
+            next_attempt_time: now,
+        });
+    }
+}
+
+/// Sends messages of the outbox
+pub trait Dispatcher: Send + Sync {
+    fn dispatch(&self, entry: &OutboxEntry) -> Result<(), String>;
+}
+
+/// Outcome of relaying the outbox
+#[derive(Debug, Default, PartialEq)]
+pub struct RelayReport {
+    pub dispatched: usize,
+    /// Entries rescheduled after failing to dispatch
+    pub rescheduled: usize,
+}
+
+/// File-based outbox, one file per entry
+pub struct Outbox<'a> {
+    fs: &'a mut dyn FileSystem,
+    storage_dirpath: String,
+    /// Name of the journal of this instance
+    instance: String,
+}
+
+impl<'a> Outbox<'a> {
+    pub fn new(fs: &'a mut dyn FileSystem, storage_dirpath: &str) -> Self {
+        Self {
+            fs,
+            storage_dirpath: storage_dirpath.to_owned(),
+            instance: uuid::Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// Commits a transaction, applying its writes and queuing its sends
+    pub fn commit(&mut self, transaction: &Transaction) -> Result<(), OutboxError> {
+        let journal = serde_json::to_string(transaction).map_err(OutboxError::ParseError)?;
+        let journal_path = self.journal_path(&self.instance);
+        self.fs
+            .create_dir_all(&self.journals_dirpath())
+            .map_err(OutboxError::IoError)?;
+        self.fs
+            .write_atomic(&journal_path, &journal)
+            .map_err(OutboxError::IoError)?;
+
+        self.apply(transaction)?;
+
+        self.fs
+            .remove_file(&journal_path)
+            .map_err(OutboxError::IoError)
+    }
+
+    /// Completes the transactions interrupted by a crash, returning how
+    /// many there were. Applying a transaction again is harmless, as its
+    /// writes replace whole files and its entries keep their ids. This is
+    /// meant for startup, before any transaction is committed.
+    pub fn recover(&mut self) -> Result<usize, OutboxError> {
+        let mut paths = match self.fs.read_dir_files(&self.journals_dirpath()) {
+            Ok(paths) => paths,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(OutboxError::IoError(err)),
+        };
+        paths.retain(|path| path.ends_with(".json"));
+        paths.sort();
+
+        for path in &paths {
+            let journal = self.fs.read_to_string(path).map_err(OutboxError::IoError)?;
+
+            // Journals are written atomically, so an unreadable one was
+            // never committed, and its writes were never applied
+            match serde_json::from_str(&journal) {
+                Ok(transaction) => self.apply(&transaction)?,
+                Err(err) => tracing::warn!("discarding incomplete outbox journal {path}: {err}"),
+            }
+
+            self.fs.remove_file(path).map_err(OutboxError::IoError)?;
+        }
+
+        Ok(paths.len())
+    }
+
+    /// Entries awaiting dispatch, oldest first. Corrupted entries are
+    /// moved to quarantine.
+    pub fn pending(&mut self) -> Result<Vec<OutboxEntry>, OutboxError> {
+        let mut paths = match self.fs.read_dir_files(&self.dirpath()) {
+            Ok(paths) => paths,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(OutboxError::IoError(err)),
+        };
+        paths.retain(|path| path.ends_with(".json"));
+        paths.sort();
+
+        let mut entries = vec![];
+        for path in paths {
+            let content = self
+                .fs
+                .read_to_string(&path)
+                .map_err(OutboxError::IoError)?;
+
+            match serde_json::from_str(&content) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => {
+                    tracing::error!("quarantining corrupted outbox entry {path}: {err}");
+                    self.quarantine(&path, &content)?;
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Removes an entry without dispatching it
+    pub fn remove(&mut self, entry: &OutboxEntry) -> Result<(), OutboxError> {
+        self.fs
+            .remove_file(&self.entry_path(entry))
+            .map_err(OutboxError::IoError)
+    }
+
+    /// Paths of corrupted entries moved to quarantine
+    pub fn quarantined(&self) -> Result<Vec<String>, OutboxError> {
+        match self.fs.read_dir_files(&self.quarantine_dirpath()) {
+            Ok(mut paths) => {
+                paths.sort();
+                Ok(paths)
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(vec![]),
+            Err(err) => Err(OutboxError::IoError(err)),
+        }
+    }
+
+    fn quarantine(&mut self, path: &str, content: &str) -> Result<(), OutboxError> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let quarantined = format!("{}/{name}", self.quarantine_dirpath());
+
+        self.fs
+            .create_dir_all(&self.quarantine_dirpath())
+            .map_err(OutboxError::IoError)?;
+        self.fs
+            .write_atomic(&quarantined, content)
+            .map_err(OutboxError::IoError)?;
+        self.fs.remove_file(path).map_err(OutboxError::IoError)
+    }
+
+    /// Dispatches due entries, removing dispatched ones and rescheduling
+    /// the others
+    pub fn relay(
+        &mut self,
+        dispatcher: &dyn Dispatcher,
+        policy: &RetryPolicy,
+        now: i64,
+    ) -> Result<RelayReport, OutboxError> {
+        let mut report = RelayReport::default();
+        for mut entry in self.pending()? {
+            if entry.next_attempt_time > now {
+                continue;
+            }
+
+            match dispatcher.dispatch(&entry) {
+                Ok(()) => {
+                    self.fs
+                        .remove_file(&self.entry_path(&entry))
+                        .map_err(OutboxError::IoError)?;
+                    report.dispatched += 1;
+                }
+                Err(reason) => {
+                    // Entries are never dropped, retries being spaced up to
+                    // the maximum delay of the policy
+                    entry.attempts += 1;
+                    let delay = policy.delay(entry.attempts);
+                    entry.next_attempt_time = now + delay.as_secs_f64().ceil() as i64;
+                    tracing::warn!(
+                        "failed to dispatch outbox entry {} to {} ({} attempts): {reason}",
+                        entry.id,
+                        entry.destination,
+                        entry.attempts
+                    );
+
+                    self.put(&entry)?;
+                    report.rescheduled += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn apply(&mut self, transaction: &Transaction) -> Result<(), OutboxError> {
+        for (path, content) in &transaction.writes {
+            self.fs
+                .write_atomic(path, content)
+                .map_err(OutboxError::IoError)?;
+        }
+
+        if !transaction.sends.is_empty() {
+            self.fs
+                .create_dir_all(&self.dirpath())
+                .map_err(OutboxError::IoError)?;
+        }
+        for entry in &transaction.sends {
+            self.put(entry)?;
+        }
+
+        Ok(())
+    }
+
+    fn put(&mut self, entry: &OutboxEntry) -> Result<(), OutboxError> {
+        let content = serde_json::to_string(entry).map_err(OutboxError::ParseError)?;
+        self.fs
+            .write_atomic(&self.entry_path(entry), &content)
+            .map_err(OutboxError::IoError)
+    }
+
+    fn dirpath(&self) -> String {
+        format!("{}/outbox", self.storage_dirpath)
+    }
+
+    fn journals_dirpath(&self) -> String {
+        format!("{}/outbox/journals", self.storage_dirpath)
+    }
+
+    fn journal_path(&self, instance: &str) -> String {
+        format!("{}/{instance}.json", self.journals_dirpath())
+    }
+
+    fn quarantine_dirpath(&self) -> String {
+        format!("{}/outbox/quarantine", self.storage_dirpath)
+    }
+
+    // Names sort entries by creation time
+    fn entry_path(&self, entry: &OutboxEntry) -> String {
+        format!(
+            "{}/{}-{}.json",
+            self.dirpath(),
+            entry.created_time,
+            entry.id
+        )
+    }
+}
+
+/// Relays the outbox every `interval` on a background task
+pub fn relay_periodically(
+    tasks: &BackgroundTasks,
+    dispatcher: Arc<dyn Dispatcher>,
+    policy: RetryPolicy,
+    storage_dirpath: &str,
+    interval: Duration,
+) {
+    let storage_dirpath = storage_dirpath.to_owned();
+
+    tasks.every("outbox-relay", interval, move || {
+        let now = chrono::Utc::now().timestamp();
+        let mut fs = StdFileSystem;
+        let mut outbox = Outbox::new(&mut fs, &storage_dirpath);
+        if let Err(err) = outbox.relay(dispatcher.as_ref(), &policy, now) {
+            tracing::error!("failed to relay outbox: {err}");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use did_endpoint::util::test_utils::{MemoryFileSystem, TEST_EPOCH};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    const STORAGE: &str = "/storage";
+
+    #[derive(Default)]
+    struct RecordingDispatcher {
+        sent: Mutex<Vec<String>>,
+        failing: bool,
+    }
+
+    impl Dispatcher for RecordingDispatcher {
+        fn dispatch(&self, entry: &OutboxEntry) -> Result<(), String> {
+            if self.failing {
+                return Err(String::from("connection refused"));
+            }
+
+            self.sent.lock().unwrap().push(entry.destination.clone());
+            Ok(())
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            jitter: false,
+            base_delay: Duration::from_secs(10),
+            max_delay: Duration::from_secs(60),
+            ..Default::default()
+        }
+    }
+
+    fn transaction() -> Transaction {
+        let mut transaction = Transaction::new();
+        transaction.write("/storage/state.json", r#"{"granted": true}"#);
+        transaction.send(
+            "https://hooks.example.com",
+            json!({"event": "granted"}),
+            TEST_EPOCH,
+        );
+        transaction.send("did:key:alice", json!({"id": "msg-1"}), TEST_EPOCH);
+        transaction
+    }
+
+    #[test]
+    fn test_committing_transactions() {
+        let fs = MemoryFileSystem::new();
+        let mut handle = fs.clone();
+        let mut outbox = Outbox::new(&mut handle, STORAGE);
+
+        outbox.commit(&transaction()).unwrap();
+
+        assert_eq!(
+            fs.get("/storage/state.json").unwrap(),
+            r#"{"granted": true}"#
+        );
+        assert!(fs.paths().iter().all(|path| !path.contains("journals")));
+
+        let pending = outbox.pending().unwrap();
+        assert_eq!(pending.len(), 2);
+        assert!(pending.iter().all(|entry| entry.attempts == 0));
+    }
+
+    #[test]
+    fn test_recovering_interrupted_transactions() {
+        let fs = MemoryFileSystem::new();
+        let mut handle = fs.clone();
+
+        // Crash right after journaling, while another instance journals
+        // a transaction never committed
+        let journal = serde_json::to_string(&transaction()).unwrap();
+        handle
+            .write("/storage/outbox/journals/crashed.json", &journal)
+            .unwrap();
+        handle
+            .write("/storage/outbox/journals/torn.json", &journal[..10])
+            .unwrap();
+
+        let mut outbox = Outbox::new(&mut handle, STORAGE);
+        assert_eq!(outbox.recover().unwrap(), 2);
+        assert_eq!(outbox.recover().unwrap(), 0);
+
+        assert!(fs.get("/storage/state.json").is_some());
+        assert_eq!(outbox.pending().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_relaying_entries() {
+        let mut fs = MemoryFileSystem::new();
+        let mut outbox = Outbox::new(&mut fs, STORAGE);
+        outbox.commit(&transaction()).unwrap();
+
+        let failing = RecordingDispatcher {
+            failing: true,
+            ..Default::default()
+        };
+        let report = outbox.relay(&failing, &policy(), TEST_EPOCH).unwrap();
+        assert_eq!(report.rescheduled, 2);
+
+        let pending = outbox.pending().unwrap();
+        assert!(pending.iter().all(|entry| entry.attempts == 1));
+        assert!(pending
+            .iter()
+            .all(|entry| entry.next_attempt_time == TEST_EPOCH + 10));
+
+        // Rescheduled entries wait until due
+        let dispatcher = RecordingDispatcher::default();
+        let report = outbox
+            .relay(&dispatcher, &policy(), TEST_EPOCH + 5)
+            .unwrap();
+        assert_eq!(report, RelayReport::default());
+
+        let report = outbox
+            .relay(&dispatcher, &policy(), TEST_EPOCH + 10)
+            .unwrap();
+        assert_eq!(report.dispatched, 2);
+        assert_eq!(dispatcher.sent.lock().unwrap().len(), 2);
+        assert!(outbox.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_quarantining_corrupted_entries() {
+        let fs = MemoryFileSystem::new();
+        let mut handle = fs.clone();
+        let mut outbox = Outbox::new(&mut handle, STORAGE);
+        outbox.commit(&transaction()).unwrap();
+        outbox
+            .fs
+            .write("/storage/outbox/0-corrupted.json", "{\"id\":")
+            .unwrap();
+
+        assert_eq!(outbox.pending().unwrap().len(), 2);
+        assert_eq!(
+            outbox.quarantined().unwrap(),
+            ["/storage/outbox/quarantine/0-corrupted.json"]
+        );
+        assert!(fs.get("/storage/outbox/0-corrupted.json").is_none());
+        assert_eq!(
+            fs.get("/storage/outbox/quarantine/0-corrupted.json")
+                .unwrap(),
+            "{\"id\":"
+        );
+    }
+}
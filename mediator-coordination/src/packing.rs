@@ -0,0 +1,296 @@
+//! Packing modes of response messages.
+//!
+//! Responses are packed with authenticated encryption unless configured
+//! otherwise, per protocol through the `RESPONSE_PACKING` setting and per
+//! connection at runtime, e.g.:
+//! ```json
+//! {
+//!   "default": "authcrypt",
+//!   "protocols": {"https://didcomm.org/report-problem/2.0": "anoncrypt"}
+//! }
+//! ```
+//!
+//! Handlers override both for a response by setting its ephemeral
+//! [`PACKING_HEADER`], e.g. to report problems to senders who could not be
+//! authenticated with anonymous encryption. The header is stripped before
+//! packing.
+
+use protocols_registry::{MessageType, Protocol, ProtocolId};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+/// Header overriding the packing mode of the response carrying it
+pub const PACKING_HEADER: &str = "packing";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PackingError {
+    #[error("invalid response packing: {0}")]
+    ParseError(String),
+    #[error("invalid packing mode `{0}`")]
+    InvalidMode(String),
+    #[error("failed to pack response: {0}")]
+    PackingFailed(String),
+}
+
+/// How a response is packed
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PackingMode {
+    /// Encrypted to the recipient, authenticating the mediator
+    #[default]
+    Authcrypt,
+    /// Encrypted to the recipient, anonymously
+    Anoncrypt,
+    /// Signed by the mediator, in the clear
+    Signed,
+}
+
+/// Packs messages in each of the modes
+pub trait Packer {
+    fn authcrypt(&self, message: &Value, to: &str) -> Result<String, PackingError>;
+
+    fn anoncrypt(&self, message: &Value, to: &str) -> Result<String, PackingError>;
+
+    fn sign(&self, message: &Value) -> Result<String, PackingError>;
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PackingConfigDocument {
+    #[serde(default)]
+    default: PackingMode,
+
+    #[serde(default)]
+    protocols: BTreeMap<String, PackingMode>,
+}
+
+/// Packing modes of responses, by protocol
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PackingConfig {
+    /// Mode of responses of protocols not listed
+    pub default: PackingMode,
+
+    pub protocols: BTreeMap<Protocol, PackingMode>,
+}
+
+impl PackingConfig {
+    /// Modes configured by `RESPONSE_PACKING`, authcrypt for all protocols
+    /// if missing. Invalid settings are rejected rather than ignored.
+    pub fn from_env() -> Result<Self, PackingError> {
+        match server_plugin::reload::var("RESPONSE_PACKING") {
+            Some(content) => Self::parse(&content),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Parses modes keyed by protocol URIs, at the major version served
+    pub fn parse(content: &str) -> Result<Self, PackingError> {
+        let document: PackingConfigDocument = serde_json::from_str(content)
+            .map_err(|err| PackingError::ParseError(err.to_string()))?;
+
+        let protocols = document
+            .protocols
+            .into_iter()
+            .map(|(uri, mode)| match uri.parse::<ProtocolId>() {
+                Ok(id) if id.is_supported() => Ok((id.protocol, mode)),
+                _ => Err(PackingError::ParseError(format!("unknown protocol {uri}"))),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            default: document.default,
+            protocols,
+        })
+    }
+}
+
+/// Packing modes of responses, by protocol and by connection. Clones share
+/// the same connection modes.
+#[derive(Debug, Clone, Default)]
+pub struct ResponsePacking {
+    config: PackingConfig,
+    connections: Arc<Mutex<HashMap<String, PackingMode>>>,
+}
+
+impl ResponsePacking {
+    pub fn new(config: PackingConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the mode of the responses to a connection, over that of their
+    /// protocol
+    pub fn set_connection_mode(&self, connection: &str, mode: PackingMode) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.insert(connection.to_owned(), mode);
+    }
+
+    /// Reverts the responses to a connection to the mode of their protocol
+    pub fn clear_connection_mode(&self, connection: &str) {
+        self.connections.lock().unwrap().remove(connection);
+    }
+
+    /// Mode of a response to a connection: that of its header, if any, or
+    /// else that of the connection, of the protocol of the response, or
+    /// the default
+    pub fn mode(&self, connection: &str, response: &Value) -> Result<PackingMode, PackingError> {
+        if let Some(mode) = response.get(PACKING_HEADER) {
+            return serde_json::from_value(mode.clone())
+                .map_err(|_| PackingError::InvalidMode(mode.to_string()));
+        }
+
+        if let Some(mode) = self.connections.lock().unwrap().get(connection) {
+            return Ok(*mode);
+        }
+
+        let protocol = response["type"]
+            .as_str()
+            .and_then(|t| t.parse::<MessageType>().ok())
+            .and_then(|t| self.config.protocols.get(&t.protocol()));
+
+        Ok(protocol.copied().unwrap_or(self.config.default))
+    }
+}
+
+/// Overrides the packing mode of a response
+pub fn set_packing_mode(response: &mut Value, mode: PackingMode) {
+    if let Some(response) = response.as_object_mut() {
+        response.insert(PACKING_HEADER.to_owned(), json!(mode));
+    }
+}
+
+/// Packs a response to a connection in the mode resolved by
+/// [`ResponsePacking::mode`], encrypting it to `to`, usually the DID of the
+/// sender of the request
+pub fn pack_response_message(
+    packer: &dyn Packer,
+    packing: &ResponsePacking,
+    connection: &str,
+    to: &str,
+    mut response: Value,
+) -> Result<String, PackingError> {
+    let mode = packing.mode(connection, &response)?;
+    if let Some(response) = response.as_object_mut() {
+        response.remove(PACKING_HEADER);
+    }
+
+    match mode {
+        PackingMode::Authcrypt => packer.authcrypt(&response, to),
+        PackingMode::Anoncrypt => packer.anoncrypt(&response, to),
+        PackingMode::Signed => packer.sign(&response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::*;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+
+    /// Packs messages as the mode and the content packed, for inspection
+    struct Recorder;
+
+    impl Packer for Recorder {
+        fn authcrypt(&self, message: &Value, to: &str) -> Result<String, PackingError> {
+            Ok(format!("authcrypt:{to}:{message}"))
+        }
+
+        fn anoncrypt(&self, message: &Value, to: &str) -> Result<String, PackingError> {
+            Ok(format!("anoncrypt:{to}:{message}"))
+        }
+
+        fn sign(&self, message: &Value) -> Result<String, PackingError> {
+            Ok(format!("signed:{message}"))
+        }
+    }
+
+    fn packing() -> ResponsePacking {
+        let config = PackingConfig::parse(
+            r#"{
+                "protocols": {
+                    "https://didcomm.org/report-problem/2.0": "anoncrypt",
+                    "https://didcomm.org/messagepickup/3.0": "signed"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        ResponsePacking::new(config)
+    }
+
+    #[test]
+    fn can_parse_packing_config() {
+        let config = packing().config;
+        assert_eq!(config.default, PackingMode::Authcrypt);
+        assert_eq!(
+            config.protocols[&Protocol::ReportProblem],
+            PackingMode::Anoncrypt
+        );
+
+        for invalid in [
+            r#"{"default": "plaintext"}"#,
+            r#"{"protocols": {"https://example.com/ping/1.0": "signed"}}"#,
+            r#"{"protocols": {"https://didcomm.org/messagepickup/2.0": "signed"}}"#,
+            r#"{"connections": {}}"#,
+        ] {
+            assert!(PackingConfig::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn can_resolve_packing_modes() {
+        let packing = packing();
+        let status = json!({"id": "1", "type": STATUS_3_0, "body": {}});
+        let grant = json!({"id": "2", "type": MEDIATE_GRANT_2_0, "body": {}});
+
+        assert_eq!(packing.mode(ALICE, &status), Ok(PackingMode::Signed));
+        assert_eq!(packing.mode(ALICE, &grant), Ok(PackingMode::Authcrypt));
+
+        // Connection modes apply over those of protocols
+        packing.set_connection_mode(ALICE, PackingMode::Anoncrypt);
+        assert_eq!(packing.mode(ALICE, &status), Ok(PackingMode::Anoncrypt));
+        assert_eq!(
+            packing.mode("did:example:bob", &status),
+            Ok(PackingMode::Signed)
+        );
+        packing.clear_connection_mode(ALICE);
+        assert_eq!(packing.mode(ALICE, &status), Ok(PackingMode::Signed));
+
+        // Responses of unknown types are packed in the default mode
+        let other = json!({"id": "3", "type": "https://example.com/ping/1.0/pong"});
+        assert_eq!(packing.mode(ALICE, &other), Ok(PackingMode::Authcrypt));
+    }
+
+    #[test]
+    fn can_override_packing_mode_of_responses() {
+        let packing = packing();
+        packing.set_connection_mode(ALICE, PackingMode::Signed);
+
+        // e.g. problem reports to unauthenticated senders
+        let mut report = json!({"id": "1", "type": PROBLEM_REPORT_2_0, "body": {}});
+        set_packing_mode(&mut report, PackingMode::Anoncrypt);
+
+        let packed = pack_response_message(&Recorder, &packing, ALICE, ALICE, report).unwrap();
+        let (mode, content) = packed.split_at(packed.find(":{").unwrap());
+        assert_eq!(mode, format!("anoncrypt:{ALICE}"));
+
+        // The header is stripped before packing
+        let content: Value = serde_json::from_str(&content[1..]).unwrap();
+        assert_eq!(content.get(PACKING_HEADER), None);
+
+        let invalid = json!({"id": "2", "type": PROBLEM_REPORT_2_0, PACKING_HEADER: "plaintext"});
+        let result = pack_response_message(&Recorder, &packing, ALICE, ALICE, invalid);
+        assert_eq!(
+            result,
+            Err(PackingError::InvalidMode(r#""plaintext""#.to_owned()))
+        );
+    }
+}
@@ -0,0 +1,1087 @@
+//! Message pickup, as per the Message Pickup 3.0 protocol.
+//!
+//! Messages forwarded to a connection are queued until its recipient picks
+//! them up. Deliveries are assembled in batches bounded by the requested
+//! `limit` and by a server-side size budget. Delivered messages are leased
+//! rather than removed, so that successive delivery requests stream through
+//! the queue, while messages not acknowledged before their lease expires
+//! are delivered again.
+//!
+//! Messages scheduled for later delivery are held back until due, and
+//! messages with an expiry are dropped once expired unless acknowledged,
+//! see [`crate::timing`].
+//!
+//! Deliveries and acknowledgements of messages whose sender requested
+//! acknowledgement are reported to a [`DeliveryTracker`], if any.
+//!
+//! See https://didcomm.org/messagepickup/3.0/
+
+use chrono::Utc;
+use multibase::Base::Base64Url;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ops::Bound,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use thiserror::Error;
+
+use crate::{
+    constants::*,
+    delivery::DeliveryTracker,
+    didcomm::{
+        attachment::Attachment,
+        problem_report::ProblemReport,
+        validation::{MessageValidator, INVALID_MESSAGE_CODE, UNSUPPORTED_MESSAGE_CODE},
+    },
+    model::{coord::CoordMessage, delivery::DeliveryState, pickup::*},
+};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PickupError {
+    #[error("invalid resumption token")]
+    InvalidResumeToken,
+}
+
+impl From<PickupError> for ProblemReport {
+    fn from(err: PickupError) -> Self {
+        ProblemReport::new(
+            INVALID_MESSAGE_CODE,
+            Some("Invalid delivery request: {1}"),
+            Some(vec![err.to_string()]),
+        )
+    }
+}
+
+/// Limits applied to deliveries
+#[derive(Debug, Clone, PartialEq)]
+pub struct PickupConfig {
+    /// Maximum number of messages per delivery, whatever the requested limit
+    pub max_batch_messages: u64,
+
+    /// Size of the messages per delivery, in bytes, beyond which no
+    /// further message is added. A single larger message is still
+    /// delivered on its own.
+    pub max_batch_bytes: usize,
+
+    /// Lease of delivered messages, in seconds, after which they are
+    /// delivered again unless acknowledged
+    pub redelivery_after: i64,
+}
+
+impl Default for PickupConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_messages: 100,
+            max_batch_bytes: 1024 * 1024,
+            redelivery_after: 60,
+        }
+    }
+}
+
+/// Message awaiting pickup
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QueuedMessage {
+    /// Identifier, as acknowledged by the recipient
+    pub id: String,
+
+    /// DID or key identifier the message was forwarded to
+    pub recipient_did: String,
+
+    /// Packed message, as forwarded
+    pub payload: String,
+
+    /// Delivery priority, higher first
+    pub priority: u8,
+
+    /// Time of reception, as a UNIX timestamp
+    pub received_time: i64,
+
+    /// Time from which the message is dropped unless acknowledged, as a
+    /// UNIX timestamp
+    pub expires_time: Option<i64>,
+
+    /// Time before which the message is held back, as a UNIX timestamp
+    pub deliver_after: Option<i64>,
+
+    /// Sender to notify should the message expire
+    pub expiry_notice_to: Option<String>,
+}
+
+impl QueuedMessage {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires_time.is_some_and(|time| time <= now)
+    }
+
+    fn to_attachment(&self) -> Attachment {
+        let attachment = match serde_json::from_str(&self.payload) {
+            Ok(json) => Attachment::from_json(json),
+            Err(_) => Attachment::from_base64(self.payload.as_bytes()),
+        };
+
+        attachment.with_id(&self.id)
+    }
+}
+
+/// Messages selected for a delivery
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DeliveryBatch {
+    pub messages: Vec<QueuedMessage>,
+
+    /// Number of matching messages not yet delivered after this batch
+    pub remaining: u64,
+
+    /// Opaque token resuming the next batch after this one, if any
+    pub resume_token: Option<String>,
+}
+
+/// Delivery state of a queued message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessageStatus {
+    /// Awaiting delivery
+    Queued,
+    /// Delivered, awaiting acknowledgement until the lease expires
+    Leased,
+}
+
+#[derive(Debug)]
+struct Entry {
+    message: QueuedMessage,
+    status: MessageStatus,
+    leased_time: i64,
+}
+
+/// Orders messages by status, then by priority, highest first, then in
+/// reception order
+type IndexKey = (MessageStatus, Reverse<u8>, u64);
+
+/// Lowest key of leased messages, bounding the range of queued ones
+const LEASED_START: IndexKey = (MessageStatus::Leased, Reverse(u8::MAX), 0);
+
+/// Position of the last message of a delivery, from which the next one
+/// resumes. Tokens of a purged queue refer to none of its successor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ResumeToken {
+    generation: u64,
+    priority: u8,
+    seq: u64,
+}
+
+impl ResumeToken {
+    fn key(&self) -> IndexKey {
+        (MessageStatus::Queued, Reverse(self.priority), self.seq)
+    }
+
+    fn encode(&self) -> String {
+        let token = format!("{}.{}.{}", self.generation, self.priority, self.seq);
+        Base64Url.encode(token)
+    }
+
+    fn decode(token: &str) -> Result<Self, PickupError> {
+        let token = Base64Url
+            .decode(token)
+            .ok()
+            .and_then(|token| String::from_utf8(token).ok())
+            .ok_or(PickupError::InvalidResumeToken)?;
+
+        let mut parts = token.split('.');
+        let mut part = || parts.next().ok_or(PickupError::InvalidResumeToken);
+        let token = Self {
+            generation: part()?
+                .parse()
+                .map_err(|_| PickupError::InvalidResumeToken)?,
+            priority: part()?
+                .parse()
+                .map_err(|_| PickupError::InvalidResumeToken)?,
+            seq: part()?
+                .parse()
+                .map_err(|_| PickupError::InvalidResumeToken)?,
+        };
+
+        match parts.next() {
+            None => Ok(token),
+            Some(_) => Err(PickupError::InvalidResumeToken),
+        }
+    }
+}
+
+/// Compound index over a set of messages, maintaining the aggregates
+/// reported in statuses
+#[derive(Debug, Default)]
+struct Index {
+    keys: BTreeSet<IndexKey>,
+
+    /// Sequence numbers, i.e. reception order
+    seqs: BTreeSet<u64>,
+
+    leased: usize,
+    total_bytes: u64,
+}
+
+impl Index {
+    fn insert(&mut self, key: IndexKey, size: u64) {
+        self.leased += usize::from(key.0 == MessageStatus::Leased);
+        self.total_bytes += size;
+        self.seqs.insert(key.2);
+        self.keys.insert(key);
+    }
+
+    fn remove(&mut self, key: &IndexKey, size: u64) {
+        self.leased -= usize::from(key.0 == MessageStatus::Leased);
+        self.total_bytes -= size;
+        self.seqs.remove(&key.2);
+        self.keys.remove(key);
+    }
+
+    fn len(&self) -> usize {
+        self.seqs.len()
+    }
+
+    /// Queued messages following a position, then those preceding it, so
+    /// that a scan resumed from it visits no more entries than it takes
+    fn queued_after(&self, cursor: Option<IndexKey>) -> impl Iterator<Item = u64> + '_ {
+        let (tail, head) = match cursor {
+            Some(key) => (
+                self.keys
+                    .range((Bound::Excluded(key), Bound::Excluded(LEASED_START))),
+                self.keys.range(..=key),
+            ),
+            None => (
+                self.keys.range(..LEASED_START),
+                self.keys.range(LEASED_START..LEASED_START),
+            ),
+        };
+
+        tail.chain(head).map(|key| key.2)
+    }
+
+    fn leased(&self) -> impl Iterator<Item = u64> + '_ {
+        self.keys.range(LEASED_START..).map(|key| key.2)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// Distinguishes successive queues of a connection, e.g. across purges
+    generation: u64,
+
+    entries: HashMap<u64, Entry>,
+    seqs_by_id: HashMap<String, u64>,
+    next_seq: u64,
+
+    /// Indexes over all messages of the connection, and per recipient
+    all: Index,
+    by_recipient: HashMap<String, Index>,
+
+    /// Expiry of indexed messages
+    expiries: BTreeSet<(i64, u64)>,
+
+    /// Messages held back, by time due
+    held: BTreeMap<(i64, u64), QueuedMessage>,
+}
+
+impl Queue {
+    fn index(&self, recipient_did: Option<&str>) -> Option<&Index> {
+        match recipient_did {
+            None => Some(&self.all),
+            Some(did) => self.by_recipient.get(did),
+        }
+    }
+
+    fn insert(&mut self, seq: u64, entry: Entry) {
+        let key = (entry.status, Reverse(entry.message.priority), seq);
+        let size = entry.message.payload.len() as u64;
+
+        self.all.insert(key, size);
+        self.by_recipient
+            .entry(entry.message.recipient_did.clone())
+            .or_default()
+            .insert(key, size);
+        self.seqs_by_id.insert(entry.message.id.clone(), seq);
+        if let Some(time) = entry.message.expires_time {
+            self.expiries.insert((time, seq));
+        }
+        self.entries.insert(seq, entry);
+    }
+
+    fn remove(&mut self, seq: u64) -> Option<Entry> {
+        let entry = self.entries.remove(&seq)?;
+        let key = (entry.status, Reverse(entry.message.priority), seq);
+        let size = entry.message.payload.len() as u64;
+
+        self.all.remove(&key, size);
+        let recipient_did = &entry.message.recipient_did;
+        if let Some(index) = self.by_recipient.get_mut(recipient_did) {
+            index.remove(&key, size);
+            if index.len() == 0 {
+                self.by_recipient.remove(recipient_did);
+            }
+        }
+        self.seqs_by_id.remove(&entry.message.id);
+        if let Some(time) = entry.message.expires_time {
+            self.expiries.remove(&(time, seq));
+        }
+
+        Some(entry)
+    }
+
+    /// Queues held messages now due, and drops expired messages, returning
+    /// the latter
+    fn maintain(&mut self, now: i64) -> Vec<QueuedMessage> {
+        let mut expired = vec![];
+
+        while let Some(entry) = self.held.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+
+            let ((_, seq), message) = entry.remove_entry();
+            match message.is_expired(now) {
+                true => expired.push(message),
+                false => self.insert(
+                    seq,
+                    Entry {
+                        message,
+                        status: MessageStatus::Queued,
+                        leased_time: 0,
+                    },
+                ),
+            }
+        }
+
+        let due: Vec<_> = self
+            .expiries
+            .iter()
+            .take_while(|(time, _)| *time <= now)
+            .map(|&(_, seq)| seq)
+            .collect();
+        expired.extend(
+            due.into_iter()
+                .filter_map(|seq| self.remove(seq))
+                .map(|entry| entry.message),
+        );
+
+        // Held messages expiring before they are due are never delivered
+        let held: Vec<_> = self
+            .held
+            .iter()
+            .filter(|(_, message)| message.is_expired(now))
+            .map(|(&key, _)| key)
+            .collect();
+        expired.extend(held.iter().filter_map(|key| self.held.remove(key)));
+
+        expired
+    }
+
+    fn set_status(&mut self, seq: u64, status: MessageStatus, now: i64) {
+        if let Some(entry) = self.remove(seq) {
+            let entry = Entry {
+                status,
+                leased_time: now,
+                ..entry
+            };
+            self.insert(seq, entry);
+        }
+    }
+}
+
+/// Totals of the messages that went through queues
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QueueTotals {
+    /// Messages awaiting pickup, delivered or not
+    pub depth: u64,
+
+    /// Messages queued since startup
+    pub enqueued: u64,
+
+    /// Messages delivered since startup, redeliveries included
+    pub delivered: u64,
+}
+
+/// In-memory queues of messages awaiting pickup, per connection.
+///
+/// Messages are indexed per recipient by status, priority and reception
+/// order, so that lookups do not scan the queue of a connection.
+#[derive(Debug, Default)]
+pub struct PickupQueue {
+    config: PickupConfig,
+    queues: Mutex<HashMap<String, Queue>>,
+    tracker: Option<DeliveryTracker>,
+
+    /// Messages dropped on expiry, awaiting [`PickupQueue::expire`]
+    expired: Mutex<Vec<QueuedMessage>>,
+    generations: AtomicU64,
+    enqueued: AtomicU64,
+    delivered: AtomicU64,
+}
+
+impl PickupQueue {
+    pub fn new(config: PickupConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Reports deliveries and acknowledgements to a tracker
+    pub fn with_tracker(self, tracker: DeliveryTracker) -> Self {
+        Self {
+            tracker: Some(tracker),
+            ..self
+        }
+    }
+
+    /// Queues a message for a connection. Messages scheduled for later
+    /// delivery are held back until due.
+    pub fn enqueue(&self, connection: &str, message: QueuedMessage) {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues
+            .entry(connection.to_owned())
+            .or_insert_with(|| Queue {
+                generation: self.generations.fetch_add(1, Ordering::Relaxed),
+                ..Default::default()
+            });
+
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(due) = message.deliver_after {
+            queue.held.insert((due, seq), message);
+            return;
+        }
+        queue.insert(
+            seq,
+            Entry {
+                message,
+                status: MessageStatus::Queued,
+                leased_time: 0,
+            },
+        );
+    }
+
+    /// Drops the expired messages of a queue, to be collected through
+    /// [`PickupQueue::expire`]
+    fn maintain(&self, queue: &mut Queue, now: i64) {
+        let expired = queue.maintain(now);
+        if expired.is_empty() {
+            return;
+        }
+
+        if let Some(tracker) = &self.tracker {
+            let ids = expired.iter().map(|message| message.id.as_str());
+            tracker.advance(ids, DeliveryState::Expired, now);
+        }
+        self.expired.lock().unwrap().extend(expired);
+    }
+
+    /// Drops expired messages across connections, returning them along
+    /// with those dropped since the last call, e.g. to notify their senders
+    pub fn expire(&self, now: i64) -> Vec<QueuedMessage> {
+        let mut queues = self.queues.lock().unwrap();
+        for queue in queues.values_mut() {
+            self.maintain(queue, now);
+        }
+
+        std::mem::take(&mut *self.expired.lock().unwrap())
+    }
+
+    /// Describes the messages awaiting pickup, delivered or not. Messages
+    /// held back are not reported until due.
+    pub fn status(&self, connection: &str, recipient_did: Option<&str>, now: i64) -> StatusBody {
+        let mut queues = self.queues.lock().unwrap();
+        if let Some(queue) = queues.get_mut(connection) {
+            self.maintain(queue, now);
+        }
+        let queue = queues.get(connection);
+        let index = queue.and_then(|queue| queue.index(recipient_did));
+
+        let received_time = |seq: Option<&u64>| {
+            let entry = queue?.entries.get(seq?)?;
+            Some(entry.message.received_time)
+        };
+        let oldest_received_time = received_time(index.and_then(|index| index.seqs.first()));
+
+        StatusBody {
+            recipient_did: recipient_did.map(str::to_owned),
+            message_count: index.map_or(0, Index::len) as u64,
+            longest_waited_seconds: oldest_received_time
+                .map(|t| now.saturating_sub(t).max(0) as u64),
+            newest_received_time: received_time(index.and_then(|index| index.seqs.last())),
+            oldest_received_time,
+            total_bytes: index.map_or(0, |index| index.total_bytes),
+            live_delivery: false,
+        }
+    }
+
+    /// Totals of the messages across connections
+    pub fn totals(&self) -> QueueTotals {
+        let queues = self.queues.lock().unwrap();
+
+        QueueTotals {
+            depth: queues
+                .values()
+                .map(|queue| (queue.all.len() + queue.held.len()) as u64)
+                .sum(),
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            delivered: self.delivered.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Number of messages awaiting pickup by a connection, held back or not
+    pub fn depth(&self, connection: &str) -> u64 {
+        let queues = self.queues.lock().unwrap();
+        queues
+            .get(connection)
+            .map_or(0, |queue| (queue.all.len() + queue.held.len()) as u64)
+    }
+
+    /// Number of messages awaiting pickup, held back or not, per connection
+    pub fn depths(&self) -> BTreeMap<String, u64> {
+        let queues = self.queues.lock().unwrap();
+        queues
+            .iter()
+            .map(|(connection, queue)| {
+                let depth = queue.all.len() + queue.held.len();
+                (connection.clone(), depth as u64)
+            })
+            .collect()
+    }
+
+    /// Lists the messages awaiting pickup, delivered or not, in reception
+    /// order, followed by those held back. They remain queued.
+    pub fn messages(&self, connection: &str) -> Vec<QueuedMessage> {
+        let queues = self.queues.lock().unwrap();
+        let Some(queue) = queues.get(connection) else {
+            return vec![];
+        };
+
+        queue
+            .all
+            .seqs
+            .iter()
+            .map(|seq| queue.entries[seq].message.clone())
+            .chain(queue.held.values().cloned())
+            .collect()
+    }
+
+    /// Leases the next messages to deliver, highest priority first.
+    /// Messages whose lease expired without acknowledgement are delivered
+    /// again. Messages are packed in order as long as they fit within
+    /// limits.
+    pub fn next_batch(
+        &self,
+        connection: &str,
+        recipient_did: Option<&str>,
+        limit: u64,
+        now: i64,
+    ) -> DeliveryBatch {
+        self.next_batch_at(connection, recipient_did, limit, None, now)
+    }
+
+    /// Leases the next messages to deliver, as per
+    /// [`PickupQueue::next_batch`], resuming after the batch a token was
+    /// returned with. Messages preceding it, e.g. of higher priority or
+    /// whose lease expired since, are delivered once the following ones
+    /// are. Tokens of purged queues resume from the start.
+    pub fn resume_batch(
+        &self,
+        connection: &str,
+        recipient_did: Option<&str>,
+        limit: u64,
+        resume_token: &str,
+        now: i64,
+    ) -> Result<DeliveryBatch, PickupError> {
+        let token = ResumeToken::decode(resume_token)?;
+        Ok(self.next_batch_at(connection, recipient_did, limit, Some(token), now))
+    }
+
+    fn next_batch_at(
+        &self,
+        connection: &str,
+        recipient_did: Option<&str>,
+        limit: u64,
+        token: Option<ResumeToken>,
+        now: i64,
+    ) -> DeliveryBatch {
+        let mut queues = self.queues.lock().unwrap();
+        let Some(queue) = queues.get_mut(connection) else {
+            return DeliveryBatch::default();
+        };
+        self.maintain(queue, now);
+
+        // Release expired leases
+        let expired: Vec<_> = queue
+            .index(recipient_did)
+            .into_iter()
+            .flat_map(Index::leased)
+            .filter(|seq| queue.entries[seq].leased_time + self.config.redelivery_after <= now)
+            .collect();
+        for seq in expired {
+            queue.set_status(seq, MessageStatus::Queued, now);
+        }
+
+        let Some(index) = queue.index(recipient_did) else {
+            return DeliveryBatch::default();
+        };
+
+        let cursor = token
+            .filter(|token| token.generation == queue.generation)
+            .map(|token| token.key());
+
+        let limit = limit.min(self.config.max_batch_messages) as usize;
+        let mut selected = vec![];
+        let mut bytes = 0;
+
+        for seq in index.queued_after(cursor).take(limit) {
+            let size = queue.entries[&seq].message.payload.len();
+            if !selected.is_empty() && bytes + size > self.config.max_batch_bytes {
+                break;
+            }
+
+            bytes += size;
+            selected.push(seq);
+        }
+
+        let remaining = (index.len() - index.leased - selected.len()) as u64;
+        let resume_token = selected.last().map(|&seq| {
+            ResumeToken {
+                generation: queue.generation,
+                priority: queue.entries[&seq].message.priority,
+                seq,
+            }
+            .encode()
+        });
+        let messages: Vec<_> = selected
+            .into_iter()
+            .map(|seq| {
+                queue.set_status(seq, MessageStatus::Leased, now);
+                queue.entries[&seq].message.clone()
+            })
+            .collect();
+
+        self.delivered
+            .fetch_add(messages.len() as u64, Ordering::Relaxed);
+
+        if let Some(tracker) = &self.tracker {
+            let ids = messages.iter().map(|message| message.id.as_str());
+            tracker.advance(ids, DeliveryState::Delivered, now);
+        }
+
+        DeliveryBatch {
+            messages,
+            remaining,
+            resume_token,
+        }
+    }
+
+    /// Removes acknowledged messages, returning how many were removed
+    pub fn acknowledge(&self, connection: &str, message_ids: &[String], now: i64) -> usize {
+        let mut queues = self.queues.lock().unwrap();
+        let Some(queue) = queues.get_mut(connection) else {
+            return 0;
+        };
+
+        let seqs: Vec<_> = message_ids
+            .iter()
+            .filter_map(|id| queue.seqs_by_id.get(id).copied())
+            .collect();
+        let removed: Vec<_> = seqs
+            .into_iter()
+            .filter_map(|seq| queue.remove(seq))
+            .collect();
+
+        if let Some(tracker) = &self.tracker {
+            let ids = removed.iter().map(|entry| entry.message.id.as_str());
+            tracker.advance(ids, DeliveryState::Acknowledged, now);
+        }
+
+        removed.len()
+    }
+
+    /// Drops the queue of a connection, returning how many messages it held
+    pub fn purge(&self, connection: &str) -> usize {
+        let mut queues = self.queues.lock().unwrap();
+        queues
+            .remove(connection)
+            .map_or(0, |queue| queue.all.len() + queue.held.len())
+    }
+
+    /// Handles a plaintext pickup message from an authenticated sender,
+    /// returning the response message to send back.
+    #[allow(clippy::result_large_err)]
+    pub fn handle(&self, sender: &str, message: &Value) -> Result<Value, ProblemReport> {
+        self.handle_at(sender, message, Utc::now().timestamp())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn handle_at(&self, sender: &str, message: &Value, now: i64) -> Result<Value, ProblemReport> {
+        MessageValidator::new(message_specs()).check(message)?;
+
+        match message["type"].as_str().unwrap_or_default() {
+            STATUS_REQUEST_3_0 => {
+                let request: StatusRequest = deserialize(message)?;
+                let body = self.status(sender, request.body.recipient_did.as_deref(), now);
+
+                Ok(json!(Status::reply_to(&request, STATUS_3_0, body)))
+            }
+            DELIVERY_REQUEST_3_0 => {
+                let request: DeliveryRequest = deserialize(message)?;
+                let recipient_did = request.body.recipient_did.as_deref();
+                let limit = request.body.limit;
+                let batch = match &request.body.resume_token {
+                    None => self.next_batch(sender, recipient_did, limit, now),
+                    Some(token) => self
+                        .resume_batch(sender, recipient_did, limit, token, now)
+                        .map_err(|err| ProblemReport::from(err).with_pthid(Some(&request.id)))?,
+                };
+
+                // Status is reported in place of empty deliveries
+                if batch.messages.is_empty() {
+                    let body = self.status(sender, recipient_did, now);
+                    return Ok(json!(Status::reply_to(&request, STATUS_3_0, body)));
+                }
+
+                let body = DeliveryBody {
+                    recipient_did: request.body.recipient_did.clone(),
+                    resume_token: batch.resume_token,
+                };
+                let attachments = batch
+                    .messages
+                    .iter()
+                    .map(QueuedMessage::to_attachment)
+                    .collect();
+
+                Ok(json!(
+                    Delivery::reply_to(&request, DELIVERY_3_0, body).with_attachments(attachments)
+                ))
+            }
+            MESSAGES_RECEIVED_3_0 => {
+                let request: MessagesReceived = deserialize(message)?;
+                self.acknowledge(sender, &request.body.message_id_list, now);
+                let body = self.status(sender, None, now);
+
+                Ok(json!(Status::reply_to(&request, STATUS_3_0, body)))
+            }
+            // Responses are not handled by the mediator
+            t => Err(ProblemReport::new(
+                UNSUPPORTED_MESSAGE_CODE,
+                Some("Unsupported message type {1}"),
+                Some(vec![t.to_owned()]),
+            )
+            .with_pthid(message.get("id").and_then(Value::as_str))),
+        }
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn deserialize<B: DeserializeOwned + Default>(
+    message: &Value,
+) -> Result<CoordMessage<B>, ProblemReport> {
+    serde_json::from_value(message.clone()).map_err(|_| {
+        ProblemReport::new(INVALID_MESSAGE_CODE, Some("Malformed message"), None)
+            .with_pthid(message.get("id").and_then(Value::as_str))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+    const ALICE_KEY: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH#key-1";
+    const ALICE_OTHER_KEY: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH#key-2";
+
+    fn message(id: usize, recipient_did: &str, size: usize) -> QueuedMessage {
+        QueuedMessage {
+            id: format!("msg-{id}"),
+            recipient_did: recipient_did.to_owned(),
+            payload: "x".repeat(size),
+            priority: 0,
+            received_time: 1000 + id as i64,
+            ..Default::default()
+        }
+    }
+
+    fn ids(batch: &DeliveryBatch) -> Vec<&str> {
+        batch.messages.iter().map(|m| m.id.as_str()).collect()
+    }
+
+    fn queue() -> PickupQueue {
+        let queue = PickupQueue::new(PickupConfig {
+            max_batch_messages: 3,
+            max_batch_bytes: 100,
+            redelivery_after: 30,
+        });
+
+        for i in 0..5 {
+            queue.enqueue(ALICE, message(i, ALICE_KEY, 40));
+        }
+        queue
+    }
+
+    #[test]
+    fn can_pack_batches_within_limits() {
+        let queue = queue();
+
+        // Two messages of 40 bytes fit within 100 bytes
+        let batch = queue.next_batch(ALICE, None, 10, 2000);
+        assert_eq!(ids(&batch), ["msg-0", "msg-1"]);
+        assert_eq!(batch.remaining, 3);
+
+        // The requested limit applies when lower
+        let queue = self::queue();
+        let batch = queue.next_batch(ALICE, None, 1, 2000);
+        assert_eq!(ids(&batch), ["msg-0"]);
+        assert_eq!(batch.remaining, 4);
+
+        // An oversized message is delivered on its own
+        let queue = PickupQueue::default();
+        queue.enqueue(ALICE, message(0, ALICE_KEY, 2 * 1024 * 1024));
+        queue.enqueue(ALICE, message(1, ALICE_KEY, 10));
+        let batch = queue.next_batch(ALICE, None, 10, 2000);
+        assert_eq!(ids(&batch), ["msg-0"]);
+        assert_eq!(batch.remaining, 1);
+    }
+
+    #[test]
+    fn can_stream_batches_and_redeliver_unacknowledged() {
+        let queue = queue();
+
+        let batch = queue.next_batch(ALICE, None, 10, 2000);
+        assert_eq!(ids(&batch), ["msg-0", "msg-1"]);
+
+        // Subsequent requests resume after the last delivered message
+        let batch = queue.next_batch(ALICE, None, 10, 2010);
+        assert_eq!(ids(&batch), ["msg-2", "msg-3"]);
+        assert_eq!(batch.remaining, 1);
+
+        // Acknowledged messages are removed, whether leased or not
+        let acked = ["msg-0", "msg-1", "msg-2"].map(String::from);
+        assert_eq!(queue.acknowledge(ALICE, &acked, 2020), 3);
+        assert_eq!(queue.status(ALICE, None, 2020).message_count, 2);
+
+        let batch = queue.next_batch(ALICE, None, 10, 2020);
+        assert_eq!(ids(&batch), ["msg-4"]);
+        assert_eq!(batch.remaining, 0);
+
+        // Unacknowledged messages are delivered again once their lease expires
+        assert!(queue.next_batch(ALICE, None, 10, 2030).messages.is_empty());
+        let batch = queue.next_batch(ALICE, None, 10, 2050);
+        assert_eq!(ids(&batch), ["msg-3", "msg-4"]);
+
+        // Redeliveries count towards delivered totals
+        let totals = queue.totals();
+        assert_eq!(
+            totals,
+            QueueTotals {
+                depth: 2,
+                enqueued: 5,
+                delivered: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn can_resume_batches_from_tokens() {
+        let queue = queue();
+
+        let batch = queue.next_batch(ALICE, None, 10, 2000);
+        assert_eq!(ids(&batch), ["msg-0", "msg-1"]);
+        let token = batch.resume_token.unwrap();
+
+        // Expired leases are delivered again once the following messages are
+        let batch = queue.resume_batch(ALICE, None, 10, &token, 2040).unwrap();
+        assert_eq!(ids(&batch), ["msg-2", "msg-3"]);
+        let token = batch.resume_token.unwrap();
+
+        let batch = queue.resume_batch(ALICE, None, 10, &token, 2040).unwrap();
+        assert_eq!(ids(&batch), ["msg-4", "msg-0"]);
+        assert_eq!(batch.remaining, 1);
+
+        // Tokens of a purged queue resume from the start of the next one
+        queue.purge(ALICE);
+        queue.enqueue(ALICE, message(5, ALICE_KEY, 40));
+        queue.enqueue(ALICE, message(6, ALICE_KEY, 40));
+        let batch = queue.resume_batch(ALICE, None, 1, &token, 2040).unwrap();
+        assert_eq!(ids(&batch), ["msg-5"]);
+
+        for token in [
+            "",
+            "nope",
+            &Base64Url.encode("0.256.1"),
+            &Base64Url.encode("0.0.1.2"),
+        ] {
+            let result = queue.resume_batch(ALICE, None, 10, token, 2040);
+            assert_eq!(result, Err(PickupError::InvalidResumeToken), "{token}");
+        }
+    }
+
+    #[test]
+    fn can_deliver_by_priority() {
+        let queue = queue();
+        queue.enqueue(
+            ALICE,
+            QueuedMessage {
+                priority: 1,
+                ..message(5, ALICE_KEY, 10)
+            },
+        );
+
+        let batch = queue.next_batch(ALICE, None, 10, 2000);
+        assert_eq!(ids(&batch), ["msg-5", "msg-0", "msg-1"]);
+
+        // Reception order still drives the reported ages
+        let status = queue.status(ALICE, None, 2000);
+        assert_eq!(status.oldest_received_time, Some(1000));
+        assert_eq!(status.newest_received_time, Some(1005));
+    }
+
+    #[test]
+    fn can_filter_by_recipient() {
+        let queue = queue();
+        queue.enqueue(ALICE, message(5, ALICE_OTHER_KEY, 10));
+
+        let batch = queue.next_batch(ALICE, Some(ALICE_OTHER_KEY), 10, 2000);
+        assert_eq!(ids(&batch), ["msg-5"]);
+
+        let status = queue.status(ALICE, Some(ALICE_KEY), 2000);
+        assert_eq!(status.message_count, 5);
+        assert_eq!(status.total_bytes, 200);
+        assert_eq!(status.oldest_received_time, Some(1000));
+        assert_eq!(status.newest_received_time, Some(1004));
+        assert_eq!(status.longest_waited_seconds, Some(1000));
+
+        assert_eq!(queue.status("did:example:bob", None, 2000).message_count, 0);
+    }
+
+    #[test]
+    fn can_report_deliveries_to_tracker() {
+        let tracker = DeliveryTracker::default();
+        let queue = queue().with_tracker(tracker.clone());
+
+        let forward = json!({"id": "msg-0", "please_ack": [""]});
+        tracker.track("did:example:bob", &forward, 1000);
+
+        let state = |now| tracker.report("did:example:bob", &["msg-0".to_owned()], now)[0].state;
+        assert_eq!(state(1000), DeliveryState::Queued);
+
+        queue.next_batch(ALICE, None, 1, 2000);
+        assert_eq!(state(2000), DeliveryState::Delivered);
+
+        queue.acknowledge(ALICE, &["msg-0".to_owned()], 2010);
+        assert_eq!(state(2010), DeliveryState::Acknowledged);
+    }
+
+    #[test]
+    fn can_handle_pickup_messages() {
+        let queue = PickupQueue::default();
+        queue.enqueue(
+            ALICE,
+            QueuedMessage {
+                id: "msg-0".to_string(),
+                recipient_did: ALICE_KEY.to_string(),
+                payload: r#"{"protected":"eyJ0eXAiOiJKV00ifQ","ciphertext":"..."}"#.to_string(),
+                priority: 0,
+                received_time: 1000,
+                ..Default::default()
+            },
+        );
+
+        let request = json!({
+            "id": "123456780",
+            "type": DELIVERY_REQUEST_3_0,
+            "body": {"limit": 10}
+        });
+
+        let delivery = queue.handle_at(ALICE, &request, 2000).unwrap();
+        assert_eq!(delivery["type"], DELIVERY_3_0);
+        assert_eq!(delivery["thid"], "123456780");
+        assert_eq!(delivery["attachments"][0]["id"], "msg-0");
+        assert_eq!(
+            delivery["attachments"][0]["data"]["json"]["ciphertext"],
+            "..."
+        );
+
+        // Deliveries resume from the token of the previous one
+        let token = delivery["body"]["resume_token"].as_str().unwrap();
+        let resumed = json!({
+            "id": "123456783",
+            "type": DELIVERY_REQUEST_3_0,
+            "body": {"limit": 10, "resume_token": token}
+        });
+        let status = queue.handle_at(ALICE, &resumed, 2001).unwrap();
+        assert_eq!(status["type"], STATUS_3_0);
+
+        let invalid = json!({
+            "id": "123456784",
+            "type": DELIVERY_REQUEST_3_0,
+            "body": {"limit": 10, "resume_token": "?"}
+        });
+        let report = queue.handle_at(ALICE, &invalid, 2001).unwrap_err();
+        assert_eq!(report.body.code, INVALID_MESSAGE_CODE);
+        assert_eq!(report.pthid.as_deref(), Some("123456784"));
+
+        // Nothing left to deliver yields a status
+        let status = queue.handle_at(ALICE, &request, 2001).unwrap();
+        assert_eq!(status["type"], STATUS_3_0);
+        assert_eq!(status["body"]["message_count"], 1);
+
+        let received = json!({
+            "id": "123456781",
+            "type": MESSAGES_RECEIVED_3_0,
+            "body": {"message_id_list": ["msg-0"]}
+        });
+        let status = queue.handle_at(ALICE, &received, 2002).unwrap();
+        assert_eq!(status["body"]["message_count"], 0);
+
+        let invalid = json!({
+            "id": "123456782",
+            "type": DELIVERY_REQUEST_3_0,
+            "body": {}
+        });
+        let report = queue.handle_at(ALICE, &invalid, 2003).unwrap_err();
+        assert_eq!(report.body.code, INVALID_MESSAGE_CODE);
+    }
+
+    /// Measures pickup latency on a large queue. Run with:
+    /// `cargo test --release -p mediator-coordination -- --ignored bench`
+    #[test]
+    #[ignore]
+    fn bench_pickup_with_a_million_queued_messages() {
+        use std::time::Instant;
+
+        let queue = PickupQueue::default();
+        let recipients: Vec<_> = (0..1000).map(|i| format!("{ALICE}#key-{i}")).collect();
+
+        let start = Instant::now();
+        for i in 0..1_000_000 {
+            queue.enqueue(ALICE, message(i, &recipients[i % recipients.len()], 64));
+        }
+        println!("enqueued 1M messages in {:?}", start.elapsed());
+
+        let start = Instant::now();
+        let status = queue.status(ALICE, Some(&recipients[42]), 2000);
+        println!("status in {:?}", start.elapsed());
+        assert_eq!(status.message_count, 1000);
+
+        let start = Instant::now();
+        let batch = queue.next_batch(ALICE, Some(&recipients[42]), 100, 2000);
+        println!(
+            "delivery of {} messages in {:?}",
+            batch.messages.len(),
+            start.elapsed()
+        );
+        assert_eq!(batch.remaining, 900);
+
+        let acked: Vec<_> = batch.messages.iter().map(|m| m.id.clone()).collect();
+        let start = Instant::now();
+        assert_eq!(queue.acknowledge(ALICE, &acked, 2000), 100);
+        println!("acknowledgement in {:?}", start.elapsed());
+    }
+}
@@ -1,11 +1,53 @@
-use crate::{util, web};
+use crate::{
+    archival::{ArchivalConfig, Connections},
+    attestation::DisclosedAttestation,
+    challenge::{ChallengeConfig, PickupChallenges},
+    degradation::LoadShedder,
+    ephemeral::{SessionKeyConfig, SessionKeyRing},
+    failover::{Failover, FailoverConfig},
+    jose::policy::CryptoPolicy,
+    lists::DistributionLists,
+    metrics::{self, PersistentCounters},
+    outbox::Outbox,
+    pickup::PickupQueue,
+    policy::{self, DisclosedPolicy},
+    privacy::{PrivacyConfig, PublicStatistics},
+    repository::MemoryRepository,
+    scheduler::LimitOverrides,
+    subject::DataSubjects,
+    timeseries::QueueTimeSeries,
+    timing::{TimingConfig, TimingPolicies},
+    util, web,
+};
 
 use axum::Router;
 use did_endpoint::{didgen, util::filesystem::StdFileSystem};
-use server_plugin::{Plugin, PluginError};
+use server_plugin::{
+    reload::ReloadableSettings, state::StateMap, tasks::BackgroundTasks, Plugin, PluginError,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, OnceLock,
+};
+use utoipa::{openapi::OpenApi as OpenApiDoc, OpenApi};
 
 #[derive(Default)]
-pub struct MediatorCoordinationPlugin;
+pub struct MediatorCoordinationPlugin {
+    shedder: LoadShedder,
+    counters: PersistentCounters,
+    policy: DisclosedPolicy,
+    attestation: DisclosedAttestation,
+    queue: Arc<PickupQueue>,
+    queue_stats: QueueTimeSeries,
+    failover: OnceLock<Failover>,
+    limits: LimitOverrides,
+    lists: DistributionLists,
+    connections: OnceLock<Connections>,
+    tasks: OnceLock<BackgroundTasks>,
+
+    /// Whether counters were restored, and may be snapshotted
+    snapshotting: AtomicBool,
+}
 
 impl Plugin for MediatorCoordinationPlugin {
     fn name(&self) -> &'static str {
@@ -13,7 +55,7 @@ impl Plugin for MediatorCoordinationPlugin {
     }
 
     fn mount(&self) -> Result<(), PluginError> {
-        let storage_dirpath = std::env::var("STORAGE_DIRPATH").map_err(|_| {
+        let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").ok_or_else(|| {
             tracing::error!("STORAGE_DIRPATH env variable required");
             PluginError::InitError
         })?;
@@ -24,21 +66,243 @@ impl Plugin for MediatorCoordinationPlugin {
             return Err(PluginError::InitError);
         }
 
+        // Invalid crypto policies must not fall back to permissive ones
+        if let Err(err) = CryptoPolicy::from_env() {
+            tracing::error!("{err}");
+            return Err(PluginError::InitError);
+        }
+
         Ok(())
     }
 
     fn unmount(&self) -> Result<(), PluginError> {
+        // Totals since the last periodic snapshot are kept on shutdown
+        if !self.snapshotting.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").unwrap_or_default();
+        let now = chrono::Utc::now().timestamp();
+        if let Err(err) = self
+            .counters
+            .snapshot(&mut StdFileSystem, &storage_dirpath, now)
+        {
+            tracing::error!("failed to snapshot metrics: {err}");
+        }
+
         Ok(())
     }
 
+    fn provide(&self, state: &mut StateMap) {
+        // Periodic snapshots, samples and purges are stopped by the host
+        if let Some(tasks) = state.get::<BackgroundTasks>() {
+            let _ = self.tasks.set((*tasks).clone());
+        }
+
+        // Handlers of other plugins consult the degradation level
+        state.insert(self.shedder.clone());
+
+        // and count protocol events towards persistent totals
+        state.insert(self.counters.clone());
+
+        // Messages forwarded by other plugins are queued for pickup, as
+        // timed by their headers and the policy of their connection
+        state.insert(self.queue.clone());
+        state.insert(TimingPolicies::new(TimingConfig::from_env()));
+
+        // or fanned out to the members of distribution lists
+        state.insert(self.lists.clone());
+
+        // Connections of clients are archived, not deleted, on revocation
+        state.insert(self.connections().clone());
+
+        // and admit messages as per the role of the node
+        state.insert(self.failover().clone());
+
+        // Request schedulers apply the limits overridden by operators
+        state.insert(self.limits.clone());
+
+        // Delivery requests over plain HTTP may require a challenge first
+        state.insert(PickupChallenges::new(ChallengeConfig::from_env()));
+
+        // Live delivery channels pack messages with keys of their own, and
+        // are not opened while live delivery is shed
+        let ring = SessionKeyRing::new(SessionKeyConfig::from_env());
+        state.insert(ring.with_shedder(self.shedder.clone()));
+
+        // The disclosed policy is replaced when reconfigured, along with
+        // the attestation pinning it
+        if let Some(settings) = state.get::<ReloadableSettings>() {
+            let disclosed = self.policy.clone();
+            let attestation = self.attestation.clone();
+            settings.register("MEDIATOR_POLICY", move |value| {
+                reload_policy(&disclosed, &attestation, value)
+            });
+        }
+    }
+
     fn routes(&self) -> Router {
         let msg = "This should not occur following successful mounting.";
-        let storage_dirpath = std::env::var("STORAGE_DIRPATH").expect(msg);
+        let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").expect(msg);
 
         let mut fs = StdFileSystem;
         let diddoc = util::read_diddoc(&fs, &storage_dirpath).expect(msg);
+
+        // Transactions interrupted by a crash are completed first
+        match Outbox::new(&mut fs, &storage_dirpath).recover() {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("recovered {count} interrupted outbox transactions"),
+            Err(err) => tracing::error!("failed to recover outbox transactions: {err}"),
+        }
+        let policy = policy::load_policy(&fs, &storage_dirpath).unwrap_or_else(|err| {
+            tracing::error!("failed to load mediator policy: {err}");
+            None
+        });
+
+        // A snapshot failing to restore is left for operators to recover
+        let tasks = self.tasks.get_or_init(BackgroundTasks::new);
+        match self.counters.restore(&fs, &storage_dirpath) {
+            Ok(_) => {
+                self.snapshotting.store(true, Ordering::Relaxed);
+                let interval = metrics::snapshot_interval_from_env();
+                self.counters
+                    .snapshot_periodically(tasks, &storage_dirpath, interval);
+            }
+            Err(err) => tracing::error!("failed to restore metrics snapshot: {err}"),
+        }
+
+        self.queue_stats
+            .sample_periodically(tasks, self.queue.clone());
+        self.connections()
+            .purge_periodically(tasks, self.queue.clone());
+        self.shedder.sample_periodically(tasks, self.queue.clone());
+
         let keystore = util::read_keystore(&mut fs, &storage_dirpath).expect(msg);
 
-        web::routes(diddoc, keystore)
+        // A policy failing to sign is not disclosed
+        if let Some(policy) = policy {
+            if let Err(err) = self.policy.sign(policy, &diddoc, &keystore) {
+                tracing::error!("failed to sign mediator policy: {err}");
+            }
+        }
+
+        // An attestation failing to sign is not served
+        let disclosed = self.policy.get();
+        if let Err(err) = self
+            .attestation
+            .sign(disclosed.as_ref(), &diddoc, &keystore)
+        {
+            tracing::error!("failed to sign mediator attestation: {err}");
+        }
+
+        let mut routes = web::routes(self.policy.clone())
+            .merge(web::attestation_routes(self.attestation.clone()))
+            .merge(web::health_routes(self.shedder.clone()));
+
+        // Public statistics are opt-in, and noised
+        let stats = PublicStatistics::new(PrivacyConfig::from_env(), self.queue.clone());
+        if stats.is_enabled() {
+            routes = routes.merge(web::public_stats_routes(stats));
+        }
+
+        // Administrative routes are opt-in, and require API keys
+        let admin_enabled =
+            server_plugin::reload::var("ADMIN_API_ENABLED").is_some_and(|v| v == "true");
+        if admin_enabled {
+            routes
+                .merge(web::admin_routes(&storage_dirpath))
+                .merge(web::metrics_routes(self.counters.clone(), &storage_dirpath))
+                .merge(web::stats_routes(
+                    self.queue_stats.clone(),
+                    &storage_dirpath,
+                ))
+                .merge(web::depths_routes(self.queue.clone(), &storage_dirpath))
+                .merge(web::failover_routes(
+                    self.failover().clone(),
+                    self.queue.clone(),
+                    &storage_dirpath,
+                ))
+                .merge(web::limits_routes(self.limits.clone(), &storage_dirpath))
+                .merge(web::lists_routes(self.lists.clone(), &storage_dirpath))
+                .merge(web::connections_routes(
+                    self.connections().clone(),
+                    &storage_dirpath,
+                ))
+                .merge(web::subjects_routes(
+                    DataSubjects::new(
+                        self.connections().clone(),
+                        self.queue.clone(),
+                        self.lists.clone(),
+                    )
+                    .with_failover(self.failover().clone()),
+                    &storage_dirpath,
+                ))
+        } else {
+            routes
+        }
     }
+
+    fn openapi(&self) -> Option<OpenApiDoc> {
+        let mut doc = web::ApiDoc::openapi();
+
+        let admin_enabled =
+            server_plugin::reload::var("ADMIN_API_ENABLED").is_some_and(|v| v == "true");
+        if admin_enabled {
+            doc.merge(web::AdminApiDoc::openapi());
+        }
+
+        Some(doc)
+    }
+
+    fn message_types(&self) -> Vec<&'static str> {
+        // Invitations are published by the out-of-band plugin, not handled
+        protocols_registry::MESSAGE_TYPES
+            .iter()
+            .filter(|t| t.protocol() != protocols_registry::Protocol::OutOfBand)
+            .map(|t| t.as_str())
+            .collect()
+    }
+}
+
+impl MediatorCoordinationPlugin {
+    /// Failover state, as configured at startup
+    fn failover(&self) -> &Failover {
+        self.failover.get_or_init(|| {
+            let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").unwrap_or_default();
+            Failover::new(FailoverConfig::from_env(), &storage_dirpath)
+        })
+    }
+
+    /// Connections of clients, retained as configured at startup
+    fn connections(&self) -> &Connections {
+        self.connections.get_or_init(|| {
+            let repository = Arc::new(MemoryRepository::new());
+            Connections::new(repository, ArchivalConfig::from_env())
+        })
+    }
+}
+
+/// Signs a policy reconfigured at runtime, and discloses it along with an
+/// attestation pinning it
+fn reload_policy(
+    disclosed: &DisclosedPolicy,
+    attestation: &DisclosedAttestation,
+    content: &str,
+) -> Result<(), String> {
+    let policy = policy::parse_policy(content).map_err(|err| err.to_string())?;
+
+    let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").unwrap_or_default();
+    let mut fs = StdFileSystem;
+    let diddoc = util::read_diddoc(&fs, &storage_dirpath)
+        .map_err(|err| format!("failed to read DID document: {err:?}"))?;
+    let keystore = util::read_keystore(&mut fs, &storage_dirpath)
+        .map_err(|err| format!("failed to read keystore: {err}"))?;
+
+    disclosed
+        .sign(policy, &diddoc, &keystore)
+        .map_err(|err| err.to_string())?;
+
+    attestation
+        .sign(disclosed.get().as_ref(), &diddoc, &keystore)
+        .map_err(|err| err.to_string())
 }
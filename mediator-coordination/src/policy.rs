@@ -0,0 +1,358 @@
+//! Disclosure of the mediator policy.
+//!
+//! Operators describe the terms of their service (retention period, quotas,
+//! jurisdiction, pricing) in `policy.json` under the storage directory. The
+//! mediator signs it with its assertion key and serves it over HTTP, and
+//! mediation grants reference it through the `mediator_policy` extension
+//! header, pinning its digest.
+//!
+//! The policy may instead be provided as a JSON document in the
+//! `MEDIATOR_POLICY` setting, which is reloaded at runtime.
+
+use did_endpoint::util::{filesystem::FileSystem, keystore::KeyStore};
+use did_utils::{
+    canonicalization::canonicalize,
+    crypto::{ed25519::Ed25519KeyPair, sha256_hash::sha256_hash},
+    didcore::Document,
+    proof::{
+        eddsa_jcs_2022::{
+            EdDsaJcs2022, CRYPRO_SUITE_EDDSA_JCS_2022, PROOF_TYPE_DATA_INTEGRITY_PROOF,
+        },
+        model::Proof,
+        traits::CryptoProof,
+    },
+};
+use multibase::Base;
+use serde_json::Value;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+use crate::{
+    model::policy::{MediatorPolicy, PolicyReference},
+    util,
+};
+
+/// Path the signed policy is served at
+pub const POLICY_PATH: &str = "/.well-known/didcomm/policy.json";
+
+#[derive(Debug, Error)]
+pub enum PolicyError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("invalid policy: {0}")]
+    ParseError(serde_json::Error),
+    #[error("no assertion key in DID document")]
+    MissingAssertionKey,
+    #[error("assertion key not found in keystore")]
+    MissingSigningKey,
+    #[error("failed to sign policy")]
+    SigningError,
+    #[error("policy not signed by {0}")]
+    InvalidSignature(String),
+    #[error("policy does not match its reference")]
+    DigestMismatch,
+}
+
+/// Signed policy disclosed to clients, replaced when reconfigured.
+/// Clones share the same policy.
+#[derive(Debug, Clone, Default)]
+pub struct DisclosedPolicy(Arc<RwLock<Option<MediatorPolicy>>>);
+
+impl DisclosedPolicy {
+    pub fn get(&self) -> Option<MediatorPolicy> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Signs a policy and discloses it in place of the current one, which
+    /// is kept if signing fails
+    pub fn sign(
+        &self,
+        policy: MediatorPolicy,
+        diddoc: &Document,
+        keystore: &KeyStore,
+    ) -> Result<(), PolicyError> {
+        let signed = sign_policy(policy, diddoc, keystore)?;
+        *self.0.write().unwrap() = Some(signed);
+        Ok(())
+    }
+}
+
+/// Loads the policy configured by the operator, if any.
+pub fn load_policy(
+    fs: &dyn FileSystem,
+    storage_dirpath: &str,
+) -> Result<Option<MediatorPolicy>, PolicyError> {
+    if let Some(content) = server_plugin::reload::var("MEDIATOR_POLICY") {
+        return parse_policy(&content).map(Some);
+    }
+
+    match fs.read_to_string(&format!("{storage_dirpath}/policy.json")) {
+        Ok(content) => parse_policy(&content).map(Some),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(PolicyError::IoError(err)),
+    }
+}
+
+pub fn parse_policy(content: &str) -> Result<MediatorPolicy, PolicyError> {
+    serde_json::from_str(content).map_err(PolicyError::ParseError)
+}
+
+/// Signs a policy on behalf of the mediator, with its assertion key.
+pub fn sign_policy(
+    policy: MediatorPolicy,
+    diddoc: &Document,
+    keystore: &KeyStore,
+) -> Result<MediatorPolicy, PolicyError> {
+    let policy = MediatorPolicy {
+        issuer: diddoc.id.clone(),
+        proof: None,
+        ..policy
+    };
+
+    let payload = serde_json::to_value(&policy).map_err(PolicyError::ParseError)?;
+    let proof = assertion_proof(payload, diddoc, keystore)?;
+
+    Ok(MediatorPolicy {
+        proof: Some(proof),
+        ..policy
+    })
+}
+
+/// Verifies a policy against the DID document of the mediator that
+/// disclosed it and, if any, against the reference found in a grant.
+pub fn verify_policy(
+    policy: &MediatorPolicy,
+    diddoc: &Document,
+    reference: Option<&PolicyReference>,
+) -> Result<(), PolicyError> {
+    if policy.issuer != diddoc.id {
+        return Err(PolicyError::InvalidSignature(diddoc.id.clone()));
+    }
+
+    let payload = serde_json::to_value(policy).map_err(PolicyError::ParseError)?;
+    verify_assertion_proof(payload, policy.proof.clone(), diddoc)?;
+
+    match reference {
+        Some(reference) if reference.digest != policy_digest(policy)? => {
+            Err(PolicyError::DigestMismatch)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Proof of a statement made by the mediator, with its assertion key
+pub(crate) fn assertion_proof(
+    payload: Value,
+    diddoc: &Document,
+    keystore: &KeyStore,
+) -> Result<Proof, PolicyError> {
+    let (vm_id, pubkey) =
+        util::extract_assertion_key(diddoc).ok_or(PolicyError::MissingAssertionKey)?;
+    let jwk = keystore
+        .find_keypair(&pubkey)
+        .ok_or(PolicyError::MissingSigningKey)?;
+
+    let prover = EdDsaJcs2022 {
+        proof: proof_options(vm_id),
+        key_pair: jwk.try_into().map_err(|_| PolicyError::SigningError)?,
+        proof_value_codec: Some(Base::Base58Btc),
+    };
+
+    prover.proof(payload).map_err(|_| PolicyError::SigningError)
+}
+
+/// Verifies a statement against the assertion key of the mediator that
+/// made it
+pub(crate) fn verify_assertion_proof(
+    payload: Value,
+    proof: Option<Proof>,
+    diddoc: &Document,
+) -> Result<(), PolicyError> {
+    let invalid_signature = || PolicyError::InvalidSignature(diddoc.id.clone());
+
+    let proof = proof.ok_or_else(invalid_signature)?;
+    let (vm_id, pubkey) =
+        util::extract_assertion_key(diddoc).ok_or(PolicyError::MissingAssertionKey)?;
+    if proof.verification_method != vm_id {
+        return Err(invalid_signature());
+    }
+
+    let key_pair: Ed25519KeyPair = pubkey.try_into().map_err(|_| invalid_signature())?;
+    let verifier = EdDsaJcs2022 {
+        proof,
+        key_pair,
+        proof_value_codec: None,
+    };
+
+    verifier.verify(payload).map_err(|_| invalid_signature())
+}
+
+/// Computes the digest of a signed policy, as carried by references.
+pub fn policy_digest(policy: &MediatorPolicy) -> Result<String, PolicyError> {
+    let canonical = canonicalize(policy).map_err(|_| PolicyError::SigningError)?;
+    Ok(multibase::encode(
+        Base::Base58Btc,
+        sha256_hash(canonical.as_bytes()),
+    ))
+}
+
+impl PolicyReference {
+    /// References a signed policy served at a URL
+    pub fn new(url: &str, policy: &MediatorPolicy) -> Result<Self, PolicyError> {
+        Ok(Self {
+            url: url.to_owned(),
+            digest: policy_digest(policy)?,
+        })
+    }
+}
+
+pub(crate) fn proof_options(verification_method: String) -> Proof {
+    Proof {
+        id: None,
+        proof_type: PROOF_TYPE_DATA_INTEGRITY_PROOF.to_owned(),
+        cryptosuite: Some(CRYPRO_SUITE_EDDSA_JCS_2022.to_owned()),
+        proof_purpose: String::from("assertionMethod"),
+        verification_method,
+        created: None,
+        expires: None,
+        domain: None,
+        challenge: None,
+        proof_value: None,
+        previous_proof: None,
+        nonce: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::*,
+        model::coord::{MediateGrant, MediateGrantBody, MediateRequest},
+        storage::StorageQuota,
+        util::mock_fs,
+    };
+    use serde_json::json;
+
+    fn policy() -> MediatorPolicy {
+        serde_json::from_value(json!({
+            "retentionPeriod": 2592000,
+            "jurisdiction": "DE",
+            "pricing": "free",
+            "termsOfService": "https://mediator.example/tos",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn can_sign_and_verify_policy() {
+        let mut mock_fs = mock_fs();
+        let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+
+        let signed = sign_policy(policy(), &diddoc, &keystore).unwrap();
+        assert_eq!(signed.issuer, diddoc.id);
+        assert!(signed.proof.is_some());
+        verify_policy(&signed, &diddoc, None).unwrap();
+
+        // Altered terms do not verify
+        let altered = MediatorPolicy {
+            retention_period: 365 * 24 * 3600,
+            ..signed.clone()
+        };
+        assert!(matches!(
+            verify_policy(&altered, &diddoc, None),
+            Err(PolicyError::InvalidSignature(_))
+        ));
+
+        // Nor do policies signed on behalf of another mediator
+        let mut other = diddoc.clone();
+        other.id = String::from("did:web:another.example");
+        assert!(matches!(
+            verify_policy(&signed, &other, None),
+            Err(PolicyError::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn can_reference_policy_from_grant() {
+        let mut mock_fs = mock_fs();
+        let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+
+        let signed = sign_policy(policy(), &diddoc, &keystore).unwrap();
+        let url = format!("https://mediator.example{POLICY_PATH}");
+        let reference = PolicyReference::new(&url, &signed).unwrap();
+
+        let request = MediateRequest::new(MEDIATE_REQUEST_2_0, Default::default());
+        let grant = MediateGrant::reply_to(
+            &request,
+            MEDIATE_GRANT_2_0,
+            MediateGrantBody {
+                routing_did: vec![diddoc.id.clone()],
+            },
+        )
+        .with_mediator_policy(reference.clone());
+
+        let grant = json!(grant);
+        assert_eq!(grant["mediator_policy"]["url"], url);
+
+        let grant: MediateGrant = serde_json::from_value(grant).unwrap();
+        let reference = grant.mediator_policy.unwrap();
+        verify_policy(&signed, &diddoc, Some(&reference)).unwrap();
+
+        // Policies updated since the grant do not match its reference
+        let updated = sign_policy(
+            MediatorPolicy {
+                quotas: (&StorageQuota::default()).into(),
+                ..policy()
+            },
+            &diddoc,
+            &keystore,
+        )
+        .unwrap();
+        assert!(matches!(
+            verify_policy(&updated, &diddoc, Some(&reference)),
+            Err(PolicyError::DigestMismatch)
+        ));
+    }
+
+    #[test]
+    fn can_replace_disclosed_policy() {
+        let mut mock_fs = mock_fs();
+        let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+
+        let disclosed = DisclosedPolicy::default();
+        assert_eq!(disclosed.get(), None);
+
+        disclosed
+            .clone()
+            .sign(policy(), &diddoc, &keystore)
+            .unwrap();
+        verify_policy(&disclosed.get().unwrap(), &diddoc, None).unwrap();
+
+        let reconfigured = parse_policy(r#"{"retentionPeriod": 3600, "jurisdiction": "FR"}"#);
+        disclosed
+            .sign(reconfigured.unwrap(), &diddoc, &keystore)
+            .unwrap();
+        assert_eq!(disclosed.get().unwrap().jurisdiction.as_deref(), Some("FR"));
+
+        // Policies failing to sign leave the disclosed one in place
+        let mut unsigning = diddoc.clone();
+        unsigning.assertion_method = None;
+        assert!(matches!(
+            disclosed.sign(policy(), &unsigning, &keystore),
+            Err(PolicyError::MissingAssertionKey)
+        ));
+        assert_eq!(disclosed.get().unwrap().jurisdiction.as_deref(), Some("FR"));
+
+        assert!(matches!(parse_policy("{"), Err(PolicyError::ParseError(_))));
+    }
+
+    #[test]
+    fn can_load_configured_policy() {
+        let mock_fs = mock_fs();
+        assert_eq!(load_policy(&mock_fs, "").unwrap(), None);
+    }
+}
@@ -0,0 +1,315 @@
+//! Differentially private statistics, for public exposure.
+//!
+//! On small deployments, exact statistics disclose the activity of
+//! individual users, e.g. that a given recipient just received messages.
+//! Public statistics are therefore aggregated over connections into
+//! buckets, and noised with the Laplace mechanism, so that any one
+//! connection barely affects what is published. Exact per-connection
+//! values remain available to operators through the admin API only.
+//!
+//! Each publication spends the configured privacy budget `epsilon`, split
+//! between the histogram of queue depths and the total of queued messages.
+//! Statistics are published at most once per interval, so that averaging
+//! successive publications does not wash out the noise.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+use utoipa::ToSchema;
+
+use crate::pickup::PickupQueue;
+
+/// Path the noised statistics are served at, when enabled
+pub const PUBLIC_STATS_PATH: &str = "/stats";
+
+/// Lower bounds of the buckets of queue depths
+pub const DEPTH_BUCKETS: [u64; 5] = [0, 1, 10, 100, 1000];
+
+/// Messages a single connection contributes to the published total, at
+/// most, bounding the sensitivity of the total
+pub const MAX_CONTRIBUTION: u64 = 1000;
+
+/// Most a single connection changes the histogram of queue depths by, in
+/// L1 distance: a connection whose queue grows or shrinks leaves one
+/// bucket for another, changing two counts by one
+pub const HISTOGRAM_SENSITIVITY: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrivacyConfig {
+    /// Whether noised statistics are published
+    pub enabled: bool,
+
+    /// Privacy budget spent per publication, lower values adding more noise
+    pub epsilon: f64,
+
+    /// Seconds a publication is served for before being renewed
+    pub interval: i64,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            epsilon: 1.0,
+            interval: 3600,
+        }
+    }
+}
+
+impl PrivacyConfig {
+    /// Configuration from `PUBLIC_STATS_ENABLED`, `PUBLIC_STATS_EPSILON`
+    /// and `PUBLIC_STATS_INTERVAL_SECS`
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let epsilon = server_plugin::reload::var("PUBLIC_STATS_EPSILON")
+            .and_then(|v| v.parse().ok())
+            .filter(|&epsilon: &f64| epsilon.is_finite() && epsilon > 0.0);
+        let interval = server_plugin::reload::var("PUBLIC_STATS_INTERVAL_SECS")
+            .and_then(|v| v.parse().ok())
+            .filter(|&secs: &i64| secs > 0);
+
+        Self {
+            enabled: server_plugin::reload::var("PUBLIC_STATS_ENABLED")
+                .is_some_and(|v| v == "true"),
+            epsilon: epsilon.unwrap_or(default.epsilon),
+            interval: interval.unwrap_or(default.interval),
+        }
+    }
+}
+
+/// Connections whose queue depth falls within a range
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct DepthBucket {
+    /// Lower bound of the range, inclusive
+    pub min: u64,
+
+    /// Upper bound of the range, exclusive, unbounded if absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<u64>,
+
+    /// Noised number of connections
+    pub connections: u64,
+}
+
+/// Noised statistics, fit for public exposure
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct PublicStats {
+    /// Noised number of connections with a queue
+    pub connections: u64,
+
+    /// Noised number of messages awaiting pickup
+    pub queued_messages: u64,
+
+    /// Noised histogram of queue depths
+    pub queue_depths: Vec<DepthBucket>,
+
+    /// Privacy budget spent on the publication
+    pub epsilon: f64,
+
+    /// Time of the publication, as a UNIX timestamp
+    pub published_time: i64,
+}
+
+/// Samples the Laplace distribution centered on zero
+pub fn laplace(rng: &mut impl Rng, scale: f64) -> f64 {
+    // Inverse of the cumulative distribution function, u in (-1/2, 1/2)
+    let u: f64 = rng.gen_range(-0.5..0.5);
+    let p = (1.0 - 2.0 * u.abs()).max(f64::MIN_POSITIVE);
+    -scale * u.signum() * p.ln()
+}
+
+/// Noises a count with the Laplace mechanism, for a privacy budget and the
+/// most a single connection may change the count by
+pub fn noised_count(rng: &mut impl Rng, count: u64, sensitivity: f64, epsilon: f64) -> u64 {
+    let noised = count as f64 + laplace(rng, sensitivity / epsilon);
+    noised.round().max(0.0) as u64
+}
+
+/// Histogram of queue depths, exact
+pub fn depth_histogram(depths: &BTreeMap<String, u64>) -> Vec<u64> {
+    let mut histogram = vec![0; DEPTH_BUCKETS.len()];
+    for &depth in depths.values() {
+        let bucket = DEPTH_BUCKETS.iter().rposition(|&min| depth >= min);
+        histogram[bucket.unwrap_or(0)] += 1;
+    }
+
+    histogram
+}
+
+/// Publisher of noised statistics about pickup queues. Clones share the
+/// same publication.
+#[derive(Clone)]
+pub struct PublicStatistics {
+    config: PrivacyConfig,
+    queue: Arc<PickupQueue>,
+    published: Arc<Mutex<Option<PublicStats>>>,
+}
+
+impl PublicStatistics {
+    pub fn new(config: PrivacyConfig, queue: Arc<PickupQueue>) -> Self {
+        Self {
+            config,
+            queue,
+            published: Arc::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Current publication, renewed once its interval elapsed
+    pub fn get(&self, rng: &mut impl Rng, now: i64) -> PublicStats {
+        let mut published = self.published.lock().unwrap();
+        match &*published {
+            Some(stats) if now < stats.published_time + self.config.interval => stats.clone(),
+            _ => published.insert(self.publish(rng, now)).clone(),
+        }
+    }
+
+    fn publish(&self, rng: &mut impl Rng, now: i64) -> PublicStats {
+        let depths = self.queue.depths();
+        let epsilon = self.config.epsilon / 2.0;
+
+        // Buckets are disjoint, so that a connection moving between them
+        // changes two counts by one, and the histogram is noised with half
+        // the budget
+        let histogram = depth_histogram(&depths);
+        let queue_depths: Vec<_> = histogram
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| DepthBucket {
+                min: DEPTH_BUCKETS[i],
+                max: DEPTH_BUCKETS.get(i + 1).copied(),
+                connections: noised_count(rng, count, HISTOGRAM_SENSITIVITY, epsilon),
+            })
+            .collect();
+
+        let total = depths.values().map(|&d| d.min(MAX_CONTRIBUTION)).sum();
+        let queued_messages = noised_count(rng, total, MAX_CONTRIBUTION as f64, epsilon);
+
+        PublicStats {
+            connections: queue_depths.iter().map(|bucket| bucket.connections).sum(),
+            queued_messages,
+            queue_depths,
+            epsilon: self.config.epsilon,
+            published_time: now,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pickup::QueuedMessage;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn queue(depths: &[(&str, usize)]) -> Arc<PickupQueue> {
+        let queue = PickupQueue::default();
+        for &(connection, depth) in depths {
+            for i in 0..depth {
+                let message = QueuedMessage {
+                    id: format!("{connection}-{i}"),
+                    recipient_did: connection.to_owned(),
+                    ..Default::default()
+                };
+                queue.enqueue(connection, message);
+            }
+        }
+
+        Arc::new(queue)
+    }
+
+    #[test]
+    fn test_laplace_distribution() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let samples: Vec<_> = (0..100_000).map(|_| laplace(&mut rng, 2.0)).collect();
+
+        // Centered on zero, with a variance of twice the squared scale
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        assert!(mean.abs() < 0.05, "mean {mean}");
+        assert!((variance - 8.0).abs() < 0.4, "variance {variance}");
+    }
+
+    #[test]
+    fn test_depth_histogram() {
+        let depths = BTreeMap::from([
+            ("did:example:alice".to_owned(), 0),
+            ("did:example:bob".to_owned(), 3),
+            ("did:example:carol".to_owned(), 10),
+            ("did:example:dave".to_owned(), 5000),
+        ]);
+
+        assert_eq!(depth_histogram(&depths), [1, 1, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_histogram_sensitivity() {
+        let depths = BTreeMap::from([
+            ("did:example:alice".to_owned(), 0),
+            ("did:example:bob".to_owned(), 3),
+        ]);
+        let l1 = |other: &BTreeMap<String, u64>| -> u64 {
+            let (a, b) = (depth_histogram(&depths), depth_histogram(other));
+            a.iter().zip(&b).map(|(x, y)| x.abs_diff(*y)).sum()
+        };
+
+        // A connection whose queue changes moves between buckets
+        let mut changed = depths.clone();
+        changed.insert("did:example:bob".to_owned(), 500);
+        assert_eq!(l1(&changed) as f64, HISTOGRAM_SENSITIVITY);
+
+        // while one added or removed changes a single bucket
+        let mut added = depths.clone();
+        added.insert("did:example:carol".to_owned(), 500);
+        assert!(l1(&added) as f64 <= HISTOGRAM_SENSITIVITY);
+    }
+
+    #[test]
+    fn should_noise_published_statistics() {
+        let queue = queue(&[("did:example:alice", 3), ("did:example:bob", 12)]);
+        let config = PrivacyConfig {
+            enabled: true,
+            epsilon: 0.1,
+            interval: 3600,
+        };
+        let stats = PublicStatistics::new(config, queue.clone());
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let published = stats.get(&mut rng, 1000);
+        assert_eq!(published.queue_depths.len(), DEPTH_BUCKETS.len());
+        assert_eq!(published.queue_depths[4].max, None);
+        assert_ne!((published.connections, published.queued_messages), (2, 15));
+
+        // Publications are renewed once per interval only
+        queue.enqueue("did:example:carol", QueuedMessage::default());
+        assert_eq!(stats.get(&mut rng, 4599), published);
+        assert_eq!(stats.get(&mut rng, 4600).published_time, 4600);
+    }
+
+    #[test]
+    fn should_converge_to_exact_values_with_a_large_budget() {
+        let queue = queue(&[("did:example:alice", 3), ("did:example:bob", 12)]);
+        let config = PrivacyConfig {
+            enabled: true,
+            epsilon: 1e9,
+            interval: 3600,
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let published = PublicStatistics::new(config, queue).get(&mut rng, 1000);
+        assert_eq!(published.connections, 2);
+        assert_eq!(published.queued_messages, 15);
+        let counts: Vec<_> = published
+            .queue_depths
+            .iter()
+            .map(|b| b.connections)
+            .collect();
+        assert_eq!(counts, [0, 1, 1, 0, 0]);
+    }
+}
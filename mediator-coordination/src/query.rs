@@ -0,0 +1,235 @@
+//! Typed queries over stored records.
+//!
+//! Handlers express the records they are after as a [`Filter`] on the
+//! serialized fields of records, e.g.
+//! `Filter::eq("connection", did).and(Filter::gt("period_end", from))`,
+//! rather than in the query language of a storage backend. Backends
+//! translate filters into their own queries by walking them, while records
+//! held as JSON, as by the file-based stores of this crate, are matched
+//! with [`Filter::matches`].
+
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// Comparison of a field against a value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+/// Condition on records, over fields named by their serialized name.
+/// Nested fields are named by their dot-separated path, e.g. `body.key`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Matches all records
+    All,
+    Compare {
+        field: String,
+        comparison: Comparison,
+        value: Value,
+    },
+    /// Matches records whose field equals any of the values
+    In {
+        field: String,
+        values: Vec<Value>,
+    },
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn all() -> Self {
+        Self::All
+    }
+
+    pub fn compare(field: &str, comparison: Comparison, value: impl Into<Value>) -> Self {
+        Self::Compare {
+            field: field.to_owned(),
+            comparison,
+            value: value.into(),
+        }
+    }
+
+    pub fn eq(field: &str, value: impl Into<Value>) -> Self {
+        Self::compare(field, Comparison::Eq, value)
+    }
+
+    pub fn ne(field: &str, value: impl Into<Value>) -> Self {
+        Self::compare(field, Comparison::Ne, value)
+    }
+
+    pub fn gt(field: &str, value: impl Into<Value>) -> Self {
+        Self::compare(field, Comparison::Gt, value)
+    }
+
+    pub fn gte(field: &str, value: impl Into<Value>) -> Self {
+        Self::compare(field, Comparison::Gte, value)
+    }
+
+    pub fn lt(field: &str, value: impl Into<Value>) -> Self {
+        Self::compare(field, Comparison::Lt, value)
+    }
+
+    pub fn lte(field: &str, value: impl Into<Value>) -> Self {
+        Self::compare(field, Comparison::Lte, value)
+    }
+
+    pub fn is_in<V: Into<Value>>(field: &str, values: impl IntoIterator<Item = V>) -> Self {
+        Self::In {
+            field: field.to_owned(),
+            values: values.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Matches records matching both filters
+    pub fn and(self, other: Filter) -> Self {
+        match (self, other) {
+            (Self::All, filter) | (filter, Self::All) => filter,
+            (Self::And(mut filters), Self::And(others)) => {
+                filters.extend(others);
+                Self::And(filters)
+            }
+            (Self::And(mut filters), filter) => {
+                filters.push(filter);
+                Self::And(filters)
+            }
+            (filter, other) => Self::And(vec![filter, other]),
+        }
+    }
+
+    /// Matches records matching either filter
+    pub fn or(self, other: Filter) -> Self {
+        match (self, other) {
+            (Self::Or(mut filters), Self::Or(others)) => {
+                filters.extend(others);
+                Self::Or(filters)
+            }
+            (Self::Or(mut filters), filter) => {
+                filters.push(filter);
+                Self::Or(filters)
+            }
+            (filter, other) => Self::Or(vec![filter, other]),
+        }
+    }
+
+    /// Matches records not matching the filter
+    pub fn negate(self) -> Self {
+        match self {
+            Self::Not(filter) => *filter,
+            filter => Self::Not(Box::new(filter)),
+        }
+    }
+
+    /// Whether a record, serialized as JSON, matches the filter. Missing
+    /// fields match no comparison, except inequality.
+    pub fn matches(&self, record: &Value) -> bool {
+        match self {
+            Self::All => true,
+            Self::Compare {
+                field,
+                comparison,
+                value,
+            } => {
+                let Some(actual) = lookup(record, field) else {
+                    return *comparison == Comparison::Ne;
+                };
+
+                match comparison {
+                    Comparison::Eq => equals(actual, value),
+                    Comparison::Ne => !equals(actual, value),
+                    Comparison::Gt => order(actual, value) == Some(Ordering::Greater),
+                    Comparison::Gte => order(actual, value).is_some_and(Ordering::is_ge),
+                    Comparison::Lt => order(actual, value) == Some(Ordering::Less),
+                    Comparison::Lte => order(actual, value).is_some_and(Ordering::is_le),
+                }
+            }
+            Self::In { field, values } => lookup(record, field)
+                .is_some_and(|actual| values.iter().any(|value| equals(actual, value))),
+            Self::And(filters) => filters.iter().all(|filter| filter.matches(record)),
+            Self::Or(filters) => filters.iter().any(|filter| filter.matches(record)),
+            Self::Not(filter) => !filter.matches(record),
+        }
+    }
+}
+
+fn lookup<'a>(record: &'a Value, field: &str) -> Option<&'a Value> {
+    field
+        .split('.')
+        .try_fold(record, |value, name| value.get(name))
+}
+
+// Numbers compare by value, whether integers or floats
+fn equals(actual: &Value, value: &Value) -> bool {
+    match (actual.as_f64(), value.as_f64()) {
+        (Some(actual), Some(value)) => actual == value,
+        _ => actual == value,
+    }
+}
+
+fn order(actual: &Value, value: &Value) -> Option<Ordering> {
+    match (actual, value) {
+        (Value::Number(actual), Value::Number(value)) => {
+            actual.as_f64()?.partial_cmp(&value.as_f64()?)
+        }
+        (Value::String(actual), Value::String(value)) => Some(actual.cmp(value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record() -> Value {
+        json!({
+            "connection": "did:key:alice",
+            "period_end": 1700000000,
+            "bytes": 2048.0,
+            "body": {"key": "avatar"},
+        })
+    }
+
+    #[test]
+    fn test_matching_comparisons() {
+        let record = record();
+
+        assert!(Filter::all().matches(&record));
+        assert!(Filter::eq("connection", "did:key:alice").matches(&record));
+        assert!(Filter::ne("connection", "did:key:bob").matches(&record));
+        assert!(Filter::eq("bytes", 2048).matches(&record));
+        assert!(Filter::gt("period_end", 1600000000).matches(&record));
+        assert!(Filter::lte("period_end", 1700000000).matches(&record));
+        assert!(!Filter::lt("period_end", 1700000000).matches(&record));
+        assert!(Filter::gte("connection", "did:key:a").matches(&record));
+        assert!(Filter::eq("body.key", "avatar").matches(&record));
+        assert!(Filter::is_in("body.key", ["avatar", "banner"]).matches(&record));
+
+        // Missing fields and mismatched types do not compare
+        assert!(!Filter::eq("messages", 0).matches(&record));
+        assert!(Filter::ne("messages", 0).matches(&record));
+        assert!(!Filter::gt("connection", 0).matches(&record));
+    }
+
+    #[test]
+    fn test_combining_filters() {
+        let record = record();
+        let alice = Filter::eq("connection", "did:key:alice");
+        let recent = Filter::gt("period_end", 1800000000);
+
+        assert_eq!(Filter::all().and(alice.clone()), alice);
+        assert!(!alice.clone().and(recent.clone()).matches(&record));
+        assert!(alice.clone().or(recent.clone()).matches(&record));
+        assert!(recent.clone().negate().matches(&record));
+        assert_eq!(recent.clone().negate().negate(), recent);
+
+        let filter = alice.clone().and(recent.clone()).and(Filter::all());
+        assert_eq!(filter, Filter::And(vec![alice, recent]));
+    }
+}
@@ -0,0 +1,189 @@
+//! Replay of captured traffic, to reproduce incidents.
+//!
+//! Captures are the JSON files of a directory, each holding a plaintext
+//! message as unpacked by the mediator, its authenticated sender and,
+//! optionally, the response expected back, e.g.:
+//!
+//! ```json
+//! {
+//!   "sender": "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH",
+//!   "message": {
+//!     "id": "1",
+//!     "type": "https://didcomm.org/coordinate-mediation/2.0/mediate-request",
+//!     "body": {}
+//!   },
+//!   "expected": {
+//!     "type": "https://didcomm.org/coordinate-mediation/2.0/mediate-grant",
+//!     "thid": "1"
+//!   }
+//! }
+//! ```
+//!
+//! Captures are replayed in the order of their file names against a
+//! [`LocalMediator`], whose state they build up. Expected responses are
+//! patterns: objects match responses holding at least their fields, so
+//! that fields varying between runs, such as `id` or `created_time`, are
+//! left out. Problem reports are matched as any other response.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use thiserror::Error;
+
+use crate::local::LocalMediator;
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("malformed capture {0}: {1}")]
+    Malformed(String, serde_json::Error),
+}
+
+/// Message captured from a sender, and the response expected, if checked
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Capture {
+    /// File name of the capture
+    #[serde(skip)]
+    pub name: String,
+    pub sender: String,
+    pub message: Value,
+    #[serde(default)]
+    pub expected: Option<Value>,
+}
+
+/// Outcome of replaying a capture
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The response matched the expected one
+    Matched,
+    /// No response was expected
+    Unchecked,
+    /// The response, if any, did not match the expected one
+    Mismatched {
+        expected: Value,
+        actual: Option<Value>,
+    },
+}
+
+/// Outcome of replaying a capture, by file name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replayed {
+    pub name: String,
+    pub response: Option<Value>,
+    pub outcome: Outcome,
+}
+
+/// Reads the captures of a directory, ordered by file name
+pub fn load_captures(dirpath: &Path) -> Result<Vec<Capture>, ReplayError> {
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(dirpath).map_err(ReplayError::IoError)? {
+        let path = entry.map_err(ReplayError::IoError)?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let content = std::fs::read_to_string(&path).map_err(ReplayError::IoError)?;
+            let capture: Capture = serde_json::from_str(&content)
+                .map_err(|err| ReplayError::Malformed(name.to_string(), err))?;
+
+            Ok(Capture {
+                name: name.into_owned(),
+                ..capture
+            })
+        })
+        .collect()
+}
+
+/// Replays captures in order, checking responses against expected ones
+pub fn replay(mediator: &LocalMediator, captures: &[Capture]) -> Vec<Replayed> {
+    captures
+        .iter()
+        .map(|capture| {
+            let response = match mediator.handle(&capture.sender, &capture.message) {
+                Ok(response) => response,
+                Err(report) => Some(json!(report)),
+            };
+
+            let outcome = match &capture.expected {
+                None => Outcome::Unchecked,
+                Some(expected) if response.as_ref().is_some_and(|r| matches(expected, r)) => {
+                    Outcome::Matched
+                }
+                Some(expected) => Outcome::Mismatched {
+                    expected: expected.clone(),
+                    actual: response.clone(),
+                },
+            };
+
+            Replayed {
+                name: capture.name.clone(),
+                response,
+                outcome,
+            }
+        })
+        .collect()
+}
+
+/// Whether a value matches a pattern: objects hold at least the fields of
+/// the pattern, matching theirs, and other values are equal
+pub fn matches(pattern: &Value, actual: &Value) -> bool {
+    match (pattern, actual) {
+        (Value::Object(pattern), Value::Object(actual)) => pattern
+            .iter()
+            .all(|(key, value)| actual.get(key).is_some_and(|v| matches(value, v))),
+        (Value::Array(pattern), Value::Array(actual)) => {
+            pattern.len() == actual.len() && pattern.iter().zip(actual).all(|(p, a)| matches(p, a))
+        }
+        _ => pattern == actual,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn can_match_patterns() {
+        let response =
+            json!({"id": "2", "thid": "1", "body": {"n": 1, "list": [{"a": 1, "b": 2}]}});
+
+        assert!(matches(&json!({"thid": "1"}), &response));
+        assert!(matches(&json!({"body": {"list": [{"a": 1}]}}), &response));
+        assert!(!matches(&json!({"thid": "2"}), &response));
+        assert!(!matches(&json!({"body": {"list": []}}), &response));
+        assert!(!matches(&json!({"pthid": null}), &response));
+    }
+
+    #[test]
+    fn can_replay_captured_traffic() {
+        let dirpath = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test/replay");
+        let captures = load_captures(&dirpath).unwrap();
+        assert_eq!(captures[0].name, "01-mediate-request.json");
+
+        let mediator = LocalMediator::new("did:web:mediators-r-us.com");
+        let replayed = replay(&mediator, &captures);
+        let outcomes: Vec<_> = replayed.iter().map(|r| r.outcome.clone()).collect();
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Matched,
+                Outcome::Matched,
+                Outcome::Unchecked,
+                Outcome::Matched
+            ]
+        );
+        assert_eq!(replayed[2].response, None);
+
+        // Replaying again against the same state yields other responses
+        let replayed = replay(&mediator, &captures);
+        assert!(matches!(replayed[1].outcome, Outcome::Mismatched { .. }));
+    }
+}
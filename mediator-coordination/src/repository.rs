@@ -0,0 +1,323 @@
+//! Repositories of versioned entities, with optimistic concurrency.
+//!
+//! Entities carry a version, bumped on every update. Updates are
+//! compare-and-swap: they apply only if the stored entity still has the
+//! version the update was derived from, and fail with
+//! [`RepositoryError::Conflict`] otherwise, rather than clobbering a
+//! concurrent update. Callers then re-read the entity and apply their
+//! change again, e.g. with [`update_with_retry`], or report the conflict.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+use crate::{
+    didcomm::problem_report::ProblemReport,
+    model::{connection::Connection, storage::StoredBlobs},
+};
+
+/// Problem code for updates conflicting with concurrent ones
+pub const CONFLICT_CODE: &str = "e.p.req.conflict";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum RepositoryError {
+    #[error("no entity {0}")]
+    NotFound(String),
+    #[error("entity {0} already exists")]
+    AlreadyExists(String),
+    #[error("entity {id} was updated concurrently, version {current} is not {expected}")]
+    Conflict {
+        id: String,
+        expected: u64,
+        current: u64,
+    },
+}
+
+impl RepositoryError {
+    pub fn to_problem_report(&self) -> ProblemReport {
+        match self {
+            Self::Conflict { id, .. } => ProblemReport::new(
+                CONFLICT_CODE,
+                Some("Concurrent update of {1}, please retry"),
+                Some(vec![id.clone()]),
+            ),
+            err => ProblemReport::new(
+                "e.p.req",
+                Some("Failed to update record: {1}"),
+                Some(vec![err.to_string()]),
+            ),
+        }
+    }
+}
+
+/// Entity identified and versioned
+pub trait Entity: Clone + Send {
+    fn id(&self) -> &str;
+
+    /// Version the entity was read at
+    fn version(&self) -> u64;
+
+    fn set_version(&mut self, version: u64);
+}
+
+impl Entity for Connection {
+    fn id(&self) -> &str {
+        &self.client_did
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+
+impl Entity for StoredBlobs {
+    fn id(&self) -> &str {
+        &self.connection
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+
+pub trait Repository<E: Entity>: Send + Sync {
+    fn find(&self, id: &str) -> Option<E>;
+
+    fn all(&self) -> Vec<E>;
+
+    /// Stores a new entity, at version 1
+    fn insert(&self, entity: E) -> Result<E, RepositoryError>;
+
+    /// Replaces an entity, provided it was not updated since it was read,
+    /// returning it at its new version
+    fn update(&self, entity: E) -> Result<E, RepositoryError>;
+
+    fn delete(&self, id: &str) -> Result<(), RepositoryError>;
+}
+
+/// In-memory repository. Clones share the same entities.
+#[derive(Debug, Clone)]
+pub struct MemoryRepository<E> {
+    entities: Arc<Mutex<HashMap<String, E>>>,
+}
+
+impl<E> Default for MemoryRepository<E> {
+    fn default() -> Self {
+        Self {
+            entities: Arc::default(),
+        }
+    }
+}
+
+impl<E> MemoryRepository<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<E: Entity> Repository<E> for MemoryRepository<E> {
+    fn find(&self, id: &str) -> Option<E> {
+        self.entities.lock().unwrap().get(id).cloned()
+    }
+
+    fn all(&self) -> Vec<E> {
+        self.entities.lock().unwrap().values().cloned().collect()
+    }
+
+    fn insert(&self, mut entity: E) -> Result<E, RepositoryError> {
+        let mut entities = self.entities.lock().unwrap();
+        if entities.contains_key(entity.id()) {
+            return Err(RepositoryError::AlreadyExists(entity.id().to_owned()));
+        }
+
+        entity.set_version(1);
+        entities.insert(entity.id().to_owned(), entity.clone());
+        Ok(entity)
+    }
+
+    fn update(&self, mut entity: E) -> Result<E, RepositoryError> {
+        let mut entities = self.entities.lock().unwrap();
+        let stored = entities
+            .get_mut(entity.id())
+            .ok_or_else(|| RepositoryError::NotFound(entity.id().to_owned()))?;
+
+        if stored.version() != entity.version() {
+            return Err(RepositoryError::Conflict {
+                id: entity.id().to_owned(),
+                expected: entity.version(),
+                current: stored.version(),
+            });
+        }
+
+        entity.set_version(stored.version() + 1);
+        *stored = entity.clone();
+        Ok(entity)
+    }
+
+    fn delete(&self, id: &str) -> Result<(), RepositoryError> {
+        match self.entities.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(RepositoryError::NotFound(id.to_owned())),
+        }
+    }
+}
+
+/// Applies a change to the latest version of an entity, reading it anew
+/// and applying the change again on conflicts, up to `attempts` times.
+/// Returns the updated entity along with the outcome of the change.
+pub fn update_with_retry<E, T, F>(
+    repository: &dyn Repository<E>,
+    id: &str,
+    attempts: usize,
+    mut change: F,
+) -> Result<(E, T), RepositoryError>
+where
+    E: Entity,
+    F: FnMut(&mut E) -> T,
+{
+    let mut attempt = 1;
+    loop {
+        let mut entity = repository
+            .find(id)
+            .ok_or_else(|| RepositoryError::NotFound(id.to_owned()))?;
+        let outcome = change(&mut entity);
+
+        match repository.update(entity) {
+            Ok(entity) => return Ok((entity, outcome)),
+            Err(RepositoryError::Conflict { .. }) if attempt < attempts => {
+                tracing::debug!("retrying conflicting update of {id}");
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Barrier, thread};
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+
+    fn repository() -> MemoryRepository<Connection> {
+        let repository = MemoryRepository::new();
+        let connection = Connection {
+            client_did: ALICE.to_owned(),
+            ..Default::default()
+        };
+        repository.insert(connection).unwrap();
+        repository
+    }
+
+    #[test]
+    fn can_compare_and_swap() {
+        let repository = repository();
+        let read = repository.find(ALICE).unwrap();
+        assert_eq!(read.version, 1);
+
+        // Of two writers having read the same version, the last one fails
+        let mut first = read.clone();
+        first.keylist.push(format!("{ALICE}#key-1"));
+        let first = repository.update(first).unwrap();
+        assert_eq!(first.version, 2);
+
+        let mut second = read;
+        second.keylist.push(format!("{ALICE}#key-2"));
+        assert_eq!(
+            repository.update(second),
+            Err(RepositoryError::Conflict {
+                id: ALICE.to_owned(),
+                expected: 1,
+                current: 2,
+            })
+        );
+        assert_eq!(repository.find(ALICE).unwrap(), first);
+
+        assert_eq!(
+            repository.insert(first),
+            Err(RepositoryError::AlreadyExists(ALICE.to_owned()))
+        );
+    }
+
+    #[test]
+    fn should_not_lose_concurrent_updates() {
+        const WRITERS: usize = 8;
+        const UPDATES: usize = 25;
+
+        let repository = repository();
+        let barrier = Arc::new(Barrier::new(WRITERS));
+
+        let handles: Vec<_> = (0..WRITERS)
+            .map(|writer| {
+                let (repository, barrier) = (repository.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    for update in 0..UPDATES {
+                        let key = format!("{ALICE}#key-{writer}-{update}");
+                        update_with_retry(&repository, ALICE, usize::MAX, |connection| {
+                            thread::yield_now();
+                            connection.keylist.push(key.clone());
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let connection = repository.find(ALICE).unwrap();
+        assert_eq!(connection.keylist.len(), WRITERS * UPDATES);
+        assert_eq!(connection.version, 1 + (WRITERS * UPDATES) as u64);
+    }
+
+    #[test]
+    fn should_give_up_after_attempts() {
+        /// Repository updated concurrently before every write
+        struct Contended(MemoryRepository<Connection>);
+
+        impl Repository<Connection> for Contended {
+            fn find(&self, id: &str) -> Option<Connection> {
+                self.0.find(id)
+            }
+
+            fn all(&self) -> Vec<Connection> {
+                self.0.all()
+            }
+
+            fn insert(&self, entity: Connection) -> Result<Connection, RepositoryError> {
+                self.0.insert(entity)
+            }
+
+            fn update(&self, entity: Connection) -> Result<Connection, RepositoryError> {
+                let concurrent = self.0.find(entity.id()).unwrap();
+                self.0.update(concurrent)?;
+                self.0.update(entity)
+            }
+
+            fn delete(&self, id: &str) -> Result<(), RepositoryError> {
+                self.0.delete(id)
+            }
+        }
+
+        let repository = Contended(repository());
+        let mut attempts = 0;
+        let err = update_with_retry(&repository, ALICE, 3, |_| attempts += 1).unwrap_err();
+
+        assert!(matches!(err, RepositoryError::Conflict { .. }));
+        assert_eq!(attempts, 3);
+        assert_eq!(err.to_problem_report().body.code, CONFLICT_CODE);
+    }
+}
@@ -0,0 +1,286 @@
+//! Retries of fallible operations.
+//!
+//! A [`RetryPolicy`] spaces attempts with a backoff strategy, capped at a
+//! maximum delay. Full jitter draws each delay uniformly below the backoff,
+//! so that clients failing together do not retry together. Retries also
+//! stop once the policy's budget for the whole operation is spent, delays
+//! included, however many attempts are left.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Growth of the delay between attempts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetryStrategy {
+    /// Same delay before every retry
+    Fixed,
+    /// Delay multiplied by a factor on every retry
+    Exponential { factor: u32 },
+    /// Delay following the Fibonacci sequence, growing slower than
+    /// doubling it
+    Fibonacci,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub strategy: RetryStrategy,
+
+    /// Number of attempts, the first one included
+    pub max_attempts: u32,
+
+    /// Delay before the first retry
+    pub base_delay: Duration,
+
+    /// Cap on the delay before any retry
+    pub max_delay: Duration,
+
+    /// Whether delays are drawn uniformly below the backoff
+    pub jitter: bool,
+
+    /// Time the whole operation may take, delays included
+    pub budget: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            strategy: RetryStrategy::Exponential { factor: 2 },
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            jitter: true,
+            budget: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Reads `RETRY_STRATEGY` (`fixed`, `exponential` or `fibonacci`),
+    /// `RETRY_FACTOR`, `RETRY_MAX_ATTEMPTS`, `RETRY_BASE_DELAY_MS`,
+    /// `RETRY_MAX_DELAY_MS`, `RETRY_JITTER` (`on` or `off`) and
+    /// `RETRY_BUDGET_MS`, falling back to defaults for unset or invalid
+    /// values.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |key: &str| server_plugin::reload::var(key);
+        let millis = |key: &str| {
+            var(key)
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_millis)
+        };
+
+        let factor = var("RETRY_FACTOR")
+            .and_then(|v| v.parse().ok())
+            .filter(|&factor| factor > 0)
+            .unwrap_or(2);
+        let strategy = match var("RETRY_STRATEGY").as_deref() {
+            Some("fixed") => RetryStrategy::Fixed,
+            Some("exponential") => RetryStrategy::Exponential { factor },
+            Some("fibonacci") => RetryStrategy::Fibonacci,
+            _ => default.strategy,
+        };
+
+        Self {
+            strategy,
+            max_attempts: var("RETRY_MAX_ATTEMPTS")
+                .and_then(|v| v.parse().ok())
+                .filter(|&attempts| attempts > 0)
+                .unwrap_or(default.max_attempts),
+            base_delay: millis("RETRY_BASE_DELAY_MS").unwrap_or(default.base_delay),
+            max_delay: millis("RETRY_MAX_DELAY_MS").unwrap_or(default.max_delay),
+            jitter: var("RETRY_JITTER").map_or(default.jitter, |v| v == "on"),
+            budget: millis("RETRY_BUDGET_MS").or(default.budget),
+        }
+    }
+
+    /// Delay before the given retry, counted from 1, without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let multiplier = match self.strategy {
+            RetryStrategy::Fixed => 1,
+            RetryStrategy::Exponential { factor } => factor.saturating_pow(retry.saturating_sub(1)),
+            RetryStrategy::Fibonacci => fibonacci(retry),
+        };
+
+        self.base_delay
+            .saturating_mul(multiplier)
+            .min(self.max_delay)
+    }
+
+    /// Delay before the given retry, counted from 1
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        if !self.jitter {
+            return backoff;
+        }
+
+        // Random fraction drawn from the random bits of a v4 UUID
+        let random = uuid::Uuid::new_v4().as_u128() as u64 as f64 / u64::MAX as f64;
+        backoff.mul_f64(random)
+    }
+
+    /// Runs an operation until it succeeds, fails with an error `retry_on`
+    /// rejects, or attempts or budget run out, returning its last outcome.
+    /// The operation is passed the number of the attempt, counted from 1.
+    pub fn run<T, E, F, P>(&self, mut operation: F, retry_on: P) -> Result<T, E>
+    where
+        F: FnMut(u32) -> Result<T, E>,
+        P: Fn(&E) -> bool,
+    {
+        let started = Instant::now();
+        let mut attempt = 1;
+
+        loop {
+            let err = match operation(attempt) {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+
+            if attempt >= self.max_attempts || !retry_on(&err) {
+                return Err(err);
+            }
+
+            let delay = self.delay(attempt);
+            if self
+                .budget
+                .is_some_and(|budget| started.elapsed() + delay > budget)
+            {
+                tracing::debug!("retry budget spent after {attempt} attempts");
+                return Err(err);
+            }
+
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+fn fibonacci(n: u32) -> u32 {
+    let (mut previous, mut current) = (0u32, 1u32);
+    for _ in 1..n {
+        (previous, current) = (current, previous.saturating_add(current));
+    }
+
+    current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Failure {
+        Transient,
+        Permanent,
+    }
+
+    fn policy(strategy: RetryStrategy) -> RetryPolicy {
+        RetryPolicy {
+            strategy,
+            max_attempts: 6,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: false,
+            budget: None,
+        }
+    }
+
+    #[test]
+    fn test_backing_off_by_strategy() {
+        let delays = |policy: RetryPolicy| -> Vec<u128> {
+            (1..=6)
+                .map(|retry| policy.backoff(retry).as_millis())
+                .collect()
+        };
+
+        assert_eq!(
+            delays(policy(RetryStrategy::Fixed)),
+            [100, 100, 100, 100, 100, 100]
+        );
+        assert_eq!(
+            delays(policy(RetryStrategy::Exponential { factor: 2 })),
+            [100, 200, 400, 800, 1000, 1000]
+        );
+        assert_eq!(
+            delays(policy(RetryStrategy::Fibonacci)),
+            [100, 100, 200, 300, 500, 800]
+        );
+
+        // Far retries saturate at the cap
+        let policy = policy(RetryStrategy::Exponential { factor: 10 });
+        assert_eq!(policy.backoff(40), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_jittering_delays() {
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy(RetryStrategy::Exponential { factor: 2 })
+        };
+
+        for retry in 1..=6 {
+            assert!(policy.delay(retry) <= policy.backoff(retry));
+        }
+    }
+
+    #[test]
+    fn test_retrying_operations() {
+        let policy = RetryPolicy {
+            base_delay: Duration::ZERO,
+            ..policy(RetryStrategy::Fixed)
+        };
+        let transient = |err: &Failure| *err == Failure::Transient;
+
+        let outcome = policy.run(
+            |attempt| match attempt {
+                1 | 2 => Err(Failure::Transient),
+                _ => Ok(attempt),
+            },
+            transient,
+        );
+        assert_eq!(outcome, Ok(3));
+
+        // Errors not retried on are returned at once
+        let mut attempts = 0;
+        let outcome: Result<(), _> = policy.run(
+            |_| {
+                attempts += 1;
+                Err(Failure::Permanent)
+            },
+            transient,
+        );
+        assert_eq!(outcome, Err(Failure::Permanent));
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let outcome: Result<(), _> = policy.run(
+            |_| {
+                attempts += 1;
+                Err(Failure::Transient)
+            },
+            transient,
+        );
+        assert_eq!(outcome, Err(Failure::Transient));
+        assert_eq!(attempts, 6);
+    }
+
+    #[test]
+    fn test_retrying_within_budget() {
+        let policy = RetryPolicy {
+            budget: Some(Duration::from_millis(250)),
+            ..policy(RetryStrategy::Fixed)
+        };
+
+        let mut attempts = 0;
+        let outcome: Result<(), _> = policy.run(
+            |_| {
+                attempts += 1;
+                Err(Failure::Transient)
+            },
+            |_| true,
+        );
+        assert_eq!(outcome, Err(Failure::Transient));
+        assert_eq!(attempts, 3);
+    }
+}
@@ -0,0 +1,469 @@
+//! Fair scheduling of requests per connection.
+//!
+//! Requests are queued per connection, and workers take them in weighted
+//! round-robin over the connections with requests waiting: a connection
+//! of weight `w` is served up to `w` requests in a row before the next one
+//! gets its turn. Connections may moreover have at most a number of
+//! requests in flight, so that one chatty agent cannot monopolize the
+//! workers, whatever its weight.
+//!
+//! Limits default to those configured globally, and may be overridden per
+//! connection through [`LimitOverrides`], e.g. by operators.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+};
+use utoipa::ToSchema;
+
+use crate::workers::SubmitError;
+
+/// Limits applying to the requests of a connection
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionLimits {
+    /// Requests of the connection processed concurrently, at most
+    pub max_in_flight: usize,
+
+    /// Requests of the connection served in a row, in turn with others
+    pub weight: u32,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 4,
+            weight: 1,
+        }
+    }
+}
+
+impl ConnectionLimits {
+    /// Whether the limits let requests through at all
+    pub fn is_valid(&self) -> bool {
+        self.max_in_flight > 0 && self.weight > 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchedulerConfig {
+    /// Number of workers
+    pub workers: usize,
+
+    /// Number of requests a connection may have waiting before further
+    /// submissions are refused
+    pub queue_capacity: usize,
+
+    /// Limits of connections without overrides
+    pub defaults: ConnectionLimits,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            workers: thread::available_parallelism().map_or(4, usize::from),
+            queue_capacity: 256,
+            defaults: ConnectionLimits::default(),
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// Reads `REQUEST_WORKERS`, `CONNECTION_QUEUE_CAPACITY`,
+    /// `CONNECTION_MAX_IN_FLIGHT` and `CONNECTION_WEIGHT`, falling back to
+    /// defaults for unset or invalid values.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |key: &str| server_plugin::reload::var(key);
+        let positive = |key: &str| {
+            var(key)
+                .and_then(|v| v.parse().ok())
+                .filter(|&value: &usize| value > 0)
+        };
+
+        Self {
+            workers: positive("REQUEST_WORKERS").unwrap_or(default.workers),
+            queue_capacity: positive("CONNECTION_QUEUE_CAPACITY").unwrap_or(default.queue_capacity),
+            defaults: ConnectionLimits {
+                max_in_flight: positive("CONNECTION_MAX_IN_FLIGHT")
+                    .unwrap_or(default.defaults.max_in_flight),
+                weight: var("CONNECTION_WEIGHT")
+                    .and_then(|v| v.parse().ok())
+                    .filter(|&weight: &u32| weight > 0)
+                    .unwrap_or(default.defaults.weight),
+            },
+        }
+    }
+}
+
+/// Limits overridden per connection. Clones share the same overrides.
+#[derive(Debug, Clone, Default)]
+pub struct LimitOverrides {
+    overrides: Arc<Mutex<HashMap<String, ConnectionLimits>>>,
+}
+
+impl LimitOverrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides the limits of a connection, returning those replaced
+    pub fn set(&self, connection: &str, limits: ConnectionLimits) -> Option<ConnectionLimits> {
+        let mut overrides = self.overrides.lock().unwrap();
+        overrides.insert(connection.to_owned(), limits)
+    }
+
+    pub fn get(&self, connection: &str) -> Option<ConnectionLimits> {
+        self.overrides.lock().unwrap().get(connection).copied()
+    }
+
+    /// Reverts a connection to the default limits
+    pub fn remove(&self, connection: &str) -> Option<ConnectionLimits> {
+        self.overrides.lock().unwrap().remove(connection)
+    }
+
+    /// All overrides, keyed by connection
+    pub fn all(&self) -> HashMap<String, ConnectionLimits> {
+        self.overrides.lock().unwrap().clone()
+    }
+}
+
+/// Requests of a connection
+struct ConnectionQueue<J> {
+    jobs: VecDeque<J>,
+    in_flight: usize,
+
+    /// Requests served in a row during the current turn
+    served: u32,
+}
+
+struct Queues<J> {
+    connections: HashMap<String, ConnectionQueue<J>>,
+
+    /// Connections with requests waiting or in flight, in turn order
+    turns: VecDeque<String>,
+    stopping: bool,
+}
+
+impl<J> Queues<J> {
+    fn queued(&self) -> usize {
+        self.connections
+            .values()
+            .map(|queue| queue.jobs.len())
+            .sum()
+    }
+
+    /// Takes the next request due, if any connection may be served
+    fn next(
+        &mut self,
+        config: &SchedulerConfig,
+        overrides: &LimitOverrides,
+    ) -> Option<(String, J)> {
+        for _ in 0..self.turns.len() {
+            let connection = self.turns.front()?.clone();
+            let limits = overrides.get(&connection).unwrap_or(config.defaults);
+            let queue = self.connections.get_mut(&connection)?;
+
+            if !queue.jobs.is_empty() && queue.in_flight < limits.max_in_flight {
+                let job = queue.jobs.pop_front()?;
+                queue.in_flight += 1;
+                queue.served += 1;
+
+                // The turn passes once the weight is used up
+                if queue.served >= limits.weight || queue.jobs.is_empty() {
+                    queue.served = 0;
+                    self.turns.rotate_left(1);
+                }
+
+                return Some((connection, job));
+            }
+
+            queue.served = 0;
+            self.turns.rotate_left(1);
+        }
+
+        None
+    }
+
+    /// Releases a request processed, forgetting idle connections
+    fn release(&mut self, connection: &str) {
+        let Some(queue) = self.connections.get_mut(connection) else {
+            return;
+        };
+
+        queue.in_flight -= 1;
+        if queue.in_flight == 0 && queue.jobs.is_empty() {
+            self.connections.remove(connection);
+            self.turns.retain(|c| c != connection);
+        }
+    }
+}
+
+struct Shared<J> {
+    config: SchedulerConfig,
+    overrides: LimitOverrides,
+    queues: Mutex<Queues<J>>,
+    ready: Condvar,
+}
+
+/// Pool of workers serving the requests of connections fairly
+pub struct FairScheduler<J> {
+    shared: Arc<Shared<J>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl<J: Send + 'static> FairScheduler<J> {
+    /// Starts the workers, processing requests with `handler`. Requests
+    /// panicking are logged and do not stop their worker.
+    pub fn spawn<F>(config: SchedulerConfig, overrides: LimitOverrides, handler: F) -> Self
+    where
+        F: Fn(J) + Send + Sync + 'static,
+    {
+        let workers = config.workers.max(1);
+        let handler = Arc::new(handler);
+        let shared = Arc::new(Shared {
+            config,
+            overrides,
+            queues: Mutex::new(Queues {
+                connections: HashMap::new(),
+                turns: VecDeque::new(),
+                stopping: false,
+            }),
+            ready: Condvar::new(),
+        });
+
+        let handles = (0..workers)
+            .map(|worker| {
+                let shared = shared.clone();
+                let handler = handler.clone();
+
+                thread::spawn(move || loop {
+                    let (connection, job) = {
+                        let mut queues = shared.queues.lock().unwrap();
+                        loop {
+                            if let Some(next) = queues.next(&shared.config, &shared.overrides) {
+                                break next;
+                            }
+                            if queues.stopping && queues.queued() == 0 {
+                                return;
+                            }
+                            queues = shared.ready.wait(queues).unwrap();
+                        }
+                    };
+
+                    if panic::catch_unwind(AssertUnwindSafe(|| handler(job))).is_err() {
+                        tracing::error!("request of {connection} panicked on worker {worker}");
+                    }
+
+                    shared.queues.lock().unwrap().release(&connection);
+                    shared.ready.notify_all();
+                })
+            })
+            .collect();
+
+        Self { shared, handles }
+    }
+
+    /// Queues a request of a connection, without blocking. Refused
+    /// requests are handed back.
+    pub fn submit(&self, connection: &str, job: J) -> Result<(), SubmitError<J>> {
+        let mut queues = self.shared.queues.lock().unwrap();
+        if queues.stopping {
+            return Err(SubmitError::Stopped(job));
+        }
+
+        if !queues.connections.contains_key(connection) {
+            queues.turns.push_back(connection.to_owned());
+        }
+        let queue = queues
+            .connections
+            .entry(connection.to_owned())
+            .or_insert_with(|| ConnectionQueue {
+                jobs: VecDeque::new(),
+                in_flight: 0,
+                served: 0,
+            });
+        if queue.jobs.len() >= self.shared.config.queue_capacity {
+            return Err(SubmitError::Full(job));
+        }
+        queue.jobs.push_back(job);
+        drop(queues);
+
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+
+    /// Requests of a connection being processed
+    pub fn in_flight(&self, connection: &str) -> usize {
+        let queues = self.shared.queues.lock().unwrap();
+        queues
+            .connections
+            .get(connection)
+            .map_or(0, |queue| queue.in_flight)
+    }
+
+    /// Requests of a connection waiting for a worker
+    pub fn queued(&self, connection: &str) -> usize {
+        let queues = self.shared.queues.lock().unwrap();
+        queues
+            .connections
+            .get(connection)
+            .map_or(0, |queue| queue.jobs.len())
+    }
+
+    /// Stops accepting requests and waits for the queued ones to be
+    /// processed
+    pub fn shutdown(self) {
+        self.shared.queues.lock().unwrap().stopping = true;
+        self.shared.ready.notify_all();
+
+        for handle in self.handles {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Barrier,
+    };
+
+    fn config(workers: usize, max_in_flight: usize) -> SchedulerConfig {
+        SchedulerConfig {
+            workers,
+            queue_capacity: 1000,
+            defaults: ConnectionLimits {
+                max_in_flight,
+                weight: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn can_interleave_connections_by_weight() {
+        let order = Arc::new(Mutex::new(vec![]));
+        let gate = Arc::new(Barrier::new(2));
+        let overrides = LimitOverrides::new();
+        overrides.set(
+            "bob",
+            ConnectionLimits {
+                max_in_flight: 1,
+                weight: 2,
+            },
+        );
+
+        let scheduler = {
+            let (order, gate) = (order.clone(), gate.clone());
+            FairScheduler::spawn(
+                config(1, 1),
+                overrides,
+                move |(connection, seq): (&str, usize)| {
+                    if connection == "gate" {
+                        gate.wait();
+                    }
+                    order.lock().unwrap().push(format!("{connection}-{seq}"));
+                },
+            )
+        };
+
+        // Hold the worker until all requests are queued
+        scheduler.submit("gate", ("gate", 0)).unwrap();
+        for seq in 0..6 {
+            scheduler.submit("alice", ("alice", seq)).unwrap();
+        }
+        for seq in 0..4 {
+            scheduler.submit("bob", ("bob", seq)).unwrap();
+        }
+        gate.wait();
+        scheduler.shutdown();
+
+        let order = order.lock().unwrap();
+        assert_eq!(
+            order[1..],
+            [
+                "alice-0", "bob-0", "bob-1", "alice-1", "bob-2", "bob-3", "alice-2", "alice-3",
+                "alice-4", "alice-5"
+            ]
+        );
+    }
+
+    #[test]
+    fn should_cap_requests_in_flight_per_connection() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let scheduler = {
+            let (running, peak) = (running.clone(), peak.clone());
+            FairScheduler::spawn(config(8, 2), LimitOverrides::new(), move |_: usize| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(std::time::Duration::from_millis(2));
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        };
+
+        for seq in 0..40 {
+            scheduler.submit("chatty", seq).unwrap();
+        }
+        scheduler.shutdown();
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn should_refuse_requests_beyond_capacity() {
+        let gate = Arc::new(Barrier::new(2));
+        let scheduler = {
+            let gate = gate.clone();
+            let config = SchedulerConfig {
+                queue_capacity: 1,
+                ..config(1, 1)
+            };
+            FairScheduler::spawn(config, LimitOverrides::new(), move |_: usize| {
+                gate.wait();
+            })
+        };
+
+        // The first request is being processed, the second one queued
+        scheduler.submit("alice", 0).unwrap();
+        while scheduler.in_flight("alice") == 0 {
+            thread::yield_now();
+        }
+        scheduler.submit("alice", 1).unwrap();
+        assert_eq!(scheduler.submit("alice", 2), Err(SubmitError::Full(2)));
+        assert_eq!(scheduler.queued("alice"), 1);
+
+        // Other connections have queues of their own
+        scheduler.submit("bob", 3).unwrap();
+
+        for _ in 0..3 {
+            gate.wait();
+        }
+        scheduler.shutdown();
+    }
+
+    #[test]
+    fn should_survive_panicking_requests() {
+        let processed = Arc::new(Mutex::new(vec![]));
+        let scheduler = {
+            let processed = processed.clone();
+            FairScheduler::spawn(config(1, 1), LimitOverrides::new(), move |job: usize| {
+                if job == 0 {
+                    panic!("malformed request");
+                }
+                processed.lock().unwrap().push(job);
+            })
+        };
+
+        scheduler.submit("alice", 0).unwrap();
+        scheduler.submit("alice", 1).unwrap();
+        scheduler.shutdown();
+
+        assert_eq!(*processed.lock().unwrap(), [1]);
+    }
+}
@@ -0,0 +1,670 @@
+//! Wallet-attached storage for edge agents.
+//!
+//! Recipients may park small blobs at the mediator, e.g. an encrypted backup
+//! of their keylist or application state. Blobs are opaque to the mediator:
+//! they are stored under the authenticated sender of the storing message,
+//! provided it was granted mediation, within per-connection quotas, and
+//! expire after a bounded time to live.
+
+use chrono::Utc;
+use multibase::Base::Base64Url;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc};
+use thiserror::Error;
+
+use crate::{
+    constants::*,
+    didcomm::{
+        problem_report::ProblemReport,
+        validation::{MessageValidator, INVALID_MESSAGE_CODE, UNSUPPORTED_MESSAGE_CODE},
+    },
+    keylist::UPDATE_ATTEMPTS,
+    model::{connection::Connection, coord::CoordMessage, storage::*},
+    repository::{Repository, RepositoryError},
+};
+
+/// Problem code for lookups of missing or expired entries
+pub const STORAGE_NOT_FOUND_CODE: &str = "e.p.storage.not-found";
+
+/// Problem code for writes exceeding the connection's quota
+pub const STORAGE_QUOTA_EXCEEDED_CODE: &str = "e.p.storage.quota-exceeded";
+
+/// Problem code for writes from senders not granted mediation
+pub const STORAGE_NOT_MEDIATED_CODE: &str = "e.p.storage.not-mediated";
+
+/// Maximum length of entry keys
+const MAX_KEY_LENGTH: usize = 256;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum StorageError {
+    #[error("no entry under key `{0}`")]
+    NotFound(String),
+    #[error("invalid key")]
+    InvalidKey,
+    #[error("data is not valid base64url")]
+    InvalidData,
+    #[error("entry of {0} bytes exceeds the limit of {1} bytes")]
+    EntryTooLarge(u64, u64),
+    #[error("storage quota of {0} bytes exceeded")]
+    QuotaExceeded(u64),
+    #[error("limit of {0} entries reached")]
+    TooManyEntries(usize),
+    #[error("no mediation granted to {0}")]
+    NotMediated(String),
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+}
+
+impl StorageError {
+    /// Describes the error as a problem report
+    pub fn to_problem_report(&self) -> ProblemReport {
+        let (code, comment, args) = match self {
+            Self::NotFound(key) => (
+                STORAGE_NOT_FOUND_CODE,
+                "No entry under key {1}",
+                vec![key.clone()],
+            ),
+            Self::InvalidKey => (INVALID_MESSAGE_CODE, "Invalid key", vec![]),
+            Self::InvalidData => (INVALID_MESSAGE_CODE, "Data is not valid base64url", vec![]),
+            Self::EntryTooLarge(size, max) => (
+                STORAGE_QUOTA_EXCEEDED_CODE,
+                "Entry of {1} bytes exceeds the limit of {2} bytes",
+                vec![size.to_string(), max.to_string()],
+            ),
+            Self::QuotaExceeded(max) => (
+                STORAGE_QUOTA_EXCEEDED_CODE,
+                "Storage quota of {1} bytes exceeded",
+                vec![max.to_string()],
+            ),
+            Self::TooManyEntries(max) => (
+                STORAGE_QUOTA_EXCEEDED_CODE,
+                "Limit of {1} entries reached",
+                vec![max.to_string()],
+            ),
+            Self::NotMediated(connection) => (
+                STORAGE_NOT_MEDIATED_CODE,
+                "No mediation granted to {1}",
+                vec![connection.clone()],
+            ),
+            Self::Repository(err) => return err.to_problem_report(),
+        };
+
+        ProblemReport::new(code, Some(comment), (!args.is_empty()).then_some(args))
+    }
+}
+
+/// Limits applied to the storage of each connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageQuota {
+    /// Maximum number of entries
+    pub max_entries: usize,
+
+    /// Maximum size of an entry, in bytes
+    pub max_entry_size: u64,
+
+    /// Maximum size of all entries, in bytes
+    pub max_total_size: u64,
+
+    /// Time to live of entries not requesting one, in seconds
+    pub default_ttl: u64,
+
+    /// Maximum time to live of entries, in seconds
+    pub max_ttl: u64,
+}
+
+impl Default for StorageQuota {
+    fn default() -> Self {
+        Self {
+            max_entries: 64,
+            max_entry_size: 64 * 1024,
+            max_total_size: 1024 * 1024,
+            default_ttl: 30 * 24 * 3600,
+            max_ttl: 365 * 24 * 3600,
+        }
+    }
+}
+
+fn describe(key: &str, entry: &StoredBlob) -> StorageEntryBody {
+    StorageEntryBody {
+        key: key.to_owned(),
+        size: entry.data.len() as u64,
+        expires_time: entry.expires_time,
+    }
+}
+
+/// Store of blobs, persisted through a repository with one entity per
+/// connection. Clones share the same repositories.
+#[derive(Clone)]
+pub struct BlobStore {
+    quota: StorageQuota,
+    repository: Arc<dyn Repository<StoredBlobs>>,
+    connections: Arc<dyn Repository<Connection>>,
+}
+
+impl BlobStore {
+    /// Store of blobs, accepting blobs only from the senders granted
+    /// mediation in `connections`
+    pub fn new(
+        quota: StorageQuota,
+        repository: Arc<dyn Repository<StoredBlobs>>,
+        connections: Arc<dyn Repository<Connection>>,
+    ) -> Self {
+        Self {
+            quota,
+            repository,
+            connections,
+        }
+    }
+
+    /// Limits applied to each connection
+    pub fn quota(&self) -> &StorageQuota {
+        &self.quota
+    }
+
+    /// Applies a change to the blobs of a connection, creating them if
+    /// missing, and applying it again on conflicting writes. Nothing is
+    /// written if the change fails.
+    fn modify<T>(
+        &self,
+        connection: &str,
+        mut change: impl FnMut(&mut StoredBlobs) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        let mut attempt = 1;
+        loop {
+            let stored = self.repository.find(connection);
+            let exists = stored.is_some();
+            let mut blobs = stored.unwrap_or_else(|| StoredBlobs {
+                connection: connection.to_owned(),
+                ..Default::default()
+            });
+            let outcome = change(&mut blobs)?;
+
+            let written = match exists {
+                true => self.repository.update(blobs),
+                false => self.repository.insert(blobs),
+            };
+            match written {
+                Ok(_) => return Ok(outcome),
+                Err(RepositoryError::Conflict { .. } | RepositoryError::AlreadyExists(_))
+                    if attempt < UPDATE_ATTEMPTS =>
+                {
+                    tracing::debug!("retrying conflicting write of blobs of {connection}");
+                    attempt += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Stores a blob, replacing any prior entry under the same key.
+    /// Only connections granted mediation, and not archived, may store
+    /// blobs.
+    pub fn put(
+        &self,
+        connection: &str,
+        key: &str,
+        data: Vec<u8>,
+        ttl: Option<u64>,
+        now: i64,
+    ) -> Result<StorageEntryBody, StorageError> {
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+            return Err(StorageError::InvalidKey);
+        }
+
+        let size = data.len() as u64;
+        if size > self.quota.max_entry_size {
+            return Err(StorageError::EntryTooLarge(size, self.quota.max_entry_size));
+        }
+
+        self.connections
+            .find(connection)
+            .filter(|mediated| !mediated.is_archived())
+            .ok_or_else(|| StorageError::NotMediated(connection.to_owned()))?;
+
+        let ttl = ttl
+            .unwrap_or(self.quota.default_ttl)
+            .min(self.quota.max_ttl);
+        let entry = StoredBlob {
+            data,
+            expires_time: now.saturating_add(ttl as i64),
+        };
+
+        self.modify(connection, |blobs| {
+            let entries = &mut blobs.entries;
+            entries.retain(|_, entry| entry.expires_time > now);
+
+            let replaced = entries.get(key).map(|entry| entry.data.len() as u64);
+            if replaced.is_none() && entries.len() >= self.quota.max_entries {
+                return Err(StorageError::TooManyEntries(self.quota.max_entries));
+            }
+
+            let used: u64 = entries.values().map(|entry| entry.data.len() as u64).sum();
+            if used - replaced.unwrap_or(0) + size > self.quota.max_total_size {
+                return Err(StorageError::QuotaExceeded(self.quota.max_total_size));
+            }
+
+            entries.insert(key.to_owned(), entry.clone());
+            Ok(describe(key, &entry))
+        })
+    }
+
+    /// Retrieves a blob and its expiry
+    pub fn get(
+        &self,
+        connection: &str,
+        key: &str,
+        now: i64,
+    ) -> Result<(Vec<u8>, i64), StorageError> {
+        self.repository
+            .find(connection)
+            .and_then(|mut blobs| blobs.entries.remove(key))
+            .filter(|entry| entry.expires_time > now)
+            .map(|entry| (entry.data, entry.expires_time))
+            .ok_or_else(|| StorageError::NotFound(key.to_owned()))
+    }
+
+    /// Deletes a blob
+    pub fn delete(&self, connection: &str, key: &str, now: i64) -> Result<(), StorageError> {
+        if self.repository.find(connection).is_none() {
+            return Err(StorageError::NotFound(key.to_owned()));
+        }
+
+        self.modify(connection, |blobs| {
+            blobs
+                .entries
+                .remove(key)
+                .filter(|entry| entry.expires_time > now)
+                .map(|_| ())
+                .ok_or_else(|| StorageError::NotFound(key.to_owned()))
+        })
+    }
+
+    /// Lists the live entries of a connection, ordered by key
+    pub fn list(&self, connection: &str, now: i64) -> StorageKeysBody {
+        let blobs = self.repository.find(connection).unwrap_or_default();
+        let keys: Vec<_> = blobs
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_time > now)
+            .map(|(key, entry)| describe(key, entry))
+            .collect();
+
+        StorageKeysBody {
+            used: keys.iter().map(|entry| entry.size).sum(),
+            quota: self.quota.max_total_size,
+            keys,
+        }
+    }
+
+    /// Size of the live entries held by each connection, in bytes
+    pub fn footprint(&self, now: i64) -> HashMap<String, u64> {
+        self.repository
+            .all()
+            .into_iter()
+            .map(|blobs| {
+                let used = blobs
+                    .entries
+                    .values()
+                    .filter(|entry| entry.expires_time > now)
+                    .map(|entry| entry.data.len() as u64)
+                    .sum();
+                (blobs.connection, used)
+            })
+            .filter(|(_, used)| *used > 0)
+            .collect()
+    }
+
+    /// Drops all entries of a connection, returning how many were dropped
+    pub fn forget(&self, connection: &str) -> usize {
+        let Some(blobs) = self.repository.find(connection) else {
+            return 0;
+        };
+
+        match self.repository.delete(connection) {
+            Ok(()) => blobs.entries.len(),
+            Err(_) => 0,
+        }
+    }
+
+    /// Drops expired entries of all connections, returning how many were dropped
+    pub fn purge_expired(&self, now: i64) -> usize {
+        let mut purged = 0;
+
+        for blobs in self.repository.all() {
+            if blobs.entries.values().all(|entry| entry.expires_time > now) {
+                continue;
+            }
+
+            let dropped = self.modify(&blobs.connection, |blobs| {
+                let count = blobs.entries.len();
+                blobs.entries.retain(|_, entry| entry.expires_time > now);
+                Ok(count - blobs.entries.len())
+            });
+            match dropped {
+                Ok(dropped) => purged += dropped,
+                Err(err) => tracing::warn!("failed to purge blobs of {}: {err}", blobs.connection),
+            }
+        }
+
+        purged
+    }
+
+    /// Handles a plaintext storage message from an authenticated sender,
+    /// returning the response message to send back.
+    #[allow(clippy::result_large_err)]
+    pub fn handle(&self, sender: &str, message: &Value) -> Result<Value, ProblemReport> {
+        self.handle_at(sender, message, Utc::now().timestamp())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn handle_at(&self, sender: &str, message: &Value, now: i64) -> Result<Value, ProblemReport> {
+        MessageValidator::new(message_specs()).check(message)?;
+
+        let pthid = message
+            .get("thid")
+            .or_else(|| message.get("id"))
+            .and_then(Value::as_str);
+        let report = |err: StorageError| err.to_problem_report().with_pthid(pthid);
+
+        match message["type"].as_str().unwrap_or_default() {
+            STORAGE_PUT_1_0 => {
+                let request: StoragePut = deserialize(message)?;
+                let data = Base64Url
+                    .decode(&request.body.data)
+                    .map_err(|_| report(StorageError::InvalidData))?;
+                let body = self
+                    .put(sender, &request.body.key, data, request.body.ttl, now)
+                    .map_err(report)?;
+
+                Ok(json!(StorageStored::reply_to(
+                    &request,
+                    STORAGE_STORED_1_0,
+                    body
+                )))
+            }
+            STORAGE_GET_1_0 => {
+                let request: StorageGet = deserialize(message)?;
+                let (data, expires_time) =
+                    self.get(sender, &request.body.key, now).map_err(report)?;
+                let body = StorageValueBody {
+                    key: request.body.key.clone(),
+                    data: Base64Url.encode(data),
+                    expires_time,
+                };
+
+                Ok(json!(StorageValue::reply_to(
+                    &request,
+                    STORAGE_VALUE_1_0,
+                    body
+                )))
+            }
+            STORAGE_DELETE_1_0 => {
+                let request: StorageDelete = deserialize(message)?;
+                self.delete(sender, &request.body.key, now)
+                    .map_err(report)?;
+                let body = request.body.clone();
+
+                Ok(json!(StorageDeleted::reply_to(
+                    &request,
+                    STORAGE_DELETED_1_0,
+                    body
+                )))
+            }
+            STORAGE_LIST_1_0 => {
+                let request: StorageList = deserialize(message)?;
+                let body = self.list(sender, now);
+
+                Ok(json!(StorageKeys::reply_to(
+                    &request,
+                    STORAGE_KEYS_1_0,
+                    body
+                )))
+            }
+            // Responses are not handled by the mediator
+            t => Err(ProblemReport::new(
+                UNSUPPORTED_MESSAGE_CODE,
+                Some("Unsupported message type {1}"),
+                Some(vec![t.to_owned()]),
+            )
+            .with_pthid(pthid)),
+        }
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn deserialize<B: DeserializeOwned + Default>(
+    message: &Value,
+) -> Result<CoordMessage<B>, ProblemReport> {
+    serde_json::from_value(message.clone()).map_err(|_| {
+        ProblemReport::new(INVALID_MESSAGE_CODE, Some("Malformed message"), None)
+            .with_pthid(message.get("id").and_then(Value::as_str))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::MemoryRepository;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+    const BOB: &str = "did:key:z6Mkfriq1MqLBoPWecGoDLjguo1sB9brj6wT3qZ5BxkKpuP6";
+
+    fn quota() -> StorageQuota {
+        StorageQuota {
+            max_entries: 2,
+            max_entry_size: 8,
+            max_total_size: 12,
+            default_ttl: 60,
+            max_ttl: 3600,
+        }
+    }
+
+    /// Store of blobs, with mediation granted to Alice and Bob
+    fn store() -> BlobStore {
+        let connections = MemoryRepository::new();
+        for did in [ALICE, BOB] {
+            let connection = Connection {
+                client_did: did.to_owned(),
+                ..Default::default()
+            };
+            connections.insert(connection).unwrap();
+        }
+
+        BlobStore::new(
+            quota(),
+            Arc::new(MemoryRepository::new()),
+            Arc::new(connections),
+        )
+    }
+
+    #[test]
+    fn can_store_blobs_per_connection() {
+        let store = store();
+
+        let stored = store
+            .put(ALICE, "backup", b"secret".to_vec(), None, 1000)
+            .unwrap();
+        assert_eq!(stored.size, 6);
+        assert_eq!(stored.expires_time, 1060);
+
+        assert_eq!(
+            store.get(ALICE, "backup", 1000).unwrap(),
+            (b"secret".to_vec(), 1060)
+        );
+        assert_eq!(
+            store.get(BOB, "backup", 1000),
+            Err(StorageError::NotFound(String::from("backup")))
+        );
+
+        // Requested TTLs are capped
+        let stored = store
+            .put(ALICE, "state", b"app".to_vec(), Some(7200), 1000)
+            .unwrap();
+        assert_eq!(stored.expires_time, 4600);
+
+        let list = store.list(ALICE, 1000);
+        assert_eq!(
+            list.keys.iter().map(|e| e.key.as_str()).collect::<Vec<_>>(),
+            ["backup", "state"]
+        );
+        assert_eq!((list.used, list.quota), (9, 12));
+        assert!(store.list(BOB, 1000).keys.is_empty());
+
+        store.delete(ALICE, "backup", 1000).unwrap();
+        assert!(store.get(ALICE, "backup", 1000).is_err());
+        assert!(store.delete(ALICE, "backup", 1000).is_err());
+    }
+
+    #[test]
+    fn should_enforce_quotas() {
+        let store = store();
+
+        assert_eq!(
+            store.put(ALICE, "big", vec![0; 9], None, 0),
+            Err(StorageError::EntryTooLarge(9, 8))
+        );
+        assert_eq!(
+            store.put(ALICE, "", vec![0; 1], None, 0),
+            Err(StorageError::InvalidKey)
+        );
+
+        store.put(ALICE, "a", vec![0; 8], None, 0).unwrap();
+        assert_eq!(
+            store.put(ALICE, "b", vec![0; 5], None, 0),
+            Err(StorageError::QuotaExceeded(12))
+        );
+
+        // Replacing an entry only accounts for the size difference
+        store.put(ALICE, "a", vec![0; 4], None, 0).unwrap();
+        store.put(ALICE, "b", vec![0; 8], None, 0).unwrap();
+        assert_eq!(
+            store.put(ALICE, "c", vec![0; 1], None, 0),
+            Err(StorageError::TooManyEntries(2))
+        );
+
+        // Quotas are per connection
+        store.put(BOB, "c", vec![0; 8], None, 0).unwrap();
+    }
+
+    #[test]
+    fn should_require_mediation_to_store_blobs() {
+        let store = store();
+        let carol = "did:example:carol";
+
+        assert_eq!(
+            store.put(carol, "a", vec![0; 1], None, 0),
+            Err(StorageError::NotMediated(carol.to_owned()))
+        );
+        assert!(store.list(carol, 0).keys.is_empty());
+
+        // Archived connections no longer store blobs
+        store.put(ALICE, "a", vec![0; 1], None, 0).unwrap();
+        let mut connection = store.connections.find(ALICE).unwrap();
+        connection.archived_time = Some(0);
+        store.connections.update(connection).unwrap();
+        assert_eq!(
+            store.put(ALICE, "b", vec![0; 1], None, 0),
+            Err(StorageError::NotMediated(ALICE.to_owned()))
+        );
+
+        let put = json!({
+            "id": "put-1",
+            "type": STORAGE_PUT_1_0,
+            "body": {"key": "backup", "data": Base64Url.encode(b"secret")}
+        });
+        let report = store.handle_at(carol, &put, 0).unwrap_err();
+        assert_eq!(report.body.code, STORAGE_NOT_MEDIATED_CODE);
+    }
+
+    #[test]
+    fn should_expire_entries() {
+        let store = store();
+
+        store.put(ALICE, "a", vec![0; 8], Some(10), 0).unwrap();
+        store.put(ALICE, "b", vec![0; 4], Some(20), 0).unwrap();
+
+        assert!(store.get(ALICE, "a", 10).is_err());
+        assert_eq!(store.list(ALICE, 10).used, 4);
+
+        // Expired entries do not count against quotas
+        store.put(ALICE, "c", vec![0; 8], None, 10).unwrap();
+
+        assert_eq!(store.purge_expired(20), 1);
+        assert_eq!(store.list(ALICE, 20).keys.len(), 1);
+    }
+
+    #[test]
+    fn can_handle_storage_messages() {
+        let store = store();
+
+        let put = json!({
+            "id": "put-1",
+            "type": STORAGE_PUT_1_0,
+            "body": {"key": "backup", "data": Base64Url.encode(b"secret"), "ttl": 30}
+        });
+
+        let response = store.handle_at(ALICE, &put, 1000).unwrap();
+        assert_eq!(response["type"], STORAGE_STORED_1_0);
+        assert_eq!(response["thid"], "put-1");
+        assert_eq!(
+            response["body"],
+            json!({"key": "backup", "size": 6, "expires_time": 1030})
+        );
+
+        let get = json!({"id": "get-1", "type": STORAGE_GET_1_0, "body": {"key": "backup"}});
+        let response = store.handle_at(ALICE, &get, 1000).unwrap();
+        assert_eq!(response["type"], STORAGE_VALUE_1_0);
+        assert_eq!(
+            Base64Url
+                .decode(response["body"]["data"].as_str().unwrap())
+                .unwrap(),
+            b"secret"
+        );
+
+        let list = json!({"id": "list-1", "type": STORAGE_LIST_1_0});
+        let response = store.handle_at(ALICE, &list, 1000).unwrap();
+        assert_eq!(response["type"], STORAGE_KEYS_1_0);
+        assert_eq!(response["body"]["used"], 6);
+
+        let delete =
+            json!({"id": "delete-1", "type": STORAGE_DELETE_1_0, "body": {"key": "backup"}});
+        let response = store.handle_at(ALICE, &delete, 1000).unwrap();
+        assert_eq!(response["type"], STORAGE_DELETED_1_0);
+
+        let report = store.handle_at(ALICE, &get, 1000).unwrap_err();
+        assert_eq!(report.body.code, STORAGE_NOT_FOUND_CODE);
+        assert_eq!(report.pthid.as_deref(), Some("get-1"));
+    }
+
+    #[test]
+    fn should_report_problems_on_invalid_storage_messages() {
+        let store = store();
+
+        let cases = [
+            (
+                json!({"id": "1", "type": STORAGE_PUT_1_0, "body": {"key": "backup"}}),
+                INVALID_MESSAGE_CODE,
+            ),
+            (
+                json!({"id": "2", "type": STORAGE_PUT_1_0, "body": {"key": "backup", "data": "!"}}),
+                INVALID_MESSAGE_CODE,
+            ),
+            (
+                json!({"id": "3", "type": STORAGE_PUT_1_0, "body": {"key": "backup", "data": Base64Url.encode([0; 9])}}),
+                STORAGE_QUOTA_EXCEEDED_CODE,
+            ),
+            (
+                json!({"id": "4", "thid": "3", "type": STORAGE_KEYS_1_0, "body": {"keys": [], "used": 0, "quota": 0}}),
+                UNSUPPORTED_MESSAGE_CODE,
+            ),
+        ];
+
+        for (message, code) in cases {
+            assert_eq!(
+                store.handle_at(ALICE, &message, 0).unwrap_err().body.code,
+                code
+            );
+        }
+    }
+}
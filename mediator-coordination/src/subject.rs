@@ -0,0 +1,739 @@
+//! Data subject requests: export and erasure of the data held about a DID.
+//!
+//! Data about a DID is spread across the connection of its client, the
+//! messages queued for it, distribution lists, and the anomaly and usage
+//! logs, as well as, where the mediator keeps them, the blobs it stored,
+//! the delivery statuses of the messages it forwarded, its writes awaiting
+//! the promotion of a standby, its dead letters, and the outbox entries
+//! destined to it. Exports gather it in machine-readable form, leaving out
+//! the payloads of queued messages and the content of blobs, which are
+//! opaque to the mediator anyway.
+//!
+//! Erasure deletes the connection along with its queue and the rest of
+//! the data above, and removes the DID from distribution lists, while log
+//! entries, retained for security and billing, are anonymized. Each
+//! erasure is recorded to `audit/erasures.jsonl` under the storage
+//! directory, identifying the DID by its HMAC only, keyed with a server
+//! secret so that the log cannot be checked against guessed DIDs.
+
+use did_endpoint::util::filesystem::FileSystem;
+use hmac::{Hmac, Mac};
+use multibase::Base;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{collections::BTreeSet, io::ErrorKind, sync::Arc};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::{
+    anomaly::{self, AnomalyReport},
+    archival::{ArchivalError, Connections},
+    delivery::DeliveryTracker,
+    failover::{Failover, FailoverError, PendingWrite},
+    handler::DeadLetters,
+    lists::DistributionLists,
+    metering::{MeteringError, UsageLedger, UsageRecord},
+    model::{connection::Connection, delivery::DeliveryReport, storage::StorageEntryBody},
+    outbox::{Outbox, OutboxEntry, OutboxError},
+    pickup::{PickupQueue, QueuedMessage},
+    repository::RepositoryError,
+    storage::BlobStore,
+};
+
+/// Placeholder of anonymized identifiers in retained log entries
+pub const ANONYMIZED: &str = "anonymized";
+
+#[derive(Debug, Error)]
+pub enum SubjectError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error(transparent)]
+    Metering(#[from] MeteringError),
+    #[error(transparent)]
+    Archival(#[from] ArchivalError),
+    #[error(transparent)]
+    Failover(#[from] FailoverError),
+    #[error(transparent)]
+    Outbox(#[from] OutboxError),
+}
+
+/// Metadata of a queued message, without its payload
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct QueuedMessageMetadata {
+    pub id: String,
+
+    /// DID or key identifier the message was forwarded to
+    pub recipient_did: String,
+
+    /// Size of the packed message, in bytes
+    pub size: u64,
+    pub priority: u8,
+
+    /// Time of reception, as a UNIX timestamp
+    pub received_time: i64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_time: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deliver_after: Option<i64>,
+}
+
+impl From<QueuedMessage> for QueuedMessageMetadata {
+    fn from(message: QueuedMessage) -> Self {
+        Self {
+            size: message.payload.len() as u64,
+            id: message.id,
+            recipient_did: message.recipient_did,
+            priority: message.priority,
+            received_time: message.received_time,
+            expires_time: message.expires_time,
+            deliver_after: message.deliver_after,
+        }
+    }
+}
+
+/// Role of a DID in a distribution list
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct ListMembership {
+    /// DID of the list
+    pub list_did: String,
+
+    /// Whether messages to the list are fanned out to the DID
+    pub member: bool,
+
+    /// Whether the DID may forward messages to the list
+    pub sender: bool,
+}
+
+/// Data held about a DID
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct SubjectExport {
+    pub did: String,
+
+    /// Time of the export, as a UNIX timestamp
+    pub exported_time: i64,
+
+    /// Connection of the DID as a client, archived or not
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection: Option<Connection>,
+
+    /// Messages awaiting pickup by the DID
+    pub queued_messages: Vec<QueuedMessageMetadata>,
+
+    pub distribution_lists: Vec<ListMembership>,
+
+    /// Envelopes from or to the DID flagged as suspicious
+    #[schema(value_type = Vec<Object>)]
+    pub anomalies: Vec<AnomalyReport>,
+
+    pub usage_records: Vec<UsageRecord>,
+
+    /// Blobs stored by the DID, without their content
+    #[schema(value_type = Vec<Object>)]
+    pub stored_blobs: Vec<StorageEntryBody>,
+
+    /// Delivery statuses of the messages forwarded by the DID
+    #[schema(value_type = Vec<Object>)]
+    pub delivery_statuses: Vec<DeliveryReport>,
+
+    /// Messages from the DID received on standby, awaiting promotion
+    #[schema(value_type = Vec<Object>)]
+    pub pending_writes: Vec<PendingWrite>,
+
+    /// Messages from the DID of unknown types, kept for analysis
+    pub dead_letters: Vec<Value>,
+
+    /// Messages to the DID awaiting dispatch
+    #[schema(value_type = Vec<Object>)]
+    pub outbox_entries: Vec<OutboxEntry>,
+}
+
+/// Audit record of an erasure, counting what was erased or anonymized
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct ErasureRecord {
+    /// Multibase-encoded HMAC-SHA256 of the erased DID, keyed with the
+    /// server secret
+    pub subject_hash: String,
+
+    /// Time of the erasure, as a UNIX timestamp
+    pub erased_time: i64,
+
+    /// Whether a connection was deleted
+    pub connection: bool,
+    pub queued_messages: usize,
+    pub distribution_lists: usize,
+    pub anomalies: usize,
+    pub usage_records: usize,
+    pub stored_blobs: usize,
+    pub delivery_statuses: usize,
+    pub pending_writes: usize,
+    pub dead_letters: usize,
+    pub outbox_entries: usize,
+}
+
+/// Keyed hash identifying a DID in erasure records
+pub fn subject_hash(secret: &[u8], did: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(did.as_bytes());
+    multibase::encode(Base::Base58Btc, mac.finalize().into_bytes())
+}
+
+/// Secret keying the hashes of erased DIDs, from `SUBJECT_HASH_SECRET`, or
+/// else generated once and kept at `audit/subject-hash.key` under the
+/// storage directory
+pub fn hash_secret(fs: &mut dyn FileSystem, storage_dirpath: &str) -> std::io::Result<Vec<u8>> {
+    if let Some(secret) = server_plugin::reload::var("SUBJECT_HASH_SECRET") {
+        return Ok(secret.into_bytes());
+    }
+
+    let path = format!("{storage_dirpath}/audit/subject-hash.key");
+    match fs.read_to_string(&path) {
+        Ok(encoded) => multibase::decode(encoded.trim())
+            .map(|(_, secret)| secret)
+            .map_err(std::io::Error::other),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            let secret = rand::random::<[u8; 32]>();
+            fs.create_dir_all(&format!("{storage_dirpath}/audit"))?;
+            fs.write_atomic(&path, &multibase::encode(Base::Base64Url, secret))?;
+            Ok(secret.to_vec())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Data held by the mediator, as consulted for data subject requests
+#[derive(Clone)]
+pub struct DataSubjects {
+    connections: Connections,
+    queue: Arc<PickupQueue>,
+    lists: DistributionLists,
+    blobs: Option<Arc<BlobStore>>,
+    tracker: Option<DeliveryTracker>,
+    failover: Option<Failover>,
+    dead_letters: Option<DeadLetters>,
+}
+
+impl DataSubjects {
+    pub fn new(
+        connections: Connections,
+        queue: Arc<PickupQueue>,
+        lists: DistributionLists,
+    ) -> Self {
+        Self {
+            connections,
+            queue,
+            lists,
+            blobs: None,
+            tracker: None,
+            failover: None,
+            dead_letters: None,
+        }
+    }
+
+    /// Covers the blobs stored by subjects
+    pub fn with_blobs(self, blobs: Arc<BlobStore>) -> Self {
+        Self {
+            blobs: Some(blobs),
+            ..self
+        }
+    }
+
+    /// Covers the delivery statuses of the messages forwarded by subjects
+    pub fn with_tracker(self, tracker: DeliveryTracker) -> Self {
+        Self {
+            tracker: Some(tracker),
+            ..self
+        }
+    }
+
+    /// Covers the writes of subjects awaiting the promotion of a standby
+    pub fn with_failover(self, failover: Failover) -> Self {
+        Self {
+            failover: Some(failover),
+            ..self
+        }
+    }
+
+    /// Covers the dead letters of subjects
+    pub fn with_dead_letters(self, dead_letters: DeadLetters) -> Self {
+        Self {
+            dead_letters: Some(dead_letters),
+            ..self
+        }
+    }
+
+    /// Exports the data held about a DID
+    pub fn export(
+        &self,
+        fs: &mut dyn FileSystem,
+        storage_dirpath: &str,
+        did: &str,
+        now: i64,
+    ) -> Result<SubjectExport, SubjectError> {
+        let connection = self.connections.get(did);
+        let identifiers = identifiers(did, connection.as_ref());
+
+        let distribution_lists = self
+            .lists
+            .all()
+            .into_iter()
+            .filter_map(|(list_did, list)| {
+                let (member, sender) = (list.members.contains(did), list.senders.contains(did));
+                (member || sender).then_some(ListMembership {
+                    list_did,
+                    member,
+                    sender,
+                })
+            })
+            .collect();
+
+        let anomalies = anomaly::read_anomaly_log(fs, storage_dirpath)
+            .map_err(SubjectError::IoError)?
+            .into_iter()
+            .filter(|report| is_about(report, &identifiers))
+            .collect();
+
+        let usage_records = UsageLedger::new(fs, storage_dirpath)
+            .records(None, None)?
+            .into_iter()
+            .filter(|record| record.connection == did)
+            .collect();
+
+        let pending_writes = match &self.failover {
+            Some(failover) => failover.pending(fs)?,
+            None => vec![],
+        };
+
+        let outbox_entries = Outbox::new(fs, storage_dirpath)
+            .pending()?
+            .into_iter()
+            .filter(|entry| identifiers.contains(did_of(&entry.destination)))
+            .collect();
+
+        Ok(SubjectExport {
+            did: did.to_owned(),
+            exported_time: now,
+            connection,
+            queued_messages: self
+                .queue
+                .messages(did)
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            distribution_lists,
+            anomalies,
+            usage_records,
+            stored_blobs: self
+                .blobs
+                .as_ref()
+                .map(|blobs| blobs.list(did, now).keys)
+                .unwrap_or_default(),
+            delivery_statuses: self
+                .tracker
+                .as_ref()
+                .map(|tracker| tracker.of_sender(did, now))
+                .unwrap_or_default(),
+            pending_writes: pending_writes
+                .into_iter()
+                .filter(|write| identifiers.contains(did_of(&write.sender)))
+                .collect(),
+            dead_letters: self
+                .dead_letters
+                .iter()
+                .flat_map(DeadLetters::all)
+                .filter(|letter| identifiers.contains(did_of(&letter.sender)))
+                .map(|letter| letter.message)
+                .collect(),
+            outbox_entries,
+        })
+    }
+
+    /// Erases the data held about a DID, recording the erasure
+    pub fn erase(
+        &self,
+        fs: &mut dyn FileSystem,
+        storage_dirpath: &str,
+        did: &str,
+        now: i64,
+    ) -> Result<ErasureRecord, SubjectError> {
+        let connection = self.connections.get(did);
+        let identifiers = identifiers(did, connection.as_ref());
+
+        // Logs are anonymized first, so that a failure leaves the rest of
+        // the data in place for the erasure to be retried
+        let mut reports =
+            anomaly::read_anomaly_log(fs, storage_dirpath).map_err(SubjectError::IoError)?;
+        let mut anomalies = 0;
+        for report in reports.iter_mut().filter(|r| is_about(r, &identifiers)) {
+            let envelope = &mut report.envelope;
+            if identifiers.contains(did_of(&envelope.recipient)) {
+                envelope.recipient = ANONYMIZED.to_owned();
+            }
+            if let Some(sender) = &mut envelope.sender {
+                if identifiers.contains(did_of(sender)) {
+                    *sender = ANONYMIZED.to_owned();
+                }
+            }
+            anomalies += 1;
+        }
+        if anomalies > 0 {
+            anomaly::rewrite_anomaly_log(fs, storage_dirpath, &reports)
+                .map_err(SubjectError::IoError)?;
+        }
+
+        let mut ledger = UsageLedger::new(fs, storage_dirpath);
+        let mut records = ledger.records(None, None)?;
+        let mut usage_records = 0;
+        for record in records.iter_mut().filter(|r| r.connection == did) {
+            record.connection = ANONYMIZED.to_owned();
+            usage_records += 1;
+        }
+        if usage_records > 0 {
+            ledger.rewrite(&records)?;
+        }
+
+        let secret = hash_secret(fs, storage_dirpath).map_err(SubjectError::IoError)?;
+
+        // Stored writes and sends are dropped before data in memory, for
+        // the same reason
+        let pending_writes = match &self.failover {
+            Some(failover) => failover.forget(fs, |sender| identifiers.contains(did_of(sender)))?,
+            None => 0,
+        };
+
+        let mut outbox = Outbox::new(fs, storage_dirpath);
+        let mut outbox_entries = 0;
+        for entry in outbox.pending()? {
+            if identifiers.contains(did_of(&entry.destination)) {
+                outbox.remove(&entry)?;
+                outbox_entries += 1;
+            }
+        }
+
+        match self.connections.delete(did) {
+            Ok(()) | Err(ArchivalError::Repository(RepositoryError::NotFound(_))) => (),
+            Err(err) => return Err(err.into()),
+        }
+
+        let record = ErasureRecord {
+            subject_hash: subject_hash(&secret, did),
+            erased_time: now,
+            connection: connection.is_some(),
+            queued_messages: self.queue.purge(did),
+            distribution_lists: self.lists.forget(did).len(),
+            anomalies,
+            usage_records,
+            stored_blobs: self.blobs.as_ref().map_or(0, |blobs| blobs.forget(did)),
+            delivery_statuses: self.tracker.as_ref().map_or(0, |t| t.forget(did)),
+            pending_writes,
+            dead_letters: self.dead_letters.as_ref().map_or(0, |letters| {
+                letters.remove(|letter| identifiers.contains(did_of(&letter.sender)))
+            }),
+            outbox_entries,
+        };
+
+        let line = serde_json::to_string(&record).map_err(std::io::Error::other);
+        let dirpath = format!("{storage_dirpath}/audit");
+        line.and_then(|line| {
+            fs.create_dir_all(&dirpath)?;
+            fs.append(&format!("{dirpath}/erasures.jsonl"), &(line + "\n"))
+        })
+        .map_err(|err| {
+            tracing::error!("failed to record erasure of {}: {err}", record.subject_hash);
+            SubjectError::IoError(err)
+        })?;
+
+        tracing::info!("erased data of {}", record.subject_hash);
+        Ok(record)
+    }
+}
+
+/// DIDs designating a subject: its own, and those of the keys routed to it
+fn identifiers(did: &str, connection: Option<&Connection>) -> BTreeSet<String> {
+    let keys = connection.into_iter().flat_map(|c| &c.keylist);
+    std::iter::once(did)
+        .chain(keys.map(|key| did_of(key)))
+        .map(str::to_owned)
+        .collect()
+}
+
+fn is_about(report: &AnomalyReport, identifiers: &BTreeSet<String>) -> bool {
+    let envelope = &report.envelope;
+    identifiers.contains(did_of(&envelope.recipient))
+        || envelope
+            .sender
+            .as_deref()
+            .is_some_and(|sender| identifiers.contains(did_of(sender)))
+}
+
+/// DID of a DID or key identifier
+fn did_of(kid: &str) -> &str {
+    kid.split('#').next().unwrap_or(kid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        anomaly::{EnvelopeMetadata, Verdict},
+        archival::ArchivalConfig,
+        failover::{FailoverConfig, Role, StorageMode},
+        handler::{MessageHandlers, UnknownTypePolicy},
+        lists::DistributionList,
+        outbox::Transaction,
+        repository::{MemoryRepository, Repository},
+        storage::StorageQuota,
+    };
+    use did_endpoint::util::test_utils::{MemoryFileSystem, TEST_EPOCH};
+    use serde_json::json;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+    const BOB: &str = "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7";
+    const LIST: &str = "did:web:mediator.example:lists:team";
+
+    fn anomaly(sender: Option<&str>, recipient: &str) -> AnomalyReport {
+        AnomalyReport {
+            envelope: EnvelopeMetadata {
+                sender: sender.map(str::to_owned),
+                recipient: recipient.to_owned(),
+                size: 512,
+                received_time: TEST_EPOCH,
+            },
+            verdict: Verdict::Suspicious {
+                reason: "flood".to_owned(),
+                throttle: true,
+            },
+        }
+    }
+
+    fn usage(connection: &str) -> UsageRecord {
+        UsageRecord {
+            connection: connection.to_owned(),
+            period_start: TEST_EPOCH - 3600,
+            period_end: TEST_EPOCH,
+            messages: 3,
+            bytes: 1536,
+            storage_byte_days: 0.0,
+        }
+    }
+
+    /// Data about Alice, along with data about Bob
+    fn setup(fs: &mut MemoryFileSystem) -> DataSubjects {
+        let repository = MemoryRepository::new();
+        for client_did in [ALICE, BOB] {
+            let connection = Connection {
+                client_did: client_did.to_owned(),
+                keylist: vec![format!("{client_did}#key-0")],
+                ..Default::default()
+            };
+            repository.insert(connection).unwrap();
+        }
+        let blobs = Arc::new(BlobStore::new(
+            StorageQuota::default(),
+            Arc::new(MemoryRepository::new()),
+            Arc::new(repository.clone()),
+        ));
+        let connections = Connections::new(Arc::new(repository), ArchivalConfig::default());
+
+        let queue = Arc::new(PickupQueue::default());
+        for client_did in [ALICE, BOB] {
+            let message = QueuedMessage {
+                id: format!("msg-{client_did}"),
+                recipient_did: format!("{client_did}#key-0"),
+                payload: String::from(r#"{"ciphertext":"..."}"#),
+                received_time: TEST_EPOCH,
+                ..Default::default()
+            };
+            queue.enqueue(client_did, message);
+        }
+
+        let lists = DistributionLists::new();
+        let list = DistributionList {
+            members: BTreeSet::from([ALICE.to_owned(), BOB.to_owned()]),
+            senders: BTreeSet::from([ALICE.to_owned()]),
+        };
+        lists.set(LIST, list).unwrap();
+
+        let reports = [
+            anomaly(None, &format!("{ALICE}#key-0")),
+            anomaly(Some(BOB), ALICE),
+            anomaly(Some(BOB), "did:example:carol"),
+        ];
+        anomaly::rewrite_anomaly_log(fs, "storage", &reports).unwrap();
+        UsageLedger::new(fs, "storage")
+            .append(&[usage(ALICE), usage(BOB)])
+            .unwrap();
+
+        let tracker = DeliveryTracker::default();
+        let failover = Failover::new(
+            FailoverConfig {
+                role: Role::Standby,
+                storage_mode: StorageMode::Replicated,
+            },
+            "storage",
+        );
+        let dead_letters = DeadLetters::default();
+        let handlers = MessageHandlers::new()
+            .with_unknown_type_policy(UnknownTypePolicy::DeadLetter)
+            .with_dead_letters(dead_letters.clone());
+        let mut transaction = Transaction::new();
+
+        for client_did in [ALICE, BOB] {
+            blobs
+                .put(client_did, "backup", vec![0; 16], None, TEST_EPOCH)
+                .unwrap();
+
+            let forward = json!({"id": format!("fwd-{client_did}"), "please_ack": []});
+            tracker.track(client_did, &forward, TEST_EPOCH);
+
+            let update = json!({"id": "1", "type": crate::constants::KEYLIST_UPDATE_2_0});
+            failover.admit(fs, client_did, &update, TEST_EPOCH).unwrap();
+
+            let unknown = json!({"id": "2", "type": "https://example.com/ping/1.0/ping"});
+            handlers.dispatch(client_did, &unknown).unwrap();
+
+            let destination = format!("{client_did}#key-0");
+            transaction.send(&destination, json!({"id": "3"}), TEST_EPOCH);
+        }
+        Outbox::new(fs, "storage").commit(&transaction).unwrap();
+
+        DataSubjects::new(connections, queue, lists)
+            .with_blobs(blobs)
+            .with_tracker(tracker)
+            .with_failover(failover)
+            .with_dead_letters(dead_letters)
+    }
+
+    #[test]
+    fn can_export_subject_data() {
+        let mut fs = MemoryFileSystem::new();
+        let subjects = setup(&mut fs);
+
+        let export = subjects
+            .export(&mut fs, "storage", ALICE, TEST_EPOCH)
+            .unwrap();
+        assert_eq!(export.connection.as_ref().unwrap().client_did, ALICE);
+        assert_eq!(export.queued_messages.len(), 1);
+        assert_eq!(export.queued_messages[0].size, 20);
+        assert_eq!(
+            export.distribution_lists,
+            [ListMembership {
+                list_did: LIST.to_owned(),
+                member: true,
+                sender: true,
+            }]
+        );
+        assert_eq!(export.anomalies.len(), 2);
+        assert_eq!(export.usage_records, [usage(ALICE)]);
+        assert_eq!(export.stored_blobs[0].size, 16);
+        assert_eq!(
+            export.delivery_statuses[0].message_id,
+            format!("fwd-{ALICE}")
+        );
+        assert_eq!(export.pending_writes.len(), 1);
+        assert_eq!(export.pending_writes[0].sender, ALICE);
+        assert_eq!(export.dead_letters.len(), 1);
+        assert_eq!(export.outbox_entries.len(), 1);
+        assert_eq!(
+            export.outbox_entries[0].destination,
+            format!("{ALICE}#key-0")
+        );
+
+        // Payloads are not exported
+        let json = serde_json::to_string(&export).unwrap();
+        assert!(!json.contains("ciphertext"));
+    }
+
+    #[test]
+    fn can_erase_subject_data() {
+        let mut fs = MemoryFileSystem::new();
+        let subjects = setup(&mut fs);
+
+        let record = subjects
+            .erase(&mut fs, "storage", ALICE, TEST_EPOCH)
+            .unwrap();
+        let secret = hash_secret(&mut fs, "storage").unwrap();
+        assert_eq!(
+            record,
+            ErasureRecord {
+                subject_hash: subject_hash(&secret, ALICE),
+                erased_time: TEST_EPOCH,
+                connection: true,
+                queued_messages: 1,
+                distribution_lists: 1,
+                anomalies: 2,
+                usage_records: 1,
+                stored_blobs: 1,
+                delivery_statuses: 1,
+                pending_writes: 1,
+                dead_letters: 1,
+                outbox_entries: 1,
+            }
+        );
+
+        let export = subjects
+            .export(&mut fs, "storage", ALICE, TEST_EPOCH)
+            .unwrap();
+        assert_eq!(export.connection, None);
+        assert!(export.queued_messages.is_empty());
+        assert!(export.distribution_lists.is_empty());
+        assert!(export.anomalies.is_empty());
+        assert!(export.usage_records.is_empty());
+        assert!(export.stored_blobs.is_empty());
+        assert!(export.delivery_statuses.is_empty());
+        assert!(export.pending_writes.is_empty());
+        assert!(export.dead_letters.is_empty());
+        assert!(export.outbox_entries.is_empty());
+
+        // Entries about Alice are anonymized rather than dropped, while the
+        // data about Bob is kept
+        let reports = anomaly::read_anomaly_log(&fs, "storage").unwrap();
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].envelope.recipient, ANONYMIZED);
+        assert_eq!(reports[1].envelope.recipient, ANONYMIZED);
+        assert_eq!(reports[1].envelope.sender.as_deref(), Some(BOB));
+
+        let export = subjects
+            .export(&mut fs, "storage", BOB, TEST_EPOCH)
+            .unwrap();
+        assert!(export.connection.is_some());
+        assert_eq!(export.queued_messages.len(), 1);
+        assert_eq!(export.distribution_lists.len(), 1);
+        assert_eq!(export.anomalies.len(), 2);
+        assert_eq!(export.usage_records, [usage(BOB)]);
+        assert_eq!(export.stored_blobs.len(), 1);
+        assert_eq!(export.delivery_statuses.len(), 1);
+        assert_eq!(export.pending_writes.len(), 1);
+        assert_eq!(export.dead_letters.len(), 1);
+        assert_eq!(export.outbox_entries.len(), 1);
+
+        // The erasure is recorded without the DID
+        let log = fs.get("storage/audit/erasures.jsonl").unwrap();
+        assert!(log.contains(&record.subject_hash));
+        assert!(!log.contains(ALICE));
+    }
+
+    #[test]
+    fn can_key_subject_hashes() {
+        let mut fs = MemoryFileSystem::new();
+
+        // The secret is generated once
+        let secret = hash_secret(&mut fs, "storage").unwrap();
+        assert_eq!(secret.len(), 32);
+        assert_eq!(hash_secret(&mut fs, "storage").unwrap(), secret);
+
+        // Hashes cannot be recomputed without it
+        let hash = subject_hash(&secret, ALICE);
+        assert_eq!(hash, subject_hash(&secret, ALICE));
+        assert_ne!(hash, subject_hash(b"guessed", ALICE));
+        assert_ne!(
+            hash,
+            multibase::encode(
+                Base::Base58Btc,
+                did_utils::crypto::sha256_hash::sha256_hash(ALICE.as_bytes())
+            )
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Time series of pickup queue activity, for capacity planning.
+//!
+//! The pickup queue is sampled every minute into a [`QueueTimeSeries`],
+//! recording its depth along with the rates at which messages entered
+//! and left it since the previous sample. The series is capped, the
+//! oldest samples being dropped first, so that operators can follow
+//! trends over the last day without standing up a metrics stack.
+
+use serde::{Deserialize, Serialize};
+use server_plugin::tasks::BackgroundTasks;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use utoipa::ToSchema;
+
+use crate::pickup::{PickupQueue, QueueTotals};
+
+/// Interval between samples
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Samples kept by default, covering a day
+const DEFAULT_CAPACITY: usize = 1440;
+
+/// State of the pickup queue at some time
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueSample {
+    /// Time of the sample, as a UNIX timestamp
+    pub time: i64,
+
+    /// Messages awaiting pickup
+    pub depth: u64,
+
+    /// Messages queued per minute since the previous sample
+    pub ingress_rate: f64,
+
+    /// Messages delivered per minute since the previous sample
+    pub delivery_rate: f64,
+}
+
+/// Capped series of queue samples. Clones share the same samples.
+#[derive(Debug, Clone)]
+pub struct QueueTimeSeries {
+    series: Arc<Mutex<Series>>,
+}
+
+#[derive(Debug)]
+struct Series {
+    capacity: usize,
+    samples: VecDeque<QueueSample>,
+
+    /// Totals as of the last sample, from which rates derive
+    last: Option<(i64, QueueTotals)>,
+}
+
+impl Default for QueueTimeSeries {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl QueueTimeSeries {
+    pub fn new(capacity: usize) -> Self {
+        let series = Series {
+            capacity: capacity.max(1),
+            samples: VecDeque::new(),
+            last: None,
+        };
+
+        Self {
+            series: Arc::new(Mutex::new(series)),
+        }
+    }
+
+    /// Records the totals of the queue. Rates of the first sample are zero.
+    pub fn record(&self, totals: QueueTotals, now: i64) {
+        let mut series = self.series.lock().unwrap();
+
+        let (ingress_rate, delivery_rate) = match series.last {
+            Some((time, last)) if now > time => {
+                let minutes = (now - time) as f64 / 60.0;
+                let rate = |current: u64, last: u64| current.saturating_sub(last) as f64 / minutes;
+                (
+                    rate(totals.enqueued, last.enqueued),
+                    rate(totals.delivered, last.delivered),
+                )
+            }
+            _ => (0.0, 0.0),
+        };
+
+        if series.samples.len() == series.capacity {
+            series.samples.pop_front();
+        }
+        series.samples.push_back(QueueSample {
+            time: now,
+            depth: totals.depth,
+            ingress_rate,
+            delivery_rate,
+        });
+        series.last = Some((now, totals));
+    }
+
+    /// Samples taken from a time on, if given, oldest first
+    pub fn samples(&self, since: Option<i64>) -> Vec<QueueSample> {
+        let series = self.series.lock().unwrap();
+        series
+            .samples
+            .iter()
+            .filter(|sample| since.is_none_or(|since| sample.time >= since))
+            .cloned()
+            .collect()
+    }
+
+    /// Samples a queue every minute on a background task
+    pub fn sample_periodically(&self, tasks: &BackgroundTasks, queue: Arc<PickupQueue>) {
+        let series = self.clone();
+
+        tasks.every("queue-sampling", SAMPLE_INTERVAL, move || {
+            let now = chrono::Utc::now().timestamp();
+            series.record(queue.totals(), now);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn totals(depth: u64, enqueued: u64, delivered: u64) -> QueueTotals {
+        QueueTotals {
+            depth,
+            enqueued,
+            delivered,
+        }
+    }
+
+    #[test]
+    fn test_recording_samples() {
+        let series = QueueTimeSeries::new(3);
+        series.record(totals(10, 10, 0), 0);
+        series.record(totals(25, 40, 15), 60);
+        series.record(totals(5, 40, 50), 180);
+
+        let samples = series.samples(None);
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[0].ingress_rate, 0.0);
+        assert_eq!(samples[1].ingress_rate, 30.0);
+        assert_eq!(samples[1].delivery_rate, 15.0);
+        assert_eq!(samples[2].ingress_rate, 0.0);
+        assert_eq!(samples[2].delivery_rate, 17.5);
+        assert_eq!(samples[2].depth, 5);
+
+        assert_eq!(series.samples(Some(60)).len(), 2);
+    }
+
+    #[test]
+    fn test_capping_samples() {
+        let series = QueueTimeSeries::new(2);
+        for minute in 0..5 {
+            series.record(totals(minute, minute, 0), minute as i64 * 60);
+        }
+
+        let times: Vec<_> = series.samples(None).iter().map(|s| s.time).collect();
+        assert_eq!(times, [180, 240]);
+    }
+}
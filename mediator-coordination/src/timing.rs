@@ -0,0 +1,381 @@
+//! Message TTL and scheduled delivery of forwarded messages.
+//!
+//! Senders bound the lifetime of a forwarded message with the standard
+//! `expires_time` header of the forward message, and schedule its delivery
+//! with the `deliver_after` header, both UNIX timestamps. Messages expired
+//! before being acknowledged are dropped from the pickup queue, and their
+//! sender is notified if it set the `expiry_notice` header.
+//!
+//! Recipients may set a [`TimingPolicy`] per connection, whose TTL applies
+//! to messages forwarded without expiry and caps the expiry of others, and
+//! which may request notices on behalf of all senders.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+use crate::{
+    didcomm::{problem_report::ProblemReport, validation::INVALID_MESSAGE_CODE},
+    pickup::QueuedMessage,
+};
+
+/// Header scheduling the delivery of a forwarded message
+pub const DELIVER_AFTER_HEADER: &str = "deliver_after";
+
+/// Header requesting a notice should a forwarded message expire
+pub const EXPIRY_NOTICE_HEADER: &str = "expiry_notice";
+
+/// Code of the notices of expired messages
+pub const EXPIRED_MESSAGE_CODE: &str = "e.m.msg.expired";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TimingError {
+    #[error("invalid header {0}, expected a UNIX timestamp")]
+    InvalidHeader(&'static str),
+    #[error("message already expired")]
+    AlreadyExpired,
+    #[error("message would expire before its scheduled delivery")]
+    DeliveryAfterExpiry,
+    #[error("delivery may be scheduled at most {0} seconds ahead")]
+    DelayTooLong(i64),
+}
+
+impl TimingError {
+    pub fn to_problem_report(&self) -> ProblemReport {
+        ProblemReport::new(
+            INVALID_MESSAGE_CODE,
+            Some("Invalid message timing: {1}"),
+            Some(vec![self.to_string()]),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimingConfig {
+    /// Seconds ahead deliveries may be scheduled, at most
+    pub max_delay: i64,
+}
+
+impl Default for TimingConfig {
+    fn default() -> Self {
+        Self {
+            max_delay: 30 * 24 * 3600,
+        }
+    }
+}
+
+impl TimingConfig {
+    /// Configuration from `MAX_DELIVERY_DELAY_SECS`
+    pub fn from_env() -> Self {
+        let max_delay = server_plugin::reload::var("MAX_DELIVERY_DELAY_SECS")
+            .and_then(|v| v.parse().ok())
+            .filter(|&secs: &i64| secs > 0);
+
+        Self {
+            max_delay: max_delay.unwrap_or(Self::default().max_delay),
+        }
+    }
+}
+
+/// Timing defaults of the messages forwarded to a connection
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct TimingPolicy {
+    /// Lifetime of messages, in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<i64>,
+
+    /// Whether senders are notified of expired messages, whether they
+    /// requested it or not
+    #[serde(default)]
+    pub notify_expiry: bool,
+}
+
+/// Timing of a forwarded message, resolved from its headers and the
+/// policy of its connection
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ForwardTiming {
+    pub expires_time: Option<i64>,
+    pub deliver_after: Option<i64>,
+    pub expiry_notice_to: Option<String>,
+}
+
+impl ForwardTiming {
+    /// Applies the timing to a message about to be queued
+    pub fn apply(self, message: QueuedMessage) -> QueuedMessage {
+        QueuedMessage {
+            expires_time: self.expires_time,
+            deliver_after: self.deliver_after,
+            expiry_notice_to: self.expiry_notice_to,
+            ..message
+        }
+    }
+}
+
+/// Timing policies of connections. Clones share the same policies.
+#[derive(Debug, Clone, Default)]
+pub struct TimingPolicies {
+    config: TimingConfig,
+    policies: Arc<Mutex<HashMap<String, TimingPolicy>>>,
+}
+
+impl TimingPolicies {
+    pub fn new(config: TimingConfig) -> Self {
+        Self {
+            config,
+            policies: Arc::default(),
+        }
+    }
+
+    /// Sets the policy of a connection, lifting it when it is the default
+    pub fn set(&self, connection: &str, policy: TimingPolicy) {
+        let mut policies = self.policies.lock().unwrap();
+        match policy == TimingPolicy::default() {
+            true => policies.remove(connection),
+            false => policies.insert(connection.to_owned(), policy),
+        };
+    }
+
+    pub fn get(&self, connection: &str) -> TimingPolicy {
+        let policies = self.policies.lock().unwrap();
+        policies.get(connection).copied().unwrap_or_default()
+    }
+
+    /// Resolves the timing of a forward message from an authenticated
+    /// sender to a connection
+    pub fn resolve(
+        &self,
+        connection: &str,
+        sender: &str,
+        forward: &Value,
+        now: i64,
+    ) -> Result<ForwardTiming, TimingError> {
+        let policy = self.get(connection);
+        let timestamp = |name: &'static str| match forward.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => value
+                .as_i64()
+                .map(Some)
+                .ok_or(TimingError::InvalidHeader(name)),
+        };
+
+        let expires_time = match (timestamp("expires_time")?, policy.ttl) {
+            (Some(time), Some(ttl)) => Some(time.min(now + ttl)),
+            (time, ttl) => time.or(ttl.map(|ttl| now + ttl)),
+        };
+        if expires_time.is_some_and(|time| time <= now) {
+            return Err(TimingError::AlreadyExpired);
+        }
+
+        // Deliveries due already are not held back
+        let deliver_after = timestamp(DELIVER_AFTER_HEADER)?.filter(|&time| time > now);
+        if let Some(time) = deliver_after {
+            if time - now > self.config.max_delay {
+                return Err(TimingError::DelayTooLong(self.config.max_delay));
+            }
+            if expires_time.is_some_and(|expiry| time >= expiry) {
+                return Err(TimingError::DeliveryAfterExpiry);
+            }
+        }
+
+        let notice_requested = forward
+            .get(EXPIRY_NOTICE_HEADER)
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        let expiry_notice_to = (expires_time.is_some()
+            && (notice_requested || policy.notify_expiry))
+            .then(|| sender.to_owned());
+
+        Ok(ForwardTiming {
+            expires_time,
+            deliver_after,
+            expiry_notice_to,
+        })
+    }
+}
+
+/// Notice of an expired message, along with the sender to send it to, if
+/// requested
+pub fn expiry_notice(message: &QueuedMessage) -> Option<(String, ProblemReport)> {
+    let sender = message.expiry_notice_to.clone()?;
+    let report = ProblemReport::new(
+        EXPIRED_MESSAGE_CODE,
+        Some("Message {1} expired before its recipient picked it up"),
+        Some(vec![message.id.clone()]),
+    )
+    .with_pthid(Some(&message.id));
+
+    Some((sender, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        delivery::DeliveryTracker,
+        model::delivery::DeliveryState,
+        pickup::{PickupConfig, PickupQueue},
+    };
+    use serde_json::json;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+    const BOB: &str = "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7";
+    const NOW: i64 = 1_700_000_000;
+
+    fn message(id: &str) -> QueuedMessage {
+        QueuedMessage {
+            id: id.to_owned(),
+            recipient_did: ALICE.to_owned(),
+            payload: String::from(r#"{"ciphertext":"..."}"#),
+            received_time: NOW,
+            ..Default::default()
+        }
+    }
+
+    fn ids(messages: &[QueuedMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_resolving_timing_from_headers_and_policy() {
+        let policies = TimingPolicies::new(TimingConfig { max_delay: 3600 });
+        let forward = json!({
+            "id": "fwd-1",
+            "expires_time": NOW + 600,
+            "deliver_after": NOW + 60,
+            "expiry_notice": true
+        });
+
+        let timing = policies.resolve(ALICE, BOB, &forward, NOW).unwrap();
+        assert_eq!(
+            timing,
+            ForwardTiming {
+                expires_time: Some(NOW + 600),
+                deliver_after: Some(NOW + 60),
+                expiry_notice_to: Some(BOB.to_owned()),
+            }
+        );
+
+        // Policies cap expiries, and apply to messages without any
+        policies.set(
+            ALICE,
+            TimingPolicy {
+                ttl: Some(300),
+                notify_expiry: false,
+            },
+        );
+        let timing = policies.resolve(ALICE, BOB, &forward, NOW).unwrap();
+        assert_eq!(timing.expires_time, Some(NOW + 300));
+        let timing = policies
+            .resolve(ALICE, BOB, &json!({"id": "fwd-2"}), NOW)
+            .unwrap();
+        assert_eq!(timing.expires_time, Some(NOW + 300));
+        assert_eq!(timing.expiry_notice_to, None);
+
+        // Other connections are unaffected
+        let timing = policies
+            .resolve(BOB, ALICE, &json!({"id": "fwd-3"}), NOW)
+            .unwrap();
+        assert_eq!(timing, ForwardTiming::default());
+    }
+
+    #[test]
+    fn test_rejecting_invalid_timing() {
+        let policies = TimingPolicies::new(TimingConfig { max_delay: 3600 });
+        let resolve = |forward: Value| policies.resolve(ALICE, BOB, &forward, NOW);
+
+        assert_eq!(
+            resolve(json!({"expires_time": NOW})),
+            Err(TimingError::AlreadyExpired)
+        );
+        assert_eq!(
+            resolve(json!({"deliver_after": "tomorrow"})),
+            Err(TimingError::InvalidHeader(DELIVER_AFTER_HEADER))
+        );
+        assert_eq!(
+            resolve(json!({"deliver_after": NOW + 3601})),
+            Err(TimingError::DelayTooLong(3600))
+        );
+        assert_eq!(
+            resolve(json!({"expires_time": NOW + 60, "deliver_after": NOW + 60})),
+            Err(TimingError::DeliveryAfterExpiry)
+        );
+
+        // Deliveries due already are not held back
+        assert_eq!(
+            resolve(json!({"deliver_after": NOW}))
+                .unwrap()
+                .deliver_after,
+            None
+        );
+    }
+
+    #[test]
+    fn test_holding_scheduled_messages() {
+        let queue = PickupQueue::default();
+        let timing = ForwardTiming {
+            deliver_after: Some(NOW + 60),
+            ..Default::default()
+        };
+        queue.enqueue(ALICE, timing.apply(message("later")));
+        queue.enqueue(ALICE, message("now"));
+
+        let batch = queue.next_batch(ALICE, None, 10, NOW);
+        assert_eq!(ids(&batch.messages), ["now"]);
+        assert_eq!(queue.status(ALICE, None, NOW + 59).message_count, 1);
+        assert_eq!(queue.totals().depth, 2);
+        queue.acknowledge(ALICE, &["now".to_owned()], NOW);
+
+        let batch = queue.next_batch(ALICE, None, 10, NOW + 60);
+        assert_eq!(ids(&batch.messages), ["later"]);
+    }
+
+    #[test]
+    fn test_dropping_expired_messages() {
+        let tracker = DeliveryTracker::default();
+        let queue = PickupQueue::new(PickupConfig {
+            redelivery_after: 30,
+            ..Default::default()
+        })
+        .with_tracker(tracker.clone());
+        tracker.track(BOB, &json!({"id": "short", "please_ack": [""]}), NOW);
+
+        let expiring = |id: &str, expires_time: i64, deliver_after: Option<i64>| {
+            let timing = ForwardTiming {
+                expires_time: Some(expires_time),
+                deliver_after,
+                expiry_notice_to: Some(BOB.to_owned()),
+            };
+            timing.apply(message(id))
+        };
+        queue.enqueue(ALICE, expiring("short", NOW + 10, None));
+        queue.enqueue(ALICE, expiring("long", NOW + 100, None));
+        queue.enqueue(ALICE, expiring("held", NOW + 50, Some(NOW + 40)));
+        queue.enqueue(ALICE, message("forever"));
+
+        // Delivered messages expire all the same unless acknowledged
+        let batch = queue.next_batch(ALICE, None, 1, NOW);
+        assert_eq!(ids(&batch.messages), ["short"]);
+
+        assert_eq!(queue.status(ALICE, None, NOW + 10).message_count, 2);
+        let state = tracker.report(BOB, &["short".to_owned()], NOW + 10)[0].state;
+        assert_eq!(state, DeliveryState::Expired);
+
+        // Held messages may expire without ever being delivered
+        let expired = queue.expire(NOW + 50);
+        assert_eq!(ids(&expired), ["short", "held"]);
+        assert!(queue.expire(NOW + 50).is_empty());
+
+        let batch = queue.next_batch(ALICE, None, 10, NOW + 50);
+        assert_eq!(ids(&batch.messages), ["long", "forever"]);
+
+        let (sender, notice) = expiry_notice(&expired[0]).unwrap();
+        assert_eq!(sender, BOB);
+        assert_eq!(notice.body.code, EXPIRED_MESSAGE_CODE);
+        assert_eq!(notice.pthid.as_deref(), Some("short"));
+        assert_eq!(expiry_notice(&message("forever")), None);
+    }
+}
@@ -0,0 +1,265 @@
+//! Hop-by-hop tracing of routed messages.
+//!
+//! Debugging latency across several mediators requires knowing when each
+//! of them received and dispatched a message. When tracing is enabled for
+//! a connection, the mediator appends an entry with its identifier and
+//! these timestamps to the ephemeral `trace` header of the messages it
+//! dispatches for that connection, after the entries of the message it
+//! received from the previous hop.
+//!
+//! Entries disclose nothing about senders or recipients, only when each
+//! mediator handled the message. Tracing is off unless enabled with
+//! `DIDCOMM_TRACE`, in which case connections still have to opt in.
+//! Otherwise the header is stripped from dispatched messages.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+/// Header holding the trace entries of a message
+pub const TRACE_HEADER: &str = "trace";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceConfig {
+    /// Whether connections may enable tracing at all
+    pub enabled: bool,
+
+    /// Number of entries kept on a message, the oldest being dropped
+    pub max_hops: usize,
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_hops: 16,
+        }
+    }
+}
+
+impl TraceConfig {
+    /// Reads `DIDCOMM_TRACE` (`on` or `off`) and `DIDCOMM_TRACE_MAX_HOPS`,
+    /// falling back to defaults for unset or invalid values.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |key: &str| server_plugin::reload::var(key);
+
+        Self {
+            enabled: var("DIDCOMM_TRACE").map_or(default.enabled, |v| v == "on"),
+            max_hops: var("DIDCOMM_TRACE_MAX_HOPS")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.max_hops),
+        }
+    }
+}
+
+/// Passage of a message through a mediator
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TraceEntry {
+    /// Identifier of the mediator, usually its DID
+    pub mediator: String,
+
+    /// Time the mediator received the message, in milliseconds
+    pub received_time: i64,
+
+    /// Time the mediator dispatched the message, in milliseconds
+    pub dispatched_time: i64,
+}
+
+/// Appends trace entries for the connections having enabled tracing.
+/// Clones share the same connections.
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    config: TraceConfig,
+    mediator: String,
+    connections: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Tracer {
+    pub fn new(config: TraceConfig, mediator: &str) -> Self {
+        Self {
+            config,
+            mediator: mediator.to_owned(),
+            connections: Arc::default(),
+        }
+    }
+
+    /// Enables tracing for a connection, returning whether tracing is
+    /// allowed by the configuration
+    pub fn enable(&self, connection: &str) -> bool {
+        if self.config.enabled {
+            let mut connections = self.connections.lock().unwrap();
+            connections.insert(connection.to_owned());
+        }
+
+        self.config.enabled
+    }
+
+    pub fn disable(&self, connection: &str) {
+        self.connections.lock().unwrap().remove(connection);
+    }
+
+    pub fn is_enabled(&self, connection: &str) -> bool {
+        self.config.enabled && self.connections.lock().unwrap().contains(connection)
+    }
+
+    /// Sets the trace header of a message dispatched for a connection to
+    /// the entries of the message received from the previous hop, followed
+    /// by an entry of this mediator. The header is stripped instead if the
+    /// connection has not enabled tracing.
+    ///
+    /// Malformed entries are dropped, as are fields unknown to entries.
+    pub fn relay(
+        &self,
+        connection: &str,
+        received: &Value,
+        dispatched: &mut Value,
+        received_time: i64,
+        dispatched_time: i64,
+    ) {
+        if !self.is_enabled(connection) {
+            strip(dispatched);
+            return;
+        }
+
+        let mut entries = entries(received);
+        entries.push(TraceEntry {
+            mediator: self.mediator.clone(),
+            received_time,
+            dispatched_time,
+        });
+
+        let excess = entries.len().saturating_sub(self.config.max_hops);
+        entries.drain(..excess);
+
+        if let Some(message) = dispatched.as_object_mut() {
+            message.insert(TRACE_HEADER.to_owned(), serde_json::json!(entries));
+        }
+    }
+}
+
+/// Trace entries of a message, skipping malformed ones
+pub fn entries(message: &Value) -> Vec<TraceEntry> {
+    message
+        .get(TRACE_HEADER)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| serde_json::from_value(entry.clone()).ok())
+        .collect()
+}
+
+/// Removes the trace header of a message
+pub fn strip(message: &mut Value) {
+    if let Some(message) = message.as_object_mut() {
+        message.remove(TRACE_HEADER);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const MEDIATOR: &str = "did:web:mediators-r-us.com";
+    const CONNECTION: &str = "did:key:z6MkfyTREjTxQ8hUwSwBPeDHf3uPL3qCjSSuNPwsyMpWUGH7";
+
+    fn received() -> Value {
+        json!({
+            "id": "fwd-0",
+            "type": "https://didcomm.org/routing/2.0/forward",
+            "body": {"next": CONNECTION},
+            "trace": [
+                {
+                    "mediator": "did:web:upstream.example",
+                    "received_time": 1000,
+                    "dispatched_time": 1004,
+                    "sender": "did:example:alice"
+                },
+                {"mediator": "did:web:malformed.example"}
+            ]
+        })
+    }
+
+    fn dispatched() -> Value {
+        json!({
+            "id": "fwd-1",
+            "type": "https://didcomm.org/routing/2.0/forward",
+            "body": {"next": CONNECTION},
+            "trace": [{"mediator": "did:web:forged.example"}]
+        })
+    }
+
+    fn tracer(max_hops: usize) -> Tracer {
+        let config = TraceConfig {
+            enabled: true,
+            max_hops,
+        };
+        Tracer::new(config, MEDIATOR)
+    }
+
+    #[test]
+    fn can_append_entries_for_enabled_connections() {
+        let tracer = tracer(16);
+        assert!(tracer.enable(CONNECTION));
+
+        let mut message = dispatched();
+        tracer.relay(CONNECTION, &received(), &mut message, 1010, 1012);
+
+        assert_eq!(
+            message["trace"],
+            json!([
+                {
+                    "mediator": "did:web:upstream.example",
+                    "received_time": 1000,
+                    "dispatched_time": 1004
+                },
+                {
+                    "mediator": MEDIATOR,
+                    "received_time": 1010,
+                    "dispatched_time": 1012
+                }
+            ])
+        );
+    }
+
+    #[test]
+    fn should_keep_latest_entries() {
+        let tracer = tracer(1);
+        tracer.enable(CONNECTION);
+
+        let mut message = dispatched();
+        tracer.relay(CONNECTION, &received(), &mut message, 1010, 1012);
+
+        let entries = entries(&message);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].mediator, MEDIATOR);
+    }
+
+    #[test]
+    fn should_strip_header_unless_enabled() {
+        let tracer = tracer(16);
+
+        let mut message = dispatched();
+        tracer.relay(CONNECTION, &received(), &mut message, 1010, 1012);
+        assert!(message.get(TRACE_HEADER).is_none());
+
+        tracer.enable(CONNECTION);
+        tracer.disable(CONNECTION);
+        let mut message = dispatched();
+        tracer.relay(CONNECTION, &received(), &mut message, 1010, 1012);
+        assert!(message.get(TRACE_HEADER).is_none());
+
+        // Connections cannot opt in when tracing is off
+        let tracer = Tracer::new(TraceConfig::default(), MEDIATOR);
+        assert!(!tracer.enable(CONNECTION));
+        assert!(!tracer.is_enabled(CONNECTION));
+
+        let mut message = dispatched();
+        tracer.relay(CONNECTION, &received(), &mut message, 1010, 1012);
+        assert!(message.get(TRACE_HEADER).is_none());
+    }
+}
@@ -12,7 +12,7 @@ use serde_json::Error as SerdeError;
 use std::io;
 
 #[cfg(test)]
-use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use did_endpoint::util::test_utils::MemoryFileSystem;
 
 /// Custom error type that wraps different kinds of errors that could occur.
 #[derive(Debug)]
@@ -88,6 +88,30 @@ pub fn extract_agreement_key(diddoc: &Document) -> Option<(String, Jwk)> {
     extract_key_from_diddoc!(KeyAgreement)(diddoc, method)
 }
 
+/// Search an assertion key by verification method ID in a DID document.
+///
+/// Keys not referenced as assertion methods are not found.
+pub fn find_assertion_key(diddoc: &Document, vm_id: &str) -> Option<(String, Jwk)> {
+    diddoc
+        .assertion_method
+        .as_ref()?
+        .iter()
+        .filter_map(|method| extract_key_from_diddoc!(AssertionMethod)(diddoc, method))
+        .find(|(id, _)| id == vm_id)
+}
+
+/// Search an agreement key by verification method ID in a DID document.
+///
+/// Keys not referenced as key agreements are not found.
+pub fn find_agreement_key(diddoc: &Document, vm_id: &str) -> Option<(String, Jwk)> {
+    diddoc
+        .key_agreement
+        .as_ref()?
+        .iter()
+        .filter_map(|method| extract_key_from_diddoc!(KeyAgreement)(diddoc, method))
+        .find(|(id, _)| id == vm_id)
+}
+
 /// Reads public JWK from verification method.
 ///
 /// Return verification method ID and JWK if present, else Option::None.
@@ -98,6 +122,22 @@ fn extract_public_jwk_from_vm(vm: &VerificationMethod) -> Option<(String, Jwk)>
     })
 }
 
+/// In-memory file system holding the DID document and key store of
+/// `test/storage`, under the root storage directory
+#[cfg(test)]
+pub fn mock_fs() -> MemoryFileSystem {
+    let mut fs = MemoryFileSystem::new();
+    fs.write("/did.json", include_str!("../../test/storage/did.json"))
+        .unwrap();
+    fs.write(
+        "/keystore/1697624245.json",
+        include_str!("../../test/storage/keystore/1697624245.json"),
+    )
+    .unwrap();
+
+    fs
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,14 +146,14 @@ mod tests {
 
     #[test]
     fn can_read_persisted_entities() {
-        let mut mock_fs = MockFileSystem;
+        let mut mock_fs = mock_fs();
         assert!(read_diddoc(&mock_fs, "").is_ok());
         assert!(read_keystore(&mut mock_fs, "").is_ok());
     }
 
     #[test]
     fn can_extract_assertion_key() {
-        let mock_fs = MockFileSystem;
+        let mock_fs = mock_fs();
         let diddoc = read_diddoc(&mock_fs, "").unwrap();
 
         let (vm_id, jwk) = extract_assertion_key(&diddoc).unwrap();
@@ -135,7 +175,7 @@ mod tests {
 
     #[test]
     fn can_extract_agreement_key() {
-        let mock_fs = MockFileSystem;
+        let mock_fs = mock_fs();
         let diddoc = read_diddoc(&mock_fs, "").unwrap();
 
         let (vm_id, jwk) = extract_agreement_key(&diddoc).unwrap();
@@ -155,38 +195,3 @@ mod tests {
         );
     }
 }
-
-#[cfg(test)]
-#[derive(Default)]
-pub struct MockFileSystem;
-
-#[cfg(test)]
-impl FileSystem for MockFileSystem {
-    fn read_to_string(&self, path: &str) -> IoResult<String> {
-        match path {
-            p if p.ends_with("did.json") => {
-                Ok(include_str!("../../test/storage/did.json").to_string())
-            }
-            p if p.contains("keystore") => {
-                Ok(include_str!("../../test/storage/keystore/1697624245.json").to_string())
-            }
-            _ => Err(IoError::new(ErrorKind::NotFound, "NotFound")),
-        }
-    }
-
-    fn write(&mut self, path: &str, content: &str) -> IoResult<()> {
-        Ok(())
-    }
-
-    fn read_dir_files(&self, _path: &str) -> IoResult<Vec<String>> {
-        Ok(vec!["/keystore/1697624245.json".to_string()])
-    }
-
-    fn create_dir_all(&mut self, _path: &str) -> IoResult<()> {
-        Ok(())
-    }
-
-    fn write_with_lock(&self, _path: &str, _content: &str) -> IoResult<()>{
-        Ok(())
-    }
-}
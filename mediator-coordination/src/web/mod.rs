@@ -1,7 +1,1364 @@
-use axum::Router;
-use did_endpoint::util::keystore::KeyStore;
-use did_utils::didcore::Document;
+use axum::http::StatusCode;
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post, put},
+    Router,
+};
+use did_endpoint::util::{
+    apikeys::{self, ApiKeyScope, ApiKeySecurity},
+    filesystem::StdFileSystem,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+use utoipa::{IntoParams, OpenApi};
 
-pub(crate) fn routes(_diddoc: Document, _keystore: KeyStore) -> Router {
+use crate::{
+    archival::{ArchivalError, Connections},
+    attestation::{DisclosedAttestation, ATTESTATION_PATH},
+    degradation::{DegradationLevel, LoadShedder},
+    didcomm::validation::MessageValidator,
+    failover::{Failover, FailoverError, PromotionReport, Replayer, Role, StorageMode},
+    lists::{DistributionList, DistributionLists, ListError},
+    metering::{self, UsageLedger, UsageRecord},
+    metrics::PersistentCounters,
+    model::{
+        attestation::Attestation,
+        challenge,
+        connection::{Connection, Disclosures},
+        coord, delivery, migration, pickup,
+        policy::MediatorPolicy,
+        storage, windows,
+    },
+    pickup::PickupQueue,
+    policy::{DisclosedPolicy, POLICY_PATH},
+    privacy::{DepthBucket, PublicStatistics, PublicStats, PUBLIC_STATS_PATH},
+    repository::RepositoryError,
+    scheduler::{ConnectionLimits, LimitOverrides},
+    subject::{DataSubjects, ErasureRecord, ListMembership, QueuedMessageMetadata, SubjectExport},
+    timeseries::{QueueSample, QueueTimeSeries},
+};
+
+pub(crate) fn routes(policy: DisclosedPolicy) -> Router {
+    Router::new() //
+        .route("/.well-known/didcomm/schemas", get(schemas))
+        .route(POLICY_PATH, get(mediator_policy))
+        .with_state(policy)
+}
+
+/// Route serving the signed attestation of the mediator
+pub(crate) fn attestation_routes(attestation: DisclosedAttestation) -> Router {
+    Router::new()
+        .route(ATTESTATION_PATH, get(mediator_attestation))
+        .with_state(attestation)
+}
+
+/// Health route, reporting the current degradation level, as sampled in
+/// the background.
+pub(crate) fn health_routes(shedder: LoadShedder) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .with_state(shedder)
+}
+
+/// Administrative routes, which must only be exposed to operators.
+///
+/// They export the usage records of connections for billing, and
+/// require an API key granted the `usage` scope.
+pub(crate) fn admin_routes(storage_dirpath: &str) -> Router {
+    let routes = Router::new()
+        .route("/admin/usage", get(usage_records))
+        .with_state(storage_dirpath.to_owned());
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Usage)
+}
+
+/// Route publishing the totals of persistent counters to operators,
+/// under the same API keys as usage records.
+pub(crate) fn metrics_routes(counters: PersistentCounters, storage_dirpath: &str) -> Router {
+    let routes = Router::new()
+        .route("/admin/metrics/persistent", get(persistent_metrics))
+        .with_state(counters);
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Usage)
+}
+
+/// Route publishing the time series of pickup queue activity to
+/// operators, under the same API keys as usage records.
+pub(crate) fn stats_routes(series: QueueTimeSeries, storage_dirpath: &str) -> Router {
+    let routes = Router::new()
+        .route("/admin/stats/timeseries", get(queue_timeseries))
+        .with_state(series);
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Usage)
+}
+
+/// Route publishing the exact depth of the queue of each connection to
+/// operators, under the same API keys as usage records.
+pub(crate) fn depths_routes(queue: Arc<PickupQueue>, storage_dirpath: &str) -> Router {
+    let routes = Router::new()
+        .route("/admin/stats/connections", get(queue_depths))
+        .with_state(queue);
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Usage)
+}
+
+/// Route publishing noised statistics, open to the public
+pub(crate) fn public_stats_routes(stats: PublicStatistics) -> Router {
     Router::new()
+        .route(PUBLIC_STATS_PATH, get(public_stats))
+        .with_state(stats)
+}
+
+/// Routes through which operators fail over to a standby node, which
+/// require an API key granted the `admin` scope.
+pub(crate) fn failover_routes(
+    failover: Failover,
+    replayer: Arc<dyn Replayer>,
+    storage_dirpath: &str,
+) -> Router {
+    let routes = Router::new()
+        .route("/admin/failover", get(failover_status))
+        .route("/admin/failover/promote", post(promote))
+        .with_state(FailoverState { failover, replayer });
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Admin)
+}
+
+/// Routes through which operators override the concurrency limits of
+/// connections, which require an API key granted the `admin` scope.
+pub(crate) fn limits_routes(overrides: LimitOverrides, storage_dirpath: &str) -> Router {
+    let routes = Router::new()
+        .route("/admin/connections/limits", get(connection_limits))
+        .route(
+            "/admin/connections/:connection/limits",
+            put(override_connection_limits).delete(revert_connection_limits),
+        )
+        .with_state(overrides);
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Admin)
+}
+
+/// Routes through which operators manage distribution lists, which
+/// require an API key granted the `admin` scope.
+pub(crate) fn lists_routes(lists: DistributionLists, storage_dirpath: &str) -> Router {
+    let routes = Router::new()
+        .route("/admin/lists", get(distribution_lists))
+        .route(
+            "/admin/lists/:did",
+            put(set_distribution_list).delete(remove_distribution_list),
+        )
+        .with_state(lists);
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Admin)
+}
+
+/// Routes through which operators revoke and restore connections, which
+/// require an API key granted the `admin` scope.
+pub(crate) fn connections_routes(connections: Connections, storage_dirpath: &str) -> Router {
+    let routes = Router::new()
+        .route("/admin/connections", get(list_connections))
+        .route("/admin/connections/:connection", delete(archive_connection))
+        .route(
+            "/admin/connections/:connection/restore",
+            post(restore_connection),
+        )
+        .with_state(connections);
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Admin)
+}
+
+/// Routes through which operators serve data subject requests, exporting
+/// or erasing the data held about a DID, which require an API key granted
+/// the `admin` scope.
+pub(crate) fn subjects_routes(subjects: DataSubjects, storage_dirpath: &str) -> Router {
+    let routes = Router::new()
+        .route(
+            "/admin/subjects/:did",
+            get(export_subject).delete(erase_subject),
+        )
+        .with_state(SubjectsState {
+            subjects,
+            storage_dirpath: storage_dirpath.to_owned(),
+        });
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Admin)
+}
+
+#[derive(Clone)]
+struct SubjectsState {
+    subjects: DataSubjects,
+    storage_dirpath: String,
+}
+
+#[derive(Clone)]
+struct FailoverState {
+    failover: Failover,
+    replayer: Arc<dyn Replayer>,
+}
+
+/// Validator of all message types supported by the mediator
+pub(crate) fn validator() -> MessageValidator {
+    MessageValidator::new(
+        coord::message_specs()
+            .into_iter()
+            .chain(storage::message_specs())
+            .chain(pickup::message_specs())
+            .chain(delivery::message_specs())
+            .chain(migration::message_specs())
+            .chain(windows::message_specs())
+            .chain(challenge::message_specs()),
+    )
+}
+
+/// OpenAPI description of the public routes
+#[derive(OpenApi)]
+#[openapi(
+    paths(schemas, mediator_policy, mediator_attestation, health, public_stats),
+    components(schemas(PublicStats, DepthBucket)),
+    tags((name = "mediator-coordination", description = "Capabilities and health of the mediator"))
+)]
+pub(crate) struct ApiDoc;
+
+/// OpenAPI description of the administrative routes
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        usage_records,
+        persistent_metrics,
+        queue_timeseries,
+        queue_depths,
+        failover_status,
+        promote,
+        connection_limits,
+        override_connection_limits,
+        revert_connection_limits,
+        distribution_lists,
+        set_distribution_list,
+        remove_distribution_list,
+        list_connections,
+        archive_connection,
+        restore_connection,
+        export_subject,
+        erase_subject
+    ),
+    components(schemas(
+        UsageRecord,
+        QueueSample,
+        Role,
+        StorageMode,
+        PromotionReport,
+        ConnectionLimits,
+        DistributionList,
+        Connection,
+        Disclosures,
+        SubjectExport,
+        QueuedMessageMetadata,
+        ListMembership,
+        ErasureRecord
+    )),
+    modifiers(&ApiKeySecurity),
+    tags((name = "admin", description = "Administration of the mediator, for operators"))
+)]
+pub(crate) struct AdminApiDoc;
+
+/// Serves JSON Schemas of supported message types, keyed by message type
+#[utoipa::path(
+    get,
+    path = "/.well-known/didcomm/schemas",
+    tag = "mediator-coordination",
+    responses((status = 200, description = "JSON Schemas, keyed by message type", body = Value))
+)]
+async fn schemas() -> Json<Value> {
+    let schemas = validator()
+        .specs()
+        .map(|spec| (spec.message_type.to_owned(), spec.json_schema()))
+        .collect();
+
+    Json(Value::Object(schemas))
+}
+
+/// Serves the signed mediator policy, if configured
+#[utoipa::path(
+    get,
+    path = "/.well-known/didcomm/policy.json",
+    tag = "mediator-coordination",
+    responses(
+        (status = 200, description = "Mediator policy, signed by the mediator", body = Value),
+        (status = 404, description = "No policy is configured"),
+    )
+)]
+async fn mediator_policy(
+    State(policy): State<DisclosedPolicy>,
+) -> Result<Json<MediatorPolicy>, StatusCode> {
+    policy.get().map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Serves the signed statement of the software, protocols and policy the
+/// mediator runs
+#[utoipa::path(
+    get,
+    path = "/.well-known/didcomm/attestation.json",
+    tag = "mediator-coordination",
+    responses(
+        (status = 200, description = "Attestation, signed by the mediator", body = Value),
+        (status = 404, description = "The mediator could not sign an attestation"),
+    )
+)]
+async fn mediator_attestation(
+    State(attestation): State<DisclosedAttestation>,
+) -> Result<Json<Attestation>, StatusCode> {
+    attestation.get().map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Serves the degradation level, along with the signals that drove it
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "mediator-coordination",
+    responses((status = 200, description = "Status, degradation level and health signals", body = Value))
+)]
+async fn health(State(shedder): State<LoadShedder>) -> Json<Value> {
+    let level = shedder.level();
+    let status = match level {
+        DegradationLevel::Full => "ok",
+        _ => "degraded",
+    };
+
+    Json(json!({
+        "status": status,
+        "degradation": level,
+        "signals": shedder.signals(),
+    }))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UsageQuery {
+    /// Export format, `json` or `csv`
+    format: Option<String>,
+
+    /// Excludes periods ending at or before this UNIX timestamp
+    from: Option<i64>,
+
+    /// Excludes periods ending after this UNIX timestamp
+    to: Option<i64>,
+}
+
+/// Renders persistent counters in the Prometheus text format
+#[utoipa::path(
+    get,
+    path = "/admin/metrics/persistent",
+    tag = "admin",
+    responses((status = 200, description = "Totals of persistent counters", content_type = "text/plain", body = String)),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn persistent_metrics(State(counters): State<PersistentCounters>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        counters.render(),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TimeSeriesQuery {
+    /// Excludes samples taken before this UNIX timestamp
+    since: Option<i64>,
+}
+
+/// Lists per-minute samples of the depth of the pickup queue, and of the
+/// rates at which messages enter and leave it
+#[utoipa::path(
+    get,
+    path = "/admin/stats/timeseries",
+    tag = "admin",
+    params(TimeSeriesQuery),
+    responses((status = 200, description = "Samples, oldest first", body = [QueueSample])),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn queue_timeseries(
+    State(series): State<QueueTimeSeries>,
+    Query(query): Query<TimeSeriesQuery>,
+) -> Json<Vec<QueueSample>> {
+    Json(series.samples(query.since))
+}
+
+/// Lists the exact number of messages awaiting pickup, per connection
+#[utoipa::path(
+    get,
+    path = "/admin/stats/connections",
+    tag = "admin",
+    responses((status = 200, description = "Queue depths, keyed by connection", body = Value)),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn queue_depths(State(queue): State<Arc<PickupQueue>>) -> Json<BTreeMap<String, u64>> {
+    Json(queue.depths())
+}
+
+/// Serves statistics of pickup queues, aggregated and noised so as not to
+/// disclose the activity of individual connections
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "mediator-coordination",
+    responses(
+        (status = 200, description = "Noised statistics", body = PublicStats),
+    )
+)]
+async fn public_stats(State(stats): State<PublicStatistics>) -> Json<PublicStats> {
+    let now = chrono::Utc::now().timestamp();
+    Json(stats.get(&mut rand::thread_rng(), now))
+}
+
+/// Reports the role of the node, and the writes it queued while on standby
+#[utoipa::path(
+    get,
+    path = "/admin/failover",
+    tag = "admin",
+    responses((status = 200, description = "Role, storage mode and number of pending writes", body = Value)),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn failover_status(State(state): State<FailoverState>) -> Result<Json<Value>, StatusCode> {
+    let pending = state.failover.pending(&StdFileSystem).map_err(|err| {
+        tracing::error!("failed to read pending writes: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(json!({
+        "role": state.failover.role(),
+        "storageMode": state.failover.storage_mode(),
+        "pendingWrites": pending.len(),
+    })))
+}
+
+/// Promotes a standby node to active, replaying the writes it queued
+#[utoipa::path(
+    post,
+    path = "/admin/failover/promote",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Node promoted", body = PromotionReport),
+        (status = 409, description = "Node is already active"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn promote(State(state): State<FailoverState>) -> Result<Json<PromotionReport>, StatusCode> {
+    let mut fs = StdFileSystem;
+    match state.failover.promote(&mut fs, state.replayer.as_ref()) {
+        Ok(report) => Ok(Json(report)),
+        Err(FailoverError::AlreadyActive) => Err(StatusCode::CONFLICT),
+        Err(err) => {
+            tracing::error!("failed to promote node: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Lists the concurrency limits overridden per connection
+#[utoipa::path(
+    get,
+    path = "/admin/connections/limits",
+    tag = "admin",
+    responses((status = 200, description = "Overridden limits, keyed by connection", body = Value)),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn connection_limits(
+    State(overrides): State<LimitOverrides>,
+) -> Json<HashMap<String, ConnectionLimits>> {
+    Json(overrides.all())
+}
+
+/// Overrides the concurrency limits of a connection
+#[utoipa::path(
+    put,
+    path = "/admin/connections/{connection}/limits",
+    tag = "admin",
+    params(("connection" = String, Path, description = "Connection, e.g. the DID of its agent")),
+    request_body = ConnectionLimits,
+    responses(
+        (status = 200, description = "Limits overridden", body = ConnectionLimits),
+        (status = 400, description = "Limits would block the connection"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn override_connection_limits(
+    State(overrides): State<LimitOverrides>,
+    Path(connection): Path<String>,
+    Json(limits): Json<ConnectionLimits>,
+) -> Result<Json<ConnectionLimits>, StatusCode> {
+    if !limits.is_valid() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    overrides.set(&connection, limits);
+    Ok(Json(limits))
+}
+
+/// Reverts a connection to the default concurrency limits
+#[utoipa::path(
+    delete,
+    path = "/admin/connections/{connection}/limits",
+    tag = "admin",
+    params(("connection" = String, Path, description = "Connection, e.g. the DID of its agent")),
+    responses(
+        (status = 204, description = "Limits reverted"),
+        (status = 404, description = "Limits of the connection were not overridden"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn revert_connection_limits(
+    State(overrides): State<LimitOverrides>,
+    Path(connection): Path<String>,
+) -> StatusCode {
+    match overrides.remove(&connection) {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+/// Lists the distribution lists, by DID
+#[utoipa::path(
+    get,
+    path = "/admin/lists",
+    tag = "admin",
+    responses((status = 200, description = "Distribution lists, keyed by DID", body = Value)),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn distribution_lists(
+    State(lists): State<DistributionLists>,
+) -> Json<BTreeMap<String, DistributionList>> {
+    Json(lists.all())
+}
+
+/// Defines a distribution list, replacing any list under the same DID
+#[utoipa::path(
+    put,
+    path = "/admin/lists/{did}",
+    tag = "admin",
+    params(("did" = String, Path, description = "DID messages are forwarded to")),
+    request_body = DistributionList,
+    responses(
+        (status = 200, description = "List defined", body = DistributionList),
+        (status = 400, description = "Invalid DIDs, or too many members"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn set_distribution_list(
+    State(lists): State<DistributionLists>,
+    Path(did): Path<String>,
+    Json(list): Json<DistributionList>,
+) -> Result<Json<DistributionList>, (StatusCode, String)> {
+    match lists.set(&did, list.clone()) {
+        Ok(()) => Ok(Json(list)),
+        Err(err @ (ListError::InvalidDid(_) | ListError::TooManyMembers(_))) => {
+            Err((StatusCode::BAD_REQUEST, err.to_string()))
+        }
+        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    }
+}
+
+/// Removes a distribution list
+#[utoipa::path(
+    delete,
+    path = "/admin/lists/{did}",
+    tag = "admin",
+    params(("did" = String, Path, description = "DID messages are forwarded to")),
+    responses(
+        (status = 204, description = "List removed"),
+        (status = 404, description = "No such list"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn remove_distribution_list(
+    State(lists): State<DistributionLists>,
+    Path(did): Path<String>,
+) -> StatusCode {
+    match lists.remove(&did) {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ConnectionsQuery {
+    /// Whether archived connections are listed too
+    #[serde(default)]
+    include_archived: bool,
+}
+
+/// Lists connections, leaving out archived ones unless asked
+#[utoipa::path(
+    get,
+    path = "/admin/connections",
+    tag = "admin",
+    params(ConnectionsQuery),
+    responses((status = 200, description = "Connections, by client DID", body = [Connection])),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn list_connections(
+    State(connections): State<Connections>,
+    Query(query): Query<ConnectionsQuery>,
+) -> Json<Vec<Connection>> {
+    Json(connections.list(query.include_archived))
+}
+
+/// Revokes a connection, archiving it along with its queued messages until
+/// its retention expires
+#[utoipa::path(
+    delete,
+    path = "/admin/connections/{connection}",
+    tag = "admin",
+    params(("connection" = String, Path, description = "DID of the client")),
+    responses(
+        (status = 200, description = "Connection archived", body = Connection),
+        (status = 404, description = "No such connection"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn archive_connection(
+    State(connections): State<Connections>,
+    Path(connection): Path<String>,
+) -> Result<Json<Connection>, (StatusCode, String)> {
+    let now = chrono::Utc::now().timestamp();
+    connections
+        .archive(&connection, now)
+        .map(Json)
+        .map_err(archival_error)
+}
+
+/// Restores an archived connection, within its retention
+#[utoipa::path(
+    post,
+    path = "/admin/connections/{connection}/restore",
+    tag = "admin",
+    params(("connection" = String, Path, description = "DID of the client")),
+    responses(
+        (status = 200, description = "Connection restored", body = Connection),
+        (status = 404, description = "No such connection"),
+        (status = 409, description = "Connection not archived, or updated concurrently"),
+        (status = 410, description = "Retention of the connection expired"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn restore_connection(
+    State(connections): State<Connections>,
+    Path(connection): Path<String>,
+) -> Result<Json<Connection>, (StatusCode, String)> {
+    let now = chrono::Utc::now().timestamp();
+    connections
+        .restore(&connection, now)
+        .map(Json)
+        .map_err(archival_error)
+}
+
+fn archival_error(err: ArchivalError) -> (StatusCode, String) {
+    let status = match err {
+        ArchivalError::Repository(RepositoryError::NotFound(_)) => StatusCode::NOT_FOUND,
+        ArchivalError::NotArchived(_)
+        | ArchivalError::Repository(RepositoryError::Conflict { .. }) => StatusCode::CONFLICT,
+        ArchivalError::RetentionExpired(_) => StatusCode::GONE,
+        ArchivalError::Repository(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    (status, err.to_string())
+}
+
+/// Exports the data held about a DID
+#[utoipa::path(
+    get,
+    path = "/admin/subjects/{did}",
+    tag = "admin",
+    params(("did" = String, Path, description = "DID of the data subject")),
+    responses(
+        (status = 200, description = "Data held about the DID", body = SubjectExport),
+        (status = 500, description = "Unreadable logs"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn export_subject(
+    State(state): State<SubjectsState>,
+    Path(did): Path<String>,
+) -> Result<Json<SubjectExport>, StatusCode> {
+    let now = chrono::Utc::now().timestamp();
+    state
+        .subjects
+        .export(&mut StdFileSystem, &state.storage_dirpath, &did, now)
+        .map(Json)
+        .map_err(|err| {
+            tracing::error!("failed to export subject data: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Erases the data held about a DID, anonymizing retained log entries
+#[utoipa::path(
+    delete,
+    path = "/admin/subjects/{did}",
+    tag = "admin",
+    params(("did" = String, Path, description = "DID of the data subject")),
+    responses(
+        (status = 200, description = "Audit record of the erasure", body = ErasureRecord),
+        (status = 500, description = "Erasure failed, and may be retried"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn erase_subject(
+    State(state): State<SubjectsState>,
+    Path(did): Path<String>,
+) -> Result<Json<ErasureRecord>, StatusCode> {
+    let now = chrono::Utc::now().timestamp();
+    state
+        .subjects
+        .erase(&mut StdFileSystem, &state.storage_dirpath, &did, now)
+        .map(Json)
+        .map_err(|err| {
+            tracing::error!("failed to erase subject data: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Exports usage records of closed billing periods
+#[utoipa::path(
+    get,
+    path = "/admin/usage",
+    tag = "admin",
+    params(UsageQuery),
+    responses(
+        (status = 200, description = "Usage records, as JSON or CSV", body = [UsageRecord]),
+        (status = 400, description = "Unsupported format"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn usage_records(
+    State(storage_dirpath): State<String>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Response, StatusCode> {
+    let mut fs = StdFileSystem;
+    let records = UsageLedger::new(&mut fs, &storage_dirpath)
+        .records(query.from, query.to)
+        .map_err(|err| {
+            tracing::error!("failed to read usage records: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    match query.format.as_deref() {
+        None | Some("json") => Ok(Json(records).into_response()),
+        Some("csv") => Ok((
+            [(header::CONTENT_TYPE, "text/csv")],
+            metering::to_csv(&records),
+        )
+            .into_response()),
+        Some(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use hyper::StatusCode;
+    use tower::util::ServiceExt;
+
+    use crate::{
+        archival::ArchivalConfig,
+        constants::*,
+        failover::FailoverConfig,
+        privacy::PrivacyConfig,
+        repository::{MemoryRepository, Repository},
+        util::{self, mock_fs},
+    };
+    use did_endpoint::util::{apikeys::ApiKeyStore, test_utils::AdminFixture};
+
+    fn setup() -> Router {
+        setup_with_policy(None)
+    }
+
+    fn setup_with_policy(policy: Option<MediatorPolicy>) -> Router {
+        let mut mock_fs = mock_fs();
+
+        let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+
+        let disclosed = DisclosedPolicy::default();
+        if let Some(policy) = policy {
+            disclosed.sign(policy, &diddoc, &keystore).unwrap();
+        }
+
+        routes(disclosed)
+    }
+
+    #[test]
+    fn can_describe_routes() {
+        let doc = ApiDoc::openapi();
+        assert!(doc.paths.paths.contains_key(POLICY_PATH));
+        assert!(doc.paths.paths.contains_key(ATTESTATION_PATH));
+
+        let doc = AdminApiDoc::openapi();
+        assert!(doc.paths.paths.contains_key("/admin/usage"));
+        assert!(doc.paths.paths.contains_key("/admin/metrics/persistent"));
+        assert!(doc.paths.paths.contains_key("/admin/failover/promote"));
+        assert!(doc
+            .paths
+            .paths
+            .contains_key("/admin/connections/{connection}/limits"));
+        assert!(doc.paths.paths.contains_key("/admin/lists/{did}"));
+    }
+
+    #[tokio::test]
+    async fn can_serve_message_schemas() {
+        let app = setup();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/.well-known/didcomm/schemas")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let schemas: Value = serde_json::from_slice(&body).unwrap();
+
+        for message_type in [
+            MEDIATE_REQUEST_2_0,
+            MEDIATE_DENY_2_0,
+            MEDIATE_GRANT_2_0,
+            KEYLIST_UPDATE_2_0,
+            KEYLIST_UPDATE_RESPONSE_2_0,
+            KEYLIST_QUERY_2_0,
+            KEYLIST_2_0,
+            STORAGE_PUT_1_0,
+            STORAGE_KEYS_1_0,
+            DELIVERY_REQUEST_3_0,
+            MESSAGES_RECEIVED_3_0,
+            DELIVERY_STATUS_QUERY_1_0,
+            MIGRATION_REQUEST_1_0,
+        ] {
+            assert_eq!(schemas[message_type]["$id"], message_type);
+        }
+    }
+
+    #[tokio::test]
+    async fn can_serve_signed_policy() {
+        let response = setup()
+            .oneshot(
+                Request::builder()
+                    .uri(POLICY_PATH)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let policy = MediatorPolicy {
+            retention_period: 3600,
+            jurisdiction: Some(String::from("DE")),
+            ..Default::default()
+        };
+
+        let response = setup_with_policy(Some(policy))
+            .oneshot(
+                Request::builder()
+                    .uri(POLICY_PATH)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let policy: MediatorPolicy = serde_json::from_slice(&body).unwrap();
+
+        let diddoc = util::read_diddoc(&mock_fs(), "").unwrap();
+        crate::policy::verify_policy(&policy, &diddoc, None).unwrap();
+        assert_eq!(policy.retention_period, 3600);
+    }
+
+    #[tokio::test]
+    async fn can_serve_signed_attestation() {
+        let mut mock_fs = mock_fs();
+        let diddoc = util::read_diddoc(&mock_fs, "").unwrap();
+        let keystore = util::read_keystore(&mut mock_fs, "").unwrap();
+
+        let disclosed = DisclosedAttestation::default();
+        disclosed.sign(None, &diddoc, &keystore).unwrap();
+
+        let response = attestation_routes(disclosed)
+            .oneshot(
+                Request::builder()
+                    .uri(ATTESTATION_PATH)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let attestation: Attestation = serde_json::from_slice(&body).unwrap();
+        crate::attestation::verify_attestation(&attestation, &diddoc).unwrap();
+    }
+
+    #[tokio::test]
+    async fn can_report_degradation_level() {
+        let shedder = LoadShedder::default();
+        shedder.observe(
+            crate::degradation::HealthSignals {
+                queue_depth: Some(60_000),
+                ..Default::default()
+            },
+            0,
+        );
+
+        let response = health_routes(shedder)
+            .oneshot(
+                Request::builder()
+                    .uri("/health")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let health: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(health["status"], "degraded");
+        assert_eq!(health["degradation"], "queue-only");
+        assert_eq!(health["signals"]["queueDepth"], 60_000);
+    }
+
+    #[tokio::test]
+    async fn can_export_usage_records() {
+        let fixture = AdminFixture::new(&[ApiKeyScope::Usage]);
+        let storage_dirpath = &fixture.storage_dirpath;
+
+        // Usage records require a key granted the usage scope
+        let request = Request::builder().uri("/admin/usage").body(Body::empty());
+        let response = admin_routes(storage_dirpath)
+            .oneshot(request.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let mut fs = StdFileSystem;
+        let (_, admin_key) = ApiKeyStore::new(&mut fs, storage_dirpath)
+            .create("operator", &[ApiKeyScope::Admin], 0)
+            .unwrap();
+        let request = Request::builder()
+            .uri("/admin/usage")
+            .header(apikeys::API_KEY_HEADER, admin_key)
+            .body(Body::empty());
+        let response = admin_routes(storage_dirpath)
+            .oneshot(request.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // No usage record has been persisted yet
+        for (uri, content_type) in [
+            ("/admin/usage", "application/json"),
+            ("/admin/usage?format=csv&from=0", "text/csv"),
+        ] {
+            let response = admin_routes(storage_dirpath)
+                .oneshot(fixture.request("GET", uri, None))
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+        }
+
+        let response = admin_routes(storage_dirpath)
+            .oneshot(fixture.request("GET", "/admin/usage?format=xml", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn can_serve_queue_timeseries() {
+        let fixture = AdminFixture::new(&[ApiKeyScope::Usage]);
+        let storage_dirpath = &fixture.storage_dirpath;
+
+        let series = QueueTimeSeries::new(10);
+        series.record(Default::default(), 1700000000);
+        series.record(Default::default(), 1700000060);
+
+        let uri = "/admin/stats/timeseries?since=1700000060";
+        let response = stats_routes(series, storage_dirpath)
+            .oneshot(fixture.request("GET", uri, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!([{"time": 1700000060, "depth": 0, "ingressRate": 0.0, "deliveryRate": 0.0}])
+        );
+    }
+
+    #[tokio::test]
+    async fn can_serve_persistent_metrics() {
+        let fixture = AdminFixture::new(&[ApiKeyScope::Usage]);
+        let storage_dirpath = &fixture.storage_dirpath;
+
+        let counters = PersistentCounters::new();
+        counters.increment(crate::metrics::MESSAGES_ROUTED, 42);
+
+        let uri = "/admin/metrics/persistent";
+        let response = metrics_routes(counters, storage_dirpath)
+            .oneshot(fixture.request("GET", uri, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("\nmediator_messages_routed_total_persistent 42\n"));
+    }
+
+    #[tokio::test]
+    async fn can_promote_standby() {
+        let fixture = AdminFixture::new(&[ApiKeyScope::Admin]);
+        let storage_dirpath = &fixture.storage_dirpath;
+
+        let config = FailoverConfig {
+            role: Role::Standby,
+            storage_mode: StorageMode::Replicated,
+        };
+        let failover = Failover::new(config, storage_dirpath);
+        let mut fs = StdFileSystem;
+        let ack =
+            json!({"id": "ack", "type": MESSAGES_RECEIVED_3_0, "body": {"message_id_list": []}});
+        failover.admit(&mut fs, "did:key:alice", &ack, 0).unwrap();
+
+        let app = failover_routes(
+            failover.clone(),
+            Arc::new(crate::pickup::PickupQueue::default()),
+            storage_dirpath,
+        );
+        let request = |method: &str, uri: &str| fixture.request(method, uri, None);
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/admin/failover"))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"role": "standby", "storageMode": "replicated", "pendingWrites": 1})
+        );
+
+        let response = app
+            .clone()
+            .oneshot(request("POST", "/admin/failover/promote"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let report: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report, json!({"replayed": 1, "rejected": 0}));
+        assert_eq!(failover.role(), Role::Active);
+
+        let response = app
+            .oneshot(request("POST", "/admin/failover/promote"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn can_override_connection_limits() {
+        let fixture = AdminFixture::new(&[ApiKeyScope::Admin]);
+        let storage_dirpath = &fixture.storage_dirpath;
+
+        let overrides = LimitOverrides::new();
+        let app = limits_routes(overrides.clone(), storage_dirpath);
+        let request = |method: &str, uri: &str, body| fixture.request(method, uri, body);
+        let uri = "/admin/connections/did:key:alice/limits";
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                uri,
+                Some(json!({"maxInFlight": 1, "weight": 3})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            overrides.get("did:key:alice"),
+            Some(ConnectionLimits {
+                max_in_flight: 1,
+                weight: 3
+            })
+        );
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/admin/connections/limits", None))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({"did:key:alice": {"maxInFlight": 1, "weight": 3}})
+        );
+
+        // Limits blocking the connection are refused
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                uri,
+                Some(json!({"maxInFlight": 0, "weight": 1})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", uri, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(overrides.get("did:key:alice"), None);
+
+        let response = app.oneshot(request("DELETE", uri, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn can_manage_distribution_lists() {
+        let fixture = AdminFixture::new(&[ApiKeyScope::Admin]);
+        let storage_dirpath = &fixture.storage_dirpath;
+
+        let lists = DistributionLists::new();
+        let app = lists_routes(lists.clone(), storage_dirpath);
+        let request = |method: &str, uri: &str, body| fixture.request(method, uri, body);
+        let uri = "/admin/lists/did:example:team";
+        let list = json!({
+            "members": ["did:example:alice", "did:example:bob"],
+            "senders": ["did:example:carol"]
+        });
+
+        let response = app
+            .clone()
+            .oneshot(request("PUT", uri, Some(list.clone())))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(lists.is_list("did:example:team"));
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/admin/lists", None))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"did:example:team": list}));
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "PUT",
+                uri,
+                Some(json!({"members": ["alice"], "senders": []})),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", uri, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = app.oneshot(request("DELETE", uri, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn can_archive_and_restore_connections() {
+        let fixture = AdminFixture::new(&[ApiKeyScope::Admin]);
+        let storage_dirpath = &fixture.storage_dirpath;
+
+        let repository = MemoryRepository::new();
+        for client_did in ["did:example:alice", "did:example:bob"] {
+            let connection = Connection {
+                client_did: client_did.to_owned(),
+                ..Default::default()
+            };
+            repository.insert(connection).unwrap();
+        }
+        let connections = Connections::new(Arc::new(repository), ArchivalConfig::default());
+
+        let app = connections_routes(connections.clone(), storage_dirpath);
+        let request = |method: &str, uri: &str| fixture.request(method, uri, None);
+        let clients = |body: &[u8]| -> Vec<String> {
+            let connections: Vec<Connection> = serde_json::from_slice(body).unwrap();
+            connections.into_iter().map(|c| c.client_did).collect()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request("DELETE", "/admin/connections/did:example:alice"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(connections.find("did:example:alice"), None);
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/admin/connections"))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(clients(&body), ["did:example:bob"]);
+
+        let response = app
+            .clone()
+            .oneshot(request("GET", "/admin/connections?include_archived=true"))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(clients(&body), ["did:example:alice", "did:example:bob"]);
+
+        let uri = "/admin/connections/did:example:alice/restore";
+        let response = app.clone().oneshot(request("POST", uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(connections.find("did:example:alice").is_some());
+
+        let response = app.clone().oneshot(request("POST", uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let uri = "/admin/connections/did:example:carol";
+        let response = app.oneshot(request("DELETE", uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn can_export_and_erase_subject_data() {
+        let fixture = AdminFixture::new(&[ApiKeyScope::Admin]);
+        let storage_dirpath = &fixture.storage_dirpath;
+
+        let repository = MemoryRepository::new();
+        let connection = Connection {
+            client_did: "did:example:alice".to_owned(),
+            ..Default::default()
+        };
+        repository.insert(connection).unwrap();
+        let connections = Connections::new(Arc::new(repository), ArchivalConfig::default());
+        let subjects = DataSubjects::new(
+            connections.clone(),
+            Arc::new(crate::pickup::PickupQueue::default()),
+            DistributionLists::new(),
+        );
+
+        let app = subjects_routes(subjects, storage_dirpath);
+        let uri = "/admin/subjects/did:example:alice";
+        let request = |method: &str| fixture.request(method, uri, None);
+
+        let response = app.clone().oneshot(request("GET")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let export: SubjectExport = serde_json::from_slice(&body).unwrap();
+        assert_eq!(export.connection.unwrap().client_did, "did:example:alice");
+
+        let response = app.oneshot(request("DELETE")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let record: ErasureRecord = serde_json::from_slice(&body).unwrap();
+        assert!(record.connection);
+        assert_eq!(connections.get("did:example:alice"), None);
+
+        let log = std::fs::read_to_string(format!("{storage_dirpath}/audit/erasures.jsonl"));
+        assert!(log.unwrap().contains(&record.subject_hash));
+    }
+
+    #[tokio::test]
+    async fn can_serve_noised_and_exact_statistics() {
+        let fixture = AdminFixture::new(&[ApiKeyScope::Usage]);
+        let storage_dirpath = &fixture.storage_dirpath;
+
+        let queue = Arc::new(crate::pickup::PickupQueue::default());
+        for i in 0..3 {
+            let message = crate::pickup::QueuedMessage {
+                id: format!("msg-{i}"),
+                ..Default::default()
+            };
+            queue.enqueue("did:example:alice", message);
+        }
+
+        let config = PrivacyConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let app = public_stats_routes(PublicStatistics::new(config, queue.clone()))
+            .merge(depths_routes(queue, storage_dirpath));
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/stats")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let stats: PublicStats = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats.epsilon, 1.0);
+        assert_eq!(stats.queue_depths.len(), 5);
+
+        // Exact values require an API key
+        let uri = "/admin/stats/connections";
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .oneshot(fixture.request("GET", uri, None))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"did:example:alice": 3}));
+    }
 }
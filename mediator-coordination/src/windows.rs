@@ -0,0 +1,409 @@
+//! Delivery windows and quiet hours per connection.
+//!
+//! Recipients may restrict the times at which the mediator pushes messages
+//! to them, e.g. notifications between 8am and 10pm in their time zone.
+//! Outside of their windows, messages are queued as usual and await the
+//! next opening, or an explicit pickup, which windows never restrict.
+//!
+//! Live delivery and webhooks are expected to check
+//! [`DeliveryWindows::allows_push`] before pushing to a connection.
+
+use chrono::{DateTime, Datelike, Days, NaiveTime, TimeZone, Weekday};
+use chrono_tz::Tz;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+use crate::{
+    constants::*,
+    didcomm::{
+        problem_report::ProblemReport,
+        validation::{MessageValidator, INVALID_MESSAGE_CODE},
+    },
+    handler::{didcomm_handler, MessageHandlers},
+    model::windows::{self as model, *},
+};
+
+/// Maximum number of windows per connection
+const MAX_WINDOWS: usize = 16;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum WindowsError {
+    #[error("unknown time zone `{0}`")]
+    InvalidTimezone(String),
+    #[error("invalid time `{0}`, expected HH:MM")]
+    InvalidTime(String),
+    #[error("invalid day `{0}`")]
+    InvalidDay(String),
+    #[error("window opens and closes at {0}")]
+    EmptyWindow(String),
+    #[error("at most {0} windows may be set")]
+    TooManyWindows(usize),
+}
+
+impl From<WindowsError> for ProblemReport {
+    fn from(err: WindowsError) -> Self {
+        ProblemReport::new(
+            INVALID_MESSAGE_CODE,
+            Some("Invalid delivery windows: {1}"),
+            Some(vec![err.to_string()]),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Window {
+    start: NaiveTime,
+    end: NaiveTime,
+
+    /// Days the window opens on, every day when empty
+    days: Vec<Weekday>,
+}
+
+impl Window {
+    fn opens_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn contains(&self, time: NaiveTime, day: Weekday) -> bool {
+        match self.start < self.end {
+            true => self.opens_on(day) && self.start <= time && time < self.end,
+            // Overnight windows are open since the previous day
+            false => {
+                (self.opens_on(day) && self.start <= time)
+                    || (self.opens_on(day.pred()) && time < self.end)
+            }
+        }
+    }
+}
+
+impl TryFrom<&model::DeliveryWindow> for Window {
+    type Error = WindowsError;
+
+    fn try_from(window: &model::DeliveryWindow) -> Result<Self, Self::Error> {
+        let time = |value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M")
+                .map_err(|_| WindowsError::InvalidTime(value.to_owned()))
+        };
+
+        let start = time(&window.start)?;
+        let end = time(&window.end)?;
+        if start == end {
+            return Err(WindowsError::EmptyWindow(window.start.clone()));
+        }
+
+        let days = window
+            .days
+            .iter()
+            .flatten()
+            .map(|day| {
+                day.parse()
+                    .map_err(|_| WindowsError::InvalidDay(day.clone()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { start, end, days })
+    }
+}
+
+/// Windows of a connection, in the time zone of its recipient
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    timezone: Tz,
+    windows: Vec<Window>,
+}
+
+impl TryFrom<&DeliveryWindowsBody> for Schedule {
+    type Error = WindowsError;
+
+    fn try_from(body: &DeliveryWindowsBody) -> Result<Self, Self::Error> {
+        if body.windows.len() > MAX_WINDOWS {
+            return Err(WindowsError::TooManyWindows(MAX_WINDOWS));
+        }
+
+        Ok(Self {
+            timezone: body
+                .timezone
+                .parse()
+                .map_err(|_| WindowsError::InvalidTimezone(body.timezone.clone()))?,
+            windows: body
+                .windows
+                .iter()
+                .map(Window::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Schedule {
+    fn local(&self, now: i64) -> Option<DateTime<Tz>> {
+        Some(DateTime::from_timestamp(now, 0)?.with_timezone(&self.timezone))
+    }
+
+    /// Whether pushes are allowed at a time, as a UNIX timestamp
+    pub fn is_open(&self, now: i64) -> bool {
+        let Some(local) = self.local(now) else {
+            return false;
+        };
+
+        self.windows.is_empty()
+            || self
+                .windows
+                .iter()
+                .any(|window| window.contains(local.time(), local.weekday()))
+    }
+
+    /// Next time pushes are allowed from a time, as UNIX timestamps
+    pub fn next_opening(&self, now: i64) -> Option<i64> {
+        if self.is_open(now) {
+            return Some(now);
+        }
+
+        let today = self.local(now)?.date_naive();
+        (0..=7)
+            .filter_map(|offset| today.checked_add_days(Days::new(offset)))
+            .flat_map(|date| {
+                self.windows
+                    .iter()
+                    .filter(move |window| window.opens_on(date.weekday()))
+                    .filter_map(move |window| {
+                        // Openings skipped by a DST transition are missed
+                        let local = date.and_time(window.start);
+                        self.timezone.from_local_datetime(&local).earliest()
+                    })
+            })
+            .map(|opening| opening.timestamp())
+            .filter(|&opening| opening > now)
+            .min()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    body: DeliveryWindowsBody,
+    schedule: Schedule,
+}
+
+/// Delivery windows configured by recipients, per connection.
+/// Clones share the same windows.
+#[derive(Debug, Clone, Default)]
+pub struct DeliveryWindows {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl DeliveryWindows {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the windows of a connection, lifting restrictions when there
+    /// are no windows
+    pub fn set(&self, connection: &str, body: DeliveryWindowsBody) -> Result<(), WindowsError> {
+        let schedule = Schedule::try_from(&body)?;
+        let mut entries = self.entries.lock().unwrap();
+
+        match body.windows.is_empty() {
+            true => entries.remove(connection),
+            false => entries.insert(connection.to_owned(), Entry { body, schedule }),
+        };
+
+        Ok(())
+    }
+
+    /// Windows of a connection, if restricted
+    pub fn get(&self, connection: &str) -> Option<DeliveryWindowsBody> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(connection).map(|entry| entry.body.clone())
+    }
+
+    /// Whether messages may be pushed to a connection at a time, as a UNIX
+    /// timestamp
+    pub fn allows_push(&self, connection: &str, now: i64) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(connection)
+            .is_none_or(|entry| entry.schedule.is_open(now))
+    }
+
+    /// Next time messages may be pushed to a connection, to defer pushes to
+    pub fn next_opening(&self, connection: &str, now: i64) -> Option<i64> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(connection) {
+            Some(entry) => entry.schedule.next_opening(now),
+            None => Some(now),
+        }
+    }
+
+    /// Handles a plaintext delivery windows message from an authenticated
+    /// sender, returning the response message to send back.
+    #[allow(clippy::result_large_err)]
+    pub fn handle(&self, sender: &str, message: &Value) -> Result<Value, ProblemReport> {
+        // Responses are not handled by the mediator
+        MessageHandlers::new()
+            .with_validator(MessageValidator::new(message_specs()))
+            .register(SetWindows(self.clone()))
+            .register(GetWindows(self.clone()))
+            .handle(sender, message)
+    }
+
+    /// Windows of a connection, reported as always open in UTC when
+    /// unrestricted
+    fn current(&self, connection: &str) -> DeliveryWindowsBody {
+        self.get(connection).unwrap_or_else(|| DeliveryWindowsBody {
+            timezone: "UTC".to_owned(),
+            windows: vec![],
+        })
+    }
+}
+
+/// Sets the windows of the sender, replying with them
+#[didcomm_handler(DELIVERY_WINDOWS_SET_1_0, reply = DELIVERY_WINDOWS_1_0)]
+fn set_windows(
+    windows: &DeliveryWindows,
+    sender: &str,
+    body: DeliveryWindowsBody,
+) -> Result<DeliveryWindowsBody, WindowsError> {
+    windows.set(sender, body)?;
+    Ok(windows.current(sender))
+}
+
+/// Replies with the windows of the sender
+#[didcomm_handler(DELIVERY_WINDOWS_GET_1_0, reply = DELIVERY_WINDOWS_1_0)]
+fn get_windows(
+    windows: &DeliveryWindows,
+    sender: &str,
+    _body: DeliveryWindowsGetBody,
+) -> Result<DeliveryWindowsBody, WindowsError> {
+    Ok(windows.current(sender))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const ALICE: &str = "did:key:z6MkpTHR8VNsBxYAAWHut2Geadd9jSwuBV8xRoAnwWsdvktH";
+
+    /// 2024-01-15T12:00:00Z, a Monday
+    const MONDAY_NOON: i64 = 1705320000;
+    const HOUR: i64 = 3600;
+
+    fn body(timezone: &str, windows: &[(&str, &str, Option<&[&str]>)]) -> DeliveryWindowsBody {
+        DeliveryWindowsBody {
+            timezone: timezone.to_owned(),
+            windows: windows
+                .iter()
+                .map(|(start, end, days)| model::DeliveryWindow {
+                    start: start.to_string(),
+                    end: end.to_string(),
+                    days: days.map(|days| days.iter().map(|day| day.to_string()).collect()),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn can_restrict_pushes_to_windows() {
+        let windows = DeliveryWindows::new();
+        assert!(windows.allows_push(ALICE, MONDAY_NOON));
+
+        // 8am-10pm in Berlin, i.e. 7am-9pm UTC in winter
+        let berlin = body("Europe/Berlin", &[("08:00", "22:00", None)]);
+        windows.set(ALICE, berlin.clone()).unwrap();
+        assert_eq!(windows.get(ALICE), Some(berlin));
+
+        assert!(windows.allows_push(ALICE, MONDAY_NOON));
+        assert!(windows.allows_push(ALICE, MONDAY_NOON - 5 * HOUR));
+        assert!(!windows.allows_push(ALICE, MONDAY_NOON - 5 * HOUR - 1));
+        assert!(!windows.allows_push(ALICE, MONDAY_NOON + 9 * HOUR));
+
+        assert_eq!(
+            windows.next_opening(ALICE, MONDAY_NOON + 9 * HOUR),
+            Some(MONDAY_NOON + 19 * HOUR)
+        );
+
+        // Lifting restrictions
+        windows.set(ALICE, body("UTC", &[])).unwrap();
+        assert!(windows.allows_push(ALICE, MONDAY_NOON + 9 * HOUR));
+        assert_eq!(windows.get(ALICE), None);
+    }
+
+    #[test]
+    fn can_span_midnight_on_given_days() {
+        let schedule =
+            Schedule::try_from(&body("UTC", &[("22:00", "06:00", Some(&["sun"]))])).unwrap();
+
+        // Opened on Sunday, still open on Monday morning
+        assert!(schedule.is_open(MONDAY_NOON - 7 * HOUR));
+        assert!(!schedule.is_open(MONDAY_NOON - 6 * HOUR));
+        assert!(!schedule.is_open(MONDAY_NOON + 11 * HOUR));
+
+        // Next Sunday at 22:00
+        assert_eq!(
+            schedule.next_opening(MONDAY_NOON),
+            Some(MONDAY_NOON + 6 * 24 * HOUR + 10 * HOUR)
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_windows() {
+        let invalid = |body: DeliveryWindowsBody| Schedule::try_from(&body).unwrap_err();
+
+        assert_eq!(
+            invalid(body("Mars/Olympus", &[])),
+            WindowsError::InvalidTimezone("Mars/Olympus".to_owned())
+        );
+        assert_eq!(
+            invalid(body("UTC", &[("8am", "22:00", None)])),
+            WindowsError::InvalidTime("8am".to_owned())
+        );
+        assert_eq!(
+            invalid(body("UTC", &[("08:00", "22:00", Some(&["someday"]))])),
+            WindowsError::InvalidDay("someday".to_owned())
+        );
+        assert_eq!(
+            invalid(body("UTC", &[("08:00", "08:00", None)])),
+            WindowsError::EmptyWindow("08:00".to_owned())
+        );
+    }
+
+    #[test]
+    fn can_handle_windows_messages() {
+        let windows = DeliveryWindows::new();
+
+        let set = json!({
+            "id": "123456780",
+            "type": DELIVERY_WINDOWS_SET_1_0,
+            "body": {
+                "timezone": "America/New_York",
+                "windows": [{"start": "08:00", "end": "22:00", "days": ["mon", "tue"]}]
+            }
+        });
+        let response = windows.handle(ALICE, &set).unwrap();
+        assert_eq!(response["type"], DELIVERY_WINDOWS_1_0);
+        assert_eq!(response["thid"], "123456780");
+        assert_eq!(response["body"], set["body"]);
+
+        let get = json!({
+            "id": "123456781",
+            "type": DELIVERY_WINDOWS_GET_1_0,
+            "body": {}
+        });
+        let response = windows.handle(ALICE, &get).unwrap();
+        assert_eq!(response["body"], set["body"]);
+
+        let invalid = json!({
+            "id": "123456782",
+            "type": DELIVERY_WINDOWS_SET_1_0,
+            "body": {"timezone": "Nowhere", "windows": []}
+        });
+        let report = windows.handle(ALICE, &invalid).unwrap_err();
+        assert_eq!(report.body.code, INVALID_MESSAGE_CODE);
+
+        // Failed updates leave windows unchanged
+        assert!(windows.get(ALICE).is_some());
+    }
+}
@@ -0,0 +1,291 @@
+//! Worker pool processing forwards serially per recipient.
+//!
+//! Processing forwards concurrently scales with the number of workers, but
+//! could reorder the messages of a recipient. [`ShardedWorkers`] assigns
+//! each recipient DID to one worker by consistent hashing, so that the
+//! messages of a recipient are processed one after the other, in the order
+//! they were submitted, while different recipients are spread over the
+//! pool.
+//!
+//! Consistent hashing keeps most recipients on the same shard when the
+//! number of workers changes, e.g. between restarts, which limits the
+//! recipients whose in-flight work could overlap during such changes.
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    hash::{Hash, Hasher},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SubmitError<J> {
+    #[error("the queue of the shard is full")]
+    Full(J),
+    #[error("the worker of the shard has stopped")]
+    Stopped(J),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerConfig {
+    /// Number of workers, each serving one shard
+    pub workers: usize,
+
+    /// Number of jobs a shard may hold before submissions are refused
+    pub queue_capacity: usize,
+
+    /// Points per shard on the hash ring, evening out the distribution
+    pub virtual_nodes: usize,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            workers: thread::available_parallelism().map_or(4, usize::from),
+            queue_capacity: 1024,
+            virtual_nodes: 64,
+        }
+    }
+}
+
+impl WorkerConfig {
+    /// Reads `FORWARD_WORKERS` and `FORWARD_WORKER_QUEUE_CAPACITY`,
+    /// falling back to defaults for unset or invalid values.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |key: &str| server_plugin::reload::var(key);
+
+        Self {
+            workers: var("FORWARD_WORKERS")
+                .and_then(|v| v.parse().ok())
+                .filter(|&workers| workers > 0)
+                .unwrap_or(default.workers),
+            queue_capacity: var("FORWARD_WORKER_QUEUE_CAPACITY")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.queue_capacity),
+            ..default
+        }
+    }
+}
+
+/// Consistent hash ring mapping keys to shards
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    points: BTreeMap<u64, usize>,
+}
+
+impl HashRing {
+    /// Creates a ring over `shards` shards, each placed at `virtual_nodes`
+    /// points
+    pub fn new(shards: usize, virtual_nodes: usize) -> Self {
+        let points = (0..shards)
+            .flat_map(|shard| (0..virtual_nodes.max(1)).map(move |node| (shard, node)))
+            .map(|(shard, node)| (hash(&(shard, node)), shard))
+            .collect();
+
+        Self { points }
+    }
+
+    /// Shard of a key, i.e. of the first point following its hash
+    pub fn shard(&self, key: &str) -> usize {
+        let hash = hash(&key);
+        self.points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map_or(0, |(_, &shard)| shard)
+    }
+}
+
+fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Pool of workers, each processing the jobs of its shard of recipients
+/// in submission order
+pub struct ShardedWorkers<J> {
+    ring: HashRing,
+    senders: Vec<SyncSender<J>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl<J: Send + 'static> ShardedWorkers<J> {
+    /// Starts the workers, processing jobs with `handler`. Jobs panicking
+    /// are logged and do not stop their worker.
+    pub fn spawn<F>(config: WorkerConfig, handler: F) -> Self
+    where
+        F: Fn(J) + Send + Sync + 'static,
+    {
+        let workers = config.workers.max(1);
+        let handler = Arc::new(handler);
+
+        let (senders, handles) = (0..workers)
+            .map(|shard| {
+                let (sender, receiver) = mpsc::sync_channel::<J>(config.queue_capacity);
+                let handler = handler.clone();
+
+                let handle = thread::spawn(move || {
+                    for job in receiver {
+                        if panic::catch_unwind(AssertUnwindSafe(|| handler(job))).is_err() {
+                            tracing::error!("job of shard {shard} panicked");
+                        }
+                    }
+                });
+
+                (sender, handle)
+            })
+            .unzip();
+
+        Self {
+            ring: HashRing::new(workers, config.virtual_nodes),
+            senders,
+            handles,
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Shard processing the jobs of a recipient
+    pub fn shard(&self, recipient_did: &str) -> usize {
+        self.ring.shard(recipient_did)
+    }
+
+    /// Queues a job for the worker of a recipient, without blocking.
+    /// Refused jobs are handed back.
+    pub fn submit(&self, recipient_did: &str, job: J) -> Result<(), SubmitError<J>> {
+        self.senders[self.shard(recipient_did)]
+            .try_send(job)
+            .map_err(|err| match err {
+                TrySendError::Full(job) => SubmitError::Full(job),
+                TrySendError::Disconnected(job) => SubmitError::Stopped(job),
+            })
+    }
+
+    /// Stops accepting jobs and waits for the queued ones to be processed
+    pub fn shutdown(self) {
+        drop(self.senders);
+        for handle in self.handles {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::HashMap,
+        sync::{Barrier, Mutex},
+    };
+
+    fn config(workers: usize, queue_capacity: usize) -> WorkerConfig {
+        WorkerConfig {
+            workers,
+            queue_capacity,
+            virtual_nodes: 64,
+        }
+    }
+
+    fn recipient(i: usize) -> String {
+        format!("did:example:recipient-{i}")
+    }
+
+    #[test]
+    fn can_process_jobs_in_order_per_recipient() {
+        let processed = Arc::new(Mutex::new(HashMap::<String, Vec<usize>>::new()));
+        let workers = {
+            let processed = processed.clone();
+            ShardedWorkers::spawn(config(4, 10_000), move |(recipient, seq)| {
+                let mut processed = processed.lock().unwrap();
+                processed.entry(recipient).or_default().push(seq);
+            })
+        };
+
+        for seq in 0..500 {
+            for i in 0..8 {
+                workers.submit(&recipient(i), (recipient(i), seq)).unwrap();
+            }
+        }
+        workers.shutdown();
+
+        let processed = processed.lock().unwrap();
+        assert_eq!(processed.len(), 8);
+        for seqs in processed.values() {
+            assert_eq!(*seqs, (0..500).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn can_spread_recipients_over_shards() {
+        let ring = HashRing::new(4, 64);
+        let mut counts = [0; 4];
+        for i in 0..4000 {
+            counts[ring.shard(&recipient(i))] += 1;
+        }
+        assert!(counts.iter().all(|&count| count > 500), "{counts:?}");
+
+        // Adding a shard only moves the recipients it takes over
+        let grown = HashRing::new(5, 64);
+        let moved = (0..4000)
+            .filter(|&i| {
+                let (before, after) = (ring.shard(&recipient(i)), grown.shard(&recipient(i)));
+                before != after && after != 4
+            })
+            .count();
+        assert_eq!(moved, 0);
+    }
+
+    #[test]
+    fn should_refuse_jobs_beyond_capacity() {
+        let barrier = Arc::new(Barrier::new(2));
+        let workers = {
+            let barrier = barrier.clone();
+            ShardedWorkers::spawn(config(1, 1), move |_: usize| {
+                barrier.wait();
+            })
+        };
+
+        // The first job is being processed, the second one queued
+        workers.submit("did:example:alice", 0).unwrap();
+        while workers.submit("did:example:alice", 1).is_err() {
+            thread::yield_now();
+        }
+        assert_eq!(
+            workers.submit("did:example:alice", 2),
+            Err(SubmitError::Full(2))
+        );
+
+        barrier.wait();
+        barrier.wait();
+        workers.shutdown();
+    }
+
+    #[test]
+    fn should_survive_panicking_jobs() {
+        let processed = Arc::new(Mutex::new(vec![]));
+        let workers = {
+            let processed = processed.clone();
+            ShardedWorkers::spawn(config(1, 10), move |job: usize| {
+                if job == 0 {
+                    panic!("malformed forward");
+                }
+                processed.lock().unwrap().push(job);
+            })
+        };
+
+        workers.submit("did:example:alice", 0).unwrap();
+        workers.submit("did:example:alice", 1).unwrap();
+        workers.shutdown();
+
+        assert_eq!(*processed.lock().unwrap(), [1]);
+    }
+}
@@ -0,0 +1,47 @@
+//! Handlers generated outside of the mediator coordination crate.
+
+use mediator_coordination::{
+    didcomm::problem_report::ProblemReport,
+    handler::{didcomm_handler, MessageHandlers},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const GREET: &str = "https://example.com/greeting/1.0/greet";
+const GREETING: &str = "https://example.com/greeting/1.0/greeting";
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct GreetBody {
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct GreetingBody {
+    greeting: String,
+}
+
+#[didcomm_handler(GREET, reply = GREETING)]
+#[allow(clippy::result_large_err)]
+fn greet(
+    salutation: &String,
+    _sender: &str,
+    body: GreetBody,
+) -> Result<GreetingBody, ProblemReport> {
+    Ok(GreetingBody {
+        greeting: format!("{salutation}, {}", body.name),
+    })
+}
+
+#[test]
+fn can_generate_handlers_in_other_crates() {
+    let handlers = MessageHandlers::new().register(Greet(String::from("Hello")));
+
+    let message = json!({"id": "1", "type": GREET, "body": {"name": "Alice"}});
+    let reply = handlers
+        .dispatch("did:example:alice", &message)
+        .unwrap()
+        .unwrap();
+    assert_eq!(reply["type"], GREETING);
+    assert_eq!(reply["thid"], "1");
+    assert_eq!(reply["body"]["greeting"], "Hello, Alice");
+}
@@ -1 +1,3 @@
-pub const OOB_INVITATION_2_0: &str = "https://didcomm.org/out-of-band/2.0/invitation";
\ No newline at end of file
+use protocols_registry as registry;
+
+pub const OOB_INVITATION_2_0: &str = registry::OOB_INVITATION_2_0.as_str();
@@ -0,0 +1,296 @@
+use did_endpoint::util::filesystem::FileSystem;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+/// Goal code of invitations to request mediation
+pub const GOAL_CODE_REQUEST_MEDIATE: &str = "request-mediate";
+
+/// Serializes read-modify-write cycles on the invitation file
+static LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, thiserror::Error)]
+pub enum InvitationError {
+    #[error("ioerror: {0}")]
+    IoError(std::io::Error),
+    #[error("corrupted invitation store: {0}")]
+    ParseError(serde_json::Error),
+    #[error("unknown invitation")]
+    NotFound,
+    #[error("invitation expired")]
+    Expired,
+    #[error("invitation already used")]
+    Exhausted,
+    #[error("invitation not valid for goal `{0}`")]
+    GoalMismatch(String),
+}
+
+/// Restrictions bound to an invitation when it is minted
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct InvitationPolicy {
+    /// Number of times the invitation can be redeemed
+    #[serde(default = "InvitationPolicy::default_max_uses")]
+    pub max_uses: u32,
+
+    /// Goal code the invitation can be redeemed for
+    #[serde(default = "InvitationPolicy::default_goal_code")]
+    pub goal_code: String,
+
+    /// Validity period in seconds
+    #[serde(default = "InvitationPolicy::default_expires_in")]
+    pub expires_in: u64,
+}
+
+impl InvitationPolicy {
+    fn default_max_uses() -> u32 {
+        1
+    }
+
+    fn default_goal_code() -> String {
+        GOAL_CODE_REQUEST_MEDIATE.to_owned()
+    }
+
+    fn default_expires_in() -> u64 {
+        24 * 3600
+    }
+}
+
+impl Default for InvitationPolicy {
+    fn default() -> Self {
+        Self {
+            max_uses: Self::default_max_uses(),
+            goal_code: Self::default_goal_code(),
+            expires_in: Self::default_expires_in(),
+        }
+    }
+}
+
+/// Record of an invitation minted for controlled onboarding.
+///
+/// Its identifier is that of the out-of-band invitation message,
+/// which responses reference as their parent thread (`pthid`).
+#[derive(Debug, Serialize, Deserialize, ToSchema, Clone, PartialEq)]
+pub struct ScopedInvitation {
+    pub id: String,
+    pub goal_code: String,
+    pub max_uses: u32,
+    pub uses: u32,
+    pub created_time: i64,
+    pub expires_time: i64,
+}
+
+/// File-based store of scoped invitations.
+///
+/// Spent and expired invitations are dropped whenever the store is written.
+pub struct InvitationStore<'a> {
+    fs: &'a mut dyn FileSystem,
+    dirpath: String,
+}
+
+impl<'a> InvitationStore<'a> {
+    /// Constructs store persisting invitations under a directory.
+    pub fn new(fs: &'a mut dyn FileSystem, storage_dirpath: &str) -> Self {
+        Self {
+            fs,
+            dirpath: storage_dirpath.to_owned(),
+        }
+    }
+
+    /// Mints an invitation bound to a policy.
+    pub fn mint(
+        &mut self,
+        policy: &InvitationPolicy,
+        now: i64,
+    ) -> Result<ScopedInvitation, InvitationError> {
+        let invitation = ScopedInvitation {
+            id: uuid::Uuid::new_v4().to_string(),
+            goal_code: policy.goal_code.clone(),
+            max_uses: policy.max_uses,
+            uses: 0,
+            created_time: now,
+            expires_time: now.saturating_add(policy.expires_in as i64),
+        };
+
+        let _guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let mut invitations = self.read()?;
+        invitations.push(invitation.clone());
+        self.write(invitations, now)?;
+
+        Ok(invitation)
+    }
+
+    /// Lists pending invitations.
+    pub fn list(&self, now: i64) -> Result<Vec<ScopedInvitation>, InvitationError> {
+        let invitations = self.read()?;
+        Ok(invitations
+            .into_iter()
+            .filter(|inv| inv.is_pending(now))
+            .collect())
+    }
+
+    /// Revokes an invitation.
+    pub fn revoke(&mut self, id: &str, now: i64) -> Result<(), InvitationError> {
+        let _guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let mut invitations = self.read()?;
+
+        let count = invitations.len();
+        invitations.retain(|inv| inv.id != id);
+        if invitations.len() == count {
+            return Err(InvitationError::NotFound);
+        }
+
+        self.write(invitations, now)
+    }
+
+    /// Redeems an invitation for a goal, consuming one of its uses.
+    pub fn redeem(
+        &mut self,
+        id: &str,
+        goal_code: &str,
+        now: i64,
+    ) -> Result<ScopedInvitation, InvitationError> {
+        let _guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let mut invitations = self.read()?;
+
+        let invitation = invitations
+            .iter_mut()
+            .find(|inv| inv.id == id)
+            .ok_or(InvitationError::NotFound)?;
+
+        if invitation.expires_time <= now {
+            return Err(InvitationError::Expired);
+        }
+
+        if invitation.uses >= invitation.max_uses {
+            return Err(InvitationError::Exhausted);
+        }
+
+        if invitation.goal_code != goal_code {
+            return Err(InvitationError::GoalMismatch(goal_code.to_owned()));
+        }
+
+        invitation.uses += 1;
+        let redeemed = invitation.clone();
+        self.write(invitations, now)?;
+
+        Ok(redeemed)
+    }
+
+    fn path(&self) -> String {
+        format!("{}/invitations.json", self.dirpath)
+    }
+
+    fn read(&self) -> Result<Vec<ScopedInvitation>, InvitationError> {
+        match self.fs.read_to_string(&self.path()) {
+            Ok(content) => serde_json::from_str(&content).map_err(InvitationError::ParseError),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(err) => Err(InvitationError::IoError(err)),
+        }
+    }
+
+    fn write(
+        &mut self,
+        mut invitations: Vec<ScopedInvitation>,
+        now: i64,
+    ) -> Result<(), InvitationError> {
+        invitations.retain(|inv| inv.is_pending(now));
+        let content =
+            serde_json::to_string_pretty(&invitations).map_err(InvitationError::ParseError)?;
+
+        self.fs
+            .create_dir_all(&self.dirpath)
+            .map_err(InvitationError::IoError)?;
+        self.fs
+            .write_atomic(&self.path(), &content)
+            .map_err(InvitationError::IoError)
+    }
+}
+
+impl ScopedInvitation {
+    /// Whether the invitation can still be redeemed
+    pub fn is_pending(&self, now: i64) -> bool {
+        self.expires_time > now && self.uses < self.max_uses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use did_endpoint::util::test_utils::MemoryFileSystem;
+
+    #[test]
+    fn test_single_use_invitation() {
+        let mut fs = MemoryFileSystem::default();
+        let mut store = InvitationStore::new(&mut fs, "storage");
+
+        let invitation = store.mint(&InvitationPolicy::default(), 1000).unwrap();
+        assert_eq!(invitation.expires_time, 1000 + 24 * 3600);
+        assert_eq!(store.list(1000).unwrap(), vec![invitation.clone()]);
+
+        let redeemed = store
+            .redeem(&invitation.id, GOAL_CODE_REQUEST_MEDIATE, 1001)
+            .unwrap();
+        assert_eq!(redeemed.uses, 1);
+
+        // Spent invitations are dropped
+        assert!(matches!(
+            store.redeem(&invitation.id, GOAL_CODE_REQUEST_MEDIATE, 1002),
+            Err(InvitationError::NotFound)
+        ));
+        assert!(store.list(1002).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invitation_policy_enforcement() {
+        let mut fs = MemoryFileSystem::default();
+        let mut store = InvitationStore::new(&mut fs, "storage");
+
+        let policy = InvitationPolicy {
+            max_uses: 2,
+            goal_code: String::from("request-mediate"),
+            expires_in: 60,
+        };
+        let invitation = store.mint(&policy, 0).unwrap();
+
+        assert!(matches!(
+            store.redeem(&invitation.id, "other-goal", 10),
+            Err(InvitationError::GoalMismatch(_))
+        ));
+
+        store
+            .redeem(&invitation.id, GOAL_CODE_REQUEST_MEDIATE, 10)
+            .unwrap();
+        store
+            .redeem(&invitation.id, GOAL_CODE_REQUEST_MEDIATE, 20)
+            .unwrap();
+        assert!(matches!(
+            store.redeem(&invitation.id, GOAL_CODE_REQUEST_MEDIATE, 30),
+            Err(InvitationError::NotFound)
+        ));
+
+        let invitation = store.mint(&policy, 0).unwrap();
+        assert!(matches!(
+            store.redeem(&invitation.id, GOAL_CODE_REQUEST_MEDIATE, 60),
+            Err(InvitationError::Expired)
+        ));
+
+        let invitation = store.mint(&policy, 0).unwrap();
+        store.revoke(&invitation.id, 0).unwrap();
+        assert!(matches!(
+            store.revoke(&invitation.id, 0),
+            Err(InvitationError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_policy_defaults() {
+        let policy: InvitationPolicy = serde_json::from_str(r#"{"expires_in": 300}"#).unwrap();
+        assert_eq!(
+            policy,
+            InvitationPolicy {
+                expires_in: 300,
+                ..Default::default()
+            }
+        );
+    }
+}
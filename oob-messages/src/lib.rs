@@ -1,5 +1,6 @@
-mod models;
 mod constants;
-mod util;
+pub mod invitations;
+mod models;
 pub mod plugin;
-pub mod web;
\ No newline at end of file
+mod util;
+pub mod web;
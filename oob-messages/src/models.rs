@@ -1,4 +1,5 @@
 use crate::constants::OOB_INVITATION_2_0;
+use crate::invitations::ScopedInvitation;
 use base64::{encode_config, STANDARD};
 use did_endpoint::util::filesystem::FileSystem;
 use image::{DynamicImage, Luma};
@@ -12,9 +13,6 @@ use std::error::Error;
 use std::sync::Mutex;
 use url::{ParseError, Url};
 
-#[cfg(test)]
-use std::io::{Error as IoError, ErrorKind, Result as IoResult};
-
 // region: --- Model
 
 // OOB Inv: Is an unencrypted message (In the form of an URL or QR code that contains a b64urlencode JWM) with the mediator public DID.
@@ -84,6 +82,25 @@ impl OobMessage {
     }
 }
 
+// Receives server path/port and an invitation minted for controlled onboarding, and returns
+// a String with the OOB URL. The invitation identifies the OOB message so that responses
+// reference it as their parent thread.
+pub fn generate_scoped_oob_inv(
+    server_public_domain: &str,
+    server_local_port: &str,
+    invitation: &ScopedInvitation,
+) -> Result<String, String> {
+    let did = url_to_did_web_id(&format!("{}:{}/", server_public_domain, server_local_port))
+        .map_err(|e| format!("Url to Did address error: {}", e))?;
+
+    let mut oob_message = OobMessage::new(&did);
+    oob_message.id = invitation.id.clone();
+    oob_message.body.goal_code = invitation.goal_code.clone();
+
+    let url = &format!("{}:{}", server_public_domain, server_local_port);
+    OobMessage::serialize_oob_message(&oob_message, url)
+}
+
 // Receives server path/port and local storage path and returns a String with the OOB URL.
 pub fn retrieve_or_generate_oob_inv<'a>(
     fs: &mut dyn FileSystem,
@@ -221,45 +238,29 @@ fn url_to_did_web_id(url: &str) -> Result<String, Box<dyn Error>> {
     Ok(format!("did:web:{}{}{}", domain, port, path))
 }
 
-#[cfg(test)]
-#[derive(Default)]
-pub struct MockFileSystem;
-
-#[cfg(test)]
-impl FileSystem for MockFileSystem {
-    fn read_to_string(&self, path: &str) -> IoResult<String> {
-        match path {
-            p if p.ends_with("oob_invitation.txt") => {
-                Ok(include_str!("../test/storage/oob_invitation.txt").to_string())
-            }
-            p if p.contains("qrcode.txt") => {
-                Ok(include_str!("../test/storage/qrcode.txt").to_string())
-            }
-            _ => Err(IoError::new(ErrorKind::NotFound, "NotFound")),
-        }
-    }
-
-    fn write(&mut self, _path: &str, _content: &str) -> IoResult<()> {
-        Ok(())
-    }
-
-    fn read_dir_files(&self, _path: &str) -> IoResult<Vec<String>> {
-        Ok(vec![])
-    }
-
-    fn create_dir_all(&mut self, _path: &str) -> IoResult<()> {
-        Ok(())
-    }
-
-    fn write_with_lock(&self, _path: &str, _content: &str) -> IoResult<()> {
-        Ok(())
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::util::dotenv_flow_read;
+    use did_endpoint::util::test_utils::MemoryFileSystem;
+
+    /// In-memory file system holding the invitation and QR code of
+    /// `test/storage`, under the storage directory of tests
+    fn mock_fs() -> MemoryFileSystem {
+        let mut fs = MemoryFileSystem::new();
+        fs.write(
+            "testpath/oob_invitation.txt",
+            include_str!("../test/storage/oob_invitation.txt"),
+        )
+        .unwrap();
+        fs.write(
+            "testpath/qrcode.txt",
+            include_str!("../test/storage/qrcode.txt"),
+        )
+        .unwrap();
+
+        fs
+    }
 
     #[test]
     fn test_create_oob_message() {
@@ -299,6 +300,30 @@ mod tests {
         assert!(oob_url.contains("_oob="));
     }
 
+    #[test]
+    fn test_generate_scoped_oob_inv() {
+        let invitation = ScopedInvitation {
+            id: String::from("0a2c57a5-5662-48a8-bca8-78275cef3c80"),
+            goal_code: String::from("request-mediate"),
+            max_uses: 1,
+            uses: 0,
+            created_time: 0,
+            expires_time: 3600,
+        };
+
+        let oob_url =
+            generate_scoped_oob_inv("https://testadress.com", "3000", &invitation).unwrap();
+
+        let (url, encoded_jwm) = oob_url.split_once("?_oob=").unwrap();
+        assert_eq!(url, "https://testadress.com:3000");
+
+        let plaintext = Base64Url.decode(encoded_jwm).unwrap();
+        let oob_message: OobMessage = serde_json::from_slice(&plaintext).unwrap();
+        assert_eq!(oob_message.id, invitation.id);
+        assert_eq!(oob_message.body.goal_code, invitation.goal_code);
+        assert_eq!(oob_message.from, "did:web:testadress.com%3A3000");
+    }
+
     #[test]
     fn test_retrieve_or_generate_oob_inv() {
         // Test data
@@ -306,7 +331,7 @@ mod tests {
         let server_local_port = dotenv_flow_read("SERVER_LOCAL_PORT").unwrap();
         let storage_dirpath = String::from("testpath");
 
-        let mut mock_fs = MockFileSystem;
+        let mut mock_fs = mock_fs();
 
         let result = retrieve_or_generate_oob_inv(
             &mut mock_fs,
@@ -324,7 +349,7 @@ mod tests {
 
     #[test]
     fn test_retrieve_or_generate_qr_image() {
-        let mut mock_fs = MockFileSystem;
+        let mut mock_fs = mock_fs();
         let url = "https://example.com";
         let storage_dirpath = String::from("testpath");
 
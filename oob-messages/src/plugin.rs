@@ -4,6 +4,7 @@ use super::web;
 use axum::Router;
 use did_endpoint::util::filesystem::StdFileSystem;
 use server_plugin::{Plugin, PluginError};
+use utoipa::{openapi::OpenApi as OpenApiDoc, OpenApi};
 
 #[derive(Default)]
 pub struct OOBMessagesPlugin;
@@ -16,17 +17,19 @@ impl Plugin for OOBMessagesPlugin {
     fn mount(&self) -> Result<(), PluginError> {
         let mut fs = StdFileSystem;
 
-        let server_public_domain = std::env::var("SERVER_PUBLIC_DOMAIN").map_err(|_| {
-            tracing::error!("SERVER_PUBLIC_DOMAIN env variable required");
-            PluginError::InitError
-        })?;
+        let server_public_domain =
+            server_plugin::reload::var("SERVER_PUBLIC_DOMAIN").ok_or_else(|| {
+                tracing::error!("SERVER_PUBLIC_DOMAIN env variable required");
+                PluginError::InitError
+            })?;
 
-        let server_local_port = std::env::var("SERVER_LOCAL_PORT").map_err(|_| {
-            tracing::error!("SERVER_LOCAL_PORT env variable required");
-            PluginError::InitError
-        })?;
+        let server_local_port =
+            server_plugin::reload::var("SERVER_LOCAL_PORT").ok_or_else(|| {
+                tracing::error!("SERVER_LOCAL_PORT env variable required");
+                PluginError::InitError
+            })?;
 
-        let storage_dirpath = std::env::var("STORAGE_DIRPATH").map_err(|_| {
+        let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH").ok_or_else(|| {
             tracing::error!("STORAGE_DIRPATH env variable required");
             PluginError::InitError
         })?;
@@ -68,6 +71,28 @@ impl Plugin for OOBMessagesPlugin {
     }
 
     fn routes(&self) -> Router {
-        web::routes()
+        let routes = web::routes();
+
+        // Administrative routes are opt-in, and require API keys
+        let admin_enabled =
+            server_plugin::reload::var("ADMIN_API_ENABLED").is_some_and(|v| v == "true");
+        match server_plugin::reload::var("STORAGE_DIRPATH") {
+            Some(storage_dirpath) if admin_enabled => {
+                routes.merge(web::admin_routes(&storage_dirpath))
+            }
+            _ => routes,
+        }
+    }
+
+    fn openapi(&self) -> Option<OpenApiDoc> {
+        let mut doc = web::ApiDoc::openapi();
+
+        let admin_enabled =
+            server_plugin::reload::var("ADMIN_API_ENABLED").is_some_and(|v| v == "true");
+        if admin_enabled {
+            doc.merge(web::AdminApiDoc::openapi());
+        }
+
+        Some(doc)
     }
 }
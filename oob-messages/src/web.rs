@@ -1,12 +1,40 @@
-use super::models::{retrieve_or_generate_oob_inv, retrieve_or_generate_qr_image};
-use axum::http::header;
+use super::invitations::{InvitationError, InvitationPolicy, InvitationStore, ScopedInvitation};
+use super::models::{
+    generate_scoped_oob_inv, retrieve_or_generate_oob_inv, retrieve_or_generate_qr_image,
+};
+use axum::http::{header, StatusCode};
 use axum::{
-    response::{Html, IntoResponse, Response},
-    routing::get,
+    extract::{Path, State},
+    response::{Html, IntoResponse, Json, Response},
+    routing::{delete, get},
     Router,
 };
-use did_endpoint::util::filesystem::StdFileSystem;
+use chrono::Utc;
+use did_endpoint::util::{
+    apikeys::{self, ApiKeyScope, ApiKeySecurity},
+    filesystem::StdFileSystem,
+};
+use serde::Serialize;
 use std::error::Error;
+use utoipa::{OpenApi, ToSchema};
+
+/// OpenAPI description of the public routes
+#[derive(OpenApi)]
+#[openapi(
+    paths(handler_oob_inv, handler_oob_qr, handler_landing_page_oob),
+    tags((name = "oob-messages", description = "Out-of-band invitation of the mediator"))
+)]
+pub struct ApiDoc;
+
+/// OpenAPI description of the administrative routes
+#[derive(OpenApi)]
+#[openapi(
+    paths(list_invitations, mint_invitation, revoke_invitation),
+    components(schemas(InvitationPolicy, ScopedInvitation, MintedInvitation)),
+    modifiers(&ApiKeySecurity),
+    tags((name = "admin", description = "Administration of the mediator, for operators"))
+)]
+pub struct AdminApiDoc;
 
 pub fn routes() -> Router {
     Router::new() //
@@ -15,6 +43,136 @@ pub fn routes() -> Router {
         .route("/", get(handler_landing_page_oob))
 }
 
+/// Administrative routes, which must only be exposed to operators.
+///
+/// They mint and revoke invitations scoped by a policy, e.g. single-use
+/// invitations to request mediation, for controlled onboarding. They
+/// require an API key granted the `invitations` scope.
+pub fn admin_routes(storage_dirpath: &str) -> Router {
+    let routes = Router::new()
+        .route(
+            "/admin/invitations",
+            get(list_invitations).post(mint_invitation),
+        )
+        .route("/admin/invitations/:id", delete(revoke_invitation))
+        .with_state(storage_dirpath.to_owned());
+
+    apikeys::require_api_key(routes, storage_dirpath, ApiKeyScope::Invitations)
+}
+
+#[derive(Serialize, ToSchema)]
+struct MintedInvitation {
+    #[serde(flatten)]
+    invitation: ScopedInvitation,
+    oob_url: String,
+}
+
+/// Mints an invitation scoped by a policy, single-use for mediation
+/// requests and valid for a day by default
+#[utoipa::path(
+    post,
+    path = "/admin/invitations",
+    tag = "admin",
+    request_body(content = Option<InvitationPolicy>, description = "Policy of the invitation"),
+    responses(
+        (status = 201, description = "Minted invitation, with its URL", body = MintedInvitation),
+        (status = 400, description = "Invitation could never be redeemed"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn mint_invitation(
+    State(storage_dirpath): State<String>,
+    policy: Option<Json<InvitationPolicy>>,
+) -> Result<(StatusCode, Json<MintedInvitation>), StatusCode> {
+    let policy = policy.map(|Json(policy)| policy).unwrap_or_default();
+    if policy.max_uses == 0 || policy.expires_in == 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let (server_public_domain, server_local_port, _) =
+        get_environment_variables().map_err(|err| {
+            tracing::error!("Error getting environment variables: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut fs = StdFileSystem;
+    let invitation = InvitationStore::new(&mut fs, &storage_dirpath)
+        .mint(&policy, Utc::now().timestamp())
+        .map_err(|err| {
+            tracing::error!("failed to mint invitation: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let oob_url = generate_scoped_oob_inv(&server_public_domain, &server_local_port, &invitation)
+        .map_err(|err| {
+        tracing::error!("failed to generate invitation URL: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(MintedInvitation {
+            invitation,
+            oob_url,
+        }),
+    ))
+}
+
+/// Lists the invitations that can still be redeemed
+#[utoipa::path(
+    get,
+    path = "/admin/invitations",
+    tag = "admin",
+    responses((status = 200, description = "Invitations", body = [ScopedInvitation])),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn list_invitations(
+    State(storage_dirpath): State<String>,
+) -> Result<Json<Vec<ScopedInvitation>>, StatusCode> {
+    let mut fs = StdFileSystem;
+    match InvitationStore::new(&mut fs, &storage_dirpath).list(Utc::now().timestamp()) {
+        Ok(invitations) => Ok(Json(invitations)),
+        Err(err) => {
+            tracing::error!("failed to list invitations: {err}");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Revokes an invitation
+#[utoipa::path(
+    delete,
+    path = "/admin/invitations/{id}",
+    tag = "admin",
+    params(("id" = String, Path, description = "Identifier of the invitation")),
+    responses(
+        (status = 204, description = "Invitation revoked"),
+        (status = 404, description = "Unknown invitation"),
+    ),
+    security(("api_key" = []), ("bearer" = []))
+)]
+async fn revoke_invitation(
+    State(storage_dirpath): State<String>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    let mut fs = StdFileSystem;
+    match InvitationStore::new(&mut fs, &storage_dirpath).revoke(&id, Utc::now().timestamp()) {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(InvitationError::NotFound) => StatusCode::NOT_FOUND,
+        Err(err) => {
+            tracing::error!("failed to revoke invitation: {err}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Serves the out-of-band invitation URL of the mediator
+#[utoipa::path(
+    get,
+    path = "/oob_url",
+    tag = "oob-messages",
+    responses((status = 200, description = "Page holding the invitation URL", content_type = "text/html", body = String))
+)]
 async fn handler_oob_inv() -> Response {
     let (server_public_domain, server_local_port, storage_dirpath) =
         match get_environment_variables() {
@@ -57,9 +215,27 @@ async fn handler_oob_inv() -> Response {
         html_content
     );
 
-    Html(html_content).into_response()
+    // The page is framed by the landing page
+    (
+        [
+            (
+                header::CONTENT_SECURITY_POLICY,
+                "default-src 'none'; style-src 'unsafe-inline'; frame-ancestors 'self'",
+            ),
+            (header::X_FRAME_OPTIONS, "SAMEORIGIN"),
+        ],
+        Html(html_content),
+    )
+        .into_response()
 }
 
+/// Serves the out-of-band invitation of the mediator as a QR code
+#[utoipa::path(
+    get,
+    path = "/oob_qr",
+    tag = "oob-messages",
+    responses((status = 200, description = "Page holding the QR code", content_type = "text/html", body = String))
+)]
 async fn handler_oob_qr() -> Response {
     let (server_public_domain, server_local_port, storage_dirpath) =
         match get_environment_variables() {
@@ -109,6 +285,13 @@ async fn handler_oob_qr() -> Response {
         .into_response()
 }
 
+/// Serves a landing page with the out-of-band invitation of the mediator
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "oob-messages",
+    responses((status = 200, description = "Landing page", content_type = "text/html", body = String))
+)]
 async fn handler_landing_page_oob() -> Response {
     let (server_public_domain, server_local_port, storage_dirpath) =
         match get_environment_variables() {
@@ -178,14 +361,14 @@ async fn handler_landing_page_oob() -> Response {
 }
 
 fn get_environment_variables() -> Result<(String, String, String), Box<dyn Error>> {
-    let server_public_domain = std::env::var("SERVER_PUBLIC_DOMAIN")
-        .map_err(|_| "SERVER_PUBLIC_DOMAIN env variable required")?;
+    let server_public_domain = server_plugin::reload::var("SERVER_PUBLIC_DOMAIN")
+        .ok_or("SERVER_PUBLIC_DOMAIN env variable required")?;
 
-    let server_local_port = std::env::var("SERVER_LOCAL_PORT")
-        .map_err(|_| "SERVER_LOCAL_PORT env variable required")?;
+    let server_local_port = server_plugin::reload::var("SERVER_LOCAL_PORT")
+        .ok_or("SERVER_LOCAL_PORT env variable required")?;
 
-    let storage_dirpath =
-        std::env::var("STORAGE_DIRPATH").map_err(|_| "STORAGE_DIRPATH env variable required")?;
+    let storage_dirpath = server_plugin::reload::var("STORAGE_DIRPATH")
+        .ok_or("STORAGE_DIRPATH env variable required")?;
 
     Ok((server_public_domain, server_local_port, storage_dirpath))
 }
@@ -198,6 +381,7 @@ mod tests {
         body::Body,
         http::{Request, StatusCode},
     };
+    use did_endpoint::util::apikeys::ApiKeyStore;
     use tempdir::TempDir;
     use tower::util::ServiceExt;
 
@@ -247,4 +431,89 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::OK);
     }
+
+    #[tokio::test]
+    async fn test_admin_routes() {
+        let temp_dir = TempDir::new("temp_test_dir").expect("Failed to create temp directory");
+        let storage_dirpath = temp_dir.path().to_str().unwrap().to_owned();
+
+        std::env::set_var("SERVER_PUBLIC_DOMAIN", "http://example.com");
+        std::env::set_var("SERVER_LOCAL_PORT", "8080");
+        std::env::set_var("STORAGE_DIRPATH", &storage_dirpath);
+
+        let request = |method: &str, uri: &str, token: Option<&str>| {
+            let mut builder = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(token) = token {
+                builder = builder.header(header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        let mut fs = StdFileSystem;
+        let mut keys = ApiKeyStore::new(&mut fs, &storage_dirpath);
+        let (_, key) = keys
+            .create("onboarding", &[ApiKeyScope::Invitations], 0)
+            .unwrap();
+        let (_, usage_key) = keys.create("billing", &[ApiKeyScope::Usage], 0).unwrap();
+
+        // Requests without a key granted the invitations scope are rejected
+        for (token, status) in [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some("wrong-token"), StatusCode::UNAUTHORIZED),
+            (Some(usage_key.as_str()), StatusCode::FORBIDDEN),
+        ] {
+            let response = admin_routes(&storage_dirpath)
+                .oneshot(request("GET", "/admin/invitations", token))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        let response = admin_routes(&storage_dirpath)
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/admin/invitations")
+                    .header(header::AUTHORIZATION, format!("Bearer {key}"))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"expires_in": 600}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let minted: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let id = minted["id"].as_str().unwrap().to_owned();
+        assert_eq!(minted["goal_code"], "request-mediate");
+        assert_eq!(minted["max_uses"], 1);
+        assert!(minted["oob_url"]
+            .as_str()
+            .unwrap()
+            .starts_with("http://example.com:8080?_oob="));
+
+        let response = admin_routes(&storage_dirpath)
+            .oneshot(request("GET", "/admin/invitations", Some(&key)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let invitations: Vec<ScopedInvitation> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(invitations.len(), 1);
+        assert_eq!(invitations[0].id, id);
+
+        let uri = format!("/admin/invitations/{id}");
+        for expected in [StatusCode::NO_CONTENT, StatusCode::NOT_FOUND] {
+            let response = admin_routes(&storage_dirpath)
+                .oneshot(request("DELETE", &uri, Some(&key)))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected);
+        }
+    }
 }
@@ -0,0 +1,377 @@
+//! Registry of the DIDComm protocols the mediator speaks.
+//!
+//! Message types are URIs naming a protocol, its version and a kind of
+//! message, e.g. `https://didcomm.org/messagepickup/3.0/status`. They are
+//! declared here once, as typed [`MessageType`] constants, so that plugins
+//! handling messages, validating them and disclosing the protocols served
+//! cannot drift apart. Types received from peers are parsed into the
+//! declared ones, minor versions being compatible as per DIDComm.
+
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ParseError {
+    #[error("malformed version: {0}")]
+    MalformedVersion(String),
+    #[error("unknown protocol: {0}")]
+    UnknownProtocol(String),
+    #[error("unsupported version {version} of {protocol}")]
+    UnsupportedVersion {
+        protocol: Protocol,
+        version: Version,
+    },
+    #[error("unknown message type: {0}")]
+    UnknownMessageType(String),
+}
+
+/// Version of a protocol, whose minor versions are compatible
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl Version {
+    pub const fn new(major: u16, minor: u16) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for Version {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || ParseError::MalformedVersion(s.to_owned());
+        let (major, minor) = s.split_once('.').ok_or_else(malformed)?;
+        Ok(Self {
+            major: major.parse().map_err(|_| malformed())?,
+            minor: minor.parse().map_err(|_| malformed())?,
+        })
+    }
+}
+
+/// Declares protocols, at the version served, and the types of their
+/// messages, deriving their URIs
+macro_rules! registry {
+    ($(
+        $(#[$doc:meta])*
+        $protocol:ident = $base:literal, $name:literal, $major:literal, $minor:literal {
+            $($constant:ident = $kind:literal),* $(,)?
+        }
+    )*) => {
+        /// Protocols of the registry
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum Protocol {
+            $($(#[$doc])* $protocol,)*
+        }
+
+        impl Protocol {
+            pub const ALL: &'static [Protocol] = &[$(Protocol::$protocol),*];
+
+            /// Name of the protocol, e.g. `coordinate-mediation`
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $(Protocol::$protocol => $name,)*
+                }
+            }
+
+            /// Base of the URI of the protocol, e.g. `https://didcomm.org`
+            pub const fn base(self) -> &'static str {
+                match self {
+                    $(Protocol::$protocol => $base,)*
+                }
+            }
+
+            /// Version of the protocol served
+            pub const fn version(self) -> Version {
+                match self {
+                    $(Protocol::$protocol => Version::new($major, $minor),)*
+                }
+            }
+        }
+
+        $($(
+            pub const $constant: MessageType = MessageType {
+                uri: concat!($base, "/", $name, "/", $major, ".", $minor, "/", $kind),
+                protocol: Protocol::$protocol,
+                kind: $kind,
+            };
+        )*)*
+
+        /// Types of the messages of all protocols of the registry
+        pub const MESSAGE_TYPES: &[MessageType] = &[$($($constant,)*)*];
+    };
+}
+
+registry! {
+    /// Coordination of the mediation of a recipient's messages
+    CoordinateMediation = "https://didcomm.org", "coordinate-mediation", 2, 0 {
+        MEDIATE_REQUEST_2_0 = "mediate-request",
+        MEDIATE_DENY_2_0 = "mediate-deny",
+        MEDIATE_GRANT_2_0 = "mediate-grant",
+        KEYLIST_UPDATE_2_0 = "keylist-update",
+        KEYLIST_UPDATE_RESPONSE_2_0 = "keylist-update-response",
+        KEYLIST_QUERY_2_0 = "keylist-query",
+        KEYLIST_2_0 = "keylist",
+    }
+
+    /// Pickup of messages queued for a recipient
+    MessagePickup = "https://didcomm.org", "messagepickup", 3, 0 {
+        STATUS_REQUEST_3_0 = "status-request",
+        STATUS_3_0 = "status",
+        DELIVERY_REQUEST_3_0 = "delivery-request",
+        DELIVERY_3_0 = "delivery",
+        MESSAGES_RECEIVED_3_0 = "messages-received",
+    }
+
+    /// Forwards of messages through mediators to their recipients
+    Routing = "https://didcomm.org", "routing", 2, 0 {
+        FORWARD_2_0 = "forward",
+    }
+
+    /// Reports of problems with messages
+    ReportProblem = "https://didcomm.org", "report-problem", 2, 0 {
+        PROBLEM_REPORT_2_0 = "problem-report",
+    }
+
+    /// Disclosures of the protocols and headers peers support
+    DiscoverFeatures = "https://didcomm.org", "discover-features", 2, 0 {
+        DISCOVER_QUERIES_2_0 = "queries",
+        DISCOVER_DISCLOSE_2_0 = "disclose",
+    }
+
+    /// Invitations to connect out of band
+    OutOfBand = "https://didcomm.org", "out-of-band", 2, 0 {
+        OOB_INVITATION_2_0 = "invitation",
+    }
+
+    /// Storage of values on behalf of recipients
+    Storage = "https://github.com/adorsys/didcomm-mediator-rs/protocols", "storage", 1, 0 {
+        STORAGE_PUT_1_0 = "put",
+        STORAGE_STORED_1_0 = "stored",
+        STORAGE_GET_1_0 = "get",
+        STORAGE_VALUE_1_0 = "value",
+        STORAGE_DELETE_1_0 = "delete",
+        STORAGE_DELETED_1_0 = "deleted",
+        STORAGE_LIST_1_0 = "list",
+        STORAGE_KEYS_1_0 = "keys",
+    }
+
+    /// Status of the delivery of messages forwarded to recipients
+    DeliveryStatus = "https://github.com/adorsys/didcomm-mediator-rs/protocols", "delivery-status", 1, 0 {
+        DELIVERY_STATUS_QUERY_1_0 = "query",
+        DELIVERY_STATUS_1_0 = "status",
+    }
+
+    /// Migration of recipients between mediators
+    Migration = "https://github.com/adorsys/didcomm-mediator-rs/protocols", "migration", 1, 0 {
+        MIGRATION_REQUEST_1_0 = "export-request",
+        MIGRATION_EXPORT_1_0 = "export",
+    }
+
+    /// Windows within which recipients accept deliveries
+    DeliveryWindows = "https://github.com/adorsys/didcomm-mediator-rs/protocols", "delivery-windows", 1, 0 {
+        DELIVERY_WINDOWS_SET_1_0 = "set",
+        DELIVERY_WINDOWS_GET_1_0 = "get",
+        DELIVERY_WINDOWS_1_0 = "windows",
+    }
+
+    /// Challenges authenticating recipients before delivery
+    PickupAuth = "https://github.com/adorsys/didcomm-mediator-rs/protocols", "pickup-auth", 1, 0 {
+        PICKUP_CHALLENGE_1_0 = "challenge",
+        PICKUP_CHALLENGE_RESPONSE_1_0 = "response",
+        PICKUP_AUTHENTICATED_1_0 = "authenticated",
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Protocol at a version, identified by a URI, e.g.
+/// `https://didcomm.org/coordinate-mediation/2.0`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ProtocolId {
+    pub protocol: Protocol,
+    pub version: Version,
+}
+
+impl ProtocolId {
+    /// Whether messages of the protocol at this version are understood,
+    /// i.e. whether it is the major version served
+    pub fn is_supported(&self) -> bool {
+        self.version.major == self.protocol.version().major
+    }
+}
+
+impl From<Protocol> for ProtocolId {
+    fn from(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            version: protocol.version(),
+        }
+    }
+}
+
+impl fmt::Display for ProtocolId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let protocol = self.protocol;
+        write!(
+            f,
+            "{}/{}/{}",
+            protocol.base(),
+            protocol.name(),
+            self.version
+        )
+    }
+}
+
+impl FromStr for ProtocolId {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || ParseError::UnknownProtocol(s.to_owned());
+        let (path, version) = s.rsplit_once('/').ok_or_else(unknown)?;
+        let (base, name) = path.rsplit_once('/').ok_or_else(unknown)?;
+        let protocol = Protocol::ALL
+            .iter()
+            .find(|protocol| protocol.base() == base && protocol.name() == name)
+            .ok_or_else(unknown)?;
+
+        Ok(Self {
+            protocol: *protocol,
+            version: version.parse()?,
+        })
+    }
+}
+
+/// Type of a message of a protocol of the registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageType {
+    uri: &'static str,
+    protocol: Protocol,
+    kind: &'static str,
+}
+
+impl MessageType {
+    /// URI of the type, as set in the `type` header of messages
+    pub const fn as_str(&self) -> &'static str {
+        self.uri
+    }
+
+    pub const fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Protocol of the type, at the version served
+    pub fn protocol_id(&self) -> ProtocolId {
+        self.protocol.into()
+    }
+
+    /// Kind of message within its protocol, e.g. `mediate-request`
+    pub const fn kind(&self) -> &'static str {
+        self.kind
+    }
+}
+
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.uri)
+    }
+}
+
+impl FromStr for MessageType {
+    type Err = ParseError;
+
+    /// Declared type of a message, whose protocol may be at another minor
+    /// version than the one served
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || ParseError::UnknownMessageType(s.to_owned());
+        let (protocol, kind) = s.rsplit_once('/').ok_or_else(unknown)?;
+        let id: ProtocolId = protocol.parse()?;
+        let ProtocolId { protocol, version } = id;
+        if !id.is_supported() {
+            return Err(ParseError::UnsupportedVersion { protocol, version });
+        }
+
+        MESSAGE_TYPES
+            .iter()
+            .find(|message_type| message_type.protocol == protocol && message_type.kind == kind)
+            .copied()
+            .ok_or_else(unknown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_deriving_uris() {
+        assert_eq!(
+            MEDIATE_REQUEST_2_0.as_str(),
+            "https://didcomm.org/coordinate-mediation/2.0/mediate-request"
+        );
+        assert_eq!(
+            PICKUP_AUTHENTICATED_1_0.to_string(),
+            "https://github.com/adorsys/didcomm-mediator-rs/protocols/pickup-auth/1.0/authenticated"
+        );
+        assert_eq!(
+            STATUS_3_0.protocol_id().to_string(),
+            "https://didcomm.org/messagepickup/3.0"
+        );
+
+        // Types are declared once
+        let uris: HashSet<_> = MESSAGE_TYPES.iter().map(MessageType::as_str).collect();
+        assert_eq!(uris.len(), MESSAGE_TYPES.len());
+    }
+
+    #[test]
+    fn test_parsing_message_types() {
+        for message_type in MESSAGE_TYPES {
+            assert_eq!(message_type.as_str().parse(), Ok(*message_type));
+        }
+
+        // Minor versions are compatible, major ones are not
+        let parsed = "https://didcomm.org/messagepickup/3.1/status".parse();
+        assert_eq!(parsed, Ok(STATUS_3_0));
+        assert_eq!(
+            "https://didcomm.org/messagepickup/4.0/status".parse::<MessageType>(),
+            Err(ParseError::UnsupportedVersion {
+                protocol: Protocol::MessagePickup,
+                version: Version::new(4, 0),
+            })
+        );
+
+        assert!(matches!(
+            "https://didcomm.org/messagepickup/3.0/unknown".parse::<MessageType>(),
+            Err(ParseError::UnknownMessageType(_))
+        ));
+        assert!(matches!(
+            "https://example.com/protocol/1.0/ping".parse::<MessageType>(),
+            Err(ParseError::UnknownProtocol(_))
+        ));
+        assert!(matches!(
+            "https://didcomm.org/messagepickup/three/status".parse::<MessageType>(),
+            Err(ParseError::MalformedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn test_parsing_protocol_ids() {
+        for protocol in Protocol::ALL {
+            let id = ProtocolId::from(*protocol);
+            assert_eq!(id.to_string().parse(), Ok(id));
+        }
+    }
+}
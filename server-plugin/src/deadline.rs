@@ -0,0 +1,114 @@
+//! Deadlines of the requests being served.
+//!
+//! Requests are given a deadline when received, after which their response
+//! is of no use to their client. The deadline is scoped to the task serving
+//! the request, so that handlers, and the stores or downstream services
+//! they call, can give up on work that would complete past it rather than
+//! hold on to resources meanwhile.
+
+use std::{
+    fmt,
+    future::Future,
+    time::{Duration, Instant},
+};
+
+tokio::task_local! {
+    static DEADLINE: Deadline;
+}
+
+/// Instant past which a request is no longer worth serving
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline(Instant);
+
+/// Error of work given up on as its deadline passed
+#[derive(Debug, PartialEq)]
+pub struct DeadlineExceeded;
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline exceeded")
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+impl Deadline {
+    pub fn at(instant: Instant) -> Self {
+        Self(instant)
+    }
+
+    /// Deadline after a budget from now
+    pub fn after(budget: Duration) -> Self {
+        Self(Instant::now() + budget)
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// Time left until the deadline, zero if it passed
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// Run a future with this deadline as the current one
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        DEADLINE.scope(self, future).await
+    }
+
+    /// Deadline of the request being served, if any
+    pub fn current() -> Option<Self> {
+        DEADLINE.try_with(|deadline| *deadline).ok()
+    }
+
+    /// Fail if the deadline of the request being served passed, e.g.
+    /// between steps of blocking work
+    pub fn check() -> Result<(), DeadlineExceeded> {
+        match Self::current() {
+            Some(deadline) if deadline.is_expired() => Err(DeadlineExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run a future, giving up on it once the deadline of the request
+    /// being served passes
+    pub async fn within<F: Future>(future: F) -> Result<F::Output, DeadlineExceeded> {
+        match Self::current() {
+            None => Ok(future.await),
+            Some(deadline) => tokio::time::timeout(deadline.remaining(), future)
+                .await
+                .map_err(|_| DeadlineExceeded),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scoping_deadlines() {
+        assert_eq!(Deadline::current(), None);
+        assert_eq!(Deadline::check(), Ok(()));
+        assert_eq!(Deadline::within(async { 1 }).await, Ok(1));
+
+        let deadline = Deadline::after(Duration::from_millis(50));
+        deadline
+            .scope(async move {
+                assert_eq!(Deadline::current(), Some(deadline));
+                assert_eq!(Deadline::check(), Ok(()));
+                assert_eq!(Deadline::within(async { 1 }).await, Ok(1));
+
+                let slow = tokio::time::sleep(Duration::from_secs(5));
+                assert_eq!(Deadline::within(slow).await, Err(DeadlineExceeded));
+                assert_eq!(Deadline::check(), Err(DeadlineExceeded));
+            })
+            .await;
+
+        assert_eq!(Deadline::current(), None);
+    }
+}
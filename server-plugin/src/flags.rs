@@ -0,0 +1,192 @@
+//! Runtime feature flags.
+//!
+//! Flags toggle experimental protocols per environment without rebuilding.
+//! They are read from the `FEATURE_FLAGS` variable as a comma-separated list,
+//! e.g. `FEATURE_FLAGS="plugin.oob_messages=off,wallet-storage"`, and may be
+//! overridden from a JSON file mapping flags to booleans, located at
+//! `FEATURE_FLAGS_FILE`. The file is reloaded whenever it changes, so flags
+//! consulted at runtime can be flipped without restarting the server.
+
+use std::{collections::HashMap, path::PathBuf, sync::Mutex, time::SystemTime};
+
+/// Feature flags, backed by configuration and an optional hot-reloaded file
+#[derive(Debug, Default)]
+pub struct FeatureFlags {
+    configured: HashMap<String, bool>,
+    file: Option<PathBuf>,
+    cache: Mutex<FileCache>,
+}
+
+#[derive(Debug, Default)]
+struct FileCache {
+    modified: Option<SystemTime>,
+    flags: HashMap<String, bool>,
+}
+
+impl FeatureFlags {
+    /// Read flags as configured by the process environment
+    pub fn from_env() -> Self {
+        let flags = Self::parse(&std::env::var("FEATURE_FLAGS").unwrap_or_default());
+
+        match std::env::var("FEATURE_FLAGS_FILE") {
+            Ok(path) if !path.is_empty() => flags.with_file(path),
+            _ => flags,
+        }
+    }
+
+    /// Parse a comma-separated list of flags, each optionally set to a value.
+    /// Bare flags are enabled, and unrecognized values are ignored.
+    pub fn parse(list: &str) -> Self {
+        let configured = list
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| match entry.split_once('=') {
+                None => Some((entry.to_owned(), true)),
+                Some((flag, value)) => {
+                    let enabled = match value.trim().to_lowercase().as_str() {
+                        "1" | "on" | "true" | "yes" => true,
+                        "0" | "off" | "false" | "no" => false,
+                        _ => {
+                            tracing::warn!("ignoring invalid value for feature flag {flag}");
+                            return None;
+                        }
+                    };
+
+                    Some((flag.trim().to_owned(), enabled))
+                }
+            })
+            .collect();
+
+        Self {
+            configured,
+            ..Default::default()
+        }
+    }
+
+    /// Override flags from a JSON file, reloaded on change
+    pub fn with_file(self, path: impl Into<PathBuf>) -> Self {
+        Self {
+            file: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// Whether a flag is enabled, falling back to a default if unset
+    pub fn is_enabled(&self, flag: &str, default: bool) -> bool {
+        if let Some(enabled) = self.reload().get(flag) {
+            return *enabled;
+        }
+
+        self.configured.get(flag).copied().unwrap_or(default)
+    }
+
+    /// Whether a plugin should be mounted. Plugins are enabled unless
+    /// their `plugin.<name>` flag is turned off.
+    pub fn is_plugin_enabled(&self, name: &str) -> bool {
+        self.is_enabled(&format!("plugin.{name}"), true)
+    }
+
+    /// Keep only the protocols whose flag is not turned off, for
+    /// advertising in feature disclosures
+    pub fn enabled_protocols<'p>(&self, protocols: &[&'p str]) -> Vec<&'p str> {
+        protocols
+            .iter()
+            .copied()
+            .filter(|protocol| self.is_enabled(protocol, true))
+            .collect()
+    }
+
+    fn reload(&self) -> HashMap<String, bool> {
+        let mut cache = self.cache.lock().unwrap();
+
+        let Some(path) = &self.file else {
+            return HashMap::new();
+        };
+
+        // A missing file overrides nothing
+        let Ok(modified) = std::fs::metadata(path).and_then(|meta| meta.modified()) else {
+            *cache = FileCache::default();
+            return HashMap::new();
+        };
+
+        if cache.modified != Some(modified) {
+            let parsed = std::fs::read_to_string(path)
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok());
+
+            match parsed {
+                Some(flags) => {
+                    tracing::info!("reloaded feature flags from {}", path.display());
+                    *cache = FileCache {
+                        modified: Some(modified),
+                        flags,
+                    };
+                }
+                // Keep previous flags until the file is fixed
+                None => tracing::error!("invalid feature flags file {}", path.display()),
+            }
+        }
+
+        cache.flags.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parsing_configured_flags() {
+        let flags =
+            FeatureFlags::parse(" v1-compat, wallet-storage=off ,plugin.index=ON,bogus=maybe");
+
+        assert!(flags.is_enabled("v1-compat", false));
+        assert!(!flags.is_enabled("wallet-storage", true));
+        assert!(flags.is_plugin_enabled("index"));
+        assert!(flags.is_enabled("bogus", true));
+        assert!(!flags.is_enabled("bogus", false));
+        assert!(!flags.is_enabled("unset", false));
+    }
+
+    #[test]
+    fn test_filtering_plugins_and_protocols() {
+        let flags =
+            FeatureFlags::parse("plugin.oob_messages=off,https://didcomm.org/pickup/3.0=false");
+
+        assert!(!flags.is_plugin_enabled("oob_messages"));
+        assert!(flags.is_plugin_enabled("did_endpoint"));
+        assert_eq!(
+            flags.enabled_protocols(&[
+                "https://didcomm.org/coordinate-mediation/2.0",
+                "https://didcomm.org/pickup/3.0",
+            ]),
+            vec!["https://didcomm.org/coordinate-mediation/2.0"]
+        );
+    }
+
+    #[test]
+    fn test_hot_reloading_file_overrides() {
+        let dir = std::env::temp_dir().join(format!("feature-flags-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("flags.json");
+        let _ = std::fs::remove_file(&path);
+
+        let flags = FeatureFlags::parse("wallet-storage=off").with_file(&path);
+        assert!(!flags.is_enabled("wallet-storage", true));
+
+        std::fs::write(&path, r#"{"wallet-storage": true}"#).unwrap();
+        assert!(flags.is_enabled("wallet-storage", false));
+
+        // Invalid contents keep the previous flags
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(&path, "{").unwrap();
+        assert!(flags.is_enabled("wallet-storage", false));
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(&path, r#"{"wallet-storage": false}"#).unwrap();
+        assert!(!flags.is_enabled("wallet-storage", true));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
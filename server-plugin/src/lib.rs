@@ -1,27 +1,77 @@
+pub mod deadline;
+pub mod flags;
+pub mod reload;
+pub mod state;
+pub mod tasks;
+
 use std::{
     fmt::Debug,
     hash::{Hash, Hasher},
 };
 
 use axum::Router;
+use state::StateMap;
+use utoipa::openapi::OpenApi;
 
 #[derive(Debug, PartialEq)]
 pub enum PluginError {
     InitError,
+    /// A service the plugin consumes is provided by no plugin
+    MissingService(String),
 }
 
 pub trait Plugin: Sync {
     /// Define a unique identifier
     fn name(&self) -> &'static str;
 
+    /// Migrate persisted data to the layout this version expects, before
+    /// mounting
+    fn migrate(&self) -> Result<(), PluginError> {
+        Ok(())
+    }
+
     /// Provide initialization actions as needed
     fn mount(&self) -> Result<(), PluginError>;
 
+    /// Check that the plugin is able to serve, once mounted
+    fn self_test(&self) -> Result<(), PluginError> {
+        Ok(())
+    }
+
     /// Revert initialization actions as needed
     fn unmount(&self) -> Result<(), PluginError>;
 
     /// Export managed endpoints
     fn routes(&self) -> Router;
+
+    /// Describe the managed endpoints that are not DIDComm endpoints, as
+    /// exported by [`Plugin::routes`]
+    fn openapi(&self) -> Option<OpenApi> {
+        None
+    }
+
+    /// Declare the paths of the routes exported by [`Plugin::routes`], so
+    /// that conflicts with other plugins are detected on load. Defaults to
+    /// the paths described by [`Plugin::openapi`].
+    fn paths(&self) -> Vec<String> {
+        self.openapi()
+            .map(|doc| doc.paths.paths.into_keys().collect())
+            .unwrap_or_default()
+    }
+
+    /// Declare the types of the DIDComm messages the plugin handles
+    fn message_types(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// Register services provided to other plugins
+    fn provide(&self, _state: &mut StateMap) {}
+
+    /// Declare services consumed from other plugins, by their
+    /// [`StateMap::key`]
+    fn consumes(&self) -> Vec<&'static str> {
+        vec![]
+    }
 }
 
 impl Eq for dyn Plugin {}
@@ -0,0 +1,138 @@
+//! Settings reloaded at runtime.
+//!
+//! Most settings are read from the process environment once, when plugins
+//! are mounted, and only take effect after a restart. Settings that can
+//! safely change while serving are registered into [`ReloadableSettings`]
+//! along with how to validate and apply a new value. The registry is shared
+//! with plugins through the [`StateMap`](crate::state::StateMap), so that
+//! they can register their own settings while providing services.
+//!
+//! Values of settings mounted at startup or reloaded since are recorded
+//! here rather than exported to the environment, which is not safe to
+//! modify while other threads read it. Readers query them with [`var`],
+//! falling back to the environment.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+};
+
+/// Values recorded for settings, process-wide like the environment
+static VALUES: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+/// Value of a setting, as last recorded or reloaded, or else as found in
+/// the process environment
+pub fn var(key: &str) -> Option<String> {
+    let recorded = VALUES.read().unwrap().get(key).cloned();
+    recorded.or_else(|| std::env::var(key).ok())
+}
+
+type Apply = Arc<dyn Fn(&str) -> Result<(), String> + Send + Sync>;
+
+/// Registry of settings applied at runtime. Clones share registrations.
+#[derive(Clone, Default)]
+pub struct ReloadableSettings {
+    appliers: Arc<Mutex<HashMap<String, Apply>>>,
+}
+
+impl ReloadableSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register how a setting is validated and applied. Values failing
+    /// validation must be rejected with a reason, leaving the previous
+    /// value in effect.
+    pub fn register<F>(&self, key: &str, apply: F)
+    where
+        F: Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        let mut appliers = self.appliers.lock().unwrap();
+        appliers.insert(key.to_owned(), Arc::new(apply));
+    }
+
+    /// Whether a setting can change without a restart
+    pub fn is_reloadable(&self, key: &str) -> bool {
+        self.appliers.lock().unwrap().contains_key(key)
+    }
+
+    /// Apply a new value of a setting, if it is reloadable, recording it
+    /// once applied
+    pub fn apply(&self, key: &str, value: &str) -> Option<Result<(), String>> {
+        // Appliers may take time, and must not hold the registry
+        let apply = self.appliers.lock().unwrap().get(key).cloned()?;
+        let applied = apply(value);
+        if applied.is_ok() {
+            self.record(key, value);
+        }
+
+        Some(applied)
+    }
+
+    /// Record the value of a setting, overriding the environment for
+    /// readers of [`var`], without applying it
+    pub fn record(&self, key: &str, value: &str) {
+        VALUES
+            .write()
+            .unwrap()
+            .insert(key.to_owned(), value.to_owned());
+    }
+
+    /// Value of a setting, as per [`var`]
+    pub fn get(&self, key: &str) -> Option<String> {
+        var(key)
+    }
+}
+
+impl fmt::Debug for ReloadableSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.appliers.lock().unwrap().keys())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_applying_registered_settings() {
+        let settings = ReloadableSettings::new();
+        let limit = Arc::new(AtomicU64::new(10));
+
+        let applied = limit.clone();
+        settings.clone().register("LIMIT", move |value| {
+            let value = value
+                .parse()
+                .map_err(|_| format!("not a number: {value}"))?;
+            applied.store(value, Ordering::Relaxed);
+            Ok(())
+        });
+
+        assert!(settings.is_reloadable("LIMIT"));
+        assert_eq!(settings.apply("LIMIT", "20"), Some(Ok(())));
+        assert_eq!(limit.load(Ordering::Relaxed), 20);
+        assert_eq!(var("LIMIT").as_deref(), Some("20"));
+
+        // Invalid values leave the previous one in effect
+        assert!(matches!(settings.apply("LIMIT", "many"), Some(Err(_))));
+        assert_eq!(limit.load(Ordering::Relaxed), 20);
+        assert_eq!(settings.get("LIMIT").as_deref(), Some("20"));
+
+        assert!(!settings.is_reloadable("STORAGE_DIRPATH"));
+        assert_eq!(settings.apply("STORAGE_DIRPATH", "/tmp"), None);
+    }
+
+    #[test]
+    fn test_recording_settings() {
+        assert_eq!(var("RELOAD_TEST_RECORDED"), None);
+
+        // Recorded values take precedence over the environment
+        ReloadableSettings::new().record("RELOAD_TEST_RECORDED", "mounted");
+        assert_eq!(var("RELOAD_TEST_RECORDED").as_deref(), Some("mounted"));
+        assert_eq!(std::env::var("RELOAD_TEST_RECORDED").ok(), None);
+    }
+}
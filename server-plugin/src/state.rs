@@ -0,0 +1,110 @@
+//! State shared between plugins.
+//!
+//! Rather than reaching into a common application state, each plugin
+//! registers the services it provides into a [`StateMap`] keyed by type,
+//! and declares the ones it consumes from other plugins. The map is made
+//! available to request handlers as an extension, e.g.
+//!
+//! ```ignore
+//! async fn handler(Extension(state): Extension<StateMap>) {
+//!     let keystore = state.get::<KeyStore>().unwrap();
+//! }
+//! ```
+
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+};
+
+/// Typed map of services, holding at most one service per type
+#[derive(Clone, Default)]
+pub struct StateMap {
+    services: HashMap<TypeId, (&'static str, Arc<dyn Any + Send + Sync>)>,
+}
+
+impl StateMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Key under which services of a type are declared
+    pub fn key<T: 'static>() -> &'static str {
+        type_name::<T>()
+    }
+
+    /// Register a service, returning the one it replaces if any
+    pub fn insert<T: Send + Sync + 'static>(&mut self, service: T) -> Option<Arc<T>> {
+        self.services
+            .insert(TypeId::of::<T>(), (Self::key::<T>(), Arc::new(service)))
+            .and_then(|(_, previous)| previous.downcast().ok())
+    }
+
+    /// Look up the service of a type
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.services
+            .get(&TypeId::of::<T>())
+            .and_then(|(_, service)| service.clone().downcast().ok())
+    }
+
+    /// Whether a service is registered under a declared key
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.services.values().any(|(name, _)| *name == key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.services.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
+}
+
+impl fmt::Debug for StateMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.services.values().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Keystore(&'static str);
+
+    #[derive(Debug, PartialEq)]
+    struct Quota(usize);
+
+    #[test]
+    fn test_registering_services_by_type() {
+        let mut state = StateMap::new();
+        assert!(state.is_empty());
+
+        assert!(state.insert(Keystore("first")).is_none());
+        assert!(state.insert(Quota(10)).is_none());
+        assert_eq!(state.len(), 2);
+
+        assert_eq!(*state.get::<Keystore>().unwrap(), Keystore("first"));
+        assert_eq!(*state.get::<Quota>().unwrap(), Quota(10));
+        assert!(state.get::<String>().is_none());
+
+        let previous = state.insert(Keystore("second")).unwrap();
+        assert_eq!(*previous, Keystore("first"));
+        assert_eq!(*state.get::<Keystore>().unwrap(), Keystore("second"));
+        assert_eq!(state.len(), 2);
+    }
+
+    #[test]
+    fn test_looking_up_declared_keys() {
+        let mut state = StateMap::new();
+        state.insert(Keystore("keystore"));
+
+        assert!(state.contains_key(StateMap::key::<Keystore>()));
+        assert!(!state.contains_key(StateMap::key::<Quota>()));
+    }
+}
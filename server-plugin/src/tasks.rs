@@ -0,0 +1,128 @@
+//! Background tasks of plugins.
+//!
+//! Plugins run periodic work, e.g. snapshots or purges, on threads of
+//! their own. Spawning it through [`BackgroundTasks`], shared with plugins
+//! through the [`StateMap`](crate::state::StateMap), lets the host stop all
+//! of it on shutdown rather than leaving threads running detached.
+
+use std::{
+    fmt,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Registry of background tasks. Clones share tasks.
+#[derive(Clone, Default)]
+pub struct BackgroundTasks {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    stopped: Mutex<bool>,
+    wakeup: Condvar,
+    handles: Mutex<Vec<(String, JoinHandle<()>)>>,
+}
+
+impl BackgroundTasks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run a task right away, then every `interval` on a background thread,
+    /// until shut down. Tasks are not spawned once shut down.
+    pub fn every<F>(&self, name: &str, interval: Duration, mut task: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        if self.is_stopped() {
+            return;
+        }
+
+        let tasks = self.clone();
+        let spawned = thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || loop {
+                task();
+                if tasks.wait(interval) {
+                    return;
+                }
+            });
+
+        match spawned {
+            Ok(handle) => {
+                let mut handles = self.inner.handles.lock().unwrap();
+                handles.push((name.to_owned(), handle));
+            }
+            Err(err) => tracing::error!("failed to spawn background task {name}: {err}"),
+        }
+    }
+
+    /// Wait for an interval, returning early with `true` once shut down
+    fn wait(&self, interval: Duration) -> bool {
+        let stopped = self.inner.stopped.lock().unwrap();
+        let (stopped, _) = self
+            .inner
+            .wakeup
+            .wait_timeout_while(stopped, interval, |stopped| !*stopped)
+            .unwrap();
+        *stopped
+    }
+
+    /// Whether tasks were shut down
+    pub fn is_stopped(&self) -> bool {
+        *self.inner.stopped.lock().unwrap()
+    }
+
+    /// Names of the tasks spawned
+    pub fn names(&self) -> Vec<String> {
+        let handles = self.inner.handles.lock().unwrap();
+        handles.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Stop tasks, waiting for runs in progress to complete
+    pub fn shutdown(&self) {
+        *self.inner.stopped.lock().unwrap() = true;
+        self.inner.wakeup.notify_all();
+
+        let handles = std::mem::take(&mut *self.inner.handles.lock().unwrap());
+        for (name, handle) in handles {
+            if handle.join().is_err() {
+                tracing::error!("background task {name} panicked");
+            }
+        }
+    }
+}
+
+impl fmt::Debug for BackgroundTasks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_shutting_down_tasks() {
+        let tasks = BackgroundTasks::new();
+        let runs = Arc::new(AtomicU64::new(0));
+
+        let counted = runs.clone();
+        tasks.every("count", Duration::from_secs(3600), move || {
+            counted.fetch_add(1, Ordering::Relaxed);
+        });
+        assert_eq!(tasks.names(), ["count"]);
+
+        // Tasks waiting for their next run are woken up, and stop
+        tasks.shutdown();
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        assert!(tasks.names().is_empty());
+
+        tasks.every("late", Duration::from_secs(1), || ());
+        assert!(tasks.names().is_empty());
+    }
+}
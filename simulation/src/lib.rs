@@ -0,0 +1,244 @@
+//! Simulation of edge agents and their mediator, in-process.
+//!
+//! A [`Simulation`] runs a [`LocalMediator`] and edge agents exchanging
+//! plaintext messages over channels rather than HTTP. Messages sent to the
+//! mediator are only handled when the simulation is stepped, one at a
+//! time in the order they were sent, and responses are delivered to their
+//! agent right away. Scenarios, e.g. of keys rotated while forwards are in
+//! flight or of concurrent pickups, thus play out the same on every run,
+//! without network flakiness.
+//!
+//! ```
+//! use simulation::Simulation;
+//!
+//! let mut simulation = Simulation::new(2);
+//! simulation.agent_mut(1).request_mediation();
+//! let key = simulation.agent(1).key(1);
+//! simulation.agent_mut(1).update_keylist("add", &key);
+//!
+//! simulation.agent_mut(0).forward(&key, serde_json::json!({"ciphertext": "..."}));
+//! simulation.agent_mut(1).request_delivery(10);
+//! simulation.run();
+//!
+//! let delivery = simulation.agent(1).received().last().unwrap();
+//! assert_eq!(delivery["attachments"].as_array().unwrap().len(), 1);
+//! ```
+
+use mediator_coordination::{constants::*, local::LocalMediator};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+/// DID of the mediator of simulations
+pub const MEDIATOR_DID: &str = "did:web:mediator.simulation";
+
+/// Message in transit between an agent and the mediator
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub from: String,
+    pub to: String,
+    pub message: Value,
+}
+
+/// Edge agent, sending messages to the mediator and keeping those it
+/// receives back
+pub struct Agent {
+    name: String,
+    did: String,
+    to_mediator: Sender<Envelope>,
+    inbox: Receiver<Envelope>,
+    received: Vec<Value>,
+    sent: usize,
+}
+
+impl Agent {
+    pub fn did(&self) -> &str {
+        &self.did
+    }
+
+    /// Key of the agent, e.g. to add to its keylist
+    pub fn key(&self, n: usize) -> String {
+        format!("{}#key-{n}", self.did)
+    }
+
+    /// Sends a message to the mediator as is
+    pub fn send_message(&self, message: Value) {
+        let envelope = Envelope {
+            from: self.did.clone(),
+            to: MEDIATOR_DID.to_owned(),
+            message,
+        };
+
+        // The simulation outlives its agents
+        let _ = self.to_mediator.send(envelope);
+    }
+
+    /// Sends a message of a type to the mediator, returning its ID. IDs
+    /// number the messages of each agent, e.g. `agent-0-3`.
+    pub fn send(&mut self, message_type: &str, body: Value) -> String {
+        self.send_with(message_type, body, None)
+    }
+
+    fn send_with(&mut self, message_type: &str, body: Value, attachments: Option<Value>) -> String {
+        self.sent += 1;
+        let id = format!("{}-{}", self.name, self.sent);
+
+        let mut message = json!({"id": id, "type": message_type, "body": body});
+        if let Some(attachments) = attachments {
+            message["attachments"] = attachments;
+        }
+        self.send_message(message);
+
+        id
+    }
+
+    pub fn request_mediation(&mut self) -> String {
+        self.send(MEDIATE_REQUEST_2_0, json!({}))
+    }
+
+    /// Adds a key to, or removes it from, the keylist of the agent
+    pub fn update_keylist(&mut self, action: &str, key: &str) -> String {
+        let updates = json!([{"recipient_did": key, "action": action}]);
+        self.send(KEYLIST_UPDATE_2_0, json!({"updates": updates}))
+    }
+
+    /// Forwards a message to the recipient of a key through the mediator
+    pub fn forward(&mut self, next: &str, message: Value) -> String {
+        let attachments = json!([{"data": {"json": message}}]);
+        self.send_with(FORWARD_2_0, json!({"next": next}), Some(attachments))
+    }
+
+    pub fn request_status(&mut self) -> String {
+        self.send(STATUS_REQUEST_3_0, json!({}))
+    }
+
+    pub fn request_delivery(&mut self, limit: u64) -> String {
+        self.send(DELIVERY_REQUEST_3_0, json!({"limit": limit}))
+    }
+
+    /// Acknowledges the receipt of delivered messages
+    pub fn acknowledge(&mut self, message_ids: &[String]) -> String {
+        self.send(
+            MESSAGES_RECEIVED_3_0,
+            json!({"message_id_list": message_ids}),
+        )
+    }
+
+    /// Messages received from the mediator, oldest first
+    pub fn received(&self) -> &[Value] {
+        &self.received
+    }
+
+    /// Response received to a message, or problem report about it, if any
+    pub fn response_to(&self, id: &str) -> Option<&Value> {
+        self.received
+            .iter()
+            .find(|message| message["thid"] == id || message["pthid"] == id)
+    }
+
+    fn receive(&mut self) {
+        while let Ok(envelope) = self.inbox.try_recv() {
+            self.received.push(envelope.message);
+        }
+    }
+}
+
+/// Mediator and edge agents, exchanging messages in-process
+pub struct Simulation {
+    mediator: LocalMediator,
+    inbox: Receiver<Envelope>,
+    agents: Vec<Agent>,
+    to_agents: HashMap<String, Sender<Envelope>>,
+}
+
+impl Simulation {
+    /// Simulation of a number of agents, named `agent-0`, `agent-1`, etc.
+    pub fn new(agents: usize) -> Self {
+        Self::with_mediator(LocalMediator::new(MEDIATOR_DID), agents)
+    }
+
+    /// Simulation of a number of agents and of a mediator configured
+    /// otherwise than by default
+    pub fn with_mediator(mediator: LocalMediator, agents: usize) -> Self {
+        let (to_mediator, inbox) = mpsc::channel();
+
+        let mut to_agents = HashMap::new();
+        let agents = (0..agents)
+            .map(|i| {
+                let name = format!("agent-{i}");
+                let did = format!("did:example:{name}");
+                let (to_agent, agent_inbox) = mpsc::channel();
+                to_agents.insert(did.clone(), to_agent);
+
+                Agent {
+                    name,
+                    did,
+                    to_mediator: to_mediator.clone(),
+                    inbox: agent_inbox,
+                    received: vec![],
+                    sent: 0,
+                }
+            })
+            .collect();
+
+        Self {
+            mediator,
+            inbox,
+            agents,
+            to_agents,
+        }
+    }
+
+    pub fn mediator(&self) -> &LocalMediator {
+        &self.mediator
+    }
+
+    pub fn agent(&self, i: usize) -> &Agent {
+        &self.agents[i]
+    }
+
+    pub fn agent_mut(&mut self, i: usize) -> &mut Agent {
+        &mut self.agents[i]
+    }
+
+    /// Handles the next message in transit to the mediator, delivering
+    /// the response or problem report to its sender. Returns whether a
+    /// message was in transit.
+    pub fn step(&mut self) -> bool {
+        let Ok(envelope) = self.inbox.try_recv() else {
+            return false;
+        };
+
+        let response = match self.mediator.handle(&envelope.from, &envelope.message) {
+            Ok(response) => response,
+            Err(report) => Some(json!(report)),
+        };
+        if let (Some(message), Some(to_agent)) = (response, self.to_agents.get(&envelope.from)) {
+            let envelope = Envelope {
+                from: MEDIATOR_DID.to_owned(),
+                to: envelope.from,
+                message,
+            };
+            let _ = to_agent.send(envelope);
+        }
+
+        for agent in &mut self.agents {
+            agent.receive();
+        }
+
+        true
+    }
+
+    /// Steps until no message is in transit, returning how many were
+    /// handled
+    pub fn run(&mut self) -> usize {
+        let mut steps = 0;
+        while self.step() {
+            steps += 1;
+        }
+
+        steps
+    }
+}
@@ -0,0 +1,159 @@
+use mediator_coordination::{
+    constants::*,
+    forward::{ForwardConfig, FORWARD_QUOTA_EXCEEDED_CODE},
+    local::LocalMediator,
+};
+use serde_json::{json, Value};
+use simulation::{Simulation, MEDIATOR_DID};
+
+/// Simulation whose agents were granted mediation and added their first
+/// key to their keylist
+fn mediated(mut simulation: Simulation, agents: usize) -> Simulation {
+    for i in 0..agents {
+        let key = simulation.agent(i).key(1);
+        let agent = simulation.agent_mut(i);
+        agent.request_mediation();
+        agent.update_keylist("add", &key);
+    }
+    simulation.run();
+
+    simulation
+}
+
+fn attachment_ids(delivery: &Value) -> Vec<String> {
+    delivery["attachments"]
+        .as_array()
+        .map(|attachments| {
+            attachments
+                .iter()
+                .map(|a| a["id"].as_str().unwrap().to_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn payloads(delivery: &Value) -> Vec<&Value> {
+    delivery["attachments"]
+        .as_array()
+        .map(|attachments| attachments.iter().map(|a| &a["data"]["json"]).collect())
+        .unwrap_or_default()
+}
+
+#[test]
+fn agents_exchange_messages_through_the_mediator() {
+    let mut simulation = mediated(Simulation::new(3), 3);
+
+    let grant = simulation.agent(0).response_to("agent-0-1").unwrap();
+    assert_eq!(grant["type"], MEDIATE_GRANT_2_0);
+    assert_eq!(grant["body"]["routing_did"], json!([MEDIATOR_DID]));
+
+    let key = simulation.agent(2).key(1);
+    simulation.agent_mut(0).forward(&key, json!({"from": 0}));
+    simulation.agent_mut(1).forward(&key, json!({"from": 1}));
+    assert_eq!(simulation.run(), 2);
+    assert_eq!(
+        simulation
+            .mediator()
+            .queue()
+            .messages(simulation.agent(2).did())
+            .len(),
+        2
+    );
+
+    let id = simulation.agent_mut(2).request_delivery(10);
+    simulation.run();
+    let delivery = simulation.agent(2).response_to(&id).unwrap();
+    assert_eq!(delivery["type"], DELIVERY_3_0);
+    assert_eq!(
+        payloads(delivery),
+        [&json!({"from": 0}), &json!({"from": 1})]
+    );
+
+    // Forwards go unanswered
+    assert_eq!(simulation.agent(0).received().len(), 2);
+    assert_eq!(simulation.agent(1).received().len(), 2);
+}
+
+#[test]
+fn keys_rotate_while_forwards_are_in_flight() {
+    let mut simulation = mediated(Simulation::new(2), 2);
+    let old_key = simulation.agent(1).key(1);
+    let new_key = simulation.agent(1).key(2);
+
+    // The recipient rotates its key between forwards, which the mediator
+    // handles in order
+    simulation.agent_mut(0).forward(&old_key, json!({"n": 1}));
+    simulation.agent_mut(1).update_keylist("add", &new_key);
+    simulation.agent_mut(1).update_keylist("remove", &old_key);
+    let stale = simulation.agent_mut(0).forward(&old_key, json!({"n": 2}));
+    simulation.agent_mut(0).forward(&new_key, json!({"n": 3}));
+    assert_eq!(simulation.run(), 5);
+
+    let report = simulation.agent(0).response_to(&stale).unwrap();
+    assert_eq!(report["type"], PROBLEM_REPORT_2_0);
+
+    let id = simulation.agent_mut(1).request_delivery(10);
+    simulation.run();
+    let delivery = simulation.agent(1).response_to(&id).unwrap();
+    assert_eq!(payloads(delivery), [&json!({"n": 1}), &json!({"n": 3})]);
+}
+
+#[test]
+fn concurrent_pickups_deliver_messages_once() {
+    let mut simulation = mediated(Simulation::new(2), 2);
+    let key = simulation.agent(1).key(1);
+    for n in 0..3 {
+        simulation.agent_mut(0).forward(&key, json!({"n": n}));
+    }
+    simulation.run();
+
+    // Both requests are in flight before either is handled
+    let first = simulation.agent_mut(1).request_delivery(2);
+    let second = simulation.agent_mut(1).request_delivery(2);
+    simulation.run();
+
+    let first = attachment_ids(simulation.agent(1).response_to(&first).unwrap());
+    let second = attachment_ids(simulation.agent(1).response_to(&second).unwrap());
+    assert_eq!((first.len(), second.len()), (2, 1));
+    assert!(second.iter().all(|id| !first.contains(id)));
+
+    let ids: Vec<_> = first.into_iter().chain(second).collect();
+    let ack = simulation.agent_mut(1).acknowledge(&ids);
+    simulation.run();
+    let status = simulation.agent(1).response_to(&ack).unwrap();
+    assert_eq!(status["body"]["message_count"], 0);
+}
+
+#[test]
+fn forwards_are_refused_once_the_quota_is_exhausted() {
+    let mediator = LocalMediator::new(MEDIATOR_DID).with_forward_config(ForwardConfig {
+        max_queued_messages: 2,
+    });
+    let mut simulation = mediated(Simulation::with_mediator(mediator, 2), 2);
+    let key = simulation.agent(1).key(1);
+
+    let forwards: Vec<_> = (0..3)
+        .map(|n| simulation.agent_mut(0).forward(&key, json!({"n": n})))
+        .collect();
+    simulation.run();
+    assert!(simulation.agent(0).response_to(&forwards[1]).is_none());
+    let report = simulation.agent(0).response_to(&forwards[2]).unwrap();
+    assert_eq!(report["body"]["code"], FORWARD_QUOTA_EXCEEDED_CODE);
+
+    // Picking up messages frees the queue
+    let id = simulation.agent_mut(1).request_delivery(10);
+    simulation.run();
+    let ids = attachment_ids(simulation.agent(1).response_to(&id).unwrap());
+    simulation.agent_mut(1).acknowledge(&ids);
+    let retried = simulation.agent_mut(0).forward(&key, json!({"n": 2}));
+    simulation.run();
+    assert!(simulation.agent(0).response_to(&retried).is_none());
+    assert_eq!(
+        simulation
+            .mediator()
+            .queue()
+            .messages(simulation.agent(1).did())
+            .len(),
+        1
+    );
+}